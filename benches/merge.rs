@@ -0,0 +1,80 @@
+//! Benchmarks for union merging of large entities
+//!
+//! Run with `cargo bench --bench merge`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::{json, Value};
+
+use rocrate_consolidate::collect::CollectedEntity;
+use rocrate_consolidate::merge::{merge_by_id, union_merge_entities, union_merge_values};
+
+/// A `hasPart` array of `len` references, starting at file number `offset`
+fn has_part(offset: usize, len: usize) -> Value {
+    Value::Array(
+        (offset..offset + len)
+            .map(|i| json!({"@id": format!("./data/file{}.csv", i)}))
+            .collect(),
+    )
+}
+
+fn bench_union_merge_values(c: &mut Criterion) {
+    let mut group = c.benchmark_group("union_merge_values/hasPart");
+    for len in [100, 1_000, 10_000] {
+        // Half of the second array overlaps with the first
+        let a = has_part(0, len);
+        let b = has_part(len / 2, len);
+        group.bench_with_input(BenchmarkId::from_parameter(len), &len, |bench, _| {
+            bench.iter(|| union_merge_values(black_box(&a), black_box(&b)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_union_merge_entities(c: &mut Criterion) {
+    let a = json!({
+        "@id": "./",
+        "@type": "Dataset",
+        "name": "Root",
+        "hasPart": has_part(0, 10_000)
+    });
+    let b = json!({
+        "@id": "./",
+        "@type": ["Dataset", "Subcrate"],
+        "description": "Merged root",
+        "hasPart": has_part(5_000, 10_000)
+    });
+    c.bench_function("union_merge_entities/root_10k", |bench| {
+        bench.iter(|| union_merge_entities(black_box(&a), black_box(&b)))
+    });
+}
+
+fn bench_merge_by_id(c: &mut Criterion) {
+    // Mostly unique shared entities, with every tenth id occurring twice
+    let entities: Vec<CollectedEntity> = (0..10_000)
+        .map(|i| {
+            let n = if i % 10 == 1 { i - 1 } else { i };
+            let id = format!("https://orcid.org/{}", n);
+            CollectedEntity {
+                entity: json!({"@id": id, "@type": "Person", "name": format!("Person {}", i)}),
+                original_id: id,
+                namespace: "".into(),
+                index: i,
+            }
+        })
+        .collect();
+    c.bench_function("merge_by_id/shared_10k", |bench| {
+        bench.iter_batched(
+            || entities.clone(),
+            merge_by_id,
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_union_merge_values,
+    bench_union_merge_entities,
+    bench_merge_by_id
+);
+criterion_main!(benches);
@@ -0,0 +1,160 @@
+//! Benchmarks for the collect and rewrite phases and a full consolidation
+//!
+//! Run with `cargo bench --bench pipeline`. Merge benchmarks live in
+//! `benches/merge.rs`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+
+use rocrate_consolidate::collect::collect_from_graph;
+use rocrate_consolidate::id::{build_id_map, rewrite_references};
+use rocrate_consolidate::{
+    consolidate, consolidate_mapped, parse_graph, parse_raw_graph, to_json_string_styled,
+    ConsolidateInput, ConsolidateOptions, FragmentIdPolicy, MergeCrate, NoOpLoader, OutputStyle,
+};
+
+/// A crate graph with `files` local files, each authored by one of ten people
+fn crate_graph(files: usize) -> Vec<Value> {
+    let mut graph = vec![
+        json!({
+            "@id": "ro-crate-metadata.json",
+            "@type": "CreativeWork",
+            "about": {"@id": "./"},
+            "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+        }),
+        json!({
+            "@id": "./",
+            "@type": "Dataset",
+            "name": "Benchmark crate",
+            "hasPart": (0..files)
+                .map(|i| json!({"@id": format!("./data/file{}.csv", i)}))
+                .collect::<Vec<_>>()
+        }),
+    ];
+    graph.extend((0..files).map(|i| {
+        json!({
+            "@id": format!("./data/file{}.csv", i),
+            "@type": "File",
+            "name": format!("File {}", i),
+            "author": {"@id": format!("https://orcid.org/{}", i % 10)}
+        })
+    }));
+    graph.extend((0..10).map(|i| {
+        json!({
+            "@id": format!("https://orcid.org/{}", i),
+            "@type": "Person",
+            "name": format!("Person {}", i)
+        })
+    }));
+    graph
+}
+
+fn bench_collect(c: &mut Criterion) {
+    let graph = crate_graph(10_000);
+    c.bench_function("collect_from_graph/files_10k", |bench| {
+        bench.iter_batched(
+            || graph.clone(),
+            |graph| collect_from_graph(graph, black_box("sub")),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn bench_rewrite(c: &mut Criterion) {
+    let graph = crate_graph(10_000);
+    let ids: Vec<String> = graph
+        .iter()
+        .filter_map(|e| e.get("@id").and_then(Value::as_str))
+        .filter(|id| id.starts_with("./data/"))
+        .map(String::from)
+        .collect();
+
+    c.bench_function("build_id_map/files_10k", |bench| {
+        bench.iter(|| {
+            build_id_map(
+                ids.iter().map(String::as_str),
+                black_box("sub"),
+                &mut HashSet::new(),
+                FragmentIdPolicy::default(),
+            )
+        })
+    });
+
+    let id_map = build_id_map(
+        ids.iter().map(String::as_str),
+        "sub",
+        &mut HashSet::new(),
+        FragmentIdPolicy::default(),
+    );
+    c.bench_function("rewrite_references/files_10k", |bench| {
+        bench.iter_batched(
+            || graph.clone(),
+            |mut graph| {
+                for entity in graph.iter_mut() {
+                    rewrite_references(entity, &id_map);
+                }
+                graph
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn bench_consolidate(c: &mut Criterion) {
+    let main = crate_graph(5_000);
+    let other = crate_graph(5_000);
+    c.bench_function("consolidate/merge_2x5k", |bench| {
+        bench.iter_batched(
+            || ConsolidateInput::Merge {
+                main: main.clone(),
+                others: vec![MergeCrate {
+                    graph: other.clone(),
+                    folder_id: "./other/".to_string(),
+                    name: None,
+                }],
+            },
+            |input| consolidate(input, &NoOpLoader, &ConsolidateOptions::default()),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+/// Reading, consolidating and writing a file-heavy crate, with and without
+/// writing unchanged entities verbatim
+fn bench_passthrough(c: &mut Criterion) {
+    let content = json!({
+        "@context": "https://w3id.org/ro/crate/1.1/context",
+        "@graph": crate_graph(10_000)
+    })
+    .to_string();
+    let style = OutputStyle::default();
+    c.bench_function("passthrough/off_files_10k", |bench| {
+        bench.iter(|| {
+            let graph = parse_graph(black_box(&content), "bench").unwrap();
+            let options = ConsolidateOptions::default();
+            let result = consolidate(ConsolidateInput::Single(graph), &NoOpLoader, &options);
+            to_json_string_styled(&result.unwrap(), &style).unwrap()
+        })
+    });
+    c.bench_function("passthrough/on_files_10k", |bench| {
+        bench.iter(|| {
+            let graph = parse_raw_graph(black_box(&content), "bench").unwrap();
+            let options = ConsolidateOptions {
+                raw_passthrough: true,
+                ..Default::default()
+            };
+            let mapped = consolidate_mapped(graph, &NoOpLoader, &options).unwrap();
+            mapped.to_json_string_styled(&style).unwrap()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_collect,
+    bench_rewrite,
+    bench_consolidate,
+    bench_passthrough
+);
+criterion_main!(benches);
@@ -0,0 +1,167 @@
+//! Contiguous storage for collected entities
+//!
+//! All entities collected from a crate hierarchy are moved into a single
+//! arena and referenced by index. Because a crate and its subcrates are
+//! collected depth-first, the local entities of any subtree occupy one
+//! contiguous range of keys.
+
+use crate::collect::CollectedEntity;
+
+/// Index of an entity in an [`EntityArena`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EntityKey(usize);
+
+/// Store owning every entity collected during consolidation
+#[derive(Debug, Default)]
+pub struct EntityArena {
+    /// All entities in collection order
+    entities: Vec<CollectedEntity>,
+    /// Keys of entities with local ids (namespaced per crate)
+    local: Vec<EntityKey>,
+    /// Keys of entities with absolute ids (merged across crates)
+    shared: Vec<EntityKey>,
+}
+
+impl EntityArena {
+    /// Create an empty arena
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entity with a local id
+    pub fn push_local(&mut self, entity: CollectedEntity) -> EntityKey {
+        let key = self.push(entity);
+        self.local.push(key);
+        key
+    }
+
+    /// Add an entity with an absolute id
+    pub fn push_shared(&mut self, entity: CollectedEntity) -> EntityKey {
+        let key = self.push(entity);
+        self.shared.push(key);
+        key
+    }
+
+    /// Reserve room for `additional` more entities, most of them local
+    pub fn reserve(&mut self, additional: usize) {
+        self.entities.reserve(additional);
+        self.local.reserve(additional);
+    }
+
+    fn push(&mut self, entity: CollectedEntity) -> EntityKey {
+        let key = EntityKey(self.entities.len());
+        self.entities.push(entity);
+        key
+    }
+
+    /// Get an entity by key
+    pub fn get(&self, key: EntityKey) -> &CollectedEntity {
+        &self.entities[key.0]
+    }
+
+    /// Total number of entities
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Check if the arena holds no entities
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// Number of local entities, usable as a mark for [`local_since`](Self::local_since)
+    pub fn local_len(&self) -> usize {
+        self.local.len()
+    }
+
+    /// Local entities added after `mark` was taken with [`local_len`](Self::local_len)
+    pub fn local_since(&self, mark: usize) -> impl Iterator<Item = &CollectedEntity> {
+        self.local[mark..].iter().map(|key| self.get(*key))
+    }
+
+    /// All local entities in collection order
+    pub fn local(&self) -> impl Iterator<Item = &CollectedEntity> {
+        self.local_since(0)
+    }
+
+    /// Mutable access to all local entities in collection order
+    pub fn local_mut(&mut self) -> impl Iterator<Item = &mut CollectedEntity> {
+        let mut slots: Vec<Option<&mut CollectedEntity>> =
+            self.entities.iter_mut().map(Some).collect();
+        let mut local = Vec::with_capacity(self.local.len());
+        for key in &self.local {
+            local.extend(slots[key.0].take());
+        }
+        local.into_iter()
+    }
+
+    /// All shared entities in collection order
+    pub fn shared(&self) -> impl Iterator<Item = &CollectedEntity> {
+        self.shared.iter().map(|key| self.get(*key))
+    }
+
+    /// Move all entities of `other` to the end of this arena, keeping their order
+    pub fn append(&mut self, other: EntityArena) {
+        let offset = self.entities.len();
+        self.entities.extend(other.entities);
+        self.local
+            .extend(other.local.into_iter().map(|key| EntityKey(key.0 + offset)));
+        self.shared.extend(
+            other
+                .shared
+                .into_iter()
+                .map(|key| EntityKey(key.0 + offset)),
+        );
+    }
+
+    /// Move the entities out as (local, shared), each in collection order
+    pub fn into_parts(self) -> (Vec<CollectedEntity>, Vec<CollectedEntity>) {
+        let mut slots: Vec<Option<CollectedEntity>> = self.entities.into_iter().map(Some).collect();
+        let mut take = |keys: Vec<EntityKey>| -> Vec<CollectedEntity> {
+            keys.into_iter()
+                .filter_map(|key| slots[key.0].take())
+                .collect()
+        };
+        let local = take(self.local);
+        let shared = take(self.shared);
+        (local, shared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn entity(id: &str, namespace: &str) -> CollectedEntity {
+        CollectedEntity {
+            entity: json!({"@id": id}),
+            original_id: id.to_string(),
+            namespace: namespace.into(),
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn test_arena() {
+        let mut arena = EntityArena::new();
+        arena.push_local(entity("./a.csv", ""));
+        let person = arena.push_shared(entity("https://orcid.org/1", ""));
+        let mark = arena.local_len();
+        arena.push_local(entity("./sub/b.csv", "sub"));
+        arena.push_local(entity("./sub/inner/c.csv", "sub/inner"));
+
+        assert_eq!(arena.len(), 4);
+        assert_eq!(arena.get(person).original_id, "https://orcid.org/1");
+        let subtree: Vec<&str> = arena
+            .local_since(mark)
+            .map(|e| e.original_id.as_str())
+            .collect();
+        assert_eq!(subtree, vec!["./sub/b.csv", "./sub/inner/c.csv"]);
+
+        let (local, shared) = arena.into_parts();
+        assert_eq!(local.len(), 3);
+        assert_eq!(local[0].original_id, "./a.csv");
+        assert_eq!(shared.len(), 1);
+    }
+}
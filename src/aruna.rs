@@ -0,0 +1,647 @@
+//! Loading crates stored in an Aruna instance, and writing them back
+//!
+//! A crate is addressed by the id of the Aruna resource (project, collection
+//! or dataset) holding its `ro-crate-metadata.json` object. Resources below
+//! it that hold a metadata object of their own are consolidated as its
+//! subcrates, in folders named after the resources between them, whether or
+//! not the crate's metadata marks them as crates.
+//!
+//! Resources are read through the REST gateway of the Aruna v2 API:
+//! `GET /v2/resources/{id}` for a resource and its relations, and
+//! `GET /v2/objects/{id}/download` for an object's download URL.
+//!
+//! [`upload_crate`] writes a consolidated crate back as a new dataset, with
+//! `POST /v2/datasets` and `POST /v2/objects` to create the dataset and its
+//! metadata object, `GET /v2/objects/{id}/upload` for the object's upload
+//! URL, and `PATCH /v2/objects/{id}/finish` once the content is uploaded.
+
+use reqwest::blocking::RequestBuilder;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+
+use crate::audit::{self, AccessKind};
+use crate::consolidate::{parse_graph, SubcrateLoader};
+use crate::error::{BoxError, ConsolidateError};
+use crate::loader::{read_to_string_limited, send_with_retries, UrlLoaderOptions};
+use crate::path::component_to_id;
+use crate::vocab::METADATA_DESCRIPTOR_ID;
+
+/// Scheme of sources naming an Aruna resource, as in "aruna://<resource-id>"
+pub const ARUNA_SCHEME: &str = "aruna://";
+
+/// Kind of an Aruna resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceVariant {
+    Project,
+    Collection,
+    Dataset,
+    Object,
+}
+
+/// An Aruna resource and the resources belonging to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArunaResource {
+    pub id: String,
+    pub name: String,
+    pub variant: ResourceVariant,
+    /// Ids of the resources belonging to this one
+    pub children: Vec<String>,
+}
+
+impl ArunaResource {
+    /// Parse a `GetResource` response of the REST gateway
+    ///
+    /// Field names may be in proto (`resource_id`) or JSON (`resourceId`) case.
+    pub fn from_response(response: &Value) -> Result<Self, ConsolidateError> {
+        let invalid = |reason: &str| {
+            ConsolidateError::InvalidStructure(format!("Aruna resource response {}", reason))
+        };
+        // The resource is wrapped as {"resource": {"resource": {"<variant>": {...}}}}
+        let mut wrapper = response;
+        let (variant, resource) = loop {
+            let variants = [
+                ("project", ResourceVariant::Project),
+                ("collection", ResourceVariant::Collection),
+                ("dataset", ResourceVariant::Dataset),
+                ("object", ResourceVariant::Object),
+            ];
+            if let Some((variant, resource)) = variants
+                .iter()
+                .find_map(|(key, variant)| Some((*variant, wrapper.get(*key)?)))
+            {
+                break (variant, resource);
+            }
+            wrapper = wrapper
+                .get("resource")
+                .ok_or_else(|| invalid("has no resource"))?;
+        };
+
+        let string = |value: &Value, keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| value.get(*key)?.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let children = resource
+            .get("relations")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|relation| relation.get("internal"))
+            .filter(|internal| {
+                string(internal, &["defined_variant", "definedVariant"]).ends_with("BELONGS_TO")
+                    && string(internal, &["direction"]).ends_with("OUTBOUND")
+            })
+            .map(|internal| string(internal, &["resource_id", "resourceId"]))
+            .filter(|id| !id.is_empty())
+            .collect();
+        Ok(Self {
+            id: string(resource, &["id"]),
+            name: string(resource, &["name"]),
+            variant,
+            children,
+        })
+    }
+}
+
+/// Access to the resources of an Aruna instance
+pub trait ArunaApi: Sync {
+    /// Get a resource by id
+    fn resource(&self, id: &str) -> Result<ArunaResource, ConsolidateError>;
+
+    /// Download the content of an object
+    fn download(&self, object_id: &str) -> Result<String, ConsolidateError>;
+
+    /// Create a dataset named `name` below the project or collection
+    /// `parent`, returning its id
+    ///
+    /// Read-only implementations keep the default, which fails.
+    fn create_dataset(
+        &self,
+        parent: &ArunaResource,
+        name: &str,
+    ) -> Result<String, ConsolidateError> {
+        let _ = name;
+        Err(read_only(&parent.id))
+    }
+
+    /// Create an object named `name` with `content` in the dataset
+    /// `dataset_id`, returning its id
+    ///
+    /// Read-only implementations keep the default, which fails.
+    fn upload(
+        &self,
+        dataset_id: &str,
+        name: &str,
+        content: &[u8],
+    ) -> Result<String, ConsolidateError> {
+        let _ = (name, content);
+        Err(read_only(dataset_id))
+    }
+}
+
+fn read_only(id: &str) -> ConsolidateError {
+    ConsolidateError::LoadError {
+        path: format!("{}{}", ARUNA_SCHEME, id),
+        reason: "This Aruna API is read-only".to_string(),
+        source: None,
+    }
+}
+
+/// Write the consolidated `metadata` back to Aruna, as the
+/// `ro-crate-metadata.json` object of a new dataset `name` below the project
+/// or collection `parent_id`
+///
+/// Returns the id of the dataset, from which `aruna://<id>` loads the crate.
+pub fn upload_crate(
+    api: &impl ArunaApi,
+    parent_id: &str,
+    name: &str,
+    metadata: &str,
+) -> Result<String, ConsolidateError> {
+    let parent = api.resource(parent_id)?;
+    if !matches!(
+        parent.variant,
+        ResourceVariant::Project | ResourceVariant::Collection
+    ) {
+        return Err(ConsolidateError::InvalidStructure(format!(
+            "Aruna resource {} is a {:?}, not a project or collection to create the crate's dataset in",
+            parent_id, parent.variant
+        )));
+    }
+    let dataset_id = api.create_dataset(&parent, name)?;
+    api.upload(&dataset_id, METADATA_DESCRIPTOR_ID, metadata.as_bytes())?;
+    Ok(dataset_id)
+}
+
+/// Client of an Aruna instance's REST gateway
+pub struct ArunaClient {
+    /// Base URL of the gateway, e.g. "https://api.aruna.example.org"
+    endpoint: String,
+    /// API token sent as bearer token
+    token: Option<String>,
+    http: reqwest::blocking::Client,
+    /// Retries and timeout of requests
+    options: UrlLoaderOptions,
+}
+
+impl ArunaClient {
+    pub fn new(endpoint: impl Into<String>, token: Option<String>) -> Self {
+        Self {
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            token,
+            http: reqwest::blocking::Client::new(),
+            options: UrlLoaderOptions::DEFAULT,
+        }
+    }
+
+    /// Retry and time out requests as `options` say instead of as
+    /// [`UrlLoaderOptions::DEFAULT`]; their credentials aren't sent
+    pub fn with_url_options(mut self, options: UrlLoaderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn get(&self, url: &str, authorize: bool) -> Result<String, ConsolidateError> {
+        self.send(url, authorize, true, || self.http.get(url))
+    }
+
+    /// Send the request made by `request`, with the Aruna token if
+    /// `authorize`, and read the response
+    ///
+    /// Requests that aren't `idempotent` are sent once, so that a retry can't
+    /// create a resource twice.
+    fn send(
+        &self,
+        url: &str,
+        authorize: bool,
+        idempotent: bool,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<String, ConsolidateError> {
+        let load_error = |reason: String, source: Option<BoxError>| ConsolidateError::LoadError {
+            path: url.to_string(),
+            reason,
+            source,
+        };
+        let request = || match (authorize, &self.token) {
+            (true, Some(token)) => request().bearer_auth(token),
+            _ => request(),
+        };
+        // Only the Aruna token is sent
+        let options = UrlLoaderOptions {
+            auth: None,
+            retries: if idempotent { self.options.retries } else { 0 },
+            ..self.options.clone()
+        };
+        let response = send_with_retries(url, &options, request)?
+            .error_for_status()
+            .map_err(|e| load_error(format!("HTTP request failed: {}", e), Some(e.into())))?;
+        let content = read_to_string_limited(response)
+            .map_err(|e| load_error(format!("Failed to read response: {}", e), Some(e.into())))?;
+        audit::record(AccessKind::Url, url, None, content.as_bytes());
+        Ok(content)
+    }
+}
+
+impl ArunaApi for ArunaClient {
+    fn resource(&self, id: &str) -> Result<ArunaResource, ConsolidateError> {
+        let url = format!("{}/v2/resources/{}", self.endpoint, id);
+        let response: Value = serde_json::from_str(&self.get(&url, true)?)?;
+        ArunaResource::from_response(&response)
+    }
+
+    fn download(&self, object_id: &str) -> Result<String, ConsolidateError> {
+        let url = format!("{}/v2/objects/{}/download", self.endpoint, object_id);
+        let response: Value = serde_json::from_str(&self.get(&url, true)?)?;
+        let download_url = response.get("url").and_then(Value::as_str).ok_or_else(|| {
+            ConsolidateError::InvalidStructure(format!("No download URL for object {}", object_id))
+        })?;
+        // Download URLs are presigned
+        self.get(download_url, false)
+    }
+
+    fn create_dataset(
+        &self,
+        parent: &ArunaResource,
+        name: &str,
+    ) -> Result<String, ConsolidateError> {
+        let parent_key = match parent.variant {
+            ResourceVariant::Project => "projectId",
+            ResourceVariant::Collection => "collectionId",
+            variant => {
+                return Err(ConsolidateError::InvalidStructure(format!(
+                    "Datasets can't be created in a {:?}",
+                    variant
+                )))
+            }
+        };
+        let url = format!("{}/v2/datasets", self.endpoint);
+        let mut body = json!({"name": name, "dataClass": "DATA_CLASS_PRIVATE"});
+        body[parent_key] = json!(parent.id);
+        let response = self.send(&url, true, false, || json_body(self.http.post(&url), &body))?;
+        created_id(&serde_json::from_str(&response)?, "dataset", &url)
+    }
+
+    fn upload(
+        &self,
+        dataset_id: &str,
+        name: &str,
+        content: &[u8],
+    ) -> Result<String, ConsolidateError> {
+        let url = format!("{}/v2/objects", self.endpoint);
+        let body = json!({
+            "name": name,
+            "dataClass": "DATA_CLASS_PRIVATE",
+            "datasetId": dataset_id,
+        });
+        let response = self.send(&url, true, false, || json_body(self.http.post(&url), &body))?;
+        let object_id = created_id(&serde_json::from_str(&response)?, "object", &url)?;
+
+        let url = format!("{}/v2/objects/{}/upload", self.endpoint, object_id);
+        let response: Value = serde_json::from_str(&self.get(&url, true)?)?;
+        let upload_url = response.get("url").and_then(Value::as_str).ok_or_else(|| {
+            ConsolidateError::InvalidStructure(format!("No upload URL for object {}", object_id))
+        })?;
+        // Upload URLs are presigned
+        self.send(upload_url, false, true, || {
+            self.http.put(upload_url).body(content.to_vec())
+        })?;
+
+        let url = format!("{}/v2/objects/{}/finish", self.endpoint, object_id);
+        let body = json!({"contentLen": content.len().to_string()});
+        self.send(&url, true, true, || json_body(self.http.patch(&url), &body))?;
+        Ok(object_id)
+    }
+}
+
+/// `request` with `body` as its JSON content
+fn json_body(request: RequestBuilder, body: &Value) -> RequestBuilder {
+    request
+        .header(CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+}
+
+/// Id of the resource `kind` a create request answered with, as in
+/// {"dataset": {"id": "..."}}
+fn created_id(response: &Value, kind: &str, url: &str) -> Result<String, ConsolidateError> {
+    response
+        .get(kind)
+        .and_then(|resource| resource.get("id"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| {
+            ConsolidateError::InvalidStructure(format!("No {} id in the response of {}", kind, url))
+        })
+}
+
+/// Subcrate loader for a crate hierarchy stored in Aruna
+pub struct ArunaLoader<A> {
+    api: A,
+    /// Metadata object of the root crate
+    root_metadata: String,
+    /// (subcrate id, metadata object) of subcrates by parent namespace
+    listed: HashMap<String, Vec<(String, String)>>,
+}
+
+impl<A: ArunaApi> ArunaLoader<A> {
+    /// Find the crates at and below the resource `root_id`
+    ///
+    /// Walks all resources below the root, so large hierarchies take many
+    /// requests.
+    pub fn new(api: A, root_id: &str) -> Result<Self, ConsolidateError> {
+        let root = api.resource(root_id)?;
+        let (root_metadata, folders) = crate_contents(&api, &root)?;
+        let root_metadata = root_metadata.ok_or_else(|| ConsolidateError::LoadError {
+            path: format!("{}{}", ARUNA_SCHEME, root_id),
+            reason: format!("No {} object", METADATA_DESCRIPTOR_ID),
+            source: None,
+        })?;
+        let mut loader = Self {
+            api,
+            root_metadata,
+            listed: HashMap::new(),
+        };
+        for folder in folders {
+            let path = segment(&folder_name(&folder));
+            loader.find_subcrates(folder, "", &path)?;
+        }
+        Ok(loader)
+    }
+
+    /// Graph of the root crate
+    pub fn root_graph(&self) -> Result<Vec<Value>, ConsolidateError> {
+        self.load_metadata(&self.root_metadata)
+    }
+
+    /// Number of subcrates found below the root
+    pub fn len(&self) -> usize {
+        self.listed.values().map(Vec::len).sum()
+    }
+
+    /// Check if no subcrates were found below the root
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Record the crates at and below `resource`, found at `path` below the
+    /// crate with namespace `namespace`
+    fn find_subcrates(
+        &mut self,
+        resource: ArunaResource,
+        namespace: &str,
+        path: &str,
+    ) -> Result<(), ConsolidateError> {
+        let (metadata, folders) = crate_contents(&self.api, &resource)?;
+        let (namespace, path) = match metadata {
+            Some(metadata) => {
+                self.listed
+                    .entry(namespace.to_string())
+                    .or_default()
+                    .push((format!("./{}/", path), metadata));
+                let subcrate_namespace = if namespace.is_empty() {
+                    path.to_string()
+                } else {
+                    format!("{}/{}", namespace, path)
+                };
+                (subcrate_namespace, String::new())
+            }
+            None => (namespace.to_string(), format!("{}/", path)),
+        };
+        for folder in folders {
+            let folder_path = format!("{}{}", path, segment(&folder_name(&folder)));
+            self.find_subcrates(folder, &namespace, &folder_path)?;
+        }
+        Ok(())
+    }
+
+    fn load_metadata(&self, object_id: &str) -> Result<Vec<Value>, ConsolidateError> {
+        let content = self.api.download(object_id)?;
+        parse_graph(&content, &format!("{}{}", ARUNA_SCHEME, object_id))
+    }
+}
+
+impl<A: ArunaApi> SubcrateLoader for ArunaLoader<A> {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        _subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let metadata = self
+            .listed
+            .get(parent_namespace)
+            .and_then(|siblings| siblings.iter().find(|(id, _)| id == subcrate_id))
+            .map(|(_, metadata)| metadata)
+            .ok_or_else(|| ConsolidateError::LoadError {
+                path: subcrate_id.to_string(),
+                reason: "No Aruna resource holds this subcrate".to_string(),
+                source: None,
+            })?;
+        self.load_metadata(metadata)
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        self.listed
+            .get(namespace)
+            .map(|siblings| siblings.iter().map(|(id, _)| id.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// The metadata object of a resource, if it has one, and the resources
+/// below it that aren't objects
+fn crate_contents(
+    api: &impl ArunaApi,
+    resource: &ArunaResource,
+) -> Result<(Option<String>, Vec<ArunaResource>), ConsolidateError> {
+    let mut metadata = None;
+    let mut folders = Vec::new();
+    for child in &resource.children {
+        let child = api.resource(child)?;
+        match child.variant {
+            ResourceVariant::Object if child.name == METADATA_DESCRIPTOR_ID => {
+                metadata = Some(child.id)
+            }
+            ResourceVariant::Object => {}
+            _ => folders.push(child),
+        }
+    }
+    Ok((metadata, folders))
+}
+
+/// Name of a resource's folder, falling back to its id
+fn folder_name(resource: &ArunaResource) -> String {
+    if resource.name.is_empty() {
+        resource.id.clone()
+    } else {
+        resource.name.clone()
+    }
+}
+
+fn segment(name: &str) -> String {
+    component_to_id(OsStr::new(name)).replace('/', "%2F")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Resources by id, with the content of each object
+    struct MapApi(Mutex<HashMap<String, (ArunaResource, String)>>);
+
+    impl MapApi {
+        fn new(resources: &[(&str, &str, ResourceVariant, &[&str])]) -> Self {
+            let entries = resources.iter().map(|(id, name, variant, children)| {
+                let resource = ArunaResource {
+                    id: id.to_string(),
+                    name: name.to_string(),
+                    variant: *variant,
+                    children: children.iter().map(|c| c.to_string()).collect(),
+                };
+                let content = json!({"@graph": [{"@id": "./", "name": id}]}).to_string();
+                (id.to_string(), (resource, content))
+            });
+            Self(Mutex::new(entries.collect()))
+        }
+
+        /// Add a resource below `parent`, with an id made from its name
+        fn add(
+            &self,
+            parent: &str,
+            name: &str,
+            variant: ResourceVariant,
+            content: String,
+        ) -> String {
+            let mut resources = self.0.lock().unwrap();
+            let id = format!("{}-{}", parent, name);
+            let resource = ArunaResource {
+                id: id.clone(),
+                name: name.to_string(),
+                variant,
+                children: Vec::new(),
+            };
+            resources.insert(id.clone(), (resource, content));
+            resources
+                .get_mut(parent)
+                .unwrap()
+                .0
+                .children
+                .push(id.clone());
+            id
+        }
+    }
+
+    impl ArunaApi for MapApi {
+        fn resource(&self, id: &str) -> Result<ArunaResource, ConsolidateError> {
+            Ok(self.0.lock().unwrap()[id].0.clone())
+        }
+
+        fn download(&self, object_id: &str) -> Result<String, ConsolidateError> {
+            Ok(self.0.lock().unwrap()[object_id].1.clone())
+        }
+
+        fn create_dataset(
+            &self,
+            parent: &ArunaResource,
+            name: &str,
+        ) -> Result<String, ConsolidateError> {
+            Ok(self.add(&parent.id, name, ResourceVariant::Dataset, String::new()))
+        }
+
+        fn upload(
+            &self,
+            dataset_id: &str,
+            name: &str,
+            content: &[u8],
+        ) -> Result<String, ConsolidateError> {
+            let content = String::from_utf8(content.to_vec()).unwrap();
+            Ok(self.add(dataset_id, name, ResourceVariant::Object, content))
+        }
+    }
+
+    #[test]
+    fn test_resource_from_response() {
+        let response = json!({
+            "resource": {
+                "resource": {
+                    "collection": {
+                        "id": "01COLL",
+                        "name": "Runs",
+                        "relations": [
+                            {"internal": {
+                                "resourceId": "01PROJ",
+                                "definedVariant": "INTERNAL_RELATION_VARIANT_BELONGS_TO",
+                                "direction": "RELATION_DIRECTION_INBOUND"
+                            }},
+                            {"internal": {
+                                "resource_id": "01DATA",
+                                "defined_variant": "INTERNAL_RELATION_VARIANT_BELONGS_TO",
+                                "direction": "RELATION_DIRECTION_OUTBOUND"
+                            }},
+                            {"external": {"identifier": "https://doi.org/10.1234/x"}}
+                        ]
+                    }
+                },
+                "permission": "PERMISSION_LEVEL_READ"
+            }
+        });
+        let resource = ArunaResource::from_response(&response).unwrap();
+        assert_eq!(resource.id, "01COLL");
+        assert_eq!(resource.name, "Runs");
+        assert_eq!(resource.variant, ResourceVariant::Collection);
+        assert_eq!(resource.children, vec!["01DATA"]);
+
+        let err = ArunaResource::from_response(&json!({})).unwrap_err();
+        assert_eq!(err.code(), "invalid_structure");
+    }
+
+    #[test]
+    fn test_aruna_loader() {
+        use ResourceVariant::*;
+        let api = MapApi::new(&[
+            ("root", "Project", Project, &["meta", "runs", "notes"]),
+            ("meta", METADATA_DESCRIPTOR_ID, Object, &[]),
+            // A plain collection between the root and its subcrates
+            ("runs", "runs", Collection, &["run1"]),
+            ("run1", "run 1", Dataset, &["run1-meta", "inner"]),
+            ("run1-meta", METADATA_DESCRIPTOR_ID, Object, &[]),
+            ("inner", "inner", Dataset, &["inner-meta"]),
+            ("inner-meta", METADATA_DESCRIPTOR_ID, Object, &[]),
+            ("notes", "notes", Dataset, &[]),
+        ]);
+        let loader = ArunaLoader::new(api, "root").unwrap();
+        assert_eq!(loader.len(), 2);
+        assert_eq!(loader.root_graph().unwrap()[0]["name"], "meta");
+        assert_eq!(loader.listed_subcrates(""), vec!["./runs/run 1/"]);
+        assert_eq!(loader.listed_subcrates("runs/run 1"), vec!["./inner/"]);
+        let graph = loader.load("./inner/", "runs/run 1", None).unwrap();
+        assert_eq!(graph[0]["name"], "inner-meta");
+        assert!(loader.load("./notes/", "", None).is_err());
+
+        let api = MapApi::new(&[("empty", "Empty", Dataset, &[])]);
+        let err = ArunaLoader::new(api, "empty").err().unwrap();
+        assert_eq!(err.code(), "load_error");
+    }
+
+    #[test]
+    fn test_upload_crate() {
+        use ResourceVariant::*;
+        let api = MapApi::new(&[
+            ("proj", "Project", Project, &["data"]),
+            ("data", "data", Dataset, &[]),
+        ]);
+        let metadata = json!({"@graph": [{"@id": "./", "name": "Consolidated"}]}).to_string();
+        let dataset_id = upload_crate(&api, "proj", "consolidated", &metadata).unwrap();
+        assert_eq!(api.resource(&dataset_id).unwrap().name, "consolidated");
+        let loader = ArunaLoader::new(api, &dataset_id).unwrap();
+        assert_eq!(loader.root_graph().unwrap()[0]["name"], "Consolidated");
+
+        // Datasets hold objects, not datasets
+        let api = MapApi::new(&[("data", "data", Dataset, &[])]);
+        let err = upload_crate(&api, "data", "consolidated", &metadata).unwrap_err();
+        assert_eq!(err.code(), "invalid_structure");
+    }
+}
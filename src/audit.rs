@@ -0,0 +1,244 @@
+//! Audit log of external accesses
+//!
+//! Publishing a consolidated crate may require evidence of exactly what it
+//! was built from. While an [`AuditLog`] is active on a thread, every file
+//! read, archive member extracted and URL fetched by this library's loaders is
+//! recorded in it with its size and SHA-256 hash, and the validators that
+//! tell whether it changed without reading it again: the modification time
+//! of files and archives, and the `ETag` and `Last-Modified` of URLs.
+//! Consolidation passes the active log on to the threads it spawns.
+//!
+//! ```ignore
+//! let log = Arc::new(AuditLog::new());
+//! let result = {
+//!     let _active = log.activate();
+//!     consolidate(input, &loader, &options)?
+//! };
+//! let accesses = log.records();
+//! ```
+
+use std::cell::RefCell;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::loader::read_to_string_limited;
+
+/// What kind of resource was accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessKind {
+    /// A local file was read
+    File,
+    /// A member of a zip archive was extracted
+    ZipMember,
+    /// A member of a tar archive (optionally gzipped) was extracted
+    TarMember,
+    /// A URL was fetched
+    Url,
+}
+
+/// One external access
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessRecord {
+    pub kind: AccessKind,
+    /// Path of the file or archive, or the URL
+    pub location: String,
+    /// Path of the extracted member within a zip or tar archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub member: Option<String>,
+    /// Size of the content read, in bytes
+    pub bytes: u64,
+    /// Hex-encoded SHA-256 hash of the content read
+    pub sha256: String,
+    /// Whether the content was served from the HTTP cache instead of
+    /// being downloaded
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cached: bool,
+    /// Modification time of the file or archive, in nanoseconds since the
+    /// Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified_ns: Option<u64>,
+    /// `ETag` the URL was served with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    /// `Last-Modified` the URL was served with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+/// External accesses recorded during a run, in the order they happened
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    records: Mutex<Vec<AccessRecord>>,
+}
+
+thread_local! {
+    /// The log recording accesses of the current thread
+    static ACTIVE: RefCell<Option<Arc<AuditLog>>> = const { RefCell::new(None) };
+}
+
+impl AuditLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record accesses of the current thread in this log until the guard is
+    /// dropped
+    pub fn activate(self: &Arc<Self>) -> ActiveAuditLog {
+        activate(Some(Arc::clone(self)))
+    }
+
+    /// The recorded accesses
+    pub fn records(&self) -> Vec<AccessRecord> {
+        self.records.lock().unwrap().clone()
+    }
+
+    pub(crate) fn push(&self, record: AccessRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+}
+
+/// Guard of an active [`AuditLog`], restoring the previously active log when
+/// dropped
+pub struct ActiveAuditLog {
+    previous: Option<Arc<AuditLog>>,
+}
+
+impl Drop for ActiveAuditLog {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        ACTIVE.with(|active| *active.borrow_mut() = previous);
+    }
+}
+
+/// The log active on the current thread, if any
+pub(crate) fn active() -> Option<Arc<AuditLog>> {
+    ACTIVE.with(|active| active.borrow().clone())
+}
+
+/// Make `log` the active log of the current thread, e.g. one taken from
+/// [`active`] on the thread that spawned it
+pub(crate) fn activate(log: Option<Arc<AuditLog>>) -> ActiveAuditLog {
+    let previous = ACTIVE.with(|active| active.replace(log));
+    ActiveAuditLog { previous }
+}
+
+/// Record an access of `content` in the active log, if any
+///
+/// Files and archives are recorded with their current modification time.
+pub fn record(kind: AccessKind, location: &str, member: Option<&str>, content: &[u8]) {
+    let Some(log) = active() else {
+        return;
+    };
+    let modified_ns = match kind {
+        AccessKind::Url => None,
+        _ => modified_ns(Path::new(location)),
+    };
+    log.push(AccessRecord {
+        modified_ns,
+        ..access(kind, location, member, content)
+    });
+}
+
+/// Record a fetch of `url`, which may have come from the HTTP cache, with
+/// the validators it was served with
+pub(crate) fn record_fetch(
+    url: &str,
+    content: &[u8],
+    cached: bool,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+) {
+    let Some(log) = active() else {
+        return;
+    };
+    log.push(AccessRecord {
+        cached,
+        etag: etag.map(str::to_string),
+        last_modified: last_modified.map(str::to_string),
+        ..access(AccessKind::Url, url, None, content)
+    });
+}
+
+/// Record of an access of `content`, without validators
+fn access(kind: AccessKind, location: &str, member: Option<&str>, content: &[u8]) -> AccessRecord {
+    AccessRecord {
+        kind,
+        location: location.to_string(),
+        member: member.map(str::to_string),
+        bytes: content.len() as u64,
+        sha256: sha256(content),
+        cached: false,
+        modified_ns: None,
+        etag: None,
+        last_modified: None,
+    }
+}
+
+/// Modification time of the file at `path`, in nanoseconds since the Unix
+/// epoch, if the platform has one
+pub(crate) fn modified_ns(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let since_epoch = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    u64::try_from(since_epoch.as_nanos()).ok()
+}
+
+/// Hex-encoded SHA-256 hash of `content`
+pub(crate) fn sha256(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Read a UTF-8 file, recording the access
+pub fn read_file(path: &Path) -> io::Result<String> {
+    let content = read_to_string_limited(std::fs::File::open(path)?)?;
+    record(
+        AccessKind::File,
+        &path.display().to_string(),
+        None,
+        content.as_bytes(),
+    );
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_log() {
+        // Nothing is recorded without an active log
+        record(AccessKind::Url, "https://example.org/", None, b"ignored");
+
+        let log = Arc::new(AuditLog::new());
+        {
+            let _active = log.activate();
+            record(AccessKind::Url, "https://example.org/", None, b"abc");
+            // Threads spawned with the active log record into it too
+            let inherited = active();
+            std::thread::spawn(move || {
+                let _active = activate(inherited);
+                record(AccessKind::ZipMember, "crate.zip", Some("a/b.json"), b"");
+            })
+            .join()
+            .unwrap();
+        }
+        record(AccessKind::File, "after.json", None, b"ignored");
+
+        let records = log.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].bytes, 3);
+        assert_eq!(
+            records[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(records[1].member.as_deref(), Some("a/b.json"));
+        assert_eq!(
+            serde_json::to_value(&records[1]).unwrap()["kind"],
+            "zip_member"
+        );
+    }
+}
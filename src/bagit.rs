@@ -0,0 +1,398 @@
+//! RO-Crates packaged in BagIt bags
+//!
+//! Many repositories ship crates inside [BagIt](https://www.rfc-editor.org/rfc/rfc8493)
+//! bags: a `bagit.txt` declaration, the crate as the payload in `data/`, and
+//! `manifest-<algorithm>.txt` files listing the checksum of every payload
+//! file. Loaders look for the crate in the payload of bags, in directories
+//! as in zip and tar archives, and [`verify_bag`] checks a bag's payload
+//! against its manifests before consolidating.
+//!
+//! Only SHA-256 and SHA-512 manifests are verified; manifests with other
+//! algorithms are ignored, and a bag with none of those fails verification.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256, Sha512};
+use zip::ZipArchive;
+
+use crate::error::{BoxError, ConsolidateError};
+use crate::loader::{is_tar_path, open_tar, tar_entry_name};
+
+/// The bag declaration identifying a BagIt bag
+pub const BAG_DECLARATION: &str = "bagit.txt";
+
+/// Directory holding a bag's payload
+pub const BAG_PAYLOAD_DIR: &str = "data";
+
+/// Checksum algorithms of verified manifests
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Algorithm {
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// The algorithm of a payload manifest, by its file name
+    fn of_manifest(name: &str) -> Option<Self> {
+        match name {
+            "manifest-sha256.txt" => Some(Algorithm::Sha256),
+            "manifest-sha512.txt" => Some(Algorithm::Sha512),
+            _ => None,
+        }
+    }
+
+    /// Hex-encoded checksum of everything `reader` yields
+    fn checksum(self, reader: &mut impl Read) -> io::Result<String> {
+        let digest = match self {
+            Algorithm::Sha256 => {
+                let mut hasher = Sha256::new();
+                io::copy(reader, &mut hasher)?;
+                hasher.finalize().to_vec()
+            }
+            Algorithm::Sha512 => {
+                let mut hasher = Sha512::new();
+                io::copy(reader, &mut hasher)?;
+                hasher.finalize().to_vec()
+            }
+        };
+        Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+}
+
+/// Payload directory of the bag in `dir`, if it is one
+pub fn bag_payload_dir(dir: &Path) -> Option<PathBuf> {
+    let payload = dir.join(BAG_PAYLOAD_DIR);
+    (dir.join(BAG_DECLARATION).is_file() && payload.is_dir()).then_some(payload)
+}
+
+/// Directory of the bag among the entries of an archive: "" if the bag
+/// declaration is at the archive root, or its single top-level folder
+/// (ending with '/')
+pub(crate) fn find_bag_prefix(entries: &[String]) -> Option<String> {
+    entries.iter().find_map(|entry| {
+        let prefix = entry.strip_suffix(BAG_DECLARATION)?;
+        (prefix.is_empty()
+            || prefix.trim_end_matches('/').split('/').count() == 1 && prefix.ends_with('/'))
+        .then(|| prefix.to_string())
+    })
+}
+
+/// Verify the payload of the bag at `path` (a directory, zip or tar file)
+/// against its manifests, returning the number of files verified
+///
+/// Fails with [`ConsolidateError::BagVerification`] listing every file that
+/// is missing, has a different checksum, or isn't in the manifests.
+pub fn verify_bag(path: &Path) -> Result<usize, ConsolidateError> {
+    let verification = if path.is_dir() {
+        verify_dir_bag(path)?
+    } else if is_tar_path(path) {
+        verify_tar_bag(path)?
+    } else {
+        verify_zip_bag(path)?
+    };
+    verification.finish(path)
+}
+
+/// Checksums expected by a bag's manifests, and the failures found so far
+#[derive(Debug, Default)]
+struct Verification {
+    /// Expected checksums by payload path (e.g. "data/file.csv")
+    expected: BTreeMap<String, Vec<(Algorithm, String)>>,
+    /// Payload paths checked
+    seen: BTreeSet<String>,
+    failures: Vec<String>,
+}
+
+impl Verification {
+    /// Add the entries of the manifest `name`, ignoring manifests of
+    /// algorithms that aren't supported
+    fn add_manifest(&mut self, name: &str, content: &str) {
+        let Some(algorithm) = Algorithm::of_manifest(name) else {
+            return;
+        };
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match line.split_once(char::is_whitespace) {
+                Some((checksum, path)) => self
+                    .expected
+                    .entry(decode_manifest_path(path.trim_start()))
+                    .or_default()
+                    .push((algorithm, checksum.to_ascii_lowercase())),
+                None => self
+                    .failures
+                    .push(format!("{}: invalid line '{}'", name, line)),
+            }
+        }
+    }
+
+    /// Check the payload file at `path`, read from `open`
+    fn check<R: Read>(&mut self, path: &str, open: impl FnOnce() -> io::Result<R>) {
+        self.seen.insert(path.to_string());
+        // Checking against one manifest is enough, the strongest if several
+        let Some((algorithm, checksum)) = self.expected.get(path).and_then(|e| e.iter().max())
+        else {
+            self.failures.push(format!("{}: not in any manifest", path));
+            return;
+        };
+        match open().and_then(|mut reader| algorithm.checksum(&mut reader)) {
+            Ok(actual) if actual == *checksum => {}
+            Ok(_) => self.failures.push(format!("{}: checksum mismatch", path)),
+            Err(e) => self.failures.push(format!("{}: {}", path, e)),
+        }
+    }
+
+    /// Report missing files and all failures
+    fn finish(mut self, path: &Path) -> Result<usize, ConsolidateError> {
+        if self.expected.is_empty() && self.failures.is_empty() {
+            self.failures
+                .push("no SHA-256 or SHA-512 payload manifest".to_string());
+        }
+        for missing in self.expected.keys().filter(|p| !self.seen.contains(*p)) {
+            self.failures.push(format!("{}: missing", missing));
+        }
+        if self.failures.is_empty() {
+            Ok(self.seen.len())
+        } else {
+            Err(ConsolidateError::BagVerification {
+                path: path.display().to_string(),
+                failures: self.failures,
+            })
+        }
+    }
+}
+
+/// Decode the percent-encoded line breaks and '%' of a manifest path
+fn decode_manifest_path(path: &str) -> String {
+    path.replace("%0A", "\n")
+        .replace("%0D", "\r")
+        .replace("%25", "%")
+}
+
+fn verify_dir_bag(dir: &Path) -> Result<Verification, ConsolidateError> {
+    let mut verification = Verification::default();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(name) = entry.file_name().to_str() {
+            if Algorithm::of_manifest(name).is_some() {
+                verification.add_manifest(name, &std::fs::read_to_string(entry.path())?);
+            }
+        }
+    }
+
+    let mut pending = vec![(dir.join(BAG_PAYLOAD_DIR), BAG_PAYLOAD_DIR.to_string())];
+    while let Some((path, name)) = pending.pop() {
+        let mut entries = std::fs::read_dir(&path)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let child = format!("{}/{}", name, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                pending.push((entry.path(), child));
+            } else {
+                verification.check(&child, || File::open(entry.path()));
+            }
+        }
+    }
+    Ok(verification)
+}
+
+fn verify_zip_bag(path: &Path) -> Result<Verification, ConsolidateError> {
+    let load_error = |reason: String, source: Option<BoxError>| ConsolidateError::LoadError {
+        path: path.display().to_string(),
+        reason,
+        source,
+    };
+    let mut archive = ZipArchive::new(File::open(path)?)
+        .map_err(|e| load_error(format!("Failed to read zip archive: {}", e), Some(e.into())))?;
+    let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    let prefix =
+        find_bag_prefix(&names).ok_or_else(|| load_error("not a BagIt bag".to_string(), None))?;
+
+    let mut verification = Verification::default();
+    for name in &names {
+        let Some(manifest) = name.strip_prefix(&prefix) else {
+            continue;
+        };
+        if Algorithm::of_manifest(manifest).is_some() {
+            let mut content = String::new();
+            archive
+                .by_name(name)
+                .map_err(|e| {
+                    load_error(format!("Failed to extract {}: {}", name, e), Some(e.into()))
+                })?
+                .read_to_string(&mut content)?;
+            verification.add_manifest(manifest, &content);
+        }
+    }
+    let payload = format!("{}{}/", prefix, BAG_PAYLOAD_DIR);
+    for name in names.iter().filter(|name| !name.ends_with('/')) {
+        if let Some(file) = name.strip_prefix(&payload) {
+            let relative = format!("{}/{}", BAG_PAYLOAD_DIR, file);
+            verification.check(&relative, || {
+                archive.by_name(name).map_err(io::Error::other)
+            });
+        }
+    }
+    Ok(verification)
+}
+
+fn verify_tar_bag(path: &Path) -> Result<Verification, ConsolidateError> {
+    let read_error = |e: io::Error| ConsolidateError::LoadError {
+        path: path.display().to_string(),
+        reason: format!("Failed to read tar archive: {}", e),
+        source: Some(e.into()),
+    };
+
+    // Manifests may follow the payload, so they're read in a first pass
+    let mut names = Vec::new();
+    let mut manifests = Vec::new();
+    for entry in open_tar(path)?.entries().map_err(read_error)? {
+        let mut entry = entry.map_err(read_error)?;
+        let Some(name) = tar_entry_name(&entry) else {
+            continue;
+        };
+        let manifest = name.rsplit('/').next().unwrap_or(&name).to_string();
+        if Algorithm::of_manifest(&manifest).is_some() {
+            let mut content = String::new();
+            entry.read_to_string(&mut content).map_err(read_error)?;
+            manifests.push((name.clone(), manifest, content));
+        }
+        names.push(name);
+    }
+    let prefix = find_bag_prefix(&names).ok_or_else(|| ConsolidateError::LoadError {
+        path: path.display().to_string(),
+        reason: "not a BagIt bag".to_string(),
+        source: None,
+    })?;
+
+    let mut verification = Verification::default();
+    for (name, manifest, content) in manifests {
+        if name == format!("{}{}", prefix, manifest) {
+            verification.add_manifest(&manifest, &content);
+        }
+    }
+    let payload = format!("{}{}/", prefix, BAG_PAYLOAD_DIR);
+    for entry in open_tar(path)?.entries().map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let Some(name) = tar_entry_name(&entry) else {
+            continue;
+        };
+        if let Some(file) = name.strip_prefix(&payload) {
+            let relative = format!("{}/{}", BAG_PAYLOAD_DIR, file);
+            verification.check(&relative, || Ok(entry));
+        }
+    }
+    Ok(verification)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a bag holding a crate to `dir`, with a SHA-256 manifest
+    fn write_bag(dir: &Path) {
+        let metadata = r#"{"@graph": [{"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+            {"@id": "./", "@type": "Dataset"}]}"#;
+        std::fs::create_dir_all(dir.join("data/sub")).unwrap();
+        std::fs::write(dir.join("bagit.txt"), "BagIt-Version: 1.0\n").unwrap();
+        std::fs::write(dir.join("data/ro-crate-metadata.json"), metadata).unwrap();
+        std::fs::write(dir.join("data/sub/file.csv"), "a,b\n").unwrap();
+        let sha256 = |content: &[u8]| {
+            Algorithm::Sha256
+                .checksum(&mut std::io::Cursor::new(content))
+                .unwrap()
+        };
+        let manifest = format!(
+            "{}  data/ro-crate-metadata.json\n{} data/sub/file.csv\n",
+            sha256(metadata.as_bytes()),
+            sha256(b"a,b\n")
+        );
+        std::fs::write(dir.join("manifest-sha256.txt"), manifest).unwrap();
+        std::fs::write(dir.join("manifest-md5.txt"), "0 data/ignored\n").unwrap();
+    }
+
+    #[test]
+    fn test_verify_dir_bag() {
+        let dir = std::env::temp_dir().join(format!("bag-{}", std::process::id()));
+        write_bag(&dir);
+        assert_eq!(bag_payload_dir(&dir), Some(dir.join("data")));
+        assert_eq!(bag_payload_dir(&dir.join("data")), None);
+        assert_eq!(verify_bag(&dir).unwrap(), 2);
+
+        std::fs::write(dir.join("data/sub/file.csv"), "a,c\n").unwrap();
+        std::fs::write(dir.join("data/extra.txt"), "").unwrap();
+        let err = verify_bag(&dir).unwrap_err();
+        assert_eq!(err.code(), "bag_verification");
+        let ConsolidateError::BagVerification { failures, .. } = err else {
+            unreachable!();
+        };
+        assert_eq!(
+            failures,
+            vec![
+                "data/extra.txt: not in any manifest",
+                "data/sub/file.csv: checksum mismatch"
+            ]
+        );
+
+        std::fs::remove_file(dir.join("data/sub/file.csv")).unwrap();
+        std::fs::remove_file(dir.join("data/extra.txt")).unwrap();
+        let err = verify_bag(&dir).unwrap_err();
+        assert!(err.to_string().ends_with("data/sub/file.csv: missing"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify_archived_bag() {
+        let dir = std::env::temp_dir().join(format!("bag-archive-{}", std::process::id()));
+        write_bag(&dir.join("bag"));
+
+        let tar_path = dir.join("bag.tar");
+        let mut builder = tar::Builder::new(File::create(&tar_path).unwrap());
+        builder.append_dir_all("bag", dir.join("bag")).unwrap();
+        builder.into_inner().unwrap();
+        assert_eq!(verify_bag(&tar_path).unwrap(), 2);
+
+        let zip_path = dir.join("bag.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for name in [
+            "bagit.txt",
+            "manifest-sha256.txt",
+            "data/ro-crate-metadata.json",
+            "data/sub/file.csv",
+        ] {
+            writer
+                .start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            let content = std::fs::read(dir.join("bag").join(name)).unwrap();
+            std::io::Write::write_all(&mut writer, &content).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(verify_bag(&zip_path).unwrap(), 2);
+
+        let (_, content, prefix) = crate::loader::load_from_zip(&zip_path).unwrap();
+        assert!(content.contains("Dataset"));
+        assert_eq!(prefix, "data");
+        let (_, _, prefix) = crate::loader::load_from_tar(&tar_path).unwrap();
+        assert_eq!(prefix, "bag/data");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_bag_prefix() {
+        let entries = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            find_bag_prefix(&entries(&["data/x", "bagit.txt"])),
+            Some(String::new())
+        );
+        assert_eq!(
+            find_bag_prefix(&entries(&["bag/bagit.txt"])),
+            Some("bag/".to_string())
+        );
+        assert_eq!(find_bag_prefix(&entries(&["a/b/bagit.txt"])), None);
+        assert_eq!(find_bag_prefix(&entries(&["notbagit.txt"])), None);
+    }
+}
@@ -2,15 +2,48 @@
 //!
 //! Command-line tool for consolidating RO-Crate hierarchies and merging crates.
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 
-use clap::{Args, Parser, Subcommand};
-use serde_json::Value;
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
 
+use rocrate_consolidate::audit::read_file;
+use rocrate_consolidate::id::{namespace_from_folder_id, normalize_id};
+use rocrate_consolidate::loader::{
+    fetch_url_with, find_subcrate_metadata_in_tar, find_subcrate_metadata_in_zip, go_offline,
+    is_tar_path, limit_reads, load_from_tar, load_from_tar_subpath, load_from_url_with,
+    load_from_zip_subpath, set_retry_wait_budget, tar_root_prefix, zip_root_prefix, HttpAuth,
+    UrlLoaderOptions, DEFAULT_RETRY_WAIT_BUDGET, DEFAULT_TIMEOUT,
+};
+use rocrate_consolidate::metadata_file::{
+    metadata_file_patterns, metadata_file_rank, use_metadata_file_patterns,
+};
+use rocrate_consolidate::output::to_styled_string;
+use rocrate_consolidate::path::{component_to_id, join_id, path_to_string};
+use rocrate_consolidate::vocab::METADATA_DESCRIPTOR_ID;
+#[cfg(feature = "scripting")]
+use rocrate_consolidate::EntityScript;
 use rocrate_consolidate::{
-    consolidate, load_from_url, parse_graph, to_json_string, ConsolidateError, ConsolidateInput,
-    ConsolidateOptions, MergeCrate, NoOpLoader, SubcrateLoader, UrlLoader,
+    bag_payload_dir, build_manifest, build_sitemap, check_freshness_with, collection_graph,
+    consolidate, consolidate_mapped, deconsolidate, expand_folder_template, load_from_zip,
+    manifest_to_csv, notification, parse_graph, parse_raw_graph, plan_fetches, profile,
+    profile_crate, sitemap_entity, split_s3_url, to_json_string_styled, unique_folder_id,
+    upload_crate, verify_bag, AggregateCoverage, ArunaClient, ArunaLoader, AuditLog, BoxError,
+    BuiltinRule, CaseCollisionPolicy, ConflictStrategy, ConsolidateCitations, ConsolidateError,
+    ConsolidateInput, ConsolidateOptions, ConsolidateResult, DirSubcrateCache, DistributionPointer,
+    EntityLibrary, FragmentIdPolicy, Freshness, HarvestProtocol, HarvestState, HarvestedRecord,
+    Harvester, HtmlReport, HttpCache, HybridLoader, KeyEntityRanking, KeyOrder, LintProfile,
+    Linter, ManifestLoader, MappedFile, MentionKeyEntities, MergeCrate, Mirrors,
+    MissingDescriptorPolicy, MultiRootPolicy, NoOpLoader, NormalizeLicenses, NotifyFormat,
+    OutputStyle, Pipeline, PostProcessor, Profile, PropertyStrategy, ReferenceMergePolicy,
+    Registry, RootHandling, S3Client, S3Loader, Severity, ShapePolicy, SourceLocation,
+    SubcrateCache, SubcrateLoader, SubcrateOverrides, TemplateVars, UrlLoader, ValueComparison,
+    ARUNA_SCHEME, S3_SCHEME,
 };
 
 #[derive(Parser)]
@@ -20,6 +53,66 @@ use rocrate_consolidate::{
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Print nothing; the exit status tells whether the run succeeded
+    #[arg(long, global = true, conflicts_with = "summary")]
+    quiet: bool,
+
+    /// Print a single JSON summary line instead of progress messages
+    ///
+    /// The line goes to stdout, or to stderr when the crate is written to stdout.
+    #[arg(long, global = true)]
+    summary: bool,
+
+    /// Longest time in seconds the fetches of each consolidation may wait in
+    /// total before retrying
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = DEFAULT_RETRY_WAIT_BUDGET.as_secs())]
+    retry_wait_budget: u64,
+
+    /// Timeout in seconds of each HTTP request, 0 for none
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+
+    /// How often a failed HTTP request is retried
+    #[arg(long, global = true, value_name = "N", default_value_t = UrlLoaderOptions::DEFAULT.retries)]
+    retries: u32,
+
+    /// File name pattern of metadata files, e.g. ro-crate-metadata.jsonld
+    /// (repeatable, earlier patterns are preferred; replaces the default
+    /// ro-crate-metadata.json and *-ro-crate-metadata.json)
+    #[arg(long = "metadata-name", global = true, value_name = "PATTERN")]
+    metadata_names: Vec<String>,
+}
+
+/// How much the CLI reports while it runs
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+enum Verbosity {
+    /// Progress messages, warnings and errors on stderr
+    #[default]
+    Normal,
+    /// One JSON summary line
+    Summary,
+    /// Nothing
+    Quiet,
+}
+
+static VERBOSITY: OnceLock<Verbosity> = OnceLock::new();
+
+fn verbosity() -> Verbosity {
+    VERBOSITY.get().copied().unwrap_or_default()
+}
+
+/// External accesses of the command, recorded on the main thread and the
+/// threads consolidation spawns
+static AUDIT_LOG: OnceLock<Arc<AuditLog>> = OnceLock::new();
+
+/// Print a progress message to stderr, unless quiet or summarizing
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if verbosity() == Verbosity::Normal {
+            eprintln!($($arg)*);
+        }
+    };
 }
 
 #[derive(Subcommand)]
@@ -28,6 +121,30 @@ enum Commands {
     Consolidate(ConsolidateArgs),
     /// Merge multiple independent crates
     Merge(MergeArgs),
+    /// Harvest a repository's crates into a collection crate
+    Harvest(HarvestArgs),
+    /// Move a crate's ids into a folder, without consolidating or merging it
+    Namespace(NamespaceArgs),
+    /// Split a consolidated crate back into its nested subcrates
+    Deconsolidate(DeconsolidateArgs),
+    /// Check a crate's graph for broken references, descriptor and root
+    Lint(LintArgs),
+    /// Serve consolidation over gRPC
+    #[cfg(feature = "grpc")]
+    ServeGrpc(ServeGrpcArgs),
+    /// Work with the consolidation vocabulary
+    #[command(subcommand)]
+    Vocab(VocabCommand),
+}
+
+#[derive(Subcommand)]
+enum VocabCommand {
+    /// Write the consolidation profile, defining its terms, as a Profile Crate
+    Export {
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Args)]
@@ -39,17 +156,260 @@ struct ConsolidateArgs {
     #[arg(short, long)]
     output: Option<PathBuf>,
 
-    /// Pretty-print JSON output
-    #[arg(long)]
-    pretty: bool,
+    #[command(flatten)]
+    style: StyleArgs,
 
     /// Don't add Subcrate type to converted folders
     #[arg(long)]
     no_subcrate_type: bool,
 
+    /// Give subcrate folders whose root has type or conformsTo profile NATURE
+    /// the extra TYPE too (repeatable)
+    #[arg(long = "subcrate-type", value_name = "NATURE=TYPE", value_parser = parse_subcrate_type)]
+    subcrate_types: Vec<(String, String)>,
+
     /// Don't extend @context with consolidation vocabulary
     #[arg(long)]
     no_extend_context: bool,
+
+    /// Flatten subcrates instead of describing them as Subcrate folders:
+    /// their parts become parts of the root, and no consolidation vocabulary
+    /// is used
+    #[arg(long)]
+    flatten: bool,
+
+    /// Fail if a contentUrl or distribution pointer is not an absolute URI
+    #[arg(long)]
+    require_absolute_pointers: bool,
+
+    /// Emit root and relative ids against this absolute IRI instead of "./"
+    #[arg(long, value_name = "IRI")]
+    base_id: Option<String>,
+
+    /// Keep subcrate descriptors as files of their folders instead of dropping them
+    #[arg(long)]
+    keep_subcrate_descriptors: bool,
+
+    /// @id of the output's metadata descriptor (default: the output file's
+    /// name if it ends in ro-crate-metadata.json, else the root crate's)
+    #[arg(long, value_name = "FILENAME")]
+    descriptor_id: Option<String>,
+
+    /// Compare ids exactly as written instead of after Unicode NFC normalization
+    #[arg(long)]
+    no_normalize_unicode: bool,
+
+    /// Take copies of shared entities, and values merged into arrays, to be
+    /// the same if they only differ in the Unicode form of their @ids or in
+    /// wrapping values in plain value objects
+    #[arg(long)]
+    canonical_values: bool,
+
+    /// Describe the run as a CreateAction recording the options used
+    #[arg(long)]
+    provenance: bool,
+
+    /// Skip the run, exiting with status 3, if the output was written from
+    /// the same inputs with the same options (implies --provenance)
+    #[arg(long, requires = "output")]
+    if_changed: bool,
+
+    /// Keep the entities of each collected crate in DIR, and reuse those of
+    /// unchanged crates in later runs; fetched remote metadata is kept too and
+    /// only downloaded again if it changed
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// How to handle crates with more than one root entity
+    #[arg(long, value_enum, default_value_t = MultiRootArg::PickDescriptorAbout)]
+    multi_root: MultiRootArg,
+
+    /// How to handle subcrates without a metadata descriptor
+    #[arg(long, value_enum, default_value_t = MissingDescriptorArg::Synthesize)]
+    missing_descriptor: MissingDescriptorArg,
+
+    /// Whether subcrate folders merge in the parent's reference or keep only
+    /// the subcrate root's own properties
+    #[arg(long, value_enum, default_value_t = RootHandlingArg::MergeReference)]
+    root_handling: RootHandlingArg,
+
+    /// Which side wins properties both a subcrate's root and the parent's
+    /// reference to it set
+    #[arg(long, value_enum, default_value_t = ReferenceMergeArg::Union)]
+    reference_merge: ReferenceMergeArg,
+
+    /// How to merge properties shared entities set to different single
+    /// values
+    #[arg(long, value_enum, default_value_t = ConflictStrategyArg::Union)]
+    conflict_strategy: ConflictStrategyArg,
+
+    /// Merge shared entities by the policy registered under this name
+    /// (union, prefer-main, prefer-incoming, error or latest-dates), below
+    /// --property-strategy and --merge-rules
+    #[arg(long, value_name = "NAME[=ARG]", conflicts_with = "conflict_strategy")]
+    merge_policy: Option<String>,
+
+    /// Merge PROPERTY of shared entities by STRATEGY: union, prefer-main,
+    /// prefer-incoming, latest or earliest (repeatable)
+    #[arg(long = "property-strategy", value_name = "PROPERTY=STRATEGY", value_parser = parse_property_strategy)]
+    property_strategies: Vec<(String, PropertyStrategy)>,
+
+    /// TOML or JSON file of property strategies, e.g. dateModified =
+    /// "latest", overridden by --property-strategy
+    #[arg(long, value_name = "FILE")]
+    merge_rules: Option<PathBuf>,
+
+    /// Link each subcrate folder to the folder (or root) it was nested in
+    /// with isPartOf
+    #[arg(long)]
+    link_parents: bool,
+
+    /// Consolidate only the first N levels of nested subcrates, leaving
+    /// deeper ones as plain Dataset references
+    #[arg(long, value_name = "N")]
+    depth: Option<usize>,
+
+    /// Consolidate only subcrates whose folder id matches PATTERN, e.g.
+    /// './experiments/*' (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Leave subcrates whose folder id matches PATTERN plain references
+    /// (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// TOML or JSON file (or inline JSON object) of options for the
+    /// subcrates whose folder id matches a pattern, e.g. "./instrument/" =
+    /// { strip_properties = ["email"], exclude_types = ["PropertyValue"],
+    /// max_depth = 0, conflict_strategy = "prefer-main" }
+    #[arg(long, value_name = "FILE_OR_JSON")]
+    subcrate_overrides: Option<String>,
+
+    /// Consolidate the subcrate at PATH below the root crate (or URL) into
+    /// the folder ./NAMESPACE/ instead, e.g. raw_data_v2=raw (repeatable)
+    #[arg(long = "rename-namespace", value_name = "PATH=NAMESPACE", value_parser = parse_namespace_rename)]
+    namespace_renames: Vec<(String, String)>,
+
+    /// TOML or JSON file (or inline JSON object) of namespaces by subcrate
+    /// path, e.g. raw_data_v2 = "raw", overridden by --rename-namespace
+    #[arg(long, value_name = "FILE_OR_JSON")]
+    namespace_map: Option<String>,
+
+    /// How to handle subcrate folders whose names differ only in case
+    #[arg(long, value_enum, default_value_t = CaseCollisionArg::Warn)]
+    case_collisions: CaseCollisionArg,
+
+    /// Don't follow symlinked subcrates that lead outside the source crate
+    #[arg(long)]
+    confine_links: bool,
+
+    /// Fetch the subcrates a local crate references by URL (or by a remote
+    /// subjectOf) instead of skipping them
+    #[arg(long)]
+    allow_remote: bool,
+
+    /// Verify the payload checksums of a source packaged as a BagIt bag
+    /// (directory, zip or tar file) before consolidating it
+    #[arg(long)]
+    verify_bag: bool,
+
+    /// Also consolidate the subcrates listed in this JSON manifest (path or
+    /// URL; requires a URL source)
+    ///
+    /// The manifest is an array of subcrate URLs, or an object with one under
+    /// "subcrates". Listed crates are loaded even if their parent doesn't mark
+    /// them as RO-Crates.
+    #[arg(long, value_name = "PATH_OR_URL")]
+    subcrate_manifest: Option<String>,
+
+    /// Load the subcrates with the loader registered under this name
+    /// (filesystem, archive, url, s3 or none) instead of the one the source
+    /// calls for
+    #[arg(long, value_name = "NAME")]
+    loader: Option<String>,
+
+    /// Fetch URLs starting with SOURCE from MIRROR instead when they fail
+    /// (repeatable; mirrors are tried in order)
+    #[arg(long = "mirror", value_name = "SOURCE=MIRROR", value_parser = parse_mirror)]
+    mirrors: Vec<(String, String)>,
+
+    /// Base URL of the Aruna REST gateway, for sources like aruna://<resource-id>
+    #[arg(long, value_name = "URL")]
+    aruna_endpoint: Option<String>,
+
+    /// Aruna API token (rather set ROCRATE_CONSOLIDATE_ARUNA_TOKEN than pass it here)
+    #[arg(long, value_name = "TOKEN")]
+    aruna_token: Option<String>,
+
+    /// Also write the consolidated crate back to Aruna, as a new dataset NAME
+    /// below the project or collection PARENT (requires --aruna-endpoint)
+    #[arg(long, value_name = "PARENT=NAME", value_parser = parse_aruna_upload)]
+    aruna_upload: Option<(String, String)>,
+
+    /// Base URL of the object store, for sources like s3://<bucket>/<key>
+    /// (AWS_ENDPOINT_URL or AWS's endpoint of AWS_REGION by default)
+    #[arg(long, value_name = "URL")]
+    s3_endpoint: Option<String>,
+
+    /// Fail if a subcrate cannot be loaded instead of skipping it
+    #[arg(long)]
+    strict: bool,
+
+    /// Fail instead of fetching anything over the network
+    #[arg(long)]
+    offline: bool,
+
+    /// Fail on a crate whose metadata takes more than BYTES as compact JSON
+    #[arg(long, value_name = "BYTES")]
+    max_metadata_bytes: Option<u64>,
+
+    /// Fail on a crate with more than N entities
+    #[arg(long, value_name = "N")]
+    max_crate_entities: Option<usize>,
+
+    /// Fail once the crates loaded have more than N entities in total
+    #[arg(long, value_name = "N")]
+    max_entities: Option<usize>,
+
+    /// On failure, still write the partially consolidated result
+    #[arg(long)]
+    keep_partial: bool,
+
+    /// How single property values are shaped in the output
+    #[arg(long, value_enum, default_value_t = ShapeArg::AsIs)]
+    shape: ShapeArg,
+
+    /// Instead of consolidating, list the URLs a run would fetch, one per
+    /// line, as far as the source's metadata tells
+    ///
+    /// Subcrates are not loaded, so those only referenced from within other
+    /// subcrates are missing from the list.
+    #[arg(long)]
+    plan_fetches: bool,
+
+    /// Map the local metadata file read-only instead of reading it
+    ///
+    /// Meant for huge crates, together with --passthrough.
+    #[arg(long, conflicts_with_all = ["plan_fetches", "profile", "manifest"])]
+    mmap: bool,
+
+    /// Write the entities consolidation doesn't change verbatim from the
+    /// local metadata file, instead of parsing and serializing them
+    ///
+    /// Speeds up crates with many files and absolute ids, which are written
+    /// after the other entities.
+    #[arg(long, conflicts_with_all = ["plan_fetches", "profile", "manifest"])]
+    passthrough: bool,
+
+    #[command(flatten)]
+    auth: AuthArgs,
+
+    #[command(flatten)]
+    post_processing: PostProcessArgs,
+
+    #[command(flatten)]
+    reports: ReportArgs,
 }
 
 #[derive(Args)]
@@ -59,13 +419,40 @@ struct MergeArgs {
 
     /// Crates to merge: --merge <path_or_url> --as <folder_id> [--name <name>]
     /// Can be repeated for multiple crates
+    ///
+    /// A path with '*', '?' or '[' is a glob (e.g. 'crates/*.tar.gz'); the crates
+    /// it matches are named like those of --merge-dir.
     #[arg(long = "merge", value_name = "PATH_OR_URL")]
     merge_sources: Vec<String>,
 
-    /// Folder IDs for merged crates (must match number of --merge args)
+    /// Folder IDs for merged crates (must match number of --merge args that
+    /// aren't globs)
     #[arg(long = "as", value_name = "FOLDER_ID")]
     folder_ids: Vec<String>,
 
+    /// Merge the crates listed in this file, one path or URL per line ('-' for
+    /// stdin; repeatable)
+    ///
+    /// Blank lines and lines starting with '#' are ignored, and relative paths
+    /// are resolved against the file's directory. Folder IDs are generated as
+    /// for --merge-dir.
+    #[arg(long = "merge-list", value_name = "FILE")]
+    merge_lists: Vec<PathBuf>,
+
+    /// Merge every crate directory, zip and tar file in this directory
+    /// (repeatable)
+    ///
+    /// Their folder IDs come from --as-template, or are named after them.
+    #[arg(long = "merge-dir", value_name = "DIR")]
+    merge_dirs: Vec<PathBuf>,
+
+    /// Generate folder IDs from each merged crate instead of giving --as, e.g.
+    /// '{name-slug}-{index}/' (placeholders: name, name-slug, source-slug, index)
+    ///
+    /// IDs already taken get a "-2", "-3", ... suffix.
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "folder_ids")]
+    as_template: Option<String>,
+
     /// Optional names for merged crate folders
     #[arg(long = "name", value_name = "NAME")]
     names: Vec<String>,
@@ -74,232 +461,3231 @@ struct MergeArgs {
     #[arg(short, long)]
     output: Option<PathBuf>,
 
-    /// Pretty-print JSON output
-    #[arg(long)]
-    pretty: bool,
+    #[command(flatten)]
+    style: StyleArgs,
 
     /// Don't add Subcrate type to converted folders
     #[arg(long)]
     no_subcrate_type: bool,
 
+    /// Give subcrate folders whose root has type or conformsTo profile NATURE
+    /// the extra TYPE too (repeatable)
+    #[arg(long = "subcrate-type", value_name = "NATURE=TYPE", value_parser = parse_subcrate_type)]
+    subcrate_types: Vec<(String, String)>,
+
     /// Don't extend @context
     #[arg(long)]
     no_extend_context: bool,
-}
 
-/// Check if a source string is a URL
-fn is_url(source: &str) -> bool {
-    source.starts_with("http://") || source.starts_with("https://")
-}
+    /// Flatten subcrates instead of describing them as Subcrate folders:
+    /// their parts become parts of the root, and no consolidation vocabulary
+    /// is used
+    #[arg(long)]
+    flatten: bool,
 
-/// Filesystem-based subcrate loader
-struct FilesystemLoader {
-    base_path: PathBuf,
-}
+    /// Fail if a contentUrl or distribution pointer is not an absolute URI
+    #[arg(long)]
+    require_absolute_pointers: bool,
 
-impl FilesystemLoader {
-    fn new(base_path: PathBuf) -> Self {
-        Self { base_path }
-    }
-}
+    /// Emit root and relative ids against this absolute IRI instead of "./"
+    #[arg(long, value_name = "IRI")]
+    base_id: Option<String>,
 
-impl SubcrateLoader for FilesystemLoader {
-    fn load(
-        &self,
-        subcrate_id: &str,
-        parent_namespace: &str,
-        _subcrate_entity: Option<&Value>,
-    ) -> Result<Vec<Value>, ConsolidateError> {
-        // Build the path to the subcrate
-        let subcrate_path = if parent_namespace.is_empty() {
-            // Direct child of root
-            let relative = subcrate_id.trim_start_matches("./").trim_end_matches('/');
-            self.base_path.join(relative)
-        } else {
-            // Nested subcrate
-            let full_path = format!(
-                "{}/{}",
-                parent_namespace,
-                subcrate_id.trim_start_matches("./").trim_end_matches('/')
-            );
-            self.base_path.join(full_path)
-        };
+    /// Keep subcrate descriptors as files of their folders instead of dropping them
+    #[arg(long)]
+    keep_subcrate_descriptors: bool,
 
-        // Load the metadata file
-        let metadata_path = find_metadata_file(&subcrate_path)?;
-        let content =
-            fs::read_to_string(&metadata_path).map_err(|e| ConsolidateError::LoadError {
-                path: metadata_path.display().to_string(),
-                reason: e.to_string(),
-            })?;
+    /// Verify the payload checksums of the main and merged crates, which must
+    /// all be local BagIt bags, before merging them
+    #[arg(long)]
+    verify_bag: bool,
 
-        parse_graph(&content, &metadata_path.display().to_string())
-    }
-}
+    /// @id of the output's metadata descriptor (default: the output file's
+    /// name if it ends in ro-crate-metadata.json, else the root crate's)
+    #[arg(long, value_name = "FILENAME")]
+    descriptor_id: Option<String>,
 
-/// Find ro-crate-metadata.json in a directory
-fn find_metadata_file(dir: &PathBuf) -> Result<PathBuf, ConsolidateError> {
-    let standard = dir.join("ro-crate-metadata.json");
-    if standard.exists() {
-        return Ok(standard);
-    }
+    /// Compare ids exactly as written instead of after Unicode NFC normalization
+    #[arg(long)]
+    no_normalize_unicode: bool,
 
-    // Look for *-ro-crate-metadata.json
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            if let Some(name) = entry.file_name().to_str() {
-                if name.ends_with("-ro-crate-metadata.json") {
-                    return Ok(entry.path());
-                }
-            }
-        }
-    }
+    /// Take copies of shared entities, and values merged into arrays, to be
+    /// the same if they only differ in the Unicode form of their @ids or in
+    /// wrapping values in plain value objects
+    #[arg(long)]
+    canonical_values: bool,
 
-    Err(ConsolidateError::LoadError {
-        path: dir.display().to_string(),
-        reason: "No ro-crate-metadata.json found".to_string(),
-    })
-}
+    /// Describe the run as a CreateAction recording the options used
+    #[arg(long)]
+    provenance: bool,
 
-/// Load a crate's @graph from a path (local file/directory)
-fn load_graph_from_path(path: &PathBuf) -> Result<Vec<Value>, ConsolidateError> {
-    let metadata_path = if path.is_dir() {
-        find_metadata_file(path)?
-    } else if path.is_file() {
-        path.clone()
-    } else {
-        return Err(ConsolidateError::InvalidPath(path.clone()));
-    };
+    /// Skip the run, exiting with status 3, if the output was written from
+    /// the same inputs with the same options (implies --provenance)
+    #[arg(long, requires = "output")]
+    if_changed: bool,
 
-    let content = fs::read_to_string(&metadata_path).map_err(|e| ConsolidateError::LoadError {
-        path: metadata_path.display().to_string(),
-        reason: e.to_string(),
-    })?;
+    /// Keep the entities of each collected crate in DIR, and reuse those of
+    /// unchanged crates in later runs; fetched remote metadata is kept too and
+    /// only downloaded again if it changed
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
 
-    parse_graph(&content, &metadata_path.display().to_string())
-}
+    /// How to handle crates with more than one root entity
+    #[arg(long, value_enum, default_value_t = MultiRootArg::PickDescriptorAbout)]
+    multi_root: MultiRootArg,
 
-/// Load a crate's @graph from a URL
-fn load_graph_from_url(url: &str) -> Result<Vec<Value>, ConsolidateError> {
-    let (_, content) = load_from_url(url)?;
-    parse_graph(&content, url)
-}
+    /// How to handle subcrates without a metadata descriptor
+    #[arg(long, value_enum, default_value_t = MissingDescriptorArg::Synthesize)]
+    missing_descriptor: MissingDescriptorArg,
 
-/// Load a crate's @graph from either a URL or local path
-fn load_graph(source: &str) -> Result<Vec<Value>, ConsolidateError> {
-    if is_url(source) {
-        load_graph_from_url(source)
-    } else {
-        load_graph_from_path(&PathBuf::from(source))
-    }
-}
+    /// Whether subcrate folders merge in the parent's reference or keep only
+    /// the subcrate root's own properties
+    #[arg(long, value_enum, default_value_t = RootHandlingArg::MergeReference)]
+    root_handling: RootHandlingArg,
 
-/// Write output to file or stdout
-fn write_output(content: &str, output: Option<&PathBuf>) -> Result<(), ConsolidateError> {
-    match output {
-        Some(path) => {
-            fs::write(path, content)?;
-            eprintln!("Wrote consolidated crate to {}", path.display());
-        }
-        None => {
-            println!("{}", content);
-        }
-    }
-    Ok(())
-}
+    /// Which side wins properties both a subcrate's root and the parent's
+    /// reference to it set
+    #[arg(long, value_enum, default_value_t = ReferenceMergeArg::Union)]
+    reference_merge: ReferenceMergeArg,
 
-fn run_consolidate(args: ConsolidateArgs) -> Result<(), ConsolidateError> {
-    let graph = load_graph(&args.source)?;
+    /// How to merge properties shared entities set to different single
+    /// values
+    #[arg(long, value_enum, default_value_t = ConflictStrategyArg::Union)]
+    conflict_strategy: ConflictStrategyArg,
 
-    let options = ConsolidateOptions {
-        add_subcrate_type: !args.no_subcrate_type,
-        extend_context: !args.no_extend_context,
-    };
+    /// Merge shared entities by the policy registered under this name
+    /// (union, prefer-main, prefer-incoming, error or latest-dates), below
+    /// --property-strategy and --merge-rules
+    #[arg(long, value_name = "NAME[=ARG]", conflicts_with = "conflict_strategy")]
+    merge_policy: Option<String>,
 
-    // Choose loader based on source type
-    let loader: Box<dyn SubcrateLoader> = if is_url(&args.source) {
-        eprintln!("Loading from URL: {}", args.source);
-        Box::new(UrlLoader::from_metadata_url(&args.source))
-    } else {
-        let path = PathBuf::from(&args.source);
-        let base_path = if path.is_dir() {
-            path
-        } else {
-            path.parent().map(|p| p.to_path_buf()).unwrap_or_default()
-        };
-        Box::new(FilesystemLoader::new(base_path))
-    };
+    /// Merge PROPERTY of shared entities by STRATEGY: union, prefer-main,
+    /// prefer-incoming, latest or earliest (repeatable)
+    #[arg(long = "property-strategy", value_name = "PROPERTY=STRATEGY", value_parser = parse_property_strategy)]
+    property_strategies: Vec<(String, PropertyStrategy)>,
 
-    let result = consolidate(ConsolidateInput::Single(graph), loader.as_ref(), &options)?;
+    /// TOML or JSON file of property strategies, e.g. dateModified =
+    /// "latest", overridden by --property-strategy
+    #[arg(long, value_name = "FILE")]
+    merge_rules: Option<PathBuf>,
 
-    eprintln!(
-        "Consolidated {} crates, {} total entities ({} merged)",
-        result.stats.crates_consolidated, result.stats.total_entities, result.stats.merged_entities
-    );
+    /// Link each subcrate folder to the folder (or root) it was nested in
+    /// with isPartOf
+    #[arg(long)]
+    link_parents: bool,
+
+    /// Consolidate only the first N levels of nested subcrates, leaving
+    /// deeper ones as plain Dataset references
+    #[arg(long, value_name = "N")]
+    depth: Option<usize>,
+
+    /// Consolidate only subcrates whose folder id matches PATTERN, e.g.
+    /// './experiments/*' (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Leave subcrates whose folder id matches PATTERN plain references
+    /// (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// TOML or JSON file (or inline JSON object) of options for the
+    /// subcrates whose folder id matches a pattern, e.g. "./instrument/" =
+    /// { strip_properties = ["email"], exclude_types = ["PropertyValue"],
+    /// max_depth = 0, conflict_strategy = "prefer-main" }
+    #[arg(long, value_name = "FILE_OR_JSON")]
+    subcrate_overrides: Option<String>,
+
+    /// Consolidate the subcrate at PATH below the root crate (or URL) into
+    /// the folder ./NAMESPACE/ instead, e.g. raw_data_v2=raw (repeatable)
+    #[arg(long = "rename-namespace", value_name = "PATH=NAMESPACE", value_parser = parse_namespace_rename)]
+    namespace_renames: Vec<(String, String)>,
+
+    /// TOML or JSON file (or inline JSON object) of namespaces by subcrate
+    /// path, e.g. raw_data_v2 = "raw", overridden by --rename-namespace
+    #[arg(long, value_name = "FILE_OR_JSON")]
+    namespace_map: Option<String>,
 
-    let output = to_json_string(&result, args.pretty)?;
-    write_output(&output, args.output.as_ref())
+    /// How to handle subcrate folders whose names differ only in case
+    #[arg(long, value_enum, default_value_t = CaseCollisionArg::Warn)]
+    case_collisions: CaseCollisionArg,
+
+    /// Fail if a subcrate cannot be loaded instead of skipping it
+    #[arg(long)]
+    strict: bool,
+
+    /// Fail instead of fetching anything over the network
+    #[arg(long)]
+    offline: bool,
+
+    /// Fail on a crate whose metadata takes more than BYTES as compact JSON
+    #[arg(long, value_name = "BYTES")]
+    max_metadata_bytes: Option<u64>,
+
+    /// Fail on a crate with more than N entities
+    #[arg(long, value_name = "N")]
+    max_crate_entities: Option<usize>,
+
+    /// Fail once the crates loaded have more than N entities in total
+    #[arg(long, value_name = "N")]
+    max_entities: Option<usize>,
+
+    /// On failure, still write the partially consolidated result
+    #[arg(long)]
+    keep_partial: bool,
+
+    /// How single property values are shaped in the output
+    #[arg(long, value_enum, default_value_t = ShapeArg::AsIs)]
+    shape: ShapeArg,
+
+    /// Number of merge crates to collect in parallel
+    #[arg(short, long, default_value_t = 1, value_name = "N")]
+    jobs: usize,
+
+    /// Create each merged crate's folder next to the output file
+    #[arg(long, requires = "output")]
+    scaffold: bool,
+
+    /// With --scaffold, make folders of local crates symlinks to their
+    /// source directories instead of empty directories
+    #[arg(long, requires = "scaffold")]
+    link_sources: bool,
+
+    #[command(flatten)]
+    auth: AuthArgs,
+
+    #[command(flatten)]
+    post_processing: PostProcessArgs,
+
+    #[command(flatten)]
+    reports: ReportArgs,
 }
 
-fn run_merge(args: MergeArgs) -> Result<(), ConsolidateError> {
-    // Validate arguments
-    if args.merge_sources.len() != args.folder_ids.len() {
-        return Err(ConsolidateError::InvalidStructure(format!(
-            "Number of --merge ({}) must match number of --as ({})",
-            args.merge_sources.len(),
-            args.folder_ids.len()
-        )));
-    }
+#[derive(Args)]
+struct NamespaceArgs {
+    /// Path to RO-Crate directory, ro-crate-metadata.json file, or URL
+    source: String,
 
-    // Load main crate
-    let main_graph = load_graph(&args.main)?;
+    /// Folder ID to move the crate's ids into (e.g. ./experiments/)
+    #[arg(long = "as", value_name = "FOLDER_ID")]
+    folder_id: String,
 
-    // Load crates to merge
-    let mut others = Vec::new();
-    for (i, (source, folder_id)) in args.merge_sources.iter().zip(&args.folder_ids).enumerate() {
-        let graph = load_graph(source)?;
-        let name = args.names.get(i).cloned();
-        others.push(MergeCrate {
-            graph,
-            folder_id: folder_id.clone(),
-            name,
-        });
-    }
+    /// Output file (default: stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
 
-    let options = ConsolidateOptions {
-        add_subcrate_type: !args.no_subcrate_type,
-        extend_context: !args.no_extend_context,
-    };
+    /// Write the map from original to rewritten ids to FILE as JSON
+    #[arg(long, value_name = "FILE")]
+    id_map: Option<PathBuf>,
 
-    // Use NoOpLoader since we're explicitly merging
-    let result = consolidate(
-        ConsolidateInput::Merge {
-            main: main_graph,
-            others,
-        },
-        &NoOpLoader,
-        &options,
-    )?;
+    #[command(flatten)]
+    style: StyleArgs,
+}
 
-    eprintln!(
-        "Merged {} crates, {} total entities ({} shared entities merged)",
-        result.stats.crates_consolidated, result.stats.total_entities, result.stats.merged_entities
-    );
+#[derive(Args)]
+struct DeconsolidateArgs {
+    /// Path to the consolidated crate's directory or metadata file, or URL
+    source: String,
+
+    /// Directory to write the crates into, each into its folder below it
+    #[arg(short, long, value_name = "DIR")]
+    output: PathBuf,
 
-    let output = to_json_string(&result, args.pretty)?;
-    write_output(&output, args.output.as_ref())
+    #[command(flatten)]
+    style: StyleArgs,
 }
 
-fn main() {
-    let cli = Cli::parse();
+#[derive(Args)]
+struct LintArgs {
+    /// Path to RO-Crate directory, ro-crate-metadata.json file, or URL
+    source: String,
+
+    #[command(flatten)]
+    rules: LintRuleArgs,
+
+    /// Print findings as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Args)]
+struct HarvestArgs {
+    /// OAI-PMH base URL, or the first page of a paged JSON API
+    endpoint: String,
+
+    /// How the repository lists its records
+    #[arg(long, value_enum, default_value_t = ProtocolArg::OaiPmh)]
+    protocol: ProtocolArg,
+
+    /// OAI-PMH metadata format to request
+    #[arg(long, default_value = "oai_dc", value_name = "PREFIX")]
+    metadata_prefix: String,
+
+    /// OAI-PMH set to harvest
+    #[arg(long, value_name = "SET")]
+    set: Option<String>,
+
+    /// Directory keeping the harvested crates and the harvest state between runs
+    ///
+    /// Later runs ask only for records changed since the previous one.
+    #[arg(long, value_name = "DIR")]
+    state_dir: PathBuf,
+
+    /// Harvest records changed since this datestamp instead of since the last run
+    #[arg(long, value_name = "DATESTAMP")]
+    from: Option<String>,
+
+    /// Path or URL to the collection crate (default: an empty collection)
+    #[arg(long, value_name = "PATH_OR_URL")]
+    main: Option<String>,
+
+    /// Name of the empty collection crate
+    #[arg(long, value_name = "NAME", conflicts_with = "main")]
+    collection_name: Option<String>,
+
+    /// Folder IDs of new records (placeholders: name, name-slug, source-slug,
+    /// index); records keep their folder when harvested again
+    #[arg(long, value_name = "TEMPLATE", default_value = DEFAULT_FOLDER_TEMPLATE)]
+    as_template: String,
+
+    /// Output file (default: stdout)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    style: StyleArgs,
+
+    /// Describe the run as a CreateAction recording the options used
+    #[arg(long)]
+    provenance: bool,
+
+    /// Fail if a record's crate cannot be loaded instead of skipping it until
+    /// the next harvest
+    #[arg(long)]
+    strict: bool,
+
+    /// Number of record crates to collect in parallel
+    #[arg(short, long, default_value_t = 1, value_name = "N")]
+    jobs: usize,
+
+    #[command(flatten)]
+    reports: ReportArgs,
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Args)]
+struct ServeGrpcArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    listen: std::net::SocketAddr,
+
+    /// Keep submitted jobs and their results in this directory, so they
+    /// survive restarts (default: in memory)
+    #[arg(long)]
+    job_dir: Option<PathBuf>,
+
+    /// Number of jobs to run at the same time
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    job_workers: usize,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics
+    #[arg(long, value_name = "ADDR")]
+    metrics_listen: Option<std::net::SocketAddr>,
+
+    /// Authenticate requests with the bearer tokens in this JSON file, an
+    /// object mapping each token to its tenant's id (default: run every
+    /// request for a single tenant)
+    #[arg(long, value_name = "FILE")]
+    tenant_tokens: Option<PathBuf>,
+
+    /// Let job callback URLs, and URLs jobs fetch, starting with PREFIX reach
+    /// loopback and private addresses, e.g. http://127.0.0.1:8080/ (repeatable)
+    #[arg(long, value_name = "PREFIX")]
+    allow_callback: Vec<String>,
+
+    /// Consolidate at most N levels of nested subcrates, whatever clients
+    /// ask for (default: 16)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Fail on a subcrate whose metadata takes more than BYTES, whatever
+    /// clients ask for (default: 64 MiB)
+    #[arg(long, value_name = "BYTES")]
+    max_metadata_bytes: Option<u64>,
+
+    /// Fail on a crate with more than N entities, whatever clients ask for
+    /// (default: 1000000)
+    #[arg(long, value_name = "N")]
+    max_crate_entities: Option<usize>,
+
+    /// Fail once the crates of a consolidation have more than N entities in
+    /// total, whatever clients ask for (default: 10000000)
+    #[arg(long, value_name = "N")]
+    max_entities: Option<usize>,
+
+    /// Refuse uploads whose entities take more than BYTES as sent
+    /// (default: 1 GiB)
+    #[arg(long, value_name = "BYTES")]
+    max_upload_bytes: Option<u64>,
+}
+
+/// Layout of the written JSON
+#[derive(Args)]
+struct StyleArgs {
+    /// Pretty-print JSON output
+    #[arg(long)]
+    pretty: bool,
+
+    /// Spaces per indentation level (implies --pretty)
+    #[arg(long, value_name = "N")]
+    indent: Option<usize>,
+
+    /// Write @id and @type first, then all other keys alphabetically
+    #[arg(long)]
+    sort_keys: bool,
+
+    /// End the output file with a newline
+    #[arg(long)]
+    trailing_newline: bool,
+
+    /// Minimize diffs between consolidations: sorted keys and entities,
+    /// one chunk per entity and namespaced fragment ids
+    #[arg(long)]
+    vcs_friendly: bool,
+}
+
+impl From<&StyleArgs> for OutputStyle {
+    fn from(args: &StyleArgs) -> Self {
+        if args.vcs_friendly {
+            return OutputStyle {
+                indent: args.indent.or(Some(2)),
+                ..OutputStyle::vcs_friendly()
+            };
+        }
+        OutputStyle {
+            key_order: if args.sort_keys {
+                KeyOrder::Canonical
+            } else {
+                KeyOrder::AsIs
+            },
+            indent: args.indent.or(if args.pretty { Some(2) } else { None }),
+            trailing_newline: args.trailing_newline,
+            ..OutputStyle::default()
+        }
+    }
+}
+
+/// Credentials for protected endpoints
+#[derive(Args)]
+struct AuthArgs {
+    /// Bearer token to fetch protected crates with (rather set
+    /// ROCRATE_CONSOLIDATE_TOKEN than pass it here)
+    #[arg(long, value_name = "TOKEN", conflicts_with = "basic_auth")]
+    token: Option<String>,
+
+    /// USER:PASSWORD to fetch protected crates with HTTP basic authentication
+    /// (rather set ROCRATE_CONSOLIDATE_BASIC_AUTH than pass it here)
+    #[arg(long, value_name = "USER:PASSWORD")]
+    basic_auth: Option<String>,
+
+    /// Header to send when fetching protected crates, as 'NAME: VALUE'
+    /// (repeatable)
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Send the credentials and headers to URLs starting with PREFIX instead
+    /// of the hosts of the sources given (repeatable)
+    #[arg(long = "auth-prefix", value_name = "PREFIX")]
+    auth_prefixes: Vec<String>,
+}
+
+impl AuthArgs {
+    /// The credentials and headers given, if any, for the given prefixes or
+    /// else the hosts of the URLs among `sources`
+    fn auth<'a>(&self, sources: impl IntoIterator<Item = &'a String>) -> Option<Arc<HttpAuth>> {
+        if self.token.is_none() && self.basic_auth.is_none() && self.headers.is_empty() {
+            return None;
+        }
+        let mut auth = HttpAuth::new();
+        if let Some(token) = &self.token {
+            auth = auth.with_bearer_token(token);
+        }
+        if let Some(credentials) = &self.basic_auth {
+            auth = match credentials.split_once(':') {
+                Some((user, password)) => auth.with_basic_auth(user, Some(password.to_string())),
+                None => auth.with_basic_auth(credentials, None),
+            };
+        }
+        for (name, value) in &self.headers {
+            auth = auth.with_header(name, value);
+        }
+        let prefixes: Vec<String> = if self.auth_prefixes.is_empty() {
+            sources
+                .into_iter()
+                .filter(|source| is_url(source))
+                .filter_map(|source| url::Url::parse(source).ok())
+                .map(|url| format!("{}/", url.origin().ascii_serialization()))
+                .collect()
+        } else {
+            self.auth_prefixes.clone()
+        };
+        for prefix in prefixes {
+            auth = auth.for_prefix(prefix);
+        }
+        Some(Arc::new(auth))
+    }
+}
+
+/// Post-processing of the consolidated crate
+#[derive(Args)]
+struct PostProcessArgs {
+    /// Aggregate the spatial and/or temporal coverage of all crates onto the
+    /// root (--aggregate-coverage=spatial for only one)
+    #[arg(
+        long,
+        value_enum,
+        value_name = "COVERAGE",
+        default_missing_value = "all"
+    )]
+    #[arg(num_args = 0..=1, require_equals = true)]
+    aggregate_coverage: Option<CoverageArg>,
+
+    /// Replace variants of common licenses, organizations and RO-Crate
+    /// profiles with their canonical entities
+    #[arg(long)]
+    canonical_entities: bool,
+
+    /// Also replace the variants listed in this JSON entity library
+    /// (repeatable, implies --canonical-entities)
+    #[arg(long, value_name = "FILE")]
+    entity_library: Vec<PathBuf>,
+
+    /// Replace license names, SPDX identifiers and URL variants with
+    /// references to canonical license entities
+    #[arg(long)]
+    normalize_licenses: bool,
+
+    /// Normalize the DOIs of cited works (citation, isBasedOn, isPartOf) and
+    /// merge the entities describing the same work
+    #[arg(long)]
+    consolidate_citations: bool,
+
+    /// Also let the root cite every work cited within the crate (implies
+    /// --consolidate-citations)
+    #[arg(long)]
+    summarize_citations: bool,
+
+    /// Let the root mention the key entities of all crates (workflows,
+    /// instruments and people by default)
+    #[arg(long)]
+    mention_key_entities: bool,
+
+    /// Type of the key entities to mention (repeatable, replaces the defaults,
+    /// implies --mention-key-entities)
+    #[arg(long = "key-entity-type", value_name = "TYPE")]
+    key_entity_types: Vec<String>,
+
+    /// Select and rank the key entities by the JSON ranking in FILE, weighing
+    /// types, references to them and explicit ids (implies
+    /// --mention-key-entities)
+    #[arg(long, value_name = "FILE", conflicts_with = "key_entity_types")]
+    key_entity_ranking: Option<PathBuf>,
+
+    /// Mention the key entities on an index entity with this @id, which the
+    /// root mentions, instead of on the root (implies --mention-key-entities)
+    #[arg(long, value_name = "ID")]
+    key_entity_index: Option<String>,
+
+    /// Rewrite the @ids of the consolidated graph, and references to them, by
+    /// the first of the id rewriters registered under these names that
+    /// applies (comma-separated or repeatable; e.g.
+    /// replace-prefix=FROM=TO or base=URL)
+    #[arg(long = "rewrite-ids", value_name = "NAME[=ARG]", value_delimiter = ',')]
+    rewrite_ids: Vec<String>,
+
+    /// Also run the post-processors registered under these names, in order,
+    /// after the others (comma-separated or repeatable; NAME=ARG passes an
+    /// argument, e.g. redact=email:telephone)
+    #[arg(long = "post", value_name = "NAME[=ARG]", value_delimiter = ',')]
+    post: Vec<String>,
+
+    /// Run this Rhai script over every entity, last (repeatable): it may
+    /// change `entity`, and drops it by evaluating to false
+    #[cfg(feature = "scripting")]
+    #[arg(long = "entity-script", value_name = "SCRIPT")]
+    entity_scripts: Vec<String>,
+
+    /// Run the Rhai script in FILE over every entity, after the --entity-script
+    /// ones (repeatable)
+    #[cfg(feature = "scripting")]
+    #[arg(long = "entity-script-file", value_name = "FILE")]
+    entity_script_files: Vec<PathBuf>,
+}
+
+impl PostProcessArgs {
+    /// The post-processors aggregating coverage, consolidating citations,
+    /// mentioning key entities, normalizing licenses and replacing the
+    /// variants, as asked for, followed by the id rewriters, those named with
+    /// --post and the entity scripts
+    fn post_processors(&self) -> Result<Vec<Arc<dyn PostProcessor>>, ConsolidateError> {
+        let canonical_entities = self.canonical_entities || !self.entity_library.is_empty();
+        let mut processors: Vec<Arc<dyn PostProcessor>> = Vec::new();
+        if let Some(coverage) = self.aggregate_coverage {
+            processors.push(Arc::new(
+                AggregateCoverage::new()
+                    .with_spatial(coverage != CoverageArg::Temporal)
+                    .with_temporal(coverage != CoverageArg::Spatial),
+            ));
+        }
+        if self.consolidate_citations || self.summarize_citations {
+            processors.push(Arc::new(
+                ConsolidateCitations::new().with_summary(self.summarize_citations),
+            ));
+        }
+        if self.mention_key_entities
+            || !self.key_entity_types.is_empty()
+            || self.key_entity_ranking.is_some()
+            || self.key_entity_index.is_some()
+        {
+            let mut mention = MentionKeyEntities::new();
+            if !self.key_entity_types.is_empty() {
+                mention = mention.with_types(&self.key_entity_types);
+            }
+            if let Some(path) = &self.key_entity_ranking {
+                mention = mention.with_ranking(KeyEntityRanking::from_file(path)?);
+            }
+            if let Some(index_id) = &self.key_entity_index {
+                mention = mention.with_index_entity(index_id);
+            }
+            processors.push(Arc::new(mention));
+        }
+        if canonical_entities || self.normalize_licenses {
+            let mut library = EntityLibrary::builtin();
+            for path in &self.entity_library {
+                library = library.with_file(path)?;
+            }
+            if self.normalize_licenses {
+                processors.push(Arc::new(NormalizeLicenses::new(library.clone())));
+            }
+            if canonical_entities {
+                processors.push(Arc::new(library));
+            }
+        }
+        let registry = Registry::with_builtins();
+        if !self.rewrite_ids.is_empty() {
+            let rewrite = registry.rewrite_ids(self.rewrite_ids.iter().map(String::as_str))?;
+            processors.push(Arc::new(rewrite));
+        }
+        processors.extend(registry.post_processors(self.post.iter().map(String::as_str))?);
+        #[cfg(feature = "scripting")]
+        for script in &self.entity_scripts {
+            processors.push(Arc::new(EntityScript::new(script)?));
+        }
+        #[cfg(feature = "scripting")]
+        for path in &self.entity_script_files {
+            processors.push(Arc::new(EntityScript::from_file(path)?));
+        }
+        Ok(processors)
+    }
+}
+
+/// Side outputs written next to the consolidated crate
+#[derive(Args)]
+struct ReportArgs {
+    /// Write all contentUrl/distribution pointers as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    distributions: Option<PathBuf>,
+
+    /// Write a download manifest of all payload files to this file
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Format of the download manifest
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+    manifest_format: ManifestFormat,
+
+    /// Write a sitemap of consolidated ids to original locations to this file
+    #[arg(long, value_name = "FILE")]
+    sitemap: Option<PathBuf>,
+
+    /// Embed the sitemap as a #ro-crate-resolve entity in the output
+    #[arg(long)]
+    embed_sitemap: bool,
+
+    /// Write a JSON failure report to this file if the result is partial
+    #[arg(long, value_name = "FILE")]
+    failure_report: Option<PathBuf>,
+
+    /// Write a JSON report of the options, statistics, warnings and files and
+    /// URLs read to this file
+    #[arg(long, value_name = "FILE")]
+    run_report: Option<PathBuf>,
+
+    /// Write an HTML report of the statistics, conflicts, warnings, lint
+    /// findings and crates consolidated to this file
+    #[arg(long, value_name = "FILE")]
+    html_report: Option<PathBuf>,
+
+    /// Link entity ids in the HTML report into this preview of the output,
+    /// e.g. ro-crate-preview.html
+    #[arg(long, value_name = "URL")]
+    report_preview: Option<String>,
+
+    /// Print a short summary of the run for chat notifications in FORMAT
+    /// (to stderr when the crate is written to stdout)
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    notify_format: Option<NotifyFormatArg>,
+
+    /// Print per-phase timings and counters to stderr
+    #[arg(long)]
+    profile: bool,
+
+    /// Rules the output is linted with for the run report
+    #[command(flatten)]
+    lint: LintRuleArgs,
+}
+
+/// Which lint rules are checked, and how severe their findings are
+#[derive(Args)]
+struct LintRuleArgs {
+    /// Rules and severities to start from
+    #[arg(long, value_enum, default_value_t = LintProfileArg::Default)]
+    lint_profile: LintProfileArg,
+
+    /// Don't check RULE, e.g. dangling_reference (repeatable)
+    #[arg(long, value_name = "RULE", value_parser = parse_lint_rule)]
+    lint_disable: Vec<BuiltinRule>,
+
+    /// Check RULE even if disabled (repeatable)
+    #[arg(long, value_name = "RULE", value_parser = parse_lint_rule)]
+    lint_enable: Vec<BuiltinRule>,
+
+    /// Report findings of RULE as LEVEL, error or warning (repeatable)
+    #[arg(long, value_name = "RULE=LEVEL", value_parser = parse_lint_severity)]
+    lint_severity: Vec<(BuiltinRule, Severity)>,
+}
+
+impl LintRuleArgs {
+    fn linter(&self) -> Linter {
+        let linter = Linter::with_profile(self.lint_profile.into());
+        let linter = self
+            .lint_severity
+            .iter()
+            .fold(linter, |l, &(rule, severity)| {
+                l.with_severity(rule, severity)
+            });
+        let linter = self
+            .lint_disable
+            .iter()
+            .fold(linter, |l, &rule| l.without(rule));
+        self.lint_enable
+            .iter()
+            .fold(linter, |l, &rule| l.with(rule))
+    }
+}
+
+/// Output format for download manifests
+#[derive(Clone, Copy, ValueEnum)]
+enum ManifestFormat {
+    Json,
+    Csv,
+}
+
+/// CLI spelling of [`MultiRootPolicy`]
+#[derive(Clone, Copy, ValueEnum)]
+enum MultiRootArg {
+    Error,
+    PickDescriptorAbout,
+    Merge,
+}
+
+impl From<MultiRootArg> for MultiRootPolicy {
+    fn from(arg: MultiRootArg) -> Self {
+        match arg {
+            MultiRootArg::Error => MultiRootPolicy::Error,
+            MultiRootArg::PickDescriptorAbout => MultiRootPolicy::PickDescriptorAbout,
+            MultiRootArg::Merge => MultiRootPolicy::Merge,
+        }
+    }
+}
+
+/// CLI spelling of [`MissingDescriptorPolicy`]
+#[derive(Clone, Copy, ValueEnum)]
+enum MissingDescriptorArg {
+    Synthesize,
+    PlainDataset,
+    Fail,
+}
+
+impl From<MissingDescriptorArg> for MissingDescriptorPolicy {
+    fn from(arg: MissingDescriptorArg) -> Self {
+        match arg {
+            MissingDescriptorArg::Synthesize => MissingDescriptorPolicy::Synthesize,
+            MissingDescriptorArg::PlainDataset => MissingDescriptorPolicy::PlainDataset,
+            MissingDescriptorArg::Fail => MissingDescriptorPolicy::Fail,
+        }
+    }
+}
+
+/// CLI spelling of [`RootHandling`]
+#[derive(Clone, Copy, ValueEnum)]
+enum RootHandlingArg {
+    MergeReference,
+    PreserveRoot,
+}
+
+impl From<RootHandlingArg> for RootHandling {
+    fn from(arg: RootHandlingArg) -> Self {
+        match arg {
+            RootHandlingArg::MergeReference => RootHandling::MergeReference,
+            RootHandlingArg::PreserveRoot => RootHandling::PreserveRoot,
+        }
+    }
+}
+
+/// CLI spelling of [`NotifyFormat`]
+#[derive(Clone, Copy, ValueEnum)]
+enum NotifyFormatArg {
+    Text,
+    Markdown,
+    Slack,
+}
+
+impl From<NotifyFormatArg> for NotifyFormat {
+    fn from(arg: NotifyFormatArg) -> Self {
+        match arg {
+            NotifyFormatArg::Text => NotifyFormat::Text,
+            NotifyFormatArg::Markdown => NotifyFormat::Markdown,
+            NotifyFormatArg::Slack => NotifyFormat::Slack,
+        }
+    }
+}
+
+/// CLI spelling of [`ConflictStrategy`]
+#[derive(Clone, Copy, ValueEnum)]
+enum ConflictStrategyArg {
+    Union,
+    PreferMain,
+    PreferIncoming,
+    Error,
+}
+
+impl From<ConflictStrategyArg> for ConflictStrategy {
+    fn from(arg: ConflictStrategyArg) -> Self {
+        match arg {
+            ConflictStrategyArg::Union => ConflictStrategy::Union,
+            ConflictStrategyArg::PreferMain => ConflictStrategy::PreferMain,
+            ConflictStrategyArg::PreferIncoming => ConflictStrategy::PreferIncoming,
+            ConflictStrategyArg::Error => ConflictStrategy::Error,
+        }
+    }
+}
+
+/// CLI spelling of [`ReferenceMergePolicy`]
+#[derive(Clone, Copy, ValueEnum)]
+enum ReferenceMergeArg {
+    Union,
+    ParentWins,
+    ChildWins,
+}
+
+impl From<ReferenceMergeArg> for ReferenceMergePolicy {
+    fn from(arg: ReferenceMergeArg) -> Self {
+        match arg {
+            ReferenceMergeArg::Union => ReferenceMergePolicy::Union,
+            ReferenceMergeArg::ParentWins => ReferenceMergePolicy::ParentWins,
+            ReferenceMergeArg::ChildWins => ReferenceMergePolicy::ChildWins,
+        }
+    }
+}
+
+/// CLI spelling of [`CaseCollisionPolicy`]
+#[derive(Clone, Copy, ValueEnum)]
+enum CaseCollisionArg {
+    Warn,
+    Rename,
+    Allow,
+}
+
+impl From<CaseCollisionArg> for CaseCollisionPolicy {
+    fn from(arg: CaseCollisionArg) -> Self {
+        match arg {
+            CaseCollisionArg::Warn => CaseCollisionPolicy::Warn,
+            CaseCollisionArg::Rename => CaseCollisionPolicy::Rename,
+            CaseCollisionArg::Allow => CaseCollisionPolicy::Allow,
+        }
+    }
+}
+
+/// CLI spelling of [`ShapePolicy`]
+#[derive(Clone, Copy, ValueEnum)]
+enum ShapeArg {
+    AsIs,
+    AlwaysArray,
+    ScalarWhenSingle,
+}
+
+impl From<ShapeArg> for ShapePolicy {
+    fn from(arg: ShapeArg) -> Self {
+        match arg {
+            ShapeArg::AsIs => ShapePolicy::AsIs,
+            ShapeArg::AlwaysArray => ShapePolicy::AlwaysArray,
+            ShapeArg::ScalarWhenSingle => ShapePolicy::ScalarWhenSingle,
+        }
+    }
+}
+
+/// Which coverage --aggregate-coverage aggregates
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CoverageArg {
+    All,
+    Spatial,
+    Temporal,
+}
+
+/// CLI spelling of [`HarvestProtocol`]
+#[derive(Clone, Copy, ValueEnum)]
+enum ProtocolArg {
+    OaiPmh,
+    Json,
+}
+
+impl From<ProtocolArg> for HarvestProtocol {
+    fn from(arg: ProtocolArg) -> Self {
+        match arg {
+            ProtocolArg::OaiPmh => HarvestProtocol::OaiPmh,
+            ProtocolArg::Json => HarvestProtocol::Json,
+        }
+    }
+}
+
+/// CLI spelling of [`LintProfile`]
+#[derive(Clone, Copy, ValueEnum)]
+enum LintProfileArg {
+    Default,
+    Publishable,
+}
+
+impl From<LintProfileArg> for LintProfile {
+    fn from(arg: LintProfileArg) -> Self {
+        match arg {
+            LintProfileArg::Default => LintProfile::Default,
+            LintProfileArg::Publishable => LintProfile::Publishable,
+        }
+    }
+}
+
+/// Check if a source string is a URL
+fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Parse a `NAME: VALUE` header
+fn parse_header(header: &str) -> Result<(String, String), String> {
+    match header.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected NAME: VALUE, got '{}'", header)),
+    }
+}
+
+/// Parse a `SOURCE=MIRROR` pair of URL prefixes
+fn parse_mirror(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((source, mirror)) if !source.is_empty() && !mirror.is_empty() => {
+            Ok((source.to_string(), mirror.to_string()))
+        }
+        _ => Err(format!("expected SOURCE=MIRROR, got '{}'", pair)),
+    }
+}
+
+/// Parse a `PARENT=NAME` pair of an Aruna resource id and the name of the
+/// dataset to create below it
+fn parse_aruna_upload(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((parent, name)) if !parent.is_empty() && !name.is_empty() => {
+            Ok((parent.to_string(), name.to_string()))
+        }
+        _ => Err(format!("expected PARENT=NAME, got '{}'", pair)),
+    }
+}
+
+/// Parse a `NATURE=TYPE` pair of a subcrate root's type or profile and a
+/// type of its folder
+fn parse_subcrate_type(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((nature, t)) if !nature.is_empty() && !t.is_empty() => {
+            Ok((nature.to_string(), t.to_string()))
+        }
+        _ => Err(format!("expected NATURE=TYPE, got '{}'", pair)),
+    }
+}
+
+/// Parse the code of a lint rule
+fn parse_lint_rule(code: &str) -> Result<BuiltinRule, String> {
+    BuiltinRule::from_code(code).ok_or_else(|| {
+        let codes: Vec<_> = BuiltinRule::ALL.iter().map(BuiltinRule::code).collect();
+        format!(
+            "unknown rule '{}', expected one of {}",
+            code,
+            codes.join(", ")
+        )
+    })
+}
+
+/// Parse a `RULE=LEVEL` pair of a lint rule and the severity of its findings
+fn parse_lint_severity(pair: &str) -> Result<(BuiltinRule, Severity), String> {
+    let (code, level) = pair
+        .split_once('=')
+        .ok_or_else(|| format!("expected RULE=LEVEL, got '{}'", pair))?;
+    let severity = match level {
+        "error" => Severity::Error,
+        "warning" => Severity::Warning,
+        _ => {
+            return Err(format!(
+                "unknown level '{}', expected error or warning",
+                level
+            ))
+        }
+    };
+    Ok((parse_lint_rule(code)?, severity))
+}
+
+/// Parse the name of a property strategy, e.g. prefer-main
+fn parse_strategy_name(name: &str) -> Result<PropertyStrategy, String> {
+    match name.replace('_', "-").as_str() {
+        "union" => Ok(PropertyStrategy::Union),
+        "prefer-main" => Ok(PropertyStrategy::PreferMain),
+        "prefer-incoming" => Ok(PropertyStrategy::PreferIncoming),
+        "latest" => Ok(PropertyStrategy::Latest),
+        "earliest" => Ok(PropertyStrategy::Earliest),
+        _ => Err(format!(
+            "unknown strategy '{}', expected union, prefer-main, prefer-incoming, latest or earliest",
+            name
+        )),
+    }
+}
+
+/// Parse a `PROPERTY=STRATEGY` pair of a property and how it is merged
+fn parse_property_strategy(pair: &str) -> Result<(String, PropertyStrategy), String> {
+    match pair.split_once('=') {
+        Some((property, name)) if !property.is_empty() => {
+            Ok((property.to_string(), parse_strategy_name(name)?))
+        }
+        _ => Err(format!("expected PROPERTY=STRATEGY, got '{}'", pair)),
+    }
+}
+
+/// The property strategies of a merge rules file, with `--property-strategy`
+/// pairs on top
+fn property_strategies(
+    rules: Option<&Path>,
+    pairs: &[(String, PropertyStrategy)],
+) -> Result<BTreeMap<String, PropertyStrategy>, ConsolidateError> {
+    let mut strategies = BTreeMap::new();
+    if let Some(path) = rules {
+        let content = fs::read_to_string(path)?;
+        let invalid = |reason: String| {
+            ConsolidateError::InvalidStructure(format!(
+                "Invalid merge rules {}: {}",
+                path.display(),
+                reason
+            ))
+        };
+        let names: BTreeMap<String, String> = if path.extension().is_some_and(|e| e == "toml") {
+            toml::from_str(&content).map_err(|e| invalid(e.to_string()))?
+        } else {
+            serde_json::from_str(&content).map_err(|e| invalid(e.to_string()))?
+        };
+        for (property, name) in names {
+            strategies.insert(property, parse_strategy_name(&name).map_err(invalid)?);
+        }
+    }
+    strategies.extend(pairs.iter().cloned());
+    Ok(strategies)
+}
+
+/// Parse a `PATH=NAMESPACE` pair of a subcrate and the namespace it is
+/// consolidated into
+fn parse_namespace_rename(pair: &str) -> Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((path, namespace)) if !path.is_empty() && !namespace.is_empty() => {
+            Ok((path.to_string(), namespace.to_string()))
+        }
+        _ => Err(format!("expected PATH=NAMESPACE, got '{}'", pair)),
+    }
+}
+
+/// The namespaces of `--namespace-map`, read from the file or inline JSON,
+/// with `--rename-namespace` pairs on top
+fn namespace_map(
+    source: Option<&str>,
+    pairs: &[(String, String)],
+) -> Result<BTreeMap<String, String>, ConsolidateError> {
+    let invalid = |reason: String| {
+        ConsolidateError::InvalidStructure(format!("Invalid namespace map: {}", reason))
+    };
+    let mut namespaces: BTreeMap<String, String> = match source {
+        None => BTreeMap::new(),
+        Some(json) if json.trim_start().starts_with('{') => {
+            serde_json::from_str(json).map_err(|e| invalid(e.to_string()))?
+        }
+        Some(path) if path.ends_with(".toml") => {
+            toml::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))?
+        }
+        Some(path) => {
+            serde_json::from_str(&fs::read_to_string(path)?).map_err(|e| invalid(e.to_string()))?
+        }
+    };
+    namespaces.extend(pairs.iter().cloned());
+    Ok(namespaces)
+}
+
+/// The options of `--subcrate-overrides` by pattern, read from the file or
+/// inline JSON
+///
+/// Option names and conflict strategies may be written with '-' or '_'.
+fn subcrate_overrides(
+    source: Option<&str>,
+) -> Result<BTreeMap<String, SubcrateOverrides>, ConsolidateError> {
+    let Some(source) = source else {
+        return Ok(BTreeMap::new());
+    };
+    let invalid = |reason: String| {
+        ConsolidateError::InvalidStructure(format!("Invalid subcrate overrides: {}", reason))
+    };
+    let table: BTreeMap<String, serde_json::Map<String, Value>> =
+        if source.trim_start().starts_with('{') {
+            serde_json::from_str(source).map_err(|e| invalid(e.to_string()))?
+        } else if source.ends_with(".toml") {
+            toml::from_str(&fs::read_to_string(source)?).map_err(|e| invalid(e.to_string()))?
+        } else {
+            serde_json::from_str(&fs::read_to_string(source)?)
+                .map_err(|e| invalid(e.to_string()))?
+        };
+    table
+        .into_iter()
+        .map(|(pattern, options)| {
+            let options: serde_json::Map<String, Value> = options
+                .into_iter()
+                .map(|(key, value)| match (key.replace('-', "_"), value) {
+                    (key, Value::String(name)) if key == "conflict_strategy" => {
+                        (key, Value::String(name.replace('-', "_")))
+                    }
+                    other => other,
+                })
+                .collect();
+            let overrides = serde_json::from_value(Value::Object(options))
+                .map_err(|e| invalid(format!("{}: {}", pattern, e)))?;
+            Ok((pattern, overrides))
+        })
+        .collect()
+}
+
+/// The extra folder types of `--subcrate-type` pairs, by nature
+fn subcrate_types(pairs: &[(String, String)]) -> BTreeMap<String, Vec<String>> {
+    let mut types: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (nature, t) in pairs {
+        types.entry(nature.clone()).or_default().push(t.clone());
+    }
+    types
+}
+
+/// The built-in loaders and post-processors, the remote loaders fetching
+/// with `fetch`, and the loaders of local crates the binary adds for --loader
+fn registry(confine_links: bool, fetch: &UrlLoaderOptions) -> Registry {
+    Registry::with_builtins()
+        .with_url_options(fetch.clone())
+        .with_loader("filesystem", move |source: &str| {
+            let loader = FilesystemLoader::new(local_base_path(source), confine_links);
+            Ok(Box::new(loader) as Box<dyn SubcrateLoader>)
+        })
+        .with_loader("archive", |source: &str| {
+            let loader = ArchiveLoader::new(PathBuf::from(source))?;
+            Ok(Box::new(loader) as Box<dyn SubcrateLoader>)
+        })
+}
+
+/// Directory the subcrates of the local crate `source` are relative to
+fn local_base_path(source: &str) -> PathBuf {
+    let path = PathBuf::from(source);
+    if path.is_dir() {
+        bag_payload_dir(&path).unwrap_or(path)
+    } else {
+        path.parent().map(|p| p.to_path_buf()).unwrap_or_default()
+    }
+}
+
+/// Loader of local subcrates, which with --allow-remote also fetches those
+/// referenced by URL
+fn local_loader(
+    loader: impl SubcrateLoader + 'static,
+    allow_remote: bool,
+    mirrors: &Arc<Mirrors>,
+    fetch: &UrlLoaderOptions,
+) -> Box<dyn SubcrateLoader> {
+    if allow_remote {
+        let remote = UrlLoader::new("")
+            .with_options(fetch.clone())
+            .with_mirrors(Arc::clone(mirrors));
+        Box::new(HybridLoader::new(loader).with_remote(remote))
+    } else {
+        Box::new(loader)
+    }
+}
+
+/// Filesystem-based subcrate loader
+///
+/// Subcrate directories may be symlinks. Crates are compared by their
+/// canonical directories, so a link back to a crate or one of its ancestors
+/// is reported as a cycle instead of being followed forever.
+struct FilesystemLoader {
+    base_path: PathBuf,
+    /// Refuse subcrates that resolve to a directory outside the root crate
+    confine_links: bool,
+    /// Canonical directory of each loaded crate by namespace
+    dirs: Mutex<HashMap<String, PathBuf>>,
+}
+
+impl FilesystemLoader {
+    fn new(base_path: PathBuf, confine_links: bool) -> Self {
+        Self {
+            base_path,
+            confine_links,
+            dirs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fail if `path` resolves to somewhere outside the root crate
+    fn check_confined(&self, path: &Path, root: &Path) -> Result<(), ConsolidateError> {
+        if self.confine_links && !path.starts_with(root) {
+            return Err(ConsolidateError::LoadError {
+                path: path.display().to_string(),
+                reason: format!("links outside the root crate {}", root.display()),
+                source: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl SubcrateLoader for FilesystemLoader {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        _subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let relative = subcrate_id.trim_start_matches("./").trim_end_matches('/');
+        let mut dirs = self.dirs.lock().expect("loader lock poisoned");
+        let root = match dirs.get("") {
+            Some(root) => root.clone(),
+            None => {
+                let root = fs::canonicalize(&self.base_path)?;
+                dirs.insert(String::new(), root.clone());
+                root
+            }
+        };
+
+        // Build the path to the subcrate below its parent's resolved directory
+        let invalid = |reason: String| ConsolidateError::LoadError {
+            path: subcrate_id.to_string(),
+            reason,
+            source: None,
+        };
+        let parent_dir = match dirs.get(parent_namespace) {
+            Some(dir) => dir.clone(),
+            None => join_id(&root, parent_namespace).map_err(invalid)?,
+        };
+        let subcrate_path = join_id(&parent_dir, relative).map_err(invalid)?;
+        let canonical =
+            fs::canonicalize(&subcrate_path).map_err(|e| ConsolidateError::LoadError {
+                path: subcrate_path.display().to_string(),
+                reason: e.to_string(),
+                source: Some(e.into()),
+            })?;
+        self.check_confined(&canonical, &root)?;
+
+        // The parent and all its ancestors, from the root down
+        let ancestors = std::iter::once("")
+            .chain(
+                parent_namespace
+                    .match_indices('/')
+                    .map(|(i, _)| &parent_namespace[..i]),
+            )
+            .chain(Some(parent_namespace).filter(|ns| !ns.is_empty()));
+        for ancestor in ancestors {
+            if dirs.get(ancestor) == Some(&canonical) {
+                return Err(ConsolidateError::CycleDetected(subcrate_id.to_string()));
+            }
+        }
+        let namespace = if parent_namespace.is_empty() {
+            relative.to_string()
+        } else {
+            format!("{}/{}", parent_namespace, relative)
+        };
+        // Subcrates packaged as BagIt bags are in their payload
+        let canonical = bag_payload_dir(&canonical).unwrap_or(canonical);
+        dirs.insert(namespace, canonical.clone());
+        drop(dirs);
+
+        // Load the metadata file
+        let metadata_path = find_metadata_file(&canonical)?;
+        self.check_confined(&fs::canonicalize(&metadata_path)?, &root)?;
+        let content = read_file(&metadata_path).map_err(|e| ConsolidateError::LoadError {
+            path: metadata_path.display().to_string(),
+            reason: e.to_string(),
+            source: Some(e.into()),
+        })?;
+
+        parse_graph(&content, &metadata_path.display().to_string())
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        _subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        let relative = subcrate_id.trim_start_matches("./").trim_end_matches('/');
+        let dirs = self.dirs.lock().expect("loader lock poisoned");
+        let namespace = if parent_namespace.is_empty() {
+            relative.to_string()
+        } else {
+            format!("{}/{}", parent_namespace, relative)
+        };
+        // Loaded subcrates are where their directory resolved to
+        if let Some(dir) = dirs.get(&namespace) {
+            return Some(dir.display().to_string());
+        }
+        let root = dirs.get("").unwrap_or(&self.base_path);
+        let parent_dir = match dirs.get(parent_namespace) {
+            Some(dir) => dir.clone(),
+            None => join_id(root, parent_namespace).ok()?,
+        };
+        let path = join_id(&parent_dir, relative).ok()?;
+        Some(path.display().to_string())
+    }
+}
+
+/// Loader of subcrates within the zip or tar archive holding the root crate
+struct ArchiveLoader {
+    archive: PathBuf,
+    /// Top-level directory holding the root crate ("" if none)
+    root_prefix: String,
+}
+
+impl ArchiveLoader {
+    fn new(archive: PathBuf) -> Result<Self, ConsolidateError> {
+        let root_prefix = if is_zip(&archive) {
+            zip_root_prefix(&archive)?
+        } else {
+            tar_root_prefix(&archive)?
+        };
+        Ok(Self {
+            archive,
+            root_prefix,
+        })
+    }
+}
+
+impl SubcrateLoader for ArchiveLoader {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        _subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let relative = subcrate_id.trim_start_matches("./").trim_end_matches('/');
+        let folder = if parent_namespace.is_empty() {
+            relative.to_string()
+        } else {
+            format!("{}/{}", parent_namespace, relative)
+        };
+        let ids = [format!("./{}/", folder)];
+        let found = if is_zip(&self.archive) {
+            find_subcrate_metadata_in_zip(&self.archive, &ids, &self.root_prefix)?
+        } else {
+            find_subcrate_metadata_in_tar(&self.archive, &ids, &self.root_prefix)?
+        };
+        let Some((_, member)) = found.into_iter().next() else {
+            return Err(ConsolidateError::LoadError {
+                path: format!("{}!{}", self.archive.display(), folder),
+                reason: "No ro-crate-metadata.json found".to_string(),
+                source: None,
+            });
+        };
+        let (_, content) = if is_zip(&self.archive) {
+            load_from_zip_subpath(&self.archive, &member)?
+        } else {
+            load_from_tar_subpath(&self.archive, &member)?
+        };
+
+        parse_graph(&content, &format!("{}!{}", self.archive.display(), member))
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        _subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        let relative = subcrate_id.trim_start_matches("./").trim_end_matches('/');
+        let member = [self.root_prefix.as_str(), parent_namespace, relative]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("/");
+        let archive = fs::canonicalize(&self.archive).unwrap_or_else(|_| self.archive.clone());
+        Some(format!("{}!{}/", archive.display(), member))
+    }
+}
+
+/// Find ro-crate-metadata.json in a directory
+fn find_metadata_file(dir: &PathBuf) -> Result<PathBuf, ConsolidateError> {
+    let payload = bag_payload_dir(dir);
+    let dir = payload.as_ref().unwrap_or(dir);
+
+    // Take the file matching the earliest metadata file pattern, the first
+    // by name if there are several (names need not be UTF-8)
+    if let Ok(entries) = fs::read_dir(dir) {
+        let found = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter_map(|path| {
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                Some((metadata_file_rank(&name)?, path))
+            })
+            .min();
+        if let Some((_, path)) = found {
+            return Ok(path);
+        }
+    }
+
+    Err(ConsolidateError::LoadError {
+        path: dir.display().to_string(),
+        reason: "No ro-crate-metadata.json found".to_string(),
+        source: None,
+    })
+}
+
+/// Verify the BagIt bag a local source is packaged in against its manifests
+fn verify_source_bag(source: &str) -> Result<(), ConsolidateError> {
+    if is_url(source) || source.starts_with(ARUNA_SCHEME) || source.starts_with(S3_SCHEME) {
+        return Err(ConsolidateError::InvalidStructure(format!(
+            "--verify-bag requires local sources, not {}",
+            source
+        )));
+    }
+    let verified = verify_bag(Path::new(source))?;
+    status!("Verified BagIt bag {} ({} payload files)", source, verified);
+    Ok(())
+}
+
+/// Load a crate's @graph from a path (local file/directory)
+fn load_graph_from_path(path: &PathBuf) -> Result<Vec<Value>, ConsolidateError> {
+    let (location, content) = read_metadata_from_path(path)?;
+    parse_graph(&content, &location)
+}
+
+/// Read the metadata document of the crate at `path`, with where it was
+/// read from
+fn read_metadata_from_path(path: &PathBuf) -> Result<(String, String), ConsolidateError> {
+    if is_zip(path) {
+        let (_, content, _) = load_from_zip(path)?;
+        return Ok((path.display().to_string(), content));
+    }
+    if is_tar(path) {
+        let (_, content, _) = load_from_tar(path)?;
+        return Ok((path.display().to_string(), content));
+    }
+    let metadata_path = if path.is_dir() {
+        find_metadata_file(path)?
+    } else if path.is_file() {
+        path.clone()
+    } else {
+        return Err(ConsolidateError::InvalidPath(path.clone()));
+    };
+
+    let content = read_file(&metadata_path).map_err(|e| ConsolidateError::LoadError {
+        path: metadata_path.display().to_string(),
+        reason: e.to_string(),
+        source: Some(e.into()),
+    })?;
+    Ok((metadata_path.display().to_string(), content))
+}
+
+/// Where the crate at `source` is loaded from, for its provenance: URLs as
+/// given, paths made absolute
+fn input_location(source: &str) -> String {
+    if is_url(source) || source.starts_with(ARUNA_SCHEME) || source.starts_with(S3_SCHEME) {
+        return source.to_string();
+    }
+    fs::canonicalize(source).map_or_else(|_| source.to_string(), |path| path.display().to_string())
+}
+
+/// Load a crate's @graph from either a URL, fetched with `fetch`, or local
+/// path
+fn load_graph(source: &str, fetch: &UrlLoaderOptions) -> Result<Vec<Value>, ConsolidateError> {
+    let (location, content) = read_metadata(source, fetch)?;
+    parse_graph(&content, &location)
+}
+
+/// Load a crate's @graph and @context like [`load_graph`]
+///
+/// Documents without a @context get the RO-Crate 1.1 context.
+fn load_graph_and_context(
+    source: &str,
+    fetch: &UrlLoaderOptions,
+) -> Result<(Vec<Value>, Value), ConsolidateError> {
+    let (location, content) = read_metadata(source, fetch)?;
+    let mut document: Value = serde_json::from_str(&content)?;
+    let context = document
+        .get_mut("@context")
+        .map(Value::take)
+        .unwrap_or_else(|| json!("https://w3id.org/ro/crate/1.1/context"));
+    Ok((parse_graph(&content, &location)?, context))
+}
+
+/// Read the metadata document of the crate at `source`, a URL fetched with
+/// `fetch` or a local path, with where it was read from
+fn read_metadata(
+    source: &str,
+    fetch: &UrlLoaderOptions,
+) -> Result<(String, String), ConsolidateError> {
+    if is_url(source) {
+        let (_, content) = load_from_url_with(source, fetch)?;
+        Ok((source.to_string(), content))
+    } else {
+        read_metadata_from_path(&PathBuf::from(source))
+    }
+}
+
+/// Create the folder of each merged crate below the output file's directory
+///
+/// Existing folders are left as they are, so scaffolding can be repeated.
+fn scaffold_folders(args: &MergeArgs, output: &Path) -> Result<(), ConsolidateError> {
+    let root = output.parent().unwrap_or(Path::new(""));
+    for (source, folder_id) in args.merge_sources.iter().zip(&args.folder_ids) {
+        let folder_id = if args.no_normalize_unicode {
+            folder_id.into()
+        } else {
+            normalize_id(folder_id)
+        };
+        let folder = join_id(root, namespace_from_folder_id(&folder_id))
+            .map_err(ConsolidateError::InvalidFolderId)?;
+
+        let source_dir = if args.link_sources && !is_url(source) && !is_archive(Path::new(source)) {
+            let path = PathBuf::from(source);
+            let dir = if path.is_dir() {
+                path
+            } else {
+                path.parent().map(Path::to_path_buf).unwrap_or_default()
+            };
+            Some(fs::canonicalize(dir)?)
+        } else {
+            None
+        };
+
+        match source_dir {
+            Some(target) if fs::symlink_metadata(&folder).is_err() => {
+                if let Some(parent) = folder.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                symlink_dir(&target, &folder)?;
+                status!("Linked {} to {}", folder.display(), target.display());
+            }
+            Some(_) => {
+                status!(
+                    "Warning: not linking {}: it already exists",
+                    folder.display()
+                );
+            }
+            None => {
+                fs::create_dir_all(&folder)?;
+                status!("Created folder {}", folder.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink_dir(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+/// Write output to file or stdout
+fn write_output(content: &str, output: Option<&PathBuf>) -> Result<(), ConsolidateError> {
+    match output {
+        Some(path) => {
+            fs::write(path, content)?;
+            status!("Wrote consolidated crate to {}", path.display());
+        }
+        None => {
+            print!("{}", content);
+            if !content.ends_with('\n') {
+                println!();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Descriptor id for the output: as given, or the output file's name if
+/// that names a metadata descriptor
+fn descriptor_id(explicit: Option<&String>, output: Option<&PathBuf>) -> Option<String> {
+    if let Some(id) = explicit {
+        return Some(id.clone());
+    }
+    output
+        .and_then(|path| path.file_name())
+        .map(component_to_id)
+        .filter(|name| name.ends_with(METADATA_DESCRIPTOR_ID))
+}
+
+/// Run a consolidation, profiling it if requested
+fn run_consolidation(
+    input: ConsolidateInput,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+    reports: &ReportArgs,
+) -> Result<ConsolidateResult, ConsolidateError> {
+    if !reports.profile {
+        return consolidate(input, loader, options);
+    }
+    let (result, profile) = profile(input, loader, options)?;
+    print_profile(&profile);
+    Ok(result)
+}
+
+/// Print per-phase counters as a table to stderr
+fn print_profile(profile: &Profile) {
+    eprintln!(
+        "{:<10} {:>6} {:>10} {:>12} {:>12}",
+        "phase", "runs", "entities", "allocations", "time"
+    );
+    for phase in &profile.phases {
+        eprintln!(
+            "{:<10} {:>6} {:>10} {:>12} {:>12.3?}",
+            phase.phase.name(),
+            phase.runs,
+            phase.entities,
+            phase.allocations,
+            phase.duration
+        );
+    }
+    eprintln!("{:<10} {:>42.3?}", "total", profile.total);
+}
+
+/// Print non-fatal consolidation warnings to stderr
+fn print_warnings(result: &ConsolidateResult) {
+    for warning in &result.warnings {
+        status!("Warning: {}", warning);
+    }
+}
+
+/// Print an error with its code and the chain of causes
+///
+/// When summarizing, the error is reported as the summary line on stdout.
+fn print_error(error: &ConsolidateError) {
+    match verbosity() {
+        Verbosity::Normal => {}
+        Verbosity::Summary => {
+            let summary = json!({
+                "status": "error",
+                "code": error.code(),
+                "error": error.to_string(),
+            });
+            println!("{}", summary);
+            return;
+        }
+        Verbosity::Quiet => return,
+    }
+    eprintln!("Error [{}]: {}", error.code(), error);
+    // Load errors repeat the message of their source, which is not printed twice
+    let mut message = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            eprintln!("  caused by: {}", cause_message);
+        }
+        message = cause_message;
+        source = cause.source();
+    }
+}
+
+/// Print the summary line of a finished run, if one was asked for
+fn print_summary(result: &ConsolidateResult, output: Option<&PathBuf>) {
+    if verbosity() != Verbosity::Summary {
+        return;
+    }
+    let summary = json!({
+        "status": if result.failure.is_some() { "partial" } else { "ok" },
+        "crates": result.stats.crates_consolidated,
+        "entities": result.stats.total_entities,
+        "merged": result.stats.merged_entities,
+        "warnings": result.warnings.len(),
+        "code": result.failure.as_ref().map(|f| &f.code),
+        "output": output.map(|path| path_to_string(path)),
+    });
+    match output {
+        Some(_) => println!("{}", summary),
+        None => eprintln!("{}", summary),
+    }
+}
+
+/// Print the chat notification of a finished run, if one was asked for
+fn print_notification(result: &ConsolidateResult, reports: &ReportArgs, output: Option<&PathBuf>) {
+    let Some(format) = reports.notify_format else {
+        return;
+    };
+    let findings = reports.lint.linter().lint(&result.graph);
+    let text = notification(result, &findings, format.into());
+    match output {
+        Some(_) => print!("{}", text),
+        None => eprint!("{}", text),
+    }
+}
+
+/// Exit with an error status if the written result is only partial
+fn exit_if_partial(result: &ConsolidateResult) {
+    if let Some(failure) = &result.failure {
+        status!(
+            "Error [{}]: {} (partial result after {} crates was written)",
+            failure.code,
+            failure.error,
+            failure.crates_consolidated
+        );
+        std::process::exit(1);
+    }
+}
+
+/// The cache of collected crates in `dir`, if given
+fn subcrate_cache(
+    dir: Option<&PathBuf>,
+) -> Result<Option<Arc<dyn SubcrateCache>>, ConsolidateError> {
+    match dir {
+        Some(dir) => Ok(Some(Arc::new(DirSubcrateCache::new(dir)?))),
+        None => Ok(None),
+    }
+}
+
+/// Cache of fetched remote metadata in the "http" folder of the cache dir
+fn http_cache(dir: Option<&PathBuf>) -> Result<Option<Arc<HttpCache>>, ConsolidateError> {
+    match dir {
+        Some(dir) => Ok(Some(Arc::new(HttpCache::new(dir.join("http"))?))),
+        None => Ok(None),
+    }
+}
+
+/// Exit status of runs skipped with --if-changed
+const EXIT_UP_TO_DATE: i32 = 3;
+
+/// Exit if `output` was consolidated from the current inputs with `options`,
+/// fetching URLs with `fetch` and, if they fail, from their `mirrors`
+fn exit_if_up_to_date(
+    output: Option<&PathBuf>,
+    options: &ConsolidateOptions,
+    fetch: &UrlLoaderOptions,
+    mirrors: Option<&Mirrors>,
+) -> Result<(), ConsolidateError> {
+    let Some(output) = output.filter(|output| output.is_file()) else {
+        return Ok(());
+    };
+    // Read directly, the previous output isn't an input of this run
+    let content = fs::read_to_string(output)?;
+    let previous = parse_graph(&content, &output.display().to_string())?;
+    match check_freshness_with(&previous, options, fetch, mirrors) {
+        Freshness::UpToDate => {
+            status!("Up to date: {}", output.display());
+            std::process::exit(EXIT_UP_TO_DATE);
+        }
+        Freshness::Stale(reason) => {
+            status!("Out of date: {}", reason);
+            Ok(())
+        }
+    }
+}
+
+/// Write collected distribution pointers as a JSON array
+fn write_distributions(
+    distributions: &[DistributionPointer],
+    path: &PathBuf,
+) -> Result<(), ConsolidateError> {
+    fs::write(path, serde_json::to_string_pretty(distributions)?)?;
+    status!(
+        "Wrote {} distribution pointers to {}",
+        distributions.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Location of the crate a source string points to
+fn source_location(source: &str) -> SourceLocation {
+    if is_url(source) || source.starts_with(ARUNA_SCHEME) || source.starts_with(S3_SCHEME) {
+        let url = source
+            .strip_suffix("ro-crate-metadata.json")
+            .unwrap_or(source)
+            .trim_end_matches('/');
+        SourceLocation::Url {
+            url: url.to_string(),
+        }
+    } else {
+        let path = PathBuf::from(source);
+        if is_zip(&path) {
+            if let Ok(member) = zip_root_prefix(&path) {
+                return SourceLocation::ZipMember {
+                    archive: path,
+                    member,
+                };
+            }
+        }
+        if is_tar(&path) {
+            if let Ok(member) = tar_root_prefix(&path) {
+                return SourceLocation::TarMember {
+                    archive: path,
+                    member,
+                };
+            }
+        }
+        let path = if path.is_file() {
+            path.parent().map(|p| p.to_path_buf()).unwrap_or_default()
+        } else {
+            path
+        };
+        SourceLocation::LocalPath { path }
+    }
+}
+
+/// Write the requested side outputs and embed the sitemap if asked to
+///
+/// `bases` maps crate namespaces to the location each crate was loaded from.
+fn write_reports(
+    result: &mut ConsolidateResult,
+    options: &ConsolidateOptions,
+    bases: &HashMap<String, SourceLocation>,
+    reports: &ReportArgs,
+) -> Result<(), ConsolidateError> {
+    if let Some(path) = &reports.distributions {
+        write_distributions(&result.distributions, path)?;
+    }
+
+    if let (Some(path), Some(failure)) = (&reports.failure_report, &result.failure) {
+        fs::write(path, serde_json::to_string_pretty(failure)?)?;
+        status!("Wrote failure report to {}", path.display());
+    }
+
+    if reports.run_report.is_some() || reports.html_report.is_some() {
+        let findings = reports.lint.linter().lint(&result.graph);
+        if let Some(path) = &reports.run_report {
+            let report = json!({
+                "options": options,
+                "stats": result.stats,
+                "warnings": result.warnings,
+                "conflicts": result.conflicts,
+                "failure": result.failure,
+                "lint": findings,
+                "accesses": AUDIT_LOG.get().map(|log| log.records()),
+            });
+            fs::write(path, serde_json::to_string_pretty(&report)?)?;
+            status!("Wrote run report to {}", path.display());
+        }
+        if let Some(path) = &reports.html_report {
+            let mut report = HtmlReport::new(result).with_findings(&findings);
+            if let Some(preview) = &reports.report_preview {
+                report = report.with_preview(preview);
+            }
+            fs::write(path, report.render())?;
+            status!("Wrote HTML report to {}", path.display());
+        }
+    }
+
+    if let Some(path) = &reports.manifest {
+        let entries = build_manifest(result, bases);
+        let content = match reports.manifest_format {
+            ManifestFormat::Json => serde_json::to_string_pretty(&entries)?,
+            ManifestFormat::Csv => manifest_to_csv(&entries),
+        };
+        fs::write(path, content)?;
+        status!(
+            "Wrote manifest of {} files to {}",
+            entries.len(),
+            path.display()
+        );
+    }
+
+    if reports.sitemap.is_some() || reports.embed_sitemap {
+        let sitemap = build_sitemap(result, bases);
+        if let Some(path) = &reports.sitemap {
+            fs::write(path, serde_json::to_string_pretty(&sitemap)?)?;
+            status!(
+                "Wrote sitemap of {} ids to {}",
+                sitemap.len(),
+                path.display()
+            );
+        }
+        if reports.embed_sitemap {
+            result.graph.push(sitemap_entity(&sitemap)?);
+        }
+    }
+
+    Ok(())
+}
+
+fn run_consolidate(args: ConsolidateArgs, fetch: UrlLoaderOptions) -> Result<(), ConsolidateError> {
+    if args.subcrate_manifest.is_some() && !is_url(&args.source) {
+        return Err(ConsolidateError::InvalidStructure(
+            "--subcrate-manifest requires a URL source".to_string(),
+        ));
+    }
+    let fetch = UrlLoaderOptions {
+        auth: args.auth.auth([&args.source]),
+        cache: http_cache(args.cache_dir.as_ref())?,
+        ..fetch
+    };
+    let _offline = args.offline.then(go_offline);
+    if (args.mmap || args.passthrough)
+        && (is_url(&args.source)
+            || is_archive(Path::new(&args.source))
+            || args.source.starts_with(ARUNA_SCHEME)
+            || args.source.starts_with(S3_SCHEME))
+    {
+        return Err(ConsolidateError::InvalidStructure(
+            "--mmap and --passthrough require a local metadata file or directory".to_string(),
+        ));
+    }
+    if args.verify_bag {
+        verify_source_bag(&args.source)?;
+    }
+    // Crates in Aruna are found by walking their resources before loading
+    let aruna = match args.source.strip_prefix(ARUNA_SCHEME) {
+        Some(resource_id) => {
+            let endpoint = args.aruna_endpoint.as_deref().ok_or_else(|| {
+                ConsolidateError::InvalidStructure(format!(
+                    "{} sources require --aruna-endpoint",
+                    ARUNA_SCHEME
+                ))
+            })?;
+            let client = ArunaClient::new(endpoint, args.aruna_token.clone())
+                .with_url_options(fetch.clone());
+            let loader = ArunaLoader::new(client, resource_id)?;
+            status!(
+                "Loading from Aruna: {} ({} subcrates found)",
+                args.source,
+                loader.len()
+            );
+            Some(loader)
+        }
+        None => None,
+    };
+    // Crates in S3 are found by listing their prefix
+    let s3 = match split_s3_url(&args.source) {
+        Some((bucket, key)) => {
+            let client = S3Client::from_env().with_url_options(fetch.clone());
+            let client = match &args.s3_endpoint {
+                Some(endpoint) => client.with_endpoint(endpoint),
+                None => client,
+            };
+            let loader = S3Loader::new(client, bucket, key)?;
+            status!(
+                "Loading from S3: {} ({} subcrates found)",
+                args.source,
+                loader.len()
+            );
+            Some(loader)
+        }
+        None => None,
+    };
+    let mirrors = Arc::new(args.mirrors.iter().fold(
+        Mirrors::new().with_url_options(fetch.clone()),
+        |mirrors, (source, mirror)| mirrors.with_mirror(source, mirror),
+    ));
+    let options = ConsolidateOptions {
+        add_subcrate_type: !args.no_subcrate_type,
+        subcrate_types: subcrate_types(&args.subcrate_types),
+        extend_context: !args.no_extend_context && !args.flatten,
+        require_absolute_pointers: args.require_absolute_pointers,
+        collect_distributions: args.reports.distributions.is_some(),
+        base_id: args.base_id.clone(),
+        multi_root_policy: args.multi_root.into(),
+        missing_descriptor_policy: args.missing_descriptor.into(),
+        root_handling: args.root_handling.into(),
+        reference_merge: args.reference_merge.into(),
+        conflict_strategy: args.conflict_strategy.into(),
+        property_strategies: property_strategies(
+            args.merge_rules.as_deref(),
+            &args.property_strategies,
+        )?,
+        subcrate_overrides: subcrate_overrides(args.subcrate_overrides.as_deref())?,
+        namespace_map: namespace_map(args.namespace_map.as_deref(), &args.namespace_renames)?,
+        link_parents: args.link_parents,
+        max_depth: args.depth,
+        include_subcrates: args.include.clone(),
+        exclude_subcrates: args.exclude.clone(),
+        strict: args.strict,
+        offline: args.offline,
+        metadata_file_patterns: metadata_file_patterns(),
+        max_metadata_bytes: args.max_metadata_bytes,
+        max_crate_entities: args.max_crate_entities,
+        max_total_entities: args.max_entities,
+        keep_partial: args.keep_partial,
+        shape: args.shape.into(),
+        fragment_id_policy: if args.style.vcs_friendly {
+            FragmentIdPolicy::AlwaysNamespace
+        } else {
+            FragmentIdPolicy::KeepUnlessCollision
+        },
+        parallelism: 1,
+        keep_subcrate_descriptors: args.keep_subcrate_descriptors,
+        descriptor_id: descriptor_id(args.descriptor_id.as_ref(), args.output.as_ref()),
+        normalize_unicode: !args.no_normalize_unicode,
+        value_comparison: if args.canonical_values {
+            ValueComparison::Canonical
+        } else {
+            ValueComparison::Exact
+        },
+        case_collision_policy: args.case_collisions.into(),
+        record_provenance: args.provenance || args.if_changed,
+        flatten_only: args.flatten,
+        raw_passthrough: args.passthrough,
+        post_processors: args.post_processing.post_processors()?,
+        cache: subcrate_cache(args.cache_dir.as_ref())?,
+        input_locations: BTreeMap::from([("./".to_string(), input_location(&args.source))]),
+    };
+    let options = match &args.merge_policy {
+        Some(spec) => options.with_merge_policy(Registry::with_builtins().merge_policy(spec)?),
+        None => options,
+    };
+    if args.if_changed {
+        exit_if_up_to_date(args.output.as_ref(), &options, &fetch, Some(&mirrors))?;
+    }
+
+    let root_read_limit = limit_reads(args.max_metadata_bytes);
+    let graph = match (&aruna, &s3) {
+        // Those crates are read by run_raw_consolidation
+        _ if args.mmap || args.passthrough => Vec::new(),
+        (Some(loader), _) => loader.root_graph()?,
+        (_, Some(loader)) => loader.root_graph()?,
+        _ if is_url(&args.source) && !mirrors.is_empty() => {
+            UrlLoader::from_metadata_url(&args.source)
+                .with_options(fetch.clone())
+                .with_mirrors(Arc::clone(&mirrors))
+                .load_graph(&args.source)?
+        }
+        _ => load_graph(&args.source, &fetch)?,
+    };
+    drop(root_read_limit);
+
+    // Choose loader based on source type, unless named
+    let loader: Box<dyn SubcrateLoader> = if let Some(name) = &args.loader {
+        status!("Loading with the {} loader: {}", name, args.source);
+        registry(args.confine_links, &fetch).loader(name, &args.source)?
+    } else if let Some(loader) = aruna {
+        Box::new(loader)
+    } else if let Some(loader) = s3 {
+        Box::new(loader)
+    } else if let Some(manifest) = &args.subcrate_manifest {
+        let content = if is_url(manifest) {
+            mirrors.fetch(manifest)?
+        } else {
+            read_file(Path::new(manifest))?
+        };
+        let loader = ManifestLoader::from_manifest(&args.source, &content)?
+            .with_options(fetch.clone())
+            .with_mirrors(Arc::clone(&mirrors));
+        status!(
+            "Loading from URL: {} ({} subcrates listed in {})",
+            args.source,
+            loader.len(),
+            manifest
+        );
+        Box::new(loader)
+    } else if is_url(&args.source) {
+        status!("Loading from URL: {}", args.source);
+        let loader = UrlLoader::from_metadata_url(&args.source)
+            .with_options(fetch.clone())
+            .with_mirrors(Arc::clone(&mirrors));
+        Box::new(loader)
+    } else if is_archive(Path::new(&args.source)) {
+        let loader = ArchiveLoader::new(PathBuf::from(&args.source))?;
+        local_loader(loader, args.allow_remote, &mirrors, &fetch)
+    } else {
+        let loader = FilesystemLoader::new(local_base_path(&args.source), args.confine_links);
+        local_loader(loader, args.allow_remote, &mirrors, &fetch)
+    };
+
+    if args.plan_fetches {
+        return write_fetch_plan(&args, graph, loader.as_ref(), &options);
+    }
+    if args.mmap || args.passthrough {
+        return run_raw_consolidation(&args, loader.as_ref(), &options, &fetch);
+    }
+
+    let mut result = run_consolidation(
+        ConsolidateInput::Single(graph),
+        loader.as_ref(),
+        &options,
+        &args.reports,
+    )?;
+    result.warnings.extend(
+        mirrors
+            .used()
+            .into_iter()
+            .map(|used| format!("Fetched {} from mirror {}", used.source, used.mirror)),
+    );
+
+    print_warnings(&result);
+    status!(
+        "Consolidated {} crates, {} total entities ({} merged)",
+        result.stats.crates_consolidated,
+        result.stats.total_entities,
+        result.stats.merged_entities
+    );
+    if result.stats.identical_entities > 0 {
+        status!(
+            "Dropped {} identical copies of shared entities",
+            result.stats.identical_entities
+        );
+    }
+    if result.stats.cached_crates > 0 {
+        status!(
+            "Took {} unchanged crates from the cache",
+            result.stats.cached_crates
+        );
+    }
+
+    let bases = HashMap::from([(String::new(), source_location(&args.source))]);
+    write_reports(&mut result, &options, &bases, &args.reports)?;
+
+    let output = to_json_string_styled(&result, &(&args.style).into())?;
+    write_output(&output, args.output.as_ref())?;
+    upload_to_aruna(&args, &output, &result, &fetch)?;
+    print_notification(&result, &args.reports, args.output.as_ref());
+    print_summary(&result, args.output.as_ref());
+    exit_if_partial(&result);
+    Ok(())
+}
+
+/// Write the consolidated crate back to Aruna, if asked to with
+/// --aruna-upload, unless consolidation failed midway
+fn upload_to_aruna(
+    args: &ConsolidateArgs,
+    output: &str,
+    result: &ConsolidateResult,
+    fetch: &UrlLoaderOptions,
+) -> Result<(), ConsolidateError> {
+    let Some((parent, name)) = &args.aruna_upload else {
+        return Ok(());
+    };
+    if result.failure.is_some() {
+        status!("Warning: not uploading the partial result to Aruna");
+        return Ok(());
+    }
+    let endpoint = args.aruna_endpoint.as_deref().ok_or_else(|| {
+        ConsolidateError::InvalidStructure("--aruna-upload requires --aruna-endpoint".to_string())
+    })?;
+    let client =
+        ArunaClient::new(endpoint, args.aruna_token.clone()).with_url_options(fetch.clone());
+    let dataset_id = upload_crate(&client, parent, name, output)?;
+    status!("Uploaded to Aruna: {}{}", ARUNA_SCHEME, dataset_id);
+    Ok(())
+}
+
+/// Consolidate the local crate of `args.source` with its entities borrowed
+/// from its metadata file, mapped read-only with --mmap
+fn run_raw_consolidation(
+    args: &ConsolidateArgs,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+    fetch: &UrlLoaderOptions,
+) -> Result<(), ConsolidateError> {
+    let path = PathBuf::from(&args.source);
+    let metadata_path = if path.is_dir() {
+        find_metadata_file(&path)?
+    } else {
+        path
+    };
+    let source = metadata_path.display().to_string();
+    let load_error = |reason: String, cause: Option<BoxError>| ConsolidateError::LoadError {
+        path: source.clone(),
+        reason,
+        source: cause,
+    };
+    let file;
+    let read;
+    let content = if args.mmap {
+        file = MappedFile::open(&metadata_path)
+            .map_err(|e| load_error(e.to_string(), Some(e.into())))?;
+        file.as_str()
+            .map_err(|e| load_error(e.to_string(), Some(e.into())))?
+    } else {
+        read = read_file(&metadata_path).map_err(|e| load_error(e.to_string(), Some(e.into())))?;
+        read.as_str()
+    };
+
+    let mut mapped = consolidate_mapped(parse_raw_graph(content, &source)?, loader, options)?;
+    print_warnings(&mapped.result);
+    status!(
+        "Consolidated {} crates, {} total entities ({} merged, {} written verbatim)",
+        mapped.result.stats.crates_consolidated,
+        mapped.result.stats.total_entities,
+        mapped.result.stats.merged_entities,
+        mapped.passthrough.len()
+    );
+
+    let bases = HashMap::from([(String::new(), source_location(&args.source))]);
+    write_reports(&mut mapped.result, options, &bases, &args.reports)?;
+
+    let output = mapped.to_json_string_styled(&(&args.style).into())?;
+    write_output(&output, args.output.as_ref())?;
+    upload_to_aruna(args, &output, &mapped.result, fetch)?;
+    print_notification(&mapped.result, &args.reports, args.output.as_ref());
+    print_summary(&mapped.result, args.output.as_ref());
+    exit_if_partial(&mapped.result);
+    Ok(())
+}
+
+/// Write the URLs consolidating `graph` would fetch, starting with those
+/// already fetched to plan it
+fn write_fetch_plan(
+    args: &ConsolidateArgs,
+    graph: Vec<Value>,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+) -> Result<(), ConsolidateError> {
+    let mut urls: Vec<String> = [Some(&args.source), args.subcrate_manifest.as_ref()]
+        .into_iter()
+        .flatten()
+        .filter(|source| is_url(source))
+        .cloned()
+        .collect();
+    for fetch in plan_fetches(graph, loader, options)? {
+        match fetch.location {
+            Some(location) if is_url(&location) => {
+                if !urls.contains(&location) {
+                    urls.push(location);
+                }
+            }
+            Some(_) => {}
+            None => status!(
+                "Warning: can't tell where subcrate {} would be loaded from",
+                fetch.subcrate_id
+            ),
+        }
+    }
+    let content: String = urls.iter().map(|url| format!("{}\n", url)).collect();
+    match &args.output {
+        Some(path) => {
+            fs::write(path, content)?;
+            status!("Wrote {} URLs to fetch to {}", urls.len(), path.display());
+        }
+        None => print!("{}", content),
+    }
+    Ok(())
+}
+
+fn run_merge(mut args: MergeArgs, fetch: UrlLoaderOptions) -> Result<(), ConsolidateError> {
+    // Sources given literally come first (--as pairs with them), followed
+    // by those from globs, --merge-list files and --merge-dir directories
+    let (literal, patterns): (Vec<String>, Vec<String>) = args
+        .merge_sources
+        .drain(..)
+        .partition(|source| !is_glob(source));
+
+    let fetch = UrlLoaderOptions {
+        auth: args.auth.auth([&args.main].into_iter().chain(&literal)),
+        cache: http_cache(args.cache_dir.as_ref())?,
+        ..fetch
+    };
+    let _offline = args.offline.then(go_offline);
+
+    // Validate arguments
+    if args.as_template.is_none() && literal.len() != args.folder_ids.len() {
+        return Err(ConsolidateError::InvalidStructure(format!(
+            "Number of --merge ({}) must match number of --as ({})",
+            literal.len(),
+            args.folder_ids.len()
+        )));
+    }
+
+    let options = ConsolidateOptions {
+        add_subcrate_type: !args.no_subcrate_type,
+        subcrate_types: subcrate_types(&args.subcrate_types),
+        extend_context: !args.no_extend_context && !args.flatten,
+        require_absolute_pointers: args.require_absolute_pointers,
+        collect_distributions: args.reports.distributions.is_some(),
+        base_id: args.base_id.clone(),
+        multi_root_policy: args.multi_root.into(),
+        missing_descriptor_policy: args.missing_descriptor.into(),
+        root_handling: args.root_handling.into(),
+        reference_merge: args.reference_merge.into(),
+        conflict_strategy: args.conflict_strategy.into(),
+        property_strategies: property_strategies(
+            args.merge_rules.as_deref(),
+            &args.property_strategies,
+        )?,
+        subcrate_overrides: subcrate_overrides(args.subcrate_overrides.as_deref())?,
+        namespace_map: namespace_map(args.namespace_map.as_deref(), &args.namespace_renames)?,
+        link_parents: args.link_parents,
+        max_depth: args.depth,
+        include_subcrates: args.include.clone(),
+        exclude_subcrates: args.exclude.clone(),
+        strict: args.strict,
+        offline: args.offline,
+        metadata_file_patterns: metadata_file_patterns(),
+        max_metadata_bytes: args.max_metadata_bytes,
+        max_crate_entities: args.max_crate_entities,
+        max_total_entities: args.max_entities,
+        keep_partial: args.keep_partial,
+        shape: args.shape.into(),
+        fragment_id_policy: if args.style.vcs_friendly {
+            FragmentIdPolicy::AlwaysNamespace
+        } else {
+            FragmentIdPolicy::KeepUnlessCollision
+        },
+        parallelism: args.jobs,
+        keep_subcrate_descriptors: args.keep_subcrate_descriptors,
+        descriptor_id: descriptor_id(args.descriptor_id.as_ref(), args.output.as_ref()),
+        normalize_unicode: !args.no_normalize_unicode,
+        value_comparison: if args.canonical_values {
+            ValueComparison::Canonical
+        } else {
+            ValueComparison::Exact
+        },
+        case_collision_policy: args.case_collisions.into(),
+        record_provenance: args.provenance || args.if_changed,
+        flatten_only: args.flatten,
+        raw_passthrough: false,
+        post_processors: args.post_processing.post_processors()?,
+        cache: subcrate_cache(args.cache_dir.as_ref())?,
+        // Set once the folder ids of the crates to merge are known
+        input_locations: BTreeMap::new(),
+    };
+    let options = match &args.merge_policy {
+        Some(spec) => options.with_merge_policy(Registry::with_builtins().merge_policy(spec)?),
+        None => options,
+    };
+    if args.if_changed {
+        exit_if_up_to_date(args.output.as_ref(), &options, &fetch, None)?;
+    }
+
+    // Load main crate
+    let main_graph = load_graph(&args.main, &fetch)?;
+
+    let explicit = literal.len();
+    args.merge_sources = literal;
+    for pattern in &patterns {
+        let found = glob_sources(pattern)?;
+        if found.is_empty() {
+            status!("Warning: no crates match {}", pattern);
+        }
+        args.merge_sources.extend(found);
+    }
+    for list in &args.merge_lists {
+        args.merge_sources.extend(read_merge_list(list)?);
+    }
+    for dir in &args.merge_dirs {
+        let found = crates_in_dir(dir)?;
+        if found.is_empty() {
+            status!("Warning: no crates found in {}", dir.display());
+        }
+        args.merge_sources.extend(found);
+    }
+
+    if args.verify_bag {
+        for source in std::iter::once(&args.main).chain(&args.merge_sources) {
+            verify_source_bag(source)?;
+        }
+    }
+
+    // Load crates to merge
+    let mut others = Vec::new();
+    for (i, source) in args.merge_sources.iter().enumerate() {
+        let graph = load_graph(source, &fetch)?;
+        let name = args.names.get(i).cloned();
+        others.push(MergeCrate {
+            graph,
+            folder_id: args.folder_ids.get(i).cloned().unwrap_or_default(),
+            name,
+        });
+    }
+    let generated = match &args.as_template {
+        Some(template) => Some((template.as_str(), 0)),
+        None if others.len() > explicit => Some((DEFAULT_FOLDER_TEMPLATE, explicit)),
+        None => None,
+    };
+    if let Some((template, first)) = generated {
+        assign_template_folder_ids(
+            template,
+            &main_graph,
+            &args.merge_sources,
+            &mut others,
+            first,
+        )?;
+        args.folder_ids = others.iter().map(|o| o.folder_id.clone()).collect();
+    }
+    let sources = std::iter::once(("./", &args.main)).chain(
+        others
+            .iter()
+            .map(|other| other.folder_id.as_str())
+            .zip(&args.merge_sources),
+    );
+    let options = ConsolidateOptions {
+        input_locations: sources
+            .map(|(folder_id, source)| (folder_id.to_string(), input_location(source)))
+            .collect(),
+        ..options
+    };
+
+    // Use NoOpLoader since we're explicitly merging
+    let mut result = run_consolidation(
+        ConsolidateInput::Merge {
+            main: main_graph,
+            others,
+        },
+        &NoOpLoader,
+        &options,
+        &args.reports,
+    )?;
+
+    print_warnings(&result);
+    status!(
+        "Merged {} crates, {} total entities ({} shared entities merged)",
+        result.stats.crates_consolidated,
+        result.stats.total_entities,
+        result.stats.merged_entities
+    );
+    if result.stats.identical_entities > 0 {
+        status!(
+            "Dropped {} identical copies of shared entities",
+            result.stats.identical_entities
+        );
+    }
+    if result.stats.cached_crates > 0 {
+        status!(
+            "Took {} unchanged crates from the cache",
+            result.stats.cached_crates
+        );
+    }
+
+    let mut bases = HashMap::from([(String::new(), source_location(&args.main))]);
+    for (source, folder_id) in args.merge_sources.iter().zip(&args.folder_ids) {
+        bases.insert(
+            namespace_from_folder_id(folder_id).to_string(),
+            source_location(source),
+        );
+    }
+    write_reports(&mut result, &options, &bases, &args.reports)?;
+
+    let output = to_json_string_styled(&result, &(&args.style).into())?;
+    write_output(&output, args.output.as_ref())?;
+    if let (true, Some(output)) = (args.scaffold, &args.output) {
+        scaffold_folders(&args, output)?;
+    }
+    print_notification(&result, &args.reports, args.output.as_ref());
+    print_summary(&result, args.output.as_ref());
+    exit_if_partial(&result);
+    Ok(())
+}
+
+fn run_namespace(args: NamespaceArgs, fetch: &UrlLoaderOptions) -> Result<(), ConsolidateError> {
+    let mut pipeline = Pipeline::new();
+    if args.style.vcs_friendly {
+        pipeline.fragment_id_policy = FragmentIdPolicy::AlwaysNamespace;
+    }
+    let (graph, context) = load_graph_and_context(&args.source, fetch)?;
+    let namespaced = pipeline.namespace(graph, &args.folder_id)?;
+    status!(
+        "Moved {} entities into {} ({} ids rewritten)",
+        namespaced.graph.len(),
+        args.folder_id,
+        namespaced.id_map.len()
+    );
+
+    let document = json!({
+        "@context": context,
+        "@graph": namespaced.graph,
+    });
+    let output = to_styled_string(&document, &(&args.style).into())?;
+    write_output(&output, args.output.as_ref())?;
+    if let Some(path) = &args.id_map {
+        let id_map: BTreeMap<_, _> = namespaced.id_map.into_iter().collect();
+        fs::write(path, serde_json::to_string_pretty(&id_map)? + "\n")?;
+        status!("Wrote id map to {}", path.display());
+    }
+    Ok(())
+}
+
+fn run_deconsolidate(
+    args: DeconsolidateArgs,
+    fetch: &UrlLoaderOptions,
+) -> Result<(), ConsolidateError> {
+    let (graph, context) = load_graph_and_context(&args.source, fetch)?;
+    let crates = deconsolidate(graph)?;
+    let style: OutputStyle = (&args.style).into();
+    for split in &crates {
+        // Split paths are checked already, but only as ids: check the folder
+        // names they decode to, and never write outside the output anyway
+        let outside = || {
+            ConsolidateError::InvalidFolderId(format!(
+                "Crate {} would be written outside {}",
+                split.folder_id,
+                args.output.display()
+            ))
+        };
+        let dir = join_id(&args.output, &split.path).map_err(|_| outside())?;
+        if !dir.starts_with(&args.output) {
+            return Err(outside());
+        }
+        fs::create_dir_all(&dir)?;
+        let document = json!({
+            "@context": context,
+            "@graph": split.graph,
+        });
+        fs::write(
+            dir.join(METADATA_DESCRIPTOR_ID),
+            to_styled_string(&document, &style)?,
+        )?;
+        status!(
+            "Wrote {} ({} entities) to {}",
+            split.folder_id,
+            split.graph.len(),
+            dir.display()
+        );
+    }
+    status!("Split {} into {} crates", args.source, crates.len());
+    Ok(())
+}
+
+/// Print the findings of linting a crate; exits with status 1 if any is an
+/// error
+fn run_lint(args: LintArgs, fetch: &UrlLoaderOptions) -> Result<(), ConsolidateError> {
+    let findings = args.rules.linter().lint(&load_graph(&args.source, fetch)?);
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&findings)?);
+    } else {
+        for finding in &findings {
+            let severity = match finding.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            let location = finding.pointer.as_deref().unwrap_or("");
+            println!(
+                "{}[{}] {} {}",
+                severity, finding.rule, finding.message, location
+            );
+        }
+    }
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+    status!(
+        "{} findings ({} errors) in {}",
+        findings.len(),
+        errors,
+        args.source
+    );
+    if errors > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// File in the --state-dir recording what was harvested
+const HARVEST_STATE_FILE: &str = "harvest-state.json";
+
+fn run_harvest(args: HarvestArgs, fetch: &UrlLoaderOptions) -> Result<(), ConsolidateError> {
+    let state_path = args.state_dir.join(HARVEST_STATE_FILE);
+    let mut state: HarvestState = if state_path.exists() {
+        serde_json::from_str(&fs::read_to_string(&state_path)?)?
+    } else {
+        HarvestState::default()
+    };
+    let harvester = Harvester {
+        endpoint: args.endpoint.clone(),
+        protocol: args.protocol.into(),
+        metadata_prefix: args.metadata_prefix.clone(),
+        set: args.set.clone(),
+    };
+    let from = args.from.clone().or_else(|| state.last_datestamp.clone());
+    match &from {
+        Some(from) => status!("Harvesting {} since {}", args.endpoint, from),
+        None => status!("Harvesting {}", args.endpoint),
+    }
+    let listed = harvester.list_records(from.as_deref(), |url| Ok(fetch_url_with(url, fetch)?))?;
+    // Records failing last time are tried again, as the datestamp moved on
+    let records = state.with_failed(listed.clone());
+
+    let main_graph = match &args.main {
+        Some(main) => load_graph(main, fetch)?,
+        None => collection_graph(
+            args.collection_name
+                .as_deref()
+                .unwrap_or(&format!("Collection harvested from {}", args.endpoint)),
+        ),
+    };
+
+    // Record crates are kept below the state directory, laid out like the
+    // collection, so unchanged records need not be fetched again
+    let crates_dir = args.state_dir.join("crates");
+    let record_dir = |folder_id: &str| {
+        join_id(&crates_dir, folder_id).map_err(ConsolidateError::InvalidFolderId)
+    };
+    let record_file = |folder_id: &str| {
+        Ok::<_, ConsolidateError>(record_dir(folder_id)?.join(METADATA_DESCRIPTOR_ID))
+    };
+    let mut taken = taken_folder_paths(
+        &main_graph,
+        state.records.values().map(|r| r.folder_id.as_str()),
+    );
+    let (mut updated, mut deleted) = (0, 0);
+    let mut failed = Vec::new();
+    for record in &records {
+        if record.deleted {
+            if let Some(old) = state.records.remove(&record.identifier) {
+                let file = record_file(&old.folder_id)?;
+                if file.exists() {
+                    fs::remove_file(&file)?;
+                }
+                // Folders holding nested records stay
+                let _ = fs::remove_dir(record_dir(&old.folder_id)?);
+                taken.remove(&folder_path(&old.folder_id));
+                status!("Removed deleted record {}", record.identifier);
+                deleted += 1;
+            }
+            continue;
+        }
+        let Some(url) = &record.url else {
+            status!(
+                "Warning: skipping record {}: no crate URL",
+                record.identifier
+            );
+            continue;
+        };
+        let loaded = load_from_url_with(url, fetch)
+            .map_err(ConsolidateError::from)
+            .and_then(|(_, content)| Ok((parse_graph(&content, url)?, content)));
+        let (graph, content) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) if args.strict => return Err(e),
+            Err(e) => {
+                status!(
+                    "Warning: skipping record {} until the next harvest: {}",
+                    record.identifier,
+                    e
+                );
+                failed.push(record.clone());
+                continue;
+            }
+        };
+        let folder_id = match state.records.get(&record.identifier) {
+            Some(known) => known.folder_id.clone(),
+            None => {
+                let vars = TemplateVars::from_graph(&graph, url, state.records.len() + 1);
+                template_folder_id(&args.as_template, &vars, &mut taken)?
+            }
+        };
+        let file = record_file(&folder_id)?;
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&file, content)?;
+        status!("Harvested {} as {}", record.identifier, folder_id);
+        state.records.insert(
+            record.identifier.clone(),
+            HarvestedRecord {
+                datestamp: record.datestamp.clone(),
+                url: url.clone(),
+                folder_id,
+            },
+        );
+        updated += 1;
+    }
+    state.advance(&listed);
+    state.failed = failed;
+    fs::create_dir_all(&args.state_dir)?;
+    fs::write(&state_path, serde_json::to_string_pretty(&state)?)?;
+    status!(
+        "{} records changed: {} harvested, {} deleted, {} failed",
+        listed.len(),
+        updated,
+        deleted,
+        state.failed.len()
+    );
+
+    // Build the collection from all records harvested so far
+    let mut others = Vec::with_capacity(state.records.len());
+    let mut bases = HashMap::new();
+    let mut locations = BTreeMap::new();
+    if let Some(main) = &args.main {
+        bases.insert(String::new(), source_location(main));
+        locations.insert("./".to_string(), input_location(main));
+    }
+    for record in state.records.values() {
+        others.push(MergeCrate {
+            graph: load_graph_from_path(&record_file(&record.folder_id)?)?,
+            folder_id: record.folder_id.clone(),
+            name: None,
+        });
+        bases.insert(
+            namespace_from_folder_id(&record.folder_id).to_string(),
+            source_location(&record.url),
+        );
+        locations.insert(record.folder_id.clone(), record.url.clone());
+    }
+
+    let options = ConsolidateOptions {
+        collect_distributions: args.reports.distributions.is_some(),
+        strict: args.strict,
+        fragment_id_policy: if args.style.vcs_friendly {
+            FragmentIdPolicy::AlwaysNamespace
+        } else {
+            FragmentIdPolicy::KeepUnlessCollision
+        },
+        parallelism: args.jobs,
+        descriptor_id: descriptor_id(None, args.output.as_ref()),
+        record_provenance: args.provenance,
+        metadata_file_patterns: metadata_file_patterns(),
+        input_locations: locations,
+        ..ConsolidateOptions::default()
+    };
+    let mut result = run_consolidation(
+        ConsolidateInput::Merge {
+            main: main_graph,
+            others,
+        },
+        &NoOpLoader,
+        &options,
+        &args.reports,
+    )?;
+
+    print_warnings(&result);
+    status!(
+        "Collected {} records, {} total entities ({} shared entities merged)",
+        state.records.len(),
+        result.stats.total_entities,
+        result.stats.merged_entities
+    );
+    write_reports(&mut result, &options, &bases, &args.reports)?;
+
+    let output = to_json_string_styled(&result, &(&args.style).into())?;
+    write_output(&output, args.output.as_ref())?;
+    print_notification(&result, &args.reports, args.output.as_ref());
+    print_summary(&result, args.output.as_ref());
+    exit_if_partial(&result);
+    Ok(())
+}
+
+/// Serve consolidation over gRPC until interrupted
+#[cfg(feature = "grpc")]
+fn run_serve_grpc(args: ServeGrpcArgs, fetch: &UrlLoaderOptions) -> Result<(), ConsolidateError> {
+    use rocrate_consolidate::grpc::{
+        serve, serve_metrics, Authenticator, ServiceLimits, SingleTenant, TokenAuthenticator,
+    };
+    use rocrate_consolidate::job::run_request;
+    use rocrate_consolidate::{
+        DirJobStore, JobContext, JobQueue, JobRequest, JobStore, MemoryJobStore, Metrics,
+    };
+    use std::sync::Arc;
+
+    let authenticator: Arc<dyn Authenticator> = match &args.tenant_tokens {
+        Some(path) => {
+            let tokens: HashMap<String, String> = serde_json::from_str(&read_file(path)?)?;
+            let authenticator = tokens.iter().fold(
+                TokenAuthenticator::new(),
+                |authenticator, (token, tenant)| authenticator.with_token(token, tenant),
+            );
+            Arc::new(authenticator)
+        }
+        None => Arc::new(SingleTenant),
+    };
+
+    let defaults = ServiceLimits::DEFAULT;
+    let limits = ServiceLimits {
+        max_depth: args.max_depth.unwrap_or(defaults.max_depth),
+        max_metadata_bytes: args
+            .max_metadata_bytes
+            .unwrap_or(defaults.max_metadata_bytes),
+        max_crate_entities: args
+            .max_crate_entities
+            .unwrap_or(defaults.max_crate_entities),
+        max_total_entities: args.max_entities.unwrap_or(defaults.max_total_entities),
+        max_upload_bytes: args.max_upload_bytes.unwrap_or(defaults.max_upload_bytes),
+        ..defaults
+    };
+
+    status!("Serving gRPC on {}", args.listen);
+    let runtime = tokio::runtime::Runtime::new()?;
+    let store: Arc<dyn JobStore> = match &args.job_dir {
+        Some(dir) => Arc::new(DirJobStore::new(dir)?),
+        None => Arc::new(MemoryJobStore::default()),
+    };
+    let metrics = Arc::new(Metrics::default());
+    let runner_metrics = Arc::clone(&metrics);
+    let runner = move |request: &JobRequest, context: &JobContext| {
+        let _fetch_errors = runner_metrics.count_fetch_errors();
+        run_request(request, context)
+    };
+    let jobs = Arc::new(JobQueue::with_runner(
+        store,
+        args.job_workers,
+        Arc::new(runner),
+    )?);
+    jobs.on_store_error(|id, e| {
+        status!("Job {} could not be stored, it runs again on restart", id);
+        print_error(e);
+    });
+    jobs.set_url_options(fetch.clone());
+    for prefix in &args.allow_callback {
+        jobs.allow_callbacks_to(prefix);
+    }
+    runtime.block_on(async {
+        let grpc = serve(
+            args.listen,
+            jobs,
+            Arc::clone(&metrics),
+            authenticator,
+            limits,
+        );
+        let grpc = async {
+            grpc.await
+                .map_err(|e| ConsolidateError::Io(std::io::Error::other(e)))
+        };
+        match args.metrics_listen {
+            Some(addr) => {
+                status!("Serving metrics on http://{}/metrics", addr);
+                let metrics = async { Ok(serve_metrics(addr, metrics).await?) };
+                tokio::try_join!(grpc, metrics).map(drop)
+            }
+            None => grpc.await,
+        }
+    })
+}
+
+/// Folder template of crates found by --merge-dir without --as-template
+const DEFAULT_FOLDER_TEMPLATE: &str = "{source-slug}/";
+
+/// Give the merged crates from `first` on folder ids generated from a template
+///
+/// Ids are unique among all merged crates and don't name a file or folder of
+/// the main crate.
+fn assign_template_folder_ids(
+    template: &str,
+    main: &[Value],
+    sources: &[String],
+    others: &mut [MergeCrate],
+    first: usize,
+) -> Result<(), ConsolidateError> {
+    let mut taken = taken_folder_paths(
+        main,
+        others[..first].iter().map(|other| other.folder_id.as_str()),
+    );
+    for (i, (source, other)) in sources.iter().zip(others).enumerate().skip(first) {
+        let vars = TemplateVars::from_graph(&other.graph, source, i + 1);
+        let folder_id = template_folder_id(template, &vars, &mut taken)?;
+        status!("Merging {} as {}", source, folder_id);
+        other.folder_id = folder_id;
+    }
+    Ok(())
+}
+
+/// Normalized paths of the main crate's relative ids and of `folder_ids`
+fn taken_folder_paths<'a>(
+    main: &'a [Value],
+    folder_ids: impl Iterator<Item = &'a str>,
+) -> HashSet<String> {
+    main.iter()
+        .filter_map(|entity| entity.get("@id")?.as_str())
+        .filter(|id| !id.starts_with('#') && !is_url(id))
+        .chain(folder_ids)
+        .map(folder_path)
+        .collect()
+}
+
+fn folder_path(id: &str) -> String {
+    normalize_id(namespace_from_folder_id(id)).into_owned()
+}
+
+/// Expand a folder template into an id whose path isn't `taken` yet, and take it
+fn template_folder_id(
+    template: &str,
+    vars: &TemplateVars,
+    taken: &mut HashSet<String>,
+) -> Result<String, ConsolidateError> {
+    let expanded = expand_folder_template(template, vars)?;
+    let folder_id = unique_folder_id(&expanded, |id| taken.contains(&folder_path(id)));
+    taken.insert(folder_path(&folder_id));
+    Ok(folder_id)
+}
+
+/// Crate directories, zip and tar files in a directory, in name order
+///
+/// Hidden entries are ignored; other directories without metadata are
+/// skipped with a warning.
+fn crates_in_dir(dir: &Path) -> Result<Vec<String>, ConsolidateError> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| ConsolidateError::LoadError {
+            path: dir.display().to_string(),
+            reason: e.to_string(),
+            source: Some(e.into()),
+        })?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+    paths.sort();
+
+    let mut sources = Vec::new();
+    for path in paths {
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."));
+        if hidden || !(path.is_dir() || is_archive(&path)) {
+            continue;
+        }
+        if path.is_dir() && find_metadata_file(&path).is_err() {
+            status!(
+                "Warning: skipping {}: no ro-crate-metadata.json",
+                path.display()
+            );
+            continue;
+        }
+        match path.to_str() {
+            Some(source) => sources.push(source.to_string()),
+            None => status!(
+                "Warning: skipping {}: path is not valid UTF-8",
+                path.display()
+            ),
+        }
+    }
+    Ok(sources)
+}
+
+/// Whether a merge source is a glob pattern rather than a path or URL
+fn is_glob(source: &str) -> bool {
+    !is_url(source) && source.contains(['*', '?', '['])
+}
+
+/// Paths matching a glob, in name order
+fn glob_sources(pattern: &str) -> Result<Vec<String>, ConsolidateError> {
+    let paths = glob::glob(pattern).map_err(|e| {
+        ConsolidateError::InvalidStructure(format!("Invalid glob '{}': {}", pattern, e))
+    })?;
+    let mut sources = Vec::new();
+    for path in paths {
+        let path = path.map_err(std::io::Error::from)?;
+        match path.to_str() {
+            Some(source) => sources.push(source.to_string()),
+            None => status!(
+                "Warning: skipping {}: path is not valid UTF-8",
+                path.display()
+            ),
+        }
+    }
+    Ok(sources)
+}
+
+/// Sources listed in a --merge-list file
+fn read_merge_list(list: &Path) -> Result<Vec<String>, ConsolidateError> {
+    let (content, base) = if list == Path::new("-") {
+        (std::io::read_to_string(std::io::stdin())?, None)
+    } else {
+        let content = read_file(list).map_err(|e| ConsolidateError::LoadError {
+            path: list.display().to_string(),
+            reason: e.to_string(),
+            source: Some(e.into()),
+        })?;
+        (content, list.parent())
+    };
+    let sources = content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|source| match base {
+            Some(base) if !is_url(source) && Path::new(source).is_relative() => {
+                base.join(source).display().to_string()
+            }
+            _ => source.to_string(),
+        })
+        .collect();
+    Ok(sources)
+}
+
+/// Whether a path is a zip file
+fn is_zip(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// Whether a path is a tar file (".tar", ".tar.gz" or ".tgz")
+fn is_tar(path: &Path) -> bool {
+    path.is_file() && is_tar_path(path)
+}
+
+/// Whether a path is a zip or tar file
+fn is_archive(path: &Path) -> bool {
+    is_zip(path) || is_tar(path)
+}
+
+fn export_vocab(output: Option<&PathBuf>) -> Result<(), ConsolidateError> {
+    let content = serde_json::to_string_pretty(&profile_crate())?;
+    match output {
+        Some(path) => {
+            fs::write(path, content + "\n")?;
+            status!("Wrote consolidation profile to {}", path.display());
+        }
+        None => println!("{}", content),
+    }
+    Ok(())
+}
+
+/// Project-level config file, looked up in the working directory and its ancestors
+const CONFIG_FILE: &str = "rocrate-consolidate.toml";
+
+/// Prefix of environment variables pre-setting options (e.g. `ROCRATE_CONSOLIDATE_JOBS`)
+const ENV_PREFIX: &str = "ROCRATE_CONSOLIDATE_";
+
+/// The config file to use: `ROCRATE_CONSOLIDATE_CONFIG`, or the nearest
+/// `rocrate-consolidate.toml`
+fn find_config() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(format!("{}CONFIG", ENV_PREFIX)) {
+        return Some(PathBuf::from(path));
+    }
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Whether an option can be preset (anything but --help and --version)
+fn presettable(arg: &clap::Arg) -> bool {
+    !matches!(
+        arg.get_action(),
+        ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version
+    )
+}
+
+/// Turn one option value into arguments, or `None` if `value` cannot set `arg`
+fn option_args(arg: &clap::Arg, value: &toml::Value) -> Option<Vec<OsString>> {
+    let long = arg.get_long()?;
+    let takes_values = arg.get_action().takes_values();
+    let args = match value {
+        toml::Value::Boolean(set) if !takes_values => {
+            if *set {
+                vec![format!("--{}", long).into()]
+            } else {
+                vec![]
+            }
+        }
+        toml::Value::Array(values) if takes_values => values
+            .iter()
+            .map(|v| option_args(arg, v).filter(|args| args.len() == 1))
+            .collect::<Option<Vec<_>>>()?
+            .concat(),
+        toml::Value::String(v) if takes_values => vec![format!("--{}={}", long, v).into()],
+        // Tables are passed on as inline JSON, e.g. for --subcrate-overrides
+        toml::Value::Table(table) if takes_values => {
+            let json = serde_json::to_string(table).ok()?;
+            vec![format!("--{}={}", long, json).into()]
+        }
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_)
+            if takes_values =>
+        {
+            vec![format!("--{}={}", long, value).into()]
+        }
+        _ => return None,
+    };
+    Some(args)
+}
+
+/// Arguments of `subcommand` set in a config file
+///
+/// Top-level keys apply to every subcommand with that option; keys in a
+/// `[consolidate]` or `[merge]` table only to that subcommand. Keys are long
+/// option names, with '-' or '_'.
+fn config_args(
+    config: &toml::Table,
+    command: &clap::Command,
+    subcommand: &clap::Command,
+    path: &Path,
+) -> Result<Vec<OsString>, ConsolidateError> {
+    let invalid = |message: String| {
+        ConsolidateError::InvalidStructure(format!(
+            "Invalid config file {}: {}",
+            path.display(),
+            message
+        ))
+    };
+    let find = |cmd: &clap::Command, key: &str| {
+        let long = key.replace('_', "-");
+        cmd.get_arguments()
+            .filter(|arg| presettable(arg))
+            .find(|arg| arg.get_long() == Some(long.as_str()))
+            .cloned()
+    };
+
+    let mut args = Vec::new();
+    let mut set = |arg: &clap::Arg, key: &str, value: &toml::Value| {
+        let values = option_args(arg, value)
+            .ok_or_else(|| invalid(format!("unsupported value for '{}'", key)))?;
+        args.extend(values);
+        Ok::<_, ConsolidateError>(())
+    };
+    for (key, value) in config {
+        match value {
+            toml::Value::Table(table) => {
+                let cmd = command
+                    .find_subcommand(key)
+                    .ok_or_else(|| invalid(format!("unknown section [{}]", key)))?;
+                if cmd.get_name() != subcommand.get_name() {
+                    continue;
+                }
+                for (key, value) in table {
+                    let arg = find(cmd, key).ok_or_else(|| {
+                        invalid(format!("unknown option '{}' in [{}]", key, cmd.get_name()))
+                    })?;
+                    set(&arg, key, value)?;
+                }
+            }
+            _ => match find(subcommand, key) {
+                Some(arg) => set(&arg, key, value)?,
+                None if command
+                    .get_subcommands()
+                    .any(|cmd| find(cmd, key).is_some()) => {}
+                None => return Err(invalid(format!("unknown option '{}'", key))),
+            },
+        }
+    }
+    Ok(args)
+}
+
+/// Arguments of `subcommand` set by `ROCRATE_CONSOLIDATE_<OPTION>` variables
+///
+/// Flags are set by "1" or "true"; other values are passed on as given.
+fn env_args(subcommand: &clap::Command) -> Vec<OsString> {
+    let mut args = Vec::new();
+    for arg in subcommand.get_arguments().filter(|arg| presettable(arg)) {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        let name = format!("{}{}", ENV_PREFIX, long.to_uppercase().replace('-', "_"));
+        let Some(value) = std::env::var_os(&name) else {
+            continue;
+        };
+        if arg.get_action().takes_values() {
+            let mut option = OsString::from(format!("--{}=", long));
+            option.push(value);
+            args.push(option);
+        } else if matches!(value.to_str(), Some("1" | "true")) {
+            args.push(format!("--{}", long).into());
+        }
+    }
+    args
+}
+
+/// Command-line arguments with the options preset by the config file and the
+/// environment inserted before those given
+///
+/// Options given later override earlier ones, so flags override environment
+/// variables, which override the config file.
+fn layered_args(command: &clap::Command) -> Result<Vec<OsString>, ConsolidateError> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    // The subcommand is the first argument that isn't an option
+    let Some(position) = args
+        .iter()
+        .skip(1)
+        .position(|arg| !arg.to_string_lossy().starts_with('-'))
+        .map(|i| i + 1)
+    else {
+        return Ok(args);
+    };
+    let subcommand = args[position]
+        .to_str()
+        .and_then(|name| command.find_subcommand(name))
+        .filter(|cmd| !cmd.has_subcommands());
+    let Some(subcommand) = subcommand else {
+        return Ok(args);
+    };
+
+    let mut preset = Vec::new();
+    if let Some(path) = find_config() {
+        let content = fs::read_to_string(&path)?;
+        let config: toml::Table = toml::from_str(&content).map_err(|e| {
+            ConsolidateError::InvalidStructure(format!(
+                "Invalid config file {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        preset.extend(config_args(&config, command, subcommand, &path)?);
+    }
+    preset.extend(env_args(subcommand));
+    args.splice(position + 1..position + 1, preset);
+    Ok(args)
+}
+
+fn main() {
+    let mut command = Cli::command().mut_subcommands(|cmd| cmd.args_override_self(true));
+    // Propagate global options to the subcommands, so they can be preset too
+    command.build();
+    let args = layered_args(&command).unwrap_or_else(|e| {
+        print_error(&e);
+        std::process::exit(1);
+    });
+    let cli = Cli::from_arg_matches(&command.get_matches_from(args)).unwrap_or_else(|e| e.exit());
+    let verbosity = if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.summary {
+        Verbosity::Summary
+    } else {
+        Verbosity::Normal
+    };
+    VERBOSITY.get_or_init(|| verbosity);
+    set_retry_wait_budget(Duration::from_secs(cli.retry_wait_budget));
+    let fetch = UrlLoaderOptions {
+        retries: cli.retries,
+        timeout: (cli.timeout > 0).then(|| Duration::from_secs(cli.timeout)),
+        ..UrlLoaderOptions::DEFAULT
+    };
+    // The command's loads, and the consolidations it runs, look for these
+    let _metadata_files = if cli.metadata_names.is_empty() {
+        None
+    } else {
+        match use_metadata_file_patterns(&cli.metadata_names) {
+            Ok(patterns) => Some(patterns),
+            Err(e) => {
+                print_error(&e);
+                std::process::exit(1);
+            }
+        }
+    };
+    // Every file and URL read for the command goes into the run report
+    let _audit = AUDIT_LOG.get_or_init(Default::default).activate();
 
     let result = match cli.command {
-        Commands::Consolidate(args) => run_consolidate(args),
-        Commands::Merge(args) => run_merge(args),
+        Commands::Consolidate(args) => run_consolidate(args, fetch),
+        Commands::Merge(args) => run_merge(args, fetch),
+        Commands::Harvest(args) => run_harvest(args, &fetch),
+        Commands::Namespace(args) => run_namespace(args, &fetch),
+        Commands::Deconsolidate(args) => run_deconsolidate(args, &fetch),
+        Commands::Lint(args) => run_lint(args, &fetch),
+        #[cfg(feature = "grpc")]
+        Commands::ServeGrpc(args) => run_serve_grpc(args, &fetch),
+        Commands::Vocab(VocabCommand::Export { output }) => export_vocab(output.as_ref()),
     };
 
     if let Err(e) = result {
-        eprintln!("Error: {}", e);
+        print_error(&e);
         std::process::exit(1);
     }
 }
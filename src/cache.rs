@@ -0,0 +1,495 @@
+//! Caching of collected crates
+//!
+//! Consolidating a large hierarchy again after a few of its crates changed
+//! mostly repeats work: every unchanged crate is collected and has its ids
+//! rewritten exactly as before. With a [`SubcrateCache`] in the
+//! [`ConsolidateOptions`], the entities of each crate are stored after
+//! rewriting, keyed by a hash of its metadata, its namespace, the subcrates
+//! its loader lists and the options, and later consolidations splice them in
+//! instead.
+//!
+//! How a crate's ids are rewritten also depends on the crates collected
+//! before it (fragment ids they used, namespaces they took), so a cached
+//! crate is only reused if its ids map exactly as they did. Subcrates are
+//! still loaded, as their metadata is part of the key.
+//!
+//! Loading them is what an [`HttpCache`] saves for remote hierarchies: it
+//! keeps fetched metadata on disk and revalidates it with the server's
+//! `ETag` and `Last-Modified`, so unchanged metadata isn't downloaded again.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use ulid::Ulid;
+
+use crate::collect::CollectedEntity;
+use crate::consolidate::ConsolidateOptions;
+use crate::error::ConsolidateError;
+
+/// What a cached crate is stored under
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// Hex-encoded SHA-256 hash of the crate's @graph
+    pub source: String,
+    /// Namespace the crate was collected into
+    pub namespace: String,
+    /// Hex-encoded SHA-256 hash of the subcrates its loader listed
+    pub listed: String,
+    /// Hex-encoded SHA-256 hash of the serialized options
+    pub options: String,
+}
+
+impl CacheKey {
+    /// Key of `graph` collected into `namespace` with the `listed`
+    /// subcrates and `options`
+    pub fn new(
+        graph: &[Value],
+        namespace: &str,
+        listed: &[String],
+        options: &ConsolidateOptions,
+    ) -> Result<Self, ConsolidateError> {
+        let mut source = HashWriter(Sha256::new());
+        serde_json::to_writer(&mut source, graph)?;
+        let mut hashed_listed = HashWriter(Sha256::new());
+        serde_json::to_writer(&mut hashed_listed, listed)?;
+        let mut hashed_options = HashWriter(Sha256::new());
+        serde_json::to_writer(&mut hashed_options, options)?;
+        Ok(Self {
+            source: format!("{:x}", source.0.finalize()),
+            namespace: namespace.to_string(),
+            listed: format!("{:x}", hashed_listed.0.finalize()),
+            options: format!("{:x}", hashed_options.0.finalize()),
+        })
+    }
+
+    /// Hex-encoded SHA-256 hash of the whole key, e.g. to name a file by
+    pub fn digest(&self) -> String {
+        let mut hasher = Sha256::new();
+        for part in [&self.source, &self.namespace, &self.listed, &self.options] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// A crate's entities after collection and id rewriting
+///
+/// Opaque apart from (de)serialization, so caches can persist it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedCrate {
+    /// Ids of its subcrates, including those its loader listed
+    pub(crate) subcrate_ids: Vec<String>,
+    /// Its references to subcrates, before rewriting
+    pub(crate) subcrate_refs: HashMap<String, Value>,
+    /// Original ids of its local entities and root, whose mapping was built
+    pub(crate) ids: Vec<String>,
+    /// Ids of its root entity candidates
+    pub(crate) root_candidates: Vec<String>,
+    /// Original id of its metadata descriptor
+    pub(crate) descriptor_id: Option<String>,
+    /// The id mapping the entities were rewritten with
+    pub(crate) id_map: HashMap<String, String>,
+    pub(crate) root_entity: Option<Value>,
+    pub(crate) metadata_descriptor: Option<Value>,
+    pub(crate) local: Vec<CachedEntity>,
+    pub(crate) shared: Vec<CachedEntity>,
+}
+
+/// A [`CollectedEntity`] without its namespace, which is part of the key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CachedEntity {
+    entity: Value,
+    original_id: String,
+    index: usize,
+}
+
+impl CachedEntity {
+    pub(crate) fn new(collected: &CollectedEntity) -> Self {
+        Self {
+            entity: collected.entity.clone(),
+            original_id: collected.original_id.clone(),
+            index: collected.index,
+        }
+    }
+
+    pub(crate) fn into_collected(self, namespace: &Arc<str>) -> CollectedEntity {
+        CollectedEntity {
+            entity: self.entity,
+            original_id: self.original_id,
+            namespace: Arc::clone(namespace),
+            index: self.index,
+        }
+    }
+}
+
+/// Storage of collected crates
+///
+/// Caches are shared by all crates of a consolidation and must be
+/// thread-safe. A crate that can't be read back is collected again.
+pub trait SubcrateCache: Send + Sync {
+    /// The crate stored under `key`, if any
+    fn get(&self, key: &CacheKey) -> Option<CachedCrate>;
+    /// Store `entry` under `key`, replacing what was stored before
+    fn put(&self, key: &CacheKey, entry: &CachedCrate) -> Result<(), ConsolidateError>;
+}
+
+impl fmt::Debug for dyn SubcrateCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SubcrateCache")
+    }
+}
+
+/// Cache keeping crates in memory, for consolidations within one process
+#[derive(Debug, Default)]
+pub struct MemorySubcrateCache {
+    entries: Mutex<HashMap<CacheKey, CachedCrate>>,
+}
+
+impl MemorySubcrateCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of cached crates
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Check if no crates are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SubcrateCache for MemorySubcrateCache {
+    fn get(&self, key: &CacheKey) -> Option<CachedCrate> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    fn put(&self, key: &CacheKey, entry: &CachedCrate) -> Result<(), ConsolidateError> {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.clone(), entry.clone());
+        Ok(())
+    }
+}
+
+/// Cache keeping one JSON file per crate in a directory
+#[derive(Debug)]
+pub struct DirSubcrateCache {
+    dir: PathBuf,
+}
+
+impl DirSubcrateCache {
+    /// Cache in `dir`, which is created if missing
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, ConsolidateError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        self.dir.join(format!("{}.json", key.digest()))
+    }
+}
+
+impl SubcrateCache for DirSubcrateCache {
+    fn get(&self, key: &CacheKey) -> Option<CachedCrate> {
+        let content = fs::read(self.path(key)).ok()?;
+        serde_json::from_slice(&content).ok()
+    }
+
+    fn put(&self, key: &CacheKey, entry: &CachedCrate) -> Result<(), ConsolidateError> {
+        write_atomic(&self.path(key), &serde_json::to_vec(entry)?)
+    }
+}
+
+/// Cache of fetched remote metadata, revalidated with `ETag` and
+/// `Last-Modified`
+///
+/// Bodies are stored content-addressed under `objects/<sha256>`, and the
+/// validators of each URL under `urls/<sha256 of the URL>.json`. Entries
+/// whose body doesn't match its hash are ignored.
+#[derive(Debug)]
+pub struct HttpCache {
+    dir: PathBuf,
+    max_age: Option<Duration>,
+}
+
+/// What an [`HttpCache`] knows about a URL
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Hex-encoded SHA-256 hash of the body
+    pub sha256: String,
+    /// When the body was last fetched or revalidated, in seconds since the
+    /// Unix epoch
+    pub fetched_at: u64,
+}
+
+impl HttpCache {
+    /// Cache in `dir`, which is created if missing
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, ConsolidateError> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("urls"))?;
+        fs::create_dir_all(dir.join("objects"))?;
+        Ok(Self { dir, max_age: None })
+    }
+
+    /// Serve entries younger than `max_age` without revalidating them
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// The entry of `url` and its body, if cached and intact
+    pub fn get(&self, url: &str) -> Option<(CachedResponse, String)> {
+        let content = fs::read(self.entry_path(url)).ok()?;
+        let entry: CachedResponse = serde_json::from_slice(&content).ok()?;
+        if entry.url != url {
+            return None;
+        }
+        let body = fs::read(self.object_path(&entry.sha256)).ok()?;
+        if format!("{:x}", Sha256::digest(&body)) != entry.sha256 {
+            return None;
+        }
+        Some((entry, String::from_utf8(body).ok()?))
+    }
+
+    /// Store `body` as the response to `url`, with its validators
+    pub fn put(
+        &self,
+        url: &str,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+        body: &str,
+    ) -> Result<(), ConsolidateError> {
+        let sha256 = format!("{:x}", Sha256::digest(body.as_bytes()));
+        let object = self.object_path(&sha256);
+        if !object.exists() {
+            write_atomic(&object, body.as_bytes())?;
+        }
+        let entry = CachedResponse {
+            url: url.to_string(),
+            etag: etag.map(str::to_string),
+            last_modified: last_modified.map(str::to_string),
+            sha256,
+            fetched_at: unix_now(),
+        };
+        write_atomic(&self.entry_path(url), &serde_json::to_vec(&entry)?)
+    }
+
+    /// Whether `entry` may be served without revalidating it
+    pub fn is_fresh(&self, entry: &CachedResponse) -> bool {
+        self.max_age
+            .is_some_and(|max_age| unix_now().saturating_sub(entry.fetched_at) < max_age.as_secs())
+    }
+
+    fn entry_path(&self, url: &str) -> PathBuf {
+        let digest = format!("{:x}", Sha256::digest(url.as_bytes()));
+        self.dir.join("urls").join(format!("{}.json", digest))
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("objects").join(sha256)
+    }
+}
+
+/// Write through a temporary file, so readers never see a partial file
+///
+/// The temporary file is named for this write alone, as other threads or
+/// processes may be writing the same file.
+fn write_atomic(path: &Path, content: &[u8]) -> Result<(), ConsolidateError> {
+    let tmp = path.with_extension(format!("{}-{}.tmp", std::process::id(), Ulid::new()));
+    let written = fs::write(&tmp, content).and_then(|()| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    Ok(written?)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// Writer feeding a hasher
+struct HashWriter(Sha256);
+
+impl io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{consolidate, ConsolidateInput, SubcrateLoader};
+    use serde_json::json;
+
+    /// Loader serving subcrates by id
+    struct MapLoader(HashMap<&'static str, Vec<Value>>);
+
+    impl SubcrateLoader for MapLoader {
+        fn load(
+            &self,
+            subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            self.0
+                .get(subcrate_id)
+                .cloned()
+                .ok_or_else(|| ConsolidateError::InvalidStructure(subcrate_id.to_string()))
+        }
+    }
+
+    fn subcrate_ref(id: &str) -> Value {
+        json!({
+            "@id": id,
+            "@type": "Dataset",
+            "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+        })
+    }
+
+    /// A crate with a file and the contact point `#contact`, named `name`
+    fn leaf(name: &str) -> Vec<Value> {
+        vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "name": name,
+                "hasPart": [{"@id": "data.csv"}],
+                "contactPoint": {"@id": "#contact"}
+            }),
+            json!({"@id": "data.csv", "@type": "File"}),
+            json!({"@id": "#contact", "@type": "ContactPoint"}),
+        ]
+    }
+
+    fn root() -> Vec<Value> {
+        vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "./a/"}, {"@id": "./b/"}]}),
+            subcrate_ref("./a/"),
+            subcrate_ref("./b/"),
+        ]
+    }
+
+    fn run(loader: &MapLoader, cache: Option<Arc<dyn SubcrateCache>>) -> (Vec<Value>, usize) {
+        let options = ConsolidateOptions {
+            cache,
+            ..Default::default()
+        };
+        let result = consolidate(ConsolidateInput::Single(root()), loader, &options).unwrap();
+        (result.graph, result.stats.cached_crates)
+    }
+
+    #[test]
+    fn test_cache_reuses_unchanged_crates() {
+        let cache: Arc<dyn SubcrateCache> = Arc::new(MemorySubcrateCache::new());
+        let mut loader = MapLoader(HashMap::from([("./a/", leaf("A")), ("./b/", leaf("B"))]));
+        let (uncached, _) = run(&loader, None);
+        assert_eq!(
+            run(&loader, Some(Arc::clone(&cache))),
+            (uncached.clone(), 0)
+        );
+        assert_eq!(run(&loader, Some(Arc::clone(&cache))), (uncached, 3));
+
+        // Only the changed crate is collected again
+        loader.0.insert("./b/", leaf("B2"));
+        let (uncached, _) = run(&loader, None);
+        assert_eq!(run(&loader, Some(cache)), (uncached, 2));
+
+        // Crates are collected again for other metadata file names
+        let legacy = ConsolidateOptions {
+            metadata_file_patterns: vec!["ro-crate-metadata.jsonld".to_string()],
+            ..Default::default()
+        };
+        assert_ne!(
+            CacheKey::new(&root(), "", &[], &legacy).unwrap(),
+            CacheKey::new(&root(), "", &[], &ConsolidateOptions::default()).unwrap()
+        );
+
+        // And once the loader lists other subcrates
+        let listed = ["./c/".to_string()];
+        assert_ne!(
+            CacheKey::new(&root(), "", &listed, &legacy).unwrap(),
+            CacheKey::new(&root(), "", &[], &legacy).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cache_requires_same_id_mapping() {
+        // b's #contact is namespaced as a already used it
+        let cache: Arc<dyn SubcrateCache> = Arc::new(MemorySubcrateCache::new());
+        let mut loader = MapLoader(HashMap::from([("./a/", leaf("A")), ("./b/", leaf("B"))]));
+        run(&loader, Some(Arc::clone(&cache)));
+
+        // Once a drops it, b's cached entities would point at the wrong id
+        let mut a = leaf("A");
+        a.pop();
+        a[1].as_object_mut().unwrap().remove("contactPoint");
+        loader.0.insert("./a/", a);
+        let (uncached, _) = run(&loader, None);
+        assert!(uncached.iter().any(|e| e["@id"] == "#contact"));
+        assert_eq!(run(&loader, Some(cache)), (uncached, 1));
+    }
+
+    #[test]
+    fn test_dir_cache() {
+        let dir = std::env::temp_dir().join(format!("subcrate-cache-{}", std::process::id()));
+        let cache: Arc<dyn SubcrateCache> = Arc::new(DirSubcrateCache::new(&dir).unwrap());
+        let loader = MapLoader(HashMap::from([("./a/", leaf("A")), ("./b/", leaf("B"))]));
+        let (uncached, _) = run(&loader, Some(Arc::clone(&cache)));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        // A later run, e.g. another process, reads them back
+        let cache: Arc<dyn SubcrateCache> = Arc::new(DirSubcrateCache::new(&dir).unwrap());
+        assert_eq!(run(&loader, Some(cache)), (uncached, 3));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_atomic_concurrently() {
+        let dir = std::env::temp_dir().join(format!("write-atomic-{}", Ulid::new()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("entry.json");
+        let contents: Vec<String> = (0..8).map(|i| format!("{{\"writer\": {}}}", i)).collect();
+        std::thread::scope(|scope| {
+            for content in &contents {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..20 {
+                        write_atomic(path, content.as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+
+        // One writer's content, whole, and no temporary files left behind
+        assert!(contents.contains(&fs::read_to_string(&path).unwrap()));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
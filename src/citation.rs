@@ -0,0 +1,325 @@
+//! Bibliography of the consolidated crate
+//!
+//! Crates link to the works they build on through `citation`, `isBasedOn`
+//! and `isPartOf`, each spelling DOIs their own way: `doi:10.1234/x`,
+//! `http://dx.doi.org/10.1234/X`, or a `ScholarlyArticle` with a local id
+//! and the DOI as its `identifier`. [`ConsolidateCitations`] normalizes all
+//! of them to `https://doi.org/<doi>` (see [`normalize_doi`]) and merges the
+//! entities that turn out to describe the same work, so the consolidated
+//! crate has a single entity per cited work.
+
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+
+use crate::collect::{extract_id, has_type};
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+use crate::merge::union_merge_entities;
+use crate::postprocess::PostProcessor;
+use crate::vocab::ROOT_ENTITY_ID;
+use crate::wellknown::canonicalize_references;
+
+/// Properties linking an entity to the works it cites or builds on
+pub const CITATION_PROPERTIES: [&str; 3] = ["citation", "isBasedOn", "isPartOf"];
+
+/// Resolver prefix of normalized DOIs
+const DOI_RESOLVER: &str = "https://doi.org/";
+
+/// Normalize a DOI in any common form to "https://doi.org/<doi>"
+///
+/// Accepts bare DOIs ("10.1234/x"), `doi:` URIs and resolver URLs
+/// (`doi.org`, `dx.doi.org`, over HTTP or HTTPS). DOIs are case-insensitive,
+/// so they're lowercased. None for anything else.
+pub fn normalize_doi(value: &str) -> Option<String> {
+    let value = value.trim().to_lowercase();
+    let doi = ["doi:", "https://", "http://"]
+        .iter()
+        .find_map(|scheme| value.strip_prefix(scheme))
+        .map(|rest| {
+            ["doi.org/", "dx.doi.org/", "www.doi.org/"]
+                .iter()
+                .find_map(|host| rest.strip_prefix(host))
+                .unwrap_or(rest)
+        })
+        .unwrap_or(&value)
+        .trim_start();
+    let (prefix, suffix) = doi.split_once('/')?;
+    let registrant = prefix.strip_prefix("10.")?;
+    let valid = !registrant.is_empty()
+        && registrant.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && !suffix.is_empty()
+        && !suffix.chars().any(char::is_whitespace);
+    valid.then(|| format!("{}{}", DOI_RESOLVER, doi))
+}
+
+/// Normalizes the DOIs of cited works and merges their duplicates
+///
+/// Entities and citation links with a DOI as id are pointed at the
+/// normalized DOI, as are `ScholarlyArticle`s with a DOI `identifier`, and
+/// DOI strings in [`CITATION_PROPERTIES`] become references. With
+/// [`with_summary`](Self::with_summary), the root also cites every work any
+/// entity of the crate cites.
+#[derive(Debug, Clone, Default)]
+pub struct ConsolidateCitations {
+    summarize: bool,
+}
+
+impl ConsolidateCitations {
+    /// Normalize and merge citations, without summarizing them on the root
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set whether the root cites all works cited within the crate
+    pub fn with_summary(mut self, summarize: bool) -> Self {
+        self.summarize = summarize;
+        self
+    }
+}
+
+impl PostProcessor for ConsolidateCitations {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        let root_id = result
+            .origins
+            .iter()
+            .find(|o| o.namespace.is_empty() && o.original_id == ROOT_ENTITY_ID)
+            .map_or(ROOT_ENTITY_ID, |o| o.id.as_str())
+            .to_string();
+
+        // Citation links given as DOI strings become references
+        for obj in result.graph.iter_mut().filter_map(Value::as_object_mut) {
+            for property in CITATION_PROPERTIES {
+                if let Some(value) = obj.get_mut(property) {
+                    link_dois(value);
+                }
+            }
+        }
+
+        // The normalized DOI of every entity and citation target that has one
+        let mut dois: HashMap<String, String> = HashMap::new();
+        for entity in &result.graph {
+            let targets = CITATION_PROPERTIES
+                .iter()
+                .flat_map(|property| values(&entity[*property]))
+                .filter_map(extract_id);
+            for id in extract_id(entity).into_iter().chain(targets) {
+                if let Some(doi) = normalize_doi(id) {
+                    dois.insert(id.to_string(), doi);
+                }
+            }
+            let Some(id) = extract_id(entity).filter(|id| *id != root_id) else {
+                continue;
+            };
+            if has_type(entity, "ScholarlyArticle") && !dois.contains_key(id) {
+                if let Some(doi) = values(&entity["identifier"]).find_map(identifier_doi) {
+                    dois.insert(id.to_string(), doi);
+                }
+            }
+        }
+        if dois.is_empty() && !self.summarize {
+            return Ok(());
+        }
+
+        for entity in &mut result.graph {
+            canonicalize_references(entity, &|id| dois.get(id).map(String::as_str));
+        }
+        for origin in &mut result.origins {
+            if let Some(doi) = dois.get(&origin.id) {
+                origin.id = doi.clone();
+            }
+        }
+
+        // Merge the entities describing the same work into the first
+        let works: HashSet<&str> = dois.values().map(String::as_str).collect();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        let mut merged: Vec<Value> = Vec::with_capacity(result.graph.len());
+        for entity in result.graph.drain(..) {
+            let work = extract_id(&entity)
+                .filter(|id| works.contains(id))
+                .map(str::to_string);
+            match work.as_ref().and_then(|id| positions.get(id)) {
+                Some(&i) => merged[i] = union_merge_entities(&merged[i], &entity),
+                None => {
+                    if let Some(id) = work {
+                        positions.insert(id, merged.len());
+                    }
+                    merged.push(entity);
+                }
+            }
+        }
+        result.graph = merged;
+
+        if self.summarize {
+            summarize_on_root(&mut result.graph, &root_id);
+        }
+        Ok(())
+    }
+}
+
+/// Let the root cite every work cited by an entity of the crate
+fn summarize_on_root(graph: &mut [Value], root_id: &str) {
+    let Some(root_index) = graph.iter().position(|e| extract_id(e) == Some(root_id)) else {
+        return;
+    };
+    let mut cited: Vec<Value> = values(&graph[root_index]["citation"]).cloned().collect();
+    for entity in graph.iter() {
+        for work in values(&entity["citation"]) {
+            let reference = match extract_id(work) {
+                Some(id) => json!({ "@id": id }),
+                None => work.clone(),
+            };
+            if !cited.contains(&reference) {
+                cited.push(reference);
+            }
+        }
+    }
+    if cited.is_empty() {
+        return;
+    }
+    let value = if cited.len() == 1 {
+        cited.remove(0)
+    } else {
+        Value::Array(cited)
+    };
+    graph[root_index]["citation"] = value;
+}
+
+/// Turn the DOI strings of a citation property into references
+fn link_dois(value: &mut Value) {
+    match value {
+        Value::String(target) => {
+            if let Some(doi) = normalize_doi(target) {
+                *value = json!({ "@id": doi });
+            }
+        }
+        Value::Array(targets) => targets.iter_mut().for_each(link_dois),
+        _ => {}
+    }
+}
+
+/// The normalized DOI an `identifier` value holds, if any: a DOI string, a
+/// reference to one, or a PropertyValue with a DOI value
+fn identifier_doi(identifier: &Value) -> Option<String> {
+    match identifier {
+        Value::String(id) => normalize_doi(id),
+        Value::Object(obj) => obj
+            .get("@id")
+            .or_else(|| obj.get("value"))
+            .and_then(Value::as_str)
+            .and_then(normalize_doi),
+        _ => None,
+    }
+}
+
+/// The values of a property, whether single or an array
+fn values(value: &Value) -> impl Iterator<Item = &Value> {
+    match value {
+        Value::Array(items) => items.iter(),
+        Value::Null => [].iter(),
+        single => std::slice::from_ref(single).iter(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{
+        consolidate, ConsolidateInput, ConsolidateOptions, MergeCrate, NoOpLoader,
+    };
+
+    #[test]
+    fn test_normalize_doi() {
+        let expected = Some("https://doi.org/10.1234/abc.5".to_string());
+        for doi in [
+            "10.1234/ABC.5",
+            "doi:10.1234/abc.5",
+            "DOI: 10.1234/abc.5",
+            "http://dx.doi.org/10.1234/abc.5",
+            "https://doi.org/10.1234/Abc.5",
+        ] {
+            assert_eq!(normalize_doi(doi), expected, "{}", doi);
+        }
+        assert_eq!(
+            normalize_doi("10.1000.10/x/y"),
+            Some("https://doi.org/10.1000.10/x/y".to_string())
+        );
+        for other in [
+            "https://example.org/10.1/x",
+            "11.1234/x",
+            "10.1234/",
+            "10.x/y",
+        ] {
+            assert_eq!(normalize_doi(other), None, "{}", other);
+        }
+    }
+
+    #[test]
+    fn test_consolidate_citations() {
+        let main = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "citation": {"@id": "https://doi.org/10.1234/ABC"}}),
+            json!({"@id": "https://doi.org/10.1234/ABC", "@type": "ScholarlyArticle", "name": "Paper"}),
+        ];
+        let other = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "data.csv"}]}),
+            json!({
+                "@id": "data.csv",
+                "@type": "File",
+                "citation": ["http://dx.doi.org/10.1234/abc", "doi:10.5555/second"],
+                "isBasedOn": {"@id": "#paper"}
+            }),
+            json!({
+                "@id": "#paper",
+                "@type": "ScholarlyArticle",
+                "identifier": "doi:10.1234/abc",
+                "author": {"@id": "https://orcid.org/0000-0002-1825-0097"}
+            }),
+        ];
+        let input = ConsolidateInput::Merge {
+            main,
+            others: vec![MergeCrate {
+                graph: other,
+                folder_id: "./other/".to_string(),
+                name: None,
+            }],
+        };
+        let options = ConsolidateOptions::default()
+            .with_post_processor(ConsolidateCitations::new().with_summary(true));
+        let result = consolidate(input, &NoOpLoader, &options).unwrap();
+
+        let paper = "https://doi.org/10.1234/abc";
+        let papers: Vec<&Value> = result
+            .graph
+            .iter()
+            .filter(|e| extract_id(e) == Some(paper))
+            .collect();
+        assert_eq!(papers.len(), 1);
+        assert_eq!(papers[0]["name"], "Paper");
+        assert_eq!(
+            papers[0]["author"]["@id"],
+            "https://orcid.org/0000-0002-1825-0097"
+        );
+        let file = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e).is_some_and(|id| id.ends_with("data.csv")))
+            .unwrap();
+        assert_eq!(
+            file["citation"],
+            json!([{"@id": paper}, {"@id": "https://doi.org/10.5555/second"}])
+        );
+        assert_eq!(file["isBasedOn"], json!({"@id": paper}));
+
+        let root = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./"))
+            .unwrap();
+        assert_eq!(
+            root["citation"],
+            json!([{"@id": paper}, {"@id": "https://doi.org/10.5555/second"}])
+        );
+        assert_eq!(result.stats.total_entities, result.graph.len());
+    }
+}
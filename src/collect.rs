@@ -3,10 +3,14 @@
 //! Handles walking a crate's graph and collecting entities with
 //! provenance tracking for consolidation.
 
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashSet;
+use std::sync::Arc;
 
-use crate::id::{classify_id, IdKind};
+use crate::error::ConsolidateError;
+use crate::id::{classify_id, is_root_alias, IdKind};
+use crate::merge::union_merge_entities;
 use crate::vocab::{ROCRATE_PROFILE_PREFIX, ROOT_ENTITY_ID};
 
 /// An entity collected from a crate's graph with provenance info
@@ -16,8 +20,18 @@ pub struct CollectedEntity {
     pub entity: Value,
     /// Original @id before any rewriting
     pub original_id: String,
-    /// Namespace path this entity came from (empty string for root crate)
-    pub namespace: String,
+    /// Namespace path this entity came from (empty string for root crate),
+    /// shared by all entities of a crate
+    pub namespace: Arc<str>,
+    /// Position of the entity in its crate's @graph array
+    pub index: usize,
+}
+
+impl CollectedEntity {
+    /// JSON pointer to this entity (or a path within it) in its crate's metadata document
+    pub fn pointer(&self, path: &[&str]) -> String {
+        graph_pointer(self.index, path)
+    }
 }
 
 /// Result of collecting entities from a single crate
@@ -31,46 +45,63 @@ pub struct CrateCollection {
     pub subcrate_ids: Vec<String>,
     /// The root entity ("./") if found
     pub root_entity: Option<CollectedEntity>,
+    /// Further root candidates (duplicate "./" entries or aliases like ".//")
+    pub extra_roots: Vec<CollectedEntity>,
     /// The metadata descriptor entity if found
     pub metadata_descriptor: Option<CollectedEntity>,
 }
 
 /// Collect entities from a crate's graph (as JSON array)
-pub fn collect_from_graph(graph: &[Value], namespace: &str) -> CrateCollection {
-    let mut local_entities = Vec::new();
+///
+/// Takes ownership of the graph so entities are moved rather than cloned.
+pub fn collect_from_graph(graph: Vec<Value>, namespace: &str) -> CrateCollection {
+    // Most entities of a crate are local files and folders
+    let mut local_entities = Vec::with_capacity(graph.len());
     let mut shared_entities = Vec::new();
     let mut subcrate_ids = Vec::new();
     let mut root_entity = None;
+    let mut extra_roots = Vec::new();
     let mut metadata_descriptor = None;
+    let namespace: Arc<str> = Arc::from(namespace);
 
-    for entity in graph {
-        let id = match extract_id(entity) {
-            Some(id) => id,
+    for (index, entity) in graph.into_iter().enumerate() {
+        let original_id = match extract_id(&entity) {
+            Some(id) => id.to_string(),
             None => continue,
         };
+        let subcrate_ref = is_subcrate_ref(&entity);
 
         let collected = CollectedEntity {
-            entity: entity.clone(),
-            original_id: id.to_string(),
-            namespace: namespace.to_string(),
+            entity,
+            original_id,
+            namespace: Arc::clone(&namespace),
+            index,
         };
+        let id = collected.original_id.as_str();
+
+        if is_root_alias(id) {
+            extra_roots.push(collected);
+            continue;
+        }
 
         match classify_id(id) {
             IdKind::Root => {
-                root_entity = Some(collected);
+                if let Some(previous) = root_entity.replace(collected) {
+                    extra_roots.push(previous);
+                }
             }
             IdKind::MetadataDescriptor => {
                 metadata_descriptor = Some(collected);
             }
             IdKind::Absolute => {
                 // Check if this absolute URL is a subcrate reference
-                if is_subcrate_ref(entity) {
+                if subcrate_ref {
                     subcrate_ids.push(id.to_string());
                 }
                 shared_entities.push(collected);
             }
             IdKind::Relative | IdKind::Fragment => {
-                if is_subcrate_ref(entity) && id != ROOT_ENTITY_ID {
+                if subcrate_ref && id != ROOT_ENTITY_ID {
                     subcrate_ids.push(id.to_string());
                 }
                 local_entities.push(collected);
@@ -83,10 +114,105 @@ pub fn collect_from_graph(graph: &[Value], namespace: &str) -> CrateCollection {
         shared_entities,
         subcrate_ids,
         root_entity,
+        extra_roots,
         metadata_descriptor,
     }
 }
 
+/// Policy for crates that contain more than one root entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiRootPolicy {
+    /// Fail consolidation
+    Error,
+    /// Keep the candidate the metadata descriptor is `about`
+    #[default]
+    PickDescriptorAbout,
+    /// Union-merge all candidates into a single root
+    Merge,
+}
+
+/// Resolve all root candidates of a collection into a single "./" root entity
+///
+/// Returns the original ids of all candidates when there was more than one
+/// (or when the only candidate was an alias), so callers can report the
+/// ambiguity and redirect references to the dropped ids.
+pub fn resolve_root(
+    collection: &mut CrateCollection,
+    policy: MultiRootPolicy,
+) -> Result<Vec<String>, ConsolidateError> {
+    if collection.extra_roots.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let candidates: Vec<CollectedEntity> = collection
+        .root_entity
+        .take()
+        .into_iter()
+        .chain(collection.extra_roots.drain(..))
+        .collect();
+    let ids: Vec<String> = candidates.iter().map(|c| c.original_id.clone()).collect();
+
+    let mut chosen = if candidates.len() == 1 {
+        candidates.into_iter().next().unwrap()
+    } else {
+        match policy {
+            MultiRootPolicy::Error => {
+                return Err(ConsolidateError::MultipleRoots {
+                    namespace: candidates[0].namespace.to_string(),
+                    ids,
+                    pointers: candidates.iter().map(|c| c.pointer(&[])).collect(),
+                });
+            }
+            MultiRootPolicy::PickDescriptorAbout => {
+                let about = collection
+                    .metadata_descriptor
+                    .as_ref()
+                    .and_then(|d| d.entity.get("about"))
+                    .and_then(extract_id);
+                let index = candidates
+                    .iter()
+                    .position(|c| Some(c.original_id.as_str()) == about)
+                    .or_else(|| {
+                        candidates
+                            .iter()
+                            .position(|c| c.original_id == ROOT_ENTITY_ID)
+                    })
+                    .unwrap_or(0);
+                candidates.into_iter().nth(index).unwrap()
+            }
+            MultiRootPolicy::Merge => candidates
+                .into_iter()
+                .reduce(|mut acc, c| {
+                    acc.entity = union_merge_entities(&acc.entity, &c.entity);
+                    acc
+                })
+                .unwrap(),
+        }
+    };
+
+    if let Some(obj) = chosen.entity.as_object_mut() {
+        obj.insert("@id".to_string(), Value::String(ROOT_ENTITY_ID.to_string()));
+    }
+    chosen.original_id = ROOT_ENTITY_ID.to_string();
+    collection.root_entity = Some(chosen);
+
+    Ok(ids)
+}
+
+/// Build a JSON pointer (RFC 6901) into the @graph of a metadata document
+///
+/// `path` segments are escaped, e.g. `graph_pointer(3, &["author", "0", "@id"])`
+/// yields `/@graph/3/author/0/@id`.
+pub fn graph_pointer(index: usize, path: &[&str]) -> String {
+    let mut pointer = format!("/@graph/{}", index);
+    for segment in path {
+        pointer.push('/');
+        pointer.push_str(&segment.replace('~', "~0").replace('/', "~1"));
+    }
+    pointer
+}
+
 /// Extract @id from an entity
 pub fn extract_id(entity: &Value) -> Option<&str> {
     entity.get("@id").and_then(|v| v.as_str())
@@ -140,8 +266,24 @@ pub fn conforms_to_rocrate(entity: &Value) -> bool {
 }
 
 /// Check if an entity is a subcrate reference
+///
+/// Subcrates are usually folders, but may also be packaged as a zip file
+/// (e.g. a `File` "experiments.zip" that conforms to RO-Crate).
 pub fn is_subcrate_ref(entity: &Value) -> bool {
-    has_type(entity, "Dataset") && conforms_to_rocrate(entity)
+    (has_type(entity, "Dataset") || is_zipped_crate(entity)) && conforms_to_rocrate(entity)
+}
+
+/// Check if an entity's @id names a zip file packaged with the crate, or one
+/// whose metadata is linked with `subjectOf`
+///
+/// Remote zip files are plain files otherwise, even if they conform to
+/// RO-Crate: their metadata isn't fetched by guessing a path inside them.
+fn is_zipped_crate(entity: &Value) -> bool {
+    let Some(id) = extract_id(entity) else {
+        return false;
+    };
+    id.to_lowercase().ends_with(".zip")
+        && (classify_id(id) == IdKind::Relative || extract_subject_of(entity).is_some())
 }
 
 /// Check if an entity is the metadata descriptor
@@ -233,6 +375,26 @@ mod tests {
 
         let regular = json!({"@id": "./data/", "@type": "Dataset"});
         assert!(!is_subcrate_ref(&regular));
+
+        let zipped = json!({
+            "@id": "experiments.zip",
+            "@type": "File",
+            "conformsTo": {"@id": "https://w3id.org/ro/crate/1.2"}
+        });
+        assert!(is_subcrate_ref(&zipped));
+        let plain = json!({"@id": "experiments.zip", "@type": "File"});
+        assert!(!is_subcrate_ref(&plain));
+
+        // Remote zip files stay plain files unless their metadata is linked
+        let remote = json!({
+            "@id": "https://example.org/data.zip",
+            "@type": "File",
+            "conformsTo": {"@id": "https://w3id.org/ro/crate/1.2"}
+        });
+        assert!(!is_subcrate_ref(&remote));
+        let mut described = remote.clone();
+        described["subjectOf"] = json!({"@id": "https://example.org/ro-crate-metadata.json"});
+        assert!(is_subcrate_ref(&described));
     }
 
     #[test]
@@ -264,7 +426,7 @@ mod tests {
             }),
         ];
 
-        let collection = collect_from_graph(&graph, "");
+        let collection = collect_from_graph(graph, "");
 
         assert!(collection.root_entity.is_some());
         assert!(collection.metadata_descriptor.is_some());
@@ -274,6 +436,45 @@ mod tests {
         assert_eq!(collection.subcrate_ids[0], "./experiments/");
     }
 
+    #[test]
+    fn test_resolve_root() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": ".//"}}),
+            json!({"@id": "./", "@type": "Dataset", "name": "First"}),
+            json!({"@id": ".//", "@type": "Dataset", "name": "Second"}),
+        ];
+
+        let mut collection = collect_from_graph(graph.clone(), "");
+        assert_eq!(collection.extra_roots.len(), 1);
+        let ids = resolve_root(&mut collection, MultiRootPolicy::PickDescriptorAbout).unwrap();
+        assert_eq!(ids, vec!["./", ".//"]);
+        let root = collection.root_entity.unwrap().entity;
+        assert_eq!(root["@id"], "./");
+        assert_eq!(root["name"], "Second");
+
+        let mut collection = collect_from_graph(graph.clone(), "");
+        resolve_root(&mut collection, MultiRootPolicy::Merge).unwrap();
+        let root = collection.root_entity.unwrap().entity;
+        assert_eq!(root["name"], json!(["First", "Second"]));
+
+        let mut collection = collect_from_graph(graph, "");
+        match resolve_root(&mut collection, MultiRootPolicy::Error) {
+            Err(ConsolidateError::MultipleRoots { pointers, .. }) => {
+                assert_eq!(pointers, vec!["/@graph/1", "/@graph/2"]);
+            }
+            other => panic!("expected MultipleRoots, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_graph_pointer() {
+        assert_eq!(
+            graph_pointer(123, &["author", "0", "@id"]),
+            "/@graph/123/author/0/@id"
+        );
+        assert_eq!(graph_pointer(0, &["a/b", "c~d"]), "/@graph/0/a~1b/c~0d");
+    }
+
     #[test]
     fn test_get_referenced_ids() {
         let entity = json!({
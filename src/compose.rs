@@ -0,0 +1,544 @@
+//! Loaders built from other loaders
+//!
+//! A hierarchy often lives in more than one place: most subcrates next to
+//! the root on disk, a few only in an archive or on a web server, some
+//! moved since the root was written. [`ChainLoader`] tries loaders in
+//! order until one finds the subcrate, [`MappingLoader`] points subcrate
+//! ids at alternate locations before loading them, and [`HybridLoader`]
+//! follows the references of local crates to remote ones.
+
+use serde_json::{json, Value};
+
+use crate::collect::{extract_id, is_metadata_descriptor};
+use crate::consolidate::{SubcrateLoader, SubcrateRef, UrlLoader};
+use crate::error::ConsolidateError;
+use crate::vocab::{CONSOLIDATED_FROM_SHORT, METADATA_DESCRIPTOR_ID, ROOT_ENTITY_ID};
+
+/// Loader trying each of its loaders in order, e.g. filesystem, then zip,
+/// then URL
+///
+/// The first loader to load a subcrate wins. Cycles and sandbox violations
+/// aren't a matter of where a subcrate is looked for, so they are returned
+/// right away; other errors fall through to the next loader, and if all
+/// fail their reasons are reported together. While offline, loaders that
+/// would fetch fall through too, but if no loader succeeds the subcrate
+/// fails as [`NetworkDisabled`](ConsolidateError::NetworkDisabled).
+#[derive(Default)]
+pub struct ChainLoader(pub Vec<Box<dyn SubcrateLoader>>);
+
+impl ChainLoader {
+    /// Chain without loaders, which loads nothing
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Try `loader` after the loaders chained before
+    pub fn with_loader(mut self, loader: impl SubcrateLoader + 'static) -> Self {
+        self.0.push(Box::new(loader));
+        self
+    }
+
+    /// Number of chained loaders
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Check if no loaders are chained
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl SubcrateLoader for ChainLoader {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        self.load_ref(&SubcrateRef::new(
+            subcrate_id,
+            parent_namespace,
+            subcrate_entity,
+        ))
+    }
+
+    fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+        let mut errors = Vec::new();
+        for loader in &self.0 {
+            match loader.load_ref(subcrate) {
+                Ok(graph) => return Ok(graph),
+                Err(e @ ConsolidateError::CycleDetected(_))
+                | Err(e @ ConsolidateError::SandboxViolation { .. }) => return Err(e),
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.len() == 1 {
+            return Err(errors.remove(0));
+        }
+        // Fetching may have been all that was left to try
+        if let Some(i) = errors
+            .iter()
+            .position(|e| matches!(e, ConsolidateError::NetworkDisabled(_)))
+        {
+            return Err(errors.swap_remove(i));
+        }
+        let reason = if errors.is_empty() {
+            "no loaders to try".to_string()
+        } else {
+            errors
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; ")
+        };
+        Err(ConsolidateError::LoadError {
+            path: subcrate.id.to_string(),
+            reason,
+            source: None,
+        })
+    }
+
+    /// The subcrates listed by any of the loaders, in order
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        let mut listed = Vec::new();
+        for id in self.0.iter().flat_map(|l| l.listed_subcrates(namespace)) {
+            if !listed.contains(&id) {
+                listed.push(id);
+            }
+        }
+        listed
+    }
+
+    /// The location of the first loader that knows one
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        self.0
+            .iter()
+            .find_map(|l| l.location(subcrate_id, parent_namespace, subcrate_entity))
+    }
+}
+
+/// Loader rewriting subcrate ids to alternate locations before passing them
+/// on to another loader
+///
+/// A mapping whose source ends with '/' applies to every id it is a prefix
+/// of, replacing that prefix; others apply to exactly their id. The longest
+/// matching source wins. Subcrates whose id is rewritten are loaded without
+/// their entity, so a `subjectOf` pointing at the old location isn't
+/// followed.
+pub struct MappingLoader {
+    inner: Box<dyn SubcrateLoader>,
+    mappings: Vec<(String, String)>,
+}
+
+impl MappingLoader {
+    /// Pass subcrates on to `inner`, without mappings yet
+    pub fn new(inner: impl SubcrateLoader + 'static) -> Self {
+        Self {
+            inner: Box::new(inner),
+            mappings: Vec::new(),
+        }
+    }
+
+    /// Load the subcrate `from` (or those below it) from `to` instead
+    pub fn with_mapping(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.mappings.push((from.into(), to.into()));
+        self
+    }
+
+    /// The id `subcrate_id` is loaded as, if a mapping applies
+    pub fn map_id(&self, subcrate_id: &str) -> Option<String> {
+        self.mappings
+            .iter()
+            .filter_map(|(from, to)| {
+                let rest = if from.ends_with('/') {
+                    subcrate_id.strip_prefix(from.as_str())?
+                } else if subcrate_id == from {
+                    ""
+                } else {
+                    return None;
+                };
+                Some((from.len(), format!("{}{}", to, rest)))
+            })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, id)| id)
+    }
+}
+
+impl SubcrateLoader for MappingLoader {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        self.load_ref(&SubcrateRef::new(
+            subcrate_id,
+            parent_namespace,
+            subcrate_entity,
+        ))
+    }
+
+    fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+        match self.map_id(subcrate.id) {
+            Some(id) => self.inner.load_ref(&SubcrateRef {
+                id: &id,
+                entity: None,
+                ..*subcrate
+            }),
+            None => self.inner.load_ref(subcrate),
+        }
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        self.inner.listed_subcrates(namespace)
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        match self.map_id(subcrate_id) {
+            Some(id) => self.inner.location(&id, parent_namespace, None),
+            None => self
+                .inner
+                .location(subcrate_id, parent_namespace, subcrate_entity),
+        }
+    }
+}
+
+/// Loader of local crates that follows their references to remote subcrates
+///
+/// Subcrates with an absolute `http(s)` id, or whose `subjectOf` is a remote
+/// metadata file, are fetched with a [`UrlLoader`], as are the subcrates of
+/// those (relative to them); all others are loaded by the local loader. The
+/// root of each fetched subcrate references the file it came from as
+/// `consolidatedFrom`, which its Subcrate folder keeps.
+pub struct HybridLoader {
+    local: Box<dyn SubcrateLoader>,
+    remote: UrlLoader,
+}
+
+impl HybridLoader {
+    /// Load local subcrates with `local`, and fetch remote ones
+    pub fn new(local: impl SubcrateLoader + 'static) -> Self {
+        Self {
+            local: Box::new(local),
+            remote: UrlLoader::new(""),
+        }
+    }
+
+    /// Fetch remote subcrates with `remote`, e.g. to use mirrors or other
+    /// options; its base URL isn't used
+    pub fn with_remote(mut self, remote: UrlLoader) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    /// URL of the metadata file of a remote subcrate, None for local ones
+    pub fn remote_url(&self, subcrate: &SubcrateRef) -> Option<String> {
+        let subject_of = subcrate
+            .entity
+            .and_then(|entity| entity.get("subjectOf"))
+            .and_then(|value| match value {
+                Value::Array(values) => values.first(),
+                value => Some(value),
+            })
+            .and_then(|value| extract_id(value).or(value.as_str()));
+        if let Some(url) = subject_of.filter(|url| is_remote(url)) {
+            return Some(url.to_string());
+        }
+        if is_remote(subcrate.id) {
+            return Some(self.remote.subcrate_url(subcrate.id, None));
+        }
+
+        // Relative to a parent fetched before
+        let parent_url = subcrate
+            .parent_root
+            .and_then(|root| root.get(CONSOLIDATED_FROM_SHORT))
+            .and_then(extract_id)?;
+        let relative = match subject_of {
+            Some(metadata) => metadata.to_string(),
+            None => format!(
+                "{}/{}",
+                subcrate.id.trim_end_matches('/'),
+                METADATA_DESCRIPTOR_ID
+            ),
+        };
+        let url = url::Url::parse(parent_url).ok()?.join(&relative).ok()?;
+        Some(url.to_string())
+    }
+}
+
+impl SubcrateLoader for HybridLoader {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        self.load_ref(&SubcrateRef::new(
+            subcrate_id,
+            parent_namespace,
+            subcrate_entity,
+        ))
+    }
+
+    fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+        let Some(url) = self.remote_url(subcrate) else {
+            return self.local.load_ref(subcrate);
+        };
+        let mut graph = self.remote.load_graph(&url)?;
+        let root_id = graph
+            .iter()
+            .find(|e| is_metadata_descriptor(e))
+            .and_then(|descriptor| descriptor.get("about"))
+            .and_then(extract_id)
+            .unwrap_or(ROOT_ENTITY_ID)
+            .to_string();
+        let root = graph
+            .iter_mut()
+            .find(|e| extract_id(e) == Some(root_id.as_str()));
+        if let Some(root) = root.and_then(Value::as_object_mut) {
+            root.insert(CONSOLIDATED_FROM_SHORT.to_string(), json!({ "@id": url }));
+        }
+        Ok(graph)
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        self.local.listed_subcrates(namespace)
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        let subcrate = SubcrateRef::new(subcrate_id, parent_namespace, subcrate_entity);
+        self.remote_url(&subcrate).or_else(|| {
+            self.local
+                .location(subcrate_id, parent_namespace, subcrate_entity)
+        })
+    }
+}
+
+fn is_remote(id: &str) -> bool {
+    id.starts_with("https://") || id.starts_with("http://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{consolidate, ConsolidateInput, ConsolidateOptions, UrlLoader};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    /// Loader serving subcrates by id
+    struct MapLoader(HashMap<&'static str, Vec<Value>>);
+
+    impl SubcrateLoader for MapLoader {
+        fn load(
+            &self,
+            subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            self.0
+                .get(subcrate_id)
+                .cloned()
+                .ok_or_else(|| ConsolidateError::LoadError {
+                    path: subcrate_id.to_string(),
+                    reason: "not in map".to_string(),
+                    source: None,
+                })
+        }
+    }
+
+    fn subcrate(name: &str) -> Vec<Value> {
+        vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "name": name}),
+        ]
+    }
+
+    #[test]
+    fn test_chain_loader() {
+        let chain = ChainLoader::new()
+            .with_loader(MapLoader(HashMap::from([("./a/", subcrate("A"))])))
+            .with_loader(MapLoader(HashMap::from([
+                ("./a/", subcrate("Shadowed")),
+                ("./b/", subcrate("B")),
+            ])));
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "./a/"}, {"@id": "./b/"}]}),
+            json!({"@id": "./a/", "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}}),
+            json!({"@id": "./b/", "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}}),
+        ];
+        let result = consolidate(
+            ConsolidateInput::Single(graph),
+            &chain,
+            &ConsolidateOptions::default(),
+        )
+        .unwrap();
+        let names: Vec<&str> = result
+            .graph
+            .iter()
+            .filter_map(|e| e["name"].as_str())
+            .collect();
+        assert!(names.contains(&"A") && names.contains(&"B"), "{:?}", names);
+        assert!(!names.contains(&"Shadowed"));
+
+        let err = chain.load("./c/", "", None).unwrap_err();
+        assert_eq!(err.code(), "load_error");
+        assert_eq!(err.to_string().matches("not in map").count(), 2, "{}", err);
+
+        // Cycles don't fall through
+        struct Cyclic;
+        impl SubcrateLoader for Cyclic {
+            fn load(
+                &self,
+                subcrate_id: &str,
+                _parent_namespace: &str,
+                _subcrate_entity: Option<&Value>,
+            ) -> Result<Vec<Value>, ConsolidateError> {
+                Err(ConsolidateError::CycleDetected(subcrate_id.to_string()))
+            }
+        }
+        let chain = ChainLoader::new()
+            .with_loader(Cyclic)
+            .with_loader(MapLoader(HashMap::from([("./a/", subcrate("A"))])));
+        let err = chain.load("./a/", "", None).unwrap_err();
+        assert_eq!(err.code(), "cycle_detected");
+    }
+
+    #[test]
+    fn test_mapping_loader() {
+        let loader = MappingLoader::new(MapLoader(HashMap::from([
+            ("./moved/", subcrate("Moved")),
+            ("./archive/runs/7/", subcrate("Run 7")),
+        ])))
+        .with_mapping("./old/", "./moved/")
+        .with_mapping("./runs/", "./archive/runs/")
+        .with_mapping("./runs/8/", "./gone/");
+        assert_eq!(
+            loader.map_id("./runs/7/").as_deref(),
+            Some("./archive/runs/7/")
+        );
+        assert_eq!(loader.map_id("./runs/8/").as_deref(), Some("./gone/"));
+        assert_eq!(loader.map_id("./other/"), None);
+
+        let graph = loader.load("./old/", "", None).unwrap();
+        assert_eq!(graph[1]["name"], "Moved");
+        let graph = loader.load("./runs/7/", "", None).unwrap();
+        assert_eq!(graph[1]["name"], "Run 7");
+        assert!(loader.load("./runs/8/", "", None).is_err());
+
+        // Mapped ids are located without the entity's subjectOf
+        let loader = MappingLoader::new(UrlLoader::new("https://example.org/crate/"))
+            .with_mapping("./data/", "https://mirror.example.org/data/");
+        let entity =
+            json!({"@id": "./data/", "subjectOf": {"@id": "https://example.org/old.json"}});
+        assert_eq!(
+            loader.location("./data/", "", Some(&entity)).as_deref(),
+            Some("https://mirror.example.org/data/ro-crate-metadata.json")
+        );
+    }
+
+    /// Server answering GET requests for the paths of `files`, 404 otherwise
+    fn file_server(files: HashMap<&'static str, Value>) -> String {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let (status, body) = match files.get(path) {
+                    Some(graph) => ("200 OK", json!({ "@graph": graph }).to_string()),
+                    None => ("404 Not Found", String::new()),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.into_inner().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        base
+    }
+
+    #[test]
+    fn test_hybrid_loader() {
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let base = file_server(HashMap::from([
+            (
+                "/remote/ro-crate-metadata.json",
+                json!([
+                    {"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+                    {"@id": "./", "@type": "Dataset", "name": "Remote", "hasPart": {"@id": "sub/"}},
+                    crate_ref("sub/"),
+                ]),
+            ),
+            (
+                "/remote/sub/ro-crate-metadata.json",
+                json!([
+                    {"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+                    {"@id": "./", "@type": "Dataset", "name": "Remote sub"},
+                ]),
+            ),
+        ]));
+        let remote_id = format!("{}/remote/", base);
+        let root = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "local/"}, {"@id": remote_id}]}),
+            crate_ref("local/"),
+            crate_ref(&remote_id),
+        ];
+        let loader = HybridLoader::new(MapLoader(HashMap::from([("local/", subcrate("Local"))])));
+        let result = consolidate(
+            ConsolidateInput::Single(root),
+            &loader,
+            &ConsolidateOptions {
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.stats.crates_consolidated, 4);
+
+        let folder = |name: &str| {
+            result
+                .graph
+                .iter()
+                .find(|e| e["name"] == name)
+                .unwrap()
+                .clone()
+        };
+        assert!(folder("Local").get(CONSOLIDATED_FROM_SHORT).is_none());
+        assert_eq!(
+            folder("Remote")[CONSOLIDATED_FROM_SHORT],
+            json!({"@id": format!("{}ro-crate-metadata.json", remote_id)})
+        );
+        assert_eq!(
+            folder("Remote sub")[CONSOLIDATED_FROM_SHORT],
+            json!({"@id": format!("{}sub/ro-crate-metadata.json", remote_id)})
+        );
+    }
+}
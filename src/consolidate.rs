@@ -3,34 +3,362 @@
 //! Recursive algorithm for consolidating RO-Crate hierarchies into
 //! a single metadata file.
 
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
 
-use crate::collect::{collect_from_graph, extract_id, CollectedEntity};
+use crate::arena::EntityArena;
+use crate::audit;
+use crate::cache::{CacheKey, CachedCrate, CachedEntity, SubcrateCache};
+use crate::collect::{
+    collect_from_graph, extract_id, extract_subject_of, has_type, resolve_root, CollectedEntity,
+    CrateCollection, MultiRootPolicy,
+};
+use crate::datetime::iso_datetime;
+use crate::detached::{collect_distributions, validate_pointers, DistributionPointer};
 use crate::error::ConsolidateError;
-use crate::id::{build_id_map, namespace_from_folder_id, rewrite_references, validate_folder_id};
-use crate::merge::merge_by_id;
-use crate::transform::{create_subcrate_folder, update_root_has_part};
-use crate::vocab::context_extension;
+use crate::id::{
+    classify_id, extend_id_map, namespace_fragment, namespace_from_folder_id, normalize_id,
+    normalize_references, rebase_id_in_place, rebase_references, remove_references,
+    rewrite_references, validate_base_id, validate_descriptor_id, validate_folder_id,
+    validate_namespace, FragmentIdPolicy, IdKind,
+};
+use crate::loader::{
+    current_fetch_error_counter, current_retry_budget, fetch_metadata_signposted, fetch_url_with,
+    go_offline, is_offline, limit_reads, link_header, retry_budget, retry_wait_budget, set_offline,
+    share_fetch_error_counter, share_retry_budget, HttpAuth, UrlLoaderOptions,
+};
+use crate::merge::{
+    dedup_merge_by_crate, ConflictStrategy, DedupMerge, MergeConflict, MergePolicy,
+    PropertyStrategy,
+};
+use crate::metadata_file::{
+    current_metadata_file_patterns, share_metadata_file_patterns, use_metadata_file_patterns,
+    DEFAULT_METADATA_FILE_PATTERNS,
+};
+use crate::mirror::Mirrors;
+use crate::output::{normalize_graph, to_json_string_styled, OutputStyle, ShapePolicy};
+use crate::postprocess::{run_post_processors, PostProcessor};
+use crate::profile::{count_allocations, Phase, Profiler};
+use crate::transform::{
+    add_conforms_to, add_reference, add_specialized_types, create_subcrate_folder_with,
+    rename_descriptor, update_root_has_part, ReferenceMergePolicy,
+};
+use crate::value::ValueComparison;
+use crate::vocab::{
+    context_extension, CONSOLIDATED_ENTITIES_SHORT, CONSOLIDATION_ACTION_ID,
+    CONSOLIDATION_INPUTS_SHORT, CONSOLIDATION_OPTIONS_SHORT, CONSOLIDATION_PROFILE_ID,
+    CONSOLIDATION_SOFTWARE_ID, ROOT_ENTITY_ID,
+};
 
 /// Options for consolidation
-#[derive(Debug, Clone)]
+///
+/// Serializes to (and deserializes from) a flat object keyed by field name,
+/// with policies in snake_case; missing fields take their default value.
+/// Post-processors and the cache are code and are not serialized, nor are
+/// the input locations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct ConsolidateOptions {
     /// Add "Subcrate" to @type of converted subcrate folders
     pub add_subcrate_type: bool,
-    /// Extend the @context with consolidation vocabulary
+    /// Further types of subcrate folders, by a type or `conformsTo` profile
+    /// of the subcrate's root entity (e.g. "LabProcess" for run crates)
+    pub subcrate_types: BTreeMap<String, Vec<String>>,
+    /// Extend the @context with consolidation vocabulary, and declare the
+    /// consolidation profile in the descriptor's `conformsTo`
     pub extend_context: bool,
+    /// Fail if a `contentUrl` or `distribution` pointer is not an absolute URI
+    pub require_absolute_pointers: bool,
+    /// Collect all `contentUrl`/`distribution` pointers into the result
+    pub collect_distributions: bool,
+    /// Absolute IRI to emit root and relative ids against instead of "./"
+    pub base_id: Option<String>,
+    /// How to handle crates with more than one root entity
+    pub multi_root_policy: MultiRootPolicy,
+    /// How to handle subcrates without a metadata descriptor
+    pub missing_descriptor_policy: MissingDescriptorPolicy,
+    /// What a subcrate's folder is made of
+    pub root_handling: RootHandling,
+    /// Link each subcrate folder to the folder of the crate containing it,
+    /// or the root, with `isPartOf`, to keep the nesting queryable
+    pub link_parents: bool,
+    /// Consolidate only this many levels of nested subcrates below each
+    /// crate given; deeper subcrates stay plain Dataset references, as if
+    /// they could not be loaded
+    pub max_depth: Option<usize>,
+    /// Glob patterns of subcrate folder ids (e.g. "./experiments/*") to
+    /// consolidate; if any are given, other subcrates stay plain references
+    ///
+    /// A nested subcrate is only reached if its parent is consolidated too.
+    pub include_subcrates: Vec<String>,
+    /// Glob patterns of subcrate folder ids (e.g. "./raw-data/") to leave
+    /// plain references, even if included
+    pub exclude_subcrates: Vec<String>,
+    /// Which side wins properties both a subcrate's root entity and the
+    /// parent's reference to it set, independent of how shared entities merge
+    pub reference_merge: ReferenceMergePolicy,
+    /// How properties shared entities set to different single values (e.g.
+    /// a person's `name` in two crates) are merged
+    pub conflict_strategy: ConflictStrategy,
+    /// How shared entities merge particular properties, e.g. "dateModified"
+    /// by [`PropertyStrategy::Latest`], instead of by the conflict strategy
+    pub property_strategies: BTreeMap<String, PropertyStrategy>,
+    /// Options overriding these for single subcrates, by glob pattern of
+    /// their folder ids as in [`include_subcrates`](Self::include_subcrates),
+    /// e.g. to filter one noisy instrument crate while the others pass through
+    pub subcrate_overrides: BTreeMap<String, SubcrateOverrides>,
+    /// Namespaces to consolidate discovered subcrates into instead of those
+    /// named after their folders, by the subcrate's path below the root
+    /// crate (or its URL), e.g. "raw_data_v2" -> "raw" to publish
+    /// "./raw_data_v2/" as "./raw/"
+    ///
+    /// Subcrates nested in a renamed one move along unless mapped
+    /// themselves. The patterns of [`include_subcrates`](Self::include_subcrates),
+    /// [`exclude_subcrates`](Self::exclude_subcrates) and
+    /// [`subcrate_overrides`](Self::subcrate_overrides) match the renamed
+    /// folder ids.
+    pub namespace_map: BTreeMap<String, String>,
+    /// Fail if a discovered subcrate cannot be loaded instead of skipping it
+    pub strict: bool,
+    /// Fail with [`ConsolidateError::NetworkDisabled`] instead of fetching
+    /// anything, so no remote content finds its way in unnoticed
+    ///
+    /// Subcrates that can't be loaded without fetching fail consolidation
+    /// even if not [`strict`](Self::strict).
+    pub offline: bool,
+    /// File name patterns of metadata files, in order of preference (see
+    /// [`metadata_file`](crate::metadata_file))
+    ///
+    /// Applies to the loads of the consolidation, from any thread.
+    pub metadata_file_patterns: Vec<String>,
+    /// Fail with [`ConsolidateError::LimitExceeded`] on a subcrate whose
+    /// metadata document takes more bytes than this as a loader reads it
+    ///
+    /// Reads stop at the limit (see [`limit_reads`](crate::limit_reads)),
+    /// so an oversized document is never held in memory; graphs passed in
+    /// and those of loaders not reading through it are not measured. Like
+    /// all limits, this fails consolidation even if not
+    /// [`strict`](Self::strict).
+    pub max_metadata_bytes: Option<u64>,
+    /// Fail on a crate with more entities than this
+    pub max_crate_entities: Option<usize>,
+    /// Fail once the crates loaded have more entities than this in total
+    pub max_total_entities: Option<usize>,
+    /// On failure, return what was consolidated so far with a failure report
+    pub keep_partial: bool,
+    /// How single property values are shaped in the output graph
+    pub shape: ShapePolicy,
+    /// How fragment ids of subcrates are rewritten
+    pub fragment_id_policy: FragmentIdPolicy,
+    /// Maximum number of explicit merge crates collected at once (1 = sequential)
+    pub parallelism: usize,
+    /// Keep subcrate descriptors as files of their folders instead of dropping them
+    ///
+    /// References to a subcrate's descriptor follow it to the folder, or are
+    /// removed along with it.
+    pub keep_subcrate_descriptors: bool,
+    /// @id of the output's metadata descriptor (e.g. "mycrate-ro-crate-metadata.json")
+    ///
+    /// Defaults to the root crate's descriptor id.
+    pub descriptor_id: Option<String>,
+    /// Normalize ids and folder ids to Unicode NFC before comparing them
+    pub normalize_unicode: bool,
+    /// When copies of shared entities, and the values merged into arrays,
+    /// are taken to be the same
+    pub value_comparison: ValueComparison,
+    /// How to handle subcrate folders whose names differ only in case
+    pub case_collision_policy: CaseCollisionPolicy,
+    /// Describe the run as a CreateAction, recording these options, which the
+    /// root entity `mentions`. While an [`AuditLog`](crate::AuditLog) is
+    /// active, the accesses recorded in it are listed as the action's inputs.
+    ///
+    /// The action also records when the run started and ended (at
+    /// `SOURCE_DATE_EPOCH` if set, for reproducible output), the absolute
+    /// identifiers of the crates consolidated as its `object`, or where
+    /// crates without one were loaded from, and the root and Subcrate
+    /// folders as its `result`.
+    pub record_provenance: bool,
+    /// Don't describe subcrates as folders: their parts become parts of the
+    /// entity referencing them, usually the root, and other references to
+    /// them point at that entity instead, for a conventional flat crate
+    ///
+    /// What a subcrate's root entity said about it (name, author, ...) is
+    /// lost. Combine with `extend_context: false` to use no consolidation
+    /// vocabulary at all.
+    pub flatten_only: bool,
+    /// Write the root crate's entities consolidation leaves unchanged
+    /// verbatim from its metadata document, after all others
+    ///
+    /// Only [`consolidate_mapped`](crate::mapped::consolidate_mapped) can,
+    /// as [`consolidate`] is given parsed graphs.
+    pub raw_passthrough: bool,
+    /// Run over the result in order, before data pointers are collected
+    #[serde(skip)]
+    pub post_processors: Vec<Arc<dyn PostProcessor>>,
+    /// Take crates collected by earlier consolidations from this cache, and
+    /// store those collected now in it
+    #[serde(skip)]
+    pub cache: Option<Arc<dyn SubcrateCache>>,
+    /// Where the crates given were loaded from (a path or URL), by folder id:
+    /// "./" for the main crate and the folder ids of the merge crates
+    ///
+    /// Subcrates are located by the loader. Recorded with
+    /// [`record_provenance`](Self::record_provenance).
+    #[serde(skip)]
+    pub input_locations: BTreeMap<String, String>,
 }
 
 impl Default for ConsolidateOptions {
     fn default() -> Self {
         Self {
             add_subcrate_type: true,
+            subcrate_types: BTreeMap::new(),
             extend_context: true,
+            require_absolute_pointers: false,
+            collect_distributions: false,
+            base_id: None,
+            multi_root_policy: MultiRootPolicy::default(),
+            missing_descriptor_policy: MissingDescriptorPolicy::default(),
+            root_handling: RootHandling::default(),
+            link_parents: false,
+            max_depth: None,
+            include_subcrates: Vec::new(),
+            exclude_subcrates: Vec::new(),
+            reference_merge: ReferenceMergePolicy::default(),
+            conflict_strategy: ConflictStrategy::default(),
+            property_strategies: BTreeMap::new(),
+            subcrate_overrides: BTreeMap::new(),
+            namespace_map: BTreeMap::new(),
+            strict: false,
+            offline: false,
+            metadata_file_patterns: DEFAULT_METADATA_FILE_PATTERNS.map(String::from).to_vec(),
+            max_metadata_bytes: None,
+            max_crate_entities: None,
+            max_total_entities: None,
+            keep_partial: false,
+            shape: ShapePolicy::default(),
+            fragment_id_policy: FragmentIdPolicy::default(),
+            parallelism: 1,
+            keep_subcrate_descriptors: false,
+            descriptor_id: None,
+            normalize_unicode: true,
+            value_comparison: ValueComparison::default(),
+            case_collision_policy: CaseCollisionPolicy::default(),
+            record_provenance: false,
+            flatten_only: false,
+            raw_passthrough: false,
+            post_processors: Vec::new(),
+            cache: None,
+            input_locations: BTreeMap::new(),
+        }
+    }
+}
+
+impl ConsolidateOptions {
+    /// Add a post-processor, run after those added before it
+    pub fn with_post_processor(mut self, processor: impl PostProcessor + 'static) -> Self {
+        self.post_processors.push(Arc::new(processor));
+        self
+    }
+
+    /// Merge shared entities by `policy`: its conflict strategy replaces
+    /// this one, and its property strategies apply to the properties without
+    /// one yet
+    pub fn with_merge_policy(mut self, policy: MergePolicy) -> Self {
+        self.conflict_strategy = policy.conflict_strategy;
+        for (property, strategy) in policy.property_strategies {
+            self.property_strategies.entry(property).or_insert(strategy);
         }
+        self
+    }
+}
+
+/// Options applying only to the subcrates matching a pattern of
+/// [`ConsolidateOptions::subcrate_overrides`]
+///
+/// Where several patterns match, those sorting later override the settings
+/// of the others and add to their lists.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubcrateOverrides {
+    /// How the subcrate's copies of shared entities merge into those of the
+    /// crates collected before it
+    pub conflict_strategy: Option<ConflictStrategy>,
+    /// Properties removed from the subcrate's entities, its root included
+    pub strip_properties: Vec<String>,
+    /// Types of entities dropped from the subcrate, along with its
+    /// references to them
+    pub exclude_types: Vec<String>,
+    /// Consolidate only this many levels of subcrates nested in the
+    /// subcrate, instead of as [`ConsolidateOptions::max_depth`] says
+    pub max_depth: Option<usize>,
+}
+
+impl SubcrateOverrides {
+    /// Apply the settings of `other` over these
+    fn extend(&mut self, other: &SubcrateOverrides) {
+        self.conflict_strategy = other.conflict_strategy.or(self.conflict_strategy);
+        self.strip_properties
+            .extend(other.strip_properties.iter().cloned());
+        self.exclude_types
+            .extend(other.exclude_types.iter().cloned());
+        self.max_depth = other.max_depth.or(self.max_depth);
     }
 }
 
+/// Policy for subcrates without a metadata descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingDescriptorPolicy {
+    /// Consolidate as if the subcrate had a standard descriptor
+    #[default]
+    Synthesize,
+    /// Consolidate the entities but don't mark the folder as a Subcrate
+    PlainDataset,
+    /// Fail consolidation
+    Fail,
+}
+
+/// What the folder standing in for a discovered subcrate is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RootHandling {
+    /// Merge the parent's reference to the subcrate into its root entity
+    #[default]
+    MergeReference,
+    /// Keep the subcrate's root entity with only its own properties
+    ///
+    /// Its metadata wiring (`subjectOf`, RO-Crate `conformsTo`) is stripped
+    /// all the same. The parent's reference is dropped.
+    PreserveRoot,
+}
+
+/// Policy for subcrate folders whose names differ only in case
+///
+/// `./Data/` and `./data/` are distinct ids, but the same directory when the
+/// consolidated crate is laid out on a case-insensitive filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseCollisionPolicy {
+    /// Keep both folders and report the collision as a warning
+    #[default]
+    Warn,
+    /// Give discovered subcrates a unique namespace, with a warning
+    ///
+    /// Folder ids of explicit merge crates are never renamed; collisions
+    /// with them are only reported.
+    Rename,
+    /// Treat folders as distinct whenever their ids differ
+    Allow,
+}
+
 /// A crate to be explicitly merged (not discovered from hierarchy)
 #[derive(Debug, Clone)]
 pub struct MergeCrate {
@@ -54,8 +382,40 @@ pub enum ConsolidateInput {
     },
 }
 
+/// A subcrate to load, with what its parent crate says about it
+#[derive(Debug, Clone, Copy)]
+pub struct SubcrateRef<'a> {
+    /// The @id of the subcrate reference (e.g., "./experiments/")
+    pub id: &'a str,
+    /// The namespace of the parent crate
+    pub parent_namespace: &'a str,
+    /// The parent's entity referencing the subcrate, as written there, with
+    /// hints like `subjectOf`, `distribution` or `url`
+    pub entity: Option<&'a Value>,
+    /// The parent's root entity, with the ids of the consolidated crate
+    pub parent_root: Option<&'a Value>,
+    /// The parent's metadata descriptor
+    pub parent_descriptor: Option<&'a Value>,
+}
+
+impl<'a> SubcrateRef<'a> {
+    /// Reference to `id` with nothing known about its parent but its namespace
+    pub fn new(id: &'a str, parent_namespace: &'a str, entity: Option<&'a Value>) -> Self {
+        Self {
+            id,
+            parent_namespace,
+            entity,
+            parent_root: None,
+            parent_descriptor: None,
+        }
+    }
+}
+
 /// Trait for loading subcrates during consolidation
-pub trait SubcrateLoader {
+///
+/// Loaders are shared between the threads collecting explicit merge crates
+/// in parallel, so they must be `Sync`.
+pub trait SubcrateLoader: Sync {
     /// Load a subcrate's @graph given its reference ID and parent namespace
     ///
     /// # Arguments
@@ -71,6 +431,38 @@ pub trait SubcrateLoader {
         parent_namespace: &str,
         subcrate_entity: Option<&Value>,
     ) -> Result<Vec<Value>, ConsolidateError>;
+
+    /// Load a subcrate given its reference and its parent's metadata
+    ///
+    /// Consolidation loads subcrates through this method, which calls
+    /// [`load`](Self::load) by default. Loaders needing the parent's root
+    /// entity or descriptor override it; loaders wrapping another forward it.
+    fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+        self.load(subcrate.id, subcrate.parent_namespace, subcrate.entity)
+    }
+
+    /// Ids of subcrates known for the crate with the given namespace
+    ///
+    /// Subcrates are discovered from references marked as RO-Crates
+    /// (`conformsTo`); ids listed here are loaded as subcrates as well,
+    /// whether or not the parent marks them. None by default.
+    fn listed_subcrates(&self, _namespace: &str) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Where the subcrate would be loaded from, if known without loading it
+    ///
+    /// Used to plan the fetches of a run (see [`crate::plan`]), and to record
+    /// the subcrates without an absolute identifier in its provenance. None
+    /// by default.
+    fn location(
+        &self,
+        _subcrate_id: &str,
+        _parent_namespace: &str,
+        _subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        None
+    }
 }
 
 /// A no-op loader that never finds subcrates (for explicit merge-only scenarios)
@@ -86,6 +478,7 @@ impl SubcrateLoader for NoOpLoader {
         Err(ConsolidateError::LoadError {
             path: "no-op".to_string(),
             reason: "NoOpLoader does not load subcrates".to_string(),
+            source: None,
         })
     }
 }
@@ -102,6 +495,10 @@ impl SubcrateLoader for NoOpLoader {
 pub struct UrlLoader {
     /// Base URL for resolving relative subcrate paths
     base_url: String,
+    /// Mirrors to fall back to when a fetch fails
+    mirrors: Option<Arc<Mirrors>>,
+    /// Retries, timeout and credentials of fetches
+    options: UrlLoaderOptions,
 }
 
 impl UrlLoader {
@@ -109,6 +506,8 @@ impl UrlLoader {
     pub fn new(base_url: impl Into<String>) -> Self {
         Self {
             base_url: base_url.into(),
+            mirrors: None,
+            options: UrlLoaderOptions::DEFAULT,
         }
     }
 
@@ -122,7 +521,227 @@ impl UrlLoader {
             let trimmed = url.trim_end_matches('/');
             format!("{}/", trimmed)
         };
-        Self { base_url: base }
+        Self::new(base)
+    }
+
+    /// Fall back to `mirrors` when fetching a subcrate fails
+    ///
+    /// The mirrors may be shared with other loaders, and record which URLs
+    /// they answered.
+    pub fn with_mirrors(mut self, mirrors: Arc<Mirrors>) -> Self {
+        self.mirrors = Some(mirrors);
+        self
+    }
+
+    /// Fetch with `options` instead of [`UrlLoaderOptions::DEFAULT`]
+    pub fn with_options(mut self, options: UrlLoaderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Send `auth`'s credentials and headers to the URLs it is for
+    pub fn with_auth(mut self, auth: Arc<HttpAuth>) -> Self {
+        self.options.auth = Some(auth);
+        self
+    }
+
+    /// Fetch and parse the @graph of the crate at `url`, its metadata file
+    /// or directory
+    pub fn load_graph(&self, url: &str) -> Result<Vec<Value>, ConsolidateError> {
+        let fetch = |url: &str| fetch_url_with(url, &self.options);
+        let content = match &self.mirrors {
+            Some(mirrors) => {
+                fetch_metadata_signposted(
+                    url,
+                    |url| mirrors.fetch_with(url, fetch),
+                    |url| link_header(url, &self.options),
+                )?
+                .1
+            }
+            None => crate::loader::load_from_url_with(url, &self.options)?.1,
+        };
+        parse_graph(&content, url)
+    }
+}
+
+/// Subcrate loader for a remote hierarchy listed by a manifest
+///
+/// Some repositories don't mark nested crates with `conformsTo` but list
+/// them at a separate endpoint. The manifest is a JSON array of subcrate
+/// URLs, or an object with such an array under `"subcrates"`:
+///
+/// ```json
+/// {"subcrates": ["experiments/", "https://example.org/crate/experiments/run1/"]}
+/// ```
+///
+/// Entries may point at a crate's directory or its metadata file, and
+/// relative entries are resolved against the root crate. Each listed crate
+/// becomes a subcrate of the closest listed crate containing it, or of the
+/// root. Subcrates not listed are loaded as by [`UrlLoader`].
+pub struct ManifestLoader {
+    urls: UrlLoader,
+    /// (subcrate id, metadata URL) of listed subcrates by parent namespace
+    listed: HashMap<String, Vec<(String, String)>>,
+}
+
+impl ManifestLoader {
+    /// Create a loader for the crate at `root_url` with the listed subcrates
+    pub fn new<S: AsRef<str>>(root_url: &str, subcrate_urls: impl IntoIterator<Item = S>) -> Self {
+        let urls = UrlLoader::from_metadata_url(root_url);
+        let base = urls.base_url.as_str();
+
+        // Directory and metadata URL of each listed crate
+        let crates: Vec<(String, String)> = subcrate_urls
+            .into_iter()
+            .filter_map(|entry| {
+                let entry = entry.as_ref().trim();
+                let url = if entry.starts_with("http://") || entry.starts_with("https://") {
+                    entry.to_string()
+                } else {
+                    format!("{}{}", base, entry.trim_start_matches("./"))
+                };
+                let dir = UrlLoader::from_metadata_url(&url).base_url;
+                let metadata_url = if url.ends_with("ro-crate-metadata.json") {
+                    url
+                } else {
+                    format!("{}ro-crate-metadata.json", dir)
+                };
+                (dir != base).then_some((dir, metadata_url))
+            })
+            .collect();
+
+        // Paths of the listed crates below the root
+        let paths: Vec<&str> = crates
+            .iter()
+            .filter_map(|(dir, _)| dir.strip_prefix(base))
+            .map(|path| path.trim_end_matches('/'))
+            .collect();
+
+        let mut listed: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        for (dir, metadata_url) in &crates {
+            let (parent, id) = match dir.strip_prefix(base) {
+                Some(path) => {
+                    let path = path.trim_end_matches('/');
+                    let parent = paths
+                        .iter()
+                        .filter(|p| {
+                            path.strip_prefix(**p)
+                                .is_some_and(|rest| rest.starts_with('/'))
+                        })
+                        .max_by_key(|p| p.len())
+                        .copied()
+                        .unwrap_or("");
+                    let relative = path[parent.len()..].trim_start_matches('/');
+                    (parent.to_string(), format!("./{}/", relative))
+                }
+                // Crates elsewhere are subcrates of the root
+                None => (String::new(), dir.clone()),
+            };
+            let siblings = listed.entry(parent).or_default();
+            if !siblings.iter().any(|(listed_id, _)| *listed_id == id) {
+                siblings.push((id, metadata_url.clone()));
+            }
+        }
+
+        Self { urls, listed }
+    }
+
+    /// Fall back to `mirrors` when fetching a subcrate fails
+    pub fn with_mirrors(mut self, mirrors: Arc<Mirrors>) -> Self {
+        self.urls = self.urls.with_mirrors(mirrors);
+        self
+    }
+
+    /// Fetch with `options` instead of [`UrlLoaderOptions::DEFAULT`]
+    pub fn with_options(mut self, options: UrlLoaderOptions) -> Self {
+        self.urls = self.urls.with_options(options);
+        self
+    }
+
+    /// Send `auth`'s credentials and headers to the URLs it is for
+    pub fn with_auth(mut self, auth: Arc<HttpAuth>) -> Self {
+        self.urls = self.urls.with_auth(auth);
+        self
+    }
+
+    /// Create a loader for the crate at `root_url` from a manifest's content
+    pub fn from_manifest(root_url: &str, manifest: &str) -> Result<Self, ConsolidateError> {
+        let mut doc: Value = serde_json::from_str(manifest)?;
+        let entries = match doc.get_mut("subcrates").map(Value::take).unwrap_or(doc) {
+            Value::Array(entries) => entries,
+            _ => {
+                return Err(ConsolidateError::InvalidStructure(
+                    "Subcrate manifest is not an array of URLs".to_string(),
+                ))
+            }
+        };
+        let urls = entries
+            .iter()
+            .map(|entry| {
+                entry.as_str().ok_or_else(|| {
+                    ConsolidateError::InvalidStructure(format!(
+                        "Subcrate manifest entry {} is not a URL",
+                        entry
+                    ))
+                })
+            })
+            .collect::<Result<Vec<&str>, _>>()?;
+        Ok(Self::new(root_url, urls))
+    }
+
+    /// Number of listed subcrates
+    pub fn len(&self) -> usize {
+        self.listed.values().map(Vec::len).sum()
+    }
+
+    /// Check if the manifest lists no subcrates
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl SubcrateLoader for ManifestLoader {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let listed = self
+            .listed
+            .get(parent_namespace)
+            .and_then(|siblings| siblings.iter().find(|(id, _)| id == subcrate_id));
+        match listed {
+            Some((_, metadata_url)) => self.urls.load_graph(metadata_url),
+            None => self
+                .urls
+                .load(subcrate_id, parent_namespace, subcrate_entity),
+        }
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        self.listed
+            .get(namespace)
+            .map(|siblings| siblings.iter().map(|(id, _)| id.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        let listed = self
+            .listed
+            .get(parent_namespace)
+            .and_then(|siblings| siblings.iter().find(|(id, _)| id == subcrate_id));
+        match listed {
+            Some((_, metadata_url)) => Some(metadata_url.clone()),
+            None => self
+                .urls
+                .location(subcrate_id, parent_namespace, subcrate_entity),
+        }
     }
 }
 
@@ -147,15 +766,11 @@ fn extract_metadata_url(entity: Option<&Value>) -> Option<String> {
         .map(|s| s.to_string())
 }
 
-impl SubcrateLoader for UrlLoader {
-    fn load(
-        &self,
-        subcrate_id: &str,
-        _parent_namespace: &str,
-        subcrate_entity: Option<&Value>,
-    ) -> Result<Vec<Value>, ConsolidateError> {
+impl UrlLoader {
+    /// URL of the metadata file of the subcrate `subcrate_id`
+    pub fn subcrate_url(&self, subcrate_id: &str, subcrate_entity: Option<&Value>) -> String {
         // First, try to get the metadata URL from subjectOf
-        let subcrate_url = if let Some(metadata_url) = extract_metadata_url(subcrate_entity) {
+        if let Some(metadata_url) = extract_metadata_url(subcrate_entity) {
             metadata_url
         } else if subcrate_id.starts_with("http://") || subcrate_id.starts_with("https://") {
             // Absolute URL - use it directly, appending ro-crate-metadata.json if needed
@@ -169,16 +784,33 @@ impl SubcrateLoader for UrlLoader {
             // Relative path - resolve against base URL
             let relative_path = subcrate_id.trim_start_matches("./").trim_end_matches('/');
             format!("{}{}/ro-crate-metadata.json", self.base_url, relative_path)
-        };
+        }
+    }
+}
+
+impl SubcrateLoader for UrlLoader {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        _parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let subcrate_url = self.subcrate_url(subcrate_id, subcrate_entity);
+        self.load_graph(&subcrate_url)
+    }
 
-        // Fetch and parse
-        let (_, content) = crate::loader::load_from_url(&subcrate_url)?;
-        parse_graph(&content, &subcrate_url)
+    fn location(
+        &self,
+        subcrate_id: &str,
+        _parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        Some(self.subcrate_url(subcrate_id, subcrate_entity))
     }
 }
 
 /// Result of consolidation
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct ConsolidateResult {
     /// The consolidated @graph
     pub graph: Vec<Value>,
@@ -186,10 +818,49 @@ pub struct ConsolidateResult {
     pub context: Value,
     /// Statistics about the consolidation
     pub stats: ConsolidateStats,
+    /// Data pointers of the consolidated graph (if collected)
+    pub distributions: Vec<DistributionPointer>,
+    /// Origin of every entity in the consolidated graph
+    pub origins: Vec<EntityOrigin>,
+    /// Non-fatal problems encountered during consolidation
+    pub warnings: Vec<String>,
+    /// Properties shared entities set to different single values, resolved
+    /// as [`ConsolidateOptions::conflict_strategy`] says
+    pub conflicts: Vec<MergeConflict>,
+    /// Set if consolidation failed midway and this result is partial
+    pub failure: Option<PartialFailure>,
+}
+
+/// Report of the failure that cut a partial consolidation short
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialFailure {
+    /// Stable code of the error that stopped consolidation
+    pub code: String,
+    /// The error that stopped consolidation
+    pub error: String,
+    /// Subcrate ids leading to the crate the error occurred in
+    pub subcrate_path: Vec<String>,
+    /// JSON pointer into that crate's metadata document (if known)
+    pub pointer: Option<String>,
+    /// Number of crates collected before the failure
+    pub crates_consolidated: usize,
+}
+
+/// Where an entity of the consolidated graph came from
+///
+/// Shared entities merged from several crates have one origin per crate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EntityOrigin {
+    /// The @id in the consolidated graph
+    pub id: String,
+    /// The @id in the originating crate
+    pub original_id: String,
+    /// Namespace of the originating crate (empty string for root crate)
+    pub namespace: String,
 }
 
 /// Statistics from consolidation
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ConsolidateStats {
     /// Number of crates consolidated (including root)
     pub crates_consolidated: usize,
@@ -197,6 +868,13 @@ pub struct ConsolidateStats {
     pub total_entities: usize,
     /// Number of shared entities that were merged
     pub merged_entities: usize,
+    /// Number of the merged entities that were identical to the one kept,
+    /// and dropped without merging
+    #[serde(default)]
+    pub identical_entities: usize,
+    /// Number of crates taken from the cache instead of being collected
+    #[serde(default)]
+    pub cached_crates: usize,
 }
 
 /// Main consolidation function
@@ -205,144 +883,361 @@ pub fn consolidate(
     loader: &dyn SubcrateLoader,
     options: &ConsolidateOptions,
 ) -> Result<ConsolidateResult, ConsolidateError> {
-    let mut stats = ConsolidateStats::default();
-    let mut visited = HashSet::new();
-    let mut fragment_tracker = HashSet::new();
+    consolidate_with_profiler(input, loader, options, &mut Profiler::disabled())
+}
+
+/// Consolidation with phase counters recorded into `profiler`
+pub(crate) fn consolidate_with_profiler(
+    input: ConsolidateInput,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+    profiler: &mut Profiler,
+) -> Result<ConsolidateResult, ConsolidateError> {
+    if let Some(base_id) = &options.base_id {
+        validate_base_id(base_id).map_err(ConsolidateError::InvalidBaseId)?;
+    }
+    if let Some(descriptor_id) = &options.descriptor_id {
+        validate_descriptor_id(descriptor_id).map_err(ConsolidateError::InvalidDescriptorId)?;
+    }
+    let _offline = options.offline.then(go_offline);
+    let _metadata_file_patterns = use_metadata_file_patterns(&options.metadata_file_patterns)?;
+    // Each consolidation waits for retries on a budget of its own
+    let _retry_budget = current_retry_budget()
+        .is_none()
+        .then(|| retry_budget(retry_wait_budget()));
+    let started_at = SystemTime::now();
+
+    let mut state = CollectState {
+        subcrate_filter: SubcrateFilter::new(options)?,
+        locations: options
+            .input_locations
+            .iter()
+            .map(|(folder_id, location)| {
+                let folder_id = match options.normalize_unicode {
+                    true => normalize_id(folder_id).into_owned(),
+                    false => folder_id.clone(),
+                };
+                (folder_id, location.clone())
+            })
+            .collect(),
+        ..CollectState::default()
+    };
 
     // Collect all entities from the hierarchy
-    let (root_graph, explicit_merges) = match input {
+    let (root_graph, mut explicit_merges) = match input {
         ConsolidateInput::Single(graph) => (graph, vec![]),
         ConsolidateInput::Merge { main, others } => (main, others),
     };
+    if options.normalize_unicode {
+        for merge in &mut explicit_merges {
+            if let Cow::Owned(folder_id) = normalize_id(&merge.folder_id) {
+                merge.folder_id = folder_id;
+            }
+        }
+    }
 
     // Process the main/root crate
-    let mut all_local: Vec<CollectedEntity> = Vec::new();
-    let mut all_shared: Vec<CollectedEntity> = Vec::new();
-    let mut subcrate_folders: Vec<Value> = Vec::new();
-    let mut processed_subcrate_ids: HashSet<String> = HashSet::new();
     let mut root_entity: Option<Value> = None;
     let mut metadata_descriptor: Option<Value> = None;
 
-    // Collect from root and its discovered subcrates
-    collect_hierarchy(
-        &root_graph,
-        "",
-        loader,
-        options,
-        &mut visited,
-        &mut fragment_tracker,
-        &mut all_local,
-        &mut all_shared,
-        &mut subcrate_folders,
-        &mut processed_subcrate_ids,
-        &mut root_entity,
-        &mut metadata_descriptor,
-        &mut stats,
-    )?;
-
-    // Process explicit merge crates
-    for merge_crate in explicit_merges {
-        validate_folder_id(&merge_crate.folder_id)
-            .map_err(|e| ConsolidateError::InvalidFolderId(e))?;
-
-        let namespace = namespace_from_folder_id(&merge_crate.folder_id);
-
-        if visited.contains(&namespace) {
-            return Err(ConsolidateError::DuplicateFolderId(merge_crate.folder_id));
-        }
-        visited.insert(namespace.clone());
-
-        // Create a synthetic parent folder reference if a name was provided
-        let parent_folder = merge_crate.name.as_ref().map(|name| {
-            json!({
-                "@id": merge_crate.folder_id,
-                "@type": "Dataset",
-                "name": name
-            })
-        });
-
+    // Collect everything, keeping what was gathered if a step fails midway
+    let collected = (|| -> Result<(), ConsolidateError> {
+        // Collect from root and its discovered subcrates
         collect_hierarchy(
-            &merge_crate.graph,
-            &namespace,
+            root_graph,
+            "",
             loader,
             options,
-            &mut visited,
-            &mut fragment_tracker,
-            &mut all_local,
-            &mut all_shared,
-            &mut subcrate_folders,
-            &mut processed_subcrate_ids,
-            &mut None, // Don't override root
-            &mut None, // Don't override descriptor
-            &mut stats,
+            &mut state,
+            &mut root_entity,
+            &mut metadata_descriptor,
+            profiler,
         )?;
 
-        // Find the root entity from the merged crate to use as subcrate root
-        let merge_collection = collect_from_graph(&merge_crate.graph, &namespace);
-        if let Some(merge_root) = merge_collection.root_entity {
-            // Collect rewritten IDs of entities from this subcrate
-            let contained_ids: Vec<String> = all_local
-                .iter()
-                .filter(|e| {
-                    e.namespace == namespace || e.namespace.starts_with(&format!("{}/", namespace))
-                })
-                .filter_map(|e| extract_id(&e.entity).map(String::from))
-                .collect();
+        // Process explicit merge crates
+        if options.parallelism > 1 && explicit_merges.len() > 1 {
+            collect_merges_parallel(explicit_merges, loader, options, &mut state, profiler)
+        } else {
+            for merge_crate in explicit_merges {
+                let namespace = claim_merge_namespace(
+                    &merge_crate.folder_id,
+                    options.case_collision_policy,
+                    &mut state.visited,
+                    &mut state.warnings,
+                )?;
+                let local_mark = state.arena.local_len();
+                let merge_root = collect_merge_crate(
+                    merge_crate.graph,
+                    &merge_crate.folder_id,
+                    &namespace,
+                    loader,
+                    options,
+                    &mut state,
+                    profiler,
+                )?;
+                push_merge_folder(
+                    &merge_crate.folder_id,
+                    merge_crate.name.as_deref(),
+                    merge_root,
+                    local_mark,
+                    options,
+                    &mut state,
+                );
+            }
+            Ok(())
+        }
+    })();
 
-            let folder = create_subcrate_folder(
-                &merge_crate.folder_id,
-                parent_folder.as_ref(),
-                &merge_root.entity,
-                contained_ids,
-                options.add_subcrate_type,
-            );
-            subcrate_folders.push(folder);
+    let failure = match collected {
+        Ok(()) => None,
+        Err(e)
+            if options.keep_partial && metadata_descriptor.is_some() && root_entity.is_some() =>
+        {
+            Some(PartialFailure {
+                code: e.code().to_string(),
+                error: e.root_cause().to_string(),
+                subcrate_path: e.subcrate_path().into_iter().map(String::from).collect(),
+                pointer: e.pointer().map(String::from),
+                crates_consolidated: state.stats.crates_consolidated,
+            })
         }
-    }
+        Err(e) => return Err(e),
+    };
+
+    let CollectState {
+        arena,
+        mut subcrate_folders,
+        processed_subcrate_ids,
+        mut stats,
+        warnings,
+        conflict_strategies,
+        locations,
+        ..
+    } = state;
+    let (all_local, mut all_shared) = arena.into_parts();
 
     // Filter out processed subcrates from shared entities (they're replaced by subcrate folders)
     all_shared.retain(|e| !processed_subcrate_ids.contains(&e.original_id));
 
+    // Without folders, the parts of each subcrate stand in for it, and the
+    // entity they become parts of for other references to it
+    let flattened_folders: Vec<Value> = if options.flatten_only {
+        std::mem::take(&mut subcrate_folders)
+    } else {
+        Vec::new()
+    };
+    let flattened: HashMap<String, Vec<Value>> = flattened_folders
+        .iter()
+        .filter_map(|folder| {
+            let parts = match folder.get("hasPart") {
+                Some(Value::Array(parts)) => parts.clone(),
+                Some(part) => vec![part.clone()],
+                None => vec![],
+            };
+            extract_id(folder).map(|id| (id.to_string(), parts))
+        })
+        .collect();
+    let flattened_parents = flattened_parents(
+        all_local.iter().map(|collected| &collected.entity),
+        &flattened,
+    );
+
+    // Record where every entity came from before merging loses the namespaces
+    let mut origins: Vec<EntityOrigin> =
+        Vec::with_capacity(all_shared.len() + all_local.len() + subcrate_folders.len() + 2);
+    for collected in &all_shared {
+        origins.push(EntityOrigin {
+            id: collected.original_id.clone(),
+            original_id: collected.original_id.clone(),
+            namespace: collected.namespace.to_string(),
+        });
+    }
+
     // Merge shared entities (those with absolute IDs appearing in multiple crates)
+    let started = profiler.start();
     let shared_before = all_shared.len();
-    let merged_shared = merge_by_id(all_shared);
+    let duplicate_ids = if profiler.is_enabled() {
+        duplicate_ids(&all_shared)
+    } else {
+        HashSet::new()
+    };
+    let DedupMerge {
+        entities: mut merged_shared,
+        identical,
+        conflicts,
+    } = dedup_merge_by_crate(
+        all_shared,
+        |namespace| {
+            conflict_strategies
+                .get(namespace)
+                .copied()
+                .unwrap_or(options.conflict_strategy)
+        },
+        &options.property_strategies,
+        options.value_comparison,
+    )?;
     stats.merged_entities = shared_before.saturating_sub(merged_shared.len());
+    stats.identical_entities = identical;
+    profiler.record(Phase::Merge, started, shared_before, || {
+        merged_shared
+            .iter()
+            .filter(|e| extract_id(e).is_some_and(|id| duplicate_ids.contains(id)))
+            .map(count_allocations)
+            .sum()
+    });
 
     // Build the final graph
-    let mut final_graph: Vec<Value> = Vec::new();
+    let started = profiler.start();
+    let mut final_graph: Vec<Value> =
+        Vec::with_capacity(merged_shared.len() + all_local.len() + subcrate_folders.len() + 2);
 
     // Add metadata descriptor (from root, kept as-is)
     if let Some(desc) = metadata_descriptor {
+        if let Some(id) = extract_id(&desc) {
+            origins.push(EntityOrigin {
+                id: id.to_string(),
+                original_id: id.to_string(),
+                namespace: String::new(),
+            });
+        }
         final_graph.push(desc);
     } else {
         return Err(ConsolidateError::MissingMetadataDescriptor);
     }
 
+    // Describe the run by what went in and what came out
+    let folders = if options.flatten_only {
+        &flattened_folders
+    } else {
+        &subcrate_folders
+    };
+    let mut provenance = match &root_entity {
+        Some(root) if options.record_provenance => Some(provenance_entities(
+            options, started_at, root, folders, &locations,
+        )?),
+        _ => None,
+    };
+    for entity in provenance.iter_mut().flatten() {
+        remap_flattened(entity, &flattened_parents);
+    }
+
     // Add root entity with updated hasPart
     if let Some(mut root) = root_entity {
         let folder_ids: Vec<String> = subcrate_folders
             .iter()
             .filter_map(|f| extract_id(f).map(String::from))
+            .chain(flattened.keys().cloned())
             .collect();
         update_root_has_part(&mut root, &folder_ids);
+        flatten_has_part(&mut root, &flattened);
+        remap_flattened(&mut root, &flattened_parents);
+        if options.record_provenance {
+            add_reference(&mut root, "mentions", CONSOLIDATION_ACTION_ID);
+        }
+        origins.push(EntityOrigin {
+            id: ROOT_ENTITY_ID.to_string(),
+            original_id: ROOT_ENTITY_ID.to_string(),
+            namespace: String::new(),
+        });
         final_graph.push(root);
     } else {
         return Err(ConsolidateError::MissingRootEntity);
     }
 
-    // Add all local entities (with rewritten IDs)
-    for collected in all_local {
+    // Add all local entities (with rewritten IDs), but the parents'
+    // references to subcrates: their folders stand in for them
+    let folder_ids: HashSet<&str> = subcrate_folders.iter().filter_map(extract_id).collect();
+    for mut collected in all_local {
+        if extract_id(&collected.entity).is_some_and(|id| folder_ids.contains(id)) {
+            continue;
+        }
+        if !flattened.is_empty() {
+            if extract_id(&collected.entity).is_some_and(|id| flattened.contains_key(id)) {
+                continue;
+            }
+            flatten_has_part(&mut collected.entity, &flattened);
+            remap_flattened(&mut collected.entity, &flattened_parents);
+        }
+        if let Some(id) = extract_id(&collected.entity) {
+            origins.push(EntityOrigin {
+                id: id.to_string(),
+                original_id: collected.original_id.clone(),
+                namespace: collected.namespace.to_string(),
+            });
+        }
         final_graph.push(collected.entity);
     }
 
-    // Add subcrate folders
+    // Add subcrate folders (each one stands in for its crate's root)
+    for folder in &subcrate_folders {
+        if let Some(id) = extract_id(folder) {
+            origins.push(EntityOrigin {
+                id: id.to_string(),
+                original_id: ROOT_ENTITY_ID.to_string(),
+                namespace: namespace_from_folder_id(id).to_string(),
+            });
+        }
+    }
     final_graph.extend(subcrate_folders);
 
     // Add merged shared entities
+    if !flattened.is_empty() {
+        for entity in &mut merged_shared {
+            flatten_has_part(entity, &flattened);
+            remap_flattened(entity, &flattened_parents);
+        }
+    }
     final_graph.extend(merged_shared);
 
+    final_graph.extend(provenance.into_iter().flatten());
+
     stats.total_entities = final_graph.len();
 
+    // Rename the descriptor and everything referring to it
+    if let Some(descriptor_id) = &options.descriptor_id {
+        let previous = rename_descriptor(&mut final_graph[0], descriptor_id);
+        if let Some(previous) = previous.filter(|id| id != descriptor_id) {
+            let rename = HashMap::from([(previous.clone(), descriptor_id.clone())]);
+            for entity in final_graph[1..].iter_mut() {
+                rewrite_references(entity, &rename);
+            }
+            for origin in origins.iter_mut().filter(|o| o.id == previous) {
+                origin.id.clone_from(descriptor_id);
+            }
+        }
+    }
+
+    // Declare the profile defining the consolidation vocabulary
+    if options.extend_context {
+        let descriptor = final_graph.first_mut().filter(|entity| {
+            extract_id(entity).is_some_and(|id| classify_id(id) == IdKind::MetadataDescriptor)
+        });
+        if let Some(descriptor) = descriptor {
+            add_conforms_to(descriptor, CONSOLIDATION_PROFILE_ID);
+        }
+    }
+
+    // Emit ids against the publication base if requested
+    if let Some(base_id) = &options.base_id {
+        for entity in final_graph.iter_mut() {
+            rebase_references(entity, base_id);
+        }
+        for origin in origins.iter_mut() {
+            rebase_id_in_place(&mut origin.id, base_id);
+        }
+    }
+
+    normalize_graph(&mut final_graph, options.shape);
+    profiler.record(Phase::Assemble, started, final_graph.len(), || {
+        // Each origin holds three strings; rebasing rewrites every entity
+        let rebased = if options.base_id.is_some() {
+            final_graph.len()
+        } else {
+            0
+        };
+        origins.len() * 3 + rebased
+    });
+
     // Build context
     let context = if options.extend_context {
         json!(["https://w3id.org/ro/crate/1.1/context", context_extension()])
@@ -350,339 +1245,3474 @@ pub fn consolidate(
         json!("https://w3id.org/ro/crate/1.1/context")
     };
 
-    Ok(ConsolidateResult {
+    let mut result = ConsolidateResult {
         graph: final_graph,
         context,
         stats,
-    })
+        distributions: vec![],
+        origins,
+        warnings,
+        conflicts,
+        failure,
+    };
+    if !options.post_processors.is_empty() {
+        run_post_processors(&options.post_processors, &mut result)?;
+        result.stats.total_entities = result.graph.len();
+    }
+
+    if options.collect_distributions {
+        result.distributions = collect_distributions(&result.graph);
+    }
+    Ok(result)
 }
 
-/// Recursively collect entities from a crate and its subcrates
-#[allow(clippy::too_many_arguments)]
-fn collect_hierarchy(
-    graph: &[Value],
-    namespace: &str,
-    loader: &dyn SubcrateLoader,
-    options: &ConsolidateOptions,
-    visited: &mut HashSet<String>,
-    fragment_tracker: &mut HashSet<String>,
-    all_local: &mut Vec<CollectedEntity>,
-    all_shared: &mut Vec<CollectedEntity>,
-    subcrate_folders: &mut Vec<Value>,
-    processed_subcrate_ids: &mut HashSet<String>,
-    root_entity: &mut Option<Value>,
-    metadata_descriptor: &mut Option<Value>,
-    stats: &mut ConsolidateStats,
-) -> Result<(), ConsolidateError> {
-    stats.crates_consolidated += 1;
-
-    let collection = collect_from_graph(graph, namespace);
+/// Replace the references to flattened subcrates in an entity's `hasPart`
+/// with the subcrates' parts, those of nested subcrates included
+fn flatten_has_part(entity: &mut Value, flattened: &HashMap<String, Vec<Value>>) {
+    fn push_parts(
+        part: &Value,
+        flattened: &HashMap<String, Vec<Value>>,
+        seen: &mut HashSet<String>,
+        parts: &mut Vec<Value>,
+    ) {
+        if let Some((id, nested)) = extract_id(part).and_then(|id| flattened.get_key_value(id)) {
+            if seen.insert(id.clone()) {
+                for part in nested {
+                    push_parts(part, flattened, seen, parts);
+                }
+            }
+        } else if !parts.contains(part) {
+            parts.push(part.clone());
+        }
+    }
 
-    // Build ID map for rewriting
-    let ids: Vec<&str> = collection
-        .local_entities
+    let Some(obj) = entity.as_object_mut() else {
+        return;
+    };
+    let has_part = match obj.get("hasPart") {
+        Some(Value::Array(parts)) => parts.clone(),
+        Some(part) => vec![part.clone()],
+        None => return,
+    };
+    if !has_part
         .iter()
-        .map(|e| e.original_id.as_str())
-        .chain(
-            collection
-                .root_entity
+        .any(|part| extract_id(part).is_some_and(|id| flattened.contains_key(id)))
+    {
+        return;
+    }
+    let mut parts = Vec::with_capacity(has_part.len());
+    let mut seen = HashSet::new();
+    for part in &has_part {
+        push_parts(part, flattened, &mut seen, &mut parts);
+    }
+    if parts.is_empty() {
+        obj.remove("hasPart");
+    } else {
+        obj.insert("hasPart".to_string(), Value::Array(parts));
+    }
+}
+
+/// The entity the parts of each flattened subcrate become parts of, by the
+/// subcrate's folder id
+///
+/// That is the entity listing the subcrate in its `hasPart`, or, if that is
+/// a flattened subcrate too, the entity its parts become parts of. The
+/// parts of subcrates no entity lists become parts of the root.
+fn flattened_parents<'a>(
+    entities: impl IntoIterator<Item = &'a Value>,
+    flattened: &'a HashMap<String, Vec<Value>>,
+) -> HashMap<String, String> {
+    let mut listed_by: HashMap<&str, &str> = HashMap::new();
+    let mut list = |parent: &'a str, parts: &'a [Value]| {
+        for id in parts.iter().filter_map(extract_id) {
+            if flattened.contains_key(id) && id != parent {
+                listed_by.entry(id).or_insert(parent);
+            }
+        }
+    };
+    for entity in entities {
+        let Some(id) = extract_id(entity).filter(|id| !flattened.contains_key(*id)) else {
+            continue;
+        };
+        match entity.get("hasPart") {
+            Some(Value::Array(parts)) => list(id, parts),
+            Some(part) => list(id, std::slice::from_ref(part)),
+            None => {}
+        }
+    }
+    for (id, parts) in flattened {
+        list(id, parts);
+    }
+
+    flattened
+        .keys()
+        .map(|id| {
+            let mut parent = id.as_str();
+            // Bounded, as subcrates listing each other would never end
+            for _ in 0..=flattened.len() {
+                if !flattened.contains_key(parent) {
+                    break;
+                }
+                parent = listed_by.get(parent).copied().unwrap_or(ROOT_ENTITY_ID);
+            }
+            if flattened.contains_key(parent) {
+                parent = ROOT_ENTITY_ID;
+            }
+            (id.clone(), parent.to_string())
+        })
+        .collect()
+}
+
+/// Point an entity's references to flattened subcrates at the entities
+/// their parts became parts of (see [`flattened_parents`])
+///
+/// References this would duplicate, or make to the entity itself, are
+/// dropped instead, and properties left without values removed.
+fn remap_flattened(entity: &mut Value, parents: &HashMap<String, String>) {
+    if parents.is_empty() {
+        return;
+    }
+    let own_id = extract_id(entity).map(String::from);
+    let Some(obj) = entity.as_object_mut() else {
+        return;
+    };
+    let parent_of = |value: &Value| extract_id(value).and_then(|id| parents.get(id));
+    obj.retain(|key, value| {
+        if key == "@id" || key == "contentUrl" {
+            return true;
+        }
+        match value {
+            Value::Array(values) if !values.is_empty() => {
+                let mut present: HashSet<String> = values
+                    .iter()
+                    .filter(|value| parent_of(value).is_none())
+                    .filter_map(|value| extract_id(value).map(String::from))
+                    .collect();
+                present.extend(own_id.clone());
+                values.retain_mut(|value| match parent_of(value) {
+                    Some(parent) if !present.insert(parent.clone()) => false,
+                    Some(parent) => {
+                        value["@id"] = json!(parent);
+                        true
+                    }
+                    None => true,
+                });
+                !values.is_empty()
+            }
+            value => match parent_of(value) {
+                Some(parent) if own_id.as_ref() == Some(parent) => false,
+                Some(parent) => {
+                    value["@id"] = json!(parent);
+                    true
+                }
+                None => true,
+            },
+        }
+    });
+    for (key, value) in obj.iter_mut() {
+        if key != "contentUrl" {
+            rewrite_references(value, parents);
+        }
+    }
+}
+
+/// Mutable state gathered while collecting a crate hierarchy
+#[derive(Debug, Default)]
+struct CollectState {
+    /// Namespaces already collected, with the location of the crate collected
+    /// into each (for cycle detection and namespace disambiguation)
+    visited: HashMap<String, String>,
+    /// Fragment ids used so far (for collision detection)
+    fragment_tracker: HashSet<String>,
+    arena: EntityArena,
+    subcrate_folders: Vec<Value>,
+    /// Folder id of the crate collected into each namespace
+    folder_ids: HashMap<String, String>,
+    processed_subcrate_ids: HashSet<String>,
+    stats: ConsolidateStats,
+    warnings: Vec<String>,
+    /// Cleared id map kept between crates so its allocation is reused
+    id_map_scratch: HashMap<String, String>,
+    /// Entities in all crates loaded so far
+    entities_loaded: usize,
+    /// Levels of subcrates above the crate being collected
+    depth: usize,
+    /// Limit on [`depth`](Self::depth) set by the overrides of a crate above,
+    /// instead of [`ConsolidateOptions::max_depth`]
+    depth_limit: Option<usize>,
+    /// Which subcrates are consolidated
+    subcrate_filter: SubcrateFilter,
+    /// Conflict strategy of each namespace whose overrides set one
+    conflict_strategies: HashMap<String, ConflictStrategy>,
+    /// Namespace of each crate moved by [`ConsolidateOptions::namespace_map`]
+    /// as it would be without, by which loaders know the crate
+    source_namespaces: HashMap<String, String>,
+    /// Where the crate of each folder id was loaded from, if known
+    locations: HashMap<String, String>,
+}
+
+impl CollectState {
+    /// The namespace loaders know the crate collected into `namespace` by
+    fn source_namespace<'a>(&'a self, namespace: &'a str) -> &'a str {
+        self.source_namespaces
+            .get(namespace)
+            .map_or(namespace, String::as_str)
+    }
+}
+
+/// Compiled [`ConsolidateOptions::include_subcrates`],
+/// [`ConsolidateOptions::exclude_subcrates`] and
+/// [`ConsolidateOptions::subcrate_overrides`] patterns, with the validated
+/// [`ConsolidateOptions::namespace_map`]
+#[derive(Debug, Clone, Default)]
+struct SubcrateFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    overrides: Vec<(Pattern, SubcrateOverrides)>,
+    renames: HashMap<String, String>,
+}
+
+impl SubcrateFilter {
+    fn new(options: &ConsolidateOptions) -> Result<Self, ConsolidateError> {
+        let compile_one = |pattern: &String| {
+            Pattern::new(pattern).map_err(|e| {
+                ConsolidateError::InvalidStructure(format!(
+                    "Invalid subcrate pattern '{}': {}",
+                    pattern, e
+                ))
+            })
+        };
+        let compile = |patterns: &[String]| {
+            patterns
                 .iter()
-                .map(|e| e.original_id.as_str()),
-        )
-        .collect();
+                .map(compile_one)
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(Self {
+            include: compile(&options.include_subcrates)?,
+            exclude: compile(&options.exclude_subcrates)?,
+            overrides: options
+                .subcrate_overrides
+                .iter()
+                .map(|(pattern, overrides)| Ok((compile_one(pattern)?, overrides.clone())))
+                .collect::<Result<Vec<_>, ConsolidateError>>()?,
+            renames: options
+                .namespace_map
+                .iter()
+                .map(|(from, to)| {
+                    let namespace = trim_folder_path(to);
+                    let valid = match namespace {
+                        "" => Err("the root crate has no namespace".to_string()),
+                        _ => validate_namespace(namespace),
+                    };
+                    valid.map_err(|reason| {
+                        ConsolidateError::InvalidStructure(format!(
+                            "Invalid namespace mapping '{}' -> '{}': {}",
+                            from, to, reason
+                        ))
+                    })?;
+                    Ok((trim_folder_path(from).to_string(), namespace.to_string()))
+                })
+                .collect::<Result<_, ConsolidateError>>()?,
+        })
+    }
 
-    let id_map = build_id_map(ids.into_iter(), namespace, fragment_tracker);
+    /// The namespace the subcrate at `location` is mapped to, if any
+    fn renamed(&self, location: &str) -> Option<&str> {
+        self.renames.get(location).map(String::as_str)
+    }
 
-    // Handle root entity
-    if namespace.is_empty() {
-        // This is the main root - preserve it
-        if let Some(collected) = collection.root_entity {
-            *root_entity = Some(collected.entity);
+    /// Whether the subcrate collected into `namespace` is consolidated
+    fn selects(&self, namespace: &str) -> bool {
+        let matches = |pattern: &Pattern| matches_folder(pattern, namespace);
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+
+    /// The overrides of the subcrate collected into `namespace`, if any
+    /// pattern matches it
+    fn overrides(&self, namespace: &str) -> Option<SubcrateOverrides> {
+        if namespace.is_empty() {
+            return None;
         }
-        if let Some(collected) = collection.metadata_descriptor {
-            *metadata_descriptor = Some(collected.entity);
+        self.overrides
+            .iter()
+            .filter(|(pattern, _)| matches_folder(pattern, namespace))
+            .fold(None, |merged, (_, overrides)| {
+                let mut merged: SubcrateOverrides = merged.unwrap_or_default();
+                merged.extend(overrides);
+                Some(merged)
+            })
+    }
+}
+
+/// `path` without a leading "./" and trailing slashes, as locations and
+/// namespaces are written
+fn trim_folder_path(path: &str) -> &str {
+    path.strip_prefix("./")
+        .unwrap_or(path)
+        .trim_end_matches('/')
+}
+
+/// Whether `pattern` matches the folder id ("./namespace/") of the subcrate
+/// collected into `namespace`, with or without the leading "./" and the
+/// trailing slash
+fn matches_folder(pattern: &Pattern, namespace: &str) -> bool {
+    let folder_id = format!("./{}/", namespace);
+    let candidates = [
+        folder_id.as_str(),
+        &folder_id[..folder_id.len() - 1],
+        &folder_id[2..],
+        namespace,
+    ];
+    candidates.iter().any(|c| pattern.matches(c))
+}
+
+/// The CreateAction describing a consolidation run started at `started_at`,
+/// and its instrument
+///
+/// The crates consolidated are those of `root` and the subcrate `folders`,
+/// which are the run's results. They are identified by their absolute
+/// identifiers, or else by where they were loaded from, of `locations` by
+/// folder id.
+fn provenance_entities(
+    options: &ConsolidateOptions,
+    started_at: SystemTime,
+    root: &Value,
+    folders: &[Value],
+    locations: &HashMap<String, String>,
+) -> Result<[Value; 2], ConsolidateError> {
+    let crates = std::iter::once(root).chain(folders);
+    let mut inputs: Vec<Value> = Vec::new();
+    for entity in crates.clone() {
+        let mut identifiers: Vec<String> = absolute_identifiers(entity)
+            .into_iter()
+            .map(String::from)
+            .collect();
+        if identifiers.is_empty() {
+            let location = extract_id(entity).and_then(|id| locations.get(id));
+            identifiers.extend(location.map(|location| location_uri(location)));
         }
-    } else {
-        // This is a subcrate - capture its root for subcrate folder creation
-        if let Some(collected) = collection.root_entity {
-            *root_entity = Some(collected.entity);
+        for identifier in identifiers {
+            let reference = json!({"@id": identifier});
+            if !inputs.contains(&reference) {
+                inputs.push(reference);
+            }
         }
     }
+    let results: Vec<Value> = crates
+        .filter_map(extract_id)
+        .map(|id| json!({"@id": id}))
+        .collect();
+    let mut action = json!({
+        "@id": CONSOLIDATION_ACTION_ID,
+        "@type": "CreateAction",
+        "name": "RO-Crate consolidation",
+        "instrument": {"@id": CONSOLIDATION_SOFTWARE_ID},
+        "startTime": iso_datetime(reproducible_time(started_at)),
+        "endTime": iso_datetime(reproducible_time(SystemTime::now())),
+        "result": results
+    });
+    if !inputs.is_empty() {
+        action["object"] = Value::Array(inputs);
+    }
+    action[CONSOLIDATION_OPTIONS_SHORT] = json!(serde_json::to_string(options)?);
+    if let Some(log) = audit::active() {
+        action[CONSOLIDATION_INPUTS_SHORT] = json!(serde_json::to_string(&log.records())?);
+    }
+    let software = json!({
+        "@id": CONSOLIDATION_SOFTWARE_ID,
+        "@type": "SoftwareApplication",
+        "name": env!("CARGO_PKG_NAME"),
+        "version": env!("CARGO_PKG_VERSION")
+    });
+    Ok([action, software])
+}
 
-    // Process and rewrite local entities
-    for mut collected in collection.local_entities {
-        // Rewrite the entity's @id if needed
-        if let Some(new_id) = id_map.get(&collected.original_id) {
-            if let Some(obj) = collected.entity.as_object_mut() {
-                obj.insert("@id".to_string(), json!(new_id));
+/// The `identifier`s of a crate's root entity or folder that are absolute
+/// URIs, such as DOIs as `https://doi.org/...`
+fn absolute_identifiers(entity: &Value) -> Vec<&str> {
+    fn identifier(value: &Value) -> Option<&str> {
+        value
+            .as_str()
+            .or_else(|| extract_id(value))
+            .filter(|id| classify_id(id) == IdKind::Absolute)
+    }
+    match entity.get("identifier") {
+        Some(Value::Array(values)) => values.iter().filter_map(identifier).collect(),
+        Some(value) => identifier(value).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// `location` as a URI: absolute paths as `file:` URLs, anything else as is
+fn location_uri(location: &str) -> String {
+    Url::from_file_path(location).map_or_else(|()| location.to_string(), String::from)
+}
+
+/// `time`, or that of `SOURCE_DATE_EPOCH` if set, so that consolidations
+/// of the same crates can produce the same output
+fn reproducible_time(time: SystemTime) -> SystemTime {
+    std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse().ok())
+        .map_or(time, |secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Validate an explicit merge crate's folder id and claim its namespace
+fn claim_merge_namespace(
+    folder_id: &str,
+    policy: CaseCollisionPolicy,
+    visited: &mut HashMap<String, String>,
+    warnings: &mut Vec<String>,
+) -> Result<String, ConsolidateError> {
+    validate_folder_id(folder_id).map_err(ConsolidateError::InvalidFolderId)?;
+
+    let namespace = namespace_from_folder_id(folder_id).to_string();
+    if visited.contains_key(&namespace) {
+        return Err(ConsolidateError::DuplicateFolderId(folder_id.to_string()));
+    }
+    if policy != CaseCollisionPolicy::Allow {
+        if let Some(existing) = case_conflict(&namespace, visited) {
+            warnings.push(case_collision_warning(folder_id, &namespace, existing));
+        }
+    }
+    visited.insert(namespace.clone(), namespace.clone());
+    Ok(namespace)
+}
+
+/// Namespace `child` below `parent` ("" for the root)
+fn join_namespace(parent: &str, child: &str) -> String {
+    if parent.is_empty() {
+        child.to_string()
+    } else {
+        format!("{}/{}", parent, child)
+    }
+}
+
+/// Claim a namespace for a discovered subcrate
+///
+/// Returns the namespace and whether it differs from the one derived from
+/// the subcrate id, or `None` if the crate at the same location was already
+/// collected (a cycle or a repeated reference). A different crate mapping to
+/// a taken namespace gets a unique one.
+fn claim_subcrate_namespace(
+    parent_namespace: &str,
+    subcrate_id: &str,
+    policy: CaseCollisionPolicy,
+    state: &mut CollectState,
+) -> Option<(String, bool)> {
+    let (natural, location) = natural_namespace(parent_namespace, subcrate_id, state);
+    let visited = &state.visited;
+
+    let rename_case = policy == CaseCollisionPolicy::Rename;
+    let taken = |candidate: &str| {
+        visited.contains_key(candidate)
+            || (rename_case && case_conflict(candidate, visited).is_some())
+    };
+    let (namespace, warning) = match visited.get(&natural) {
+        Some(owner) if *owner == location => return None,
+        Some(owner) => {
+            let unique = unique_namespace(parent_namespace, subcrate_id, taken);
+            let warning = format!(
+                "Subcrate '{}' maps to namespace '{}' already used by '{}', using '{}'",
+                subcrate_id, natural, owner, unique
+            );
+            (unique, Some(warning))
+        }
+        None => match case_conflict(&natural, visited) {
+            Some(existing) if rename_case => {
+                let unique = unique_namespace(parent_namespace, subcrate_id, taken);
+                let warning = format!(
+                    "Subcrate '{}' maps to namespace '{}' differing only in case from '{}', using '{}'",
+                    subcrate_id, natural, existing, unique
+                );
+                (unique, Some(warning))
+            }
+            Some(existing) if policy == CaseCollisionPolicy::Warn => {
+                let warning = case_collision_warning(subcrate_id, &natural, existing);
+                (natural.clone(), Some(warning))
             }
+            _ => (natural.clone(), None),
+        },
+    };
+
+    // Loaders find nested subcrates of a moved crate where it came from
+    let local = namespace_from_folder_id(subcrate_id);
+    if state.subcrate_filter.renamed(&location).is_some()
+        || state.source_namespaces.contains_key(parent_namespace)
+    {
+        let source = join_namespace(state.source_namespace(parent_namespace), local);
+        if source != namespace {
+            state.source_namespaces.insert(namespace.clone(), source);
         }
+    }
 
-        // Rewrite all @id references within the entity
-        rewrite_references(&mut collected.entity, &id_map);
+    state.warnings.extend(warning);
+    state.visited.insert(namespace.clone(), location);
+    let renamed = namespace != join_namespace(parent_namespace, local);
+    Some((namespace, renamed))
+}
+
+/// The namespace a discovered subcrate is collected into unless taken,
+/// mapped by [`ConsolidateOptions::namespace_map`] or derived from its id,
+/// and where it lives
+fn natural_namespace(
+    parent_namespace: &str,
+    subcrate_id: &str,
+    state: &CollectState,
+) -> (String, String) {
+    let parent_location = state
+        .visited
+        .get(parent_namespace)
+        .map_or("", String::as_str);
+    let location = subcrate_location(parent_location, subcrate_id);
+    let natural = match state.subcrate_filter.renamed(&location) {
+        Some(namespace) => namespace.to_string(),
+        None => join_namespace(parent_namespace, namespace_from_folder_id(subcrate_id)),
+    };
+    (natural, location)
+}
+
+/// A used namespace that `namespace` collides with on a case-insensitive filesystem
+fn case_conflict<'a>(namespace: &str, visited: &'a HashMap<String, String>) -> Option<&'a str> {
+    visited
+        .keys()
+        .find(|used| differs_only_in_case(namespace, used))
+        .map(String::as_str)
+}
 
-        all_local.push(collected);
+/// Whether two namespaces name the same folder, or one folder inside the
+/// other, only when compared case-insensitively
+fn differs_only_in_case(a: &str, b: &str) -> bool {
+    for (x, y) in a.split('/').zip(b.split('/')) {
+        if x != y {
+            return x
+                .chars()
+                .flat_map(char::to_lowercase)
+                .eq(y.chars().flat_map(char::to_lowercase));
+        }
     }
+    false
+}
 
-    // Add shared entities (will be merged later)
-    all_shared.extend(collection.shared_entities);
+fn case_collision_warning(id: &str, namespace: &str, existing: &str) -> String {
+    format!(
+        "Folder '{}' (namespace '{}') collides with namespace '{}' on case-insensitive filesystems",
+        id, namespace, existing
+    )
+}
+
+/// Where a subcrate lives: its absolute URL, or its path below the root crate
+fn subcrate_location(parent_location: &str, subcrate_id: &str) -> String {
+    if classify_id(subcrate_id) == IdKind::Absolute {
+        return subcrate_id.trim_end_matches('/').to_string();
+    }
+    let path = subcrate_id
+        .strip_prefix("./")
+        .unwrap_or(subcrate_id)
+        .trim_end_matches('/');
+    if parent_location.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}/{}", parent_location, path)
+    }
+}
 
-    // Process discovered subcrates
-    for subcrate_id in &collection.subcrate_ids {
-        let subcrate_namespace = if namespace.is_empty() {
-            namespace_from_folder_id(subcrate_id)
+/// Derive an unused namespace for a subcrate whose natural one is taken
+///
+/// Absolute subcrate ids are qualified with further segments of their URL
+/// path ("data" -> "two/data"); if that is not enough, or the id is
+/// relative, a counter is appended ("data-2").
+fn unique_namespace(
+    parent_namespace: &str,
+    subcrate_id: &str,
+    taken: impl Fn(&str) -> bool,
+) -> String {
+    let qualify = |local: &str| {
+        if parent_namespace.is_empty() {
+            local.to_string()
         } else {
-            format!("{}/{}", namespace, namespace_from_folder_id(subcrate_id))
-        };
+            format!("{}/{}", parent_namespace, local)
+        }
+    };
 
-        // Cycle detection
-        if visited.contains(&subcrate_namespace) {
-            continue;
+    let local = namespace_from_folder_id(subcrate_id);
+    if classify_id(subcrate_id) == IdKind::Absolute {
+        let path = subcrate_id
+            .split_once("://")
+            .and_then(|(_, rest)| rest.split_once('/'))
+            .map(|(_, path)| path.trim_end_matches('/'))
+            .unwrap_or_default();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        for k in 2..=segments.len() {
+            let candidate = qualify(&segments[segments.len() - k..].join("/"));
+            if !taken(&candidate) && validate_namespace(&candidate).is_ok() {
+                return candidate;
+            }
         }
-        visited.insert(subcrate_namespace.clone());
+    }
 
-        // Find the parent's reference to this subcrate (for extracting subjectOf)
-        let subcrate_entity = graph.iter().find(|e| extract_id(e) == Some(subcrate_id));
+    (2..)
+        .map(|n| qualify(&format!("{}-{}", local, n)))
+        .find(|candidate| !taken(candidate))
+        .expect("some numbered namespace is free")
+}
 
-        // Try to load the subcrate
-        let subcrate_graph = match loader.load(subcrate_id, namespace, subcrate_entity) {
-            Ok(g) => g,
-            Err(_) => {
-                // Subcrate couldn't be loaded - skip but don't fail
-                // The reference entity will remain as-is
-                continue;
-            }
-        };
+/// Collect an explicit merge crate, returning its root and whether it is a typed subcrate
+fn collect_merge_crate(
+    graph: Vec<Value>,
+    folder_id: &str,
+    namespace: &str,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+    state: &mut CollectState,
+    profiler: &mut Profiler,
+) -> Result<Option<(Value, bool)>, ConsolidateError> {
+    // The merged crate's root becomes the subcrate folder
+    let mut merge_root: Option<Value> = None;
+    let mut merge_desc: Option<Value> = None;
+    state
+        .folder_ids
+        .insert(namespace.to_string(), folder_id.to_string());
 
-        // Recursively collect from subcrate
-        let mut subcrate_root: Option<Value> = None;
-        let mut subcrate_desc: Option<Value> = None;
+    collect_hierarchy(
+        graph,
+        namespace,
+        loader,
+        options,
+        state,
+        &mut merge_root,
+        &mut merge_desc,
+        profiler,
+    )
+    .map_err(|e| e.in_subcrate(folder_id, namespace, None))?;
 
-        collect_hierarchy(
-            &subcrate_graph,
-            &subcrate_namespace,
-            loader,
-            options,
-            visited,
-            fragment_tracker,
-            all_local,
-            all_shared,
-            subcrate_folders,
-            processed_subcrate_ids,
-            &mut subcrate_root,
-            &mut subcrate_desc,
-            stats,
-        )?;
+    let typed = check_subcrate_descriptor(
+        merge_desc.as_ref(),
+        &mut merge_root,
+        folder_id,
+        options.missing_descriptor_policy,
+        &mut state.warnings,
+    )
+    .map_err(|e| e.in_subcrate(folder_id, namespace, None))?;
 
-        // Mark this subcrate as processed (so we can exclude it from shared entities)
-        processed_subcrate_ids.insert(subcrate_id.clone());
+    Ok(merge_root.map(|root| (root, typed)))
+}
 
-        // Create the subcrate folder entity
-        if let Some(sub_root) = subcrate_root {
-            let folder_id = if namespace.is_empty() {
-                subcrate_id.clone()
-            } else {
-                format!("./{}/", subcrate_namespace)
-            };
+/// Create the folder standing in for a collected explicit merge crate
+///
+/// `local_mark` is the arena's local length before the crate was collected.
+fn push_merge_folder(
+    folder_id: &str,
+    name: Option<&str>,
+    merge_root: Option<(Value, bool)>,
+    local_mark: usize,
+    options: &ConsolidateOptions,
+    state: &mut CollectState,
+) {
+    let Some((merge_root, typed)) = merge_root else {
+        return;
+    };
 
-            // Collect IDs of entities from this subcrate
-            let contained_ids: Vec<String> = all_local
-                .iter()
-                .filter(|e| {
-                    e.namespace == subcrate_namespace
-                        || e.namespace.starts_with(&format!("{}/", subcrate_namespace))
-                })
-                .filter_map(|e| {
-                    // Get the rewritten ID
-                    extract_id(&e.entity).map(String::from)
-                })
-                .collect();
+    // Create a synthetic parent folder reference if a name was provided
+    let parent_folder = name.map(|name| {
+        json!({
+            "@id": folder_id,
+            "@type": "Dataset",
+            "name": name
+        })
+    });
+
+    // Collect rewritten IDs of entities from this subcrate
+    let mut contained_ids = Vec::with_capacity(state.arena.local_len() - local_mark);
+    contained_ids.extend(
+        state
+            .arena
+            .local_since(local_mark)
+            .filter_map(|e| extract_id(&e.entity).map(String::from)),
+    );
+
+    let mut folder = create_subcrate_folder_with(
+        folder_id,
+        parent_folder.as_ref(),
+        &merge_root,
+        if typed { contained_ids } else { vec![] },
+        options.add_subcrate_type && typed,
+        options.reference_merge,
+    );
+    if typed {
+        add_specialized_types(&mut folder, &merge_root, &options.subcrate_types);
+    }
+    if options.link_parents {
+        add_reference(&mut folder, "isPartOf", ROOT_ENTITY_ID);
+    }
+    state.subcrate_folders.push(folder);
+}
+
+/// Outcome of collecting one explicit merge crate on a worker thread
+struct MergeOutcome {
+    state: CollectState,
+    profiler: Profiler,
+    merge_root: Result<Option<(Value, bool)>, ConsolidateError>,
+}
 
-            let folder = create_subcrate_folder(
+/// Collect explicit merge crates on up to `options.parallelism` threads
+///
+/// Each crate is collected into its own state, seeded with the namespaces
+/// and fragment ids known after the root crate. The outcomes are then folded
+/// into `state` in input order, renaming fragment ids that an earlier crate
+/// already used, so the result matches a sequential run. The first error in
+/// input order is returned after folding in everything collected before it.
+fn collect_merges_parallel(
+    explicit_merges: Vec<MergeCrate>,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+    state: &mut CollectState,
+    profiler: &mut Profiler,
+) -> Result<(), ConsolidateError> {
+    // Claim all namespaces up front so no crate collects another's folder
+    // as a nested subcrate
+    let mut folders = Vec::with_capacity(explicit_merges.len());
+    let mut jobs = Vec::with_capacity(explicit_merges.len());
+    for merge_crate in explicit_merges {
+        let namespace = claim_merge_namespace(
+            &merge_crate.folder_id,
+            options.case_collision_policy,
+            &mut state.visited,
+            &mut state.warnings,
+        );
+        let job_namespace = namespace.as_ref().ok().cloned();
+        jobs.push((
+            merge_crate.graph,
+            merge_crate.folder_id.clone(),
+            job_namespace,
+        ));
+        folders.push((merge_crate.folder_id, merge_crate.name, namespace));
+    }
+
+    let seed_visited = state.visited.clone();
+    let seed_fragments = state.fragment_tracker.clone();
+    let seed_loaded = state.entities_loaded;
+    let worker_profiler = profiler.fork();
+
+    let outcomes = parallel_map(
+        jobs,
+        options.parallelism,
+        |(graph, folder_id, namespace)| {
+            let namespace = namespace?;
+            let mut worker = CollectState {
+                visited: seed_visited.clone(),
+                fragment_tracker: seed_fragments.clone(),
+                entities_loaded: seed_loaded,
+                subcrate_filter: state.subcrate_filter.clone(),
+                ..CollectState::default()
+            };
+            let mut profiler = worker_profiler.fork();
+            let merge_root = collect_merge_crate(
+                graph,
                 &folder_id,
-                subcrate_entity,
-                &sub_root,
-                contained_ids,
-                options.add_subcrate_type,
+                &namespace,
+                loader,
+                options,
+                &mut worker,
+                &mut profiler,
             );
-            subcrate_folders.push(folder);
+            Some(MergeOutcome {
+                state: worker,
+                profiler,
+                merge_root,
+            })
+        },
+    );
+
+    for ((folder_id, name, namespace), outcome) in folders.into_iter().zip(outcomes) {
+        let namespace = namespace?;
+        let MergeOutcome {
+            state: mut worker,
+            profiler: worker_profiler,
+            merge_root,
+        } = outcome.expect("claimed namespaces are collected");
+        profiler.absorb(worker_profiler);
+
+        // Nested subcrates of two merge crates that ended up in the same namespace
+        if worker
+            .visited
+            .keys()
+            .any(|ns| !seed_visited.contains_key(ns) && state.visited.contains_key(ns))
+        {
+            return Err(ConsolidateError::DuplicateFolderId(folder_id));
+        }
+        // Workers could not see each other's namespaces, so case collisions
+        // between them are reported here (and never renamed)
+        if options.case_collision_policy != CaseCollisionPolicy::Allow {
+            let mut added: Vec<&String> = worker
+                .visited
+                .keys()
+                .filter(|ns| !seed_visited.contains_key(*ns))
+                .collect();
+            added.sort();
+            for ns in added {
+                let existing = state.visited.keys().find(|used| {
+                    !seed_visited.contains_key(*used) && differs_only_in_case(ns, used)
+                });
+                if let Some(existing) = existing {
+                    let location = &worker.visited[ns];
+                    state
+                        .warnings
+                        .push(case_collision_warning(location, ns, existing));
+                }
+            }
+        }
+
+        let mut merge_root = merge_root;
+        let root = merge_root
+            .as_mut()
+            .ok()
+            .and_then(Option::as_mut)
+            .map(|(root, _)| root);
+        reconcile_fragments(&mut worker, root, &namespace, &state.fragment_tracker);
+
+        let local_mark = state.arena.local_len();
+        state.visited.extend(worker.visited);
+        state.fragment_tracker.extend(worker.fragment_tracker);
+        state.arena.append(worker.arena);
+        state.subcrate_folders.extend(worker.subcrate_folders);
+        state
+            .processed_subcrate_ids
+            .extend(worker.processed_subcrate_ids);
+        state.stats.crates_consolidated += worker.stats.crates_consolidated;
+        state.stats.cached_crates += worker.stats.cached_crates;
+        state.entities_loaded += worker.entities_loaded - seed_loaded;
+        check_total_entities(state.entities_loaded, options)?;
+        state.warnings.extend(worker.warnings);
+        state.conflict_strategies.extend(worker.conflict_strategies);
+        state.source_namespaces.extend(worker.source_namespaces);
+        state.locations.extend(worker.locations);
+
+        push_merge_folder(
+            &folder_id,
+            name.as_deref(),
+            merge_root?,
+            local_mark,
+            options,
+            state,
+        );
+    }
+    Ok(())
+}
+
+/// Rename fragment ids a worker kept that an earlier crate already used
+///
+/// Mirrors what [`build_id_map`] would have done had the worker seen
+/// `used_fragments`: the entity and references to it within its crate get
+/// the namespaced id, as do `consolidatedEntities` of enclosing subcrates.
+fn reconcile_fragments(
+    worker: &mut CollectState,
+    merge_root: Option<&mut Value>,
+    merge_namespace: &str,
+    used_fragments: &HashSet<String>,
+) {
+    // Fragment renames per namespace
+    let mut renames: HashMap<String, HashMap<String, String>> = HashMap::new();
+    for collected in worker.arena.local() {
+        let id = collected.original_id.as_str();
+        if classify_id(id) == IdKind::Fragment
+            && !collected.namespace.is_empty()
+            && extract_id(&collected.entity) == Some(id)
+            && used_fragments.contains(id)
+        {
+            let new_id = namespace_fragment(id, &collected.namespace);
+            renames
+                .entry(collected.namespace.to_string())
+                .or_default()
+                .insert(id.to_string(), new_id);
+        }
+    }
+    if renames.is_empty() {
+        return;
+    }
+
+    for collected in worker.arena.local_mut() {
+        if let Some(map) = renames.get(&*collected.namespace) {
+            rewrite_references(&mut collected.entity, map);
+        }
+    }
+    if let (Some(root), Some(map)) = (merge_root, renames.get(merge_namespace)) {
+        rewrite_references(root, map);
+    }
+    for folder in worker.subcrate_folders.iter_mut() {
+        let Some(folder_namespace) = extract_id(folder)
+            .map(namespace_from_folder_id)
+            .map(String::from)
+        else {
+            continue;
+        };
+        for (namespace, map) in &renames {
+            if *namespace == folder_namespace {
+                rewrite_references(folder, map);
+            } else if namespace.starts_with(&format!("{}/", folder_namespace)) {
+                if let Some(contained) = folder.get_mut(CONSOLIDATED_ENTITIES_SHORT) {
+                    rewrite_references(contained, map);
+                }
+            }
+        }
+    }
+    worker
+        .fragment_tracker
+        .extend(renames.into_values().flat_map(HashMap::into_values));
+}
+
+/// Map `f` over `items` on up to `parallelism` scoped threads, keeping input order
+fn parallel_map<T, R, F>(items: Vec<T>, parallelism: usize, f: F) -> Vec<R>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+{
+    let len = items.len();
+    let next = AtomicUsize::new(0);
+    let items: Vec<Mutex<Option<T>>> = items.into_iter().map(|i| Mutex::new(Some(i))).collect();
+    let results: Vec<Mutex<Option<R>>> = (0..len).map(|_| Mutex::new(None)).collect();
+    let audit_log = audit::active();
+    let offline = is_offline();
+    let budget = current_retry_budget();
+    let fetch_errors = current_fetch_error_counter();
+    let metadata_file_patterns = current_metadata_file_patterns();
+
+    std::thread::scope(|scope| {
+        for _ in 0..parallelism.clamp(1, len.max(1)) {
+            let audit_log = audit_log.clone();
+            let budget = budget.clone();
+            let fetch_errors = fetch_errors.clone();
+            let metadata_file_patterns = metadata_file_patterns.clone();
+            let (next, items, results, f) = (&next, &items, &results, &f);
+            scope.spawn(move || {
+                // Loads on this thread belong to the caller's audit log, are
+                // offline if the caller is, share its retry wait budget and
+                // fetch error counter and look for the same metadata files
+                let _audit = audit::activate(audit_log);
+                let _offline = set_offline(offline);
+                let _retry_budget = share_retry_budget(budget);
+                let _fetch_errors = share_fetch_error_counter(fetch_errors);
+                let _metadata_file_patterns = share_metadata_file_patterns(metadata_file_patterns);
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= len {
+                        break;
+                    }
+                    let item = items[index].lock().unwrap().take().unwrap();
+                    let result = f(item);
+                    *results[index].lock().unwrap() = Some(result);
+                }
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|r| r.into_inner().unwrap().expect("every item was mapped"))
+        .collect()
+}
+
+/// Recursively collect entities from a crate and its subcrates
+#[allow(clippy::too_many_arguments)]
+fn collect_hierarchy(
+    graph: Vec<Value>,
+    namespace: &str,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+    state: &mut CollectState,
+    root_entity: &mut Option<Value>,
+    metadata_descriptor: &mut Option<Value>,
+    profiler: &mut Profiler,
+) -> Result<(), ConsolidateError> {
+    state.stats.crates_consolidated += 1;
+    check_crate_limits(&graph, options)?;
+    state.entities_loaded += graph.len();
+    check_total_entities(state.entities_loaded, options)?;
+    let overrides = state.subcrate_filter.overrides(namespace);
+    let source_namespace = state.source_namespace(namespace).to_string();
+    if let Some(strategy) = overrides.as_ref().and_then(|o| o.conflict_strategy) {
+        state
+            .conflict_strategies
+            .insert(namespace.to_string(), strategy);
+    }
+
+    // Subcrates the loader lists are looked up by the crate's source
+    // namespace, which differs from `namespace` if it was moved
+    let listed = loader.listed_subcrates(&source_namespace);
+
+    // A crate collected before with the same metadata, listed subcrates and
+    // options can be taken from the cache, if its ids still map the same way
+    let cache_key = match &options.cache {
+        Some(_) => Some(CacheKey::new(&graph, namespace, &listed, options)?),
+        None => None,
+    };
+    let cached = match (&options.cache, &cache_key) {
+        (Some(cache), Some(key)) => cache.get(key),
+        _ => None,
+    };
+    let mut graph = Some(graph);
+    let mut collected = match cached {
+        Some(_) => None,
+        None => {
+            let graph = graph.take().unwrap_or_default();
+            Some(collect_crate(graph, namespace, &listed, options, profiler)?)
         }
+    };
+
+    let (subcrate_ids, root_candidates, descriptor_id, ids) = match (&collected, &cached) {
+        (Some(collected), _) => {
+            let collection = &collected.collection;
+            (
+                &collection.subcrate_ids,
+                &collected.root_candidates,
+                collection
+                    .metadata_descriptor
+                    .as_ref()
+                    .map(|d| d.original_id.clone()),
+                collected.ids().collect::<Vec<_>>(),
+            )
+        }
+        (None, Some(cached)) => (
+            &cached.subcrate_ids,
+            &cached.root_candidates,
+            cached.descriptor_id.clone(),
+            cached.ids.iter().map(String::as_str).collect(),
+        ),
+        (None, None) => unreachable!("crates are either cached or collected"),
+    };
+    if root_candidates.len() > 1 {
+        state.warnings.push(format!(
+            "Crate '{}' has {} root entities ({}), resolved with {:?}",
+            namespace,
+            root_candidates.len(),
+            root_candidates.join(", "),
+            options.multi_root_policy
+        ));
+    }
+
+    // Claim namespaces of discovered subcrates before rewriting, so that
+    // references to a subcrate given another namespace follow its folder.
+    // Below the depth limit, and for subcrates not selected by the include
+    // and exclude patterns, references stay as they are.
+    let depth_limit = overrides
+        .as_ref()
+        .and_then(|o| o.max_depth)
+        .map(|levels| state.depth + levels)
+        .or(state.depth_limit);
+    let at_max_depth = depth_limit
+        .or(options.max_depth)
+        .is_some_and(|max| state.depth >= max);
+    let selected: Vec<&String> = subcrate_ids
+        .iter()
+        .filter(|_| !at_max_depth)
+        .filter(|subcrate_id| {
+            let (natural, _) = natural_namespace(namespace, subcrate_id, state);
+            state.subcrate_filter.selects(&natural)
+        })
+        .collect();
+    let subcrates: Vec<(String, String, bool)> = selected
+        .into_iter()
+        .filter_map(|subcrate_id| {
+            claim_subcrate_namespace(namespace, subcrate_id, options.case_collision_policy, state)
+                .map(|(subcrate_namespace, renamed)| {
+                    (subcrate_id.clone(), subcrate_namespace, renamed)
+                })
+        })
+        .collect();
+
+    // Build ID map for rewriting, reusing the map of the previously
+    // collected crate
+    let started = profiler.start();
+    let mut id_map = std::mem::take(&mut state.id_map_scratch);
+    extend_id_map(
+        &mut id_map,
+        ids.iter().copied(),
+        namespace,
+        &mut state.fragment_tracker,
+        options.fragment_id_policy,
+    );
+
+    // Point references to dropped root aliases at the resolved root
+    let root_id = if namespace.is_empty() {
+        ROOT_ENTITY_ID.to_string()
+    } else {
+        format!("./{}/", namespace)
+    };
+    for alias in root_candidates {
+        if alias != ROOT_ENTITY_ID {
+            id_map.insert(alias.clone(), root_id.clone());
+        }
+    }
+    for (subcrate_id, subcrate_namespace, renamed) in &subcrates {
+        if *renamed && classify_id(subcrate_id) != IdKind::Absolute {
+            id_map.insert(subcrate_id.clone(), format!("./{}/", subcrate_namespace));
+        }
+    }
+
+    // A subcrate's descriptor is dropped unless kept as a file of its folder;
+    // either way nothing may keep pointing at the crate-relative descriptor id
+    let mut dropped_descriptors: HashSet<String> = HashSet::new();
+    let mut descriptor_rename: HashMap<String, String> = HashMap::new();
+    if let (false, Some(id)) = (namespace.is_empty(), descriptor_id.clone()) {
+        if options.keep_subcrate_descriptors {
+            let file = id.strip_prefix("./").unwrap_or(&id);
+            let new_id = format!("./{}/{}", namespace, file);
+            id_map.insert(id.clone(), new_id.clone());
+            descriptor_rename.insert(id, new_id);
+        } else {
+            dropped_descriptors.insert(id);
+        }
+    }
+    let (mut rewritten, subcrate_refs) = match cached {
+        Some(cached) if cached.id_map == id_map => {
+            state.stats.cached_crates += 1;
+            rewritten_from_cache(cached, namespace)
+        }
+        _ => {
+            // Collect it after all if the cached crate's ids map differently
+            let collected = match collected.take() {
+                Some(collected) => collected,
+                None => {
+                    let graph = graph.take().unwrap_or_default();
+                    collect_crate(graph, namespace, &listed, options, profiler)?
+                }
+            };
+            let rewritten_count = collected.ids().count();
+            let ids: Vec<String> = match &cache_key {
+                Some(_) => collected.ids().map(String::from).collect(),
+                None => Vec::new(),
+            };
+            let CollectedCrate {
+                collection,
+                subcrate_refs,
+                root_candidates,
+            } = collected;
+            let subcrate_ids = match &cache_key {
+                Some(_) => collection.subcrate_ids.clone(),
+                None => Vec::new(),
+            };
+            let rewritten = rewrite_crate(
+                collection,
+                namespace,
+                &id_map,
+                &dropped_descriptors,
+                &descriptor_rename,
+                options,
+            );
+            // Each mapped id is a new string, written once as @id and again per reference
+            profiler.record(Phase::Rewrite, started, rewritten_count, || {
+                id_map.len() * 2
+            });
+
+            if let (Some(cache), Some(key)) = (&options.cache, &cache_key) {
+                let entry = CachedCrate {
+                    subcrate_ids,
+                    subcrate_refs: subcrate_refs.clone(),
+                    ids,
+                    root_candidates,
+                    descriptor_id,
+                    id_map: id_map.clone(),
+                    root_entity: rewritten.root_entity.clone(),
+                    metadata_descriptor: rewritten.metadata_descriptor.clone(),
+                    local: rewritten.local.iter().map(CachedEntity::new).collect(),
+                    shared: rewritten.shared.iter().map(CachedEntity::new).collect(),
+                };
+                if let Err(e) = cache.put(key, &entry) {
+                    state
+                        .warnings
+                        .push(format!("Failed to cache crate '{}': {}", namespace, e));
+                }
+            }
+            (rewritten, subcrate_refs)
+        }
+    };
+    id_map.clear();
+    state.id_map_scratch = id_map;
+    if let Some(overrides) = &overrides {
+        apply_overrides(&mut rewritten, overrides);
+    }
+
+    *root_entity = rewritten.root_entity;
+    *metadata_descriptor = rewritten.metadata_descriptor;
+    state
+        .arena
+        .reserve(rewritten.local.len() + rewritten.shared.len());
+    for collected in rewritten.local {
+        state.arena.push_local(collected);
+    }
+    for collected in rewritten.shared {
+        state.arena.push_shared(collected);
+    }
+
+    // Process discovered subcrates, below the depth limit of this crate
+    let parent_depth_limit = std::mem::replace(&mut state.depth_limit, depth_limit);
+    for (subcrate_id, subcrate_namespace, renamed) in &subcrates {
+        let subcrate_entity = subcrate_refs.get(subcrate_id);
+
+        // Attach the subcrate's identity to anything that fails below
+        let in_subcrate = |e: ConsolidateError| {
+            e.in_subcrate(
+                subcrate_id.as_str(),
+                subcrate_namespace.as_str(),
+                subcrate_entity.and_then(extract_subject_of),
+            )
+        };
+
+        // Ids below a reserved segment would shadow the crate's own files
+        validate_namespace(subcrate_namespace)
+            .map_err(ConsolidateError::ReservedNamespace)
+            .map_err(in_subcrate)?;
+
+        // Try to load the subcrate
+        let started = profiler.start();
+        let subcrate_ref = SubcrateRef {
+            parent_root: root_entity.as_ref(),
+            parent_descriptor: metadata_descriptor.as_ref(),
+            ..SubcrateRef::new(subcrate_id, &source_namespace, subcrate_entity)
+        };
+        let read_limit = limit_reads(options.max_metadata_bytes);
+        let loaded = loader.load_ref(&subcrate_ref);
+        let limit_hit = read_limit.hit();
+        drop(read_limit);
+        let subcrate_graph = match loaded {
+            Ok(g) => g,
+            // Like all limits, an oversized document fails even if not strict
+            Err(e) if limit_hit => {
+                let limit = options.max_metadata_bytes.unwrap_or_default();
+                let reason = format!("crate metadata exceeds the limit of {} bytes: {}", limit, e);
+                return Err(in_subcrate(ConsolidateError::LimitExceeded(reason)));
+            }
+            // Offline consolidations must not quietly miss remote subcrates
+            Err(e @ ConsolidateError::NetworkDisabled(_)) => return Err(in_subcrate(e)),
+            Err(e) if options.strict => return Err(in_subcrate(e)),
+            Err(_) => {
+                // Subcrate couldn't be loaded - skip but don't fail
+                // The reference entity will remain as-is
+                continue;
+            }
+        };
+        profiler.record(Phase::Load, started, subcrate_graph.len(), || {
+            subcrate_graph.iter().map(count_allocations).sum()
+        });
+
+        let folder_id =
+            if namespace.is_empty() && (!renamed || classify_id(subcrate_id) == IdKind::Absolute) {
+                subcrate_id.clone()
+            } else {
+                format!("./{}/", subcrate_namespace)
+            };
+        state
+            .folder_ids
+            .insert(subcrate_namespace.clone(), folder_id.clone());
+        if options.record_provenance {
+            let location = loader.location(subcrate_id, &source_namespace, subcrate_entity);
+            if let Some(location) = location {
+                state.locations.insert(folder_id.clone(), location);
+            }
+        }
+
+        // Recursively collect from subcrate
+        let mut subcrate_root: Option<Value> = None;
+        let mut subcrate_desc: Option<Value> = None;
+        let local_mark = state.arena.local_len();
+
+        state.depth += 1;
+        let collected = collect_hierarchy(
+            subcrate_graph,
+            subcrate_namespace,
+            loader,
+            options,
+            state,
+            &mut subcrate_root,
+            &mut subcrate_desc,
+            profiler,
+        );
+        state.depth -= 1;
+        collected.map_err(in_subcrate)?;
+
+        // Mark this subcrate as processed (so we can exclude it from shared entities)
+        state.processed_subcrate_ids.insert(subcrate_id.clone());
+
+        let typed = check_subcrate_descriptor(
+            subcrate_desc.as_ref(),
+            &mut subcrate_root,
+            subcrate_id,
+            options.missing_descriptor_policy,
+            &mut state.warnings,
+        )
+        .map_err(in_subcrate)?;
+
+        // Create the subcrate folder entity
+        if let Some(sub_root) = subcrate_root {
+            // Collect IDs of entities from this subcrate
+            let mut contained_ids = Vec::with_capacity(state.arena.local_len() - local_mark);
+            contained_ids.extend(state.arena.local_since(local_mark).filter_map(|e| {
+                // Get the rewritten ID
+                extract_id(&e.entity).map(String::from)
+            }));
+
+            let parent_folder = match options.root_handling {
+                RootHandling::MergeReference => subcrate_entity,
+                RootHandling::PreserveRoot => None,
+            };
+            let mut folder = create_subcrate_folder_with(
+                &folder_id,
+                parent_folder,
+                &sub_root,
+                if typed { contained_ids } else { vec![] },
+                options.add_subcrate_type && typed,
+                options.reference_merge,
+            );
+            if typed {
+                add_specialized_types(&mut folder, &sub_root, &options.subcrate_types);
+            }
+            if options.link_parents {
+                let parent_id = state
+                    .folder_ids
+                    .get(namespace)
+                    .map_or(ROOT_ENTITY_ID, String::as_str);
+                add_reference(&mut folder, "isPartOf", parent_id);
+            }
+            state.subcrate_folders.push(folder);
+        }
+    }
+    state.depth_limit = parent_depth_limit;
+
+    Ok(())
+}
+
+/// A crate gathered from its graph, before its ids are rewritten
+struct CollectedCrate {
+    collection: CrateCollection,
+    /// The crate's references to subcrates, before rewriting
+    subcrate_refs: HashMap<String, Value>,
+    /// Ids of the crate's root entity candidates
+    root_candidates: Vec<String>,
+}
+
+impl CollectedCrate {
+    /// Original ids of the local entities and root, which get rewritten
+    fn ids(&self) -> impl Iterator<Item = &str> {
+        self.collection
+            .local_entities
+            .iter()
+            .chain(&self.collection.root_entity)
+            .map(|e| e.original_id.as_str())
+    }
+}
+
+/// Fail if a crate's graph exceeds the per-crate limits of `options`
+fn check_crate_limits(
+    graph: &[Value],
+    options: &ConsolidateOptions,
+) -> Result<(), ConsolidateError> {
+    if let Some(max) = options.max_crate_entities.filter(|max| graph.len() > *max) {
+        return Err(ConsolidateError::LimitExceeded(format!(
+            "crate has {} entities, more than the limit of {}",
+            graph.len(),
+            max
+        )));
+    }
+    Ok(())
+}
+
+/// Fail if `loaded` entities exceed the total limit of `options`
+fn check_total_entities(
+    loaded: usize,
+    options: &ConsolidateOptions,
+) -> Result<(), ConsolidateError> {
+    match options.max_total_entities {
+        Some(max) if loaded > max => Err(ConsolidateError::LimitExceeded(format!(
+            "crates loaded have {} entities, more than the limit of {}",
+            loaded, max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Gather the entities of a crate's graph, resolving its root
+///
+/// `listed` are the subcrates its loader lists besides those in the graph.
+fn collect_crate(
+    mut graph: Vec<Value>,
+    namespace: &str,
+    listed: &[String],
+    options: &ConsolidateOptions,
+    profiler: &mut Profiler,
+) -> Result<CollectedCrate, ConsolidateError> {
+    // Validate against the input graph so errors point into the crate's own document
+    if options.require_absolute_pointers {
+        validate_pointers(&graph)?;
+    }
+
+    let started = profiler.start();
+    if options.normalize_unicode {
+        graph.iter_mut().for_each(normalize_references);
+    }
+    let graph_len = graph.len();
+    let mut collection = collect_from_graph(graph, namespace);
+    for id in listed {
+        if !collection.subcrate_ids.contains(id) {
+            collection.subcrate_ids.push(id.clone());
+        }
+    }
+
+    // Keep the parent's references to subcrates (for extracting subjectOf)
+    // before their ids are rewritten
+    let subcrate_refs: HashMap<String, Value> = collection
+        .subcrate_ids
+        .iter()
+        .filter_map(|id| {
+            collection
+                .local_entities
+                .iter()
+                .chain(&collection.shared_entities)
+                .find(|e| e.original_id == *id)
+                .map(|e| (id.clone(), e.entity.clone()))
+        })
+        .collect();
+
+    let root_candidates = resolve_root(&mut collection, options.multi_root_policy)?;
+    profiler.record(Phase::Collect, started, graph_len, || {
+        subcrate_refs.values().map(count_allocations).sum()
+    });
+    Ok(CollectedCrate {
+        collection,
+        subcrate_refs,
+        root_candidates,
+    })
+}
+
+/// A crate's entities after rewriting
+struct RewrittenCrate {
+    root_entity: Option<Value>,
+    metadata_descriptor: Option<Value>,
+    /// Local entities, in the order they join the arena
+    local: Vec<CollectedEntity>,
+    /// Shared entities, merged later
+    shared: Vec<CollectedEntity>,
+}
+
+/// Strip the properties and drop the entities of the types `overrides`
+/// names from a rewritten subcrate
+fn apply_overrides(rewritten: &mut RewrittenCrate, overrides: &SubcrateOverrides) {
+    let excluded = |collected: &CollectedEntity| {
+        overrides
+            .exclude_types
+            .iter()
+            .any(|type_name| has_type(&collected.entity, type_name))
+    };
+    let mut dropped = HashSet::new();
+    for entities in [&mut rewritten.local, &mut rewritten.shared] {
+        entities.retain(|collected| {
+            if !excluded(collected) {
+                return true;
+            }
+            dropped.extend(extract_id(&collected.entity).map(String::from));
+            false
+        });
+    }
+    let entities = rewritten
+        .local
+        .iter_mut()
+        .chain(&mut rewritten.shared)
+        .map(|collected| &mut collected.entity)
+        .chain(&mut rewritten.root_entity);
+    for entity in entities {
+        remove_references(entity, &dropped);
+        if let Some(obj) = entity.as_object_mut() {
+            for property in &overrides.strip_properties {
+                obj.remove(property);
+            }
+        }
+    }
+}
+
+/// Rewrite the ids of a collected crate's entities and the references
+/// within them
+fn rewrite_crate(
+    collection: CrateCollection,
+    namespace: &str,
+    id_map: &HashMap<String, String>,
+    dropped_descriptors: &HashSet<String>,
+    descriptor_rename: &HashMap<String, String>,
+    options: &ConsolidateOptions,
+) -> RewrittenCrate {
+    let mut rewritten = RewrittenCrate {
+        root_entity: None,
+        metadata_descriptor: None,
+        local: Vec::with_capacity(collection.local_entities.len() + 1),
+        shared: Vec::with_capacity(collection.shared_entities.len()),
+    };
+
+    // Handle root entity
+    if namespace.is_empty() {
+        // This is the main root - preserve it, following renamed subcrates
+        if let Some(mut collected) = collection.root_entity {
+            rewrite_references(&mut collected.entity, id_map);
+            rewritten.root_entity = Some(collected.entity);
+        }
+        if let Some(collected) = collection.metadata_descriptor {
+            rewritten.metadata_descriptor = Some(collected.entity);
+        }
+    } else {
+        // This is a subcrate - capture its root for subcrate folder creation,
+        // pointing its references at the rewritten ids
+        if let Some(mut collected) = collection.root_entity {
+            rewrite_references(&mut collected.entity, id_map);
+            remove_references(&mut collected.entity, dropped_descriptors);
+            rewritten.root_entity = Some(collected.entity);
+        }
+        // and its descriptor so the caller can check it is present
+        if let Some(collected) = collection.metadata_descriptor {
+            if options.keep_subcrate_descriptors {
+                let mut kept = collected.clone();
+                rewrite_references(&mut kept.entity, id_map);
+                rewritten.local.push(kept);
+            }
+            rewritten.metadata_descriptor = Some(collected.entity);
+        }
+    }
+
+    // Process and rewrite local entities
+    for mut collected in collection.local_entities {
+        // Rewrite the entity's @id if needed
+        if let Some(new_id) = id_map.get(&collected.original_id) {
+            if let Some(Value::String(id)) = collected.entity.get_mut("@id") {
+                id.clone_from(new_id);
+            }
+        }
+
+        // Rewrite all @id references within the entity
+        rewrite_references(&mut collected.entity, id_map);
+        remove_references(&mut collected.entity, dropped_descriptors);
+
+        rewritten.local.push(collected);
+    }
+
+    // Add shared entities (will be merged later)
+    for mut collected in collection.shared_entities {
+        if !descriptor_rename.is_empty() {
+            rewrite_references(&mut collected.entity, descriptor_rename);
+        }
+        remove_references(&mut collected.entity, dropped_descriptors);
+        rewritten.shared.push(collected);
+    }
+    rewritten
+}
+
+/// The entities of a cached crate in `namespace`, and its references to
+/// subcrates
+fn rewritten_from_cache(
+    cached: CachedCrate,
+    namespace: &str,
+) -> (RewrittenCrate, HashMap<String, Value>) {
+    let namespace: Arc<str> = Arc::from(namespace);
+    let rewritten = RewrittenCrate {
+        root_entity: cached.root_entity,
+        metadata_descriptor: cached.metadata_descriptor,
+        local: cached
+            .local
+            .into_iter()
+            .map(|e| e.into_collected(&namespace))
+            .collect(),
+        shared: cached
+            .shared
+            .into_iter()
+            .map(|e| e.into_collected(&namespace))
+            .collect(),
+    };
+    (rewritten, cached.subcrate_refs)
+}
+
+/// Ids occurring more than once among the shared entities
+fn duplicate_ids(shared: &[CollectedEntity]) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    shared
+        .iter()
+        .filter(|e| !seen.insert(e.original_id.as_str()))
+        .map(|e| e.original_id.clone())
+        .collect()
+}
+
+/// Apply the missing-descriptor policy to a collected subcrate
+///
+/// Synthesizes a minimal root if the subcrate has neither descriptor nor
+/// root. Returns whether the subcrate folder should be typed as a Subcrate.
+fn check_subcrate_descriptor(
+    descriptor: Option<&Value>,
+    subcrate_root: &mut Option<Value>,
+    subcrate_id: &str,
+    policy: MissingDescriptorPolicy,
+    warnings: &mut Vec<String>,
+) -> Result<bool, ConsolidateError> {
+    if descriptor.is_some() {
+        return Ok(true);
+    }
+
+    let typed = match policy {
+        MissingDescriptorPolicy::Fail => {
+            return Err(ConsolidateError::MissingSubcrateDescriptor(
+                subcrate_id.to_string(),
+            ));
+        }
+        MissingDescriptorPolicy::Synthesize => true,
+        MissingDescriptorPolicy::PlainDataset => false,
+    };
+
+    warnings.push(format!(
+        "Subcrate '{}' has no metadata descriptor, {}",
+        subcrate_id,
+        if typed {
+            "assuming a standard one"
+        } else {
+            "treating it as a plain dataset"
+        }
+    ));
+
+    if subcrate_root.is_none() {
+        *subcrate_root = Some(json!({"@id": ROOT_ENTITY_ID, "@type": "Dataset"}));
+    }
+
+    Ok(typed)
+}
+
+/// Parse @graph from JSON content
+pub fn parse_graph(content: &str, source: &str) -> Result<Vec<Value>, ConsolidateError> {
+    let mut doc: Value = serde_json::from_str(content)?;
+
+    match doc.get_mut("@graph").map(Value::take) {
+        Some(Value::Array(arr)) => Ok(arr),
+        Some(_) => Err(ConsolidateError::InvalidStructure(
+            "@graph is not an array".to_string(),
+        )),
+        None => Err(ConsolidateError::InvalidStructure(format!(
+            "No @graph found in {}",
+            source
+        ))),
+    }
+}
+
+/// Build a complete RO-Crate JSON-LD document from consolidation result
+pub fn to_jsonld(result: &ConsolidateResult) -> Value {
+    json!({
+        "@context": result.context,
+        "@graph": result.graph
+    })
+}
+
+/// Serialize consolidation result to JSON string
+pub fn to_json_string(
+    result: &ConsolidateResult,
+    pretty: bool,
+) -> Result<String, ConsolidateError> {
+    let style = if pretty {
+        OutputStyle::pretty()
+    } else {
+        OutputStyle::default()
+    };
+    to_json_string_styled(result, &style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_root_graph() -> Vec<Value> {
+        vec![
+            json!({
+                "@id": "ro-crate-metadata.json",
+                "@type": "CreativeWork",
+                "about": {"@id": "./"},
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "name": "Root Crate",
+                "hasPart": [{"@id": "./data.csv"}]
+            }),
+            json!({
+                "@id": "./data.csv",
+                "@type": "File",
+                "name": "Data file"
+            }),
+            json!({
+                "@id": "https://orcid.org/0000-0001",
+                "@type": "Person",
+                "name": "Alice"
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_consolidate_single_no_subcrates() {
+        let graph = sample_root_graph();
+        let result = consolidate(
+            ConsolidateInput::Single(graph),
+            &NoOpLoader,
+            &ConsolidateOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(result.stats.crates_consolidated, 1);
+        assert!(result.graph.len() >= 4);
+
+        // Check root entity is present
+        let root = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./"))
+            .unwrap();
+        assert_eq!(root.get("name"), Some(&json!("Root Crate")));
+
+        // The descriptor declares the consolidation profile
+        assert_eq!(
+            result.graph[0]["conformsTo"],
+            json!([
+                {"@id": "https://w3id.org/ro/crate/1.1"},
+                {"@id": CONSOLIDATION_PROFILE_ID}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_options_serialization() {
+        let options = ConsolidateOptions {
+            shape: ShapePolicy::AlwaysArray,
+            descriptor_id: Some("out-ro-crate-metadata.json".to_string()),
+            ..ConsolidateOptions::default()
+        };
+        let value = serde_json::to_value(&options).unwrap();
+        assert_eq!(value["shape"], "always_array");
+        assert_eq!(value["multi_root_policy"], "pick_descriptor_about");
+        assert!(value.get("post_processors").is_none());
+
+        let parsed: ConsolidateOptions = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), value);
+
+        // Missing fields take their defaults, unknown ones are rejected
+        let partial: ConsolidateOptions =
+            serde_json::from_value(json!({"strict": true, "parallelism": 4})).unwrap();
+        assert!(partial.strict);
+        assert_eq!(partial.parallelism, 4);
+        assert!(partial.normalize_unicode);
+        assert!(serde_json::from_value::<ConsolidateOptions>(json!({"strcit": true})).is_err());
+    }
+
+    #[test]
+    fn test_record_provenance() {
+        let options = ConsolidateOptions {
+            record_provenance: true,
+            keep_partial: true,
+            ..ConsolidateOptions::default()
+        };
+        let result = consolidate(
+            ConsolidateInput::Single(sample_root_graph()),
+            &NoOpLoader,
+            &options,
+        )
+        .unwrap();
+
+        let root = result.graph.iter().find(|e| e["@id"] == "./").unwrap();
+        assert_eq!(root["mentions"], json!({"@id": CONSOLIDATION_ACTION_ID}));
+        let action = result
+            .graph
+            .iter()
+            .find(|e| e["@id"] == CONSOLIDATION_ACTION_ID)
+            .unwrap();
+        assert_eq!(action["instrument"]["@id"], CONSOLIDATION_SOFTWARE_ID);
+        let recorded: ConsolidateOptions =
+            serde_json::from_str(action[CONSOLIDATION_OPTIONS_SHORT].as_str().unwrap()).unwrap();
+        assert!(recorded.keep_partial && recorded.record_provenance);
+        assert!(result
+            .graph
+            .iter()
+            .any(|e| e["@id"] == CONSOLIDATION_SOFTWARE_ID));
+        let started = action["startTime"].as_str().unwrap();
+        assert_eq!(started.len(), "2024-01-01T00:00:00Z".len());
+        assert!(started.ends_with('Z') && action["endTime"].as_str().unwrap() >= started);
+        assert_eq!(action["result"], json!([{"@id": "./"}]));
+
+        let mut main = sample_root_graph();
+        main[1]["identifier"] = json!(["https://doi.org/10.1234/main", "local-42"]);
+        let other = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "identifier": {"@id": "https://doi.org/10.1234/other"}
+            }),
+        ];
+        let result = consolidate(
+            ConsolidateInput::Merge {
+                main,
+                others: vec![MergeCrate {
+                    graph: other,
+                    folder_id: "./imported/".to_string(),
+                    name: None,
+                }],
+            },
+            &NoOpLoader,
+            &options,
+        )
+        .unwrap();
+        let action = result
+            .graph
+            .iter()
+            .find(|e| e["@id"] == CONSOLIDATION_ACTION_ID)
+            .unwrap();
+        assert_eq!(
+            action["object"],
+            json!([
+                {"@id": "https://doi.org/10.1234/main"},
+                {"@id": "https://doi.org/10.1234/other"}
+            ])
+        );
+        assert_eq!(
+            action["result"],
+            json!([{"@id": "./"}, {"@id": "./imported/"}])
+        );
+
+        // Crates without an absolute identifier are known by their location
+        let mut main = sample_root_graph();
+        main[1]["identifier"] = json!("local-42");
+        let options = ConsolidateOptions {
+            input_locations: BTreeMap::from([
+                ("./".to_string(), "/data/main crate".to_string()),
+                (
+                    "./imported/".to_string(),
+                    "https://example.org/other/".to_string(),
+                ),
+            ]),
+            ..options
+        };
+        let result = consolidate(
+            ConsolidateInput::Merge {
+                main,
+                others: vec![MergeCrate {
+                    graph: sample_root_graph(),
+                    folder_id: "./imported/".to_string(),
+                    name: None,
+                }],
+            },
+            &NoOpLoader,
+            &options,
+        )
+        .unwrap();
+        let action = result
+            .graph
+            .iter()
+            .find(|e| e["@id"] == CONSOLIDATION_ACTION_ID)
+            .unwrap();
+        assert_eq!(
+            action["object"],
+            json!([
+                {"@id": "file:///data/main%20crate"},
+                {"@id": "https://example.org/other/"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_consolidate_merge_two_crates() {
+        let main = sample_root_graph();
+        let other = vec![
+            json!({
+                "@id": "ro-crate-metadata.json",
+                "@type": "CreativeWork",
+                "about": {"@id": "./"}
+            }),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "name": "Other Crate",
+                "description": "Imported data"
+            }),
+            json!({
+                "@id": "./results.csv",
+                "@type": "File"
+            }),
+            json!({
+                "@id": "https://orcid.org/0000-0001",
+                "@type": "Person",
+                "name": "Alice Smith"  // Different name for same person
+            }),
+        ];
+
+        let result = consolidate(
+            ConsolidateInput::Merge {
+                main,
+                others: vec![MergeCrate {
+                    graph: other,
+                    folder_id: "./imported/".to_string(),
+                    name: Some("Imported Dataset".to_string()),
+                }],
+            },
+            &NoOpLoader,
+            &ConsolidateOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(result.stats.crates_consolidated, 2);
+
+        // Check subcrate folder was created
+        let folder = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./imported/"))
+            .unwrap();
+        let types = folder.get("@type").unwrap();
+        assert!(types.as_array().unwrap().contains(&json!("Subcrate")));
+
+        // Check shared entity was merged (Alice with two names)
+        let alice = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("https://orcid.org/0000-0001"))
+            .unwrap();
+        let name = alice.get("name").unwrap();
+        // Should have both names
+        assert!(name.is_array() || name == &json!("Alice"));
+    }
+
+    #[test]
+    fn test_conflict_strategy() {
+        let other = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "author": {"@id": "https://orcid.org/0000-0001"}}),
+            json!({"@id": "https://orcid.org/0000-0001", "@type": "Person", "name": "Alice Smith"}),
+        ];
+        let merge = |strategy| {
+            let input = ConsolidateInput::Merge {
+                main: sample_root_graph(),
+                others: vec![MergeCrate {
+                    graph: other.clone(),
+                    folder_id: "./imported/".to_string(),
+                    name: None,
+                }],
+            };
+            let options = ConsolidateOptions {
+                conflict_strategy: strategy,
+                ..ConsolidateOptions::default()
+            };
+            consolidate(input, &NoOpLoader, &options)
+        };
+        let name = |strategy| {
+            let result = merge(strategy).unwrap();
+            let alice = result
+                .graph
+                .iter()
+                .find(|e| extract_id(e) == Some("https://orcid.org/0000-0001"))
+                .cloned()
+                .unwrap();
+            alice["name"].clone()
+        };
+
+        assert_eq!(
+            name(ConflictStrategy::Union),
+            json!(["Alice", "Alice Smith"])
+        );
+        assert_eq!(name(ConflictStrategy::PreferMain), "Alice");
+        assert_eq!(name(ConflictStrategy::PreferIncoming), "Alice Smith");
+        let conflicts = merge(ConflictStrategy::PreferMain).unwrap().conflicts;
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].second, "Alice Smith");
+        let err = merge(ConflictStrategy::Error).unwrap_err();
+        assert_eq!(err.code(), "merge_conflict");
+        assert!(matches!(
+            err,
+            ConsolidateError::MergeConflict { ref property, .. } if property == "name"
+        ));
+        assert_eq!(err.pointer(), Some("/@graph/2/name"));
+    }
+
+    #[test]
+    fn test_subcrate_types() {
+        let run = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "conformsTo": [
+                    {"@id": "https://w3id.org/ro/crate/1.1"},
+                    {"@id": "https://w3id.org/ro/wfrun/process/0.5"}
+                ]
+            }),
+        ];
+        let collection = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": ["Dataset", "RepositoryCollection"]}),
+        ];
+        let options = ConsolidateOptions {
+            subcrate_types: BTreeMap::from([
+                (
+                    "https://w3id.org/ro/wfrun/process/0.5".to_string(),
+                    vec!["LabProcess".to_string()],
+                ),
+                (
+                    "RepositoryCollection".to_string(),
+                    vec!["Collection".to_string(), "Subcrate".to_string()],
+                ),
+            ]),
+            ..Default::default()
+        };
+        let others = [("./run/", run), ("./collection/", collection)]
+            .into_iter()
+            .map(|(folder_id, graph)| MergeCrate {
+                graph,
+                folder_id: folder_id.to_string(),
+                name: None,
+            })
+            .collect();
+        let input = ConsolidateInput::Merge {
+            main: sample_root_graph(),
+            others,
+        };
+        let result = consolidate(input, &NoOpLoader, &options).unwrap();
+        let types = |id: &str| {
+            result
+                .graph
+                .iter()
+                .find(|e| extract_id(e) == Some(id))
+                .unwrap()["@type"]
+                .clone()
+        };
+        assert_eq!(
+            types("./run/"),
+            json!(["Dataset", "Subcrate", "LabProcess"])
+        );
+        assert_eq!(
+            types("./collection/"),
+            json!(["Dataset", "Subcrate", "RepositoryCollection", "Collection"])
+        );
+    }
+
+    #[test]
+    fn test_stable_fragment_ids() {
+        let other = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "author": {"@id": "#lab"}}),
+            json!({"@id": "#lab", "@type": "Organization"}),
+        ];
+
+        let result = consolidate(
+            ConsolidateInput::Merge {
+                main: sample_root_graph(),
+                others: vec![MergeCrate {
+                    graph: other,
+                    folder_id: "./imported/".to_string(),
+                    name: None,
+                }],
+            },
+            &NoOpLoader,
+            &ConsolidateOptions {
+                fragment_id_policy: FragmentIdPolicy::AlwaysNamespace,
+                ..ConsolidateOptions::default()
+            },
+        )
+        .unwrap();
+
+        assert!(result
+            .graph
+            .iter()
+            .any(|e| extract_id(e) == Some("#imported-lab")));
+        let folder = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./imported/"))
+            .unwrap();
+        assert_eq!(folder["author"], json!({"@id": "#imported-lab"}));
+    }
+
+    #[test]
+    fn test_invalid_folder_id() {
+        let main = sample_root_graph();
+        let other = vec![json!({"@id": "./", "@type": "Dataset"})];
+
+        let result = consolidate(
+            ConsolidateInput::Merge {
+                main,
+                others: vec![MergeCrate {
+                    graph: other,
+                    folder_id: "no-trailing-slash".to_string(),
+                    name: None,
+                }],
+            },
+            &NoOpLoader,
+            &ConsolidateOptions::default(),
+        );
+
+        assert!(matches!(result, Err(ConsolidateError::InvalidFolderId(_))));
+    }
+
+    #[test]
+    fn test_detached_pointers_preserved() {
+        let main = sample_root_graph();
+        let other = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset"}),
+            json!({
+                "@id": "./remote.csv",
+                "@type": "File",
+                "contentUrl": {"@id": "./remote.csv"},
+                "distribution": {"@id": "https://example.org/remote.zip"}
+            }),
+        ];
+
+        let result = consolidate(
+            ConsolidateInput::Merge {
+                main,
+                others: vec![MergeCrate {
+                    graph: other,
+                    folder_id: "./detached/".to_string(),
+                    name: None,
+                }],
+            },
+            &NoOpLoader,
+            &ConsolidateOptions {
+                collect_distributions: true,
+                ..ConsolidateOptions::default()
+            },
+        )
+        .unwrap();
+
+        let file = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./detached/remote.csv"))
+            .unwrap();
+        assert_eq!(file["contentUrl"], json!({"@id": "./remote.csv"}));
+        assert_eq!(result.distributions.len(), 2);
+
+        let strict = ConsolidateOptions {
+            require_absolute_pointers: true,
+            ..ConsolidateOptions::default()
+        };
+        let graph = vec![
+            sample_root_graph()[0].clone(),
+            sample_root_graph()[1].clone(),
+            json!({"@id": "#data", "contentUrl": "data.csv"}),
+        ];
+        let result = consolidate(ConsolidateInput::Single(graph), &NoOpLoader, &strict);
+        assert_eq!(result.unwrap_err().pointer(), Some("/@graph/2/contentUrl"));
+    }
+
+    #[test]
+    fn test_base_id() {
+        let options = ConsolidateOptions {
+            base_id: Some("https://example.org/pub/".to_string()),
+            ..ConsolidateOptions::default()
+        };
+        let result = consolidate(
+            ConsolidateInput::Single(sample_root_graph()),
+            &NoOpLoader,
+            &options,
+        )
+        .unwrap();
+
+        let descriptor = &result.graph[0];
+        assert_eq!(descriptor["@id"], "ro-crate-metadata.json");
+        assert_eq!(descriptor["about"]["@id"], "https://example.org/pub/");
+
+        let root = &result.graph[1];
+        assert_eq!(root["@id"], "https://example.org/pub/");
+        assert_eq!(
+            root["hasPart"][0]["@id"],
+            "https://example.org/pub/data.csv"
+        );
+        assert!(result
+            .origins
+            .iter()
+            .any(|o| o.id == "https://example.org/pub/data.csv" && o.original_id == "./data.csv"));
+
+        let invalid = ConsolidateOptions {
+            base_id: Some("pub/".to_string()),
+            ..ConsolidateOptions::default()
+        };
+        let result = consolidate(
+            ConsolidateInput::Single(sample_root_graph()),
+            &NoOpLoader,
+            &invalid,
+        );
+        assert!(matches!(result, Err(ConsolidateError::InvalidBaseId(_))));
+    }
+
+    #[test]
+    fn test_descriptor_id() {
+        let mut graph = sample_root_graph();
+        graph[1]["subjectOf"] = json!({"@id": "ro-crate-metadata.json"});
+        let options = ConsolidateOptions {
+            descriptor_id: Some("mycrate-ro-crate-metadata.json".to_string()),
+            ..ConsolidateOptions::default()
+        };
+
+        let result = consolidate(ConsolidateInput::Single(graph), &NoOpLoader, &options).unwrap();
+
+        assert_eq!(result.graph[0]["@id"], "mycrate-ro-crate-metadata.json");
+        assert_eq!(result.graph[0]["about"], json!({"@id": "./"}));
+        let root = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./"))
+            .unwrap();
+        assert_eq!(
+            root["subjectOf"],
+            json!({"@id": "mycrate-ro-crate-metadata.json"})
+        );
+        assert!(result
+            .origins
+            .iter()
+            .any(|o| o.id == "mycrate-ro-crate-metadata.json"
+                && o.original_id == "ro-crate-metadata.json"));
+
+        let options = ConsolidateOptions {
+            descriptor_id: Some("out/ro-crate-metadata.json".to_string()),
+            ..ConsolidateOptions::default()
+        };
+        let err = consolidate(
+            ConsolidateInput::Single(sample_root_graph()),
+            &NoOpLoader,
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), "invalid_descriptor_id");
+    }
+
+    #[test]
+    fn test_prefixed_subcrate_descriptor() {
+        let input = || ConsolidateInput::Merge {
+            main: sample_root_graph(),
+            others: vec![MergeCrate {
+                graph: vec![
+                    json!({
+                        "@id": "sub-ro-crate-metadata.json",
+                        "@type": "CreativeWork",
+                        "about": {"@id": "./"}
+                    }),
+                    json!({
+                        "@id": "./",
+                        "@type": "Dataset",
+                        "hasPart": [{"@id": "./data.csv"}, {"@id": "sub-ro-crate-metadata.json"}]
+                    }),
+                    json!({
+                        "@id": "./data.csv",
+                        "@type": "File",
+                        "mentions": {"@id": "sub-ro-crate-metadata.json"}
+                    }),
+                    json!({
+                        "@id": "https://orcid.org/0000-0001",
+                        "@type": "Person",
+                        "subjectOf": {"@id": "sub-ro-crate-metadata.json"}
+                    }),
+                ],
+                folder_id: "./sub/".to_string(),
+                name: None,
+            }],
+        };
+        let find = |graph: &[Value], id: &str| -> Value {
+            graph
+                .iter()
+                .find(|e| extract_id(e) == Some(id))
+                .cloned()
+                .unwrap()
+        };
+
+        // Dropped by default, along with every reference to it
+        let result = consolidate(input(), &NoOpLoader, &ConsolidateOptions::default()).unwrap();
+        assert!(!result
+            .graph
+            .iter()
+            .any(|e| extract_id(e).is_some_and(|id| id.contains("sub-ro-crate"))));
+        assert!(find(&result.graph, "./sub/data.csv")
+            .get("mentions")
+            .is_none());
+        assert_eq!(
+            find(&result.graph, "./sub/")["hasPart"],
+            json!([{"@id": "./sub/data.csv"}])
+        );
+        assert!(find(&result.graph, "https://orcid.org/0000-0001")
+            .get("subjectOf")
+            .is_none());
+
+        // Kept as a file of the folder, with references following it
+        let options = ConsolidateOptions {
+            keep_subcrate_descriptors: true,
+            ..ConsolidateOptions::default()
+        };
+        let result = consolidate(input(), &NoOpLoader, &options).unwrap();
+        let kept_id = "./sub/sub-ro-crate-metadata.json";
+        assert_eq!(
+            find(&result.graph, kept_id)["about"],
+            json!({"@id": "./sub/"})
+        );
+        assert_eq!(
+            find(&result.graph, "./sub/data.csv")["mentions"],
+            json!({"@id": kept_id})
+        );
+        assert_eq!(
+            find(&result.graph, "https://orcid.org/0000-0001")["subjectOf"],
+            json!({"@id": kept_id})
+        );
+        assert_eq!(result.graph[0]["@id"], "ro-crate-metadata.json");
+    }
+
+    /// Loader serving a fixed graph for each subcrate id
+    struct MapLoader(HashMap<String, Vec<Value>>);
+
+    impl SubcrateLoader for MapLoader {
+        fn load(
+            &self,
+            subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            self.0
+                .get(subcrate_id)
+                .cloned()
+                .ok_or_else(|| ConsolidateError::LoadError {
+                    path: subcrate_id.to_string(),
+                    reason: "unknown subcrate".to_string(),
+                    source: None,
+                })
+        }
+    }
+
+    #[test]
+    fn test_subcrate_namespace_conflict() {
+        let subcrate = |name: &str| {
+            vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "name": name}),
+                json!({"@id": "./result.csv", "@type": "File"}),
+            ]
+        };
+        let reference = |id: &str| {
+            json!({
+                "@id": id,
+                "@type": "Dataset",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            })
+        };
+        let first = "https://one.example.org/run/data/";
+        let second = "https://two.example.org/run/data/";
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "hasPart": [{"@id": first}, {"@id": second}, {"@id": "./data/"}]
+            }),
+            reference(first),
+            reference(second),
+            reference("./data/"),
+        ];
+        let loader = MapLoader(HashMap::from([
+            (first.to_string(), subcrate("First")),
+            (second.to_string(), subcrate("Second")),
+            ("./data/".to_string(), subcrate("Local")),
+        ]));
+
+        let result = consolidate(
+            ConsolidateInput::Single(root),
+            &loader,
+            &ConsolidateOptions::default(),
+        )
+        .unwrap();
+        let ids: HashSet<&str> = result.graph.iter().filter_map(extract_id).collect();
+        // The first crate keeps the natural namespace, later ones are qualified
+        assert!(ids.contains("./data/result.csv"));
+        assert!(ids.contains("./run/data/result.csv"));
+        assert!(ids.contains("./data-2/result.csv"));
+        assert_eq!(
+            result
+                .warnings
+                .iter()
+                .filter(|w| w.contains("already used"))
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_flatten_only() {
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "a.txt"}, {"@id": "exp/"}], "mentions": {"@id": "exp/"}}),
+            json!({"@id": "a.txt", "@type": "File"}),
+            crate_ref("exp/"),
+            json!({"@id": "https://example.org/review", "@type": "Review", "about": [{"@id": "./"}, {"@id": "exp/"}]}),
+        ];
+        let loader = MapLoader(HashMap::from([
+            (
+                "exp/".to_string(),
+                vec![
+                    json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                    json!({"@id": "./", "@type": "Dataset", "name": "Exp", "hasPart": [{"@id": "b.txt"}, {"@id": "sub/"}]}),
+                    json!({"@id": "b.txt", "@type": "File"}),
+                    crate_ref("sub/"),
+                ],
+            ),
+            (
+                "sub/".to_string(),
+                vec![
+                    json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                    json!({"@id": "./", "@type": "Dataset", "hasPart": {"@id": "c.txt"}}),
+                    json!({"@id": "c.txt", "@type": "File", "isPartOf": {"@id": "./"}}),
+                ],
+            ),
+        ]));
+        let options = ConsolidateOptions {
+            flatten_only: true,
+            extend_context: false,
+            record_provenance: true,
+            ..Default::default()
+        };
+        let result = consolidate(ConsolidateInput::Single(root), &loader, &options).unwrap();
+
+        assert_eq!(result.stats.crates_consolidated, 3);
+        let ids: Vec<&str> = result.graph.iter().filter_map(extract_id).collect();
+        assert_eq!(
+            ids,
+            [
+                "ro-crate-metadata.json",
+                "./",
+                "a.txt",
+                "./exp/b.txt",
+                "./exp/sub/c.txt",
+                "https://example.org/review",
+                CONSOLIDATION_ACTION_ID,
+                CONSOLIDATION_SOFTWARE_ID,
+            ]
+        );
+        assert_eq!(
+            result.graph[1]["hasPart"],
+            json!([{"@id": "a.txt"}, {"@id": "./exp/b.txt"}, {"@id": "./exp/sub/c.txt"}])
+        );
+        assert_eq!(
+            result.context,
+            json!("https://w3id.org/ro/crate/1.1/context")
+        );
+        let json = serde_json::to_string(&result.graph).unwrap();
+        assert!(!json.contains("Subcrate"));
+
+        // Other references to subcrates point at the root their parts joined
+        assert!(!json.contains(r#""exp/""#) && !json.contains(r#""./exp/sub/""#));
+        assert_eq!(
+            result.graph[1]["mentions"],
+            json!({"@id": CONSOLIDATION_ACTION_ID})
+        );
+        assert_eq!(result.graph[4]["isPartOf"], json!({"@id": "./"}));
+        assert_eq!(result.graph[5]["about"], json!([{"@id": "./"}]));
+        assert_eq!(result.graph[6]["result"], json!([{"@id": "./"}]));
+    }
+
+    #[test]
+    fn test_link_parents() {
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": {"@id": "exp/"}}),
+            crate_ref("exp/"),
+        ];
+        let subcrate = |parts: Vec<Value>| {
+            let mut graph = vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset"}),
+            ];
+            graph.extend(parts);
+            graph
+        };
+        let loader = MapLoader(HashMap::from([
+            ("exp/".to_string(), subcrate(vec![crate_ref("sub/")])),
+            ("sub/".to_string(), subcrate(vec![])),
+        ]));
+        let options = ConsolidateOptions {
+            link_parents: true,
+            ..Default::default()
+        };
+        let result = consolidate(ConsolidateInput::Single(root), &loader, &options).unwrap();
+
+        let parents: HashMap<&str, &Value> = result
+            .graph
+            .iter()
+            .filter(|e| crate::collect::has_type(e, "Subcrate"))
+            .map(|e| (extract_id(e).unwrap(), &e["isPartOf"]))
+            .collect();
+        assert_eq!(parents.len(), 2);
+        assert_eq!(parents["exp/"], &json!({"@id": "./"}));
+        assert_eq!(parents["./exp/sub/"], &json!({"@id": "exp/"}));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": {"@id": "exp/"}}),
+            crate_ref("exp/"),
+        ];
+        let subcrate = |parts: Vec<Value>| {
+            let mut graph = vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "hasPart": parts.iter().map(|p| json!({"@id": p["@id"]})).collect::<Vec<_>>()}),
+            ];
+            graph.extend(parts);
+            graph
+        };
+        let loader = MapLoader(HashMap::from([
+            ("exp/".to_string(), subcrate(vec![crate_ref("sub/")])),
+            ("sub/".to_string(), subcrate(vec![crate_ref("deep/")])),
+            ("deep/".to_string(), subcrate(vec![])),
+        ]));
+        let run = |max_depth| {
+            let options = ConsolidateOptions {
+                max_depth,
+                ..Default::default()
+            };
+            consolidate(ConsolidateInput::Single(root.clone()), &loader, &options).unwrap()
+        };
+
+        assert_eq!(run(None).stats.crates_consolidated, 4);
+        let result = run(Some(1));
+        assert_eq!(result.stats.crates_consolidated, 2);
+        // The reference below the limit stays a plain Dataset in its parent's folder
+        let sub = result
+            .graph
+            .iter()
+            .find(|e| e["@id"] == "./exp/sub/")
+            .unwrap();
+        assert_eq!(sub["@type"], "Dataset");
+        assert_eq!(
+            sub["conformsTo"],
+            json!({"@id": "https://w3id.org/ro/crate"})
+        );
+        assert_eq!(run(Some(0)).stats.crates_consolidated, 1);
+    }
+
+    #[test]
+    fn test_subcrate_filters() {
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "experiments/a/"}, {"@id": "experiments/b/"}, {"@id": "raw-data/"}]}),
+            crate_ref("experiments/a/"),
+            crate_ref("experiments/b/"),
+            crate_ref("raw-data/"),
+        ];
+        let subcrate = || {
+            vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset"}),
+            ]
+        };
+        let loader = MapLoader(HashMap::from([
+            ("experiments/a/".to_string(), subcrate()),
+            ("experiments/b/".to_string(), subcrate()),
+            ("raw-data/".to_string(), subcrate()),
+        ]));
+        let run = |include: &[&str], exclude: &[&str]| {
+            let options = ConsolidateOptions {
+                include_subcrates: include.iter().map(|p| p.to_string()).collect(),
+                exclude_subcrates: exclude.iter().map(|p| p.to_string()).collect(),
+                ..Default::default()
+            };
+            consolidate(ConsolidateInput::Single(root.clone()), &loader, &options)
+        };
+        let folders = |result: ConsolidateResult| {
+            let mut ids: Vec<_> = result
+                .graph
+                .iter()
+                .filter(|e| crate::collect::has_type(e, "Subcrate"))
+                .map(|e| e["@id"].as_str().unwrap().to_string())
+                .collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(folders(run(&[], &[]).unwrap()).len(), 3);
+        assert_eq!(
+            folders(run(&["./experiments/*"], &[]).unwrap()),
+            ["experiments/a/", "experiments/b/"]
+        );
+        assert_eq!(
+            folders(run(&["experiments/*"], &["./experiments/b"]).unwrap()),
+            ["experiments/a/"]
+        );
+        let result = run(&[], &["./raw-data/"]).unwrap();
+        let raw = result
+            .graph
+            .iter()
+            .find(|e| e["@id"] == "raw-data/")
+            .unwrap();
+        assert_eq!(raw["@type"], "Dataset");
+        assert_eq!(folders(result).len(), 2);
+        assert!(matches!(
+            run(&["./[x"], &[]),
+            Err(ConsolidateError::InvalidStructure(_))
+        ));
+    }
+
+    #[test]
+    fn test_subcrate_overrides() {
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let alice = |name: &str| json!({"@id": "https://orcid.org/0000-0001", "@type": "Person", "name": name, "email": "alice@example.org"});
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "exp/"}, {"@id": "instrument/"}]}),
+            crate_ref("exp/"),
+            crate_ref("instrument/"),
+        ];
+        let subcrate = |parts: Vec<Value>| {
+            let mut graph = vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "author": {"@id": "https://orcid.org/0000-0001"}, "hasPart": [{"@id": "raw/"}, {"@id": "log.txt"}]}),
+            ];
+            graph.extend(parts);
+            graph
+        };
+        let loader = MapLoader(HashMap::from([
+            (
+                "exp/".to_string(),
+                subcrate(vec![alice("Alice"), crate_ref("raw/")]),
+            ),
+            (
+                "instrument/".to_string(),
+                subcrate(vec![
+                    alice("A. Smith"),
+                    crate_ref("raw/"),
+                    json!({"@id": "log.txt", "@type": "File", "variableMeasured": {"@id": "#calibration"}}),
+                    json!({"@id": "#calibration", "@type": "PropertyValue", "value": 3}),
+                ]),
+            ),
+            ("raw/".to_string(), subcrate(vec![])),
+        ]));
+        let run = |overrides: BTreeMap<String, SubcrateOverrides>| {
+            let options = ConsolidateOptions {
+                conflict_strategy: ConflictStrategy::Error,
+                subcrate_overrides: overrides,
+                ..Default::default()
+            };
+            consolidate(ConsolidateInput::Single(root.clone()), &loader, &options)
+        };
+
+        assert_eq!(run(BTreeMap::new()).unwrap_err().code(), "merge_conflict");
+        let overrides = SubcrateOverrides {
+            conflict_strategy: Some(ConflictStrategy::PreferMain),
+            strip_properties: vec!["email".to_string()],
+            exclude_types: vec!["PropertyValue".to_string()],
+            max_depth: Some(0),
+        };
+        let result = run(BTreeMap::from([("./instrument/".to_string(), overrides)])).unwrap();
+        let entity = |id: &str| result.graph.iter().find(|e| e["@id"] == id);
+        // Crates before the instrument's keep their values and properties
+        assert_eq!(
+            entity("https://orcid.org/0000-0001").unwrap()["name"],
+            "Alice"
+        );
+        assert!(entity("https://orcid.org/0000-0001").unwrap()["email"].is_string());
+        assert!(!result.graph.iter().any(|e| e["@type"] == "PropertyValue"));
+        assert_eq!(
+            entity("./instrument/log.txt").unwrap(),
+            &json!({"@id": "./instrument/log.txt", "@type": "File"})
+        );
+        // Only the instrument's subcrates are left references
+        assert_eq!(result.stats.crates_consolidated, 4);
+        assert_eq!(entity("./instrument/raw/").unwrap()["@type"], "Dataset");
+        assert!(result
+            .graph
+            .iter()
+            .any(|e| e["@id"] == "./exp/raw/" && crate::collect::has_type(e, "Subcrate")));
+
+        let invalid = BTreeMap::from([("[x".to_string(), SubcrateOverrides::default())]);
+        assert_eq!(run(invalid).unwrap_err().code(), "invalid_structure");
+    }
+
+    #[test]
+    fn test_namespace_map() {
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "raw_data_v2/"}, {"@id": "exp/"}]}),
+            crate_ref("raw_data_v2/"),
+            crate_ref("exp/"),
+        ];
+        let subcrate = |parts: &[&str]| {
+            let mut graph = vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "hasPart": parts.iter().map(|id| json!({"@id": id})).collect::<Vec<_>>()}),
+            ];
+            for id in parts {
+                graph.push(if id.ends_with('/') {
+                    crate_ref(id)
+                } else {
+                    json!({"@id": id, "@type": "File"})
+                });
+            }
+            graph
+        };
+        // Records the namespaces subcrates are loaded from
+        struct Recording(MapLoader, Mutex<Vec<String>>);
+        impl SubcrateLoader for Recording {
+            fn load(
+                &self,
+                subcrate_id: &str,
+                parent_namespace: &str,
+                subcrate_entity: Option<&Value>,
+            ) -> Result<Vec<Value>, ConsolidateError> {
+                let loaded = format!("{}:{}", parent_namespace, subcrate_id);
+                self.1.lock().unwrap().push(loaded);
+                self.0.load(subcrate_id, parent_namespace, subcrate_entity)
+            }
+        }
+        let loader = Recording(
+            MapLoader(HashMap::from([
+                ("raw_data_v2/".to_string(), subcrate(&["data.csv", "old/"])),
+                ("exp/".to_string(), subcrate(&["old/"])),
+                ("old/".to_string(), subcrate(&["log.txt"])),
+            ])),
+            Mutex::new(Vec::new()),
+        );
+        let run = |namespace_map: &[(&str, &str)], exclude: &[&str]| {
+            let options = ConsolidateOptions {
+                namespace_map: namespace_map
+                    .iter()
+                    .map(|(from, to)| (from.to_string(), to.to_string()))
+                    .collect(),
+                exclude_subcrates: exclude.iter().map(|p| p.to_string()).collect(),
+                ..Default::default()
+            };
+            consolidate(ConsolidateInput::Single(root.clone()), &loader, &options)
+        };
+
+        let result = run(
+            &[("./raw_data_v2/", "raw"), ("exp/old", "./logs/exp/")],
+            &[],
+        )
+        .unwrap();
+        let ids: Vec<&str> = result.graph.iter().filter_map(extract_id).collect();
+        assert!(!ids.iter().any(|id| id.contains("raw_data_v2")));
+        // Loaders still find subcrates where they came from
+        let mut loaded = loader.1.lock().unwrap().clone();
+        loaded.sort();
+        assert_eq!(
+            loaded,
+            [":exp/", ":raw_data_v2/", "exp:old/", "raw_data_v2:old/"]
+        );
+        let entity = |id: &str| result.graph.iter().find(|e| e["@id"] == id).unwrap();
+        let root = entity("./");
+        assert!(root["hasPart"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({"@id": "./raw/"})));
+        let folder = |id: &str| {
+            let is_folder = |e: &&Value| e["@id"] == id && crate::collect::has_type(e, "Subcrate");
+            result.graph.iter().find(is_folder).unwrap()
+        };
+        let raw = folder("./raw/");
+        assert!(raw["hasPart"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({"@id": "./raw/old/"})));
+        let contained = raw[CONSOLIDATED_ENTITIES_SHORT].as_array().unwrap();
+        assert!(contained.contains(&json!({"@id": "./raw/data.csv"})));
+        // Nested subcrates move along with their parent unless mapped themselves
+        assert!(entity("./raw/old/log.txt")["@type"] == "File");
+        assert!(entity("./logs/exp/log.txt")["@type"] == "File");
+        assert!(folder("exp/")["hasPart"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({"@id": "./logs/exp/"})));
+
+        // Patterns match the renamed folders
+        let result = run(&[("raw_data_v2", "raw")], &["./raw/"]).unwrap();
+        assert!(!result
+            .graph
+            .iter()
+            .any(|e| e["@id"].as_str().unwrap().starts_with("./raw/")));
+
+        for invalid in ["", "./", "../raw", "ro-crate-metadata.json"] {
+            let err = run(&[("raw_data_v2", invalid)], &[]).unwrap_err();
+            assert_eq!(err.code(), "invalid_structure");
+        }
+    }
+
+    #[test]
+    fn test_offline() {
+        let mut root = sample_root_graph();
+        root.push(json!({
+            "@id": "./exp/",
+            "@type": "Dataset",
+            "conformsTo": {"@id": "https://w3id.org/ro/crate"}
+        }));
+        let loader = UrlLoader::from_metadata_url("http://127.0.0.1:9/ro-crate-metadata.json");
+        let options = ConsolidateOptions {
+            offline: true,
+            ..Default::default()
+        };
+        // Fails even though not strict, and without trying to connect
+        let err = consolidate(ConsolidateInput::Single(root), &loader, &options).unwrap_err();
+        assert_eq!(err.code(), "network_disabled");
+        assert_eq!(err.subcrate_path(), vec!["./exp/"]);
+        assert!(!is_offline());
+
+        // Crates that need no fetching consolidate as usual
+        let result = consolidate(
+            ConsolidateInput::Single(sample_root_graph()),
+            &loader,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(result.stats.crates_consolidated, 1);
     }
 
-    Ok(())
-}
+    #[test]
+    fn test_resource_limits() {
+        let mut root = sample_root_graph();
+        root.push(json!({
+            "@id": "./exp/",
+            "@type": "Dataset",
+            "conformsTo": {"@id": "https://w3id.org/ro/crate"}
+        }));
+        let exp = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset"}),
+            json!({"@id": "./a.csv", "@type": "File"}),
+            json!({"@id": "./b.csv", "@type": "File"}),
+        ];
+        let loader = MapLoader(HashMap::from([("./exp/".to_string(), exp)]));
+        let run = |options: ConsolidateOptions| {
+            consolidate(ConsolidateInput::Single(root.clone()), &loader, &options)
+        };
 
-/// Parse @graph from JSON content
-pub fn parse_graph(content: &str, source: &str) -> Result<Vec<Value>, ConsolidateError> {
-    let doc: Value = serde_json::from_str(content)?;
+        assert!(run(ConsolidateOptions {
+            max_crate_entities: Some(5),
+            max_total_entities: Some(9),
+            max_metadata_bytes: Some(1000),
+            ..Default::default()
+        })
+        .is_ok());
 
-    match doc.get("@graph") {
-        Some(Value::Array(arr)) => Ok(arr.clone()),
-        Some(_) => Err(ConsolidateError::InvalidStructure(
-            "@graph is not an array".to_string(),
-        )),
-        None => Err(ConsolidateError::InvalidStructure(format!(
-            "No @graph found in {}",
-            source
-        ))),
-    }
-}
+        let err = run(ConsolidateOptions {
+            max_total_entities: Some(8),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert_eq!(err.code(), "limit_exceeded");
+        assert_eq!(err.subcrate_path(), vec!["./exp/"]);
 
-/// Build a complete RO-Crate JSON-LD document from consolidation result
-pub fn to_jsonld(result: &ConsolidateResult) -> Value {
-    json!({
-        "@context": result.context,
-        "@graph": result.graph
-    })
-}
+        let err = run(ConsolidateOptions {
+            max_crate_entities: Some(4),
+            ..Default::default()
+        })
+        .unwrap_err();
+        assert!(err.subcrate_path().is_empty());
+        assert!(err.to_string().contains("crate has 5 entities"));
 
-/// Serialize consolidation result to JSON string
-pub fn to_json_string(
-    result: &ConsolidateResult,
-    pretty: bool,
-) -> Result<String, ConsolidateError> {
-    let doc = to_jsonld(result);
-    if pretty {
-        Ok(serde_json::to_string_pretty(&doc)?)
-    } else {
-        Ok(serde_json::to_string(&doc)?)
+        // Documents are measured as loaders read them
+        struct FileLoader(std::path::PathBuf);
+        impl SubcrateLoader for FileLoader {
+            fn load(
+                &self,
+                _subcrate_id: &str,
+                _parent_namespace: &str,
+                _subcrate_entity: Option<&Value>,
+            ) -> Result<Vec<Value>, ConsolidateError> {
+                let content = crate::audit::read_file(&self.0)?;
+                let document: Value = serde_json::from_str(&content)?;
+                Ok(document["@graph"].as_array().cloned().unwrap_or_default())
+            }
+        }
+        let path = std::env::temp_dir().join(format!("limits-{}.json", std::process::id()));
+        let document = json!({"@graph": loader.0["./exp/"]});
+        std::fs::write(&path, serde_json::to_string(&document).unwrap()).unwrap();
+        let loader = FileLoader(path.clone());
+        let run = |options: ConsolidateOptions| {
+            consolidate(ConsolidateInput::Single(root.clone()), &loader, &options)
+        };
+        assert!(run(ConsolidateOptions {
+            max_metadata_bytes: Some(1000),
+            ..Default::default()
+        })
+        .is_ok());
+        let err = run(ConsolidateOptions {
+            max_metadata_bytes: Some(100),
+            ..Default::default()
+        })
+        .unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.code(), "limit_exceeded");
+        assert_eq!(err.subcrate_path(), vec!["./exp/"]);
+        assert!(err.root_cause().to_string().contains("limit of 100 bytes"));
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
-    fn sample_root_graph() -> Vec<Value> {
-        vec![
-            json!({
-                "@id": "ro-crate-metadata.json",
-                "@type": "CreativeWork",
-                "about": {"@id": "./"},
-                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
-            }),
+    #[test]
+    fn test_root_handling() {
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": {"@id": "exp/"}}),
             json!({
-                "@id": "./",
+                "@id": "exp/",
                 "@type": "Dataset",
-                "name": "Root Crate",
-                "hasPart": [{"@id": "./data.csv"}]
-            }),
-            json!({
-                "@id": "./data.csv",
-                "@type": "File",
-                "name": "Data file"
+                "description": "Experiments, as the parent sees them",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate"},
+                "subjectOf": {"@id": "exp/ro-crate-metadata.json"}
             }),
+        ];
+        let exp = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
             json!({
-                "@id": "https://orcid.org/0000-0001",
-                "@type": "Person",
-                "name": "Alice"
+                "@id": "./",
+                "@type": ["Dataset", "LabProcess"],
+                "name": "Experiments",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
             }),
-        ]
+        ];
+        let folder = |root_handling| {
+            let loader = MapLoader(HashMap::from([("exp/".to_string(), exp.clone())]));
+            let options = ConsolidateOptions {
+                root_handling,
+                ..Default::default()
+            };
+            let result =
+                consolidate(ConsolidateInput::Single(root.clone()), &loader, &options).unwrap();
+            // The parent's reference doesn't stay behind as a second entity
+            let mut ids: Vec<_> = result.graph.iter().filter_map(extract_id).collect();
+            let count = ids.len();
+            ids.sort_unstable();
+            ids.dedup();
+            assert_eq!(ids.len(), count, "{:?}", root_handling);
+            result
+                .graph
+                .into_iter()
+                .find(|e| extract_id(e) == Some("exp/"))
+                .unwrap()
+        };
+
+        let merged = folder(RootHandling::MergeReference);
+        assert_eq!(
+            merged["description"],
+            "Experiments, as the parent sees them"
+        );
+
+        let preserved = folder(RootHandling::PreserveRoot);
+        assert_eq!(preserved["name"], "Experiments");
+        assert_eq!(
+            preserved["@type"],
+            json!(["Dataset", "Subcrate", "LabProcess"])
+        );
+        for property in ["description", "conformsTo", "subjectOf"] {
+            assert!(preserved.get(property).is_none(), "{}", property);
+        }
     }
 
     #[test]
-    fn test_consolidate_single_no_subcrates() {
-        let graph = sample_root_graph();
+    fn test_load_ref_sees_parent() {
+        /// Records the parent's name of every subcrate it loads
+        struct ParentLoader(MapLoader, Mutex<Vec<(String, Value)>>);
+
+        impl SubcrateLoader for ParentLoader {
+            fn load(
+                &self,
+                _subcrate_id: &str,
+                _parent_namespace: &str,
+                _subcrate_entity: Option<&Value>,
+            ) -> Result<Vec<Value>, ConsolidateError> {
+                unreachable!("consolidation loads through load_ref")
+            }
+
+            fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+                assert!(subcrate.parent_descriptor.is_some());
+                assert_eq!(subcrate.entity.unwrap()["@id"], subcrate.id);
+                let parent = subcrate.parent_root.unwrap()["name"].clone();
+                self.1
+                    .lock()
+                    .unwrap()
+                    .push((subcrate.id.to_string(), parent));
+                self.0.load(subcrate.id, subcrate.parent_namespace, None)
+            }
+        }
+
+        let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}});
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "name": "Root"}),
+            crate_ref("exp/"),
+        ];
+        let exp = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "name": "Exp"}),
+            crate_ref("sub/"),
+        ];
+        let sub = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset"}),
+        ];
+        let loader = ParentLoader(
+            MapLoader(HashMap::from([
+                ("exp/".to_string(), exp),
+                ("sub/".to_string(), sub),
+            ])),
+            Mutex::new(Vec::new()),
+        );
+        consolidate(
+            ConsolidateInput::Single(root),
+            &loader,
+            &ConsolidateOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            loader.1.into_inner().unwrap(),
+            [
+                ("exp/".to_string(), json!("Root")),
+                ("sub/".to_string(), json!("Exp"))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listed_subcrates() {
+        /// Lists "./listed/" below the root, like a manifest would
+        struct ListingLoader(MapLoader);
+
+        impl SubcrateLoader for ListingLoader {
+            fn load(
+                &self,
+                subcrate_id: &str,
+                parent_namespace: &str,
+                subcrate_entity: Option<&Value>,
+            ) -> Result<Vec<Value>, ConsolidateError> {
+                self.0.load(subcrate_id, parent_namespace, subcrate_entity)
+            }
+
+            fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+                match namespace {
+                    "" => vec!["./listed/".to_string()],
+                    _ => vec![],
+                }
+            }
+        }
+
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "./listed/"}]}),
+            // Not marked as a crate
+            json!({"@id": "./listed/", "@type": "Dataset", "name": "Listed"}),
+        ];
+        let loader = ListingLoader(MapLoader(HashMap::from([(
+            "./listed/".to_string(),
+            vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset"}),
+                json!({"@id": "./result.csv", "@type": "File"}),
+            ],
+        )])));
+
         let result = consolidate(
-            ConsolidateInput::Single(graph),
-            &NoOpLoader,
+            ConsolidateInput::Single(root),
+            &loader,
             &ConsolidateOptions::default(),
         )
         .unwrap();
+        assert_eq!(result.stats.crates_consolidated, 2);
+        let folder = result
+            .graph
+            .iter()
+            .find(|e| e["@id"] == "./listed/" && e.get("consolidatedEntities").is_some())
+            .unwrap();
+        assert_eq!(folder["name"], "Listed");
+        assert_eq!(
+            folder["consolidatedEntities"],
+            json!([{"@id": "./listed/result.csv"}])
+        );
+    }
+
+    #[test]
+    fn test_manifest_loader() {
+        let manifest = json!({
+            "subcrates": [
+                "experiments/",
+                "https://example.org/crate/experiments/run1/ro-crate-metadata.json",
+                "./experiments/",
+                "https://other.example.org/data/",
+                "https://example.org/crate/"
+            ]
+        });
+        let loader = ManifestLoader::from_manifest(
+            "https://example.org/crate/ro-crate-metadata.json",
+            &manifest.to_string(),
+        )
+        .unwrap();
+        // Duplicates and the root itself are dropped
+        assert_eq!(loader.len(), 3);
+        assert_eq!(
+            loader.listed_subcrates(""),
+            vec!["./experiments/", "https://other.example.org/data/"]
+        );
+        // Nested crates belong to the closest listed crate containing them
+        assert_eq!(loader.listed_subcrates("experiments"), vec!["./run1/"]);
+        assert!(loader.listed_subcrates("experiments/run1").is_empty());
 
-        assert_eq!(result.stats.crates_consolidated, 1);
-        assert!(result.graph.len() >= 4);
+        // A bare array works as well
+        let loader =
+            ManifestLoader::from_manifest("https://example.org/crate/", r#"["a/", "a/b/"]"#)
+                .unwrap();
+        assert_eq!(loader.listed_subcrates("a"), vec!["./b/"]);
 
-        // Check root entity is present
+        for manifest in [r#"{"crates": []}"#, r#"{"subcrates": [1]}"#] {
+            let result = ManifestLoader::from_manifest("https://example.org/", manifest);
+            assert_eq!(result.err().unwrap().code(), "invalid_structure");
+        }
+    }
+
+    #[test]
+    fn test_case_collision() {
+        let reference = |id: &str| {
+            json!({
+                "@id": id,
+                "@type": "Dataset",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            })
+        };
+        let subcrate = || {
+            vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset"}),
+                json!({"@id": "./result.csv", "@type": "File"}),
+            ]
+        };
+        let root = || {
+            ConsolidateInput::Single(vec![
+                sample_root_graph()[0].clone(),
+                json!({
+                    "@id": "./",
+                    "@type": "Dataset",
+                    "hasPart": [{"@id": "./Data/"}, {"@id": "./data/"}]
+                }),
+                reference("./Data/"),
+                reference("./data/"),
+            ])
+        };
+        let loader = MapLoader(HashMap::from([
+            ("./Data/".to_string(), subcrate()),
+            ("./data/".to_string(), subcrate()),
+        ]));
+        let consolidate_with = |policy| {
+            let options = ConsolidateOptions {
+                case_collision_policy: policy,
+                ..ConsolidateOptions::default()
+            };
+            consolidate(root(), &loader, &options).unwrap()
+        };
+        let ids = |result: &ConsolidateResult| -> HashSet<String> {
+            result
+                .graph
+                .iter()
+                .filter_map(|e| extract_id(e).map(String::from))
+                .collect()
+        };
+
+        let result = consolidate_with(CaseCollisionPolicy::Warn);
+        assert!(ids(&result).contains("./Data/result.csv"));
+        assert!(ids(&result).contains("./data/result.csv"));
+        assert_eq!(result.warnings.len(), 1);
+        assert!(result.warnings[0].contains("case-insensitive"));
+
+        // The second folder moves, and the root's reference follows it
+        let result = consolidate_with(CaseCollisionPolicy::Rename);
+        assert!(ids(&result).contains("./Data/result.csv"));
+        assert!(ids(&result).contains("./data-2/result.csv"));
+        assert!(ids(&result).contains("./data-2/"));
+        assert!(!ids(&result).contains("./data/"));
         let root = result
             .graph
             .iter()
             .find(|e| extract_id(e) == Some("./"))
             .unwrap();
-        assert_eq!(root.get("name"), Some(&json!("Root Crate")));
+        assert_eq!(
+            root["hasPart"],
+            json!([{"@id": "./Data/"}, {"@id": "./data-2/"}])
+        );
+
+        let result = consolidate_with(CaseCollisionPolicy::Allow);
+        assert!(result.warnings.is_empty());
     }
 
     #[test]
-    fn test_consolidate_merge_two_crates() {
-        let main = sample_root_graph();
-        let other = vec![
-            json!({
-                "@id": "ro-crate-metadata.json",
-                "@type": "CreativeWork",
-                "about": {"@id": "./"}
-            }),
+    fn test_differs_only_in_case() {
+        assert!(differs_only_in_case("Data", "data"));
+        assert!(differs_only_in_case("Data/raw", "data"));
+        assert!(differs_only_in_case("x/\u{c4}pfel", "x/\u{e4}pfel"));
+        assert!(!differs_only_in_case("data", "data"));
+        assert!(!differs_only_in_case("data/Raw", "data/raw2"));
+        assert!(!differs_only_in_case("data", "database"));
+    }
+
+    #[test]
+    fn test_reserved_subcrate_namespace() {
+        let folder = "./ro-crate-metadata.json/";
+        let root = vec![
+            sample_root_graph()[0].clone(),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": folder}]}),
             json!({
-                "@id": "./",
+                "@id": folder,
                 "@type": "Dataset",
-                "name": "Other Crate",
-                "description": "Imported data"
-            }),
-            json!({
-                "@id": "./results.csv",
-                "@type": "File"
-            }),
-            json!({
-                "@id": "https://orcid.org/0000-0001",
-                "@type": "Person",
-                "name": "Alice Smith"  // Different name for same person
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
             }),
         ];
+        let loader = MapLoader(HashMap::from([(
+            folder.to_string(),
+            vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset"}),
+            ],
+        )]));
 
-        let result = consolidate(
+        let err = consolidate(
+            ConsolidateInput::Single(root),
+            &loader,
+            &ConsolidateOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.code(), "reserved_namespace");
+        assert_eq!(err.subcrate_path(), vec![folder]);
+    }
+
+    #[test]
+    fn test_unicode_normalization() {
+        let decomposed = "https://example.org/Jose\u{301}";
+        let precomposed = "https://example.org/Jos\u{e9}";
+        let input = || {
+            let mut main = sample_root_graph();
+            main.push(json!({"@id": precomposed, "@type": "Person", "name": "A"}));
             ConsolidateInput::Merge {
                 main,
                 others: vec![MergeCrate {
-                    graph: other,
-                    folder_id: "./imported/".to_string(),
-                    name: Some("Imported Dataset".to_string()),
+                    graph: vec![
+                        json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                        json!({"@id": "./", "@type": "Dataset", "author": {"@id": decomposed}}),
+                        json!({"@id": decomposed, "@type": "Person", "email": "a@example.org"}),
+                    ],
+                    folder_id: "./Re\u{301}sume\u{301}/".to_string(),
+                    name: None,
                 }],
-            },
-            &NoOpLoader,
-            &ConsolidateOptions::default(),
-        )
-        .unwrap();
+            }
+        };
+        let ids = |graph: &[Value]| -> Vec<String> {
+            graph
+                .iter()
+                .filter_map(|e| extract_id(e).map(String::from))
+                .collect()
+        };
 
-        assert_eq!(result.stats.crates_consolidated, 2);
+        // Both spellings merge into one entity, and the folder is precomposed
+        let result = consolidate(input(), &NoOpLoader, &ConsolidateOptions::default()).unwrap();
+        let graph_ids = ids(&result.graph);
+        assert_eq!(graph_ids.iter().filter(|id| *id == precomposed).count(), 1);
+        assert!(!graph_ids.iter().any(|id| id == decomposed));
+        assert!(graph_ids.iter().any(|id| id == "./R\u{e9}sum\u{e9}/"));
 
-        // Check subcrate folder was created
+        let options = ConsolidateOptions {
+            normalize_unicode: false,
+            ..ConsolidateOptions::default()
+        };
+        let result = consolidate(input(), &NoOpLoader, &options).unwrap();
+        let graph_ids = ids(&result.graph);
+        assert!(graph_ids.iter().any(|id| id == precomposed));
+        assert!(graph_ids.iter().any(|id| id == decomposed));
+    }
+
+    #[test]
+    fn test_missing_subcrate_descriptor() {
+        let merge = |policy| {
+            consolidate(
+                ConsolidateInput::Merge {
+                    main: sample_root_graph(),
+                    others: vec![MergeCrate {
+                        graph: vec![
+                            json!({"@id": "./", "@type": "Dataset", "name": "Bare"}),
+                            json!({"@id": "./bare.csv", "@type": "File"}),
+                        ],
+                        folder_id: "./bare/".to_string(),
+                        name: None,
+                    }],
+                },
+                &NoOpLoader,
+                &ConsolidateOptions {
+                    missing_descriptor_policy: policy,
+                    ..ConsolidateOptions::default()
+                },
+            )
+        };
+
+        let result = merge(MissingDescriptorPolicy::Synthesize).unwrap();
         let folder = result
             .graph
             .iter()
-            .find(|e| extract_id(e) == Some("./imported/"))
+            .find(|e| extract_id(e) == Some("./bare/"))
             .unwrap();
-        let types = folder.get("@type").unwrap();
-        assert!(types.as_array().unwrap().contains(&json!("Subcrate")));
+        assert!(folder["@type"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("Subcrate")));
+        assert_eq!(result.warnings.len(), 1);
 
-        // Check shared entity was merged (Alice with two names)
-        let alice = result
+        let result = merge(MissingDescriptorPolicy::PlainDataset).unwrap();
+        let folder = result
             .graph
             .iter()
-            .find(|e| extract_id(e) == Some("https://orcid.org/0000-0001"))
+            .find(|e| extract_id(e) == Some("./bare/"))
             .unwrap();
-        let name = alice.get("name").unwrap();
-        // Should have both names
-        assert!(name.is_array() || name == &json!("Alice"));
+        assert_eq!(folder["@type"], json!("Dataset"));
+        assert!(folder.get("consolidatedEntities").is_none());
+
+        let err = merge(MissingDescriptorPolicy::Fail).unwrap_err();
+        assert!(matches!(
+            err.root_cause(),
+            ConsolidateError::MissingSubcrateDescriptor(_)
+        ));
+        assert_eq!(err.code(), "missing_subcrate_descriptor");
+        assert_eq!(err.subcrate_path(), vec!["./bare/"]);
     }
 
     #[test]
-    fn test_invalid_folder_id() {
-        let main = sample_root_graph();
-        let other = vec![json!({"@id": "./", "@type": "Dataset"})];
+    fn test_keep_partial() {
+        let input = || ConsolidateInput::Merge {
+            main: sample_root_graph(),
+            others: vec![
+                MergeCrate {
+                    graph: vec![
+                        json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                        json!({"@id": "./", "@type": "Dataset"}),
+                        json!({"@id": "./first.csv", "@type": "File"}),
+                    ],
+                    folder_id: "./first/".to_string(),
+                    name: None,
+                },
+                MergeCrate {
+                    graph: vec![],
+                    folder_id: "broken".to_string(),
+                    name: None,
+                },
+            ],
+        };
 
-        let result = consolidate(
-            ConsolidateInput::Merge {
-                main,
-                others: vec![MergeCrate {
-                    graph: other,
-                    folder_id: "no-trailing-slash".to_string(),
+        assert!(consolidate(input(), &NoOpLoader, &ConsolidateOptions::default()).is_err());
+
+        let options = ConsolidateOptions {
+            keep_partial: true,
+            ..ConsolidateOptions::default()
+        };
+        let result = consolidate(input(), &NoOpLoader, &options).unwrap();
+        let failure = result.failure.unwrap();
+        assert_eq!(failure.crates_consolidated, 2);
+        assert!(result
+            .graph
+            .iter()
+            .any(|e| extract_id(e) == Some("./first/first.csv")));
+    }
+
+    #[test]
+    fn test_parallel_merge_matches_sequential() {
+        // Every crate defines #person1, so all but the first must be renamed
+        let crate_graph = |name: &str| {
+            vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "name": name, "author": {"@id": "#person1"}}),
+                json!({"@id": "#person1", "@type": "Person", "name": name}),
+                json!({"@id": "./data.csv", "@type": "File", "author": {"@id": "#person1"}}),
+                json!({"@id": "https://orcid.org/0000-0001", "@type": "Person"}),
+            ]
+        };
+        let input = || ConsolidateInput::Merge {
+            main: sample_root_graph(),
+            others: ["a", "b", "c", "d"]
+                .iter()
+                .map(|name| MergeCrate {
+                    graph: crate_graph(name),
+                    folder_id: format!("./{}/", name),
                     name: None,
-                }],
-            },
-            &NoOpLoader,
-            &ConsolidateOptions::default(),
-        );
+                })
+                .collect(),
+        };
 
-        assert!(matches!(result, Err(ConsolidateError::InvalidFolderId(_))));
+        let sequential = consolidate(input(), &NoOpLoader, &ConsolidateOptions::default()).unwrap();
+        let options = ConsolidateOptions {
+            parallelism: 3,
+            ..ConsolidateOptions::default()
+        };
+        let parallel = consolidate(input(), &NoOpLoader, &options).unwrap();
+
+        assert_eq!(parallel.graph, sequential.graph);
+        assert_eq!(parallel.origins, sequential.origins);
+        assert_eq!(parallel.stats.crates_consolidated, 5);
+
+        let b_data = parallel
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./b/data.csv"))
+            .unwrap();
+        assert_eq!(b_data["author"], json!({"@id": "#b-person1"}));
+        let b_folder = parallel
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./b/"))
+            .unwrap();
+        assert_eq!(b_folder["author"], json!({"@id": "#b-person1"}));
+    }
+
+    #[test]
+    fn test_parallel_merge_keep_partial() {
+        let input = || ConsolidateInput::Merge {
+            main: sample_root_graph(),
+            others: vec![
+                MergeCrate {
+                    graph: vec![
+                        json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                        json!({"@id": "./", "@type": "Dataset"}),
+                        json!({"@id": "./first.csv", "@type": "File"}),
+                    ],
+                    folder_id: "./first/".to_string(),
+                    name: None,
+                },
+                MergeCrate {
+                    graph: vec![],
+                    folder_id: "broken".to_string(),
+                    name: None,
+                },
+                MergeCrate {
+                    graph: vec![
+                        json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                        json!({"@id": "./", "@type": "Dataset"}),
+                    ],
+                    folder_id: "./third/".to_string(),
+                    name: None,
+                },
+            ],
+        };
+        let options = ConsolidateOptions {
+            keep_partial: true,
+            parallelism: 4,
+            ..ConsolidateOptions::default()
+        };
+
+        // Like a sequential run, stops at the first failing crate in input order
+        let result = consolidate(input(), &NoOpLoader, &options).unwrap();
+        let failure = result.failure.unwrap();
+        assert_eq!(failure.code, "invalid_folder_id");
+        assert_eq!(failure.crates_consolidated, 2);
+        assert!(result
+            .graph
+            .iter()
+            .any(|e| extract_id(e) == Some("./first/first.csv")));
+        assert!(!result
+            .graph
+            .iter()
+            .any(|e| extract_id(e) == Some("./third/")));
     }
 
     #[test]
@@ -0,0 +1,260 @@
+//! Dates and times in ISO 8601
+//!
+//! Metadata dates such as `dateModified` come in any precision, from a year
+//! to a date-time with fractional seconds, and in any time zone. Compared as
+//! strings, "2024-05-01T12:00:00+02:00" is later than "2024-05-01T11:00:00Z"
+//! though it is the earlier instant. [`parse_iso8601`] turns them into the
+//! span of instants they stand for, which compare correctly.
+//!
+//! The times of a run, such as when a consolidation started, are written
+//! as ISO 8601 date-times in UTC.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Nanoseconds in a day
+const NANOS_PER_DAY: i128 = 86_400 * 1_000_000_000;
+
+/// The instants an ISO 8601 date or date-time stands for, in nanoseconds
+/// since the Unix epoch
+///
+/// A date-time is a single instant. A date, month or year spans all
+/// instants of its days in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateSpan {
+    /// The first instant
+    pub start: i128,
+    /// The last instant, inclusive
+    pub end: i128,
+}
+
+/// Parse an ISO 8601 date, e.g. "2024", "2024-05" or "2024-05-01", or
+/// date-time, e.g. "2024-05-01T12:00", "2024-05-01T12:00:00.5Z" or
+/// "2024-05-01T12:00:00+02:00"
+///
+/// Date-times without an offset are taken to be in UTC. Returns `None` for
+/// anything else, including dates that don't exist.
+pub fn parse_iso8601(value: &str) -> Option<DateSpan> {
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 4)?;
+    let month = match parts.next() {
+        Some(month) => Some(number(month, 2)?),
+        None => None,
+    };
+    let day = match parts.next() {
+        Some(day) => Some(number(day, 2)?),
+        None => None,
+    };
+    if parts.next().is_some()
+        || month.is_some_and(|month| !(1..=12).contains(&month))
+        || day.is_some_and(|day| day < 1 || day > days_in_month(year, month.unwrap_or(1)))
+    {
+        return None;
+    }
+
+    let Some(time) = time else {
+        let (start, end) = match (month, day) {
+            (Some(month), Some(day)) => {
+                let start = days_from_civil(year, month, day);
+                (start, start + 1)
+            }
+            (Some(12), None) => (
+                days_from_civil(year, 12, 1),
+                days_from_civil(year + 1, 1, 1),
+            ),
+            (Some(month), None) => (
+                days_from_civil(year, month, 1),
+                days_from_civil(year, month + 1, 1),
+            ),
+            (None, _) => (days_from_civil(year, 1, 1), days_from_civil(year + 1, 1, 1)),
+        };
+        return Some(DateSpan {
+            start: i128::from(start) * NANOS_PER_DAY,
+            end: i128::from(end) * NANOS_PER_DAY - 1,
+        });
+    };
+
+    // Date-times need a full date
+    let days = days_from_civil(year, month?, day?);
+    let (clock, offset_secs) = split_offset(time)?;
+    let mut fields = clock.split(':');
+    let hour = number(fields.next()?, 2).filter(|hour| *hour < 24)?;
+    let minute = number(fields.next()?, 2).filter(|minute| *minute < 60)?;
+    let (second, nanos) = match fields.next() {
+        Some(second) => seconds(second)?,
+        None => (0, 0),
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+    let secs = i128::from(days) * 86_400 + i128::from(hour * 3_600 + minute * 60 + second)
+        - i128::from(offset_secs);
+    let instant = secs * 1_000_000_000 + i128::from(nanos);
+    Some(DateSpan {
+        start: instant,
+        end: instant,
+    })
+}
+
+/// Split the offset from UTC off the time of a date-time, in seconds
+fn split_offset(time: &str) -> Option<(&str, i64)> {
+    if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        return Some((clock, 0));
+    }
+    let Some(at) = time.rfind(['+', '-']) else {
+        return Some((time, 0));
+    };
+    let (clock, offset) = time.split_at(at);
+    let sign = if offset.starts_with('-') { -1 } else { 1 };
+    let offset = offset[1..].replace(':', "");
+    let (hours, minutes) = match offset.len() {
+        2 => (number(&offset, 2)?, 0),
+        4 => (number(&offset[..2], 2)?, number(&offset[2..], 2)?),
+        _ => return None,
+    };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some((clock, sign * (hours * 3_600 + minutes * 60)))
+}
+
+/// Seconds and nanoseconds of e.g. "30" or "30.25"
+fn seconds(value: &str) -> Option<(i64, u32)> {
+    let (second, fraction) = match value.split_once(['.', ',']) {
+        Some((second, fraction)) => (second, Some(fraction)),
+        None => (value, None),
+    };
+    // Leap seconds are 60
+    let second = number(second, 2).filter(|second| *second <= 60)?;
+    let nanos = match fraction {
+        Some(fraction) if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) => {
+            // Beyond nanoseconds, digits are dropped
+            let digits: String = fraction
+                .chars()
+                .chain("000000000".chars())
+                .take(9)
+                .collect();
+            digits.parse().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some((second, nanos))
+}
+
+/// `value` as a number if it has exactly `digits` ASCII digits
+fn number(value: &str, digits: usize) -> Option<i64> {
+    (value.len() == digits && value.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| value.parse().ok())
+        .flatten()
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since the Unix epoch of a date, after Howard Hinnant's
+/// days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// `time` in UTC as an ISO 8601 date-time, "YYYY-MM-DD'T'HH:MM:SS'Z'"
+pub(crate) fn iso_datetime(time: SystemTime) -> String {
+    let [year, month, day, hour, minute, second] = utc_fields(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, hour, minute, second
+    )
+}
+
+/// Year, month, day, hour, minute and second of `time` in UTC
+pub(crate) fn utc_fields(time: SystemTime) -> [i64; 6] {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, secs) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date of a day count, after Howard Hinnant's days_from_civil
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let secs = secs as i64;
+    [year, month, day, secs / 3_600, secs % 3_600 / 60, secs % 60]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_iso8601() {
+        let instant = |value: &str| {
+            let span = parse_iso8601(value).unwrap();
+            assert_eq!(span.start, span.end, "{}", value);
+            span.start
+        };
+        assert_eq!(instant("1970-01-01T00:00:00Z"), 0);
+        assert_eq!(instant("1970-01-01T00:00:01.5Z"), 1_500_000_000);
+        assert_eq!(instant("2000-03-01T00:00Z"), 951_868_800 * 1_000_000_000);
+        // Offsets count, in any notation
+        assert_eq!(
+            instant("2024-05-01T12:00:00+02:00"),
+            instant("2024-05-01T10:00:00Z")
+        );
+        assert_eq!(
+            instant("2024-05-01T12:00+0200"),
+            instant("2024-05-01T10:00")
+        );
+        assert_eq!(
+            instant("2024-05-01T05:00-05"),
+            instant("2024-05-01T10:00:00")
+        );
+
+        // Dates span their days
+        let day = parse_iso8601("2024-05-01").unwrap();
+        assert_eq!(day.start, instant("2024-05-01T00:00:00Z"));
+        assert_eq!(day.end, instant("2024-05-01T23:59:59.999999999Z"));
+        let month = parse_iso8601("2024-02").unwrap();
+        assert_eq!(month.end + 1, instant("2024-03-01T00:00Z"));
+        let year = parse_iso8601("2024").unwrap();
+        assert_eq!(year.start, instant("2024-01-01T00:00Z"));
+        assert_eq!(year.end + 1, instant("2025-01-01T00:00Z"));
+
+        for invalid in [
+            "",
+            "yesterday",
+            "24-05-01",
+            "2024-13-01",
+            "2023-02-29",
+            "2024-05-01T",
+            "2024-05T12:00",
+            "2024-05-01T25:00",
+            "2024-05-01T12:00:00.Z",
+            "2024-05-01T12:00+2",
+            "2024-05-01 12:00",
+        ] {
+            assert_eq!(parse_iso8601(invalid), None, "{}", invalid);
+        }
+        assert!(parse_iso8601("2024-02-29").is_some());
+    }
+}
@@ -0,0 +1,512 @@
+//! Splitting a consolidated crate back into its subcrates
+//!
+//! [`deconsolidate`] reverses consolidation: each Subcrate folder becomes a
+//! standalone crate again, made of the entities its `consolidatedEntities`
+//! lists, with ids relative to its folder. The crate containing it refers
+//! to it as a plain subcrate, a Dataset conforming to RO-Crate.
+//!
+//! Not everything consolidation merged can be taken apart again:
+//!
+//! - the folder's properties, merged from the subcrate's root and its
+//!   parent's reference to it, all go to the subcrate's root; the parent's
+//!   reference keeps only its name
+//! - fragment ids namespaced as "#namespace-id" get back their original id,
+//!   other ids renamed on a collision keep their new id
+//! - entities shared between crates, e.g. people with an ORCID, are copied
+//!   into every crate referencing them
+//!
+//! The run's provenance and the consolidation profile are dropped.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path};
+
+use serde_json::{json, Value};
+
+use crate::collect::{extract_id, extract_types, has_type};
+use crate::error::ConsolidateError;
+use crate::id::{
+    classify_id, namespace_from_folder_id, remove_references, rewrite_references,
+    validate_namespace, IdKind,
+};
+use crate::pipeline::Pipeline;
+use crate::vocab::{
+    CONSOLIDATED_ENTITIES_SHORT, CONSOLIDATION_ACTION_ID, CONSOLIDATION_PROFILE_ID,
+    CONSOLIDATION_SOFTWARE_ID, METADATA_DESCRIPTOR_ID, RESOLVE_ENTITY_ID, ROCRATE_SPEC_ID,
+    ROOT_ENTITY_ID, SUBCRATE_TYPE_SHORT,
+};
+
+/// What a Dataset referencing a nested crate conforms to
+const SUBCRATE_CONFORMS_TO: &str = "https://w3id.org/ro/crate";
+
+/// One crate split off a consolidated crate
+#[derive(Debug, Clone)]
+pub struct SplitCrate {
+    /// Id of the crate's folder in the consolidated crate ("./" for the
+    /// root crate)
+    pub folder_id: String,
+    /// Path of the crate's folder below the root crate's ("" for the root
+    /// crate), e.g. "experiments/run-1"
+    pub path: String,
+    /// The crate's entities, starting with its metadata descriptor and root
+    pub graph: Vec<Value>,
+}
+
+/// Split the graph of a consolidated crate into the root crate and one
+/// crate per Subcrate folder, parents before their subcrates
+///
+/// Folders are recognized by their `consolidatedEntities` or the Subcrate
+/// type. Crates consolidated with a `base_id` are not split.
+pub fn deconsolidate(graph: Vec<Value>) -> Result<Vec<SplitCrate>, ConsolidateError> {
+    let artifacts: HashSet<String> = [
+        CONSOLIDATION_ACTION_ID,
+        CONSOLIDATION_SOFTWARE_ID,
+        CONSOLIDATION_PROFILE_ID,
+        RESOLVE_ENTITY_ID,
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+    let mut entities: Vec<Value> = Pipeline::new()
+        .merge(graph)?
+        .into_iter()
+        .filter(|e| !extract_id(e).is_some_and(|id| artifacts.contains(id)))
+        .collect();
+    for entity in &mut entities {
+        remove_references(entity, &artifacts);
+    }
+    let positions: HashMap<String, usize> = entities
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| extract_id(e).map(|id| (id.to_string(), i)))
+        .collect();
+    if !positions.contains_key(ROOT_ENTITY_ID) {
+        return Err(ConsolidateError::MissingRootEntity);
+    }
+    // What the descriptor conformed to besides the consolidation profile
+    if let Some(&index) = positions.get(METADATA_DESCRIPTOR_ID) {
+        if let Some(conforms_to) = entities[index].get_mut("conformsTo") {
+            if let Value::Array(specs) = conforms_to {
+                if specs.len() == 1 {
+                    *conforms_to = specs.remove(0);
+                }
+            }
+        }
+    }
+
+    // Namespace of each folder, shortest first so parents come first
+    let mut folders: Vec<(String, usize)> = entities
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| is_folder(e))
+        .filter_map(|(i, e)| {
+            let id = extract_id(e)?;
+            (classify_id(id) == IdKind::Relative)
+                .then(|| (namespace_from_folder_id(id).to_string(), i))
+        })
+        .collect();
+    for (namespace, _) in &folders {
+        check_folder_path(namespace)?;
+    }
+    folders.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then_with(|| a.cmp(b)));
+    let folder_indices: HashSet<usize> = folders.iter().map(|(_, i)| *i).collect();
+    let parent = |namespace: &str| {
+        folders
+            .iter()
+            .map(|(ns, _)| ns.as_str())
+            .filter(|ns| namespace.len() > ns.len() && namespace.starts_with(&format!("{}/", ns)))
+            .max_by_key(|ns| ns.len())
+            .unwrap_or("")
+            .to_string()
+    };
+
+    // Each entity belongs to the deepest crate listing it, each folder to its
+    // parent, and everything else to the root crate
+    let mut owners: HashMap<&str, String> = HashMap::new();
+    for (namespace, index) in &folders {
+        let listed = entities[*index].get(CONSOLIDATED_ENTITIES_SHORT);
+        for id in listed.map(listed_ids).unwrap_or_default() {
+            let owner = owners.entry(id).or_insert_with(|| namespace.clone());
+            if owner.len() < namespace.len() {
+                owner.clone_from(namespace);
+            }
+        }
+    }
+    for (namespace, index) in &folders {
+        if let Some(id) = extract_id(&entities[*index]) {
+            owners.insert(id, parent(namespace));
+        }
+    }
+    let owner = |id: &str| owners.get(id).map_or("", String::as_str);
+
+    let root_crate = std::iter::once((String::new(), None));
+    let nested = folders.iter().map(|(ns, i)| (ns.clone(), Some(*i)));
+    let mut crates = Vec::new();
+    for (namespace, folder) in root_crate.chain(nested) {
+        let mut graph = Vec::new();
+        match folder {
+            Some(index) => {
+                // About the folder, which becomes the crate's root below
+                graph.push(json!({
+                    "@id": METADATA_DESCRIPTOR_ID,
+                    "@type": "CreativeWork",
+                    "about": {"@id": entities[index]["@id"]},
+                    "conformsTo": {"@id": ROCRATE_SPEC_ID}
+                }));
+                graph.push(subcrate_root(&entities[index]));
+            }
+            None => {
+                // The descriptor and root first, as in the other crates
+                let first = [METADATA_DESCRIPTOR_ID, ROOT_ENTITY_ID];
+                graph.extend(
+                    first
+                        .iter()
+                        .filter_map(|id| positions.get(*id))
+                        .map(|&i| entities[i].clone()),
+                );
+            }
+        }
+        for (index, entity) in entities.iter().enumerate() {
+            let Some(id) = extract_id(entity) else {
+                continue;
+            };
+            if owner(id) != namespace
+                || (namespace.is_empty() && (id == METADATA_DESCRIPTOR_ID || id == ROOT_ENTITY_ID))
+            {
+                continue;
+            }
+            if folder_indices.contains(&index) {
+                graph.push(subcrate_reference(entity));
+            } else {
+                graph.push(entity.clone());
+            }
+        }
+
+        // Parts of other crates are theirs to list
+        for entity in graph.iter_mut().filter_map(Value::as_object_mut) {
+            let theirs = |id: &str| positions.contains_key(id) && owner(id) != namespace;
+            if let Some(parts) = entity.get_mut("hasPart") {
+                if !retain_references(parts, |id| !theirs(id)) {
+                    entity.remove("hasPart");
+                }
+            }
+        }
+
+        // Copy in the shared entities and those of other crates' fragment
+        // ids the crate references
+        let mut ids: HashSet<String> = graph
+            .iter()
+            .filter_map(extract_id)
+            .map(String::from)
+            .collect();
+        let mut pending: Vec<String> = graph.iter().flat_map(reference_ids).collect();
+        while let Some(id) = pending.pop() {
+            let copyable = matches!(classify_id(&id), IdKind::Absolute | IdKind::Fragment);
+            let Some(&index) = positions.get(&id).filter(|_| copyable) else {
+                continue;
+            };
+            if folder_indices.contains(&index) || !ids.insert(id) {
+                continue;
+            }
+            pending.extend(reference_ids(&entities[index]));
+            graph.push(entities[index].clone());
+        }
+
+        // Move ids back into the crate's own folder
+        let referenced: HashSet<String> = graph.iter().flat_map(reference_ids).collect();
+        let id_map: HashMap<String, String> = ids
+            .iter()
+            .chain(&referenced)
+            .filter_map(|id| {
+                let own_fragment = owner(id) == namespace;
+                local_id(id, &namespace, own_fragment, &ids).map(|local| (id.clone(), local))
+            })
+            .collect();
+        for entity in &mut graph {
+            rewrite_references(entity, &id_map);
+        }
+
+        let folder_id = match folder {
+            Some(index) => extract_id(&entities[index]).unwrap_or_default().to_string(),
+            None => ROOT_ENTITY_ID.to_string(),
+        };
+        crates.push(SplitCrate {
+            folder_id,
+            path: namespace,
+            graph,
+        });
+    }
+    Ok(crates)
+}
+
+/// Fail unless `path` is a relative path of plain folder names, so the
+/// crate split off into it is written below the output directory
+///
+/// Folder ids come from the crate being split, e.g. one fetched from a URL,
+/// and "/etc/" or "./a/../../b/" would escape it.
+fn check_folder_path(path: &str) -> Result<(), ConsolidateError> {
+    let plain = !path.contains('\\')
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+    let valid = match validate_namespace(path) {
+        Ok(()) if plain => Ok(()),
+        Ok(()) => Err("not a relative path of folder names".to_string()),
+        Err(reason) => Err(reason),
+    };
+    valid.map_err(|reason| {
+        ConsolidateError::InvalidFolderId(format!("Cannot split off '{}': {}", path, reason))
+    })
+}
+
+/// Whether an entity is the folder of a consolidated crate
+fn is_folder(entity: &Value) -> bool {
+    entity.get(CONSOLIDATED_ENTITIES_SHORT).is_some() || has_type(entity, SUBCRATE_TYPE_SHORT)
+}
+
+/// The root entity of the crate consolidated into `folder`
+fn subcrate_root(folder: &Value) -> Value {
+    let mut root = folder.clone();
+    if let Some(obj) = root.as_object_mut() {
+        obj.remove(CONSOLIDATED_ENTITIES_SHORT);
+        // Marks the parent's reference, not the crate's root
+        if let Some(conforms_to) = obj.get_mut("conformsTo") {
+            if !retain_references(conforms_to, |id| id != SUBCRATE_CONFORMS_TO) {
+                obj.remove("conformsTo");
+            }
+        }
+        let types: Vec<String> = extract_types(folder)
+            .into_iter()
+            .filter(|t| t != SUBCRATE_TYPE_SHORT)
+            .collect();
+        let types = match types.as_slice() {
+            [] => json!("Dataset"),
+            [t] => json!(t),
+            _ => json!(types),
+        };
+        obj.insert("@type".to_string(), types);
+    }
+    root
+}
+
+/// The parent crate's reference to the crate consolidated into `folder`
+fn subcrate_reference(folder: &Value) -> Value {
+    let mut reference = json!({
+        "@id": folder["@id"],
+        "@type": "Dataset",
+        "conformsTo": {"@id": SUBCRATE_CONFORMS_TO}
+    });
+    if let Some(name) = folder.get("name") {
+        reference["name"] = name.clone();
+    }
+    reference
+}
+
+/// The id of `id` in the crate in `namespace`, if it changes
+///
+/// Relative ids become relative to the crate's folder, reaching out of it
+/// with "../". Fragment ids the crate owns lose the namespace prefix, unless
+/// that would make them clash with one of `ids`.
+fn local_id(id: &str, namespace: &str, own: bool, ids: &HashSet<String>) -> Option<String> {
+    if namespace.is_empty() {
+        return None;
+    }
+    let up = "../".repeat(namespace.split('/').count());
+    match classify_id(id) {
+        IdKind::Root => Some(up),
+        IdKind::Relative => {
+            let path = id.strip_prefix("./").unwrap_or(id);
+            let local = match path.strip_prefix(namespace) {
+                Some("" | "/") => ROOT_ENTITY_ID.to_string(),
+                Some(rest) if rest.starts_with('/') => rest[1..].to_string(),
+                _ => format!("{}{}", up, path),
+            };
+            Some(local)
+        }
+        IdKind::Fragment if own => {
+            let original = format!("#{}", id.strip_prefix(&format!("#{}-", namespace))?);
+            (!ids.contains(&original)).then_some(original)
+        }
+        _ => None,
+    }
+}
+
+/// The ids listed in a `consolidatedEntities` value
+fn listed_ids(value: &Value) -> Vec<&str> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(listed_ids).collect(),
+        Value::Object(obj) => obj.get("@id").and_then(Value::as_str).into_iter().collect(),
+        Value::String(id) => vec![id.as_str()],
+        _ => Vec::new(),
+    }
+}
+
+/// The ids of all references within an entity, not counting its own @id
+fn reference_ids(entity: &Value) -> Vec<String> {
+    fn collect(value: &Value, ids: &mut Vec<String>) {
+        match value {
+            Value::Object(obj) => {
+                if let Some(id) = obj.get("@id").and_then(Value::as_str) {
+                    ids.push(id.to_string());
+                }
+                obj.values().for_each(|v| collect(v, ids));
+            }
+            Value::Array(values) => values.iter().for_each(|v| collect(v, ids)),
+            _ => {}
+        }
+    }
+    let mut ids = Vec::new();
+    if let Some(obj) = entity.as_object() {
+        for (key, value) in obj {
+            if key != "@id" {
+                collect(value, &mut ids);
+            }
+        }
+    }
+    ids
+}
+
+/// Keep only the references in `value` to ids `keep` accepts, returning
+/// whether any value is left
+fn retain_references(value: &mut Value, keep: impl Fn(&str) -> bool) -> bool {
+    let kept = |v: &Value| v.get("@id").and_then(Value::as_str).is_none_or(&keep);
+    match value {
+        Value::Array(values) => {
+            values.retain(kept);
+            !values.is_empty()
+        }
+        single => kept(single),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{consolidate, ConsolidateInput, ConsolidateOptions, SubcrateLoader};
+
+    /// Loader serving the subcrates of [`test_round_trip`]
+    struct Subcrates;
+
+    impl SubcrateLoader for Subcrates {
+        fn load(
+            &self,
+            subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            let descriptor = json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}});
+            let crate_ref = |id: &str| json!({"@id": id, "@type": "Dataset", "conformsTo": {"@id": SUBCRATE_CONFORMS_TO}});
+            Ok(match subcrate_id {
+                "./exp/" => vec![
+                    descriptor,
+                    json!({"@id": "./", "@type": "Dataset", "name": "Exp", "hasPart": [{"@id": "data.csv"}, {"@id": "sub/"}]}),
+                    json!({"@id": "data.csv", "@type": "File", "author": {"@id": "https://orcid.org/0000-0001"}}),
+                    json!({"@id": "https://orcid.org/0000-0001", "@type": "Person", "name": "Bob"}),
+                    crate_ref("sub/"),
+                ],
+                "sub/" => vec![
+                    descriptor,
+                    json!({"@id": "./", "@type": "Dataset", "name": "Sub", "hasPart": {"@id": "x.txt"}}),
+                    json!({"@id": "x.txt", "@type": "File", "about": {"@id": "#me"}}),
+                    json!({"@id": "#me", "@type": "Person"}),
+                ],
+                _ => return Err(ConsolidateError::InvalidStructure("missing".to_string())),
+            })
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let root = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}, "conformsTo": {"@id": ROCRATE_SPEC_ID}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "./exp/"}, {"@id": "top.txt"}]}),
+            json!({"@id": "top.txt", "@type": "File"}),
+            json!({"@id": "./exp/", "@type": "Dataset", "conformsTo": {"@id": SUBCRATE_CONFORMS_TO}}),
+        ];
+        let options = ConsolidateOptions {
+            record_provenance: true,
+            fragment_id_policy: crate::FragmentIdPolicy::AlwaysNamespace,
+            ..Default::default()
+        };
+        let result = consolidate(ConsolidateInput::Single(root), &Subcrates, &options).unwrap();
+
+        let crates = deconsolidate(result.graph).unwrap();
+        let paths: Vec<_> = crates.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, ["", "exp", "exp/sub"]);
+        let find = |path: &str, id: &str| {
+            let split = crates.iter().find(|c| c.path == path).unwrap();
+            split.graph.iter().find(|e| e["@id"] == id).cloned()
+        };
+
+        // The root crate refers to its subcrate, and no longer lists its parts
+        let root = find("", "./").unwrap();
+        assert_eq!(
+            root["hasPart"],
+            json!([{"@id": "./exp/"}, {"@id": "top.txt"}])
+        );
+        assert!(root.get("mentions").is_none());
+        assert_eq!(find("", "./exp/").unwrap()["@type"], "Dataset");
+        assert!(find("", "./exp/data.csv").is_none());
+        assert!(find("", CONSOLIDATION_ACTION_ID).is_none());
+        let descriptor = find("", METADATA_DESCRIPTOR_ID).unwrap();
+        assert_eq!(descriptor["conformsTo"], json!({"@id": ROCRATE_SPEC_ID}));
+
+        // Subcrates are standalone again, with their shared entities copied in
+        let exp = find("exp", "./").unwrap();
+        assert_eq!(exp["@type"], "Dataset");
+        assert_eq!(exp["name"], "Exp");
+        assert_eq!(
+            exp["hasPart"],
+            json!([{"@id": "data.csv"}, {"@id": "sub/"}])
+        );
+        assert!(exp.get(CONSOLIDATED_ENTITIES_SHORT).is_none());
+        assert!(exp.get("conformsTo").is_none());
+        let descriptor = find("exp", METADATA_DESCRIPTOR_ID).unwrap();
+        assert_eq!(descriptor["about"], json!({"@id": "./"}));
+        assert!(find("exp", "https://orcid.org/0000-0001").is_some());
+        assert_eq!(
+            find("exp", "sub/").unwrap()["conformsTo"],
+            json!({"@id": SUBCRATE_CONFORMS_TO})
+        );
+        assert!(find("exp", "sub/x.txt").is_none());
+
+        let x = find("exp/sub", "x.txt").unwrap();
+        assert_eq!(x["about"], json!({"@id": "#me"}));
+        assert!(find("exp/sub", "#me").is_some());
+        assert_eq!(
+            find("exp/sub", "./").unwrap()["hasPart"],
+            json!({"@id": "x.txt"})
+        );
+    }
+
+    #[test]
+    fn test_local_id() {
+        let ids = HashSet::from(["#taken".to_string()]);
+        let local = |id| local_id(id, "exp/sub", true, &ids);
+        assert_eq!(local("./exp/sub/"), Some("./".to_string()));
+        assert_eq!(local("exp/sub/a/b.txt"), Some("a/b.txt".to_string()));
+        assert_eq!(local("./exp/c.txt"), Some("../../exp/c.txt".to_string()));
+        assert_eq!(local("./"), Some("../../".to_string()));
+        assert_eq!(local("#exp/sub-me"), Some("#me".to_string()));
+        assert_eq!(local("#exp/sub-taken"), None);
+        assert_eq!(local("https://orcid.org/1"), None);
+        assert_eq!(local_id("./exp/", "", true, &ids), None);
+    }
+
+    #[test]
+    fn test_hostile_folder_ids() {
+        for hostile in [
+            "/tmp/evil/",
+            "../../tmp/evil/",
+            "./a/../../b/",
+            "./a\\..\\..\\b/",
+        ] {
+            let graph = vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "hasPart": {"@id": hostile}}),
+                json!({"@id": hostile, "@type": ["Dataset", "Subcrate"], "consolidatedEntities": []}),
+            ];
+            let err = deconsolidate(graph).unwrap_err();
+            assert_eq!(err.code(), "invalid_folder_id", "{}", hostile);
+        }
+    }
+}
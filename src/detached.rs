@@ -0,0 +1,161 @@
+//! Support for detached (metadata-only) RO-Crates
+//!
+//! Detached crates describe data that lives elsewhere and point at it
+//! through `contentUrl` and `distribution`. These pointers are never
+//! namespaced during consolidation, can optionally be validated to be
+//! absolute, and can be collected into a flat list for download tooling.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::collect::{extract_id, graph_pointer};
+use crate::error::ConsolidateError;
+use crate::id::{classify_id, IdKind};
+
+/// Properties that point at data outside the crate
+pub const POINTER_PROPERTIES: &[&str] = &["contentUrl", "distribution"];
+
+/// A data pointer found on an entity of the consolidated graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DistributionPointer {
+    /// The consolidated @id of the entity carrying the pointer
+    pub entity_id: String,
+    /// The property the pointer was found under
+    pub property: String,
+    /// The pointer target as written in the crate
+    pub url: String,
+}
+
+/// Extract all pointer targets from an entity
+///
+/// Handles plain strings, `{"@id": ...}` references and arrays of either.
+pub fn extract_pointers(entity: &Value) -> Vec<DistributionPointer> {
+    let entity_id = match extract_id(entity) {
+        Some(id) => id,
+        None => return vec![],
+    };
+
+    let mut pointers = Vec::new();
+    for property in POINTER_PROPERTIES {
+        if let Some(value) = entity.get(*property) {
+            for (url, _) in pointer_targets(value) {
+                pointers.push(DistributionPointer {
+                    entity_id: entity_id.to_string(),
+                    property: property.to_string(),
+                    url: url.to_string(),
+                });
+            }
+        }
+    }
+    pointers
+}
+
+/// Collect the pointers of every entity in a graph
+pub fn collect_distributions(graph: &[Value]) -> Vec<DistributionPointer> {
+    graph.iter().flat_map(extract_pointers).collect()
+}
+
+/// Ensure every pointer in a crate's graph is an absolute URI
+///
+/// The error carries a JSON pointer to the offending value, e.g.
+/// `/@graph/4/distribution/1/@id`.
+pub fn validate_pointers(graph: &[Value]) -> Result<(), ConsolidateError> {
+    for (index, entity) in graph.iter().enumerate() {
+        let entity_id = match extract_id(entity) {
+            Some(id) => id,
+            None => continue,
+        };
+        for property in POINTER_PROPERTIES {
+            let value = match entity.get(*property) {
+                Some(value) => value,
+                None => continue,
+            };
+            for (url, path) in pointer_targets(value) {
+                if classify_id(url) != IdKind::Absolute {
+                    let mut segments = vec![*property];
+                    segments.extend(path.iter().map(String::as_str));
+                    return Err(ConsolidateError::RelativePointer {
+                        entity: entity_id.to_string(),
+                        property: property.to_string(),
+                        value: url.to_string(),
+                        pointer: graph_pointer(index, &segments),
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pointer targets of a property value, with their path below the property
+fn pointer_targets(value: &Value) -> Vec<(&str, Vec<String>)> {
+    match value {
+        Value::String(s) => vec![(s.as_str(), vec![])],
+        Value::Object(_) => extract_id(value)
+            .map(|id| (id, vec!["@id".to_string()]))
+            .into_iter()
+            .collect(),
+        Value::Array(arr) => arr
+            .iter()
+            .enumerate()
+            .flat_map(|(i, item)| {
+                pointer_targets(item)
+                    .into_iter()
+                    .map(move |(url, mut path)| {
+                        path.insert(0, i.to_string());
+                        (url, path)
+                    })
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_extract_pointers() {
+        let entity = json!({
+            "@id": "#data",
+            "@type": "File",
+            "contentUrl": "https://example.org/data.csv",
+            "distribution": [
+                {"@id": "https://example.org/data.zip"},
+                {"@id": "https://mirror.example.org/data.zip"}
+            ]
+        });
+
+        let pointers = extract_pointers(&entity);
+        assert_eq!(pointers.len(), 3);
+        assert_eq!(pointers[0].property, "contentUrl");
+        assert_eq!(pointers[0].url, "https://example.org/data.csv");
+        assert_eq!(pointers[2].url, "https://mirror.example.org/data.zip");
+    }
+
+    #[test]
+    fn test_validate_pointers() {
+        let ok = vec![json!({"@id": "#a", "contentUrl": "https://example.org/a"})];
+        assert!(validate_pointers(&ok).is_ok());
+
+        let bad = vec![
+            json!({"@id": "#a", "contentUrl": "https://example.org/a"}),
+            json!({
+                "@id": "#b",
+                "distribution": [
+                    {"@id": "https://example.org/b.zip"},
+                    {"@id": "./local.zip"}
+                ]
+            }),
+        ];
+        match validate_pointers(&bad) {
+            Err(ConsolidateError::RelativePointer { pointer, value, .. }) => {
+                assert_eq!(pointer, "/@graph/1/distribution/1/@id");
+                assert_eq!(value, "./local.zip");
+            }
+            other => panic!("expected RelativePointer, got {:?}", other),
+        }
+    }
+}
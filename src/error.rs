@@ -3,10 +3,20 @@
 use std::path::PathBuf;
 use thiserror::Error;
 
+use crate::merge::MergeConflict;
+
+/// An underlying error kept as the source of a load error
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 #[derive(Error, Debug)]
 pub enum ConsolidateError {
     #[error("Failed to load crate from {path}: {reason}")]
-    LoadError { path: String, reason: String },
+    LoadError {
+        path: String,
+        reason: String,
+        #[source]
+        source: Option<BoxError>,
+    },
 
     #[error("Invalid crate structure: {0}")]
     InvalidStructure(String),
@@ -17,15 +27,72 @@ pub enum ConsolidateError {
     #[error("Invalid folder ID '{0}': must be a relative path ending with '/'")]
     InvalidFolderId(String),
 
+    #[error("Invalid base ID: {0}")]
+    InvalidBaseId(String),
+
+    #[error("Invalid descriptor ID: {0}")]
+    InvalidDescriptorId(String),
+
+    #[error("Reserved namespace: {0}")]
+    ReservedNamespace(String),
+
+    #[error("Invalid folder template: {0}")]
+    InvalidFolderTemplate(String),
+
+    #[error("Harvesting {url} failed: {reason}")]
+    HarvestError { url: String, reason: String },
+
+    #[error("Access to {location} denied by the sandbox: {reason}")]
+    SandboxViolation { location: String, reason: String },
+
+    #[error("BagIt bag {path} failed verification: {}", failures.join("; "))]
+    BagVerification { path: String, failures: Vec<String> },
+
+    #[error("Network access disabled, cannot fetch {0}")]
+    NetworkDisabled(String),
+
+    #[error("Resource limit exceeded: {0}")]
+    LimitExceeded(String),
+
+    #[error("Entity '{id}' has conflicting values for {property}: {first} and {second}")]
+    MergeConflict {
+        id: String,
+        property: String,
+        first: Box<serde_json::Value>,
+        second: Box<serde_json::Value>,
+        pointer: Option<String>,
+    },
+
+    #[error("Entity script failed: {0}")]
+    ScriptError(String),
+
     #[error("Duplicate folder ID '{0}': already used by another crate")]
     DuplicateFolderId(String),
 
+    #[error("Multiple root entities in crate '{namespace}': {ids:?} at {pointers:?}")]
+    MultipleRoots {
+        namespace: String,
+        ids: Vec<String>,
+        pointers: Vec<String>,
+    },
+
     #[error("Missing root entity in crate")]
     MissingRootEntity,
 
     #[error("Missing metadata descriptor in crate")]
     MissingMetadataDescriptor,
 
+    #[error("Subcrate '{0}' has no metadata descriptor")]
+    MissingSubcrateDescriptor(String),
+
+    #[error("Entity '{entity}' has non-absolute {property} pointer '{value}' at {pointer}")]
+    RelativePointer {
+        entity: String,
+        property: String,
+        value: String,
+        pointer: String,
+    },
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -34,13 +101,147 @@ pub enum ConsolidateError {
 
     #[error("Invalid path: {0}")]
     InvalidPath(PathBuf),
+
+    #[error("Failed to consolidate subcrate '{subcrate_id}' (namespace '{namespace}')")]
+    Subcrate {
+        subcrate_id: String,
+        namespace: String,
+        source_location: Option<String>,
+        #[source]
+        source: Box<ConsolidateError>,
+    },
+}
+
+impl ConsolidateError {
+    /// Wrap this error with the subcrate it occurred in
+    pub fn in_subcrate(
+        self,
+        subcrate_id: impl Into<String>,
+        namespace: impl Into<String>,
+        source_location: Option<String>,
+    ) -> Self {
+        ConsolidateError::Subcrate {
+            subcrate_id: subcrate_id.into(),
+            namespace: namespace.into(),
+            source_location,
+            source: Box::new(self),
+        }
+    }
+
+    /// The innermost error, skipping all subcrate context
+    pub fn root_cause(&self) -> &ConsolidateError {
+        match self {
+            ConsolidateError::Subcrate { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+
+    /// JSON pointer into the offending crate's metadata document (if known)
+    ///
+    /// Use [`subcrate_path`](Self::subcrate_path) to find the document. Merge
+    /// conflicts point into the crate the conflicting copy of an entity came from.
+    pub fn pointer(&self) -> Option<&str> {
+        match self.root_cause() {
+            ConsolidateError::RelativePointer { pointer, .. } => Some(pointer),
+            ConsolidateError::MergeConflict { pointer, .. } => pointer.as_deref(),
+            ConsolidateError::MultipleRoots { pointers, .. } => {
+                pointers.first().map(String::as_str)
+            }
+            _ => None,
+        }
+    }
+
+    /// Stable string code of the underlying error
+    ///
+    /// Subcrate context is transparent: the code is that of the root cause.
+    pub fn code(&self) -> &'static str {
+        match self.root_cause() {
+            ConsolidateError::LoadError { .. } => "load_error",
+            ConsolidateError::InvalidStructure(_) => "invalid_structure",
+            ConsolidateError::CycleDetected(_) => "cycle_detected",
+            ConsolidateError::InvalidFolderId(_) => "invalid_folder_id",
+            ConsolidateError::InvalidBaseId(_) => "invalid_base_id",
+            ConsolidateError::InvalidDescriptorId(_) => "invalid_descriptor_id",
+            ConsolidateError::ReservedNamespace(_) => "reserved_namespace",
+            ConsolidateError::InvalidFolderTemplate(_) => "invalid_folder_template",
+            ConsolidateError::HarvestError { .. } => "harvest_error",
+            ConsolidateError::SandboxViolation { .. } => "sandbox_violation",
+            ConsolidateError::BagVerification { .. } => "bag_verification",
+            ConsolidateError::NetworkDisabled(_) => "network_disabled",
+            ConsolidateError::LimitExceeded(_) => "limit_exceeded",
+            ConsolidateError::MergeConflict { .. } => "merge_conflict",
+            ConsolidateError::ScriptError(_) => "script_error",
+            ConsolidateError::DuplicateFolderId(_) => "duplicate_folder_id",
+            ConsolidateError::MultipleRoots { .. } => "multiple_roots",
+            ConsolidateError::MissingRootEntity => "missing_root_entity",
+            ConsolidateError::MissingMetadataDescriptor => "missing_metadata_descriptor",
+            ConsolidateError::MissingSubcrateDescriptor(_) => "missing_subcrate_descriptor",
+            ConsolidateError::RelativePointer { .. } => "relative_pointer",
+            ConsolidateError::Io(_) => "io",
+            ConsolidateError::Json(_) => "json",
+            ConsolidateError::InvalidPath(_) => "invalid_path",
+            ConsolidateError::Subcrate { .. } => unreachable!("root_cause skips subcrate context"),
+        }
+    }
+
+    /// Stable numeric code of the underlying error
+    pub fn numeric_code(&self) -> u16 {
+        match self.root_cause() {
+            ConsolidateError::LoadError { .. } => 1,
+            ConsolidateError::InvalidStructure(_) => 2,
+            ConsolidateError::CycleDetected(_) => 3,
+            ConsolidateError::InvalidFolderId(_) => 4,
+            ConsolidateError::InvalidBaseId(_) => 5,
+            ConsolidateError::DuplicateFolderId(_) => 6,
+            ConsolidateError::MultipleRoots { .. } => 7,
+            ConsolidateError::MissingRootEntity => 8,
+            ConsolidateError::MissingMetadataDescriptor => 9,
+            ConsolidateError::MissingSubcrateDescriptor(_) => 10,
+            ConsolidateError::RelativePointer { .. } => 11,
+            ConsolidateError::Io(_) => 12,
+            ConsolidateError::Json(_) => 13,
+            ConsolidateError::InvalidPath(_) => 14,
+            ConsolidateError::InvalidDescriptorId(_) => 15,
+            ConsolidateError::ReservedNamespace(_) => 16,
+            ConsolidateError::InvalidFolderTemplate(_) => 17,
+            ConsolidateError::HarvestError { .. } => 18,
+            ConsolidateError::SandboxViolation { .. } => 19,
+            ConsolidateError::BagVerification { .. } => 20,
+            ConsolidateError::NetworkDisabled(_) => 21,
+            ConsolidateError::LimitExceeded(_) => 22,
+            ConsolidateError::MergeConflict { .. } => 23,
+            ConsolidateError::ScriptError(_) => 24,
+            ConsolidateError::Subcrate { .. } => unreachable!("root_cause skips subcrate context"),
+        }
+    }
+
+    /// Subcrate ids from the outermost to the innermost crate the error occurred in
+    pub fn subcrate_path(&self) -> Vec<&str> {
+        let mut path = Vec::new();
+        let mut current = self;
+        while let ConsolidateError::Subcrate {
+            subcrate_id,
+            source,
+            ..
+        } = current
+        {
+            path.push(subcrate_id.as_str());
+            current = source;
+        }
+        path
+    }
 }
 
 /// Error types for loading RO-Crates from various sources
 #[derive(Error, Debug)]
 pub enum IndexError {
     #[error("Failed to load crate from {path}: {reason}")]
-    LoadError { path: String, reason: String },
+    LoadError {
+        path: String,
+        reason: String,
+        #[source]
+        source: Option<BoxError>,
+    },
 
     #[error("Invalid path: {0}")]
     InvalidPath(PathBuf),
@@ -50,15 +251,101 @@ pub enum IndexError {
 
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
+
+    #[error("Network access disabled, cannot fetch {0}")]
+    NetworkDisabled(String),
+
+    #[error("Access to {location} denied by the sandbox: {reason}")]
+    SandboxViolation { location: String, reason: String },
+}
+
+impl From<MergeConflict> for ConsolidateError {
+    fn from(conflict: MergeConflict) -> Self {
+        ConsolidateError::MergeConflict {
+            id: conflict.id,
+            property: conflict.property,
+            first: Box::new(conflict.first),
+            second: Box::new(conflict.second),
+            pointer: None,
+        }
+    }
 }
 
 impl From<IndexError> for ConsolidateError {
     fn from(err: IndexError) -> Self {
         match err {
-            IndexError::LoadError { path, reason } => ConsolidateError::LoadError { path, reason },
+            IndexError::LoadError {
+                path,
+                reason,
+                source,
+            } => ConsolidateError::LoadError {
+                path,
+                reason,
+                source,
+            },
             IndexError::InvalidPath(p) => ConsolidateError::InvalidPath(p),
             IndexError::Io(e) => ConsolidateError::Io(e),
             IndexError::Json(e) => ConsolidateError::Json(e),
+            IndexError::NetworkDisabled(url) => ConsolidateError::NetworkDisabled(url),
+            IndexError::SandboxViolation { location, reason } => {
+                ConsolidateError::SandboxViolation { location, reason }
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subcrate_context() {
+        let err = ConsolidateError::MissingSubcrateDescriptor("./inner/".to_string())
+            .in_subcrate("./inner/", "outer/inner", None)
+            .in_subcrate(
+                "./outer/",
+                "outer",
+                Some("https://example.org/outer/ro-crate-metadata.json".to_string()),
+            );
+
+        assert_eq!(err.code(), "missing_subcrate_descriptor");
+        assert_eq!(err.numeric_code(), 10);
+        assert_eq!(err.subcrate_path(), vec!["./outer/", "./inner/"]);
+        assert!(matches!(
+            err.root_cause(),
+            ConsolidateError::MissingSubcrateDescriptor(_)
+        ));
+
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(source.to_string().contains("./inner/"));
+        assert_eq!(err.pointer(), None);
+    }
+
+    #[test]
+    fn test_pointer() {
+        let err = ConsolidateError::RelativePointer {
+            entity: "#data".to_string(),
+            property: "contentUrl".to_string(),
+            value: "data.csv".to_string(),
+            pointer: "/@graph/3/contentUrl".to_string(),
+        }
+        .in_subcrate("./inner/", "inner", None);
+
+        assert_eq!(err.pointer(), Some("/@graph/3/contentUrl"));
+    }
+
+    #[test]
+    fn test_load_error_source() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "no such file");
+        let err: ConsolidateError = IndexError::LoadError {
+            path: "crate.zip".to_string(),
+            reason: format!("Failed to open zip file: {}", io),
+            source: Some(io.into()),
+        }
+        .into();
+
+        let source = std::error::Error::source(&err).unwrap();
+        let io = source.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+    }
+}
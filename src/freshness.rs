@@ -0,0 +1,341 @@
+//! Checking whether a consolidated crate is up to date
+//!
+//! A consolidation that records provenance while an
+//! [`AuditLog`](crate::AuditLog) is active lists every file and URL it read,
+//! with their SHA-256 hashes and validators, as the inputs of its
+//! CreateAction. [`check_freshness`] compares the recorded options with the
+//! current ones and checks each input, so schedulers re-running a
+//! consolidation can skip it when nothing changed.
+//!
+//! Files whose modification time and size are as recorded, and archives
+//! whose modification time is, are taken as unchanged without reading them.
+//! URLs recorded with an `ETag` or `Last-Modified` are fetched
+//! conditionally, and a `304 Not Modified` counts as unchanged. Anything
+//! else is read again and compared by its hash.
+//!
+//! Only recorded inputs are compared: sources that would newly be found,
+//! such as crates added to a merged directory, go unnoticed. URLs are fetched
+//! with [`UrlLoaderOptions::DEFAULT`], or the options given to
+//! [`check_freshness_with`] along with the mirrors to fall back to.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::audit::{self, AccessKind, AccessRecord};
+use crate::consolidate::ConsolidateOptions;
+use crate::error::IndexError;
+use crate::loader::{fetch_url_if_modified, read_tar_member, read_zip_member, UrlLoaderOptions};
+use crate::mirror::Mirrors;
+use crate::vocab::{CONSOLIDATION_INPUTS_SHORT, CONSOLIDATION_OPTIONS_SHORT};
+
+/// Whether a consolidated crate is up to date
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Freshness {
+    /// The inputs and options are unchanged
+    UpToDate,
+    /// The crate needs to be consolidated again, for the given reason
+    Stale(String),
+}
+
+/// Check if the consolidated crate with graph `previous` is up to date for a
+/// consolidation with `options`
+///
+/// Crates without recorded options or inputs are always stale.
+pub fn check_freshness(previous: &[Value], options: &ConsolidateOptions) -> Freshness {
+    check_freshness_with(previous, options, &UrlLoaderOptions::DEFAULT, None)
+}
+
+/// Like [`check_freshness`], fetching URLs with `url_options` and, if they
+/// fail, from their `mirrors`
+pub fn check_freshness_with(
+    previous: &[Value],
+    options: &ConsolidateOptions,
+    url_options: &UrlLoaderOptions,
+    mirrors: Option<&Mirrors>,
+) -> Freshness {
+    // The action's id may have been rewritten against a base IRI
+    let Some(action) = previous
+        .iter()
+        .find(|entity| entity.get(CONSOLIDATION_OPTIONS_SHORT).is_some())
+    else {
+        return Freshness::Stale("no provenance recorded".to_string());
+    };
+
+    let recorded = action[CONSOLIDATION_OPTIONS_SHORT]
+        .as_str()
+        .and_then(|options| serde_json::from_str::<Value>(options).ok());
+    if recorded.is_none() || recorded != serde_json::to_value(options).ok() {
+        return Freshness::Stale("options changed".to_string());
+    }
+
+    let Some(inputs) = action[CONSOLIDATION_INPUTS_SHORT]
+        .as_str()
+        .and_then(|inputs| serde_json::from_str::<Vec<AccessRecord>>(inputs).ok())
+    else {
+        return Freshness::Stale("no inputs recorded".to_string());
+    };
+
+    // Checking isn't part of the run that may follow
+    let _audit = audit::activate(None);
+    for input in &inputs {
+        let name = match &input.member {
+            Some(member) => format!("{} ({})", input.location, member),
+            None => input.location.clone(),
+        };
+        match read_input(input, url_options, mirrors) {
+            Ok(None) => {}
+            Ok(Some(content)) if audit::sha256(&content) == input.sha256 => {}
+            Ok(Some(_)) => return Freshness::Stale(format!("{} changed", name)),
+            Err(e) => return Freshness::Stale(format!("{} can't be read: {}", name, e)),
+        }
+    }
+    Freshness::UpToDate
+}
+
+/// Read the current content of a recorded input, or `None` if its
+/// validators show that it is unchanged
+fn read_input(
+    input: &AccessRecord,
+    url_options: &UrlLoaderOptions,
+    mirrors: Option<&Mirrors>,
+) -> Result<Option<Vec<u8>>, IndexError> {
+    let path = Path::new(&input.location);
+    let member = input.member.as_deref().unwrap_or_default();
+    if input.kind != AccessKind::Url && is_unmodified(input, path) {
+        return Ok(None);
+    }
+    match input.kind {
+        AccessKind::File => std::fs::read(path)
+            .map(Some)
+            .map_err(|e| IndexError::LoadError {
+                path: input.location.clone(),
+                reason: e.to_string(),
+                source: Some(e.into()),
+            }),
+        AccessKind::ZipMember => read_zip_member(path, member).map(Some),
+        AccessKind::TarMember => read_tar_member(path, member).map(Some),
+        AccessKind::Url => {
+            fetch_input(input, url_options, mirrors).map(|content| content.map(String::into_bytes))
+        }
+    }
+}
+
+/// Whether the file or archive at `path` has the modification time and
+/// size it was recorded with
+fn is_unmodified(input: &AccessRecord, path: &Path) -> bool {
+    // The recorded size of a member isn't that of its archive
+    let size_unchanged = input.kind != AccessKind::File
+        || std::fs::metadata(path).is_ok_and(|metadata| metadata.len() == input.bytes);
+    input.modified_ns.is_some() && audit::modified_ns(path) == input.modified_ns && size_unchanged
+}
+
+/// Fetch a recorded URL conditionally on its validators, falling back to
+/// its mirrors
+///
+/// The validators are those of the recorded location's server, so mirrors
+/// are fetched unconditionally. If every mirror fails as well, the error of
+/// the recorded location is returned.
+fn fetch_input(
+    input: &AccessRecord,
+    url_options: &UrlLoaderOptions,
+    mirrors: Option<&Mirrors>,
+) -> Result<Option<String>, IndexError> {
+    let fetch = |url: &str, etag: Option<&str>, last_modified: Option<&str>| {
+        fetch_url_if_modified(url, etag, last_modified, url_options)
+    };
+    let err = match fetch(
+        &input.location,
+        input.etag.as_deref(),
+        input.last_modified.as_deref(),
+    ) {
+        Ok(content) => return Ok(content),
+        Err(err) => err,
+    };
+    mirrors
+        .into_iter()
+        .flat_map(|mirrors| mirrors.mirror_urls(&input.location))
+        .find_map(|mirror| fetch(&mirror, None, None).ok())
+        .ok_or(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::audit::AuditLog;
+    use crate::consolidate::{consolidate, ConsolidateInput, NoOpLoader};
+
+    /// Consolidate the crate in `path` with provenance, auditing the read
+    fn consolidate_file(path: &std::path::Path, options: &ConsolidateOptions) -> Vec<Value> {
+        let log = Arc::new(AuditLog::new());
+        let _active = log.activate();
+        let content = audit::read_file(path).unwrap();
+        let graph = crate::consolidate::parse_graph(&content, "test").unwrap();
+        consolidate(ConsolidateInput::Single(graph), &NoOpLoader, options)
+            .unwrap()
+            .graph
+    }
+
+    #[test]
+    fn test_check_freshness() {
+        let dir = std::env::temp_dir().join(format!("freshness-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ro-crate-metadata.json");
+        let crate_json = |name: &str| {
+            format!(
+                r#"{{"@graph": [{{"@id": "ro-crate-metadata.json", "about": {{"@id": "./"}}}},
+                {{"@id": "./", "@type": "Dataset", "name": "{}"}}]}}"#,
+                name
+            )
+        };
+        std::fs::write(&path, crate_json("First")).unwrap();
+        let options = ConsolidateOptions {
+            record_provenance: true,
+            ..Default::default()
+        };
+        let previous = consolidate_file(&path, &options);
+
+        assert_eq!(check_freshness(&previous, &options), Freshness::UpToDate);
+        let other_options = ConsolidateOptions {
+            strict: true,
+            ..options.clone()
+        };
+        assert_eq!(
+            check_freshness(&previous, &other_options),
+            Freshness::Stale("options changed".to_string())
+        );
+
+        // Files with their recorded modification time and size aren't read
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, crate_json("Other")).unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(check_freshness(&previous, &options), Freshness::UpToDate);
+
+        // Touched files are compared by their hash
+        std::fs::write(&path, crate_json("First")).unwrap();
+        assert_eq!(check_freshness(&previous, &options), Freshness::UpToDate);
+
+        std::fs::write(&path, crate_json("Second")).unwrap();
+        let Freshness::Stale(reason) = check_freshness(&previous, &options) else {
+            panic!("changed input not detected");
+        };
+        assert!(reason.ends_with("ro-crate-metadata.json changed"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            check_freshness(&previous, &options),
+            Freshness::Stale(reason) if reason.contains("can't be read")
+        ));
+    }
+
+    /// Server answering `If-None-Match: "v1"` with 304 and anything else
+    /// with "{}" and that ETag
+    fn etag_server() -> String {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                let mut not_modified = false;
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    not_modified |= line.eq_ignore_ascii_case("if-none-match: \"v1\"\r\n");
+                    line.clear();
+                }
+                let response = if not_modified {
+                    "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n"
+                } else {
+                    "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}"
+                };
+                reader.into_inner().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        base
+    }
+
+    #[test]
+    fn test_check_freshness_of_urls() {
+        let options = ConsolidateOptions {
+            record_provenance: true,
+            ..Default::default()
+        };
+        let graph = vec![
+            serde_json::json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            serde_json::json!({"@id": "./", "@type": "Dataset"}),
+        ];
+        let log = Arc::new(AuditLog::new());
+        let active = log.activate();
+        let previous = consolidate(ConsolidateInput::Single(graph), &NoOpLoader, &options)
+            .unwrap()
+            .graph;
+        drop(active);
+        // The provenance of a run that fetched `url` with `etag`, getting `body`
+        let fetched = |url: &str, etag: Option<&str>, body: &str| {
+            let input = AccessRecord {
+                kind: AccessKind::Url,
+                location: url.to_string(),
+                member: None,
+                bytes: body.len() as u64,
+                sha256: audit::sha256(body.as_bytes()),
+                cached: false,
+                modified_ns: None,
+                etag: etag.map(str::to_string),
+                last_modified: None,
+            };
+            let mut previous = previous.clone();
+            for entity in &mut previous {
+                if entity.get(CONSOLIDATION_INPUTS_SHORT).is_some() {
+                    entity[CONSOLIDATION_INPUTS_SHORT] =
+                        serde_json::to_string(std::slice::from_ref(&input))
+                            .unwrap()
+                            .into();
+                }
+            }
+            previous
+        };
+        let url_options = UrlLoaderOptions {
+            retries: 0,
+            ..UrlLoaderOptions::default()
+        };
+        let check = |previous: &[Value], mirrors: Option<&Mirrors>| {
+            check_freshness_with(previous, &options, &url_options, mirrors)
+        };
+
+        // Unchanged by the server's word, or by the hash of the new body
+        let base = etag_server();
+        let url = format!("{}crate.json", base);
+        let unchanged = fetched(&url, Some("\"v1\""), "ignored");
+        assert_eq!(check(&unchanged, None), Freshness::UpToDate);
+        assert_eq!(
+            check(&fetched(&url, Some("\"v0\""), "{}"), None),
+            Freshness::UpToDate
+        );
+        assert_eq!(
+            check(&fetched(&url, Some("\"v0\""), "[]"), None),
+            Freshness::Stale(format!("{} changed", url))
+        );
+
+        // URLs that can't be fetched fall back to their mirrors; nothing
+        // listens on port 1
+        let down = fetched("http://127.0.0.1:1/crate.json", None, "{}");
+        assert!(matches!(
+            check(&down, None),
+            Freshness::Stale(reason) if reason.contains("can't be read")
+        ));
+        let mirrors = Mirrors::new().with_mirror("http://127.0.0.1:1/", base);
+        assert_eq!(check(&down, Some(&mirrors)), Freshness::UpToDate);
+    }
+
+    #[test]
+    fn test_freshness_without_provenance() {
+        let graph = vec![serde_json::json!({"@id": "./", "@type": "Dataset"})];
+        assert_eq!(
+            check_freshness(&graph, &ConsolidateOptions::default()),
+            Freshness::Stale("no provenance recorded".to_string())
+        );
+    }
+}
@@ -0,0 +1,870 @@
+//! gRPC service for consolidation (feature `grpc`)
+//!
+//! The `Consolidation` service defined in `proto/consolidate.proto` takes the
+//! crates to consolidate as a stream of entity batches and streams the
+//! consolidated graph back the same way, so neither side needs to hold a
+//! whole serialized crate in a single message.
+//!
+//! `Consolidate` has no access to the server's file system or network:
+//! subcrates are not loaded, so every crate must be part of the upload.
+//! Uploads, and the options clients send, are held to the service's
+//! [`ServiceLimits`].
+//! Long consolidations of remote crates are instead submitted as jobs (see
+//! [`crate::job`]), whose results are streamed once they are complete.
+//!
+//! Every request is authenticated by the server's [`Authenticator`], which
+//! establishes the tenant it is made for (see [`crate::tenant`]); clients
+//! can't name a tenant themselves. Jobs run for that tenant and are only
+//! visible to it. A bearer token in the `x-crate-authorization` header is
+//! only sent to the hosts of the job's crates.
+//!
+//! Both kinds of consolidation are counted in [`Metrics`], which
+//! [`serve_metrics`] exposes for Prometheus to scrape.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::MetadataMap;
+use tonic::service::interceptor::{InterceptedService, Interceptor};
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::consolidate::{
+    consolidate, ConsolidateInput, ConsolidateOptions, ConsolidateStats, MergeCrate, NoOpLoader,
+    PartialFailure,
+};
+use crate::error::ConsolidateError;
+use crate::job::{self, JobQueue, JobState};
+use crate::metrics::Metrics;
+
+/// Types and service stubs generated from `proto/consolidate.proto`
+pub mod proto {
+    tonic::include_proto!("rocrate.consolidate.v1");
+}
+
+use proto::consolidate_request::Message as RequestMessage;
+use proto::consolidate_response::Message as ResponseMessage;
+use proto::consolidation_server::{Consolidation, ConsolidationServer};
+use proto::{ConsolidateRequest, ConsolidateResponse, CrateStart, EntityBatch, JobId, Summary};
+
+/// Number of entities per streamed batch of the consolidated graph
+const BATCH_SIZE: usize = 500;
+
+/// Responses buffered ahead of a slow client
+const RESPONSE_BUFFER: usize = 4;
+
+/// Limits the service holds clients' consolidations to
+///
+/// Clients send their own [`ConsolidateOptions`], but options asking for
+/// more than these limits are lowered to them, and those only the operator
+/// may set are refused.
+#[derive(Debug, Clone)]
+pub struct ServiceLimits {
+    /// Most levels of nested subcrates consolidated below each crate
+    pub max_depth: usize,
+    /// Most bytes of a subcrate's metadata document
+    pub max_metadata_bytes: u64,
+    /// Most entities of a crate
+    pub max_crate_entities: usize,
+    /// Most entities of the crates of a consolidation in total
+    pub max_total_entities: usize,
+    /// Most merge crates collected at once
+    pub max_parallelism: usize,
+    /// Most crates of an upload
+    pub max_upload_crates: usize,
+    /// Most bytes of the entities of an upload, as sent
+    ///
+    /// Uploads are held in memory until they are complete. Their entities
+    /// are also limited by `max_crate_entities` and `max_total_entities`.
+    pub max_upload_bytes: u64,
+}
+
+impl ServiceLimits {
+    /// Limits of a service not configured otherwise
+    pub const DEFAULT: Self = Self {
+        max_depth: 16,
+        max_metadata_bytes: 64 << 20,
+        max_crate_entities: 1_000_000,
+        max_total_entities: 10_000_000,
+        max_parallelism: 4,
+        max_upload_crates: 1000,
+        max_upload_bytes: 1 << 30,
+    };
+
+    /// `options` lowered to the limits, or why they are refused
+    ///
+    /// Which metadata files are looked for is up to the operator, as it
+    /// decides which documents are fetched.
+    fn apply(&self, mut options: ConsolidateOptions) -> Result<ConsolidateOptions, String> {
+        if options.metadata_file_patterns != ConsolidateOptions::default().metadata_file_patterns {
+            return Err("metadata_file_patterns can't be set by clients".to_string());
+        }
+        options.max_depth = Some(at_most(options.max_depth, self.max_depth));
+        options.max_metadata_bytes =
+            Some(at_most(options.max_metadata_bytes, self.max_metadata_bytes));
+        options.max_crate_entities =
+            Some(at_most(options.max_crate_entities, self.max_crate_entities));
+        options.max_total_entities =
+            Some(at_most(options.max_total_entities, self.max_total_entities));
+        options.parallelism = options.parallelism.clamp(1, self.max_parallelism.max(1));
+        Ok(options)
+    }
+}
+
+impl Default for ServiceLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// `value` if it is at most `max`, otherwise, or without a value, `max`
+fn at_most<T: Ord + Copy>(value: Option<T>, max: T) -> T {
+    value.map_or(max, |value| value.min(max))
+}
+
+/// Establishes who a request is made by
+pub trait Authenticator: Send + Sync + 'static {
+    /// The id of the tenant whose credentials are in `metadata`, or why the
+    /// request is refused
+    fn authenticate(&self, metadata: &MetadataMap) -> Result<String, String>;
+}
+
+/// Authenticator running every request for the tenant without id, for
+/// servers used by a single tenant
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SingleTenant;
+
+impl Authenticator for SingleTenant {
+    fn authenticate(&self, _metadata: &MetadataMap) -> Result<String, String> {
+        Ok(String::new())
+    }
+}
+
+/// Authenticator taking the tenant from the bearer token in the
+/// `authorization` header
+///
+/// Only tokens' SHA-256 digests are kept. Requests without a known token
+/// are refused.
+#[derive(Default)]
+pub struct TokenAuthenticator {
+    tenants: HashMap<[u8; 32], String>,
+}
+
+impl TokenAuthenticator {
+    /// Authenticator knowing no tokens yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Authenticate requests with `token` as tenant `tenant`
+    pub fn with_token(mut self, token: &str, tenant: impl Into<String>) -> Self {
+        self.tenants.insert(token_digest(token), tenant.into());
+        self
+    }
+}
+
+impl Authenticator for TokenAuthenticator {
+    fn authenticate(&self, metadata: &MetadataMap) -> Result<String, String> {
+        let token = bearer_token(metadata, "authorization").ok_or("missing bearer token")?;
+        self.tenants
+            .get(&token_digest(&token))
+            .cloned()
+            .ok_or_else(|| "unknown bearer token".to_string())
+    }
+}
+
+fn token_digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// The tenant an [`Authenticator`] established for a request
+#[derive(Debug, Clone)]
+struct AuthenticatedTenant(String);
+
+/// Interceptor recording the tenant of each request, refusing those its
+/// authenticator doesn't accept
+#[derive(Clone)]
+pub struct Authenticate(Arc<dyn Authenticator>);
+
+impl Interceptor for Authenticate {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let tenant = self
+            .0
+            .authenticate(request.metadata())
+            .map_err(Status::unauthenticated)?;
+        request.extensions_mut().insert(AuthenticatedTenant(tenant));
+        Ok(request)
+    }
+}
+
+/// The `Consolidation` service behind the interceptor authenticating its
+/// requests
+pub type AuthenticatedService =
+    InterceptedService<ConsolidationServer<ConsolidationService>, Authenticate>;
+
+/// Implementation of the `Consolidation` service
+#[derive(Clone)]
+pub struct ConsolidationService {
+    jobs: Arc<JobQueue>,
+    metrics: Arc<Metrics>,
+    limits: ServiceLimits,
+}
+
+impl ConsolidationService {
+    /// Service running submitted jobs on `jobs`, counting consolidations in
+    /// `metrics`, within [`ServiceLimits::DEFAULT`]
+    ///
+    /// The failed fetches of jobs are only counted if the queue's runner
+    /// holds [`Metrics::count_fetch_errors`] while it runs.
+    pub fn new(jobs: Arc<JobQueue>, metrics: Arc<Metrics>) -> Self {
+        let job_metrics = Arc::clone(&metrics);
+        jobs.on_finish(move |job| job_metrics.record_job(job));
+        Self {
+            jobs,
+            metrics,
+            limits: ServiceLimits::DEFAULT,
+        }
+    }
+
+    /// Hold consolidations to `limits` instead
+    pub fn with_limits(mut self, limits: ServiceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// The service with every request authenticated by `authenticator`
+    /// before it is handled
+    pub fn authenticated(self, authenticator: Arc<dyn Authenticator>) -> AuthenticatedService {
+        ConsolidationServer::with_interceptor(self, Authenticate(authenticator))
+    }
+}
+
+#[tonic::async_trait]
+impl Consolidation for ConsolidationService {
+    type ConsolidateStream = ReceiverStream<Result<ConsolidateResponse, Status>>;
+
+    async fn consolidate(
+        &self,
+        request: Request<Streaming<ConsolidateRequest>>,
+    ) -> Result<Response<Self::ConsolidateStream>, Status> {
+        let mut requests = request.into_inner();
+        let mut upload = Upload::new(self.limits.clone());
+        while let Some(request) = requests.message().await? {
+            upload.push(request)?;
+        }
+        let (input, options) = upload.finish().map_err(Status::invalid_argument)?;
+
+        // Consolidation is CPU-bound; keep it off the async workers
+        let metrics = Arc::clone(&self.metrics);
+        let result = tokio::task::spawn_blocking(move || {
+            let started = Instant::now();
+            let _fetch_errors = metrics.count_fetch_errors();
+            let result = consolidate(input, &NoOpLoader, &options);
+            metrics.record(&result, started.elapsed());
+            result
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map_err(|e| error_status(&e))?;
+
+        let summary = summary(result.stats, result.warnings, result.failure);
+        Ok(Response::new(stream(responses(
+            result.context,
+            result.graph,
+            summary,
+        ))))
+    }
+
+    async fn submit_job(
+        &self,
+        request: Request<proto::JobRequest>,
+    ) -> Result<Response<proto::Job>, Status> {
+        let tenant = tenant_id(&request).ok_or_else(unauthenticated)?;
+        let token = bearer_token(request.metadata(), "x-crate-authorization");
+        let request = request.into_inner();
+        let request = job::JobRequest {
+            source: request.source,
+            merge: request
+                .merge
+                .into_iter()
+                .map(|merge| job::JobMerge {
+                    source: merge.source,
+                    folder_id: merge.folder_id,
+                    name: merge.name,
+                })
+                .collect(),
+            options: parse_options(&request.options_json, &self.limits)
+                .map_err(Status::invalid_argument)?,
+            callback_url: request.callback_url,
+        };
+        let mut context = self.jobs.tenant_context(tenant);
+        if let Some(token) = token {
+            let sources =
+                std::iter::once(&request.source).chain(request.merge.iter().map(|m| &m.source));
+            for origin in sources.filter_map(|source| origin(source)) {
+                context = context.with_credentials(origin, token.clone());
+            }
+        }
+        let job = self
+            .jobs
+            .submit_for(Arc::new(context), request)
+            .map_err(|e| error_status(&e))?;
+        Ok(Response::new(job_message(job)))
+    }
+
+    async fn get_job(&self, request: Request<JobId>) -> Result<Response<proto::Job>, Status> {
+        let tenant = tenant_id(&request).ok_or_else(unauthenticated)?;
+        let id = request.into_inner().id;
+        // Other tenants' jobs are reported as missing
+        let job = self
+            .jobs
+            .job(&id)
+            .map_err(|e| error_status(&e))?
+            .filter(|job| job.tenant == tenant)
+            .ok_or_else(|| Status::not_found(format!("no job '{}'", id)))?;
+        Ok(Response::new(job_message(job)))
+    }
+
+    type GetJobResultStream = ReceiverStream<Result<ConsolidateResponse, Status>>;
+
+    async fn get_job_result(
+        &self,
+        request: Request<JobId>,
+    ) -> Result<Response<Self::GetJobResultStream>, Status> {
+        let tenant = tenant_id(&request).ok_or_else(unauthenticated)?;
+        let id = request.into_inner().id;
+        // Other tenants' jobs are reported as missing
+        let job = self
+            .jobs
+            .job(&id)
+            .map_err(|e| error_status(&e))?
+            .filter(|job| job.tenant == tenant)
+            .ok_or_else(|| Status::not_found(format!("no job '{}'", id)))?;
+        let (JobState::Completed, Some(report)) = (job.state, job.report) else {
+            return Err(Status::failed_precondition(format!(
+                "job '{}' has not completed",
+                job.id
+            )));
+        };
+        let result = self
+            .jobs
+            .result(&job.id)
+            .map_err(|e| error_status(&e))?
+            .ok_or_else(|| Status::not_found(format!("no result of job '{}'", job.id)))?;
+        let mut result: serde_json::Map<String, Value> =
+            serde_json::from_str(&result).map_err(|e| Status::internal(e.to_string()))?;
+        let context = result.remove("@context").unwrap_or_default();
+        let graph = match result.remove("@graph") {
+            Some(Value::Array(graph)) => graph,
+            _ => Vec::new(),
+        };
+        let summary = summary(report.stats, report.warnings, report.failure);
+        Ok(Response::new(stream(responses(context, graph, summary))))
+    }
+}
+
+/// Serve the `Consolidation` service on `addr` until the process ends,
+/// authenticating requests with `authenticator` and holding them to
+/// `limits`
+pub async fn serve(
+    addr: SocketAddr,
+    jobs: Arc<JobQueue>,
+    metrics: Arc<Metrics>,
+    authenticator: Arc<dyn Authenticator>,
+    limits: ServiceLimits,
+) -> Result<(), tonic::transport::Error> {
+    let service = ConsolidationService::new(jobs, metrics).with_limits(limits);
+    Server::builder()
+        .add_service(service.authenticated(authenticator))
+        .serve(addr)
+        .await
+}
+
+/// Serve `metrics` over HTTP at `GET /metrics` on `addr` until the process
+/// ends
+pub async fn serve_metrics(addr: SocketAddr, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let metrics = Arc::clone(&metrics);
+        tokio::spawn(async move {
+            // Only the request line matters
+            let mut request = [0; 1024];
+            let Ok(read) = stream.read(&mut request).await else {
+                return;
+            };
+            let response = if request[..read].starts_with(b"GET /metrics ") {
+                let body = metrics.render();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// Options sent as JSON, the defaults if empty, held to `limits`
+fn parse_options(json: &str, limits: &ServiceLimits) -> Result<ConsolidateOptions, String> {
+    if json.trim().is_empty() {
+        return limits.apply(ConsolidateOptions::default());
+    }
+    let options = serde_json::from_str(json).map_err(|e| format!("invalid options: {}", e))?;
+    limits.apply(options)
+}
+
+/// The tenant a request was authenticated for, if it was
+fn tenant_id<T>(request: &Request<T>) -> Option<String> {
+    request
+        .extensions()
+        .get::<AuthenticatedTenant>()
+        .map(|tenant| tenant.0.clone())
+}
+
+/// Status of requests that skipped authentication
+fn unauthenticated() -> Status {
+    Status::unauthenticated("request was not authenticated")
+}
+
+/// The bearer token in the `header` of a request, if any
+fn bearer_token(metadata: &MetadataMap, header: &str) -> Option<String> {
+    let value = metadata.get(header)?.to_str().ok()?;
+    value
+        .strip_prefix("Bearer ")
+        .map(|token| token.trim().to_string())
+}
+
+/// The origin of a URL as credential prefix, e.g. "https://example.org/"
+fn origin(url: &str) -> Option<String> {
+    let url = url::Url::parse(url).ok()?;
+    let origin = url.origin();
+    origin
+        .is_tuple()
+        .then(|| format!("{}/", origin.ascii_serialization()))
+}
+
+/// The message for a job
+fn job_message(job: job::Job) -> proto::Job {
+    let state = match job.state {
+        JobState::Queued => "queued",
+        JobState::Running => "running",
+        JobState::Completed => "completed",
+        JobState::Failed => "failed",
+    };
+    proto::Job {
+        id: job.id,
+        state: state.to_string(),
+        crates_loaded: job.crates_loaded as u64,
+        report_json: job
+            .report
+            .and_then(|report| serde_json::to_string(&report).ok()),
+        error_code: job.error.as_ref().map(|e| e.code.clone()),
+        error: job.error.map(|e| e.message),
+        callback_error: job.callback_error,
+    }
+}
+
+/// Stream responses to the client as it reads them
+fn stream(
+    responses: impl Iterator<Item = ConsolidateResponse> + Send + 'static,
+) -> ReceiverStream<Result<ConsolidateResponse, Status>> {
+    let (tx, rx) = mpsc::channel(RESPONSE_BUFFER);
+    tokio::spawn(async move {
+        for response in responses {
+            if tx.send(Ok(response)).await.is_err() {
+                // The client went away
+                break;
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+/// Crates and options received so far from a request stream
+///
+/// Errors are the reason the request stream is invalid.
+#[derive(Debug)]
+struct Upload {
+    limits: ServiceLimits,
+    options: Option<ConsolidateOptions>,
+    crates: Vec<(CrateStart, Vec<Value>)>,
+    /// Entities received so far, and their bytes
+    entities: usize,
+    bytes: u64,
+}
+
+impl Upload {
+    /// Nothing received yet, of a request held to `limits`
+    fn new(limits: ServiceLimits) -> Self {
+        Self {
+            limits,
+            options: None,
+            crates: Vec::new(),
+            entities: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Add what `request` sends, failing once the upload exceeds the
+    /// limits
+    fn push(&mut self, request: ConsolidateRequest) -> Result<(), UploadError> {
+        let limits = &self.limits;
+        match request.message {
+            Some(RequestMessage::OptionsJson(json)) => {
+                if self.options.is_some() || !self.crates.is_empty() {
+                    return Err("options must be sent once, before the first crate"
+                        .to_string()
+                        .into());
+                }
+                self.options = Some(parse_options(&json, limits)?);
+            }
+            Some(RequestMessage::CrateStart(start)) => {
+                if self.crates.len() >= limits.max_upload_crates {
+                    return Err(UploadError::TooLarge(format!(
+                        "the upload exceeds the limit of {} crates",
+                        limits.max_upload_crates
+                    )));
+                }
+                self.crates.push((start, Vec::new()));
+            }
+            Some(RequestMessage::Entities(batch)) => {
+                let (_, graph) = self
+                    .crates
+                    .last_mut()
+                    .ok_or("entities sent before the first crate_start".to_string())?;
+                for entity in batch.entities {
+                    self.bytes += entity.len() as u64;
+                    self.entities += 1;
+                    if self.bytes > limits.max_upload_bytes {
+                        return Err(UploadError::TooLarge(format!(
+                            "the upload exceeds the limit of {} bytes",
+                            limits.max_upload_bytes
+                        )));
+                    }
+                    if self.entities > limits.max_total_entities {
+                        return Err(UploadError::TooLarge(format!(
+                            "the upload exceeds the limit of {} entities",
+                            limits.max_total_entities
+                        )));
+                    }
+                    if graph.len() >= limits.max_crate_entities {
+                        return Err(UploadError::TooLarge(format!(
+                            "a crate exceeds the limit of {} entities",
+                            limits.max_crate_entities
+                        )));
+                    }
+                    let entity: Value = serde_json::from_str(&entity)
+                        .map_err(|e| format!("invalid entity: {}", e))?;
+                    if !entity.is_object() {
+                        return Err(format!("entity is not a JSON object: {}", entity).into());
+                    }
+                    graph.push(entity);
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// The consolidation input: the first crate is the main crate, the others
+    /// are merged into it
+    fn finish(self) -> Result<(ConsolidateInput, ConsolidateOptions), String> {
+        let mut crates = self.crates.into_iter();
+        let (_, main) = crates.next().ok_or("no crate was uploaded")?;
+        let others: Vec<MergeCrate> = crates
+            .map(|(start, graph)| MergeCrate {
+                graph,
+                folder_id: start.folder_id,
+                name: start.name,
+            })
+            .collect();
+        let input = if others.is_empty() {
+            ConsolidateInput::Single(main)
+        } else {
+            ConsolidateInput::Merge { main, others }
+        };
+        let options = match self.options {
+            Some(options) => options,
+            None => parse_options("", &self.limits)?,
+        };
+        Ok((input, options))
+    }
+}
+
+/// Why an upload fails
+#[derive(Debug)]
+enum UploadError {
+    /// The request stream is invalid
+    Invalid(String),
+    /// The upload exceeds the service's limits
+    TooLarge(String),
+}
+
+impl From<String> for UploadError {
+    fn from(reason: String) -> Self {
+        UploadError::Invalid(reason)
+    }
+}
+
+impl From<UploadError> for Status {
+    fn from(err: UploadError) -> Self {
+        match err {
+            UploadError::Invalid(reason) => Status::invalid_argument(reason),
+            UploadError::TooLarge(reason) => Status::resource_exhausted(reason),
+        }
+    }
+}
+
+/// The summary message of a consolidation
+fn summary(
+    stats: ConsolidateStats,
+    warnings: Vec<String>,
+    failure: Option<PartialFailure>,
+) -> Summary {
+    Summary {
+        crates_consolidated: stats.crates_consolidated as u64,
+        total_entities: stats.total_entities as u64,
+        merged_entities: stats.merged_entities as u64,
+        warnings,
+        failure_code: failure.as_ref().map(|f| f.code.clone()),
+        failure: failure.map(|f| f.error),
+    }
+}
+
+/// The response stream for a consolidated crate: its context, the graph in
+/// batches, then the summary
+fn responses(
+    context: Value,
+    graph: Vec<Value>,
+    summary: Summary,
+) -> impl Iterator<Item = ConsolidateResponse> {
+    let batches = (0..graph.len()).step_by(BATCH_SIZE).map(move |start| {
+        let entities = graph[start..].iter().take(BATCH_SIZE).map(Value::to_string);
+        ResponseMessage::Entities(EntityBatch {
+            entities: entities.collect(),
+        })
+    });
+    std::iter::once(ResponseMessage::ContextJson(context.to_string()))
+        .chain(batches)
+        .chain(std::iter::once(ResponseMessage::Summary(summary)))
+        .map(|message| ConsolidateResponse {
+            message: Some(message),
+        })
+}
+
+/// Status for a failed consolidation, carrying the error's stable code
+fn error_status(err: &ConsolidateError) -> Status {
+    let message = format!("{}: {}", err.code(), err);
+    match err.root_cause() {
+        ConsolidateError::LoadError { .. } => Status::failed_precondition(message),
+        _ => Status::invalid_argument(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn crate_start(folder_id: &str) -> ConsolidateRequest {
+        ConsolidateRequest {
+            message: Some(RequestMessage::CrateStart(CrateStart {
+                folder_id: folder_id.to_string(),
+                name: None,
+            })),
+        }
+    }
+
+    fn entities(entities: &[Value]) -> ConsolidateRequest {
+        ConsolidateRequest {
+            message: Some(RequestMessage::Entities(EntityBatch {
+                entities: entities.iter().map(Value::to_string).collect(),
+            })),
+        }
+    }
+
+    fn graph(name: &str) -> Vec<Value> {
+        vec![
+            json!({"@id": "ro-crate-metadata.json", "@type": "CreativeWork", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "name": name}),
+        ]
+    }
+
+    #[test]
+    fn test_upload_and_responses() {
+        let mut upload = Upload::new(ServiceLimits::DEFAULT);
+        let options = ConsolidateRequest {
+            message: Some(RequestMessage::OptionsJson(
+                r#"{"add_subcrate_type": false}"#.into(),
+            )),
+        };
+        upload.push(options.clone()).unwrap();
+        upload.push(crate_start("")).unwrap();
+        let main = graph("Main");
+        upload.push(entities(&main[..1])).unwrap();
+        upload.push(entities(&main[1..])).unwrap();
+        upload.push(crate_start("./other/")).unwrap();
+        upload.push(entities(&graph("Other"))).unwrap();
+
+        // Options come first
+        assert!(upload.push(options).is_err());
+
+        let (input, options) = upload.finish().unwrap();
+        assert!(!options.add_subcrate_type);
+        let ConsolidateInput::Merge { main, others } = &input else {
+            panic!("expected a merge");
+        };
+        assert_eq!(main.len(), 2);
+        assert_eq!(others[0].folder_id, "./other/");
+
+        let result = consolidate(input, &NoOpLoader, &options).unwrap();
+        let total = result.graph.len();
+        let summary = summary(result.stats, result.warnings, result.failure);
+        let messages: Vec<_> = responses(result.context, result.graph, summary)
+            .filter_map(|r| r.message)
+            .collect();
+        assert!(matches!(messages[0], ResponseMessage::ContextJson(_)));
+        let streamed: usize = messages
+            .iter()
+            .map(|m| match m {
+                ResponseMessage::Entities(batch) => batch.entities.len(),
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(streamed, total);
+        let Some(ResponseMessage::Summary(summary)) = messages.last() else {
+            panic!("expected a summary last");
+        };
+        assert_eq!(summary.crates_consolidated, 2);
+        assert_eq!(summary.total_entities, total as u64);
+    }
+
+    #[test]
+    fn test_invalid_upload() {
+        let mut upload = Upload::new(ServiceLimits::DEFAULT);
+        let err = upload.push(entities(&graph("Main"))).unwrap_err();
+        assert!(
+            matches!(err, UploadError::Invalid(reason) if reason.contains("first crate_start"))
+        );
+
+        upload.push(crate_start("")).unwrap();
+        let err = upload
+            .push(entities(&[json!("not an entity")]))
+            .unwrap_err();
+        assert!(
+            matches!(err, UploadError::Invalid(reason) if reason.contains("not a JSON object"))
+        );
+
+        assert!(Upload::new(ServiceLimits::DEFAULT).finish().is_err());
+    }
+
+    #[test]
+    fn test_upload_limits() {
+        let limits = ServiceLimits {
+            max_crate_entities: 2,
+            max_total_entities: 3,
+            max_upload_crates: 2,
+            max_upload_bytes: 1000,
+            ..ServiceLimits::DEFAULT
+        };
+        let exhausted = |err: UploadError| {
+            let status = Status::from(err);
+            assert_eq!(status.code(), tonic::Code::ResourceExhausted, "{}", status);
+            status.message().to_string()
+        };
+
+        let mut upload = Upload::new(limits.clone());
+        upload.push(crate_start("")).unwrap();
+        upload.push(entities(&graph("Main"))).unwrap();
+        let err = upload.push(entities(&graph("Main")[1..])).unwrap_err();
+        assert!(exhausted(err).contains("limit of 2 entities"));
+
+        let mut upload = Upload::new(limits.clone());
+        upload.push(crate_start("")).unwrap();
+        upload.push(entities(&graph("Main"))).unwrap();
+        upload.push(crate_start("./a/")).unwrap();
+        let err = upload.push(entities(&graph("A"))).unwrap_err();
+        assert!(exhausted(err).contains("limit of 3 entities"));
+        let err = upload.push(crate_start("./b/")).unwrap_err();
+        assert!(exhausted(err).contains("limit of 2 crates"));
+
+        let mut upload = Upload::new(limits);
+        upload.push(crate_start("")).unwrap();
+        let err = upload
+            .push(entities(&[json!({"@id": "./", "name": "x".repeat(1000)})]))
+            .unwrap_err();
+        assert!(exhausted(err).contains("limit of 1000 bytes"));
+    }
+
+    #[test]
+    fn test_options_are_limited() {
+        let limits = ServiceLimits {
+            max_depth: 2,
+            ..ServiceLimits::DEFAULT
+        };
+        let json = r#"{"max_depth": 10, "max_total_entities": 5, "parallelism": 64}"#;
+        let options = parse_options(json, &limits).unwrap();
+        assert_eq!(options.max_depth, Some(2));
+        assert_eq!(options.max_total_entities, Some(5));
+        assert_eq!(options.parallelism, 4);
+        let options = parse_options("", &limits).unwrap();
+        assert_eq!(options.max_metadata_bytes, Some(64 << 20));
+
+        let json = r#"{"metadata_file_patterns": ["../*.json"]}"#;
+        let err = parse_options(json, &limits).unwrap_err();
+        assert!(err.contains("metadata_file_patterns"), "{}", err);
+    }
+
+    #[test]
+    fn test_tenant_metadata() {
+        let authenticator = TokenAuthenticator::new().with_token("secret", "a");
+        let mut metadata = MetadataMap::new();
+        let err = authenticator.authenticate(&metadata).unwrap_err();
+        assert_eq!(err, "missing bearer token");
+        assert_eq!(SingleTenant.authenticate(&metadata).unwrap(), "");
+
+        // A tenant named by the client isn't taken for granted
+        metadata.insert("x-tenant-id", "b".parse().unwrap());
+        assert!(authenticator.authenticate(&metadata).is_err());
+        metadata.insert("authorization", "Bearer guess".parse().unwrap());
+        assert!(authenticator.authenticate(&metadata).is_err());
+        metadata.insert("authorization", "Bearer secret".parse().unwrap());
+        assert_eq!(authenticator.authenticate(&metadata).unwrap(), "a");
+        assert_eq!(SingleTenant.authenticate(&metadata).unwrap(), "");
+
+        // Requests that skipped authentication are refused
+        assert_eq!(tenant_id(&Request::new(())), None);
+        let mut request = Request::new(());
+        request
+            .extensions_mut()
+            .insert(AuthenticatedTenant("a".to_string()));
+        assert_eq!(tenant_id(&request).as_deref(), Some("a"));
+
+        assert_eq!(bearer_token(&metadata, "x-crate-authorization"), None);
+        metadata.insert("x-crate-authorization", "Bearer crates".parse().unwrap());
+        assert_eq!(
+            bearer_token(&metadata, "x-crate-authorization").as_deref(),
+            Some("crates")
+        );
+
+        assert_eq!(
+            origin("https://data.example.org:8443/crates/a/").as_deref(),
+            Some("https://data.example.org:8443/")
+        );
+        assert_eq!(origin("./local/crate"), None);
+    }
+}
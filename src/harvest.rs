@@ -0,0 +1,516 @@
+//! Harvesting crates from a repository into a collection crate
+//!
+//! Records are listed page by page, through OAI-PMH `ListRecords` or a
+//! simple paged JSON API, optionally only those changed since a datestamp.
+//! A [`HarvestState`] remembers what was harvested, so that the next run
+//! asks only for changes and each record keeps its folder in the collection.
+//!
+//! An OAI-PMH record's crate is the URL found in its metadata (e.g. a
+//! `dc:identifier`), preferring one that ends in `ro-crate-metadata.json`.
+//! The JSON API answers with pages like
+//!
+//! ```json
+//! {
+//!   "records": [
+//!     {"identifier": "rec-1", "datestamp": "2024-05-01", "url": "https://repo.example.org/rec-1/"},
+//!     {"identifier": "rec-2", "datestamp": "2024-05-02", "deleted": true}
+//!   ],
+//!   "next": "https://repo.example.org/api/records?page=2"
+//! }
+//! ```
+//!
+//! where `next` is left out on the last page. Both are asked for changes
+//! with a `from` query parameter.
+
+use roxmltree::Node;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use url::Url;
+
+use crate::error::ConsolidateError;
+use crate::vocab::{METADATA_DESCRIPTOR_ID, ROOT_ENTITY_ID};
+
+/// How a repository lists its records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HarvestProtocol {
+    /// OAI-PMH `ListRecords`, paged by resumption tokens
+    #[default]
+    OaiPmh,
+    /// JSON pages linked by `next` URLs
+    Json,
+}
+
+/// A repository to harvest
+#[derive(Debug, Clone)]
+pub struct Harvester {
+    /// Base URL of the OAI-PMH endpoint or the JSON API's first page
+    pub endpoint: String,
+    pub protocol: HarvestProtocol,
+    /// OAI-PMH metadata format to request
+    pub metadata_prefix: String,
+    /// OAI-PMH set to restrict the harvest to
+    pub set: Option<String>,
+}
+
+/// A record listed by the repository
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HarvestRecord {
+    pub identifier: String,
+    /// When the record last changed
+    pub datestamp: String,
+    /// Where the record's crate is (none for deleted records)
+    pub url: Option<String>,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// One page of a listing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HarvestPage {
+    pub records: Vec<HarvestRecord>,
+    /// Resumption token (OAI-PMH) or URL (JSON) of the next page
+    pub next: Option<String>,
+}
+
+/// A record harvested into the collection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HarvestedRecord {
+    pub datestamp: String,
+    pub url: String,
+    /// Folder of the record's crate in the collection
+    pub folder_id: String,
+}
+
+/// What earlier harvests of a repository collected
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HarvestState {
+    /// Latest datestamp seen, to ask for changes since
+    pub last_datestamp: Option<String>,
+    /// Harvested records by identifier
+    pub records: BTreeMap<String, HarvestedRecord>,
+    /// Records whose crates could not be harvested, to try again next time
+    /// although the datestamp moved past them
+    pub failed: Vec<HarvestRecord>,
+}
+
+impl HarvestState {
+    /// Move the datestamp to harvest from past the given records
+    ///
+    /// Datestamps are ISO 8601 of one granularity, so they compare as strings.
+    pub fn advance(&mut self, records: &[HarvestRecord]) {
+        let latest = records.iter().map(|r| r.datestamp.as_str()).max();
+        if let Some(latest) = latest {
+            if self.last_datestamp.as_deref() < Some(latest) {
+                self.last_datestamp = Some(latest.to_string());
+            }
+        }
+    }
+
+    /// The records to harvest: `listed`, after the failed ones not listed
+    /// again
+    pub fn with_failed(&self, listed: Vec<HarvestRecord>) -> Vec<HarvestRecord> {
+        let relisted: HashSet<&str> = listed.iter().map(|r| r.identifier.as_str()).collect();
+        let mut records: Vec<HarvestRecord> = self
+            .failed
+            .iter()
+            .filter(|r| !relisted.contains(r.identifier.as_str()))
+            .cloned()
+            .collect();
+        records.extend(listed);
+        records
+    }
+}
+
+impl Harvester {
+    /// Harvest an endpoint, requesting OAI-PMH records as `oai_dc`
+    pub fn new(endpoint: impl Into<String>, protocol: HarvestProtocol) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            protocol,
+            metadata_prefix: "oai_dc".to_string(),
+            set: None,
+        }
+    }
+
+    /// List the records changed since `from` (all records if `None`)
+    ///
+    /// Follows the listing page by page, fetching each page with `fetch`.
+    /// A resumption token or next page seen before fails, rather than
+    /// listing the same pages forever.
+    pub fn list_records(
+        &self,
+        from: Option<&str>,
+        mut fetch: impl FnMut(&str) -> Result<String, ConsolidateError>,
+    ) -> Result<Vec<HarvestRecord>, ConsolidateError> {
+        let mut records = Vec::new();
+        let mut seen = HashSet::new();
+        let mut url = self.first_url(from)?;
+        loop {
+            let page = self.parse_page(&fetch(url.as_str())?, url.as_str())?;
+            records.extend(page.records);
+            match page.next {
+                Some(next) if !seen.insert(next.clone()) => {
+                    return Err(ConsolidateError::HarvestError {
+                        url: url.to_string(),
+                        reason: format!("the listing repeats the next page '{}'", next),
+                    });
+                }
+                Some(next) => url = self.next_url(&url, &next)?,
+                None => return Ok(records),
+            }
+        }
+    }
+
+    /// URL of the first page
+    fn first_url(&self, from: Option<&str>) -> Result<Url, ConsolidateError> {
+        let mut url = Url::parse(&self.endpoint).map_err(|e| self.error(&e.to_string()))?;
+        let mut pairs = Vec::new();
+        if self.protocol == HarvestProtocol::OaiPmh {
+            pairs.push(("verb", "ListRecords"));
+            pairs.push(("metadataPrefix", self.metadata_prefix.as_str()));
+            pairs.extend(self.set.as_deref().map(|set| ("set", set)));
+        }
+        pairs.extend(from.map(|from| ("from", from)));
+        // Appending nothing would still leave an empty query
+        if !pairs.is_empty() {
+            url.query_pairs_mut().extend_pairs(pairs);
+        }
+        Ok(url)
+    }
+
+    /// URL of the page after `current`
+    fn next_url(&self, current: &Url, next: &str) -> Result<Url, ConsolidateError> {
+        match self.protocol {
+            HarvestProtocol::OaiPmh => {
+                // A resumption token replaces all other arguments
+                let mut url = Url::parse(&self.endpoint).map_err(|e| self.error(&e.to_string()))?;
+                url.query_pairs_mut()
+                    .append_pair("verb", "ListRecords")
+                    .append_pair("resumptionToken", next);
+                Ok(url)
+            }
+            HarvestProtocol::Json => current
+                .join(next)
+                .map_err(|e| self.error(&format!("invalid next page '{}': {}", next, e))),
+        }
+    }
+
+    /// Parse a page fetched from `url`
+    pub fn parse_page(&self, content: &str, url: &str) -> Result<HarvestPage, ConsolidateError> {
+        let page = match self.protocol {
+            HarvestProtocol::OaiPmh => parse_oai_pmh_page(content),
+            HarvestProtocol::Json => parse_json_page(content),
+        };
+        page.map_err(|reason| ConsolidateError::HarvestError {
+            url: url.to_string(),
+            reason,
+        })
+    }
+
+    fn error(&self, reason: &str) -> ConsolidateError {
+        ConsolidateError::HarvestError {
+            url: self.endpoint.clone(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Graph of a new, empty collection crate
+pub fn collection_graph(name: &str) -> Vec<Value> {
+    vec![
+        json!({
+            "@id": METADATA_DESCRIPTOR_ID,
+            "@type": "CreativeWork",
+            "about": {"@id": ROOT_ENTITY_ID},
+            "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+        }),
+        json!({
+            "@id": ROOT_ENTITY_ID,
+            "@type": "Dataset",
+            "name": name
+        }),
+    ]
+}
+
+fn parse_oai_pmh_page(content: &str) -> Result<HarvestPage, String> {
+    let doc = roxmltree::Document::parse(content).map_err(|e| e.to_string())?;
+    let text = |node: Node, name: &str| {
+        elements(node, name)
+            .next()
+            .and_then(|n| n.text())
+            .map(str::trim)
+            .unwrap_or_default()
+            .to_string()
+    };
+
+    if let Some(error) = elements(doc.root(), "error").next() {
+        let code = error.attribute("code").unwrap_or_default();
+        // An empty listing is reported as an error
+        if code == "noRecordsMatch" {
+            return Ok(HarvestPage::default());
+        }
+        return Err(format!(
+            "OAI-PMH error {}: {}",
+            code,
+            error.text().unwrap_or_default().trim()
+        ));
+    }
+
+    let records = elements(doc.root(), "record")
+        .map(|record| {
+            let header = elements(record, "header").next();
+            let url = elements(record, "metadata").next().and_then(crate_url);
+            HarvestRecord {
+                identifier: header.map(|h| text(h, "identifier")).unwrap_or_default(),
+                datestamp: header.map(|h| text(h, "datestamp")).unwrap_or_default(),
+                deleted: header.and_then(|h| h.attribute("status")) == Some("deleted"),
+                url,
+            }
+        })
+        .collect();
+    let next = elements(doc.root(), "resumptionToken")
+        .next()
+        .and_then(|n| n.text())
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(String::from);
+    Ok(HarvestPage { records, next })
+}
+
+/// Elements named `name` (in any namespace) at or below `node`
+fn elements<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.descendants()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+/// The crate URL among the texts and attributes of a record's metadata
+fn crate_url(metadata: Node) -> Option<String> {
+    let urls: Vec<&str> = metadata
+        .descendants()
+        .flat_map(|n| {
+            n.text()
+                .filter(|_| n.is_text())
+                .into_iter()
+                .chain(n.attributes().map(|a| a.value()))
+        })
+        .map(str::trim)
+        .filter(|s| s.starts_with("http://") || s.starts_with("https://"))
+        .collect();
+    urls.iter()
+        .find(|url| url.ends_with(METADATA_DESCRIPTOR_ID))
+        .or(urls.first())
+        .map(|url| url.to_string())
+}
+
+fn parse_json_page(content: &str) -> Result<HarvestPage, String> {
+    let doc: Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let string =
+        |value: &Value, key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+    let records = doc
+        .get("records")
+        .and_then(Value::as_array)
+        .ok_or("page has no \"records\" array")?
+        .iter()
+        .map(|record| HarvestRecord {
+            identifier: string(record, "identifier").unwrap_or_default(),
+            datestamp: string(record, "datestamp").unwrap_or_default(),
+            url: string(record, "url"),
+            deleted: record.get("deleted").and_then(Value::as_bool) == Some(true),
+        })
+        .collect();
+    Ok(HarvestPage {
+        records,
+        next: string(&doc, "next"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn oai_page(records: &str, token: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<OAI-PMH xmlns="http://www.openarchives.org/OAI/2.0/">
+  <responseDate>2024-06-01T00:00:00Z</responseDate>
+  <ListRecords>{}<resumptionToken cursor="0">{}</resumptionToken></ListRecords>
+</OAI-PMH>"#,
+            records, token
+        )
+    }
+
+    const RECORDS: &str = r#"
+    <record>
+      <header><identifier>oai:repo:1</identifier><datestamp>2024-05-01</datestamp></header>
+      <metadata>
+        <oai_dc:dc xmlns:oai_dc="http://www.openarchives.org/OAI/2.0/oai_dc/"
+                   xmlns:dc="http://purl.org/dc/elements/1.1/">
+          <dc:title>Run 1</dc:title>
+          <dc:identifier>https://repo.example.org/records/1</dc:identifier>
+          <dc:relation>https://repo.example.org/records/1/ro-crate-metadata.json</dc:relation>
+        </oai_dc:dc>
+      </metadata>
+    </record>
+    <record>
+      <header status="deleted"><identifier>oai:repo:2</identifier><datestamp>2024-05-03</datestamp></header>
+    </record>"#;
+
+    #[test]
+    fn test_parse_oai_pmh_page() {
+        let harvester = Harvester::new("https://repo.example.org/oai", HarvestProtocol::OaiPmh);
+        let page = harvester
+            .parse_page(&oai_page(RECORDS, "next-1"), "https://repo.example.org/oai")
+            .unwrap();
+        assert_eq!(
+            page.records,
+            vec![
+                HarvestRecord {
+                    identifier: "oai:repo:1".to_string(),
+                    datestamp: "2024-05-01".to_string(),
+                    url: Some(
+                        "https://repo.example.org/records/1/ro-crate-metadata.json".to_string()
+                    ),
+                    deleted: false,
+                },
+                HarvestRecord {
+                    identifier: "oai:repo:2".to_string(),
+                    datestamp: "2024-05-03".to_string(),
+                    url: None,
+                    deleted: true,
+                },
+            ]
+        );
+        assert_eq!(page.next.as_deref(), Some("next-1"));
+
+        // The last page has an empty token
+        let page = harvester.parse_page(&oai_page("", ""), "").unwrap();
+        assert_eq!(page, HarvestPage::default());
+
+        let no_match = r#"<OAI-PMH><error code="noRecordsMatch">none</error></OAI-PMH>"#;
+        assert_eq!(
+            harvester.parse_page(no_match, "").unwrap(),
+            HarvestPage::default()
+        );
+        let bad = r#"<OAI-PMH><error code="badArgument">from is malformed</error></OAI-PMH>"#;
+        let err = harvester.parse_page(bad, "").unwrap_err();
+        assert_eq!(err.code(), "harvest_error");
+        assert!(err.to_string().contains("badArgument"));
+    }
+
+    #[test]
+    fn test_list_records() {
+        let mut harvester = Harvester::new("https://repo.example.org/oai", HarvestProtocol::OaiPmh);
+        harvester.set = Some("crates".to_string());
+        let pages = HashMap::from([
+            (
+                "https://repo.example.org/oai?verb=ListRecords&metadataPrefix=oai_dc&set=crates&from=2024-04-30",
+                oai_page(RECORDS, "a b"),
+            ),
+            (
+                "https://repo.example.org/oai?verb=ListRecords&resumptionToken=a+b",
+                oai_page("", ""),
+            ),
+        ]);
+        let records = harvester
+            .list_records(Some("2024-04-30"), |url| {
+                pages
+                    .get(url)
+                    .cloned()
+                    .ok_or_else(|| ConsolidateError::LoadError {
+                        path: url.to_string(),
+                        reason: "unexpected request".to_string(),
+                        source: None,
+                    })
+            })
+            .unwrap();
+        assert_eq!(records.len(), 2);
+
+        let mut state = HarvestState::default();
+        state.advance(&records);
+        assert_eq!(state.last_datestamp.as_deref(), Some("2024-05-03"));
+        state.advance(&[]);
+        assert_eq!(state.last_datestamp.as_deref(), Some("2024-05-03"));
+    }
+
+    #[test]
+    fn test_repeated_resumption_token() {
+        let harvester = Harvester::new("https://repo.example.org/oai", HarvestProtocol::OaiPmh);
+        let mut fetched = 0;
+        let err = harvester
+            .list_records(None, |_| {
+                fetched += 1;
+                Ok(oai_page("", "same"))
+            })
+            .unwrap_err();
+        assert_eq!(err.code(), "harvest_error");
+        assert!(err.to_string().contains("'same'"));
+        assert_eq!(fetched, 2);
+    }
+
+    #[test]
+    fn test_failed_records_retried() {
+        let record = |identifier: &str, datestamp: &str| HarvestRecord {
+            identifier: identifier.to_string(),
+            datestamp: datestamp.to_string(),
+            url: Some(format!("https://repo.example.org/{}/", identifier)),
+            deleted: false,
+        };
+        let state = HarvestState {
+            failed: vec![record("rec-1", "2024-05-01"), record("rec-2", "2024-05-01")],
+            ..HarvestState::default()
+        };
+        let state: HarvestState =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        let records = state.with_failed(vec![record("rec-2", "2024-05-04")]);
+        assert_eq!(
+            records,
+            vec![record("rec-1", "2024-05-01"), record("rec-2", "2024-05-04")]
+        );
+    }
+
+    #[test]
+    fn test_list_json_records() {
+        let harvester = Harvester::new(
+            "https://repo.example.org/api/records",
+            HarvestProtocol::Json,
+        );
+        let pages = HashMap::from([
+            (
+                "https://repo.example.org/api/records",
+                json!({
+                    "records": [{
+                        "identifier": "rec-1",
+                        "datestamp": "2024-05-01",
+                        "url": "https://repo.example.org/rec-1/"
+                    }],
+                    "next": "records?page=2"
+                }),
+            ),
+            (
+                "https://repo.example.org/api/records?page=2",
+                json!({
+                    "records": [{"identifier": "rec-2", "datestamp": "2024-05-02", "deleted": true}]
+                }),
+            ),
+        ]);
+        let records = harvester
+            .list_records(None, |url| Ok(pages[url].to_string()))
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].url.as_deref(),
+            Some("https://repo.example.org/rec-1/")
+        );
+        assert!(records[1].deleted);
+
+        let err = harvester.parse_page("{}", "").unwrap_err();
+        assert_eq!(err.code(), "harvest_error");
+    }
+}
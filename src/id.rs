@@ -1,10 +1,18 @@
 //! ID classification and rewriting for RO-Crate consolidation
 //!
 //! Handles the transformation of entity @ids when consolidating subcrates
-//! into a parent crate's namespace.
+//! into a parent crate's namespace. Functions return borrowed ids when they
+//! leave them unchanged, so absolute URIs and the like are never copied.
 
+use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
 
+use icu_normalizer::ComposingNormalizerBorrowed;
+use serde::{Deserialize, Serialize};
+
+use crate::metadata_file::is_metadata_file;
+use crate::vocab::{METADATA_DESCRIPTOR_ID, PREVIEW_ID};
+
 /// Classification of an entity @id
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum IdKind {
@@ -17,14 +25,18 @@ pub enum IdKind {
     /// Absolute URI: "https://...", "http://...", "urn:..."
     Absolute,
     /// Metadata descriptor: "ro-crate-metadata.json" or variants
+    /// like "prefix-ro-crate-metadata.json" (see [`crate::metadata_file`])
     MetadataDescriptor,
 }
 
 /// Classify an @id string
+///
+/// A descriptor filename inside a folder ("./sub/ro-crate-metadata.json")
+/// is a file of the crate, not its descriptor, and classifies as relative.
 pub fn classify_id(id: &str) -> IdKind {
     if id == "./" {
         IdKind::Root
-    } else if id.ends_with("ro-crate-metadata.json") {
+    } else if is_descriptor_id(id) {
         IdKind::MetadataDescriptor
     } else if id.starts_with('#') {
         IdKind::Fragment
@@ -40,6 +52,22 @@ pub fn classify_id(id: &str) -> IdKind {
     }
 }
 
+/// Check if an @id names a metadata descriptor of the crate itself
+fn is_descriptor_id(id: &str) -> bool {
+    if !is_metadata_file(id) {
+        return false;
+    }
+    if id.contains("://") {
+        return true;
+    }
+    !id.strip_prefix("./").unwrap_or(id).contains('/')
+}
+
+/// Check if an @id is a malformed spelling of the root entity (e.g. ".//" or ".")
+pub fn is_root_alias(id: &str) -> bool {
+    id != "./" && id.trim_end_matches('/') == "."
+}
+
 /// Rewrite an @id to include a namespace prefix
 ///
 /// # Arguments
@@ -48,52 +76,69 @@ pub fn classify_id(id: &str) -> IdKind {
 /// * `used_fragments` - Set of already-used fragment IDs (for collision detection)
 ///
 /// # Returns
-/// The rewritten ID and whether it was actually changed
-pub fn rewrite_id(
-    id: &str,
+/// The rewritten ID (borrowed from `id` if unchanged) and whether it was actually changed
+pub fn rewrite_id<'a>(
+    id: &'a str,
     namespace: &str,
     used_fragments: &mut HashSet<String>,
-) -> (String, bool) {
+) -> (Cow<'a, str>, bool) {
     if namespace.is_empty() {
-        return (id.to_string(), false);
+        return (Cow::Borrowed(id), false);
     }
 
     match classify_id(id) {
         IdKind::Root => {
             // "./" becomes "./namespace/"
-            (format!("./{}/", namespace), true)
+            (Cow::Owned(format!("./{}/", namespace)), true)
         }
         IdKind::Relative => {
             // "./foo" becomes "./namespace/foo"
             // "foo" becomes "./namespace/foo"
             let clean_id = id.strip_prefix("./").unwrap_or(id);
-            (format!("./{}/{}", namespace, clean_id), true)
+            (Cow::Owned(format!("./{}/{}", namespace, clean_id)), true)
         }
         IdKind::Fragment => {
             // "#foo" stays "#foo" if unique, becomes "#namespace-foo" if collision
             if used_fragments.contains(id) {
-                let new_id = format!("#{}-{}", namespace, &id[1..]);
+                let new_id = namespace_fragment(id, namespace);
                 used_fragments.insert(new_id.clone());
-                (new_id, true)
+                (Cow::Owned(new_id), true)
             } else {
                 used_fragments.insert(id.to_string());
-                (id.to_string(), false)
+                (Cow::Borrowed(id), false)
             }
         }
         IdKind::Absolute | IdKind::MetadataDescriptor => {
             // Absolute IDs are never rewritten
             // Metadata descriptors are dropped, not rewritten
-            (id.to_string(), false)
+            (Cow::Borrowed(id), false)
         }
     }
 }
 
+/// Prefix a fragment id with its namespace ("#foo" -> "#namespace-foo")
+pub(crate) fn namespace_fragment(id: &str, namespace: &str) -> String {
+    format!("#{}-{}", namespace, &id[1..])
+}
+
+/// How fragment ids of subcrates are rewritten
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FragmentIdPolicy {
+    /// Keep "#foo" unless another crate already used it
+    #[default]
+    KeepUnlessCollision,
+    /// Always rewrite to "#namespace-foo", so ids don't depend on traversal order
+    AlwaysNamespace,
+}
+
 /// Build an ID mapping for all entities in a namespace
 ///
 /// # Arguments
 /// * `ids` - Iterator of original @ids from a crate
 /// * `namespace` - The namespace prefix to apply
 /// * `used_fragments` - Mutable set tracking fragment ID usage across all crates
+/// * `fragment_policy` - How fragment ids are rewritten
 ///
 /// # Returns
 /// HashMap from original ID to rewritten ID
@@ -101,33 +146,60 @@ pub fn build_id_map<'a>(
     ids: impl Iterator<Item = &'a str>,
     namespace: &str,
     used_fragments: &mut HashSet<String>,
+    fragment_policy: FragmentIdPolicy,
 ) -> HashMap<String, String> {
     let mut map = HashMap::new();
+    extend_id_map(&mut map, ids, namespace, used_fragments, fragment_policy);
+    map
+}
 
+/// Add the ID mapping for all entities in a namespace to an existing map
+///
+/// Like [`build_id_map`], but lets callers reuse one map's allocation
+/// across crates by clearing it in between.
+pub fn extend_id_map<'a>(
+    map: &mut HashMap<String, String>,
+    ids: impl Iterator<Item = &'a str>,
+    namespace: &str,
+    used_fragments: &mut HashSet<String>,
+    fragment_policy: FragmentIdPolicy,
+) {
+    map.reserve(ids.size_hint().0);
     for id in ids {
-        let (rewritten, changed) = rewrite_id(id, namespace, used_fragments);
+        let (rewritten, changed) = match fragment_policy {
+            FragmentIdPolicy::AlwaysNamespace
+                if !namespace.is_empty() && classify_id(id) == IdKind::Fragment =>
+            {
+                let new_id = namespace_fragment(id, namespace);
+                used_fragments.insert(new_id.clone());
+                (Cow::Owned(new_id), true)
+            }
+            _ => rewrite_id(id, namespace, used_fragments),
+        };
         if changed {
-            map.insert(id.to_string(), rewritten);
+            map.insert(id.to_string(), rewritten.into_owned());
         }
     }
-
-    map
 }
 
 /// Rewrite @id references within a JSON value (recursive)
 ///
-/// Finds all {"@id": "..."} patterns and rewrites them using the provided map
+/// Finds all {"@id": "..."} patterns and rewrites them using the provided map.
+/// `contentUrl` values point at data outside the crate and are left untouched.
 pub fn rewrite_references(value: &mut serde_json::Value, id_map: &HashMap<String, String>) {
     match value {
         serde_json::Value::Object(obj) => {
             // Check if this is an @id reference object
-            if let Some(serde_json::Value::String(id_val)) = obj.get("@id") {
-                if let Some(new_id) = id_map.get(id_val) {
-                    obj.insert("@id".to_string(), serde_json::Value::String(new_id.clone()));
+            if let Some(serde_json::Value::String(id_val)) = obj.get_mut("@id") {
+                if let Some(new_id) = id_map.get(id_val.as_str()) {
+                    id_val.clone_from(new_id);
                 }
             }
             // Recurse into all values
-            for (_, v) in obj.iter_mut() {
+            for (key, v) in obj.iter_mut() {
+                if key == "contentUrl" {
+                    continue;
+                }
                 rewrite_references(v, id_map);
             }
         }
@@ -140,12 +212,140 @@ pub fn rewrite_references(value: &mut serde_json::Value, id_map: &HashMap<String
     }
 }
 
+/// Normalize an @id to Unicode NFC
+///
+/// Filenames created on macOS are often decomposed ("e" + combining acute)
+/// while the same name typed elsewhere is precomposed ("é").
+pub fn normalize_id(id: &str) -> Cow<'_, str> {
+    ComposingNormalizerBorrowed::new_nfc().normalize(id)
+}
+
+/// Normalize all @ids within a JSON value to Unicode NFC (recursive)
+///
+/// Includes the value's own @id. `contentUrl` values are left untouched.
+pub fn normalize_references(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(obj) => {
+            if let Some(serde_json::Value::String(id_val)) = obj.get_mut("@id") {
+                if let Cow::Owned(normalized) = normalize_id(id_val) {
+                    *id_val = normalized;
+                }
+            }
+            for (key, v) in obj.iter_mut() {
+                if key == "contentUrl" {
+                    continue;
+                }
+                normalize_references(v);
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for item in arr.iter_mut() {
+                normalize_references(item);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Remove @id references to any of `ids` within a JSON value (recursive)
+///
+/// Properties left without values are removed entirely. The value's own
+/// @id is never touched.
+pub fn remove_references(value: &mut serde_json::Value, ids: &HashSet<String>) {
+    if ids.is_empty() {
+        return;
+    }
+    let is_removed = |v: &serde_json::Value| {
+        v.get("@id")
+            .and_then(|id| id.as_str())
+            .is_some_and(|id| ids.contains(id))
+    };
+    match value {
+        serde_json::Value::Object(obj) => {
+            obj.retain(|key, v| {
+                if key == "@id" {
+                    return true;
+                }
+                if is_removed(v) {
+                    return false;
+                }
+                if let serde_json::Value::Array(arr) = v {
+                    let before = arr.len();
+                    arr.retain(|item| !is_removed(item));
+                    return before == 0 || !arr.is_empty();
+                }
+                true
+            });
+            for v in obj.values_mut() {
+                remove_references(v, ids);
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for item in arr.iter_mut() {
+                remove_references(item, ids);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Rebase a root or relative @id onto an absolute base IRI
+///
+/// "./" -> "https://example.org/pub/"
+/// "./data/file.csv" -> "https://example.org/pub/data/file.csv"
+///
+/// Other ids (fragments, absolute URIs, metadata descriptors) are returned unchanged.
+pub fn rebase_id<'a>(id: &'a str, base_id: &str) -> Cow<'a, str> {
+    match classify_id(id) {
+        IdKind::Root | IdKind::Relative => {
+            let base = base_id.trim_end_matches('/');
+            let relative = id.strip_prefix("./").unwrap_or(id);
+            Cow::Owned(format!("{}/{}", base, relative))
+        }
+        _ => Cow::Borrowed(id),
+    }
+}
+
+/// Rebase an owned @id in place, leaving it untouched if [`rebase_id`] would
+pub fn rebase_id_in_place(id: &mut String, base_id: &str) {
+    if let Cow::Owned(rebased) = rebase_id(id, base_id) {
+        *id = rebased;
+    }
+}
+
+/// Rebase all root and relative @id references within a JSON value (recursive)
+///
+/// Like [`rewrite_references`], `contentUrl` values are left untouched.
+pub fn rebase_references(value: &mut serde_json::Value, base_id: &str) {
+    match value {
+        serde_json::Value::Object(obj) => {
+            if let Some(serde_json::Value::String(id_val)) = obj.get_mut("@id") {
+                rebase_id_in_place(id_val, base_id);
+            }
+            for (key, v) in obj.iter_mut() {
+                if key == "contentUrl" {
+                    continue;
+                }
+                rebase_references(v, base_id);
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for item in arr.iter_mut() {
+                rebase_references(item, base_id);
+            }
+        }
+        _ => {}
+    }
+}
+
 /// Extract namespace from a folder-style @id
 ///
 /// "./experiments/" -> "experiments"
 /// "./data/raw/" -> "data/raw"
 /// "https://example.org/crate/experiments/" -> "experiments"
-pub fn namespace_from_folder_id(folder_id: &str) -> String {
+///
+/// The namespace is always a slice of `folder_id`.
+pub fn namespace_from_folder_id(folder_id: &str) -> &str {
     // Handle absolute URLs by extracting the last path segment
     if folder_id.starts_with("http://") || folder_id.starts_with("https://") {
         // Parse as URL and extract the path's last segment(s)
@@ -153,19 +353,17 @@ pub fn namespace_from_folder_id(folder_id: &str) -> String {
         if let Some(pos) = without_trailing.rfind('/') {
             let segment = &without_trailing[pos + 1..];
             if !segment.is_empty() {
-                return segment.to_string();
+                return segment;
             }
         }
         // Fallback: use the whole path after the host
         if let Some(start) = folder_id.find("://") {
             let after_scheme = &folder_id[start + 3..];
             if let Some(slash_pos) = after_scheme.find('/') {
-                return after_scheme[slash_pos + 1..]
-                    .trim_end_matches('/')
-                    .to_string();
+                return after_scheme[slash_pos + 1..].trim_end_matches('/');
             }
         }
-        return folder_id.to_string();
+        return folder_id;
     }
 
     // Handle relative paths
@@ -173,7 +371,6 @@ pub fn namespace_from_folder_id(folder_id: &str) -> String {
         .strip_prefix("./")
         .unwrap_or(folder_id)
         .trim_end_matches('/')
-        .to_string()
 }
 
 /// Validate a folder ID for use as a subcrate location
@@ -190,6 +387,55 @@ pub fn validate_folder_id(folder_id: &str) -> Result<(), String> {
     if folder_id.starts_with("http://") || folder_id.starts_with("https://") {
         return Err("Folder ID cannot be an absolute URL".to_string());
     }
+    validate_namespace(namespace_from_folder_id(folder_id))
+}
+
+/// Validate that a namespace has no reserved path segments
+///
+/// Segments may not be empty, "." or "..", nor be named like a metadata
+/// descriptor or the HTML preview: ids below such a folder would be
+/// mistaken for (or shadow) the crate's own files.
+pub fn validate_namespace(namespace: &str) -> Result<(), String> {
+    for segment in namespace.split('/') {
+        let reserved = match segment {
+            "" => Some("an empty segment"),
+            "." | ".." => Some("a '.' segment"),
+            PREVIEW_ID => Some("the preview filename"),
+            s if s.ends_with(METADATA_DESCRIPTOR_ID) => Some("a metadata descriptor name"),
+            _ => None,
+        };
+        if let Some(reason) = reserved {
+            return Err(format!("Namespace '{}' contains {}", namespace, reason));
+        }
+    }
+    Ok(())
+}
+
+/// Validate a base IRI for rebasing consolidated ids
+pub fn validate_base_id(base_id: &str) -> Result<(), String> {
+    if classify_id(base_id) != IdKind::Absolute {
+        return Err(format!("Base ID must be an absolute IRI: {}", base_id));
+    }
+    Ok(())
+}
+
+/// Validate an @id for the consolidated document's metadata descriptor
+///
+/// Must be a plain filename ending in "ro-crate-metadata.json", e.g.
+/// "mycrate-ro-crate-metadata.json".
+pub fn validate_descriptor_id(descriptor_id: &str) -> Result<(), String> {
+    if classify_id(descriptor_id) != IdKind::MetadataDescriptor {
+        return Err(format!(
+            "Descriptor ID must end with 'ro-crate-metadata.json': {}",
+            descriptor_id
+        ));
+    }
+    if descriptor_id.contains('/') {
+        return Err(format!(
+            "Descriptor ID must be a filename, not a path: {}",
+            descriptor_id
+        ));
+    }
     Ok(())
 }
 
@@ -211,6 +457,14 @@ mod tests {
             classify_id("prefix-ro-crate-metadata.json"),
             IdKind::MetadataDescriptor
         );
+        assert_eq!(
+            classify_id("./prefix-ro-crate-metadata.json"),
+            IdKind::MetadataDescriptor
+        );
+        assert_eq!(
+            classify_id("./sub/ro-crate-metadata.json"),
+            IdKind::Relative
+        );
     }
 
     #[test]
@@ -258,6 +512,7 @@ mod tests {
             rewrite_id("https://orcid.org/0000-0001", "experiments", &mut fragments);
         assert_eq!(result, "https://orcid.org/0000-0001");
         assert!(!changed);
+        assert!(matches!(result, Cow::Borrowed(_)));
     }
 
     #[test]
@@ -269,6 +524,55 @@ mod tests {
         assert!(!changed);
     }
 
+    #[test]
+    fn test_build_id_map_always_namespace() {
+        let mut fragments = HashSet::new();
+        let ids = ["#person1", "./data.csv"];
+
+        let map = build_id_map(
+            ids.into_iter(),
+            "experiments",
+            &mut fragments,
+            FragmentIdPolicy::AlwaysNamespace,
+        );
+        assert_eq!(map["#person1"], "#experiments-person1");
+        assert_eq!(map["./data.csv"], "./experiments/data.csv");
+
+        let map = build_id_map(
+            ids.into_iter(),
+            "",
+            &mut fragments,
+            FragmentIdPolicy::AlwaysNamespace,
+        );
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_extend_id_map_reuses_map() {
+        let mut fragments = HashSet::new();
+        let mut map = HashMap::new();
+
+        extend_id_map(
+            &mut map,
+            ["./a.csv"].into_iter(),
+            "first",
+            &mut fragments,
+            FragmentIdPolicy::default(),
+        );
+        assert_eq!(map["./a.csv"], "./first/a.csv");
+
+        map.clear();
+        extend_id_map(
+            &mut map,
+            ["./b.csv", "https://orcid.org/0000-0001"].into_iter(),
+            "second",
+            &mut fragments,
+            FragmentIdPolicy::default(),
+        );
+        assert_eq!(map.len(), 1);
+        assert_eq!(map["./b.csv"], "./second/b.csv");
+    }
+
     #[test]
     fn test_namespace_from_folder_id() {
         assert_eq!(namespace_from_folder_id("./experiments/"), "experiments");
@@ -286,6 +590,78 @@ mod tests {
         assert!(validate_folder_id("./").is_err());
         assert!(validate_folder_id("./experiments").is_err()); // missing trailing /
         assert!(validate_folder_id("https://example.org/").is_err());
+        assert!(validate_folder_id("./ro-crate-metadata.json/").is_err());
+        assert!(validate_folder_id("./data/../raw/").is_err());
+        assert!(validate_folder_id("./data//raw/").is_err());
+    }
+
+    #[test]
+    fn test_normalize_references() {
+        let decomposed = "./Re\u{301}sume\u{301}/data.csv";
+        let precomposed = "./R\u{e9}sum\u{e9}/data.csv";
+        assert_eq!(normalize_id(decomposed), precomposed);
+        assert!(matches!(normalize_id(precomposed), Cow::Borrowed(_)));
+
+        let mut value = serde_json::json!({
+            "@id": decomposed,
+            "hasPart": [{"@id": decomposed}],
+            "name": decomposed
+        });
+        normalize_references(&mut value);
+        assert_eq!(value["@id"], precomposed);
+        assert_eq!(value["hasPart"][0]["@id"], precomposed);
+        // Only ids are normalized
+        assert_eq!(value["name"], decomposed);
+    }
+
+    #[test]
+    fn test_validate_namespace() {
+        assert!(validate_namespace("data/raw").is_ok());
+        assert!(validate_namespace("ro-crate-metadata").is_ok());
+
+        assert!(validate_namespace("ro-crate-metadata.json").is_err());
+        assert!(validate_namespace("data/x-ro-crate-metadata.json").is_err());
+        assert!(validate_namespace("ro-crate-preview.html").is_err());
+        assert!(validate_namespace("data/./raw").is_err());
+        assert!(validate_namespace("..").is_err());
+    }
+
+    #[test]
+    fn test_rebase_references() {
+        let mut value = serde_json::json!({
+            "@id": "ro-crate-metadata.json",
+            "about": {"@id": "./"},
+            "hasPart": [
+                {"@id": "./experiments/data.csv"},
+                {"@id": "#person1"},
+                {"@id": "https://external.org/resource"}
+            ],
+            "contentUrl": {"@id": "./raw.bin"}
+        });
+
+        rebase_references(&mut value, "https://example.org/pub");
+
+        assert_eq!(value["@id"], "ro-crate-metadata.json");
+        assert_eq!(value["about"]["@id"], "https://example.org/pub/");
+        assert_eq!(
+            value["hasPart"][0]["@id"],
+            "https://example.org/pub/experiments/data.csv"
+        );
+        assert_eq!(value["hasPart"][1]["@id"], "#person1");
+        assert_eq!(value["hasPart"][2]["@id"], "https://external.org/resource");
+        assert_eq!(value["contentUrl"]["@id"], "./raw.bin");
+        assert!(matches!(
+            rebase_id("https://external.org/resource", "https://example.org/pub"),
+            Cow::Borrowed(_)
+        ));
+
+        assert!(validate_base_id("https://example.org/pub/").is_ok());
+        assert!(validate_base_id("./pub/").is_err());
+
+        assert!(validate_descriptor_id("ro-crate-metadata.json").is_ok());
+        assert!(validate_descriptor_id("mycrate-ro-crate-metadata.json").is_ok());
+        assert!(validate_descriptor_id("./out/ro-crate-metadata.json").is_err());
+        assert!(validate_descriptor_id("metadata.json").is_err());
     }
 
     #[test]
@@ -312,4 +688,30 @@ mod tests {
         // External reference unchanged (not in map)
         assert_eq!(value["hasPart"][1]["@id"], "https://external.org/resource");
     }
+
+    #[test]
+    fn test_remove_references() {
+        let mut value = serde_json::json!({
+            "@id": "./",
+            "subjectOf": {"@id": "prefix-ro-crate-metadata.json"},
+            "hasPart": [
+                {"@id": "./data.csv"},
+                {"@id": "prefix-ro-crate-metadata.json"}
+            ],
+            "mentions": [{"@id": "prefix-ro-crate-metadata.json"}],
+            "keywords": []
+        });
+        let ids = HashSet::from(["prefix-ro-crate-metadata.json".to_string()]);
+
+        remove_references(&mut value, &ids);
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "@id": "./",
+                "hasPart": [{"@id": "./data.csv"}],
+                "keywords": []
+            })
+        );
+    }
 }
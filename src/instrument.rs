@@ -0,0 +1,180 @@
+//! Instrumentation of subcrate loading
+//!
+//! Services embedding the library can export metrics of the subcrates they
+//! load by wrapping any loader in an [`InstrumentedLoader`], which reports
+//! each load to a callback:
+//!
+//! ```ignore
+//! let loader = InstrumentedLoader::new(UrlLoader::new(base), |event: &LoadEvent| {
+//!     histogram.observe(event.duration.as_secs_f64());
+//!     bytes_fetched.inc_by(event.bytes);
+//! });
+//! ```
+//!
+//! What a load read is taken from the [`AuditLog`]: files, archive members
+//! and URLs read by this library's loaders are counted, while those of other
+//! loaders are not. An active audit log still records all accesses.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::audit::{self, AccessKind, AuditLog};
+use crate::consolidate::{SubcrateLoader, SubcrateRef};
+use crate::error::ConsolidateError;
+
+/// One subcrate load, as reported by an [`InstrumentedLoader`]
+#[derive(Debug, Clone)]
+pub struct LoadEvent<'a> {
+    /// Id of the subcrate, as referenced by its parent
+    pub subcrate_id: &'a str,
+    /// Namespace of the parent crate
+    pub parent_namespace: &'a str,
+    /// How long loading took
+    pub duration: Duration,
+    /// Bytes read, from files, archives, the network or the HTTP cache
+    pub bytes: u64,
+    /// URLs served from the HTTP cache
+    pub cache_hits: usize,
+    /// URLs downloaded
+    pub cache_misses: usize,
+    /// Entities loaded, or the error loading failed with
+    pub outcome: Result<usize, &'a ConsolidateError>,
+}
+
+/// A loader reporting each load of its inner loader to a callback
+pub struct InstrumentedLoader<L, F> {
+    inner: L,
+    callback: F,
+}
+
+impl<L, F> InstrumentedLoader<L, F>
+where
+    L: SubcrateLoader,
+    F: Fn(&LoadEvent) + Sync,
+{
+    pub fn new(inner: L, callback: F) -> Self {
+        Self { inner, callback }
+    }
+}
+
+impl<L, F> SubcrateLoader for InstrumentedLoader<L, F>
+where
+    L: SubcrateLoader,
+    F: Fn(&LoadEvent) + Sync,
+{
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        self.load_ref(&SubcrateRef::new(
+            subcrate_id,
+            parent_namespace,
+            subcrate_entity,
+        ))
+    }
+
+    fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+        // Record the load's accesses on their own, then pass them on to the
+        // log active before
+        let outer = audit::active();
+        let log = Arc::new(AuditLog::new());
+        let started = Instant::now();
+        let result = {
+            let _active = log.activate();
+            self.inner.load_ref(subcrate)
+        };
+        let duration = started.elapsed();
+
+        let records = log.records();
+        let urls = records.iter().filter(|r| r.kind == AccessKind::Url);
+        let cache_hits = urls.clone().filter(|r| r.cached).count();
+        (self.callback)(&LoadEvent {
+            subcrate_id: subcrate.id,
+            parent_namespace: subcrate.parent_namespace,
+            duration,
+            bytes: records.iter().map(|r| r.bytes).sum(),
+            cache_hits,
+            cache_misses: urls.count() - cache_hits,
+            outcome: result.as_ref().map(Vec::len),
+        });
+        if let Some(outer) = outer {
+            records.into_iter().for_each(|record| outer.push(record));
+        }
+        result
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        self.inner.listed_subcrates(namespace)
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        self.inner
+            .location(subcrate_id, parent_namespace, subcrate_entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Loader reading a subcrate's metadata as if from a file
+    struct FileLoader;
+
+    impl SubcrateLoader for FileLoader {
+        fn load(
+            &self,
+            subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            if subcrate_id != "./exp/" {
+                return Err(ConsolidateError::InvalidStructure("missing".to_string()));
+            }
+            let content = br#"[{"@id": "./"}]"#;
+            audit::record(AccessKind::File, "exp.json", None, content);
+            Ok(serde_json::from_slice(content)?)
+        }
+    }
+
+    #[test]
+    fn test_instrumented_loader() {
+        let events = Mutex::new(Vec::new());
+        let loader = InstrumentedLoader::new(FileLoader, |event: &LoadEvent| {
+            let outcome = event.outcome.map_err(ConsolidateError::code);
+            events.lock().unwrap().push((
+                event.subcrate_id.to_string(),
+                event.bytes,
+                event.cache_misses,
+                outcome,
+            ));
+        });
+
+        let log = Arc::new(AuditLog::new());
+        let _active = log.activate();
+        assert!(loader.load("./exp/", "", Some(&json!({}))).is_ok());
+        assert!(loader.load("./other/", "", None).is_err());
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                ("./exp/".to_string(), 15, 0, Ok(1)),
+                ("./other/".to_string(), 0, 0, Err("invalid_structure")),
+            ]
+        );
+        // The accesses still reach the active log
+        assert_eq!(log.records().len(), 1);
+    }
+}
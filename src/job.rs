@@ -0,0 +1,1085 @@
+//! Consolidation jobs for service deployments
+//!
+//! Remote consolidations can run for hours, longer than a client should
+//! wait on a single request. A [`JobQueue`] instead accepts a [`JobRequest`],
+//! returns its id right away and runs it on a worker thread; clients then
+//! poll the [`Job`] for its state and progress and fetch the consolidated
+//! crate once it has completed.
+//!
+//! Jobs and their results are kept in a [`JobStore`]: [`MemoryJobStore`]
+//! for a single process, [`DirJobStore`] to survive restarts. Jobs that were
+//! queued or running when the queue was created are run (again).
+//!
+//! To trigger downstream work, a finished job is POSTed as JSON to the
+//! request's callback URL, and passed to hooks registered with
+//! [`JobQueue::on_finish`]. Callback URLs come from clients, so they must
+//! use HTTP(S) and resolve to public addresses only, unless they start with
+//! a prefix allowed with [`JobQueue::allow_callbacks_to`]; the same holds
+//! for the crates jobs fetch. A runner that panics fails its job. Jobs the
+//! store can't load or save are passed to hooks registered with
+//! [`JobQueue::on_store_error`] instead, and run again when the queue is
+//! next created.
+//!
+//! Each job runs in the [`TenantContext`] it was submitted with, so jobs of
+//! different tenants share no credentials or cached documents. Contexts are
+//! kept in memory only: jobs resumed after a restart run without credentials.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use ulid::Ulid;
+
+use crate::audit::{AccessRecord, AuditLog};
+use crate::consolidate::{
+    consolidate, to_json_string, ConsolidateInput, ConsolidateOptions, ConsolidateResult,
+    ConsolidateStats, MergeCrate, NoOpLoader, PartialFailure, SubcrateLoader, SubcrateRef,
+};
+use crate::error::ConsolidateError;
+use crate::loader::UrlLoaderOptions;
+use crate::sandbox::public_address;
+use crate::tenant::TenantContext;
+
+/// What a job consolidates
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobRequest {
+    /// URL of the main crate
+    pub source: String,
+    /// Crates merged into the main crate; if empty, the main crate is
+    /// consolidated with its nested subcrates
+    pub merge: Vec<JobMerge>,
+    pub options: ConsolidateOptions,
+    /// URL the job is POSTed to as JSON once it has completed or failed
+    pub callback_url: Option<String>,
+}
+
+/// A crate merged into a job's main crate
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobMerge {
+    /// URL of the crate
+    pub source: String,
+    /// Folder the crate is placed under, e.g. "./imported/"
+    pub folder_id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Where a job is in its life cycle
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    /// Waiting for a worker
+    #[default]
+    Queued,
+    /// Being consolidated
+    Running,
+    /// Consolidated; the result is in the store. Partial consolidations
+    /// complete too, with the failure in the report.
+    Completed,
+    /// Consolidation failed without a result
+    Failed,
+}
+
+/// The error a job failed with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobError {
+    /// Stable code of the error (see [`ConsolidateError::code`]), or
+    /// "panicked" if the runner panicked
+    pub code: String,
+    pub message: String,
+}
+
+/// Outcome of a completed job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobReport {
+    pub stats: ConsolidateStats,
+    pub warnings: Vec<String>,
+    /// Set if the job's consolidation is partial
+    pub failure: Option<PartialFailure>,
+    /// Files and URLs read for the job
+    #[serde(default)]
+    pub accesses: Vec<AccessRecord>,
+}
+
+/// A submitted consolidation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    /// Unique id, a ULID so ids sort by submission time
+    pub id: String,
+    /// Id of the tenant the job runs for, empty if none
+    #[serde(default)]
+    pub tenant: String,
+    pub request: JobRequest,
+    pub state: JobState,
+    /// Number of crates loaded so far
+    pub crates_loaded: usize,
+    /// Report of a completed job
+    pub report: Option<JobReport>,
+    /// Error of a failed job
+    pub error: Option<JobError>,
+    /// Why the job's callback URL could not be notified
+    #[serde(default)]
+    pub callback_error: Option<String>,
+    /// How long a finished job ran, in milliseconds
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+/// Persistence of jobs and their results
+///
+/// Stores are shared between the queue's workers and must be thread-safe.
+pub trait JobStore: Send + Sync {
+    /// Insert or replace a job
+    fn save(&self, job: &Job) -> Result<(), ConsolidateError>;
+    /// The job with id `id`, if there is one
+    fn load(&self, id: &str) -> Result<Option<Job>, ConsolidateError>;
+    /// All jobs
+    fn list(&self) -> Result<Vec<Job>, ConsolidateError>;
+    /// Store the consolidated crate of a job
+    fn save_result(&self, id: &str, result: &str) -> Result<(), ConsolidateError>;
+    /// The consolidated crate of a job, if it has one
+    fn load_result(&self, id: &str) -> Result<Option<String>, ConsolidateError>;
+}
+
+/// Job store keeping everything in memory
+#[derive(Debug, Default)]
+pub struct MemoryJobStore {
+    jobs: Mutex<HashMap<String, Job>>,
+    results: Mutex<HashMap<String, String>>,
+}
+
+impl JobStore for MemoryJobStore {
+    fn save(&self, job: &Job) -> Result<(), ConsolidateError> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .insert(job.id.clone(), job.clone());
+        Ok(())
+    }
+
+    fn load(&self, id: &str) -> Result<Option<Job>, ConsolidateError> {
+        Ok(self.jobs.lock().unwrap().get(id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<Job>, ConsolidateError> {
+        Ok(self.jobs.lock().unwrap().values().cloned().collect())
+    }
+
+    fn save_result(&self, id: &str, result: &str) -> Result<(), ConsolidateError> {
+        self.results
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), result.to_string());
+        Ok(())
+    }
+
+    fn load_result(&self, id: &str) -> Result<Option<String>, ConsolidateError> {
+        Ok(self.results.lock().unwrap().get(id).cloned())
+    }
+}
+
+/// Job store keeping each job as `<id>.json` and its result as
+/// `<id>.result.json` in a directory
+#[derive(Debug)]
+pub struct DirJobStore {
+    dir: PathBuf,
+}
+
+impl DirJobStore {
+    /// Store in `dir`, which is created if missing
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, ConsolidateError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, id: &str, suffix: &str) -> Result<PathBuf, ConsolidateError> {
+        // Ids come from clients; don't let them name other files
+        if id.parse::<Ulid>().is_err() {
+            return Err(ConsolidateError::InvalidStructure(format!(
+                "invalid job id '{}'",
+                id
+            )));
+        }
+        Ok(self.dir.join(format!("{}{}", id, suffix)))
+    }
+
+    /// Write `content` to `path` through a temporary file, so readers never
+    /// see a partial file
+    fn write(path: &PathBuf, content: &str) -> Result<(), ConsolidateError> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    fn read(path: &PathBuf) -> Result<Option<String>, ConsolidateError> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl JobStore for DirJobStore {
+    fn save(&self, job: &Job) -> Result<(), ConsolidateError> {
+        Self::write(&self.path(&job.id, ".json")?, &serde_json::to_string(job)?)
+    }
+
+    fn load(&self, id: &str) -> Result<Option<Job>, ConsolidateError> {
+        match Self::read(&self.path(id, ".json")?)? {
+            Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<Job>, ConsolidateError> {
+        let mut jobs = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let name = entry?.file_name();
+            let Some(id) = name.to_str().and_then(|n| n.strip_suffix(".json")) else {
+                continue;
+            };
+            if id.ends_with(".result") {
+                continue;
+            }
+            if let Some(job) = self.load(id)? {
+                jobs.push(job);
+            }
+        }
+        Ok(jobs)
+    }
+
+    fn save_result(&self, id: &str, result: &str) -> Result<(), ConsolidateError> {
+        Self::write(&self.path(id, ".result.json")?, result)
+    }
+
+    fn load_result(&self, id: &str) -> Result<Option<String>, ConsolidateError> {
+        Self::read(&self.path(id, ".result.json")?)
+    }
+}
+
+/// What a job runs with besides its request
+pub struct JobContext<'a> {
+    /// Tenant the job runs for
+    pub tenant: &'a TenantContext,
+    /// Reports the number of crates loaded so far
+    pub progress: &'a (dyn Fn(usize) + Sync),
+}
+
+/// Runs a job's request
+pub type JobRunner =
+    dyn Fn(&JobRequest, &JobContext) -> Result<ConsolidateResult, ConsolidateError> + Send + Sync;
+
+/// Called with every job that has completed or failed
+pub type JobHook = dyn Fn(&Job) + Send + Sync;
+
+/// Called with the id of a job the store failed to load or save, and the
+/// error
+pub type StoreErrorHook = dyn Fn(&str, &ConsolidateError) + Send + Sync;
+
+type Hooks<T> = Arc<RwLock<Vec<Arc<T>>>>;
+
+/// Longest wait to connect to a callback URL
+const CALLBACK_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest wait for a callback request as a whole
+const CALLBACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Queue running submitted jobs on worker threads
+pub struct JobQueue {
+    store: Arc<dyn JobStore>,
+    sender: Mutex<mpsc::Sender<String>>,
+    hooks: Hooks<JobHook>,
+    store_error_hooks: Hooks<StoreErrorHook>,
+    /// Prefixes of callback and fetched URLs that may resolve to any
+    /// address
+    callback_prefixes: Arc<RwLock<Vec<String>>>,
+    /// How the contexts the queue makes fetch
+    url_options: Arc<RwLock<UrlLoaderOptions>>,
+    /// Contexts of the submitted jobs that have not run yet, by job id
+    tenants: Arc<Mutex<HashMap<String, Arc<TenantContext>>>>,
+}
+
+impl JobQueue {
+    /// Queue with `workers` threads running jobs with [`run_request`]
+    pub fn new(store: Arc<dyn JobStore>, workers: usize) -> Result<Self, ConsolidateError> {
+        Self::with_runner(store, workers, Arc::new(run_request))
+    }
+
+    /// Queue with `workers` threads running jobs with `runner`
+    ///
+    /// Jobs in `store` that are still queued or were running are queued
+    /// again, oldest first.
+    pub fn with_runner(
+        store: Arc<dyn JobStore>,
+        workers: usize,
+        runner: Arc<JobRunner>,
+    ) -> Result<Self, ConsolidateError> {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let hooks: Hooks<JobHook> = Arc::default();
+        let store_error_hooks: Hooks<StoreErrorHook> = Arc::default();
+        let callback_prefixes: Arc<RwLock<Vec<String>>> = Arc::default();
+        let url_options = Arc::new(RwLock::new(UrlLoaderOptions::DEFAULT));
+        let tenants: Arc<Mutex<HashMap<String, Arc<TenantContext>>>> = Arc::default();
+        for _ in 0..workers.max(1) {
+            let store = Arc::clone(&store);
+            let runner = Arc::clone(&runner);
+            let receiver = Arc::clone(&receiver);
+            let hooks = Arc::clone(&hooks);
+            let store_error_hooks = Arc::clone(&store_error_hooks);
+            let callback_prefixes = Arc::clone(&callback_prefixes);
+            let url_options = Arc::clone(&url_options);
+            let tenants = Arc::clone(&tenants);
+            thread::spawn(move || loop {
+                // Hold the lock only while waiting, not while running
+                let next = receiver.lock().unwrap().recv();
+                let Ok(id) = next else {
+                    // The queue was dropped
+                    break;
+                };
+                let tenant = tenants.lock().unwrap().remove(&id);
+                let prefixes = callback_prefixes.read().unwrap().clone();
+                let new_tenant =
+                    |id: String| tenant_context(id, &prefixes, url_options.read().unwrap().clone());
+                let (job, store_error) =
+                    match run_job(store.as_ref(), runner.as_ref(), tenant, new_tenant, &id) {
+                        Ok((job, store_error)) => (Some(job), store_error),
+                        Err(e) => (None, Some(e)),
+                    };
+                // A panicking hook must not take the worker with it
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                    if let Some(e) = store_error {
+                        for hook in store_error_hooks.read().unwrap().clone() {
+                            hook(&id, &e);
+                        }
+                    }
+                    if let Some(job) = job {
+                        finish_job(store.as_ref(), &hooks, &prefixes, job);
+                    }
+                }));
+            });
+        }
+
+        let mut pending: Vec<Job> = store
+            .list()?
+            .into_iter()
+            .filter(|job| matches!(job.state, JobState::Queued | JobState::Running))
+            .collect();
+        pending.sort_by(|a, b| a.id.cmp(&b.id));
+        for job in pending {
+            sender.send(job.id).map_err(|_| workers_stopped())?;
+        }
+
+        Ok(Self {
+            store,
+            sender: Mutex::new(sender),
+            hooks,
+            store_error_hooks,
+            callback_prefixes,
+            url_options,
+            tenants,
+        })
+    }
+
+    /// Call `hook` with every job that completes or fails from now on
+    pub fn on_finish(&self, hook: impl Fn(&Job) + Send + Sync + 'static) {
+        self.hooks.write().unwrap().push(Arc::new(hook));
+    }
+
+    /// Call `hook` whenever the store fails to load or save a job from now
+    /// on
+    ///
+    /// Such jobs keep the state they have in the store.
+    pub fn on_store_error(&self, hook: impl Fn(&str, &ConsolidateError) + Send + Sync + 'static) {
+        self.store_error_hooks.write().unwrap().push(Arc::new(hook));
+    }
+
+    /// Let callback URLs starting with `prefix` resolve to any address,
+    /// e.g. "http://127.0.0.1:8080/" for a hook running next to the service
+    ///
+    /// Contexts made by [`JobQueue::tenant_context`] from now on may fetch
+    /// such URLs too.
+    pub fn allow_callbacks_to(&self, prefix: impl Into<String>) {
+        self.callback_prefixes.write().unwrap().push(prefix.into());
+    }
+
+    /// Fetch with `options` in contexts made from now on, instead of
+    /// [`UrlLoaderOptions::DEFAULT`]
+    ///
+    /// Their credentials and cache are ignored, as by
+    /// [`TenantContext::with_url_options`].
+    pub fn set_url_options(&self, options: UrlLoaderOptions) {
+        *self.url_options.write().unwrap() = options;
+    }
+
+    /// Context of the tenant `id`, fetching from the prefixes allowed with
+    /// [`JobQueue::allow_callbacks_to`] besides public addresses, with the
+    /// options set with [`JobQueue::set_url_options`]
+    pub fn tenant_context(&self, id: impl Into<String>) -> TenantContext {
+        tenant_context(
+            id,
+            &self.callback_prefixes.read().unwrap(),
+            self.url_options.read().unwrap().clone(),
+        )
+    }
+
+    /// Queue a consolidation without tenant, returning the queued job
+    pub fn submit(&self, request: JobRequest) -> Result<Job, ConsolidateError> {
+        self.submit_for(Arc::new(self.tenant_context("")), request)
+    }
+
+    /// Queue a consolidation running in `tenant`'s context, returning the
+    /// queued job
+    pub fn submit_for(
+        &self,
+        tenant: Arc<TenantContext>,
+        request: JobRequest,
+    ) -> Result<Job, ConsolidateError> {
+        if let Some(url) = &request.callback_url {
+            check_callback_scheme(url).map_err(|reason| ConsolidateError::SandboxViolation {
+                location: url.clone(),
+                reason,
+            })?;
+        }
+        let job = Job {
+            id: Ulid::new().to_string(),
+            tenant: tenant.id().to_string(),
+            request,
+            state: JobState::Queued,
+            crates_loaded: 0,
+            report: None,
+            error: None,
+            callback_error: None,
+            duration_ms: None,
+        };
+        self.store.save(&job)?;
+        self.tenants.lock().unwrap().insert(job.id.clone(), tenant);
+        let sent = self.sender.lock().unwrap().send(job.id.clone());
+        if sent.is_err() {
+            self.tenants.lock().unwrap().remove(&job.id);
+            let mut failed = job;
+            failed.state = JobState::Failed;
+            failed.error = Some(JobError {
+                code: "io".to_string(),
+                message: workers_stopped().to_string(),
+            });
+            self.store.save(&failed)?;
+            return Err(workers_stopped());
+        }
+        Ok(job)
+    }
+
+    /// The job with id `id`, if there is one
+    pub fn job(&self, id: &str) -> Result<Option<Job>, ConsolidateError> {
+        self.store.load(id)
+    }
+
+    /// The consolidated crate of a completed job, as JSON-LD
+    pub fn result(&self, id: &str) -> Result<Option<String>, ConsolidateError> {
+        self.store.load_result(id)
+    }
+}
+
+/// Error of jobs submitted after the workers stopped
+fn workers_stopped() -> ConsolidateError {
+    ConsolidateError::Io(std::io::Error::other("the job workers have stopped"))
+}
+
+/// Context of the tenant `id` fetching with `options`, and URLs starting
+/// with one of `prefixes` from any address
+fn tenant_context(
+    id: impl Into<String>,
+    prefixes: &[String],
+    options: UrlLoaderOptions,
+) -> TenantContext {
+    let tenant = TenantContext::new(id).with_url_options(options);
+    prefixes.iter().fold(tenant, |tenant, prefix| {
+        tenant.allow_fetches_to(prefix.clone())
+    })
+}
+
+/// Run the job with id `id` in `tenant`'s context, recording its progress
+/// and outcome in `store`
+///
+/// Jobs without a context get a fresh one of their tenant from
+/// `new_tenant`. Fails if the
+/// store can't load the job or mark it running; returns the finished job
+/// and the store's error if its outcome could not be saved.
+fn run_job(
+    store: &dyn JobStore,
+    runner: &JobRunner,
+    tenant: Option<Arc<TenantContext>>,
+    new_tenant: impl FnOnce(String) -> TenantContext,
+    id: &str,
+) -> Result<(Job, Option<ConsolidateError>), ConsolidateError> {
+    let Some(mut job) = store.load(id)? else {
+        return Err(ConsolidateError::InvalidStructure(format!(
+            "job '{}' is not in the store",
+            id
+        )));
+    };
+    let tenant = tenant.unwrap_or_else(|| Arc::new(new_tenant(job.tenant.clone())));
+    job.state = JobState::Running;
+    job.crates_loaded = 0;
+    store.save(&job)?;
+
+    let started = Instant::now();
+    let progress_job = Mutex::new(job.clone());
+    let progress = |crates_loaded: usize| {
+        let mut job = progress_job.lock().unwrap();
+        job.crates_loaded = crates_loaded;
+        let _ = store.save(&job);
+    };
+    let context = JobContext {
+        tenant: &tenant,
+        progress: &progress,
+    };
+    let audit_log = Arc::new(AuditLog::new());
+    let _audit = audit_log.activate();
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        runner(&job.request, &context).and_then(|result| {
+            let output = to_json_string(&result, false)?;
+            store.save_result(&job.id, &output)?;
+            Ok(result)
+        })
+    }));
+
+    // A panic may have poisoned the progress lock mid-update; the count is
+    // still the last one reported
+    job.crates_loaded = progress_job
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .crates_loaded;
+    match outcome {
+        Ok(Ok(result)) => {
+            job.state = JobState::Completed;
+            job.crates_loaded = result.stats.crates_consolidated;
+            job.report = Some(JobReport {
+                stats: result.stats,
+                warnings: result.warnings,
+                failure: result.failure,
+                accesses: audit_log.records(),
+            });
+        }
+        Ok(Err(err)) => {
+            job.state = JobState::Failed;
+            job.error = Some(JobError {
+                code: err.code().to_string(),
+                message: err.to_string(),
+            });
+        }
+        Err(panic) => {
+            job.state = JobState::Failed;
+            job.error = Some(JobError {
+                code: "panicked".to_string(),
+                message: format!("the job runner panicked: {}", panic_message(&panic)),
+            });
+        }
+    }
+    job.duration_ms = Some(started.elapsed().as_millis() as u64);
+    // The job finished all the same; its hooks still learn how
+    let store_error = store.save(&job).err();
+    Ok((job, store_error))
+}
+
+/// The message a panic was raised with
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("no message")
+}
+
+/// Tell the hooks and the callback URL that `job` has finished
+///
+/// Callback URLs starting with one of `prefixes` may resolve to any
+/// address.
+fn finish_job(
+    store: &dyn JobStore,
+    hooks: &RwLock<Vec<Arc<JobHook>>>,
+    prefixes: &[String],
+    mut job: Job,
+) {
+    // Don't hold the lock while hooks run, so they may register others
+    let hooks = hooks.read().unwrap().clone();
+    for hook in hooks {
+        hook(&job);
+    }
+    if let Some(url) = &job.request.callback_url {
+        if let Err(e) = notify(url, &job, prefixes) {
+            job.callback_error = Some(e);
+            let _ = store.save(&job);
+        }
+    }
+}
+
+/// POST `job` as JSON to `url`, unless it resolves to an address that
+/// isn't public and doesn't start with one of `prefixes`
+fn notify(url: &str, job: &Job, prefixes: &[String]) -> Result<(), String> {
+    let body = serde_json::to_string(job).map_err(|e| e.to_string())?;
+    // Redirects could lead anywhere
+    let mut client = reqwest::blocking::Client::builder()
+        .connect_timeout(CALLBACK_CONNECT_TIMEOUT)
+        .timeout(CALLBACK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none());
+    if !prefixes
+        .iter()
+        .any(|prefix| url.starts_with(prefix.as_str()))
+    {
+        // Connect to the address checked, not to one resolved again later
+        let (host, addr) = public_address(&check_callback_scheme(url)?)?;
+        client = client.resolve(&host, addr);
+    }
+    client
+        .build()
+        .map_err(|e| e.to_string())?
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .and_then(|response| response.error_for_status())
+        .map(drop)
+        .map_err(|e| format!("notifying {} failed: {}", url, e))
+}
+
+/// Fail unless `url` is an HTTP(S) URL
+fn check_callback_scheme(url: &str) -> Result<url::Url, String> {
+    let parsed = url::Url::parse(url).map_err(|e| format!("invalid callback URL: {}", e))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(parsed),
+        scheme => Err(format!("callback URLs can't use scheme '{}'", scheme)),
+    }
+}
+
+/// Load and consolidate the crates of a job from their URLs, fetched in the
+/// job's tenant context
+///
+/// Like the CLI, a request without merge crates consolidates the main crate
+/// with its nested subcrates, loaded relative to its URL, and a request with
+/// merge crates merges only the crates given.
+pub fn run_request(
+    request: &JobRequest,
+    context: &JobContext,
+) -> Result<ConsolidateResult, ConsolidateError> {
+    let loaded = AtomicUsize::new(0);
+    let load_graph = |url: &str| {
+        let graph = context.tenant.load_graph(url)?;
+        (context.progress)(loaded.fetch_add(1, Ordering::Relaxed) + 1);
+        Ok::<_, ConsolidateError>(graph)
+    };
+
+    let main = load_graph(&request.source)?;
+    if request.merge.is_empty() {
+        let loader = CountingLoader {
+            inner: context.tenant.url_loader(&request.source),
+            loaded: &loaded,
+            progress: context.progress,
+        };
+        return consolidate(ConsolidateInput::Single(main), &loader, &request.options);
+    }
+    let others = request
+        .merge
+        .iter()
+        .map(|merge| {
+            Ok(MergeCrate {
+                graph: load_graph(&merge.source)?,
+                folder_id: merge.folder_id.clone(),
+                name: merge.name.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, ConsolidateError>>()?;
+    consolidate(
+        ConsolidateInput::Merge { main, others },
+        &NoOpLoader,
+        &request.options,
+    )
+}
+
+/// Subcrate loader reporting every subcrate it loads
+struct CountingLoader<'a, L> {
+    inner: L,
+    loaded: &'a AtomicUsize,
+    progress: &'a (dyn Fn(usize) + Sync),
+}
+
+impl<L: SubcrateLoader> SubcrateLoader for CountingLoader<'_, L> {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        self.load_ref(&SubcrateRef::new(
+            subcrate_id,
+            parent_namespace,
+            subcrate_entity,
+        ))
+    }
+
+    fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+        let graph = self.inner.load_ref(subcrate)?;
+        (self.progress)(self.loaded.fetch_add(1, Ordering::Relaxed) + 1);
+        Ok(graph)
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        self.inner.listed_subcrates(namespace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    fn graph(name: &str) -> Vec<Value> {
+        vec![
+            json!({
+                "@id": "ro-crate-metadata.json",
+                "@type": "CreativeWork",
+                "about": {"@id": "./"}
+            }),
+            json!({"@id": "./", "@type": "Dataset", "name": name}),
+        ]
+    }
+
+    /// Runner merging a crate per merge entry, failing for source "fail"
+    ///
+    /// The tenant it ran for is added as warning.
+    fn runner() -> Arc<JobRunner> {
+        Arc::new(|request: &JobRequest, context: &JobContext| {
+            if request.source == "fail" {
+                return Err(ConsolidateError::LoadError {
+                    path: "fail".to_string(),
+                    reason: "unreachable".to_string(),
+                    source: None,
+                });
+            }
+            let others = request
+                .merge
+                .iter()
+                .enumerate()
+                .map(|(i, merge)| {
+                    (context.progress)(i + 1);
+                    MergeCrate {
+                        graph: graph(&merge.source),
+                        folder_id: merge.folder_id.clone(),
+                        name: None,
+                    }
+                })
+                .collect();
+            let input = ConsolidateInput::Merge {
+                main: graph(&request.source),
+                others,
+            };
+            let mut result = consolidate(input, &NoOpLoader, &request.options)?;
+            result
+                .warnings
+                .push(format!("tenant '{}'", context.tenant.id()));
+            Ok(result)
+        })
+    }
+
+    fn wait(queue: &JobQueue, id: &str) -> Job {
+        for _ in 0..500 {
+            let job = queue.job(id).unwrap().unwrap();
+            if matches!(job.state, JobState::Completed | JobState::Failed) {
+                return job;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("job {} did not finish", id);
+    }
+
+    fn request(source: &str) -> JobRequest {
+        JobRequest {
+            source: source.to_string(),
+            merge: vec![JobMerge {
+                source: "other".to_string(),
+                folder_id: "./other/".to_string(),
+                name: None,
+            }],
+            options: ConsolidateOptions::default(),
+            callback_url: None,
+        }
+    }
+
+    #[test]
+    fn test_job_queue() {
+        let store = Arc::new(MemoryJobStore::default());
+        let queue = JobQueue::with_runner(store, 2, runner()).unwrap();
+
+        let job = queue.submit(request("main")).unwrap();
+        let job = wait(&queue, &job.id);
+        assert_eq!(job.state, JobState::Completed);
+        assert_eq!(job.crates_loaded, 2);
+        assert_eq!(job.report.unwrap().stats.crates_consolidated, 2);
+        let result: Value = serde_json::from_str(&queue.result(&job.id).unwrap().unwrap()).unwrap();
+        assert_eq!(result["@graph"].as_array().unwrap().len(), 3);
+
+        let job = queue.submit(request("fail")).unwrap();
+        let job = wait(&queue, &job.id);
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.unwrap().code, "load_error");
+        assert!(queue.result(&job.id).unwrap().is_none());
+        assert!(queue.job("unknown").unwrap().is_none());
+
+        // Jobs run in the context they were submitted with
+        let tenant = Arc::new(TenantContext::new("a"));
+        let job = queue.submit_for(tenant, request("main")).unwrap();
+        assert_eq!(job.tenant, "a");
+        let job = wait(&queue, &job.id);
+        assert_eq!(job.report.unwrap().warnings, ["tenant 'a'"]);
+    }
+
+    /// Accept one HTTP request on a local port, sending its body to the
+    /// returned receiver
+    fn callback_server() -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end().to_ascii_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let mut stream = reader.into_inner();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            tx.send(String::from_utf8(body).unwrap()).unwrap();
+        });
+        (url, rx)
+    }
+
+    #[test]
+    fn test_job_callbacks() {
+        let store = Arc::new(MemoryJobStore::default());
+        let queue = JobQueue::with_runner(store, 1, runner()).unwrap();
+        let (tx, finished) = mpsc::channel();
+        queue.on_finish(move |job| tx.send((job.id.clone(), job.state)).unwrap());
+        let timeout = Duration::from_secs(5);
+
+        // Callbacks to the service's own network need to be allowed
+        let (url, _) = callback_server();
+        let mut internal = request("main");
+        internal.callback_url = Some(url);
+        let job = queue.submit(internal).unwrap();
+        finished.recv_timeout(timeout).unwrap();
+        let error = wait_for_callback_error(&queue, &job.id);
+        assert!(error.contains("non-public address 127.0.0.1"), "{}", error);
+        let mut file = request("main");
+        file.callback_url = Some("file:///etc/passwd".to_string());
+        let err = queue.submit(file).unwrap_err();
+        assert_eq!(err.code(), "sandbox_violation");
+        assert!(check_callback_scheme("ftp://example.org/").is_err());
+        queue.allow_callbacks_to("http://127.0.0.1:");
+
+        let (url, posted) = callback_server();
+        let mut failing = request("fail");
+        failing.callback_url = Some(url);
+        let job = queue.submit(failing).unwrap();
+        assert_eq!(
+            finished.recv_timeout(timeout).unwrap(),
+            (job.id.clone(), JobState::Failed)
+        );
+        let body: Value = serde_json::from_str(&posted.recv_timeout(timeout).unwrap()).unwrap();
+        assert_eq!(body["id"], job.id.as_str());
+        assert_eq!(body["error"]["code"], "load_error");
+
+        // A callback URL nothing listens on
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut unreachable = request("main");
+        unreachable.callback_url = Some(format!("http://127.0.0.1:{}/hook", port));
+        let job = queue.submit(unreachable).unwrap();
+        assert_eq!(
+            finished.recv_timeout(timeout).unwrap(),
+            (job.id.clone(), JobState::Completed)
+        );
+        wait_for_callback_error(&queue, &job.id);
+    }
+
+    /// Serve `body` as response to every HTTP request on a local port,
+    /// returning the URL of a metadata file there
+    fn crate_server(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/ro-crate-metadata.json",
+            listener.local_addr().unwrap()
+        );
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                reader.into_inner().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_jobs_fetch_from_public_addresses() {
+        let url = crate_server(
+            r#"{"@graph": [
+                {"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+                {"@id": "./", "@type": "Dataset"}
+            ]}"#,
+        );
+        let store = Arc::new(MemoryJobStore::default());
+        let queue = JobQueue::new(store, 1).unwrap();
+        let request = JobRequest {
+            source: url,
+            ..JobRequest::default()
+        };
+
+        // Sources are chosen by clients, so the service's own network is off
+        // limits
+        let job = queue.submit(request.clone()).unwrap();
+        let job = wait(&queue, &job.id);
+        assert_eq!(job.state, JobState::Failed);
+        assert_eq!(job.error.unwrap().code, "sandbox_violation");
+
+        // Unless it is allowed, as for callbacks
+        queue.allow_callbacks_to("http://127.0.0.1:");
+        let job = queue.submit(request).unwrap();
+        let job = wait(&queue, &job.id);
+        assert_eq!(job.state, JobState::Completed, "{:?}", job.error);
+        assert_eq!(job.crates_loaded, 1);
+    }
+
+    fn wait_for_callback_error(queue: &JobQueue, id: &str) -> String {
+        for _ in 0..500 {
+            if let Some(error) = queue.job(id).unwrap().unwrap().callback_error {
+                return error;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("callback error was not recorded");
+    }
+
+    /// Store that can save jobs but not load them
+    #[derive(Default)]
+    struct UnreadableStore(MemoryJobStore);
+
+    impl JobStore for UnreadableStore {
+        fn save(&self, job: &Job) -> Result<(), ConsolidateError> {
+            self.0.save(job)
+        }
+
+        fn load(&self, _id: &str) -> Result<Option<Job>, ConsolidateError> {
+            Err(ConsolidateError::Io(std::io::Error::other("disk is gone")))
+        }
+
+        fn list(&self) -> Result<Vec<Job>, ConsolidateError> {
+            self.0.list()
+        }
+
+        fn save_result(&self, id: &str, result: &str) -> Result<(), ConsolidateError> {
+            self.0.save_result(id, result)
+        }
+
+        fn load_result(&self, id: &str) -> Result<Option<String>, ConsolidateError> {
+            self.0.load_result(id)
+        }
+    }
+
+    #[test]
+    fn test_job_failures() {
+        // A panicking runner fails its job, and the worker keeps going
+        let store = Arc::new(MemoryJobStore::default());
+        let panicking: Arc<JobRunner> = Arc::new(|request: &JobRequest, context: &JobContext| {
+            if request.source == "panic" {
+                panic!("runner bug");
+            }
+            runner()(request, context)
+        });
+        let queue = JobQueue::with_runner(store, 1, panicking).unwrap();
+        let job = wait(&queue, &queue.submit(request("panic")).unwrap().id);
+        assert_eq!(job.state, JobState::Failed);
+        let error = job.error.unwrap();
+        assert_eq!(error.code, "panicked");
+        assert!(error.message.contains("runner bug"), "{}", error.message);
+        let job = wait(&queue, &queue.submit(request("main")).unwrap().id);
+        assert_eq!(job.state, JobState::Completed);
+
+        // Jobs the store can't load are reported, not dropped quietly
+        let store = Arc::new(UnreadableStore::default());
+        let queue = JobQueue::with_runner(store, 1, runner()).unwrap();
+        let (tx, errors) = mpsc::channel();
+        queue.on_store_error(move |id, e| tx.send((id.to_string(), e.to_string())).unwrap());
+        let job = queue.submit(request("main")).unwrap();
+        let (id, error) = errors.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(id, job.id);
+        assert!(error.contains("disk is gone"), "{}", error);
+    }
+
+    #[test]
+    fn test_dir_job_store_resumes_jobs() {
+        let dir = std::env::temp_dir().join(format!("rocrate-jobs-{}", Ulid::new()));
+        let store = Arc::new(DirJobStore::new(&dir).unwrap());
+        // A job left running by a previous process
+        let interrupted = Job {
+            id: Ulid::new().to_string(),
+            tenant: "a".to_string(),
+            request: request("main"),
+            state: JobState::Running,
+            crates_loaded: 1,
+            report: None,
+            error: None,
+            callback_error: None,
+            duration_ms: None,
+        };
+        store.save(&interrupted).unwrap();
+        assert!(store.load("../escape").is_err());
+
+        let queue = JobQueue::with_runner(store.clone(), 1, runner()).unwrap();
+        let job = wait(&queue, &interrupted.id);
+        assert_eq!(job.state, JobState::Completed);
+        // A fresh context of the job's tenant
+        assert_eq!(job.report.unwrap().warnings, ["tenant 'a'"]);
+        assert!(store.load_result(&job.id).unwrap().is_some());
+        assert_eq!(store.list().unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -64,26 +64,155 @@
 //!     &ConsolidateOptions::default(),
 //! )?;
 //! ```
+//!
+//! ## Profile a consolidation
+//!
+//! ```ignore
+//! use rocrate_consolidate::{profile, Phase};
+//!
+//! let (result, profile) = profile(input, &loader, &options)?;
+//! println!("merge took {:?}", profile.phase(Phase::Merge).duration);
+//! ```
+//!
+//! ## Run single stages
+//!
+//! The steps above are public as a [`Pipeline`], for flows that need only
+//! some of them (see [`pipeline`]).
 
+pub mod arena;
+pub mod aruna;
+pub mod audit;
+pub mod bagit;
+pub mod cache;
+pub mod citation;
 pub mod collect;
+pub mod compose;
 pub mod consolidate;
+pub mod datetime;
+pub mod deconsolidate;
+pub mod detached;
 pub mod error;
+pub mod freshness;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod harvest;
 pub mod id;
+pub mod instrument;
+pub mod job;
+pub mod lint;
 pub mod loader;
+pub mod manifest;
+pub mod mapped;
+pub mod mentions;
 pub mod merge;
+pub mod metadata_file;
+pub mod metrics;
+pub mod mirror;
+pub mod output;
+pub mod path;
+pub mod pipeline;
+pub mod plan;
+pub mod postprocess;
+pub mod profile;
+pub mod registry;
+pub mod report;
+pub mod s3;
+pub mod sandbox;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod signposting;
+pub mod sitemap;
+pub mod template;
+pub mod tenant;
 pub mod transform;
+pub mod value;
 pub mod vocab;
+pub mod wellknown;
 
 // Re-export main types for convenience
+pub use crate::aruna::{
+    upload_crate, ArunaApi, ArunaClient, ArunaLoader, ArunaResource, ResourceVariant, ARUNA_SCHEME,
+};
+pub use crate::audit::{AccessKind, AccessRecord, AuditLog};
+pub use crate::bagit::{bag_payload_dir, verify_bag};
+pub use crate::cache::{
+    CacheKey, CachedCrate, CachedResponse, DirSubcrateCache, HttpCache, MemorySubcrateCache,
+    SubcrateCache,
+};
+pub use crate::citation::{normalize_doi, ConsolidateCitations};
+pub use crate::collect::MultiRootPolicy;
+pub use crate::compose::{ChainLoader, HybridLoader, MappingLoader};
 pub use crate::consolidate::{
-    consolidate, parse_graph, to_json_string, to_jsonld, ConsolidateInput, ConsolidateOptions,
-    ConsolidateResult, ConsolidateStats, MergeCrate, NoOpLoader, SubcrateLoader, UrlLoader,
+    consolidate, parse_graph, to_json_string, to_jsonld, CaseCollisionPolicy, ConsolidateInput,
+    ConsolidateOptions, ConsolidateResult, ConsolidateStats, EntityOrigin, ManifestLoader,
+    MergeCrate, MissingDescriptorPolicy, NoOpLoader, PartialFailure, RootHandling, SubcrateLoader,
+    SubcrateOverrides, SubcrateRef, UrlLoader,
+};
+pub use crate::deconsolidate::{deconsolidate, SplitCrate};
+pub use crate::detached::DistributionPointer;
+pub use crate::error::{BoxError, ConsolidateError, IndexError};
+pub use crate::freshness::{check_freshness, check_freshness_with, Freshness};
+pub use crate::harvest::{
+    collection_graph, HarvestPage, HarvestProtocol, HarvestRecord, HarvestState, HarvestedRecord,
+    Harvester,
+};
+pub use crate::id::FragmentIdPolicy;
+pub use crate::instrument::{InstrumentedLoader, LoadEvent};
+pub use crate::job::{
+    DirJobStore, Job, JobContext, JobError, JobMerge, JobQueue, JobReport, JobRequest, JobState,
+    JobStore, MemoryJobStore,
+};
+pub use crate::lint::{
+    lint, BuiltinRule, Finding, LintProfile, LintRule, Linter, Severity, Violation,
 };
-pub use crate::error::{ConsolidateError, IndexError};
 pub use crate::loader::{
-    load, load_from_directory, load_from_url, load_from_zip, load_with_json, CrateSource,
+    count_fetch_errors, fetch_url_with, go_offline, is_offline, limit_reads, load,
+    load_from_directory, load_from_tar, load_from_url, load_from_url_with, load_from_zip,
+    load_with_json, retry_budget, retry_wait_budget, CrateSource, FetchErrorCounter, HttpAuth,
+    Offline, ReadLimit, RetryBudget, UrlLoaderOptions,
+};
+pub use crate::manifest::{build_manifest, manifest_to_csv, ManifestEntry, SourceLocation};
+pub use crate::mapped::{
+    consolidate_mapped, parse_raw_graph, MappedConsolidation, MappedFile, RawGraph,
+};
+pub use crate::mentions::{KeyEntityRanking, MentionKeyEntities, DEFAULT_KEY_TYPES};
+#[allow(deprecated)]
+pub use crate::merge::MergeStrategy;
+pub use crate::merge::{
+    merge_entities, merge_graphs, resolve_merge_entities, resolve_merge_entities_with,
+    ConflictStrategy, MergeConflict, MergePolicy, PropertyStrategy,
+};
+pub use crate::metadata_file::{
+    metadata_file_patterns, use_metadata_file_patterns, MetadataFilePatterns,
+};
+pub use crate::metrics::Metrics;
+pub use crate::mirror::{MirrorUse, Mirrors};
+pub use crate::output::{to_json_string_styled, KeyOrder, OutputStyle, ShapePolicy};
+pub use crate::pipeline::{
+    build_id_map, collect_from_graph, create_subcrate_folder, create_subcrate_folder_with,
+    namespace_crate, rewrite_references, union_merge_entities, NamespacedCrate, Pipeline,
+};
+pub use crate::plan::{plan_fetches, PlannedFetch};
+pub use crate::postprocess::{
+    AggregateCoverage, ExtendContext, IdRewriter, PostProcessor, Redact, ReplaceIdPrefix,
+    RewriteIds,
+};
+pub use crate::profile::{profile, Phase, PhaseProfile, Profile};
+pub use crate::registry::{
+    IdRewriterFactory, LoaderFactory, MergePolicyFactory, PostProcessorFactory, Registry,
 };
+pub use crate::report::{notification, HtmlReport, NotifyFormat};
+pub use crate::s3::{split_s3_url, S3Api, S3Client, S3Credentials, S3Loader, S3_SCHEME};
+pub use crate::sandbox::{Sandbox, SandboxedLoader};
+#[cfg(feature = "scripting")]
+pub use crate::script::EntityScript;
+pub use crate::sitemap::{build_sitemap, sitemap_entity, SitemapEntry};
+pub use crate::template::{expand_folder_template, slugify, unique_folder_id, TemplateVars};
+pub use crate::tenant::{TenantContext, TenantUrlLoader};
+pub use crate::transform::ReferenceMergePolicy;
+pub use crate::value::{canonical_value, value_hash, values_equal, ValueComparison};
 pub use crate::vocab::{
-    CONSOLIDATED_ENTITIES, CONSOLIDATED_ENTITIES_SHORT, CONSOLIDATE_NS, SUBCRATE_TYPE,
-    SUBCRATE_TYPE_SHORT,
+    profile_crate, CONSOLIDATED_ENTITIES, CONSOLIDATED_ENTITIES_SHORT, CONSOLIDATED_FROM,
+    CONSOLIDATE_NS, CONSOLIDATION_PROFILE_ID, SUBCRATE_TYPE, SUBCRATE_TYPE_SHORT,
 };
+pub use crate::wellknown::{EntityLibrary, NormalizeLicenses};
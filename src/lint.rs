@@ -0,0 +1,579 @@
+//! Integrity checks of crate graphs
+//!
+//! A [`Linter`] checks any graph, whether an input crate, a consolidation's
+//! output or a third-party crate, for what makes it an invalid or broken
+//! RO-Crate: a missing or miswired metadata descriptor, a missing or
+//! duplicated root, entities without or sharing an @id, and references to
+//! entities the graph doesn't describe. Each [`BuiltinRule`] can be turned off
+//! or given another [`Severity`], starting from a [`LintProfile`].
+//!
+//! Checks of an organization's own, e.g. that every File has a checksum,
+//! implement [`LintRule`] and run in the same pass, reported as findings
+//! like those of the built-in rules:
+//!
+//! ```ignore
+//! let findings = Linter::new()
+//!     .without(BuiltinRule::DanglingReference)
+//!     .with_rule(FileChecksum)
+//!     .lint(&graph);
+//! if findings.iter().any(|f| f.severity == Severity::Error) { ... }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::collect::{conforms_to_rocrate, extract_id, graph_pointer, has_type};
+use crate::id::{classify_id, is_root_alias, IdKind};
+use crate::vocab::ROOT_ENTITY_ID;
+
+/// How bad a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The graph is not a valid RO-Crate
+    Error,
+    /// The graph is valid, but likely not as intended
+    Warning,
+}
+
+/// A check every [`Linter`] runs, unless turned off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BuiltinRule {
+    /// An entity has no @id
+    MissingId,
+    /// Several entities have the same @id
+    DuplicateId,
+    /// No entity is the metadata descriptor
+    MissingDescriptor,
+    /// The descriptor's `about` is not a reference to the root
+    DescriptorAbout,
+    /// The descriptor doesn't conform to an RO-Crate specification
+    DescriptorConformsTo,
+    /// No entity is the root the descriptor is about
+    MissingRoot,
+    /// Several entities are spellings of the root ("./", ".//", ".")
+    MultipleRoots,
+    /// The root is not a Dataset
+    RootNotDataset,
+    /// A relative or fragment @id is referenced but not described
+    DanglingReference,
+}
+
+impl BuiltinRule {
+    /// All rules, in the order they are checked
+    pub const ALL: [BuiltinRule; 9] = [
+        BuiltinRule::MissingId,
+        BuiltinRule::DuplicateId,
+        BuiltinRule::MissingDescriptor,
+        BuiltinRule::DescriptorAbout,
+        BuiltinRule::DescriptorConformsTo,
+        BuiltinRule::MissingRoot,
+        BuiltinRule::MultipleRoots,
+        BuiltinRule::RootNotDataset,
+        BuiltinRule::DanglingReference,
+    ];
+
+    /// Stable string code of the rule
+    pub const fn code(&self) -> &'static str {
+        match self {
+            BuiltinRule::MissingId => "missing_id",
+            BuiltinRule::DuplicateId => "duplicate_id",
+            BuiltinRule::MissingDescriptor => "missing_descriptor",
+            BuiltinRule::DescriptorAbout => "descriptor_about",
+            BuiltinRule::DescriptorConformsTo => "descriptor_conforms_to",
+            BuiltinRule::MissingRoot => "missing_root",
+            BuiltinRule::MultipleRoots => "multiple_roots",
+            BuiltinRule::RootNotDataset => "root_not_dataset",
+            BuiltinRule::DanglingReference => "dangling_reference",
+        }
+    }
+
+    /// The rule with the code `code`, if any
+    pub fn from_code(code: &str) -> Option<BuiltinRule> {
+        BuiltinRule::ALL
+            .into_iter()
+            .find(|rule| rule.code() == code)
+    }
+
+    /// Severity of the rule's findings, unless a [`Linter`] sets another
+    pub fn severity(&self) -> Severity {
+        match self {
+            BuiltinRule::DescriptorConformsTo
+            | BuiltinRule::DuplicateId
+            | BuiltinRule::DanglingReference => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl AsRef<str> for BuiltinRule {
+    fn as_ref(&self) -> &str {
+        self.code()
+    }
+}
+
+/// A check added to a [`Linter`], besides the built-in ones
+pub trait LintRule: Send + Sync {
+    /// Stable string code of the rule, e.g. "file_checksum"
+    ///
+    /// Should differ from the codes of the built-in rules and others added.
+    fn code(&self) -> &str;
+
+    /// Severity of the rule's findings, unless a [`Linter`] sets another
+    fn severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    /// What the rule finds wrong with `graph`
+    fn check(&self, graph: &[Value]) -> Vec<Violation>;
+}
+
+impl fmt::Debug for dyn LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "LintRule({})", self.code())
+    }
+}
+
+/// Something a [`LintRule`] found wrong, made a [`Finding`] by the linter
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Violation {
+    pub message: String,
+    /// @id of the entity the violation is about, if any
+    pub id: Option<String>,
+    /// JSON pointer into the graph's metadata document, if known (see
+    /// [`graph_pointer`])
+    pub pointer: Option<String>,
+}
+
+/// A set of rules and severities for a purpose
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintProfile {
+    /// All rules, with their own severities
+    #[default]
+    Default,
+    /// All rules as errors, for a consolidated crate about to be published:
+    /// it must not reference what it doesn't describe, describe anything
+    /// twice, or leave its RO-Crate version unstated
+    Publishable,
+}
+
+/// Something a [`Linter`] found wrong with a graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    /// Code of the rule
+    pub rule: String,
+    pub severity: Severity,
+    pub message: String,
+    /// @id of the entity the finding is about, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// JSON pointer into the graph's metadata document, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<String>,
+}
+
+/// Checks graphs against the [`BuiltinRule`]s, by default all, and the
+/// [`LintRule`]s added
+///
+/// Rules are named by their code, or a [`BuiltinRule`].
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    profile: LintProfile,
+    rules: Vec<Arc<dyn LintRule>>,
+    disabled: HashSet<String>,
+    severities: HashMap<String, Severity>,
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Linter checking the rules of `profile`, with its severities
+    ///
+    /// The profile applies to rules added later too.
+    pub fn with_profile(profile: LintProfile) -> Self {
+        Self {
+            profile,
+            ..Self::default()
+        }
+    }
+
+    /// Also check `rule`, after the built-in rules and those added before
+    pub fn with_rule(mut self, rule: impl LintRule + 'static) -> Self {
+        self.rules.push(Arc::new(rule));
+        self
+    }
+
+    /// Don't check `rule`
+    pub fn without(mut self, rule: impl AsRef<str>) -> Self {
+        self.disabled.insert(rule.as_ref().to_string());
+        self
+    }
+
+    /// Check `rule` again
+    pub fn with(mut self, rule: impl AsRef<str>) -> Self {
+        self.disabled.remove(rule.as_ref());
+        self
+    }
+
+    /// Report findings of `rule` with `severity`
+    pub fn with_severity(mut self, rule: impl AsRef<str>, severity: Severity) -> Self {
+        self.severities.insert(rule.as_ref().to_string(), severity);
+        self
+    }
+
+    /// Whether `rule` is checked, if it is a built-in or added rule
+    pub fn is_enabled(&self, rule: impl AsRef<str>) -> bool {
+        !self.disabled.contains(rule.as_ref())
+    }
+
+    /// Severity findings of `rule` are reported with, if it is a built-in or
+    /// added rule
+    pub fn severity(&self, rule: impl AsRef<str>) -> Option<Severity> {
+        let code = rule.as_ref();
+        let own = match BuiltinRule::from_code(code) {
+            Some(rule) => rule.severity(),
+            None => self
+                .rules
+                .iter()
+                .find(|rule| rule.code() == code)?
+                .severity(),
+        };
+        let profile = match self.profile {
+            LintProfile::Default => own,
+            LintProfile::Publishable => Severity::Error,
+        };
+        Some(self.severities.get(code).copied().unwrap_or(profile))
+    }
+
+    /// Check `graph`, returning findings in graph order per rule
+    pub fn lint(&self, graph: &[Value]) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let mut report = |rule: BuiltinRule, message: String, id: Option<&str>, pointer| {
+            if self.is_enabled(rule) {
+                findings.push(Finding {
+                    rule: rule.code().to_string(),
+                    severity: self.severity(rule).unwrap_or_else(|| rule.severity()),
+                    message,
+                    id: id.map(str::to_string),
+                    pointer,
+                });
+            }
+        };
+
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for (index, entity) in graph.iter().enumerate() {
+            match extract_id(entity) {
+                None => report(
+                    BuiltinRule::MissingId,
+                    "Entity has no @id".to_string(),
+                    None,
+                    Some(graph_pointer(index, &[])),
+                ),
+                Some(id) => {
+                    if positions.insert(id, index).is_some() {
+                        report(
+                            BuiltinRule::DuplicateId,
+                            format!("Entity '{}' is described more than once", id),
+                            Some(id),
+                            Some(graph_pointer(index, &["@id"])),
+                        );
+                    }
+                }
+            }
+        }
+
+        // The root is what the descriptor is about
+        let descriptor = graph
+            .iter()
+            .enumerate()
+            .find(|(_, e)| extract_id(e).map(classify_id) == Some(IdKind::MetadataDescriptor));
+        let mut root_id = ROOT_ENTITY_ID;
+        match descriptor {
+            None => report(
+                BuiltinRule::MissingDescriptor,
+                "No metadata descriptor (ro-crate-metadata.json)".to_string(),
+                None,
+                None,
+            ),
+            Some((index, descriptor)) => {
+                let id = extract_id(descriptor);
+                match descriptor.get("about").and_then(extract_id) {
+                    Some(about) => root_id = about,
+                    None => report(
+                        BuiltinRule::DescriptorAbout,
+                        "Metadata descriptor's about is not a reference to the root".to_string(),
+                        id,
+                        Some(graph_pointer(index, &["about"])),
+                    ),
+                }
+                if !conforms_to_rocrate(descriptor) {
+                    report(
+                        BuiltinRule::DescriptorConformsTo,
+                        "Metadata descriptor doesn't conform to an RO-Crate specification"
+                            .to_string(),
+                        id,
+                        Some(graph_pointer(index, &["conformsTo"])),
+                    );
+                }
+            }
+        }
+
+        match positions.get(root_id) {
+            None => report(
+                BuiltinRule::MissingRoot,
+                format!("No root entity '{}'", root_id),
+                Some(root_id),
+                None,
+            ),
+            Some(&index) if !has_type(&graph[index], "Dataset") => report(
+                BuiltinRule::RootNotDataset,
+                format!("Root entity '{}' is not a Dataset", root_id),
+                Some(root_id),
+                Some(graph_pointer(index, &["@type"])),
+            ),
+            Some(_) => {}
+        }
+        if root_id == ROOT_ENTITY_ID {
+            for (index, entity) in graph.iter().enumerate() {
+                if let Some(alias) = extract_id(entity).filter(|id| is_root_alias(id)) {
+                    report(
+                        BuiltinRule::MultipleRoots,
+                        format!("Entity '{}' is another root besides '{}'", alias, root_id),
+                        Some(alias),
+                        Some(graph_pointer(index, &["@id"])),
+                    );
+                }
+            }
+        }
+
+        for (index, entity) in graph.iter().enumerate() {
+            let Some(object) = entity.as_object() else {
+                continue;
+            };
+            for (key, value) in object {
+                if key.starts_with('@') {
+                    continue;
+                }
+                let mut path = vec![key.clone()];
+                dangling_references(value, &positions, &mut path, &mut |id, path| {
+                    let path: Vec<&str> = path.iter().map(String::as_str).collect();
+                    report(
+                        BuiltinRule::DanglingReference,
+                        format!("Reference to '{}', which is not described", id),
+                        Some(id),
+                        Some(graph_pointer(index, &path)),
+                    )
+                });
+            }
+        }
+
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| self.is_enabled(rule.code()))
+        {
+            let severity = self
+                .severity(rule.code())
+                .unwrap_or_else(|| rule.severity());
+            findings.extend(rule.check(graph).into_iter().map(|violation| Finding {
+                rule: rule.code().to_string(),
+                severity,
+                message: violation.message,
+                id: violation.id,
+                pointer: violation.pointer,
+            }));
+        }
+
+        findings
+    }
+}
+
+/// Check `graph` against all rules
+pub fn lint(graph: &[Value]) -> Vec<Finding> {
+    Linter::new().lint(graph)
+}
+
+/// Call `found` with each reference to a relative or fragment id within
+/// `value` that isn't in `described`, and the path to it
+fn dangling_references(
+    value: &Value,
+    described: &HashMap<&str, usize>,
+    path: &mut Vec<String>,
+    found: &mut impl FnMut(&str, &[String]),
+) {
+    match value {
+        Value::Object(object) => {
+            if let Some(id) = object.get("@id").and_then(Value::as_str) {
+                let local = matches!(classify_id(id), IdKind::Relative | IdKind::Fragment);
+                if local && !described.contains_key(id) {
+                    path.push("@id".to_string());
+                    found(id, path);
+                    path.pop();
+                }
+            }
+            for (key, value) in object {
+                path.push(key.clone());
+                dangling_references(value, described, path, found);
+                path.pop();
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter().enumerate() {
+                path.push(i.to_string());
+                dangling_references(value, described, path, found);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_lint() {
+        let valid = vec![
+            json!({
+                "@id": "ro-crate-metadata.json",
+                "about": {"@id": "./"},
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "data.csv"}]}),
+            json!({"@id": "data.csv", "@type": "File", "author": {"@id": "https://orcid.org/1"}}),
+        ];
+        assert!(lint(&valid).is_empty());
+
+        let broken = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": "./"}),
+            json!({"@id": "./", "@type": "CreativeWork", "hasPart": [{"@id": "a.csv"}, {"@id": "#me"}]}),
+            json!({"@id": ".//", "@type": "Dataset"}),
+            json!({"@id": "#me"}),
+            json!({"@id": "#me"}),
+            json!({"name": "anonymous"}),
+        ];
+        let rules: Vec<String> = lint(&broken).into_iter().map(|f| f.rule).collect();
+        assert_eq!(
+            rules,
+            [
+                "duplicate_id",
+                "missing_id",
+                "descriptor_about",
+                "descriptor_conforms_to",
+                "root_not_dataset",
+                "multiple_roots",
+                "dangling_reference",
+            ]
+        );
+        let dangling = lint(&broken).pop().unwrap();
+        assert_eq!(dangling.id.as_deref(), Some("a.csv"));
+        assert_eq!(dangling.pointer.as_deref(), Some("/@graph/1/hasPart/0/@id"));
+        assert_eq!(dangling.severity, Severity::Warning);
+
+        let linter = Linter::new()
+            .without(BuiltinRule::DanglingReference)
+            .without(BuiltinRule::DescriptorConformsTo);
+        assert_eq!(linter.lint(&broken).len(), 5);
+        assert_eq!(
+            lint(&[]).into_iter().map(|f| f.rule).collect::<Vec<_>>(),
+            ["missing_descriptor", "missing_root"]
+        );
+        assert_eq!(
+            BuiltinRule::from_code("missing_root"),
+            Some(BuiltinRule::MissingRoot)
+        );
+    }
+
+    #[test]
+    fn test_lint_profiles() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": {"@id": "missing.csv"}}),
+        ];
+        let severities = |linter: &Linter| {
+            linter
+                .lint(&graph)
+                .into_iter()
+                .map(|f| (f.rule, f.severity))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            severities(&Linter::new()),
+            [
+                ("descriptor_conforms_to".to_string(), Severity::Warning),
+                ("dangling_reference".to_string(), Severity::Warning),
+            ]
+        );
+        let publishable = Linter::with_profile(LintProfile::Publishable);
+        assert_eq!(
+            severities(&publishable),
+            [
+                ("descriptor_conforms_to".to_string(), Severity::Error),
+                ("dangling_reference".to_string(), Severity::Error),
+            ]
+        );
+        let adjusted = publishable
+            .with_severity("dangling_reference", Severity::Warning)
+            .without(BuiltinRule::DescriptorConformsTo);
+        assert_eq!(
+            severities(&adjusted),
+            [("dangling_reference".to_string(), Severity::Warning)]
+        );
+    }
+
+    /// Every File needs a checksum
+    struct FileChecksum;
+
+    impl LintRule for FileChecksum {
+        fn code(&self) -> &str {
+            "file_checksum"
+        }
+
+        fn check(&self, graph: &[Value]) -> Vec<Violation> {
+            graph
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| has_type(e, "File") && e.get("sha256").is_none())
+                .map(|(index, file)| Violation {
+                    message: "File has no sha256 checksum".to_string(),
+                    id: extract_id(file).map(String::from),
+                    pointer: Some(graph_pointer(index, &[])),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_custom_rule() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}, "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "a.csv"}, {"@id": "b.csv"}]}),
+            json!({"@id": "a.csv", "@type": "File", "sha256": "e3b0c442"}),
+            json!({"@id": "b.csv", "@type": "File"}),
+        ];
+        let linter = Linter::new().with_rule(FileChecksum);
+        let findings = linter.lint(&graph);
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].rule, "file_checksum");
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[0].id.as_deref(), Some("b.csv"));
+        assert_eq!(findings[0].pointer.as_deref(), Some("/@graph/3"));
+
+        let linter = linter.with_severity("file_checksum", Severity::Warning);
+        assert_eq!(linter.lint(&graph)[0].severity, Severity::Warning);
+        assert!(linter.without("file_checksum").lint(&graph).is_empty());
+        assert_eq!(Linter::new().severity("file_checksum"), None);
+    }
+}
@@ -1,13 +1,36 @@
+use std::cell::{Cell, RefCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{self, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use reqwest::blocking::{Client, RequestBuilder, Response};
+use reqwest::header::{
+    AUTHORIZATION, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LINK, LOCATION,
+    RETRY_AFTER,
+};
+use reqwest::{Method, StatusCode};
+use url::Url;
+
+use flate2::read::GzDecoder;
 use rocraters::ro_crate::read::read_crate_obj;
 use rocraters::ro_crate::rocrate::RoCrate;
 use ulid::Ulid;
 use zip::ZipArchive;
 
-use crate::error::IndexError;
+use crate::audit::{self, AccessKind};
+use crate::bagit::{bag_payload_dir, find_bag_prefix, BAG_PAYLOAD_DIR};
+use crate::cache::HttpCache;
+use crate::error::{BoxError, IndexError};
+use crate::metadata_file::{is_metadata_file, preferred_metadata_file};
+use crate::path::component_to_id;
+use crate::s3::{S3Api, S3Client, S3_SCHEME};
+use crate::signposting::{cite_as, metadata_link, parse_html_links, parse_link_header, Signpost};
 
 /// Source from which to load an RO-Crate
 #[derive(Debug, Clone)]
@@ -32,6 +55,20 @@ pub enum CrateSource {
         parent_id: String,
         metadata_url: String,
     },
+    /// Object or folder prefix in an S3-compatible bucket
+    S3 { bucket: String, key: String },
+    /// Local tar file, optionally gzipped, with optional name hint for ID
+    /// generation
+    TarFile {
+        path: PathBuf,
+        name_hint: Option<String>,
+    },
+    /// Subcrate within a tar archive
+    TarSubcrate {
+        parent_id: String,
+        tar_path: PathBuf,
+        subpath: String,
+    },
 }
 
 impl CrateSource {
@@ -51,6 +88,22 @@ impl CrateSource {
         }
     }
 
+    /// Create a TarFile source from a path (no name hint)
+    pub fn tar(path: PathBuf) -> Self {
+        CrateSource::TarFile {
+            path,
+            name_hint: None,
+        }
+    }
+
+    /// Create a TarFile source with a name hint
+    pub fn tar_with_name(path: PathBuf, name: impl Into<String>) -> Self {
+        CrateSource::TarFile {
+            path,
+            name_hint: Some(name.into()),
+        }
+    }
+
     /// Derive a crate identifier from the source
     /// - URLs: use the URL as-is
     /// - Local paths: <ULID> or <ULID>/name if name available
@@ -59,20 +112,26 @@ impl CrateSource {
         match self {
             CrateSource::Url(u) => normalize_url_for_id(u),
             CrateSource::Directory(p) => {
-                let name = p.file_name().and_then(|n| n.to_str()).unwrap_or("unknown");
+                let name = p
+                    .file_name()
+                    .map(component_to_id)
+                    .unwrap_or_else(|| "unknown".to_string());
                 format!("{}/{}", Ulid::new(), name)
             }
-            CrateSource::ZipFile { path, name_hint } => {
+            CrateSource::ZipFile { path, name_hint } | CrateSource::TarFile { path, name_hint } => {
                 let ulid = Ulid::new();
                 match name_hint {
                     Some(name) => {
-                        // Clean up the name - remove .zip extension if present
-                        let clean_name = name.trim_end_matches(".zip").trim_end_matches(".ZIP");
-                        format!("{}/{}", ulid, clean_name)
+                        // Clean up the name - remove the archive extension if present
+                        format!("{}/{}", ulid, archive_stem(name).unwrap_or(name))
                     }
                     None => {
                         // Try to get name from path, fall back to just ULID
-                        match path.file_stem().and_then(|n| n.to_str()) {
+                        let name = path
+                            .file_name()
+                            .map(component_to_id)
+                            .map(|name| archive_stem(&name).unwrap_or(&name).to_string());
+                        match name.as_deref() {
                             Some(name) if !name.starts_with("rocrate_") && !is_uuid_like(name) => {
                                 format!("{}/{}", ulid, name)
                             }
@@ -83,6 +142,9 @@ impl CrateSource {
             }
             CrateSource::ZipSubcrate {
                 parent_id, subpath, ..
+            }
+            | CrateSource::TarSubcrate {
+                parent_id, subpath, ..
             } => {
                 // Extract directory path from subpath, removing the metadata filename
                 let clean_subpath = extract_directory_from_metadata_path(subpath);
@@ -93,6 +155,11 @@ impl CrateSource {
                 }
             }
             CrateSource::UrlSubcrate { metadata_url, .. } => normalize_url_for_id(metadata_url),
+            CrateSource::S3 { bucket, key } => {
+                format!("{}{}/{}", S3_SCHEME, bucket, s3_folder(key))
+                    .trim_end_matches('/')
+                    .to_string()
+            }
         }
     }
 
@@ -114,17 +181,23 @@ impl CrateSource {
                     Some(format!("{}/", metadata_url))
                 }
             }
+            CrateSource::S3 { bucket, key } => match s3_folder(key) {
+                "" => Some(format!("{}{}/", S3_SCHEME, bucket)),
+                folder => Some(format!("{}{}/{}/", S3_SCHEME, bucket, folder)),
+            },
             _ => None,
         }
     }
 
-    /// Check if this is a local source (directory or zip)
+    /// Check if this is a local source (directory, zip or tar)
     pub fn is_local(&self) -> bool {
         matches!(
             self,
             CrateSource::Directory(_)
                 | CrateSource::ZipFile { .. }
                 | CrateSource::ZipSubcrate { .. }
+                | CrateSource::TarFile { .. }
+                | CrateSource::TarSubcrate { .. }
         )
     }
 
@@ -136,6 +209,46 @@ impl CrateSource {
             _ => None,
         }
     }
+
+    /// Get the tar path if this is a tar-based source
+    pub fn tar_path(&self) -> Option<&PathBuf> {
+        match self {
+            CrateSource::TarFile { path, .. } => Some(path),
+            CrateSource::TarSubcrate { tar_path, .. } => Some(tar_path),
+            _ => None,
+        }
+    }
+}
+
+/// Extensions of tar archives, gzipped or not
+const TAR_EXTENSIONS: &[&str] = &[".tar.gz", ".tgz", ".tar"];
+
+/// A file name without its archive extension (".zip", ".tar", ".tar.gz" or
+/// ".tgz", in any case), if it has one
+pub fn archive_stem(name: &str) -> Option<&str> {
+    [".zip"]
+        .iter()
+        .chain(TAR_EXTENSIONS)
+        .find_map(|extension| strip_suffix_ignore_case(name, extension))
+}
+
+/// Check if a path names a tar archive by its extension (".tar", ".tar.gz"
+/// or ".tgz")
+pub fn is_tar_path(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| {
+            TAR_EXTENSIONS
+                .iter()
+                .any(|extension| strip_suffix_ignore_case(name, extension).is_some())
+        })
+}
+
+/// `name` without `suffix`, compared ignoring ASCII case
+fn strip_suffix_ignore_case<'a>(name: &'a str, suffix: &str) -> Option<&'a str> {
+    let stem = name.len().checked_sub(suffix.len())?;
+    (name.is_char_boundary(stem) && name[stem..].eq_ignore_ascii_case(suffix))
+        .then(|| &name[..stem])
 }
 
 /// Check if a string looks like a UUID (for filtering temp filenames)
@@ -157,6 +270,13 @@ fn extract_directory_from_metadata_path(path: &str) -> String {
     }
 }
 
+/// Folder of an S3 key naming a crate's metadata object or folder
+fn s3_folder(key: &str) -> &str {
+    key.strip_suffix("ro-crate-metadata.json")
+        .unwrap_or(key)
+        .trim_matches('/')
+}
+
 /// Normalize URL for use as crate ID
 /// Removes trailing ro-crate-metadata.json if present
 fn normalize_url_for_id(url: &str) -> String {
@@ -169,15 +289,19 @@ fn normalize_url_for_id(url: &str) -> String {
     url.to_string()
 }
 
-/// Load an RO-Crate from a local directory
+/// Load an RO-Crate from a local directory, or the payload of a BagIt bag
 pub fn load_from_directory(path: &PathBuf) -> Result<RoCrate, IndexError> {
     if !path.exists() {
         return Err(IndexError::InvalidPath(path.to_path_buf()));
     }
+    // BagIt bags hold the crate in their payload directory
+    let payload = bag_payload_dir(path);
+    let path = payload.as_ref().unwrap_or(path);
 
     rocraters::ro_crate::read::read_crate(path, 0).map_err(|e| IndexError::LoadError {
         path: path.display().to_string(),
         reason: format!("{:#?}", e),
+        source: None,
     })
 }
 
@@ -191,11 +315,13 @@ pub fn load_from_zip(path: &PathBuf) -> Result<(RoCrate, String, String), IndexE
     let file = File::open(path).map_err(|e| IndexError::LoadError {
         path: path.display().to_string(),
         reason: format!("Failed to open zip file: {}", e),
+        source: Some(e.into()),
     })?;
 
     let mut archive = ZipArchive::new(file).map_err(|e| IndexError::LoadError {
         path: path.display().to_string(),
         reason: format!("Failed to read zip archive: {}", e),
+        source: Some(e.into()),
     })?;
 
     // Find the root metadata file (must be at top level)
@@ -206,6 +332,22 @@ pub fn load_from_zip(path: &PathBuf) -> Result<(RoCrate, String, String), IndexE
     Ok((crate_data, content, root_prefix))
 }
 
+/// Top-level directory holding the root crate of a zip archive ("" if none)
+pub fn zip_root_prefix(path: &Path) -> Result<String, IndexError> {
+    let file = File::open(path).map_err(|e| IndexError::LoadError {
+        path: path.display().to_string(),
+        reason: format!("Failed to open zip file: {}", e),
+        source: Some(e.into()),
+    })?;
+    let mut archive = ZipArchive::new(file).map_err(|e| IndexError::LoadError {
+        path: path.display().to_string(),
+        reason: format!("Failed to read zip archive: {}", e),
+        source: Some(e.into()),
+    })?;
+    let (_, root_prefix) = find_root_metadata_in_zip(&mut archive)?;
+    Ok(root_prefix)
+}
+
 /// Load a subcrate from within a zip archive
 pub fn load_from_zip_subpath(
     zip_path: &PathBuf,
@@ -214,11 +356,13 @@ pub fn load_from_zip_subpath(
     let file = File::open(zip_path).map_err(|e| IndexError::LoadError {
         path: zip_path.display().to_string(),
         reason: format!("Failed to open zip file: {}", e),
+        source: Some(e.into()),
     })?;
 
     let mut archive = ZipArchive::new(file).map_err(|e| IndexError::LoadError {
         path: zip_path.display().to_string(),
         reason: format!("Failed to read zip archive: {}", e),
+        source: Some(e.into()),
     })?;
 
     load_metadata_from_zip_archive(&mut archive, subpath, zip_path)
@@ -230,31 +374,36 @@ fn load_metadata_from_zip_archive(
     entry_path: &str,
     zip_path: &PathBuf,
 ) -> Result<(RoCrate, String), IndexError> {
-    let mut metadata_file = archive
-        .by_name(entry_path)
-        .map_err(|e| IndexError::LoadError {
-            path: zip_path.display().to_string(),
-            reason: format!("Failed to extract {}: {}", entry_path, e),
-        })?;
-
-    let mut content = String::new();
-    metadata_file
-        .read_to_string(&mut content)
-        .map_err(|e| IndexError::LoadError {
+    let content =
+        read_nested_zip_member(archive, entry_path).map_err(|reason| IndexError::LoadError {
             path: zip_path.display().to_string(),
-            reason: format!("Failed to read metadata file: {}", e),
+            reason,
+            source: None,
         })?;
+    let content = String::from_utf8(content).map_err(|e| IndexError::LoadError {
+        path: zip_path.display().to_string(),
+        reason: format!("Failed to read metadata file: {}", e),
+        source: Some(e.into()),
+    })?;
+    audit::record(
+        AccessKind::ZipMember,
+        &zip_path.display().to_string(),
+        Some(entry_path),
+        content.as_bytes(),
+    );
 
     let crate_data = read_crate_obj(&content, 0).map_err(|e| IndexError::LoadError {
         path: zip_path.display().to_string(),
         reason: format!("Failed to parse RO-Crate metadata: {:#?}", e),
+        source: None,
     })?;
 
     Ok((crate_data, content))
 }
 
 /// Find the root ro-crate-metadata.json in a zip archive
-/// Returns (full_path, root_prefix) where root_prefix is the top-level directory if any
+/// Returns (full_path, root_prefix) where root_prefix is the top-level directory if any,
+/// or the payload directory of a BagIt bag
 fn find_root_metadata_in_zip<R: Read + std::io::Seek>(
     archive: &mut ZipArchive<R>,
 ) -> Result<(String, String), IndexError> {
@@ -266,11 +415,20 @@ fn find_root_metadata_in_zip<R: Read + std::io::Seek>(
         }
     }
 
+    find_root_metadata(&entries).ok_or_else(|| IndexError::LoadError {
+        path: "zip".to_string(),
+        reason: "No root ro-crate-metadata.json found at archive root".to_string(),
+        source: None,
+    })
+}
+
+/// Find the root ro-crate-metadata.json among the entries of an archive
+/// Returns (full_path, root_prefix) where root_prefix is the top-level directory if any
+fn find_root_metadata(entries: &[String]) -> Option<(String, String)> {
     // First, check for metadata directly at root (no directory)
-    for entry in &entries {
-        if !entry.contains('/') && entry.ends_with("ro-crate-metadata.json") {
-            return Ok((entry.clone(), String::new()));
-        }
+    let at_root = entries.iter().filter(|entry| !entry.contains('/'));
+    if let Some(entry) = preferred_metadata_file(at_root) {
+        return Some((entry.clone(), String::new()));
     }
 
     // Find the common top-level directory (if archive was created by zipping a folder)
@@ -285,22 +443,26 @@ fn find_root_metadata_in_zip<R: Read + std::io::Seek>(
         let prefix = top_level_dirs.into_iter().next().unwrap();
         // Look for metadata in this single top-level directory
         let expected_root = format!("{}/", prefix);
-        for entry in &entries {
-            if entry.starts_with(&expected_root) {
-                let remainder = &entry[expected_root.len()..];
-                // Must be directly in the top-level dir, not a subdirectory
-                if !remainder.contains('/') && remainder.ends_with("ro-crate-metadata.json") {
-                    return Ok((entry.clone(), prefix.to_string()));
-                }
-            }
+        // Must be directly in the top-level dir, not a subdirectory
+        let in_root = entries.iter().filter(|entry| {
+            entry
+                .strip_prefix(&expected_root)
+                .is_some_and(|remainder| !remainder.contains('/'))
+        });
+        if let Some(entry) = preferred_metadata_file(in_root) {
+            return Some((entry.clone(), prefix.to_string()));
         }
     }
 
-    // If we have multiple top-level items, the root metadata must be at the actual root
-    Err(IndexError::LoadError {
-        path: "zip".to_string(),
-        reason: "No root ro-crate-metadata.json found at archive root".to_string(),
-    })
+    // BagIt bags hold the crate in their payload directory
+    let payload = format!("{}{}/", find_bag_prefix(entries)?, BAG_PAYLOAD_DIR);
+    let in_payload = entries.iter().filter(|entry| {
+        entry
+            .strip_prefix(&payload)
+            .is_some_and(|remainder| !remainder.contains('/'))
+    });
+    preferred_metadata_file(in_payload)
+        .map(|entry| (entry.clone(), payload.trim_end_matches('/').to_string()))
 }
 
 /// Find metadata files for specific subcrate entity IDs in a zip archive
@@ -313,25 +475,204 @@ pub fn find_subcrate_metadata_in_zip(
     let file = File::open(zip_path).map_err(|e| IndexError::LoadError {
         path: zip_path.display().to_string(),
         reason: format!("Failed to open zip file: {}", e),
+        source: Some(e.into()),
     })?;
 
     let mut archive = ZipArchive::new(file).map_err(|e| IndexError::LoadError {
         path: zip_path.display().to_string(),
         reason: format!("Failed to read zip archive: {}", e),
+        source: Some(e.into()),
     })?;
 
     // Collect all metadata entries (excluding root)
-    let mut metadata_entries: Vec<String> = Vec::new();
-    for i in 0..archive.len() {
-        if let Ok(file) = archive.by_index(i) {
-            let name = file.name();
-            if name.ends_with("ro-crate-metadata.json") {
-                metadata_entries.push(name.to_string());
+    let names: Vec<String> = archive.file_names().map(str::to_string).collect();
+    let metadata_entries: Vec<String> = names
+        .iter()
+        .filter(|name| is_metadata_file(name))
+        .cloned()
+        .collect();
+
+    let mut matches = match_subcrate_metadata(&metadata_entries, entity_ids, root_prefix);
+
+    // Subcrates not found as folders may be (within) nested zip archives,
+    // which are read once for all of them
+    let mut nested = HashMap::new();
+    for entity_id in entity_ids {
+        if matches.iter().any(|(id, _)| id == entity_id) {
+            continue;
+        }
+        let normalized = entity_id.trim_start_matches("./").trim_end_matches('/');
+        let expected = if root_prefix.is_empty() {
+            normalized.to_string()
+        } else {
+            format!("{}/{}", root_prefix, normalized)
+        };
+        let found = find_nested_zip_metadata(&mut archive, &names, &mut nested, &expected)
+            .map_err(|reason| IndexError::LoadError {
+                path: zip_path.display().to_string(),
+                reason,
+                source: None,
+            })?;
+        if let Some(member) = found {
+            matches.push((entity_id.clone(), member));
+        }
+    }
+    Ok(matches)
+}
+
+/// Separator between a nested archive and a member within it, as in
+/// "experiments.zip!/ro-crate-metadata.json"
+pub const NESTED_ARCHIVE_SEPARATOR: &str = "!/";
+
+/// Largest nested zip archive read into memory, whatever the read limit
+const MAX_NESTED_ARCHIVE_BYTES: u64 = 1 << 30;
+
+/// A zip archive nested in another, read into memory, with its entry names
+/// and the archives nested in it read so far
+struct NestedArchive {
+    archive: ZipArchive<Cursor<Vec<u8>>>,
+    names: Vec<String>,
+    nested: HashMap<String, NestedArchive>,
+}
+
+/// Find the metadata file of the crate at `path` within a zip archive whose
+/// entries are `names`, where `path` leads into a nested zip archive (e.g.
+/// "experiments.zip" or "experiments.zip/sub")
+///
+/// Returns the member path of the metadata file, with nested archives
+/// separated by [`NESTED_ARCHIVE_SEPARATOR`], or `None` if `path` doesn't
+/// lead into a nested archive holding a crate. Nested archives are read
+/// into memory, once: `nested` keeps those read before.
+fn find_nested_zip_metadata<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    names: &[String],
+    nested: &mut HashMap<String, NestedArchive>,
+    path: &str,
+) -> Result<Option<String>, String> {
+    // The nested archive is the first segment of `path` that is a zip entry
+    let Some(entry) = path
+        .match_indices('/')
+        .map(|(i, _)| &path[..i])
+        .chain(std::iter::once(path))
+        .find(|prefix| {
+            prefix.to_lowercase().ends_with(".zip") && names.iter().any(|name| name == prefix)
+        })
+    else {
+        return Ok(None);
+    };
+    let rest = path[entry.len()..].trim_start_matches('/');
+
+    let inner = match nested.entry(entry.to_string()) {
+        Entry::Occupied(read) => read.into_mut(),
+        Entry::Vacant(vacant) => {
+            let archive = read_nested_archive(archive, entry)?;
+            let names = archive.file_names().map(str::to_string).collect();
+            vacant.insert(NestedArchive {
+                archive,
+                names,
+                nested: HashMap::new(),
+            })
+        }
+    };
+    let Some((root_metadata, root_prefix)) = find_root_metadata(&inner.names) else {
+        return Ok(None);
+    };
+
+    let member = if rest.is_empty() {
+        root_metadata
+    } else {
+        let metadata_entries: Vec<String> = inner
+            .names
+            .iter()
+            .filter(|name| is_metadata_file(name))
+            .cloned()
+            .collect();
+        let found = match_subcrate_metadata(&metadata_entries, &[rest.to_string()], &root_prefix);
+        match found.into_iter().next() {
+            Some((_, member)) => member,
+            None => {
+                let expected = if root_prefix.is_empty() {
+                    rest.to_string()
+                } else {
+                    format!("{}/{}", root_prefix, rest)
+                };
+                let NestedArchive {
+                    archive,
+                    names,
+                    nested,
+                } = inner;
+                match find_nested_zip_metadata(archive, names, nested, &expected)? {
+                    Some(member) => member,
+                    None => return Ok(None),
+                }
             }
         }
+    };
+    Ok(Some(format!(
+        "{}{}{}",
+        entry, NESTED_ARCHIVE_SEPARATOR, member
+    )))
+}
+
+/// Read the zip archive `entry` of `archive` into memory, up to
+/// [`MAX_NESTED_ARCHIVE_BYTES`] and the read limit
+fn read_nested_archive<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    entry: &str,
+) -> Result<ZipArchive<Cursor<Vec<u8>>>, String> {
+    let error = |reason: String| format!("Failed to read nested zip archive {}: {}", entry, reason);
+    let file = archive.by_name(entry).map_err(|e| error(e.to_string()))?;
+    let mut content = Vec::new();
+    let read = read_to_end_limited(file.take(MAX_NESTED_ARCHIVE_BYTES + 1), &mut content)
+        .map_err(|e| error(e.to_string()))?;
+    if read as u64 > MAX_NESTED_ARCHIVE_BYTES {
+        return Err(error(format!(
+            "larger than {} bytes",
+            MAX_NESTED_ARCHIVE_BYTES
+        )));
     }
+    ZipArchive::new(Cursor::new(content)).map_err(|e| error(e.to_string()))
+}
+
+/// Read a member of a zip archive, descending into nested zip archives for
+/// members like "experiments.zip!/ro-crate-metadata.json"
+fn read_nested_zip_member<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    member: &str,
+) -> Result<Vec<u8>, String> {
+    if let Some((entry, rest)) = member.split_once(NESTED_ARCHIVE_SEPARATOR) {
+        return read_nested_zip_member(&mut read_nested_archive(archive, entry)?, rest);
+    }
+    let mut content = Vec::new();
+    let member_file = archive
+        .by_name(member)
+        .map_err(|e| format!("Failed to extract {}: {}", member, e))?;
+    read_to_end_limited(member_file, &mut content)
+        .map_err(|e| format!("Failed to extract {}: {}", member, e))?;
+    Ok(content)
+}
 
-    // Match entity IDs to metadata files
+/// Read a member of a zip archive by name, which may be within nested zip
+/// archives (see [`NESTED_ARCHIVE_SEPARATOR`])
+pub(crate) fn read_zip_member(path: &Path, member: &str) -> Result<Vec<u8>, IndexError> {
+    let load_error = |reason: String, source: Option<BoxError>| IndexError::LoadError {
+        path: path.display().to_string(),
+        reason,
+        source,
+    };
+    let file = File::open(path)
+        .map_err(|e| load_error(format!("Failed to open zip file: {}", e), Some(e.into())))?;
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| load_error(format!("Failed to read zip archive: {}", e), Some(e.into())))?;
+    read_nested_zip_member(&mut archive, member).map_err(|reason| load_error(reason, None))
+}
+
+/// Match subcrate entity IDs to the metadata entries of an archive
+fn match_subcrate_metadata(
+    metadata_entries: &[String],
+    entity_ids: &[String],
+    root_prefix: &str,
+) -> Vec<(String, String)> {
     let mut matches = Vec::new();
     for entity_id in entity_ids {
         // Normalize entity ID: remove leading ./ and trailing /
@@ -345,7 +686,7 @@ pub fn find_subcrate_metadata_in_zip(
         };
 
         // Look for metadata file in this directory
-        for entry in &metadata_entries {
+        for entry in metadata_entries {
             let entry_dir = extract_directory_from_metadata_path(entry);
 
             if entry_dir == expected_dir {
@@ -355,23 +696,256 @@ pub fn find_subcrate_metadata_in_zip(
         }
     }
 
-    Ok(matches)
+    matches
+}
+
+/// Open a tar archive, decompressing it if gzipped
+pub(crate) fn open_tar(path: &Path) -> Result<tar::Archive<Box<dyn Read>>, IndexError> {
+    let load_error = |reason: String, source: Option<BoxError>| IndexError::LoadError {
+        path: path.display().to_string(),
+        reason,
+        source,
+    };
+    let mut file = File::open(path)
+        .map_err(|e| load_error(format!("Failed to open tar file: {}", e), Some(e.into())))?;
+    let mut magic = [0u8; 2];
+    let gzipped = file.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b];
+    file.rewind()
+        .map_err(|e| load_error(format!("Failed to read tar file: {}", e), Some(e.into())))?;
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(tar::Archive::new(reader))
 }
 
-/// Load from a URL, handling both direct metadata URLs and directory URLs
+/// Name of a tar entry as a '/'-separated path without a leading "./"
+pub(crate) fn tar_entry_name<R: Read>(entry: &tar::Entry<R>) -> Option<String> {
+    let path = entry.path().ok()?;
+    let name = path_to_tar_name(&path);
+    (!name.is_empty()).then_some(name)
+}
+
+/// '/'-separated form of a tar entry path without a leading "./"
+fn path_to_tar_name(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            std::path::Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Names of all entries of a tar archive
+fn tar_entry_names(path: &Path) -> Result<Vec<String>, IndexError> {
+    let mut archive = open_tar(path)?;
+    let entries = archive.entries().map_err(|e| IndexError::LoadError {
+        path: path.display().to_string(),
+        reason: format!("Failed to read tar archive: {}", e),
+        source: Some(e.into()),
+    })?;
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| IndexError::LoadError {
+            path: path.display().to_string(),
+            reason: format!("Failed to read tar archive: {}", e),
+            source: Some(e.into()),
+        })?;
+        names.extend(tar_entry_name(&entry));
+    }
+    Ok(names)
+}
+
+/// Read a member of a tar archive by name
+pub(crate) fn read_tar_member(path: &Path, member: &str) -> Result<Vec<u8>, IndexError> {
+    let load_error = |reason: String, source: Option<BoxError>| IndexError::LoadError {
+        path: path.display().to_string(),
+        reason,
+        source,
+    };
+    let mut archive = open_tar(path)?;
+    let entries = archive
+        .entries()
+        .map_err(|e| load_error(format!("Failed to read tar archive: {}", e), Some(e.into())))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| {
+            load_error(format!("Failed to read tar archive: {}", e), Some(e.into()))
+        })?;
+        if tar_entry_name(&entry).as_deref() == Some(member) {
+            let mut content = Vec::new();
+            read_to_end_limited(&mut entry, &mut content).map_err(|e| {
+                load_error(
+                    format!("Failed to extract {}: {}", member, e),
+                    Some(e.into()),
+                )
+            })?;
+            return Ok(content);
+        }
+    }
+    Err(load_error(
+        format!("Failed to extract {}: not found", member),
+        None,
+    ))
+}
+
+/// Load an RO-Crate from a tar file (optionally gzipped) by extracting the root
+/// ro-crate-metadata.json
+/// Returns (crate_data, json_content, root_prefix)
+pub fn load_from_tar(path: &Path) -> Result<(RoCrate, String, String), IndexError> {
+    if !path.exists() {
+        return Err(IndexError::InvalidPath(path.to_path_buf()));
+    }
+
+    let (metadata_filename, root_prefix) = find_root_metadata_in_tar(path)?;
+    let (crate_data, content) = load_from_tar_subpath(path, &metadata_filename)?;
+
+    Ok((crate_data, content, root_prefix))
+}
+
+/// Top-level directory holding the root crate of a tar archive ("" if none)
+pub fn tar_root_prefix(path: &Path) -> Result<String, IndexError> {
+    let (_, root_prefix) = find_root_metadata_in_tar(path)?;
+    Ok(root_prefix)
+}
+
+/// Find the root ro-crate-metadata.json in a tar archive
+fn find_root_metadata_in_tar(path: &Path) -> Result<(String, String), IndexError> {
+    find_root_metadata(&tar_entry_names(path)?).ok_or_else(|| IndexError::LoadError {
+        path: path.display().to_string(),
+        reason: "No root ro-crate-metadata.json found at archive root".to_string(),
+        source: None,
+    })
+}
+
+/// Load a subcrate from within a tar archive
+pub fn load_from_tar_subpath(
+    tar_path: &Path,
+    subpath: &str,
+) -> Result<(RoCrate, String), IndexError> {
+    let content = read_tar_member(tar_path, subpath)?;
+    let content = String::from_utf8(content).map_err(|e| IndexError::LoadError {
+        path: tar_path.display().to_string(),
+        reason: format!("Failed to read metadata file: {}", e),
+        source: Some(e.into()),
+    })?;
+    audit::record(
+        AccessKind::TarMember,
+        &tar_path.display().to_string(),
+        Some(subpath),
+        content.as_bytes(),
+    );
+
+    let crate_data = read_crate_obj(&content, 0).map_err(|e| IndexError::LoadError {
+        path: tar_path.display().to_string(),
+        reason: format!("Failed to parse RO-Crate metadata: {:#?}", e),
+        source: None,
+    })?;
+
+    Ok((crate_data, content))
+}
+
+/// Find metadata files for specific subcrate entity IDs in a tar archive
+/// Only returns matches for the given entity IDs (based on the parent's @graph)
+pub fn find_subcrate_metadata_in_tar(
+    tar_path: &Path,
+    entity_ids: &[String],
+    root_prefix: &str,
+) -> Result<Vec<(String, String)>, IndexError> {
+    let metadata_entries: Vec<String> = tar_entry_names(tar_path)?
+        .into_iter()
+        .filter(|name| is_metadata_file(name))
+        .collect();
+    Ok(match_subcrate_metadata(
+        &metadata_entries,
+        entity_ids,
+        root_prefix,
+    ))
+}
+
+/// Load from a URL, handling both direct metadata URLs and directory URLs,
+/// with the default options
 pub fn load_from_url(url: &str) -> Result<(RoCrate, String), IndexError> {
-    let (final_url, content) = fetch_metadata_from_url(url)?;
+    load_from_url_with(url, &UrlLoaderOptions::DEFAULT)
+}
+
+/// Load from a URL like [`load_from_url`], fetching with `options`
+pub fn load_from_url_with(
+    url: &str,
+    options: &UrlLoaderOptions,
+) -> Result<(RoCrate, String), IndexError> {
+    let (final_url, content) = fetch_metadata_signposted(
+        url,
+        |url| fetch_url_with(url, options),
+        |url| link_header(url, options),
+    )?;
 
     let crate_data = read_crate_obj(&content, 0).map_err(|e| IndexError::LoadError {
         path: final_url,
         reason: format!("Failed to parse RO-Crate metadata: {:#?}", e),
+        source: None,
     })?;
 
     Ok((crate_data, content))
 }
 
-/// Fetch metadata from URL, trying /ro-crate-metadata.json if URL doesn't point to metadata
-fn fetch_metadata_from_url(url: &str) -> Result<(String, String), IndexError> {
+/// Load an RO-Crate from the object `key` in `bucket`, or the metadata object
+/// below it if `key` is a folder prefix
+pub fn load_from_s3(
+    api: &impl S3Api,
+    bucket: &str,
+    key: &str,
+) -> Result<(RoCrate, String), IndexError> {
+    let key = match s3_folder(key) {
+        _ if key.ends_with("ro-crate-metadata.json") => key.to_string(),
+        "" => "ro-crate-metadata.json".to_string(),
+        folder => format!("{}/ro-crate-metadata.json", folder),
+    };
+    let path = format!("{}{}/{}", S3_SCHEME, bucket, key);
+    let content = api
+        .get_object(bucket, &key)
+        .map_err(|e| IndexError::LoadError {
+            path: path.clone(),
+            reason: e.to_string(),
+            source: Some(e.into()),
+        })?;
+    let crate_data = read_crate_obj(&content, 0).map_err(|e| IndexError::LoadError {
+        path,
+        reason: format!("Failed to parse RO-Crate metadata: {:#?}", e),
+        source: None,
+    })?;
+    Ok((crate_data, content))
+}
+
+/// Fetch metadata from URL, trying /ro-crate-metadata.json if URL doesn't
+/// point to metadata, with `fetch_url` fetching a URL's body
+///
+/// Landing pages are followed to the metadata their HTML `<link>` elements
+/// point at (see [`signposting`](crate::signposting)).
+pub(crate) fn fetch_metadata_with(
+    url: &str,
+    fetch_url: impl Fn(&str) -> Result<String, IndexError>,
+) -> Result<(String, String), IndexError> {
+    fetch_metadata_signposted(url, fetch_url, |_| Vec::new())
+}
+
+/// Like [`fetch_metadata_with`], also following the signposts `links` finds
+/// in the `Link` header of a landing page
+pub(crate) fn fetch_metadata_signposted(
+    url: &str,
+    fetch_url: impl Fn(&str) -> Result<String, IndexError>,
+    links: impl Fn(&str) -> Vec<Signpost>,
+) -> Result<(String, String), IndexError> {
+    fetch_metadata_from(url, &fetch_url, &links, true)
+}
+
+fn fetch_metadata_from(
+    url: &str,
+    fetch_url: &impl Fn(&str) -> Result<String, IndexError>,
+    links: &impl Fn(&str) -> Vec<Signpost>,
+    follow_cite_as: bool,
+) -> Result<(String, String), IndexError> {
     // If URL already ends with ro-crate-metadata.json, fetch directly
     if url.ends_with("ro-crate-metadata.json") {
         let content = fetch_url(url)?;
@@ -393,27 +967,730 @@ fn fetch_metadata_from_url(url: &str) -> Result<(String, String), IndexError> {
     // Fall back to fetching URL directly (maybe it IS the metadata)
     let content = fetch_url(url)?;
     if content.trim().starts_with('{') {
-        Ok((url.to_string(), content))
-    } else {
-        Err(IndexError::LoadError {
+        return Ok((url.to_string(), content));
+    }
+
+    // Maybe it's a landing page signposting its metadata
+    let mut signposts = links(url);
+    signposts.extend(parse_html_links(&content, url));
+    if let Some(metadata_url) = metadata_link(&signposts).filter(|link| *link != url) {
+        let content = fetch_url(metadata_url)?;
+        if content.trim().starts_with('{') {
+            return Ok((metadata_url.to_string(), content));
+        }
+    }
+    match cite_as(&signposts).filter(|id| follow_cite_as && *id != url) {
+        Some(id) => fetch_metadata_from(id, fetch_url, links, false),
+        None => Err(IndexError::LoadError {
             path: url.to_string(),
             reason: "URL does not contain valid RO-Crate metadata".to_string(),
-        })
+            source: None,
+        }),
     }
 }
 
-/// Simple URL fetch
-fn fetch_url(url: &str) -> Result<String, IndexError> {
-    reqwest::blocking::get(url)
-        .map_err(|e| IndexError::LoadError {
-            path: url.to_string(),
-            reason: format!("HTTP request failed: {}", e),
-        })?
-        .text()
-        .map_err(|e| IndexError::LoadError {
-            path: url.to_string(),
-            reason: format!("Failed to read response: {}", e),
+/// Signposts in the `Link` header of `url`, none if it can't be fetched
+pub(crate) fn link_header(url: &str, options: &UrlLoaderOptions) -> Vec<Signpost> {
+    let client = reqwest::blocking::Client::new();
+    let Ok(response) = send_with_retries(url, options, || client.head(url)) else {
+        return Vec::new();
+    };
+    response
+        .headers()
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| parse_link_header(value, url))
+        .collect()
+}
+
+thread_local! {
+    /// Whether fetches of the current thread are forbidden
+    static OFFLINE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Guard of offline mode, restoring the previous mode when dropped
+pub struct Offline {
+    previous: bool,
+}
+
+impl Drop for Offline {
+    fn drop(&mut self) {
+        OFFLINE.with(|offline| offline.set(self.previous));
+    }
+}
+
+/// Make every fetch of the current thread fail with
+/// [`IndexError::NetworkDisabled`] until the guard is dropped
+///
+/// Consolidation with [`offline`](crate::ConsolidateOptions::offline) set
+/// does this for itself and the threads it spawns.
+pub fn go_offline() -> Offline {
+    set_offline(true)
+}
+
+/// Whether fetches of the current thread are forbidden
+pub fn is_offline() -> bool {
+    OFFLINE.with(Cell::get)
+}
+
+/// Set whether fetches of the current thread are forbidden, e.g. as on the
+/// thread that spawned it
+pub(crate) fn set_offline(offline: bool) -> Offline {
+    let previous = OFFLINE.with(|current| current.replace(offline));
+    Offline { previous }
+}
+
+thread_local! {
+    /// Most bytes a single document read on the current thread may take
+    static READ_LIMIT: Cell<Option<u64>> = const { Cell::new(None) };
+    /// Whether a read hit the limit since the innermost guard was made
+    static READ_LIMIT_HIT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Guard of a read limit, restoring the previous limit when dropped
+pub struct ReadLimit {
+    previous: Option<u64>,
+    previous_hit: bool,
+}
+
+impl ReadLimit {
+    /// Whether a read failed for exceeding the limit while the guard lived
+    pub fn hit(&self) -> bool {
+        READ_LIMIT_HIT.with(Cell::get)
+    }
+}
+
+impl Drop for ReadLimit {
+    fn drop(&mut self) {
+        READ_LIMIT.with(|limit| limit.set(self.previous));
+        READ_LIMIT_HIT.with(|hit| hit.set(self.previous_hit || hit.get()));
+    }
+}
+
+/// Make every read of a metadata document (file, archive member or HTTP
+/// body) on the current thread fail once it takes more than `max_bytes`,
+/// until the guard is dropped
+///
+/// Reads stop at the limit, so an oversized document is never held in
+/// memory whole. Limits nest, the smallest applying; `None` keeps the
+/// current one.
+pub fn limit_reads(max_bytes: Option<u64>) -> ReadLimit {
+    let previous = READ_LIMIT.with(Cell::get);
+    let limit = match (previous, max_bytes) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    READ_LIMIT.with(|current| current.set(limit));
+    let previous_hit = READ_LIMIT_HIT.with(|hit| hit.replace(false));
+    ReadLimit {
+        previous,
+        previous_hit,
+    }
+}
+
+/// Read `reader` to the end into `buf`, failing once it exceeds the read
+/// limit of the current thread
+pub(crate) fn read_to_end_limited(reader: impl Read, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let Some(max) = READ_LIMIT.with(Cell::get) else {
+        let mut reader = reader;
+        return reader.read_to_end(buf);
+    };
+    let read = reader.take(max.saturating_add(1)).read_to_end(buf)?;
+    check_read_limit(read as u64)?;
+    Ok(read)
+}
+
+/// Read `reader` as UTF-8 text, failing once it exceeds the read limit
+pub(crate) fn read_to_string_limited(reader: impl Read) -> io::Result<String> {
+    let mut content = Vec::new();
+    read_to_end_limited(reader, &mut content)?;
+    String::from_utf8(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Fail if a document of `bytes` exceeds the read limit of the current thread
+pub(crate) fn check_read_limit(bytes: u64) -> io::Result<()> {
+    match READ_LIMIT.with(Cell::get) {
+        Some(max) if bytes > max => {
+            READ_LIMIT_HIT.with(|hit| hit.set(true));
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("document exceeds the limit of {} bytes", max),
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// How long fetches may wait in total by default before retrying
+pub const DEFAULT_RETRY_WAIT_BUDGET: Duration = Duration::from_secs(300);
+
+/// Milliseconds the fetches of a run may wait in total before retrying
+static RETRY_WAIT_BUDGET_MS: AtomicU64 =
+    AtomicU64::new(DEFAULT_RETRY_WAIT_BUDGET.as_millis() as u64);
+
+/// Set how long the fetches of each run may wait in total before retrying
+/// a request
+///
+/// A run is a consolidation, or what a [`retry_budget`] guard covers.
+/// Fetches outside of both have the whole budget each. A fetch whose wait
+/// would exceed what is left of its run's budget fails instead of waiting.
+pub fn set_retry_wait_budget(budget: Duration) {
+    RETRY_WAIT_BUDGET_MS.store(budget.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// How long the fetches of each run may wait in total before retrying
+pub fn retry_wait_budget() -> Duration {
+    Duration::from_millis(RETRY_WAIT_BUDGET_MS.load(Ordering::Relaxed))
+}
+
+thread_local! {
+    /// Milliseconds the fetches of the current thread's run may still wait
+    static RETRY_WAIT_LEFT_MS: RefCell<Option<Arc<AtomicU64>>> = const { RefCell::new(None) };
+}
+
+/// Guard of a run's retry wait budget, restoring the previous one when
+/// dropped
+pub struct RetryBudget {
+    previous: Option<Arc<AtomicU64>>,
+}
+
+impl Drop for RetryBudget {
+    fn drop(&mut self) {
+        RETRY_WAIT_LEFT_MS.with(|left| left.replace(self.previous.take()));
+    }
+}
+
+/// Let the fetches of the current thread wait `budget` in total before
+/// retrying, until the guard is dropped
+///
+/// Consolidation does this for itself and the threads it spawns, with the
+/// budget set by [`set_retry_wait_budget`], unless a budget is already
+/// running.
+pub fn retry_budget(budget: Duration) -> RetryBudget {
+    share_retry_budget(Some(Arc::new(AtomicU64::new(budget.as_millis() as u64))))
+}
+
+/// The budget of the current thread's run, if one is running
+pub(crate) fn current_retry_budget() -> Option<Arc<AtomicU64>> {
+    RETRY_WAIT_LEFT_MS.with(|left| left.borrow().clone())
+}
+
+/// Make `budget` the one of the current thread, e.g. that of the thread
+/// that spawned it
+pub(crate) fn share_retry_budget(budget: Option<Arc<AtomicU64>>) -> RetryBudget {
+    let previous = RETRY_WAIT_LEFT_MS.with(|left| left.replace(budget));
+    RetryBudget { previous }
+}
+
+thread_local! {
+    /// Counter of the fetches of the current thread that failed
+    static FETCH_ERRORS: RefCell<Option<Arc<AtomicU64>>> = const { RefCell::new(None) };
+}
+
+/// Guard of a fetch error counter, restoring the previous one when dropped
+pub struct FetchErrorCounter {
+    previous: Option<Arc<AtomicU64>>,
+}
+
+impl Drop for FetchErrorCounter {
+    fn drop(&mut self) {
+        FETCH_ERRORS.with(|errors| errors.replace(self.previous.take()));
+    }
+}
+
+/// Count the fetches of the current thread that fail in `counter`, until
+/// the guard is dropped
+///
+/// A fetch fails when its request can't be sent or it is answered with an
+/// error status, after any retries. Consolidation counts the fetches of the
+/// threads it spawns in the same counter.
+pub fn count_fetch_errors(counter: Arc<AtomicU64>) -> FetchErrorCounter {
+    share_fetch_error_counter(Some(counter))
+}
+
+/// The fetch error counter of the current thread, if there is one
+pub(crate) fn current_fetch_error_counter() -> Option<Arc<AtomicU64>> {
+    FETCH_ERRORS.with(|errors| errors.borrow().clone())
+}
+
+/// Make `counter` the one of the current thread, e.g. that of the thread
+/// that spawned it
+pub(crate) fn share_fetch_error_counter(counter: Option<Arc<AtomicU64>>) -> FetchErrorCounter {
+    let previous = FETCH_ERRORS.with(|errors| errors.replace(counter));
+    FetchErrorCounter { previous }
+}
+
+/// Timeout of each HTTP request by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How URLs are fetched
+#[derive(Debug, Clone)]
+pub struct UrlLoaderOptions {
+    /// How often a request is retried after a connection error, a timeout or
+    /// a 429 or 5xx response
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each further one, unless the
+    /// server asks for another with `Retry-After`
+    pub backoff: Duration,
+    /// Timeout of each request, or `None` to wait indefinitely
+    pub timeout: Option<Duration>,
+    /// Credentials and headers sent with requests to the URLs they are for
+    pub auth: Option<Arc<HttpAuth>>,
+    /// Cache fetched bodies are stored in and revalidated from
+    pub cache: Option<Arc<HttpCache>>,
+}
+
+impl UrlLoaderOptions {
+    /// Three retries after one, two and four seconds, each request timing
+    /// out after 30 seconds
+    pub const DEFAULT: Self = Self {
+        retries: 3,
+        backoff: Duration::from_secs(1),
+        timeout: Some(DEFAULT_TIMEOUT),
+        auth: None,
+        cache: None,
+    };
+}
+
+impl Default for UrlLoaderOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Credentials and headers for protected endpoints
+///
+/// They are only sent with requests to URLs of the origin (scheme, host and
+/// port) of one of the prefixes they are for, below its path, so crates
+/// referencing other hosts can't collect them. Redirects are followed
+/// without them once they leave the prefixes. Prefixes should end with a
+/// '/', e.g. "https://data.example.org/".
+#[derive(Clone, Default)]
+pub struct HttpAuth {
+    prefixes: Vec<String>,
+    credentials: Option<Credentials>,
+    headers: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+enum Credentials {
+    Bearer(String),
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+}
+
+impl HttpAuth {
+    /// Credentials for nothing yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send the credentials and headers with requests to URLs starting with
+    /// `prefix`
+    pub fn for_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefixes.push(prefix.into());
+        self
+    }
+
+    /// Authenticate with `token` as bearer token
+    pub fn with_bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.credentials = Some(Credentials::Bearer(token.into()));
+        self
+    }
+
+    /// Authenticate with HTTP basic authentication
+    pub fn with_basic_auth(mut self, user: impl Into<String>, password: Option<String>) -> Self {
+        self.credentials = Some(Credentials::Basic {
+            user: user.into(),
+            password,
+        });
+        self
+    }
+
+    /// Send the header `name` with `value`
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Check if requests to `url` carry the credentials and headers
+    pub fn applies_to(&self, url: &str) -> bool {
+        let Ok(url) = Url::parse(url) else {
+            return false;
+        };
+        self.prefixes.iter().any(|prefix| match Url::parse(prefix) {
+            Ok(prefix) => url.origin() == prefix.origin() && url.path().starts_with(prefix.path()),
+            Err(_) => false,
         })
+    }
+
+    /// Send `request` for `url`, following redirects by hand so that the
+    /// credentials and headers go only to the URLs they apply to
+    ///
+    /// Only GET and HEAD requests are redirected; other redirect responses
+    /// are returned as they are.
+    fn send(&self, url: &str, request: RequestBuilder) -> reqwest::Result<Response> {
+        let client = Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        let mut request = self.apply(url, request).build()?;
+        for _ in 0..MAX_REDIRECTS {
+            let (method, timeout) = (request.method().clone(), request.timeout().copied());
+            let mut headers = request.headers().clone();
+            let response = client.execute(request)?;
+            let location = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .and_then(|location| response.url().join(location).ok());
+            let next = match location {
+                Some(next) if response.status().is_redirection() => next,
+                _ => return Ok(response),
+            };
+            if method != Method::GET && method != Method::HEAD {
+                return Ok(response);
+            }
+            headers.remove(AUTHORIZATION);
+            for (name, _) in &self.headers {
+                headers.remove(name.as_str());
+            }
+            let mut next_request = client.request(method, next.clone()).headers(headers);
+            if let Some(timeout) = timeout {
+                next_request = next_request.timeout(timeout);
+            }
+            request = self.apply(next.as_str(), next_request).build()?;
+        }
+        client.execute(request)
+    }
+
+    /// Add the credentials and headers to `request` if it is for `url`s
+    /// they apply to
+    fn apply(&self, url: &str, mut request: RequestBuilder) -> RequestBuilder {
+        if !self.applies_to(url) {
+            return request;
+        }
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        match &self.credentials {
+            Some(Credentials::Bearer(token)) => request.bearer_auth(token),
+            Some(Credentials::Basic { user, password }) => {
+                request.basic_auth(user, password.as_ref())
+            }
+            None => request,
+        }
+    }
+}
+
+impl fmt::Debug for HttpAuth {
+    // Keep secrets out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let headers: Vec<&str> = self.headers.iter().map(|(name, _)| name.as_str()).collect();
+        let credentials = self
+            .credentials
+            .as_ref()
+            .map(|credentials| match credentials {
+                Credentials::Bearer(_) => "bearer",
+                Credentials::Basic { .. } => "basic",
+            });
+        f.debug_struct("HttpAuth")
+            .field("prefixes", &self.prefixes)
+            .field("credentials", &credentials)
+            .field("headers", &headers)
+            .finish()
+    }
+}
+
+/// Redirects followed by hand, as many as reqwest follows by default
+pub(crate) const MAX_REDIRECTS: usize = 10;
+
+/// Send the request made by `request` to `url`, retrying as `options` allow
+///
+/// Connection errors, timeouts and 429 and 5xx responses are retried after
+/// the time a `Retry-After` header asks for, or after an exponential
+/// backoff without one, as long as the run's wait budget lasts (see
+/// [`retry_budget`]). Nothing is sent while offline (see [`go_offline`]).
+/// Responses other than successes and 304 Not Modified fail, and failures
+/// are counted (see [`count_fetch_errors`]).
+pub(crate) fn send_with_retries(
+    url: &str,
+    options: &UrlLoaderOptions,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<Response, IndexError> {
+    send_counting(url, options, request, false)
+}
+
+/// Like [`send_with_retries`], returning redirect responses instead of
+/// failing, for clients following redirects by hand
+pub(crate) fn send_with_retries_unredirected(
+    url: &str,
+    options: &UrlLoaderOptions,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<Response, IndexError> {
+    send_counting(url, options, request, true)
+}
+
+/// [`send_retrying`] unless offline, counting failures
+fn send_counting(
+    url: &str,
+    options: &UrlLoaderOptions,
+    request: impl Fn() -> RequestBuilder,
+    redirects: bool,
+) -> Result<Response, IndexError> {
+    if is_offline() {
+        return Err(IndexError::NetworkDisabled(url.to_string()));
+    }
+    let sent = send_retrying(url, options, request, redirects);
+    if sent.is_err() {
+        if let Some(counter) = current_fetch_error_counter() {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    sent
+}
+
+/// [`send_with_retries`] without counting failures, also returning
+/// redirect responses if `redirects` is set
+fn send_retrying(
+    url: &str,
+    options: &UrlLoaderOptions,
+    request: impl Fn() -> RequestBuilder,
+    redirects: bool,
+) -> Result<Response, IndexError> {
+    let load_error = |reason: String, source: Option<BoxError>| IndexError::LoadError {
+        path: url.to_string(),
+        reason,
+        source,
+    };
+    let budget = current_retry_budget()
+        .unwrap_or_else(|| Arc::new(AtomicU64::new(retry_wait_budget().as_millis() as u64)));
+    let mut attempt = 0;
+    loop {
+        let request = match options.timeout {
+            Some(timeout) => request().timeout(timeout),
+            None => request(),
+        };
+        let sent = match &options.auth {
+            Some(auth) if auth.applies_to(url) => auth.send(url, request),
+            _ => request.send(),
+        };
+        let (wait, failure, error) = match sent {
+            Ok(response) if !is_retryable(response.status()) => {
+                let status = response.status();
+                let redirect = status.is_redirection() && response.headers().contains_key(LOCATION);
+                if status.is_success()
+                    || status == StatusCode::NOT_MODIFIED
+                    || (redirects && redirect)
+                {
+                    return Ok(response);
+                }
+                return Err(load_error(format!("HTTP {}", status), None));
+            }
+            Ok(response) => (
+                retry_after(&response).unwrap_or_else(|| backoff(options, attempt)),
+                format!("HTTP {}", response.status()),
+                None,
+            ),
+            Err(e) if e.is_connect() || e.is_timeout() => (
+                backoff(options, attempt),
+                format!("HTTP request failed: {}", e),
+                Some(e),
+            ),
+            Err(e) => {
+                return Err(load_error(
+                    format!("HTTP request failed: {}", e),
+                    Some(e.into()),
+                ))
+            }
+        };
+        if attempt >= options.retries {
+            return Err(load_error(
+                format!("{} (after {} retries)", failure, attempt),
+                error.map(Into::into),
+            ));
+        }
+        let wait_ms = wait.as_millis() as u64;
+        let left = budget.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+            left.checked_sub(wait_ms)
+        });
+        if left.is_err() {
+            return Err(load_error(
+                format!(
+                    "{}, retrying in {}s would exceed the retry wait budget",
+                    failure,
+                    wait.as_secs(),
+                ),
+                error.map(Into::into),
+            ));
+        }
+        std::thread::sleep(wait);
+        attempt += 1;
+    }
+}
+
+/// Whether a response with `status` may succeed when retried
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// The wait before retry number `attempt` + 1 without `Retry-After`
+fn backoff(options: &UrlLoaderOptions, attempt: u32) -> Duration {
+    options.backoff * (1 << attempt.min(6))
+}
+
+/// How long the server asks to wait before retrying, if it does
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after)
+}
+
+/// Parse a `Retry-After` value, in seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Fetch the body of a URL as text, with the default options
+/// ([`UrlLoaderOptions::DEFAULT`])
+pub fn fetch_url(url: &str) -> Result<String, IndexError> {
+    fetch_url_with(url, &UrlLoaderOptions::DEFAULT)
+}
+
+/// Fetch the body of a URL as text
+///
+/// Failed requests are retried as `options` allow (see also
+/// [`set_retry_wait_budget`]). With a cache, a cached body is revalidated
+/// with its `ETag` and `Last-Modified` and only downloaded again if it
+/// changed. Responses to requests with credentials aren't cached.
+pub fn fetch_url_with(url: &str, options: &UrlLoaderOptions) -> Result<String, IndexError> {
+    let cache = options.cache.as_deref().filter(|_| {
+        !options
+            .auth
+            .as_ref()
+            .is_some_and(|auth| auth.applies_to(url))
+    });
+    let cached = cache.and_then(|cache| cache.get(url));
+    let (content, from_cache, etag, last_modified) = match (cache, cached) {
+        (Some(cache), Some((entry, body))) if cache.is_fresh(&entry) => {
+            (body, true, entry.etag, entry.last_modified)
+        }
+        (cache, cached) => {
+            let client = reqwest::blocking::Client::new();
+            let response = send_with_retries(url, options, || {
+                let mut request = client.get(url);
+                if let Some((entry, _)) = &cached {
+                    if let Some(etag) = &entry.etag {
+                        request = request.header(IF_NONE_MATCH, etag);
+                    }
+                    if let Some(last_modified) = &entry.last_modified {
+                        request = request.header(IF_MODIFIED_SINCE, last_modified);
+                    }
+                }
+                request
+            })?;
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string)
+            };
+            let (etag, last_modified) = (header(ETAG), header(LAST_MODIFIED));
+            let status = response.status();
+            let (etag, last_modified, body) = match cached {
+                Some((entry, body)) if status == StatusCode::NOT_MODIFIED => {
+                    check_read_limit(body.len() as u64).map_err(|e| IndexError::LoadError {
+                        path: url.to_string(),
+                        reason: e.to_string(),
+                        source: Some(e.into()),
+                    })?;
+                    (
+                        entry.etag.or(etag),
+                        entry.last_modified.or(last_modified),
+                        body,
+                    )
+                }
+                _ => {
+                    let body =
+                        read_to_string_limited(response).map_err(|e| IndexError::LoadError {
+                            path: url.to_string(),
+                            reason: format!("Failed to read response: {}", e),
+                            source: Some(e.into()),
+                        })?;
+                    (etag, last_modified, body)
+                }
+            };
+            if let Some(cache) = cache {
+                if etag.is_some() || last_modified.is_some() {
+                    // The cache only saves downloads, failing to update it
+                    // doesn't fail the fetch
+                    let _ = cache.put(url, etag.as_deref(), last_modified.as_deref(), &body);
+                }
+            }
+            (
+                body,
+                status == StatusCode::NOT_MODIFIED,
+                etag,
+                last_modified,
+            )
+        }
+    };
+    audit::record_fetch(
+        url,
+        content.as_bytes(),
+        from_cache,
+        etag.as_deref(),
+        last_modified.as_deref(),
+    );
+    Ok(content)
+}
+
+/// Fetch the body of a URL as text unless it is unchanged since it was
+/// served with `etag` and `last_modified`
+///
+/// The request is conditional on the validators given, and `None` is
+/// returned if the server answers that nothing changed. Unlike
+/// [`fetch_url_with`], neither the cache nor the audit log are involved, and
+/// error responses fail.
+pub(crate) fn fetch_url_if_modified(
+    url: &str,
+    etag: Option<&str>,
+    last_modified: Option<&str>,
+    options: &UrlLoaderOptions,
+) -> Result<Option<String>, IndexError> {
+    let client = reqwest::blocking::Client::new();
+    let response = send_with_retries(url, options, || {
+        let mut request = client.get(url);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request
+    })?;
+    let load_error = |reason: String, source: Option<BoxError>| IndexError::LoadError {
+        path: url.to_string(),
+        reason,
+        source,
+    };
+    match response.status() {
+        StatusCode::NOT_MODIFIED => Ok(None),
+        status if status.is_success() => read_to_string_limited(response)
+            .map(Some)
+            .map_err(|e| load_error(format!("Failed to read response: {}", e), Some(e.into()))),
+        status => Err(load_error(format!("HTTP {}", status), None)),
+    }
 }
 
 /// Load from a directory and return both the crate and raw JSON
@@ -422,41 +1699,42 @@ pub fn load_from_directory_with_json(path: &PathBuf) -> Result<(RoCrate, String)
 
     // Find metadata file (could have prefix)
     let metadata_path = find_metadata_in_directory(path)?;
-    let content = std::fs::read_to_string(&metadata_path).map_err(|e| IndexError::LoadError {
+    let content = audit::read_file(&metadata_path).map_err(|e| IndexError::LoadError {
         path: metadata_path.display().to_string(),
         reason: e.to_string(),
+        source: Some(e.into()),
     })?;
 
     Ok((crate_data, content))
 }
 
 /// Find ro-crate-metadata.json (with optional prefix) in a directory
+///
+/// Of several metadata files, the one matching the earliest pattern of
+/// [`metadata_file_patterns`](crate::metadata_file::metadata_file_patterns)
+/// is taken, the first by name if there are several.
 fn find_metadata_in_directory(path: &PathBuf) -> Result<PathBuf, IndexError> {
-    // Try standard name first
-    let standard = path.join("ro-crate-metadata.json");
-    if standard.exists() {
-        return Ok(standard);
-    }
-
-    // Look for *-ro-crate-metadata.json
+    let payload = bag_payload_dir(path);
+    let path = payload.as_ref().unwrap_or(path);
     if let Ok(entries) = std::fs::read_dir(path) {
-        for entry in entries.flatten() {
-            let name = entry.file_name();
-            if let Some(name_str) = name.to_str() {
-                if name_str.ends_with("-ro-crate-metadata.json") {
-                    return Ok(entry.path());
-                }
-            }
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        if let Some(name) = preferred_metadata_file(&names) {
+            return Ok(path.join(name));
         }
     }
 
     Err(IndexError::LoadError {
         path: path.display().to_string(),
         reason: "No ro-crate-metadata.json found".to_string(),
+        source: None,
     })
 }
 
-/// Load from any source, returning crate, JSON, and optional root prefix (for zips)
+/// Load from any source, returning crate, JSON, and optional root prefix (for zips and tars)
 pub fn load_with_json(source: &CrateSource) -> Result<(RoCrate, String, String), IndexError> {
     match source {
         CrateSource::Directory(p) => {
@@ -478,6 +1756,17 @@ pub fn load_with_json(source: &CrateSource) -> Result<(RoCrate, String, String),
             let (crate_data, json) = load_from_url(metadata_url)?;
             Ok((crate_data, json, String::new()))
         }
+        CrateSource::S3 { bucket, key } => {
+            let (crate_data, json) = load_from_s3(&S3Client::from_env(), bucket, key)?;
+            Ok((crate_data, json, String::new()))
+        }
+        CrateSource::TarFile { path, .. } => load_from_tar(path),
+        CrateSource::TarSubcrate {
+            tar_path, subpath, ..
+        } => {
+            let (crate_data, json) = load_from_tar_subpath(tar_path, subpath)?;
+            Ok((crate_data, json, String::new()))
+        }
     }
 }
 
@@ -514,6 +1803,16 @@ mod tests {
         let url_meta_source =
             CrateSource::Url("https://example.org/data/ro-crate-metadata.json".to_string());
         assert_eq!(url_meta_source.to_crate_id(), "https://example.org/data");
+
+        let s3_source = CrateSource::S3 {
+            bucket: "bucket".to_string(),
+            key: "crates/data/ro-crate-metadata.json".to_string(),
+        };
+        assert_eq!(s3_source.to_crate_id(), "s3://bucket/crates/data");
+        assert_eq!(
+            s3_source.base_url().as_deref(),
+            Some("s3://bucket/crates/data/")
+        );
     }
 
     #[test]
@@ -573,4 +1872,522 @@ mod tests {
         assert!(!id.contains('/'));
         assert!(!id.contains("rocrate_"));
     }
+    /// Add a file to a tar archive being built
+    fn append<W: std::io::Write>(builder: &mut tar::Builder<W>, path: &str, content: &str) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, content.as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_load_from_tar() {
+        let dir = std::env::temp_dir().join(format!("tar-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let metadata = |name: &str| {
+            format!(
+                r#"{{"@graph": [{{"@id": "ro-crate-metadata.json", "about": {{"@id": "./"}}}},
+                {{"@id": "./", "@type": "Dataset", "name": "{}"}}]}}"#,
+                name
+            )
+        };
+
+        // Gzipped, with the crate in a top-level folder
+        let gzipped = dir.join("Crate.TAR.GZ");
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(&gzipped).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        append(
+            &mut builder,
+            "./crate/ro-crate-metadata.json",
+            &metadata("Root"),
+        );
+        append(
+            &mut builder,
+            "./crate/sub/ro-crate-metadata.json",
+            &metadata("Sub"),
+        );
+        append(&mut builder, "./crate/data.csv", "a,b");
+        builder.into_inner().unwrap().finish().unwrap();
+
+        assert!(is_tar_path(&gzipped));
+        let (_, content, prefix) = load_from_tar(&gzipped).unwrap();
+        assert!(content.contains("Root"));
+        assert_eq!(prefix, "crate");
+        let matches =
+            find_subcrate_metadata_in_tar(&gzipped, &["./sub/".to_string()], &prefix).unwrap();
+        assert_eq!(
+            matches,
+            vec![(
+                "./sub/".to_string(),
+                "crate/sub/ro-crate-metadata.json".to_string()
+            )]
+        );
+        let (_, content) = load_from_tar_subpath(&gzipped, &matches[0].1).unwrap();
+        assert!(content.contains("Sub"));
+        let id = CrateSource::tar(gzipped).to_crate_id();
+        assert!(id.ends_with("/Crate"));
+
+        // Uncompressed, with the crate at the archive root
+        let plain = dir.join("plain.tar");
+        let mut builder = tar::Builder::new(File::create(&plain).unwrap());
+        append(&mut builder, "ro-crate-metadata.json", &metadata("Plain"));
+        builder.into_inner().unwrap();
+        let (_, content, prefix) = load_with_json(&CrateSource::tar(plain.clone())).unwrap();
+        assert!(content.contains("Plain"));
+        assert_eq!(prefix, "");
+        assert!(load_from_tar_subpath(&plain, "sub/ro-crate-metadata.json").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_nested_zip_subcrates() {
+        let metadata = |name: &str| {
+            format!(
+                r#"{{"@graph": [{{"@id": "ro-crate-metadata.json", "about": {{"@id": "./"}}}},
+                {{"@id": "./", "@type": "Dataset", "name": "{}"}}]}}"#,
+                name
+            )
+        };
+        let zip = |entries: &[(&str, &[u8])]| {
+            let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+            for (name, content) in entries {
+                writer
+                    .start_file(*name, zip::write::SimpleFileOptions::default())
+                    .unwrap();
+                std::io::Write::write_all(&mut writer, content).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+
+        // experiments.zip holds a crate in a top-level folder, with a
+        // subcrate packaged as yet another zip file
+        let innermost = zip(&[("ro-crate-metadata.json", metadata("Run").as_bytes())]);
+        let inner = zip(&[
+            (
+                "exp/ro-crate-metadata.json",
+                metadata("Experiments").as_bytes(),
+            ),
+            ("exp/sub/ro-crate-metadata.json", metadata("Sub").as_bytes()),
+            ("exp/run.zip", &innermost),
+        ]);
+        let outer = zip(&[
+            ("ro-crate-metadata.json", metadata("Root").as_bytes()),
+            ("experiments.zip", &inner),
+        ]);
+        let path = std::env::temp_dir().join(format!("nested-{}.zip", std::process::id()));
+        std::fs::write(&path, outer).unwrap();
+
+        let ids: Vec<String> = [
+            "experiments.zip",
+            "./experiments.zip/sub/",
+            "experiments.zip/run.zip",
+            "missing.zip",
+        ]
+        .iter()
+        .map(|id| id.to_string())
+        .collect();
+        let matches = find_subcrate_metadata_in_zip(&path, &ids, "").unwrap();
+        let members: Vec<&str> = matches.iter().map(|(_, m)| m.as_str()).collect();
+        assert_eq!(
+            members,
+            vec![
+                "experiments.zip!/exp/ro-crate-metadata.json",
+                "experiments.zip!/exp/sub/ro-crate-metadata.json",
+                "experiments.zip!/exp/run.zip!/ro-crate-metadata.json",
+            ]
+        );
+        for ((_, member), name) in matches.iter().zip(["Experiments", "Sub", "Run"]) {
+            let (_, content) = load_from_zip_subpath(&path, member).unwrap();
+            assert!(content.contains(name));
+            assert_eq!(read_zip_member(&path, member).unwrap(), content.as_bytes());
+        }
+
+        // A nested archive that can't be read fails the search
+        let broken = zip(&[
+            ("ro-crate-metadata.json", metadata("Root").as_bytes()),
+            ("broken.zip", b"not a zip archive"),
+        ]);
+        std::fs::write(&path, broken).unwrap();
+        let err =
+            find_subcrate_metadata_in_zip(&path, &["broken.zip".to_string()], "").unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Failed to read nested zip archive broken.zip"),
+            "{}",
+            err
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_archive_stem() {
+        assert_eq!(archive_stem("crate.zip"), Some("crate"));
+        assert_eq!(archive_stem("crate.tar.gz"), Some("crate"));
+        assert_eq!(archive_stem("crate.TGZ"), Some("crate"));
+        assert_eq!(archive_stem("crate.tar"), Some("crate"));
+        assert_eq!(archive_stem("crate.gz"), None);
+        assert!(!is_tar_path(Path::new("crate.zip")));
+    }
+
+    /// Server answering one request with each of `responses`, in order
+    fn http_server(responses: Vec<String>) -> String {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/ro-crate-metadata.json",
+            listener.local_addr().unwrap()
+        );
+        std::thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let mut stream = reader.into_inner();
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    fn response(status: &str, retry_after: Option<&str>, body: &str) -> String {
+        let retry_after = retry_after.map_or(String::new(), |v| format!("Retry-After: {}\r\n", v));
+        format!(
+            "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            retry_after,
+            body.len(),
+            body
+        )
+    }
+
+    #[test]
+    fn test_fetch_url_retries_after_rate_limit() {
+        let url = http_server(vec![
+            response("429 Too Many Requests", Some("0"), ""),
+            response("503 Service Unavailable", Some("0"), ""),
+            response("200 OK", None, "{}"),
+        ]);
+        assert_eq!(fetch_url(&url).unwrap(), "{}");
+
+        // Waits beyond the budget fail right away
+        let url = http_server(vec![response("429 Too Many Requests", Some("86400"), "")]);
+        let err = fetch_url(&url).unwrap_err();
+        assert!(err.to_string().contains("retry wait budget"), "{}", err);
+    }
+
+    #[test]
+    fn test_retry_budget_per_run() {
+        let rate_limited = || {
+            http_server(vec![
+                response("429 Too Many Requests", Some("1"), ""),
+                response("200 OK", None, "{}"),
+            ])
+        };
+
+        // The waits of a run come out of its budget
+        let run = retry_budget(Duration::from_millis(1500));
+        assert_eq!(fetch_url(&rate_limited()).unwrap(), "{}");
+        let err = fetch_url(&rate_limited()).unwrap_err();
+        assert!(err.to_string().contains("retry wait budget"), "{}", err);
+        drop(run);
+
+        // The next run starts with a budget of its own
+        let _run = retry_budget(Duration::from_millis(1500));
+        assert_eq!(fetch_url(&rate_limited()).unwrap(), "{}");
+    }
+
+    #[test]
+    fn test_count_fetch_errors() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let errors = count_fetch_errors(Arc::clone(&counter));
+
+        // Fetches that succeed after retries aren't failures
+        let url = http_server(vec![
+            response("503 Service Unavailable", Some("0"), ""),
+            response("200 OK", None, "{}"),
+        ]);
+        assert_eq!(fetch_url(&url).unwrap(), "{}");
+        assert_eq!(counter.load(Ordering::Relaxed), 0);
+
+        // Error responses are failures
+        let url = http_server(vec![response("404 Not Found", None, "")]);
+        assert!(fetch_url(&url).is_err());
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+
+        // Fetches after the guard is dropped aren't counted
+        drop(errors);
+        let url = http_server(vec![response("404 Not Found", None, "")]);
+        let _ = fetch_url(&url);
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_error_responses_fail() {
+        use crate::consolidate::{SubcrateLoader, UrlLoader};
+        use crate::mirror::Mirrors;
+
+        // A subcrate answering with an error page, even a JSON one, isn't loaded
+        let not_found = || response("404 Not Found", None, "{\"error\": \"not found\"}");
+        let url = http_server(vec![not_found()]);
+        let log = Arc::new(audit::AuditLog::new());
+        let active = log.activate();
+        let loader = UrlLoader::new(url.trim_end_matches("ro-crate-metadata.json"));
+        let err = loader.load("sub/", "", None).unwrap_err();
+        assert!(err.to_string().contains("HTTP 404"), "{}", err);
+        drop(active);
+        assert!(log.records().is_empty());
+
+        // Sources answering with an error fall back to their mirrors
+        let source = http_server(vec![not_found()]);
+        let mirror = http_server(vec![response("200 OK", None, "{}")]);
+        let mirrors = Mirrors::new().with_mirror(&source, &mirror);
+        assert_eq!(mirrors.fetch(&source).unwrap(), "{}");
+        assert_eq!(mirrors.used().len(), 1);
+    }
+
+    #[test]
+    fn test_fetch_url_revalidates_cache() {
+        let dir = std::env::temp_dir().join(format!("rocrate-http-cache-{}", Ulid::new()));
+        let cache = Arc::new(HttpCache::new(&dir).unwrap());
+        let options = UrlLoaderOptions {
+            cache: Some(Arc::clone(&cache)),
+            ..UrlLoaderOptions::DEFAULT
+        };
+        let with_etag = |status: &str, etag: &str, body: &str| {
+            format!(
+                "HTTP/1.1 {}\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                etag,
+                body.len(),
+                body
+            )
+        };
+        let url = http_server(vec![
+            with_etag("200 OK", "\"v1\"", "{\"v\": 1}"),
+            with_etag("304 Not Modified", "\"v1\"", ""),
+            with_etag("200 OK", "\"v2\"", "{\"v\": 2}"),
+        ]);
+        let log = Arc::new(audit::AuditLog::new());
+        let active = log.activate();
+        assert_eq!(fetch_url_with(&url, &options).unwrap(), "{\"v\": 1}");
+        // Not modified, so served from the cache
+        assert_eq!(fetch_url_with(&url, &options).unwrap(), "{\"v\": 1}");
+        assert_eq!(fetch_url_with(&url, &options).unwrap(), "{\"v\": 2}");
+        drop(active);
+        let cached: Vec<bool> = log.records().iter().map(|r| r.cached).collect();
+        assert_eq!(cached, [false, true, false]);
+        let (entry, _) = cache.get(&url).unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"v2\""));
+
+        // Fresh entries are served without asking the server, which is gone
+        let fresh = UrlLoaderOptions {
+            cache: Some(Arc::new(
+                HttpCache::new(&dir)
+                    .unwrap()
+                    .with_max_age(Duration::from_secs(3600)),
+            )),
+            ..UrlLoaderOptions::DEFAULT
+        };
+        assert_eq!(fetch_url_with(&url, &fresh).unwrap(), "{\"v\": 2}");
+
+        // Corrupted bodies aren't served
+        let object = dir.join("objects").join(&entry.sha256);
+        std::fs::write(&object, "{}").unwrap();
+        assert!(cache.get(&url).is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_metadata_follows_signposts() {
+        let with_link = |link: &str| {
+            format!(
+                "HTTP/1.1 200 OK\r\nLink: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                link
+            )
+        };
+        let landing = |url: String| url.replace("ro-crate-metadata.json", "records/1");
+        let url = landing(http_server(vec![
+            response("404 Not Found", None, ""),
+            response("200 OK", None, "<html></html>"),
+            with_link(r#"</files/crate.json>; rel="describedby"; type="application/ld+json""#),
+            response("200 OK", None, "{\"@graph\": []}"),
+        ]));
+        let (metadata_url, content) = fetch_metadata_signposted(
+            &url,
+            |url| fetch_url_with(url, &UrlLoaderOptions::DEFAULT),
+            |url| link_header(url, &UrlLoaderOptions::DEFAULT),
+        )
+        .unwrap();
+        assert!(
+            metadata_url.ends_with("/files/crate.json"),
+            "{}",
+            metadata_url
+        );
+        assert_eq!(content, "{\"@graph\": []}");
+
+        // Without Link headers, the page's <link> elements are followed
+        let html = r#"<head><link rel="describedby" href="ro-crate-metadata.json"></head>"#;
+        let url = landing(http_server(vec![
+            response("404 Not Found", None, ""),
+            response("200 OK", None, html),
+            response("200 OK", None, "{}"),
+        ]));
+        let (metadata_url, _) =
+            fetch_metadata_with(&url, |url| fetch_url_with(url, &UrlLoaderOptions::DEFAULT))
+                .unwrap();
+        assert!(metadata_url.ends_with("/records/ro-crate-metadata.json"));
+    }
+
+    #[test]
+    fn test_fetch_url_with_options() {
+        let options = UrlLoaderOptions {
+            retries: 1,
+            backoff: Duration::ZERO,
+            timeout: Some(Duration::from_millis(200)),
+            auth: None,
+            cache: None,
+        };
+        let url = http_server(vec![
+            response("500 Internal Server Error", None, ""),
+            response("200 OK", None, "{}"),
+        ]);
+        assert_eq!(fetch_url_with(&url, &options).unwrap(), "{}");
+
+        let url = http_server(vec![
+            response("502 Bad Gateway", None, ""),
+            response("502 Bad Gateway", None, ""),
+        ]);
+        let err = fetch_url_with(&url, &options).unwrap_err();
+        assert!(err.to_string().contains("after 1 retries"), "{}", err);
+
+        // A server that never answers times out
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let err = fetch_url_with(&url, &options).unwrap_err();
+        assert!(err.to_string().contains("after 1 retries"), "{}", err);
+    }
+
+    #[test]
+    fn test_http_auth() {
+        let auth = HttpAuth::new()
+            .for_prefix("https://data.example.org/")
+            .with_bearer_token("secret")
+            .with_header("X-Api-Key", "key");
+        let client = reqwest::blocking::Client::new();
+        let headers = |url: &str| {
+            auth.apply(url, client.get(url))
+                .build()
+                .unwrap()
+                .headers()
+                .clone()
+        };
+
+        let sent = headers("https://data.example.org/crate/");
+        assert_eq!(sent["authorization"], "Bearer secret");
+        assert_eq!(sent["x-api-key"], "key");
+        // Other hosts, even with the same name as prefix, get nothing
+        assert!(headers("https://data.example.org.evil/").is_empty());
+        assert!(headers("https://other.example.org/").is_empty());
+        // Nor do other ports or schemes, even for a prefix without '/'
+        let origin = HttpAuth::new()
+            .for_prefix("https://data.example.org")
+            .with_bearer_token("secret");
+        assert!(origin.applies_to("https://data.example.org/crate/"));
+        assert!(!origin.applies_to("https://data.example.org.evil/"));
+        assert!(!origin.applies_to("https://data.example.org:8443/"));
+        assert!(!origin.applies_to("http://data.example.org/"));
+
+        let basic = HttpAuth::new()
+            .for_prefix("https://data.example.org/")
+            .with_basic_auth("user", Some("pass".to_string()));
+        let request = basic
+            .apply(
+                "https://data.example.org/",
+                client.get("https://data.example.org/"),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["authorization"], "Basic dXNlcjpwYXNz");
+        assert!(!format!("{:?}", auth).contains("secret"));
+    }
+
+    #[test]
+    fn test_http_auth_redirects() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::sync::mpsc;
+
+        // Serves `responses`, sending the headers of each request
+        let server = |responses: Vec<String>| {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let base = format!("http://{}/", listener.local_addr().unwrap());
+            let (sender, requests) = mpsc::channel();
+            std::thread::spawn(move || {
+                for response in responses {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let (mut line, mut head) = (String::new(), String::new());
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        head.push_str(&line.to_ascii_lowercase());
+                        line.clear();
+                    }
+                    sender.send(head).unwrap();
+                    reader.into_inner().write_all(response.as_bytes()).unwrap();
+                }
+            });
+            (base, requests)
+        };
+        let redirect = |location: &str| {
+            format!(
+                "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                location
+            )
+        };
+
+        let (other, other_requests) = server(vec![response("200 OK", None, "{}")]);
+        let (base, requests) = server(vec![
+            redirect("/moved/"),
+            redirect(&format!("{}crate/", other)),
+        ]);
+        let options = UrlLoaderOptions {
+            auth: Some(Arc::new(
+                HttpAuth::new()
+                    .for_prefix(base.as_str())
+                    .with_bearer_token("secret")
+                    .with_header("X-Api-Key", "key"),
+            )),
+            ..UrlLoaderOptions::DEFAULT
+        };
+        assert_eq!(fetch_url_with(&base, &options).unwrap(), "{}");
+
+        // Redirects within the origin keep the credentials, others lose them
+        for _ in 0..2 {
+            let head = requests.recv().unwrap();
+            assert!(head.contains("authorization: bearer secret"), "{}", head);
+            assert!(head.contains("x-api-key: key"), "{}", head);
+        }
+        let head = other_requests.recv().unwrap();
+        assert!(head.starts_with("get /crate/ "), "{}", head);
+        assert!(!head.contains("authorization"), "{}", head);
+        assert!(!head.contains("x-api-key"), "{}", head);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
 }
@@ -0,0 +1,397 @@
+//! Download manifest generation
+//!
+//! Lists every payload file of a consolidated crate together with the
+//! location its bytes can be fetched from, so data-transfer tooling can
+//! stage the files that belong to the consolidated metadata.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::collect::{extract_id, has_type};
+use crate::consolidate::{ConsolidateResult, EntityOrigin};
+use crate::id::{classify_id, IdKind};
+use crate::path::{join_id, path_to_string};
+
+/// Checksum properties checked on payload entities, in order of preference
+const CHECKSUM_PROPERTIES: &[&str] = &["sha512", "sha256", "sha1", "md5"];
+
+/// Location of a crate or of a file within it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SourceLocation {
+    /// A path on the local filesystem
+    LocalPath {
+        #[serde(serialize_with = "serialize_path")]
+        path: PathBuf,
+    },
+    /// A member of a zip archive
+    ZipMember {
+        #[serde(serialize_with = "serialize_path")]
+        archive: PathBuf,
+        member: String,
+    },
+    /// A member of a tar archive (optionally gzipped)
+    TarMember {
+        #[serde(serialize_with = "serialize_path")]
+        archive: PathBuf,
+        member: String,
+    },
+    /// A remote URL
+    Url { url: String },
+}
+
+/// Serialize paths that are not valid UTF-8 with their odd bytes
+/// percent-encoded instead of failing
+fn serialize_path<S: serde::Serializer>(
+    path: &impl AsRef<Path>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path_to_string(path.as_ref()))
+}
+
+impl SourceLocation {
+    /// Resolve a crate-relative path against this location
+    ///
+    /// Returns `None` for a local path that would not stay below this one.
+    pub fn join(&self, relative: &str) -> Option<SourceLocation> {
+        let relative = relative.trim_start_matches("./");
+        if relative.is_empty() {
+            return Some(self.clone());
+        }
+        let joined = match self {
+            SourceLocation::LocalPath { path } => SourceLocation::LocalPath {
+                path: join_id(path, relative).ok()?,
+            },
+            SourceLocation::ZipMember { archive, member } => SourceLocation::ZipMember {
+                archive: archive.clone(),
+                member: join_member(member, relative),
+            },
+            SourceLocation::TarMember { archive, member } => SourceLocation::TarMember {
+                archive: archive.clone(),
+                member: join_member(member, relative),
+            },
+            SourceLocation::Url { url } => SourceLocation::Url {
+                url: format!("{}/{}", url.trim_end_matches('/'), relative),
+            },
+        };
+        Some(joined)
+    }
+
+    /// Short name of the location kind
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SourceLocation::LocalPath { .. } => "local_path",
+            SourceLocation::ZipMember { .. } => "zip_member",
+            SourceLocation::TarMember { .. } => "tar_member",
+            SourceLocation::Url { .. } => "url",
+        }
+    }
+}
+
+/// Join a relative path onto an archive member path
+fn join_member(member: &str, relative: &str) -> String {
+    if member.is_empty() {
+        relative.to_string()
+    } else {
+        format!("{}/{}", member.trim_end_matches('/'), relative)
+    }
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceLocation::LocalPath { path } => write!(f, "{}", path.display()),
+            SourceLocation::ZipMember { archive, member }
+            | SourceLocation::TarMember { archive, member } => {
+                write!(f, "{}!{}", archive.display(), member)
+            }
+            SourceLocation::Url { url } => write!(f, "{}", url),
+        }
+    }
+}
+
+/// A payload file of the consolidated crate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestEntry {
+    /// The file's @id in the consolidated graph
+    pub id: String,
+    /// Where the file's bytes can be found (if resolvable)
+    pub source: Option<SourceLocation>,
+    /// Size in bytes (if known)
+    pub size: Option<u64>,
+    /// Checksum as `<algorithm>:<value>` (if known)
+    pub checksum: Option<String>,
+}
+
+/// Build a download manifest for all payload files in a consolidation result
+///
+/// `bases` maps crate namespaces to the location of that crate ("" is the
+/// root crate). Nested namespaces without their own entry are resolved
+/// below their closest ancestor.
+pub fn build_manifest(
+    result: &ConsolidateResult,
+    bases: &HashMap<String, SourceLocation>,
+) -> Vec<ManifestEntry> {
+    let origins: HashMap<&str, &EntityOrigin> =
+        result.origins.iter().map(|o| (o.id.as_str(), o)).collect();
+
+    result
+        .graph
+        .iter()
+        .filter(|e| is_payload(e))
+        .filter_map(|entity| {
+            let id = extract_id(entity)?;
+            let source = resolve_source(entity, id, origins.get(id).copied(), bases);
+            let size = content_size(entity).or_else(|| match &source {
+                Some(SourceLocation::LocalPath { path }) => {
+                    std::fs::metadata(path).ok().map(|m| m.len())
+                }
+                _ => None,
+            });
+            Some(ManifestEntry {
+                id: id.to_string(),
+                source,
+                size,
+                checksum: checksum(entity),
+            })
+        })
+        .collect()
+}
+
+/// Render a manifest as CSV with a header row
+pub fn manifest_to_csv(entries: &[ManifestEntry]) -> String {
+    let mut out = String::from("id,source_kind,source,size,checksum\n");
+    for entry in entries {
+        let fields = [
+            entry.id.clone(),
+            entry
+                .source
+                .as_ref()
+                .map(|s| s.kind())
+                .unwrap_or("")
+                .to_string(),
+            entry
+                .source
+                .as_ref()
+                .map(|s| s.to_string())
+                .unwrap_or_default(),
+            entry.size.map(|s| s.to_string()).unwrap_or_default(),
+            entry.checksum.clone().unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|f| csv_escape(f)).collect();
+        out.push_str(&row.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// Check if an entity describes a payload file
+fn is_payload(entity: &Value) -> bool {
+    has_type(entity, "File") || has_type(entity, "MediaObject")
+}
+
+/// Resolve where a payload file's bytes live
+fn resolve_source(
+    entity: &Value,
+    id: &str,
+    origin: Option<&EntityOrigin>,
+    bases: &HashMap<String, SourceLocation>,
+) -> Option<SourceLocation> {
+    // An absolute contentUrl always wins
+    if let Some(url) = entity.get("contentUrl").and_then(first_pointer) {
+        if classify_id(url) == IdKind::Absolute {
+            return Some(SourceLocation::Url {
+                url: url.to_string(),
+            });
+        }
+    }
+
+    if classify_id(id) == IdKind::Absolute {
+        return Some(SourceLocation::Url {
+            url: id.to_string(),
+        });
+    }
+
+    let origin = origin?;
+    if classify_id(&origin.original_id) != IdKind::Relative {
+        return None;
+    }
+    resolve_in_namespace(bases, &origin.namespace, &origin.original_id)
+}
+
+/// Resolve a crate-relative id against the closest known base of a namespace
+///
+/// An empty `relative_id` resolves to the location of the crate itself.
+pub(crate) fn resolve_in_namespace(
+    bases: &HashMap<String, SourceLocation>,
+    namespace: &str,
+    relative_id: &str,
+) -> Option<SourceLocation> {
+    let relative_id = relative_id.trim_start_matches("./");
+    let mut namespace = namespace;
+    let mut below = String::new();
+
+    loop {
+        if let Some(base) = bases.get(namespace) {
+            let path = match (below.is_empty(), relative_id.is_empty()) {
+                (true, _) => relative_id.to_string(),
+                (false, true) => below,
+                (false, false) => format!("{}/{}", below, relative_id),
+            };
+            return base.join(&path);
+        }
+        if namespace.is_empty() {
+            return None;
+        }
+        let (parent, last) = namespace.rsplit_once('/').unwrap_or(("", namespace));
+        below = if below.is_empty() {
+            last.to_string()
+        } else {
+            format!("{}/{}", last, below)
+        };
+        namespace = parent;
+    }
+}
+
+fn first_pointer(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(s) => Some(s),
+        Value::Object(_) => extract_id(value),
+        Value::Array(arr) => arr.iter().find_map(first_pointer),
+        _ => None,
+    }
+}
+
+fn content_size(entity: &Value) -> Option<u64> {
+    match entity.get("contentSize")? {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn checksum(entity: &Value) -> Option<String> {
+    CHECKSUM_PROPERTIES.iter().find_map(|alg| {
+        entity
+            .get(*alg)
+            .and_then(|v| v.as_str())
+            .map(|v| format!("{}:{}", alg, v))
+    })
+}
+
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn result_with(graph: Vec<Value>, origins: Vec<EntityOrigin>) -> ConsolidateResult {
+        ConsolidateResult {
+            graph,
+            origins,
+            ..ConsolidateResult::default()
+        }
+    }
+
+    fn origin(id: &str, original_id: &str, namespace: &str) -> EntityOrigin {
+        EntityOrigin {
+            id: id.to_string(),
+            original_id: original_id.to_string(),
+            namespace: namespace.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_build_manifest() {
+        let result = result_with(
+            vec![
+                json!({"@id": "./data.csv", "@type": "File", "contentSize": "42"}),
+                json!({
+                    "@id": "./experiments/raw/run.txt",
+                    "@type": "File",
+                    "sha256": "abc"
+                }),
+                json!({
+                    "@id": "#remote",
+                    "@type": "File",
+                    "contentUrl": "https://example.org/remote.bin"
+                }),
+                json!({"@id": "https://orcid.org/0000-0001", "@type": "Person"}),
+            ],
+            vec![
+                origin("./data.csv", "./data.csv", ""),
+                origin("./experiments/raw/run.txt", "./run.txt", "experiments/raw"),
+                origin("#remote", "#remote", ""),
+            ],
+        );
+
+        let mut bases = HashMap::new();
+        bases.insert(
+            String::new(),
+            SourceLocation::Url {
+                url: "https://example.org/crate/".to_string(),
+            },
+        );
+        bases.insert(
+            "experiments".to_string(),
+            SourceLocation::ZipMember {
+                archive: PathBuf::from("/tmp/experiments.zip"),
+                member: String::new(),
+            },
+        );
+
+        let manifest = build_manifest(&result, &bases);
+        assert_eq!(manifest.len(), 3);
+
+        assert_eq!(
+            manifest[0].source,
+            Some(SourceLocation::Url {
+                url: "https://example.org/crate/data.csv".to_string()
+            })
+        );
+        assert_eq!(manifest[0].size, Some(42));
+
+        assert_eq!(
+            manifest[1].source,
+            Some(SourceLocation::ZipMember {
+                archive: PathBuf::from("/tmp/experiments.zip"),
+                member: "raw/run.txt".to_string()
+            })
+        );
+        assert_eq!(manifest[1].checksum.as_deref(), Some("sha256:abc"));
+
+        assert_eq!(
+            manifest[2].source,
+            Some(SourceLocation::Url {
+                url: "https://example.org/remote.bin".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_manifest_to_csv() {
+        let entries = vec![ManifestEntry {
+            id: "./a,b.csv".to_string(),
+            source: Some(SourceLocation::LocalPath {
+                path: PathBuf::from("/data/a,b.csv"),
+            }),
+            size: Some(7),
+            checksum: None,
+        }];
+
+        let csv = manifest_to_csv(&entries);
+        assert_eq!(
+            csv,
+            "id,source_kind,source,size,checksum\n\"./a,b.csv\",local_path,\"/data/a,b.csv\",7,\n"
+        );
+    }
+}
@@ -0,0 +1,641 @@
+//! Read-only memory-mapped loading of huge local metadata
+//!
+//! Reading a multi-gigabyte metadata file into a `String` and parsing it
+//! into a `Value` document holds it in memory about three times over. A
+//! [`MappedFile`] maps the file instead, and [`parse_raw_graph`] splits its
+//! `@graph` into entities borrowed from the mapping, which
+//! [`consolidate_mapped`] parses one at a time.
+//!
+//! With [`ConsolidateOptions::raw_passthrough`], it materializes only the
+//! entities consolidation may change. The root crate's ids are kept, so
+//! entities whose ids and references are absolute or plain relative paths
+//! (no fragments, subcrates or descriptors) come out of consolidation as
+//! they went in. They are written verbatim after the consolidated ones,
+//! which for file-heavy crates skips most of the parsing and serializing.
+//! Options that touch every entity (post-processors, shape normalization,
+//! pointer collection, flattening) turn passthrough off, and a base IRI
+//! limits it to absolute ids. An entity a subcrate has as well is parsed
+//! and merged as usual.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::Mmap;
+use serde::de::{self, DeserializeSeed, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+use crate::audit::{self, AccessKind};
+use crate::collect::extract_id;
+use crate::consolidate::{
+    consolidate, ConsolidateInput, ConsolidateOptions, ConsolidateResult, EntityOrigin,
+    SubcrateLoader,
+};
+use crate::error::ConsolidateError;
+use crate::id::{classify_id, is_root_alias, IdKind};
+use crate::merge::union_merge_entities;
+use crate::output::{write_document, write_styled, KeyOrder, OutputStyle, ShapePolicy};
+use crate::vocab::METADATA_DESCRIPTOR_ID;
+
+/// A file mapped read-only into memory
+///
+/// The file must not be truncated while it is mapped: reading the pages
+/// beyond its new end crashes the process.
+pub struct MappedFile {
+    map: Mmap,
+}
+
+impl MappedFile {
+    /// Map the file at `path`, recording the access
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only; that the file isn't changed
+        // while mapped is up to the caller, as documented above
+        let map = unsafe { Mmap::map(&file)? };
+        let mapped = Self { map };
+        audit::record(AccessKind::File, &path.display().to_string(), None, &mapped);
+        Ok(mapped)
+    }
+
+    /// The content as UTF-8
+    pub fn as_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(self)
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedFile")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
+
+/// A crate's @graph with its entities borrowed from the metadata document
+#[derive(Debug)]
+pub struct RawGraph<'a> {
+    entities: Vec<&'a RawValue>,
+}
+
+impl<'a> RawGraph<'a> {
+    /// The entities in document order
+    pub fn entities(&self) -> &[&'a RawValue] {
+        &self.entities
+    }
+
+    /// Number of entities
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Check if the graph has no entities
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// Parse the @graph of a metadata document without materializing its
+/// entities, like [`parse_graph`](crate::consolidate::parse_graph)
+pub fn parse_raw_graph<'a>(
+    content: &'a str,
+    source: &str,
+) -> Result<RawGraph<'a>, ConsolidateError> {
+    #[derive(Deserialize)]
+    struct Document<'a> {
+        #[serde(rename = "@graph", borrow)]
+        graph: Option<&'a RawValue>,
+    }
+
+    let document: Document = serde_json::from_str(content)?;
+    let graph = document.graph.ok_or_else(|| {
+        ConsolidateError::InvalidStructure(format!("No @graph found in {}", source))
+    })?;
+    let entities = serde_json::from_str(graph.get())
+        .map_err(|_| ConsolidateError::InvalidStructure("@graph is not an array".to_string()))?;
+    Ok(RawGraph { entities })
+}
+
+/// Result of [`consolidate_mapped`]
+#[derive(Debug)]
+pub struct MappedConsolidation<'a> {
+    /// The consolidation of all entities that weren't passed through
+    pub result: ConsolidateResult,
+    /// Entities written verbatim after those of the result
+    pub passthrough: Vec<&'a RawValue>,
+}
+
+impl MappedConsolidation<'_> {
+    /// Serialize as a JSON-LD document in the given style
+    ///
+    /// Passed-through entities are written verbatim, unless the style
+    /// orders keys or entities or writes them as chunks, which parses them.
+    pub fn to_json_string_styled(&self, style: &OutputStyle) -> Result<String, ConsolidateError> {
+        if style.key_order == KeyOrder::AsIs && !style.sort_entities && !style.entity_chunks {
+            let document = MappedDocument {
+                context: &self.result.context,
+                graph: MappedGraph {
+                    graph: &self.result.graph,
+                    passthrough: &self.passthrough,
+                },
+            };
+            return write_styled(&document, style);
+        }
+        let parsed = self
+            .passthrough
+            .iter()
+            .map(|raw| serde_json::from_str(raw.get()))
+            .collect::<Result<Vec<Value>, _>>()?;
+        let graph = self.result.graph.iter().chain(&parsed).collect();
+        write_document(&self.result.context, graph, style)
+    }
+}
+
+#[derive(Serialize)]
+struct MappedDocument<'r, 'a> {
+    #[serde(rename = "@context")]
+    context: &'r Value,
+    #[serde(rename = "@graph")]
+    graph: MappedGraph<'r, 'a>,
+}
+
+struct MappedGraph<'r, 'a> {
+    graph: &'r [Value],
+    passthrough: &'r [&'a RawValue],
+}
+
+impl Serialize for MappedGraph<'_, '_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+
+        let mut seq = serializer.serialize_seq(Some(self.graph.len() + self.passthrough.len()))?;
+        for entity in self.graph {
+            seq.serialize_element(entity)?;
+        }
+        for entity in self.passthrough {
+            seq.serialize_element(entity)?;
+        }
+        seq.end()
+    }
+}
+
+/// Consolidate the crate with the borrowed `graph`, passing the entities
+/// consolidation wouldn't change through if the options ask for it
+pub fn consolidate_mapped<'a>(
+    graph: RawGraph<'a>,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+) -> Result<MappedConsolidation<'a>, ConsolidateError> {
+    let (owned, passthrough) = split_graph(graph, loader, options)?;
+    let mut result = consolidate(ConsolidateInput::Single(owned), loader, options)?;
+
+    // Entities subcrates have as well are merged as usual
+    let ids: HashSet<&str> = passthrough.iter().map(|(id, _)| id.as_str()).collect();
+    let shared: HashMap<String, usize> = result
+        .graph
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entity)| {
+            extract_id(entity)
+                .filter(|id| ids.contains(id))
+                .map(|id| (id.to_string(), index))
+        })
+        .collect();
+    let mut verbatim = Vec::with_capacity(passthrough.len());
+    for (id, raw) in passthrough {
+        match shared.get(&id) {
+            Some(&index) => {
+                let entity: Value = serde_json::from_str(raw.get())?;
+                if entity == result.graph[index] {
+                    result.stats.identical_entities += 1;
+                } else {
+                    result.graph[index] = union_merge_entities(&entity, &result.graph[index]);
+                }
+                result.stats.merged_entities += 1;
+            }
+            None => verbatim.push(raw),
+        }
+        result.origins.push(EntityOrigin {
+            id: id.clone(),
+            original_id: id,
+            namespace: String::new(),
+        });
+    }
+    result.stats.total_entities += verbatim.len();
+
+    Ok(MappedConsolidation {
+        result,
+        passthrough: verbatim,
+    })
+}
+
+/// Split `graph` into the entities to consolidate and those to pass
+/// through, with their ids
+#[allow(clippy::type_complexity)]
+fn split_graph<'a>(
+    graph: RawGraph<'a>,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+) -> Result<(Vec<Value>, Vec<(String, &'a RawValue)>), ConsolidateError> {
+    let enabled = options.raw_passthrough
+        && options.post_processors.is_empty()
+        && !options.flatten_only
+        && options.shape == ShapePolicy::AsIs
+        && !options.collect_distributions
+        && !options.require_absolute_pointers;
+    if !enabled {
+        let owned = graph
+            .entities
+            .iter()
+            .map(|raw| serde_json::from_str(raw.get()))
+            .collect::<Result<_, _>>()?;
+        return Ok((owned, Vec::new()));
+    }
+
+    // Relative ids are kept unless a base IRI resolves them
+    let relative = options.base_id.is_none();
+    let mut scanned = Vec::with_capacity(graph.len());
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    // Subcrates, whose folders and namespaces consolidation may rename
+    let mut subcrates: HashSet<String> = loader
+        .listed_subcrates("")
+        .iter()
+        .map(|id| folder_key(id).to_string())
+        .collect();
+    for raw in graph.entities {
+        let scan = scan_entity(raw.get(), relative)?;
+        if let Some(id) = &scan.id {
+            *counts.entry(id.clone()).or_default() += 1;
+            if scan.conforms_to {
+                subcrates.insert(folder_key(id).to_string());
+            }
+        }
+        scanned.push((raw, scan));
+    }
+
+    let mut owned = Vec::new();
+    let mut passthrough = Vec::new();
+    for (raw, scan) in scanned {
+        let unchanged = scan.plain_ids
+            && !scan.conforms_to
+            && (scan.ascii_ids || !options.normalize_unicode)
+            && scan
+                .relative_ids
+                .iter()
+                .all(|id| !subcrates.contains(folder_key(id)));
+        match scan.id {
+            // Entities appearing twice are merged within the crate
+            Some(id) if unchanged && counts[&id] == 1 => passthrough.push((id, raw)),
+            _ => owned.push(serde_json::from_str(raw.get())?),
+        }
+    }
+    Ok((owned, passthrough))
+}
+
+/// A relative id without leading "./" and trailing '/', so a folder's
+/// spellings compare equal
+fn folder_key(id: &str) -> &str {
+    id.strip_prefix("./").unwrap_or(id).trim_end_matches('/')
+}
+
+/// What passthrough needs to know about an entity
+#[derive(Debug)]
+struct EntityScan {
+    /// The entity's own @id
+    id: Option<String>,
+    /// Whether every @id in it, its own included, is a string consolidation
+    /// keeps: absolute, or a relative path if those are kept
+    plain_ids: bool,
+    /// Its relative @ids, which mustn't be subcrates
+    relative_ids: Vec<String>,
+    /// Whether every @id in it is ASCII, which Unicode normalization keeps
+    ascii_ids: bool,
+    /// Whether it has `conformsTo`, as subcrate references do
+    conforms_to: bool,
+    /// Whether relative paths are kept
+    relative: bool,
+}
+
+impl EntityScan {
+    fn note_id(&mut self, id: &str) {
+        self.ascii_ids &= id.is_ascii();
+        match classify_id(id) {
+            IdKind::Absolute => {}
+            // Subcrate descriptors inside folders classify as relative
+            IdKind::Relative
+                if self.relative && !is_root_alias(id) && !id.ends_with(METADATA_DESCRIPTOR_ID) =>
+            {
+                self.relative_ids.push(id.to_string());
+            }
+            _ => self.plain_ids = false,
+        }
+    }
+}
+
+/// Scan the entity in `json` without materializing it
+fn scan_entity(json: &str, relative: bool) -> Result<EntityScan, ConsolidateError> {
+    let mut scan = EntityScan {
+        id: None,
+        plain_ids: true,
+        relative_ids: Vec::new(),
+        ascii_ids: true,
+        conforms_to: false,
+        relative,
+    };
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    Scanner {
+        scan: &mut scan,
+        top_level: true,
+    }
+    .deserialize(&mut deserializer)?;
+    if scan.id.is_none() {
+        scan.plain_ids = false;
+    }
+    Ok(scan)
+}
+
+/// Walks a JSON value, noting its @ids in an [`EntityScan`]
+struct Scanner<'s> {
+    scan: &'s mut EntityScan,
+    top_level: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for Scanner<'_> {
+    type Value = ();
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Scanner<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq
+            .next_element_seed(Scanner {
+                scan: &mut *self.scan,
+                top_level: false,
+            })?
+            .is_some()
+        {}
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key_seed(KeySeed)? {
+            match key {
+                Key::Id => match map.next_value::<Value>()? {
+                    Value::String(id) => {
+                        self.scan.note_id(&id);
+                        if self.top_level {
+                            self.scan.id = Some(id);
+                        }
+                    }
+                    _ => self.scan.plain_ids = false,
+                },
+                Key::ConformsTo | Key::Other => {
+                    self.scan.conforms_to |= self.top_level && key == Key::ConformsTo;
+                    map.next_value_seed(Scanner {
+                        scan: &mut *self.scan,
+                        top_level: false,
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Object keys the scan tells apart
+#[derive(PartialEq, Eq)]
+enum Key {
+    Id,
+    ConformsTo,
+    Other,
+}
+
+/// Reads an object key without allocating it
+struct KeySeed;
+
+impl<'de> DeserializeSeed<'de> for KeySeed {
+    type Value = Key;
+
+    fn deserialize<D: de::Deserializer<'de>>(self, deserializer: D) -> Result<Key, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+}
+
+impl Visitor<'_> for KeySeed {
+    type Value = Key;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an object key")
+    }
+
+    fn visit_str<E>(self, key: &str) -> Result<Key, E> {
+        Ok(match key {
+            "@id" => Key::Id,
+            "conformsTo" => Key::ConformsTo,
+            _ => Key::Other,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::NoOpLoader;
+    use serde_json::json;
+
+    fn document() -> String {
+        json!({
+            "@context": "https://w3id.org/ro/crate/1.1/context",
+            "@graph": [
+                {"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+                {
+                    "@id": "./",
+                    "@type": "Dataset",
+                    "hasPart": [{"@id": "https://data.example.org/a.csv"}, {"@id": "b.csv"}],
+                    "author": {"@id": "https://orcid.org/0000-0002-1825-0097"}
+                },
+                {
+                    "@id": "https://data.example.org/a.csv",
+                    "@type": "File",
+                    "license": {"@id": "https://spdx.org/licenses/MIT"}
+                },
+                {"@id": "b.csv", "@type": "File"},
+                {
+                    "@id": "https://orcid.org/0000-0002-1825-0097",
+                    "@type": "Person",
+                    "affiliation": {"@id": "#lab"}
+                },
+                {"@id": "#lab", "@type": "Organization"}
+            ]
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_mapped_file() {
+        let path = std::env::temp_dir().join(format!("mapped-{}.json", std::process::id()));
+        std::fs::write(&path, document()).unwrap();
+        let file = MappedFile::open(&path).unwrap();
+        assert_eq!(file.as_str().unwrap(), document());
+        let graph = parse_raw_graph(file.as_str().unwrap(), "test").unwrap();
+        assert_eq!(graph.len(), 6);
+        drop(file);
+
+        std::fs::write(&path, "").unwrap();
+        assert!(MappedFile::open(&path).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_consolidate_mapped() {
+        let content = document();
+        let graph = parse_raw_graph(&content, "test").unwrap();
+        let options = ConsolidateOptions {
+            raw_passthrough: true,
+            ..Default::default()
+        };
+        let mapped = consolidate_mapped(graph, &NoOpLoader, &options).unwrap();
+
+        // Only the files are passed through; the person references a local
+        // organization
+        let passed: Vec<&str> = mapped.passthrough.iter().map(|raw| raw.get()).collect();
+        assert_eq!(passed.len(), 2);
+        assert!(passed[0].contains("https://data.example.org/a.csv"));
+        assert!(passed[1].contains("b.csv"));
+
+        // The document holds the same entities as a full consolidation
+        let full = consolidate(
+            ConsolidateInput::Single(crate::consolidate::parse_graph(&content, "test").unwrap()),
+            &NoOpLoader,
+            &options,
+        )
+        .unwrap();
+        let written: Value = serde_json::from_str(
+            &mapped
+                .to_json_string_styled(&OutputStyle::default())
+                .unwrap(),
+        )
+        .unwrap();
+        let mut ids: Vec<&str> = written["@graph"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(extract_id)
+            .collect();
+        let mut expected: Vec<&str> = full.graph.iter().filter_map(extract_id).collect();
+        ids.sort();
+        expected.sort();
+        assert_eq!(ids, expected);
+        assert_eq!(
+            mapped.result.stats.total_entities,
+            full.stats.total_entities
+        );
+
+        // Sorted output parses the passed-through entities
+        let sorted = mapped
+            .to_json_string_styled(&OutputStyle::vcs_friendly())
+            .unwrap();
+        assert!(sorted.contains("\"@id\": \"https://data.example.org/a.csv\""));
+    }
+
+    #[test]
+    fn test_passthrough_disabled_by_shape() {
+        let content = document();
+        let options = ConsolidateOptions {
+            raw_passthrough: true,
+            shape: ShapePolicy::AlwaysArray,
+            ..Default::default()
+        };
+        let graph = parse_raw_graph(&content, "test").unwrap();
+        let mapped = consolidate_mapped(graph, &NoOpLoader, &options).unwrap();
+        assert!(mapped.passthrough.is_empty());
+
+        // Nor is anything passed through unless asked for
+        let graph = parse_raw_graph(&content, "test").unwrap();
+        let options = ConsolidateOptions::default();
+        let mapped = consolidate_mapped(graph, &NoOpLoader, &options).unwrap();
+        assert!(mapped.passthrough.is_empty());
+    }
+
+    #[test]
+    fn test_passthrough_skips_subcrate_references() {
+        let content = json!({
+            "@graph": [
+                {"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+                {"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "sub/"}]},
+                {
+                    "@id": "./sub/",
+                    "@type": "Dataset",
+                    "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+                },
+                {"@id": "notes.txt", "@type": "File", "about": {"@id": "sub"}},
+                {"@id": "data/", "@type": "Dataset", "hasPart": {"@id": "data/a.csv"}},
+                {"@id": "data/a.csv", "@type": "File"}
+            ]
+        })
+        .to_string();
+        let options = ConsolidateOptions {
+            raw_passthrough: true,
+            ..Default::default()
+        };
+        let graph = parse_raw_graph(&content, "test").unwrap();
+        let (owned, passthrough) = split_graph(graph, &NoOpLoader, &options).unwrap();
+        let passed: Vec<&str> = passthrough.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(passed, ["data/", "data/a.csv"]);
+        assert_eq!(owned.len(), 4);
+
+        // A base IRI rewrites relative ids
+        let options = ConsolidateOptions {
+            base_id: Some("https://example.org/crate/".to_string()),
+            ..options
+        };
+        let graph = parse_raw_graph(&content, "test").unwrap();
+        let (_, passthrough) = split_graph(graph, &NoOpLoader, &options).unwrap();
+        assert!(passthrough.is_empty());
+    }
+}
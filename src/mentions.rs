@@ -0,0 +1,385 @@
+//! An entry point into large consolidated crates
+//!
+//! Consolidating many subcrates yields a flat graph in which the few
+//! entities that matter, such as workflows, instruments and people, are
+//! hard to find. [`MentionKeyEntities`] lets the root `mentions` them, or
+//! adds an index entity that does and that the root mentions, so consumers
+//! of the flat crate have a curated place to start.
+//!
+//! What counts as key differs between communities, so which entities are
+//! mentioned, and in what order, is configured by a [`KeyEntityRanking`]:
+//! entities score by their types and by how often other entities reference
+//! them, and ids can be pinned or left out explicitly.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::collect::{extract_id, extract_types, get_referenced_ids, is_metadata_descriptor};
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+use crate::postprocess::PostProcessor;
+use crate::vocab::ROOT_ENTITY_ID;
+
+/// Types of the entities mentioned by default
+pub const DEFAULT_KEY_TYPES: [&str; 4] = [
+    "ComputationalWorkflow",
+    "Instrument",
+    "IndividualProduct",
+    "Person",
+];
+
+/// Which entities are key entities, and how they're ranked
+///
+/// An entity's score is the sum of the weights of its types plus
+/// `in_degree` times the number of entities referencing it. Entities
+/// scoring above `min_score` are key entities, highest score first (ties in
+/// graph order), at most `limit` of them. The `include`d ids come first,
+/// whatever their score, and `exclude`d ids are never key entities.
+///
+/// Rankings can be read from JSON documents such as `{"types":
+/// {"ComputationalWorkflow": 10, "Person": 1}, "in_degree": 0.5, "limit":
+/// 20}`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyEntityRanking {
+    /// Weight of each type
+    pub types: HashMap<String, f64>,
+    /// Weight of each entity referencing an entity
+    pub in_degree: f64,
+    /// Ids of entities that are always key entities, in this order
+    pub include: Vec<String>,
+    /// Ids of entities that are never key entities
+    pub exclude: Vec<String>,
+    /// Score an entity must exceed to be a key entity
+    pub min_score: f64,
+    /// Most key entities selected by score (the included ones not counted)
+    pub limit: Option<usize>,
+}
+
+impl KeyEntityRanking {
+    /// Select the entities of any of `types`, in graph order
+    pub fn by_types(types: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            types: types.into_iter().map(|t| (t.into(), 1.0)).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Read a ranking from a JSON document
+    pub fn from_json(json: &str) -> Result<Self, ConsolidateError> {
+        serde_json::from_str(json).map_err(|e| {
+            ConsolidateError::InvalidStructure(format!("Invalid key entity ranking: {}", e))
+        })
+    }
+
+    /// Read a ranking from the JSON document at `path`
+    pub fn from_file(path: &Path) -> Result<Self, ConsolidateError> {
+        let content = fs::read_to_string(path).map_err(|e| ConsolidateError::LoadError {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+            source: Some(e.into()),
+        })?;
+        Self::from_json(&content)
+    }
+
+    /// The ids of the key entities of `graph`, best first, leaving out
+    /// `root_id` and the metadata descriptor
+    pub fn select(&self, graph: &[Value], root_id: &str) -> Vec<String> {
+        let mut in_degrees: HashMap<String, usize> = HashMap::new();
+        if self.in_degree != 0.0 {
+            for entity in graph {
+                let own_id = extract_id(entity);
+                for id in get_referenced_ids(entity) {
+                    if Some(id.as_str()) != own_id {
+                        *in_degrees.entry(id).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        let present: HashSet<&str> = graph.iter().filter_map(extract_id).collect();
+        let mut selected: Vec<String> = self
+            .include
+            .iter()
+            .filter(|id| present.contains(id.as_str()) && !self.exclude.contains(id))
+            .cloned()
+            .collect();
+        let mut scored: Vec<(f64, &str)> = Vec::new();
+        for entity in graph {
+            let Some(id) = extract_id(entity) else {
+                continue;
+            };
+            if id == root_id
+                || is_metadata_descriptor(entity)
+                || self.exclude.iter().any(|e| e == id)
+                || selected.iter().any(|s| s == id)
+                || scored.iter().any(|(_, s)| *s == id)
+            {
+                continue;
+            }
+            let type_score: f64 = extract_types(entity)
+                .iter()
+                .filter_map(|t| self.types.get(t))
+                .sum();
+            let degree = in_degrees.get(id).copied().unwrap_or_default();
+            let score = type_score + self.in_degree * degree as f64;
+            if score > self.min_score {
+                scored.push((score, id));
+            }
+        }
+        // Stable, so ties stay in graph order
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(self.limit.unwrap_or(usize::MAX));
+        selected.extend(scored.into_iter().map(|(_, id)| id.to_string()));
+        selected
+    }
+}
+
+/// Lets the root, or an index entity, mention the key entities of all crates
+///
+/// Key entities are chosen and ordered by a [`KeyEntityRanking`], by default
+/// those of one of the [`DEFAULT_KEY_TYPES`] in graph order. Mentions the
+/// root already has are kept.
+#[derive(Debug, Clone)]
+pub struct MentionKeyEntities {
+    ranking: KeyEntityRanking,
+    index_id: Option<String>,
+}
+
+impl MentionKeyEntities {
+    /// Mention the entities of the [`DEFAULT_KEY_TYPES`] on the root
+    pub fn new() -> Self {
+        Self {
+            ranking: KeyEntityRanking::by_types(DEFAULT_KEY_TYPES),
+            index_id: None,
+        }
+    }
+
+    /// Select the entities of any of `types` instead
+    pub fn with_types(self, types: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.with_ranking(KeyEntityRanking::by_types(types))
+    }
+
+    /// Select and order the key entities by `ranking` instead
+    pub fn with_ranking(mut self, ranking: KeyEntityRanking) -> Self {
+        self.ranking = ranking;
+        self
+    }
+
+    /// Mention the key entities on an index entity with this @id (e.g.
+    /// "#key-entities"), which the root mentions, instead of on the root
+    pub fn with_index_entity(mut self, id: impl Into<String>) -> Self {
+        self.index_id = Some(id.into());
+        self
+    }
+}
+
+impl Default for MentionKeyEntities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcessor for MentionKeyEntities {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        let root_id = result
+            .origins
+            .iter()
+            .find(|o| o.namespace.is_empty() && o.original_id == ROOT_ENTITY_ID)
+            .map_or(ROOT_ENTITY_ID, |o| o.id.as_str())
+            .to_string();
+        let Some(root_index) = result
+            .graph
+            .iter()
+            .position(|e| extract_id(e) == Some(root_id.as_str()))
+        else {
+            return Ok(());
+        };
+
+        let keys: Vec<Value> = self
+            .ranking
+            .select(&result.graph, &root_id)
+            .into_iter()
+            .map(|id| json!({ "@id": id }))
+            .collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        let mentioned = match &self.index_id {
+            Some(index_id) => {
+                if result.graph.iter().any(|e| extract_id(e) == Some(index_id)) {
+                    return Err(ConsolidateError::InvalidStructure(format!(
+                        "Index entity '{}' is already in the graph",
+                        index_id
+                    )));
+                }
+                result.graph.push(json!({
+                    "@id": index_id,
+                    "@type": "CreativeWork",
+                    "name": "Key entities",
+                    "description": "Entry point to the key entities of all consolidated crates",
+                    "mentions": keys,
+                }));
+                vec![json!({ "@id": index_id })]
+            }
+            None => keys,
+        };
+        add_mentions(&mut result.graph[root_index], mentioned);
+        Ok(())
+    }
+}
+
+/// Add references to an entity's `mentions`, keeping those it has
+fn add_mentions(entity: &mut Value, references: Vec<Value>) {
+    let mut mentions = match entity.get_mut("mentions").map(Value::take) {
+        Some(Value::Array(items)) => items,
+        Some(Value::Null) | None => Vec::new(),
+        Some(single) => vec![single],
+    };
+    for reference in references {
+        if !mentions.contains(&reference) {
+            mentions.push(reference);
+        }
+    }
+    entity["mentions"] = Value::Array(mentions);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{consolidate, ConsolidateInput, ConsolidateOptions, SubcrateLoader};
+
+    /// Serves the subcrate "./run/"
+    struct RunLoader;
+
+    impl SubcrateLoader for RunLoader {
+        fn load(
+            &self,
+            _subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            Ok(vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "mainEntity": {"@id": "wf.cwl"}}),
+                json!({
+                    "@id": "wf.cwl",
+                    "@type": ["File", "SoftwareSourceCode", "ComputationalWorkflow"]
+                }),
+                json!({"@id": "#microscope", "@type": "IndividualProduct"}),
+                json!({"@id": "https://orcid.org/0000-0002-1825-0097", "@type": "Person"}),
+            ])
+        }
+    }
+
+    fn graph() -> Vec<Value> {
+        vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "hasPart": [{"@id": "run/"}],
+                "author": {"@id": "https://orcid.org/0000-0002-1825-0097"},
+                "mentions": {"@id": "#note"}
+            }),
+            json!({
+                "@id": "run/",
+                "@type": "Dataset",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }),
+            json!({"@id": "https://orcid.org/0000-0002-1825-0097", "@type": "Person"}),
+            json!({"@id": "#note", "@type": "Comment"}),
+        ]
+    }
+
+    #[test]
+    fn test_mention_key_entities_on_root() {
+        let options = ConsolidateOptions::default().with_post_processor(MentionKeyEntities::new());
+        let result = consolidate(ConsolidateInput::Single(graph()), &RunLoader, &options).unwrap();
+        let root = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./"))
+            .unwrap();
+        assert_eq!(
+            root["mentions"],
+            json!([
+                {"@id": "#note"},
+                {"@id": "./run/wf.cwl"},
+                {"@id": "#microscope"},
+                {"@id": "https://orcid.org/0000-0002-1825-0097"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_key_entity_ranking() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "a.csv"}, {"@id": "b.csv"}]}),
+            json!({"@id": "a.csv", "@type": "File", "author": {"@id": "#alice"}}),
+            json!({"@id": "b.csv", "@type": "File", "author": [{"@id": "#alice"}, {"@id": "#bob"}]}),
+            json!({"@id": "#alice", "@type": "Person"}),
+            json!({"@id": "#bob", "@type": "Person"}),
+            json!({"@id": "#carol", "@type": "Person"}),
+            json!({"@id": "wf.cwl", "@type": ["File", "ComputationalWorkflow"]}),
+        ];
+        let ranking = KeyEntityRanking::from_json(
+            r##"{"types": {"ComputationalWorkflow": 3, "Person": 1}, "in_degree": 1,
+                "include": ["b.csv", "#missing"], "exclude": ["#bob"], "limit": 2}"##,
+        )
+        .unwrap();
+        // wf.cwl and #alice score 3, a.csv 1 and #carol 1
+        assert_eq!(
+            ranking.select(&graph, "./"),
+            vec!["b.csv", "#alice", "wf.cwl"]
+        );
+
+        let ranking = KeyEntityRanking {
+            min_score: 1.0,
+            limit: None,
+            ..ranking
+        };
+        assert_eq!(
+            ranking.select(&graph, "./"),
+            vec!["b.csv", "#alice", "wf.cwl"]
+        );
+        assert_eq!(
+            KeyEntityRanking::by_types(["Person"]).select(&graph, "./"),
+            vec!["#alice", "#bob", "#carol"]
+        );
+        let err = KeyEntityRanking::from_json(r#"{"typs": {}}"#).unwrap_err();
+        assert_eq!(err.code(), "invalid_structure");
+    }
+
+    #[test]
+    fn test_mention_key_entities_on_index() {
+        let options = ConsolidateOptions::default().with_post_processor(
+            MentionKeyEntities::new()
+                .with_types(["ComputationalWorkflow"])
+                .with_index_entity("#key-entities"),
+        );
+        let result = consolidate(ConsolidateInput::Single(graph()), &RunLoader, &options).unwrap();
+        let root = result
+            .graph
+            .iter()
+            .find(|e| extract_id(e) == Some("./"))
+            .unwrap();
+        assert_eq!(
+            root["mentions"],
+            json!([{"@id": "#note"}, {"@id": "#key-entities"}])
+        );
+        let index = result.graph.last().unwrap();
+        assert_eq!(index["mentions"], json!([{"@id": "./run/wf.cwl"}]));
+        assert_eq!(result.stats.total_entities, result.graph.len());
+
+        let options = ConsolidateOptions::default()
+            .with_post_processor(MentionKeyEntities::new().with_index_entity("#note"));
+        let err = consolidate(ConsolidateInput::Single(graph()), &RunLoader, &options).unwrap_err();
+        assert_eq!(err.code(), "invalid_structure");
+    }
+}
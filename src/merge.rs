@@ -3,103 +3,475 @@
 //! Implements the union merge strategy for combining entities with
 //! the same @id from different crates.
 
+use serde::{Deserialize, Serialize};
 use serde_json::{Map, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use crate::collect::CollectedEntity;
+use crate::datetime::{parse_iso8601, DateSpan};
+use crate::error::ConsolidateError;
+use crate::value::ValueComparison;
+
+/// Arrays up to this length are searched linearly instead of being indexed
+const LINEAR_SCAN_LIMIT: usize = 16;
 
 /// Merge two JSON values using union strategy
 ///
+/// Values are compared as JSON ([`ValueComparison::Exact`]).
+///
 /// - Equal values: keep as-is
 /// - Different scalars: convert to array with both values
 /// - Arrays: union of unique elements
 /// - Objects: recursive merge of keys
 pub fn union_merge_values(a: &Value, b: &Value) -> Value {
-    if values_equal(a, b) {
-        return a.clone();
+    union_merge_values_with(a, b, ValueComparison::Exact)
+}
+
+/// Like [`union_merge_values`], comparing values as `comparison` says
+pub fn union_merge_values_with(a: &Value, b: &Value, comparison: ValueComparison) -> Value {
+    let mut result = a.clone();
+    union_merge_into_with(&mut result, b, comparison);
+    result
+}
+
+/// Union-merge `other` into `target` in place
+///
+/// Same semantics as [`union_merge_values`], but only clones the parts of
+/// `other` that are added. Array unions index the target by value hash, so
+/// merging large arrays (e.g. `hasPart` with thousands of entries) is linear.
+pub fn union_merge_into(target: &mut Value, other: &Value) {
+    union_merge_into_with(target, other, ValueComparison::Exact)
+}
+
+/// Like [`union_merge_into`], comparing values as `comparison` says
+pub fn union_merge_into_with(target: &mut Value, other: &Value, comparison: ValueComparison) {
+    if comparison.equal(target, other) {
+        return;
     }
 
-    match (a, b) {
+    match (&mut *target, other) {
         // Both arrays: union unique elements
-        (Value::Array(arr_a), Value::Array(arr_b)) => {
-            let mut result = arr_a.clone();
-            for item in arr_b {
-                if !contains_value(&result, item) {
-                    result.push(item.clone());
-                }
-            }
-            Value::Array(result)
-        }
-        // One array, one scalar: add scalar to array if not present
-        (Value::Array(arr), other) | (other, Value::Array(arr)) => {
+        (Value::Array(arr_a), Value::Array(arr_b)) => union_extend(arr_a, arr_b, comparison),
+        // Array and scalar: add scalar to array if not present
+        (Value::Array(arr), other) => union_extend(arr, std::slice::from_ref(other), comparison),
+        // Scalar and array: array elements first, then the scalar if not present
+        (current, Value::Array(arr)) => {
+            let current = current.take();
             let mut result = arr.clone();
-            if !contains_value(&result, other) {
-                result.push(other.clone());
-            }
-            Value::Array(result)
+            union_extend(&mut result, std::slice::from_ref(&current), comparison);
+            *target = Value::Array(result);
         }
         // Both objects: recursive merge
         (Value::Object(obj_a), Value::Object(obj_b)) => {
-            let merged = merge_objects(obj_a, obj_b);
-            Value::Object(merged)
+            merge_objects_into(obj_a, obj_b, comparison)
         }
         // Different scalars: create array with both
-        _ => {
-            Value::Array(vec![a.clone(), b.clone()])
+        (current, other) => {
+            let current = current.take();
+            *target = Value::Array(vec![current, other.clone()]);
         }
     }
 }
 
-/// Merge two JSON objects, combining their keys
-fn merge_objects(a: &Map<String, Value>, b: &Map<String, Value>) -> Map<String, Value> {
-    let mut result = a.clone();
-
+/// Merge the keys of `b` into `a`
+fn merge_objects_into(
+    a: &mut Map<String, Value>,
+    b: &Map<String, Value>,
+    comparison: ValueComparison,
+) {
     for (key, value_b) in b {
-        match result.get(key) {
-            Some(value_a) => {
-                // Key exists in both: merge values
-                let merged = union_merge_values(value_a, value_b);
-                result.insert(key.clone(), merged);
-            }
+        match a.get_mut(key) {
+            // Key exists in both: merge values
+            Some(value_a) => union_merge_into_with(value_a, value_b, comparison),
+            // Key only in b: add it
             None => {
-                // Key only in b: add it
-                result.insert(key.clone(), value_b.clone());
+                a.insert(key.clone(), value_b.clone());
             }
         }
     }
+}
 
-    result
+/// Append all items not yet present to an array (set-union semantics)
+fn union_extend(target: &mut Vec<Value>, items: &[Value], comparison: ValueComparison) {
+    if target.len() + items.len() <= LINEAR_SCAN_LIMIT {
+        for item in items {
+            if !target.iter().any(|value| comparison.equal(value, item)) {
+                target.push(item.clone());
+            }
+        }
+        return;
+    }
+
+    // Index existing elements by hash; buckets hold positions in `target`
+    let mut index: HashMap<u64, Vec<usize>> = HashMap::with_capacity(target.len() + items.len());
+    for (i, value) in target.iter().enumerate() {
+        index.entry(comparison.hash(value)).or_default().push(i);
+    }
+
+    for item in items {
+        let bucket = index.entry(comparison.hash(item)).or_default();
+        if !bucket.iter().any(|&i| comparison.equal(&target[i], item)) {
+            bucket.push(target.len());
+            target.push(item.clone());
+        }
+    }
 }
 
-/// Check if two values are semantically equal
-/// Handles @id reference normalization
-fn values_equal(a: &Value, b: &Value) -> bool {
-    match (a, b) {
-        (Value::Object(obj_a), Value::Object(obj_b)) => {
-            // Special case: both are @id references
-            if obj_a.len() == 1 && obj_b.len() == 1 {
-                if let (Some(id_a), Some(id_b)) = (obj_a.get("@id"), obj_b.get("@id")) {
-                    return id_a == id_b;
-                }
+/// How values are kept for a property both merged entities set
+///
+/// `FirstWins` and `SecondWins` are now [`ConflictStrategy::PreferMain`]
+/// and [`ConflictStrategy::PreferIncoming`], which keep one value of
+/// conflicting single values only and union arrays.
+#[deprecated(note = "use `ConflictStrategy`")]
+pub type MergeStrategy = ConflictStrategy;
+
+/// How consolidation resolves a property shared entities set to different
+/// single values (see [`MergeConflict`])
+///
+/// Whatever the strategy, arrays are unioned and types merged. The main
+/// entity is the one merged so far, from the crates consolidated first: the
+/// root crate comes before its subcrates, the main crate of a merge before
+/// the crates merged into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictStrategy {
+    /// Keep both values, as an array
+    #[default]
+    Union,
+    /// Keep the value of the main entity
+    #[serde(alias = "first_wins")]
+    PreferMain,
+    /// Keep the value of the entity merged into it
+    #[serde(alias = "second_wins")]
+    PreferIncoming,
+    /// Fail consolidation with [`ConsolidateError::MergeConflict`]
+    ///
+    /// [`ConsolidateError::MergeConflict`]: crate::ConsolidateError::MergeConflict
+    Error,
+}
+
+#[allow(non_upper_case_globals)]
+impl ConflictStrategy {
+    /// Former name of [`ConflictStrategy::PreferMain`]
+    #[deprecated(note = "use `ConflictStrategy::PreferMain`")]
+    pub const FirstWins: Self = Self::PreferMain;
+    /// Former name of [`ConflictStrategy::PreferIncoming`]
+    #[deprecated(note = "use `ConflictStrategy::PreferIncoming`")]
+    pub const SecondWins: Self = Self::PreferIncoming;
+}
+
+/// How consolidation merges one property of shared entities, whatever
+/// their [`ConflictStrategy`]
+///
+/// Unlike the conflict strategy, a property's strategy applies to arrays
+/// too, e.g. to keep only the main entity's `keywords`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PropertyStrategy {
+    /// Keep the values of both
+    Union,
+    /// Keep the value of the main entity
+    PreferMain,
+    /// Keep the value of the entity merged into it
+    PreferIncoming,
+    /// Keep the value with the latest date, e.g. for `dateModified`
+    ///
+    /// Dates are parsed as ISO 8601 (see
+    /// [`parse_iso8601`](crate::datetime::parse_iso8601)), so offsets count
+    /// and a date is as late as its last instant. Of arrays, the latest date
+    /// counts. Values that aren't all dates are left to the conflict
+    /// strategy.
+    Latest,
+    /// Keep the value with the earliest date, e.g. for `dateCreated`
+    ///
+    /// Like [`Latest`](Self::Latest), a date is as early as its first
+    /// instant.
+    Earliest,
+}
+
+/// How shared entities merge, as a whole: the conflict strategy and the
+/// strategies of particular properties
+///
+/// Policies are presets chosen by name (see [`Registry`]) and applied with
+/// [`ConsolidateOptions::with_merge_policy`].
+///
+/// [`Registry`]: crate::Registry
+/// [`ConsolidateOptions::with_merge_policy`]: crate::ConsolidateOptions::with_merge_policy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergePolicy {
+    /// How properties set to different single values are merged
+    pub conflict_strategy: ConflictStrategy,
+    /// How particular properties are merged, whatever the conflict strategy
+    pub property_strategies: BTreeMap<String, PropertyStrategy>,
+}
+
+impl MergePolicy {
+    /// Policy merging by `conflict_strategy` alone
+    pub fn new(conflict_strategy: ConflictStrategy) -> Self {
+        Self {
+            conflict_strategy,
+            property_strategies: BTreeMap::new(),
+        }
+    }
+
+    /// Merge `property` by `strategy`
+    pub fn with_property(
+        mut self,
+        property: impl Into<String>,
+        strategy: PropertyStrategy,
+    ) -> Self {
+        self.property_strategies.insert(property.into(), strategy);
+        self
+    }
+}
+
+/// A property two merged entities set to different single values
+///
+/// Values are single if they are not arrays: two different strings, numbers
+/// or references conflict, while arrays are taken to list several values
+/// and are unioned without conflict. Types never conflict.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergeConflict {
+    /// @id of the entities
+    pub id: String,
+    /// The property they differ in
+    pub property: String,
+    /// Value of the first entity
+    pub first: Value,
+    /// Value of the second entity
+    pub second: Value,
+}
+
+/// Merge two entity graphs by @id, e.g. for callers managing crate
+/// structure themselves
+///
+/// No entity is treated as root or descriptor. Entities of `a` come first,
+/// then those only in `b`, each merged with any later entity sharing its
+/// @id; entities without @id are kept as they are. Where merged entities
+/// set a property to two different single values (not arrays), a conflict
+/// is reported, whichever value `strategy` keeps.
+pub fn merge_graphs(
+    a: Vec<Value>,
+    b: Vec<Value>,
+    strategy: ConflictStrategy,
+) -> (Vec<Value>, Vec<MergeConflict>) {
+    let mut merged: Vec<Value> = Vec::with_capacity(a.len() + b.len());
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    let mut conflicts = Vec::new();
+
+    for entity in a.into_iter().chain(b) {
+        let Some(id) = entity.get("@id").and_then(Value::as_str) else {
+            merged.push(entity);
+            continue;
+        };
+        match positions.get(id) {
+            Some(&i) if merged[i] == entity => {}
+            Some(&i) => {
+                let (entity, found) = merge_entities(&merged[i], &entity, strategy);
+                merged[i] = entity;
+                conflicts.extend(found);
             }
-            obj_a == obj_b
+            None => {
+                positions.insert(id.to_string(), merged.len());
+                merged.push(entity);
+            }
+        }
+    }
+
+    (merged, conflicts)
+}
+
+/// Merge two entities with the same @id, keeping conflicting values as
+/// `strategy` says
+///
+/// The @id is taken from `a` and types are always merged. Returns the
+/// merged entity and the properties `a` and `b` conflict in (see
+/// [`MergeConflict`]), whichever value `strategy` keeps. Nothing fails with
+/// [`ConflictStrategy::Error`]: both values are kept, as with
+/// [`ConflictStrategy::Union`], for the caller to decide on the conflicts.
+/// [`resolve_merge_entities_with`] merges exactly as consolidation does.
+pub fn merge_entities(
+    a: &Value,
+    b: &Value,
+    strategy: ConflictStrategy,
+) -> (Value, Vec<MergeConflict>) {
+    let conflicts = entity_conflicts(a, b, ValueComparison::Exact);
+    let mut merged = union_merge_entities(a, b);
+    let keep_first = match strategy {
+        ConflictStrategy::Union | ConflictStrategy::Error => return (merged, conflicts),
+        ConflictStrategy::PreferMain => true,
+        ConflictStrategy::PreferIncoming => false,
+    };
+    for conflict in &conflicts {
+        let value = if keep_first {
+            &conflict.first
+        } else {
+            &conflict.second
+        };
+        merged[&conflict.property] = value.clone();
+    }
+    (merged, conflicts)
+}
+
+/// Merge `incoming` into `main`, an entity with the same @id, resolving
+/// conflicting single values as `strategy` says
+///
+/// Fails with the first conflict found with [`ConflictStrategy::Error`].
+pub fn resolve_merge_entities(
+    main: &Value,
+    incoming: &Value,
+    strategy: ConflictStrategy,
+) -> Result<Value, MergeConflict> {
+    let (merged, _) = resolve_merge_entities_with(
+        main,
+        incoming,
+        strategy,
+        &BTreeMap::new(),
+        ValueComparison::Exact,
+    )?;
+    Ok(merged)
+}
+
+/// Merge `incoming` into `main`, an entity with the same @id, as
+/// consolidation merges the copies of a shared entity
+///
+/// The `properties` with a strategy are merged as it says, other
+/// conflicting single values are resolved as `strategy` says, and values
+/// are compared as `comparison` says. Returns the merged entity and all
+/// conflicts found, however they were resolved. Fails with the first
+/// conflict of a property without a strategy with [`ConflictStrategy::Error`].
+pub fn resolve_merge_entities_with(
+    main: &Value,
+    incoming: &Value,
+    strategy: ConflictStrategy,
+    properties: &BTreeMap<String, PropertyStrategy>,
+    comparison: ValueComparison,
+) -> Result<(Value, Vec<MergeConflict>), MergeConflict> {
+    let conflicts = entity_conflicts(main, incoming, comparison);
+    let mut merged = union_merge_entities_with(main, incoming, comparison);
+    let ruled = apply_property_strategies(&mut merged, main, incoming, properties);
+    let unruled = conflicts
+        .iter()
+        .filter(|c| !ruled.contains(c.property.as_str()));
+    resolve_conflicts(&mut merged, unruled, strategy)?;
+    Ok((merged, conflicts))
+}
+
+/// Replace the union-merged values of `conflicts` in `merged` as `strategy`
+/// says
+fn resolve_conflicts<'a>(
+    merged: &mut Value,
+    conflicts: impl IntoIterator<Item = &'a MergeConflict>,
+    strategy: ConflictStrategy,
+) -> Result<(), MergeConflict> {
+    for conflict in conflicts {
+        let value = match strategy {
+            ConflictStrategy::Union => return Ok(()),
+            ConflictStrategy::PreferMain => &conflict.first,
+            ConflictStrategy::PreferIncoming => &conflict.second,
+            ConflictStrategy::Error => return Err(conflict.clone()),
+        };
+        merged[&conflict.property] = value.clone();
+    }
+    Ok(())
+}
+
+/// Replace the union-merged values of the properties with a strategy in
+/// `merged` as their strategy says, returning the properties it ruled on
+///
+/// Properties whose strategy can't choose, such as dates that don't parse,
+/// are left to the conflict strategy.
+fn apply_property_strategies<'a>(
+    merged: &mut Value,
+    main: &Value,
+    incoming: &Value,
+    properties: &'a BTreeMap<String, PropertyStrategy>,
+) -> HashSet<&'a str> {
+    let mut ruled = HashSet::new();
+    for (property, strategy) in properties {
+        let (Some(first), Some(second)) = (main.get(property), incoming.get(property)) else {
+            continue;
+        };
+        let value = match strategy {
+            PropertyStrategy::Union => None,
+            PropertyStrategy::PreferMain => Some(first),
+            PropertyStrategy::PreferIncoming => Some(second),
+            PropertyStrategy::Latest => match (latest_date(first), latest_date(second)) {
+                (Some(a), Some(b)) => Some(if b > a { second } else { first }),
+                _ => continue,
+            },
+            PropertyStrategy::Earliest => match (earliest_date(first), earliest_date(second)) {
+                (Some(a), Some(b)) => Some(if b < a { second } else { first }),
+                _ => continue,
+            },
+        };
+        ruled.insert(property.as_str());
+        if let Some(value) = value {
+            merged[property] = value.clone();
+        }
+    }
+    ruled
+}
+
+/// The last instant of the latest date in a value, if it holds only dates,
+/// plain or in value objects
+fn latest_date(value: &Value) -> Option<i128> {
+    dates(value)?.iter().map(|date| date.end).max()
+}
+
+/// The first instant of the earliest date in a value, if it holds only
+/// dates, plain or in value objects
+fn earliest_date(value: &Value) -> Option<i128> {
+    dates(value)?.iter().map(|date| date.start).min()
+}
+
+fn dates(value: &Value) -> Option<Vec<DateSpan>> {
+    match value {
+        Value::String(date) => Some(vec![parse_iso8601(date)?]),
+        Value::Array(values) => {
+            let dates = values.iter().map(dates).collect::<Option<Vec<_>>>()?;
+            Some(dates.concat())
         }
-        _ => a == b,
+        Value::Object(obj) => Some(vec![parse_iso8601(obj.get("@value")?.as_str()?)?]),
+        _ => None,
     }
 }
 
-/// Check if an array contains a value (using semantic equality)
-fn contains_value(arr: &[Value], value: &Value) -> bool {
-    arr.iter().any(|v| values_equal(v, value))
+/// Properties `a` and `b` set to different single values
+fn entity_conflicts(a: &Value, b: &Value, comparison: ValueComparison) -> Vec<MergeConflict> {
+    let (Some(a), Some(b)) = (a.as_object(), b.as_object()) else {
+        return Vec::new();
+    };
+    let id = a.get("@id").and_then(Value::as_str).unwrap_or_default();
+    a.iter()
+        .filter(|(key, _)| *key != "@id" && *key != "@type")
+        .filter_map(|(key, first)| {
+            let second = b.get(key)?;
+            let conflicting =
+                !comparison.equal(first, second) && !first.is_array() && !second.is_array();
+            conflicting.then(|| MergeConflict {
+                id: id.to_string(),
+                property: key.clone(),
+                first: first.clone(),
+                second: second.clone(),
+            })
+        })
+        .collect()
 }
 
 /// Merge two entities with the same @id using union strategy
 ///
-/// Special handling:
+/// Same as [`merge_entities`] with [`ConflictStrategy::Union`], without
+/// looking for conflicts. Special handling:
 /// - @id: must be identical (not merged)
 /// - @type: always produces array of unique types
 /// - Other properties: union merge
 pub fn union_merge_entities(a: &Value, b: &Value) -> Value {
+    union_merge_entities_with(a, b, ValueComparison::Exact)
+}
+
+/// Like [`union_merge_entities`], comparing values as `comparison` says
+pub fn union_merge_entities_with(a: &Value, b: &Value, comparison: ValueComparison) -> Value {
     let obj_a = match a.as_object() {
         Some(o) => o,
         None => return a.clone(),
@@ -142,7 +514,7 @@ pub fn union_merge_entities(a: &Value, b: &Value) -> Value {
 
     for key in all_keys {
         let merged = match (obj_a.get(key), obj_b.get(key)) {
-            (Some(va), Some(vb)) => union_merge_values(va, vb),
+            (Some(va), Some(vb)) => union_merge_values_with(va, vb, comparison),
             (Some(v), None) | (None, Some(v)) => v.clone(),
             (None, None) => continue,
         };
@@ -177,31 +549,92 @@ fn merge_type_arrays(a: &[String], b: &[String]) -> Vec<String> {
 
 /// Group collected entities by @id and merge duplicates
 ///
-/// Returns a vec of merged entities (as JSON Values)
+/// Returns a vec of merged entities (as JSON Values), in the order their
+/// ids were first seen. Entities occurring once are moved through as-is.
 pub fn merge_by_id(entities: Vec<CollectedEntity>) -> Vec<Value> {
-    let mut by_id: HashMap<String, Vec<Value>> = HashMap::new();
+    match dedup_merge_by_id(entities, ConflictStrategy::Union, &BTreeMap::new()) {
+        Ok(merged) => merged.entities,
+        Err(_) => unreachable!("union merges keep conflicting values"),
+    }
+}
+
+/// Entities merged by [`dedup_merge_by_id`]
+#[derive(Debug, Clone, Default)]
+pub struct DedupMerge {
+    /// The merged entities, in the order their ids were first seen
+    pub entities: Vec<Value>,
+    /// How many duplicates were dropped as identical to the entity kept
+    pub identical: usize,
+    /// Properties merged entities set to different single values, however
+    /// they were resolved
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Like [`merge_by_id`], merging the `properties` with a strategy as it
+/// says and resolving other conflicts as `strategy` says (see
+/// [`resolve_merge_entities_with`]), and also reporting identical duplicates
+/// and conflicts
+///
+/// Contextual entities such as licenses are often copied into every crate
+/// verbatim. Copies equal to the entity merged so far are dropped without a
+/// merge. Fails with [`ConsolidateError::MergeConflict`], pointing to the
+/// property of the incoming copy, with [`ConflictStrategy::Error`].
+pub fn dedup_merge_by_id(
+    entities: Vec<CollectedEntity>,
+    strategy: ConflictStrategy,
+    properties: &BTreeMap<String, PropertyStrategy>,
+) -> Result<DedupMerge, ConsolidateError> {
+    dedup_merge_by_crate(entities, |_| strategy, properties, ValueComparison::Exact)
+}
+
+/// Like [`dedup_merge_by_id`], resolving conflicts as the strategy of the
+/// crate (by namespace) the incoming copy of an entity comes from says, and
+/// comparing values as `comparison` says
+pub(crate) fn dedup_merge_by_crate(
+    entities: Vec<CollectedEntity>,
+    strategy: impl Fn(&str) -> ConflictStrategy,
+    properties: &BTreeMap<String, PropertyStrategy>,
+    comparison: ValueComparison,
+) -> Result<DedupMerge, ConsolidateError> {
+    let mut merged: Vec<Value> = Vec::with_capacity(entities.len());
+    let mut positions: HashMap<String, usize> = HashMap::with_capacity(entities.len());
+    let mut identical = 0;
+    let mut conflicts = Vec::new();
 
     for collected in entities {
-        by_id
-            .entry(collected.original_id)
-            .or_default()
-            .push(collected.entity);
-    }
-
-    by_id
-        .into_iter()
-        .map(|(_, mut entities)| {
-            if entities.len() == 1 {
-                entities.pop().unwrap()
-            } else {
+        match positions.get(&collected.original_id) {
+            Some(&i) if comparison.equal(&merged[i], &collected.entity) => identical += 1,
+            Some(&i) => {
                 // Merge all entities with same ID
-                entities
-                    .into_iter()
-                    .reduce(|acc, e| union_merge_entities(&acc, &e))
-                    .unwrap()
+                let (entity, found) = resolve_merge_entities_with(
+                    &merged[i],
+                    &collected.entity,
+                    strategy(&collected.namespace),
+                    properties,
+                    comparison,
+                )
+                .map_err(|conflict| ConsolidateError::MergeConflict {
+                    pointer: Some(collected.pointer(&[&conflict.property])),
+                    id: conflict.id,
+                    property: conflict.property,
+                    first: Box::new(conflict.first),
+                    second: Box::new(conflict.second),
+                })?;
+                merged[i] = entity;
+                conflicts.extend(found);
             }
-        })
-        .collect()
+            None => {
+                positions.insert(collected.original_id, merged.len());
+                merged.push(collected.entity);
+            }
+        }
+    }
+
+    Ok(DedupMerge {
+        entities: merged,
+        identical,
+        conflicts,
+    })
 }
 
 #[cfg(test)]
@@ -240,6 +673,26 @@ mod tests {
         assert_eq!(result, json!(["a", "b", "c"]));
     }
 
+    #[test]
+    fn test_union_merge_large_arrays() {
+        let a: Vec<Value> = (0..1000)
+            .map(|i| json!({"@id": format!("./f{}", i)}))
+            .collect();
+        let b: Vec<Value> = (500..1500)
+            .map(|i| json!({"@id": format!("./f{}", i)}))
+            .collect();
+        let result = union_merge_values(&Value::Array(a), &Value::Array(b));
+        let arr = result.as_array().unwrap();
+        assert_eq!(arr.len(), 1500);
+        assert_eq!(arr[1000], json!({"@id": "./f1000"}));
+
+        // Scalar merged into a large array is appended after the array's elements
+        let mut target = json!("./extra");
+        union_merge_into(&mut target, &result);
+        assert_eq!(target.as_array().unwrap().len(), 1501);
+        assert_eq!(target[1500], "./extra");
+    }
+
     #[test]
     fn test_union_merge_objects() {
         let a = json!({"name": "Alice", "age": 30});
@@ -292,22 +745,26 @@ mod tests {
             CollectedEntity {
                 entity: json!({"@id": "https://orcid.org/1", "name": "Alice"}),
                 original_id: "https://orcid.org/1".to_string(),
-                namespace: "".to_string(),
+                namespace: "".into(),
+                index: 0,
             },
             CollectedEntity {
                 entity: json!({"@id": "https://orcid.org/1", "name": "Alice Smith"}),
                 original_id: "https://orcid.org/1".to_string(),
-                namespace: "experiments".to_string(),
+                namespace: "experiments".into(),
+                index: 0,
             },
             CollectedEntity {
                 entity: json!({"@id": "https://orcid.org/2", "name": "Bob"}),
                 original_id: "https://orcid.org/2".to_string(),
-                namespace: "".to_string(),
+                namespace: "".into(),
+                index: 0,
             },
         ];
 
         let merged = merge_by_id(entities);
         assert_eq!(merged.len(), 2);
+        assert_eq!(merged[1]["@id"], "https://orcid.org/2");
 
         // Find the merged entity for orcid/1
         let alice = merged
@@ -319,6 +776,90 @@ mod tests {
         assert!(name.is_array());
     }
 
+    #[test]
+    fn test_dedup_merge_by_id() {
+        let license = |namespace: &str, entity: Value| CollectedEntity {
+            entity,
+            original_id: "https://spdx.org/licenses/MIT".to_string(),
+            namespace: namespace.into(),
+            index: 0,
+        };
+        let entities = vec![
+            license(
+                "",
+                json!({"@id": "https://spdx.org/licenses/MIT", "@type": ["CreativeWork"]}),
+            ),
+            license(
+                "a",
+                json!({"@type": ["CreativeWork"], "@id": "https://spdx.org/licenses/MIT"}),
+            ),
+            license(
+                "b",
+                json!({"@id": "https://spdx.org/licenses/MIT", "name": "MIT"}),
+            ),
+        ];
+
+        // Only the copy differing from the first is merged
+        let merged =
+            dedup_merge_by_id(entities, ConflictStrategy::Union, &BTreeMap::new()).unwrap();
+        assert_eq!(merged.identical, 1);
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(
+            merged.entities,
+            [
+                json!({"@id": "https://spdx.org/licenses/MIT", "@type": "CreativeWork", "name": "MIT"})
+            ]
+        );
+    }
+
+    #[test]
+    fn test_value_comparison() {
+        // The same reference and name, written differently
+        let copy = |namespace: &str, entity: Value| CollectedEntity {
+            entity,
+            original_id: "https://orcid.org/1".to_string(),
+            namespace: namespace.into(),
+            index: 0,
+        };
+        let entities = || {
+            vec![
+                copy(
+                    "",
+                    json!({"@id": "https://orcid.org/1", "name": "Ren\u{e9}", "knows": {"@id": "./caf\u{e9}/"}}),
+                ),
+                copy(
+                    "a",
+                    json!({"@id": "https://orcid.org/1", "name": {"@value": "Ren\u{e9}"}, "knows": {"@id": "./cafe\u{301}/"}}),
+                ),
+            ]
+        };
+        let merge = |comparison| {
+            dedup_merge_by_crate(
+                entities(),
+                |_| ConflictStrategy::Union,
+                &BTreeMap::new(),
+                comparison,
+            )
+            .unwrap()
+        };
+
+        // As JSON, the copies differ in both properties
+        let exact = merge(ValueComparison::Exact);
+        assert_eq!(exact.identical, 0);
+        assert_eq!(exact.conflicts.len(), 2);
+        assert_eq!(exact.entities[0]["name"].as_array().unwrap().len(), 2);
+
+        // Canonically, they are the same
+        let canonical = merge(ValueComparison::Canonical);
+        assert_eq!(canonical.identical, 1);
+        assert!(canonical.conflicts.is_empty());
+        assert_eq!(canonical.entities, [entities()[0].entity.clone()]);
+
+        // Public merges compare as JSON
+        let merged = union_merge_values(&json!([{"@value": "x"}]), &json!("x"));
+        assert_eq!(merged, json!([{"@value": "x"}, "x"]));
+    }
+
     #[test]
     fn test_id_reference_dedup() {
         let a = json!([{"@id": "#person1"}, {"@id": "#person2"}]);
@@ -328,4 +869,221 @@ mod tests {
         let arr = result.as_array().unwrap();
         assert_eq!(arr.len(), 3); // person1 not duplicated
     }
+
+    #[test]
+    fn test_merge_graphs() {
+        let a = vec![
+            json!({"@id": "#alice", "@type": "Person", "name": "Alice", "knows": [{"@id": "#bob"}]}),
+            json!({"name": "anonymous"}),
+        ];
+        let b = vec![
+            json!({"@id": "#bob", "@type": "Person"}),
+            json!({"@id": "#alice", "@type": "Author", "name": "Alice Smith", "knows": {"@id": "#carol"}}),
+        ];
+
+        let (merged, conflicts) = merge_graphs(a.clone(), b.clone(), ConflictStrategy::Union);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0]["name"], json!(["Alice", "Alice Smith"]));
+        assert_eq!(
+            merged[0]["knows"],
+            json!([{"@id": "#bob"}, {"@id": "#carol"}])
+        );
+        assert_eq!(merged[2]["@id"], "#bob");
+        assert_eq!(
+            conflicts,
+            [MergeConflict {
+                id: "#alice".to_string(),
+                property: "name".to_string(),
+                first: json!("Alice"),
+                second: json!("Alice Smith"),
+            }]
+        );
+
+        let (merged, conflicts) = merge_graphs(a.clone(), b.clone(), ConflictStrategy::PreferMain);
+        assert_eq!(merged[0]["name"], "Alice");
+        assert_eq!(merged[0]["@type"], json!(["Person", "Author"]));
+        assert_eq!(conflicts.len(), 1);
+        let (merged, _) = merge_graphs(a.clone(), b.clone(), ConflictStrategy::PreferIncoming);
+        assert_eq!(merged[0]["name"], "Alice Smith");
+        assert_eq!(
+            merged[0]["knows"],
+            json!([{"@id": "#bob"}, {"@id": "#carol"}])
+        );
+
+        // The former strategy names still work
+        #[allow(deprecated)]
+        let (first, _) = merge_graphs(a.clone(), b.clone(), MergeStrategy::FirstWins);
+        assert_eq!(first, merge_graphs(a, b, ConflictStrategy::PreferMain).0);
+        let strategy: ConflictStrategy = serde_json::from_value(json!("second_wins")).unwrap();
+        assert_eq!(strategy, ConflictStrategy::PreferIncoming);
+    }
+
+    #[test]
+    fn test_merge_entities() {
+        let a = json!({"@id": "#x", "@type": "Person", "name": "X", "email": "x@a.org", "knows": ["#y"]});
+        let b =
+            json!({"@id": "#x", "@type": "Author", "name": "X", "email": "x@b.org", "knows": "#z"});
+
+        let (union, conflicts) = merge_entities(&a, &b, ConflictStrategy::Union);
+        assert_eq!(union, union_merge_entities(&a, &b));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].id, "#x");
+        assert_eq!(conflicts[0].property, "email");
+
+        let (second, _) = merge_entities(&a, &b, ConflictStrategy::PreferIncoming);
+        assert_eq!(second["email"], "x@b.org");
+        assert_eq!(second["knows"], json!(["#y", "#z"]));
+        assert_eq!(second["@type"], json!(["Person", "Author"]));
+
+        // Entities that aren't objects are kept as the first
+        let (kept, conflicts) = merge_entities(&json!("a"), &b, ConflictStrategy::PreferIncoming);
+        assert_eq!((kept, conflicts.len()), (json!("a"), 0));
+    }
+
+    #[test]
+    fn test_conflict_strategies() {
+        let main = json!({"@id": "#x", "@type": "Person", "name": "X", "datePublished": "2024", "knows": ["#y"]});
+        let incoming = json!({"@id": "#x", "@type": "Author", "name": "X", "datePublished": "2025", "knows": "#z"});
+
+        let union = resolve_merge_entities(&main, &incoming, ConflictStrategy::Union).unwrap();
+        assert_eq!(union, union_merge_entities(&main, &incoming));
+
+        let prefer_main =
+            resolve_merge_entities(&main, &incoming, ConflictStrategy::PreferMain).unwrap();
+        assert_eq!(prefer_main["datePublished"], "2024");
+        assert_eq!(prefer_main["knows"], json!(["#y", "#z"]));
+        assert_eq!(prefer_main["@type"], json!(["Person", "Author"]));
+
+        let prefer_incoming =
+            resolve_merge_entities(&main, &incoming, ConflictStrategy::PreferIncoming).unwrap();
+        assert_eq!(prefer_incoming["datePublished"], "2025");
+        assert_eq!(prefer_incoming["knows"], json!(["#y", "#z"]));
+
+        let conflict =
+            resolve_merge_entities(&main, &incoming, ConflictStrategy::Error).unwrap_err();
+        assert_eq!(conflict.property, "datePublished");
+        assert!(resolve_merge_entities(&main, &main, ConflictStrategy::Error).is_ok());
+
+        // Property strategies apply before the conflict strategy fails
+        let properties = BTreeMap::from([("datePublished".to_string(), PropertyStrategy::Latest)]);
+        let (latest, conflicts) = resolve_merge_entities_with(
+            &main,
+            &incoming,
+            ConflictStrategy::Error,
+            &properties,
+            ValueComparison::Exact,
+        )
+        .unwrap();
+        assert_eq!(latest["datePublished"], "2025");
+        assert_eq!(conflicts.len(), 1);
+
+        // Consolidation merges shared entities the same way
+        let collected = |namespace: &str, entity: &Value| CollectedEntity {
+            entity: entity.clone(),
+            original_id: "#x".to_string(),
+            namespace: namespace.into(),
+            index: 0,
+        };
+        let dedup = dedup_merge_by_id(
+            vec![collected("", &main), collected("a", &incoming)],
+            ConflictStrategy::Error,
+            &properties,
+        )
+        .unwrap();
+        assert_eq!(dedup.entities, [latest]);
+    }
+
+    #[test]
+    fn test_date_strategies() {
+        let properties = BTreeMap::from([
+            ("dateModified".to_string(), PropertyStrategy::Latest),
+            ("dateCreated".to_string(), PropertyStrategy::Earliest),
+        ]);
+        let merge = |main: Value, incoming: Value| {
+            resolve_merge_entities_with(
+                &main,
+                &incoming,
+                ConflictStrategy::PreferMain,
+                &properties,
+                ValueComparison::Exact,
+            )
+            .unwrap()
+            .0
+        };
+
+        // Offsets count, though the strings sort the other way
+        let merged = merge(
+            json!({"@id": "#x", "dateModified": "2024-05-01T12:00:00+02:00", "dateCreated": "2024-05-01T11:00:00Z"}),
+            json!({"@id": "#x", "dateModified": "2024-05-01T11:00:00Z", "dateCreated": "2024-05-01T12:00:00+02:00"}),
+        );
+        assert_eq!(merged["dateModified"], "2024-05-01T11:00:00Z");
+        assert_eq!(merged["dateCreated"], "2024-05-01T12:00:00+02:00");
+
+        // A date lasts all day
+        let merged = merge(
+            json!({"@id": "#x", "dateModified": "2024-05-01T10:00:00Z", "dateCreated": "2024-05-01T10:00:00Z"}),
+            json!({"@id": "#x", "dateModified": "2024-05-01", "dateCreated": "2024-05"}),
+        );
+        assert_eq!(merged["dateModified"], "2024-05-01");
+        assert_eq!(merged["dateCreated"], "2024-05");
+
+        // Values that aren't dates are left to the conflict strategy
+        let merged = merge(
+            json!({"@id": "#x", "dateModified": "last week", "dateCreated": ["2020", "unknown"]}),
+            json!({"@id": "#x", "dateModified": "2024-05-01", "dateCreated": "2019"}),
+        );
+        assert_eq!(merged["dateModified"], "last week");
+        // Arrays aren't conflicts, so they are merged as a union
+        assert_eq!(merged["dateCreated"], json!(["2020", "unknown", "2019"]));
+    }
+
+    #[test]
+    fn test_property_strategies() {
+        let collected = |namespace: &str, entity: Value| CollectedEntity {
+            entity,
+            original_id: "https://example.org/dataset".to_string(),
+            namespace: namespace.into(),
+            index: 0,
+        };
+        let entities = || {
+            vec![
+                collected(
+                    "",
+                    json!({"@id": "https://example.org/dataset", "name": "Main", "email": "a@example.org",
+                        "keywords": ["a"], "dateModified": "2024-05-01", "dateCreated": ["2020-01-01", "2021-01-01"]}),
+                ),
+                collected(
+                    "exp",
+                    json!({"@id": "https://example.org/dataset", "name": "Incoming", "email": "b@example.org",
+                        "keywords": ["b"], "dateModified": {"@value": "2025-01-01"}, "dateCreated": "2019-06-01"}),
+                ),
+            ]
+        };
+        let properties = BTreeMap::from([
+            ("name".to_string(), PropertyStrategy::PreferMain),
+            ("keywords".to_string(), PropertyStrategy::PreferIncoming),
+            ("dateModified".to_string(), PropertyStrategy::Latest),
+            ("dateCreated".to_string(), PropertyStrategy::Earliest),
+            ("email".to_string(), PropertyStrategy::Union),
+        ]);
+
+        let merged = dedup_merge_by_id(entities(), ConflictStrategy::Error, &properties).unwrap();
+        let dataset = &merged.entities[0];
+        assert_eq!(dataset["name"], "Main");
+        assert_eq!(dataset["keywords"], json!(["b"]));
+        assert_eq!(dataset["dateModified"], json!({"@value": "2025-01-01"}));
+        assert_eq!(dataset["dateCreated"], "2019-06-01");
+        assert_eq!(dataset["email"], json!(["a@example.org", "b@example.org"]));
+        // Conflicts are reported however they were resolved
+        assert_eq!(merged.conflicts.len(), 3);
+
+        // Properties without a strategy are left to the conflict strategy
+        let properties = BTreeMap::from([("name".to_string(), PropertyStrategy::PreferMain)]);
+        let err = dedup_merge_by_id(entities(), ConflictStrategy::Error, &properties).unwrap_err();
+        assert!(matches!(
+            err,
+            ConsolidateError::MergeConflict { ref property, .. } if property == "dateModified"
+        ));
+        assert_eq!(err.pointer(), Some("/@graph/0/dateModified"));
+    }
 }
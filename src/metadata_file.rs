@@ -0,0 +1,189 @@
+//! Names of metadata files
+//!
+//! A crate's metadata file is named `ro-crate-metadata.json`, or carries a
+//! prefix (`mycrate-ro-crate-metadata.json`). Which file names count as
+//! metadata files is set per consolidation with
+//! [`ConsolidateOptions::metadata_file_patterns`], e.g. to also read the
+//! `ro-crate-metadata.jsonld` of RO-Crate 1.0, and for loads outside one
+//! with [`use_metadata_file_patterns`]. The patterns decide which file
+//! directories and archives are consolidated from, and which @ids
+//! [`classify_id`](crate::id::classify_id) takes for metadata descriptors.
+//!
+//! Patterns are globs matched against file names, not paths. Where several
+//! files match, those matching an earlier pattern are preferred.
+//!
+//! [`ConsolidateOptions::metadata_file_patterns`]: crate::ConsolidateOptions::metadata_file_patterns
+
+use std::cell::RefCell;
+use std::sync::Arc;
+
+use glob::Pattern;
+
+use crate::error::ConsolidateError;
+
+/// Metadata file name patterns used by default, in order of preference
+pub const DEFAULT_METADATA_FILE_PATTERNS: [&str; 2] =
+    ["ro-crate-metadata.json", "*-ro-crate-metadata.json"];
+
+/// Metadata file name of RO-Crate 1.0
+pub const LEGACY_METADATA_FILE_NAME: &str = "ro-crate-metadata.jsonld";
+
+thread_local! {
+    /// Metadata file name patterns of the current thread, unless the defaults
+    static PATTERNS: RefCell<Option<Arc<[Pattern]>>> = const { RefCell::new(None) };
+}
+
+/// Guard of the metadata file name patterns, restoring the previous ones
+/// when dropped
+pub struct MetadataFilePatterns {
+    previous: Option<Arc<[Pattern]>>,
+}
+
+impl Drop for MetadataFilePatterns {
+    fn drop(&mut self) {
+        PATTERNS.with(|patterns| patterns.replace(self.previous.take()));
+    }
+}
+
+/// Take the file names matching `patterns`, in order of preference, for
+/// metadata files on the current thread until the guard is dropped
+///
+/// Consolidation does this for itself and the threads it spawns with its
+/// [`metadata_file_patterns`](crate::ConsolidateOptions::metadata_file_patterns).
+/// Fails without changing the patterns if one is not a valid glob or none
+/// are given.
+pub fn use_metadata_file_patterns<S: AsRef<str>>(
+    patterns: &[S],
+) -> Result<MetadataFilePatterns, ConsolidateError> {
+    let is_default = patterns
+        .iter()
+        .map(AsRef::as_ref)
+        .eq(DEFAULT_METADATA_FILE_PATTERNS);
+    let compiled = if is_default {
+        None
+    } else {
+        Some(compile_patterns(patterns)?)
+    };
+    Ok(share_metadata_file_patterns(compiled))
+}
+
+/// The metadata file name patterns of the current thread, unless the
+/// defaults, e.g. to pass to the threads it spawns
+pub(crate) fn current_metadata_file_patterns() -> Option<Arc<[Pattern]>> {
+    PATTERNS.with_borrow(Clone::clone)
+}
+
+/// Take `patterns` for metadata files on the current thread, e.g. as on the
+/// thread that spawned it
+pub(crate) fn share_metadata_file_patterns(
+    patterns: Option<Arc<[Pattern]>>,
+) -> MetadataFilePatterns {
+    let previous = PATTERNS.with(|current| current.replace(patterns));
+    MetadataFilePatterns { previous }
+}
+
+fn compile_patterns<S: AsRef<str>>(patterns: &[S]) -> Result<Arc<[Pattern]>, ConsolidateError> {
+    if patterns.is_empty() {
+        return Err(ConsolidateError::InvalidStructure(
+            "At least one metadata file pattern is required".to_string(),
+        ));
+    }
+    let compiled = patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern.as_ref()).map_err(|e| {
+                ConsolidateError::InvalidStructure(format!(
+                    "Invalid metadata file pattern '{}': {}",
+                    pattern.as_ref(),
+                    e
+                ))
+            })
+        })
+        .collect::<Result<Arc<[_]>, _>>()?;
+    Ok(compiled)
+}
+
+/// The file name patterns of metadata files on the current thread, in
+/// order of preference
+pub fn metadata_file_patterns() -> Vec<String> {
+    PATTERNS.with_borrow(|patterns| match patterns {
+        Some(patterns) => patterns
+            .iter()
+            .map(|pattern| pattern.as_str().to_string())
+            .collect(),
+        None => DEFAULT_METADATA_FILE_PATTERNS.map(String::from).to_vec(),
+    })
+}
+
+/// Position of the first pattern the last segment of `path` matches, if any
+///
+/// Lower is preferred.
+pub fn metadata_file_rank(path: &str) -> Option<usize> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    PATTERNS.with_borrow(|patterns| match patterns {
+        Some(patterns) => patterns.iter().position(|pattern| pattern.matches(name)),
+        // The default patterns are a name and a suffix
+        None => {
+            let [name_pattern, suffix_pattern] = DEFAULT_METADATA_FILE_PATTERNS;
+            if name == name_pattern {
+                Some(0)
+            } else if name.ends_with(suffix_pattern.trim_start_matches('*')) {
+                Some(1)
+            } else {
+                None
+            }
+        }
+    })
+}
+
+/// Check if the last segment of `path` names a metadata file
+pub fn is_metadata_file(path: &str) -> bool {
+    metadata_file_rank(path).is_some()
+}
+
+/// The preferred metadata file among `paths`, the first of those equally
+/// preferred
+pub fn preferred_metadata_file<'a, S: AsRef<str> + ?Sized>(
+    paths: impl IntoIterator<Item = &'a S>,
+) -> Option<&'a S> {
+    paths
+        .into_iter()
+        .filter_map(|path| metadata_file_rank(path.as_ref()).map(|rank| (rank, path)))
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, path)| path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_file_patterns() {
+        assert!(is_metadata_file("ro-crate-metadata.json"));
+        assert!(is_metadata_file("crates/exp/run1-ro-crate-metadata.json"));
+        assert!(!is_metadata_file(LEGACY_METADATA_FILE_NAME));
+        assert!(!is_metadata_file("ro-crate-metadata.json/data.csv"));
+        assert_eq!(
+            preferred_metadata_file(["a-ro-crate-metadata.json", "ro-crate-metadata.json"]),
+            Some("ro-crate-metadata.json")
+        );
+
+        assert!(use_metadata_file_patterns(&["[invalid"]).is_err());
+        assert!(use_metadata_file_patterns::<&str>(&[]).is_err());
+        assert_eq!(metadata_file_patterns(), DEFAULT_METADATA_FILE_PATTERNS);
+
+        // Patterns apply to the current thread while the guard lives
+        {
+            let _patterns =
+                use_metadata_file_patterns(&["ro-crate-metadata.json*", "*.jsonld"]).unwrap();
+            assert_eq!(metadata_file_rank(LEGACY_METADATA_FILE_NAME), Some(0));
+            assert_eq!(metadata_file_rank("a/crate.jsonld"), Some(1));
+            assert!(!is_metadata_file("a-ro-crate-metadata.json"));
+            std::thread::spawn(|| assert!(!is_metadata_file(LEGACY_METADATA_FILE_NAME)))
+                .join()
+                .unwrap();
+        }
+        assert!(!is_metadata_file(LEGACY_METADATA_FILE_NAME));
+        assert_eq!(metadata_file_rank("-ro-crate-metadata.json"), Some(1));
+    }
+}
@@ -0,0 +1,279 @@
+//! Prometheus-style metrics of a consolidation service
+//!
+//! [`Metrics`] counts the consolidations a service ran, the entities they
+//! produced, the fetches that failed and how long the runs took, and renders
+//! them in the Prometheus text exposition format.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+use crate::job::{Job, JobState};
+use crate::loader::{count_fetch_errors, FetchErrorCounter};
+
+/// Prefix of every metric name
+const PREFIX: &str = "rocrate_consolidate";
+
+/// Upper bounds of the duration histogram's buckets, in seconds
+const DURATION_BUCKETS: [f64; 10] = [0.1, 0.5, 1.0, 5.0, 30.0, 60.0, 300.0, 900.0, 1800.0, 3600.0];
+
+/// How a consolidation ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Ok,
+    Partial,
+    Error,
+}
+
+const OUTCOMES: [(Outcome, &str); 3] = [
+    (Outcome::Ok, "ok"),
+    (Outcome::Partial, "partial"),
+    (Outcome::Error, "error"),
+];
+
+/// Counters of a consolidation service
+///
+/// All counters only grow; share the metrics between threads through an
+/// `Arc`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    consolidations: [AtomicU64; 3],
+    entities: AtomicU64,
+    fetch_errors: Arc<AtomicU64>,
+    /// Count per bucket of [`DURATION_BUCKETS`], not cumulative
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    duration_micros: AtomicU64,
+}
+
+impl Metrics {
+    /// Count the fetches of the current thread that fail, until the guard
+    /// is dropped
+    ///
+    /// Hold the guard while running a consolidation, e.g. in a job runner.
+    pub fn count_fetch_errors(&self) -> FetchErrorCounter {
+        count_fetch_errors(Arc::clone(&self.fetch_errors))
+    }
+
+    /// Record a consolidation that took `duration`
+    pub fn record(&self, result: &Result<ConsolidateResult, ConsolidateError>, duration: Duration) {
+        match result {
+            Ok(result) => self.record_outcome(
+                Some(result.stats.total_entities),
+                result.failure.as_ref().map(|f| f.code.as_str()),
+                duration,
+            ),
+            Err(err) => self.record_outcome(None, Some(err.code()), duration),
+        }
+    }
+
+    /// Record a finished job; jobs still queued or running are ignored
+    pub fn record_job(&self, job: &Job) {
+        let duration = Duration::from_millis(job.duration_ms.unwrap_or_default());
+        match job.state {
+            JobState::Completed => {
+                let report = job.report.as_ref();
+                self.record_outcome(
+                    Some(report.map_or(0, |r| r.stats.total_entities)),
+                    report
+                        .and_then(|r| r.failure.as_ref())
+                        .map(|f| f.code.as_str()),
+                    duration,
+                );
+            }
+            JobState::Failed => self.record_outcome(
+                None,
+                Some(job.error.as_ref().map_or("", |e| e.code.as_str())),
+                duration,
+            ),
+            JobState::Queued | JobState::Running => {}
+        }
+    }
+
+    /// Record a consolidation producing `entities` (`None` if it failed),
+    /// cut short by an error with `error_code` if any
+    fn record_outcome(
+        &self,
+        entities: Option<usize>,
+        error_code: Option<&str>,
+        duration: Duration,
+    ) {
+        let outcome = match (entities, error_code) {
+            (None, _) => Outcome::Error,
+            (Some(_), Some(_)) => Outcome::Partial,
+            (Some(_), None) => Outcome::Ok,
+        };
+        let index = OUTCOMES.iter().position(|(o, _)| *o == outcome).unwrap();
+        self.consolidations[index].fetch_add(1, Ordering::Relaxed);
+        self.entities
+            .fetch_add(entities.unwrap_or_default() as u64, Ordering::Relaxed);
+
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.duration_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let name = header(
+            &mut out,
+            "consolidations_total",
+            "counter",
+            "Consolidations run, by outcome",
+        );
+        for ((_, label), count) in OUTCOMES.iter().zip(&self.consolidations) {
+            let count = count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}{{outcome=\"{}\"}} {}", name, label, count);
+        }
+
+        let name = header(
+            &mut out,
+            "entities_total",
+            "counter",
+            "Entities in the consolidated graphs",
+        );
+        let _ = writeln!(out, "{} {}", name, self.entities.load(Ordering::Relaxed));
+
+        let name = header(
+            &mut out,
+            "fetch_errors_total",
+            "counter",
+            "Fetches that failed after their retries",
+        );
+        let _ = writeln!(
+            out,
+            "{} {}",
+            name,
+            self.fetch_errors.load(Ordering::Relaxed)
+        );
+
+        let name = header(
+            &mut out,
+            "duration_seconds",
+            "histogram",
+            "Duration of consolidations",
+        );
+        let mut cumulative = 0;
+        for (le, count) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            cumulative += count.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let total: u64 = self
+            .consolidations
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum();
+        let seconds = self.duration_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, total);
+        let _ = writeln!(out, "{}_sum {}", name, seconds);
+        let _ = writeln!(out, "{}_count {}", name, total);
+        out
+    }
+}
+
+/// Write the HELP and TYPE lines of a metric, returning its full name
+fn header(out: &mut String, name: &str, kind: &str, help: &str) -> String {
+    let name = format!("{}_{}", PREFIX, name);
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{ConsolidateStats, PartialFailure};
+    use crate::job::{JobError, JobReport, JobRequest};
+    use crate::loader::{fetch_url_with, UrlLoaderOptions};
+
+    fn job(state: JobState, report: Option<JobReport>, error: Option<JobError>) -> Job {
+        Job {
+            id: "01J00000000000000000000000".to_string(),
+            request: JobRequest::default(),
+            tenant: String::new(),
+            state,
+            crates_loaded: 0,
+            report,
+            error,
+            callback_error: None,
+            duration_ms: Some(2_000),
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::default();
+        metrics.record(
+            &Err(ConsolidateError::LoadError {
+                path: "https://example.org/".to_string(),
+                reason: "timeout".to_string(),
+                source: None,
+            }),
+            Duration::from_millis(50),
+        );
+        let report = JobReport {
+            stats: ConsolidateStats {
+                crates_consolidated: 2,
+                total_entities: 40,
+                merged_entities: 1,
+                identical_entities: 0,
+                cached_crates: 0,
+            },
+            warnings: Vec::new(),
+            failure: None,
+            accesses: Vec::new(),
+        };
+        metrics.record_job(&job(JobState::Completed, Some(report.clone()), None));
+        let partial = JobReport {
+            failure: Some(PartialFailure {
+                code: "cycle_detected".to_string(),
+                error: "cycle".to_string(),
+                subcrate_path: Vec::new(),
+                pointer: None,
+                crates_consolidated: 1,
+            }),
+            ..report
+        };
+        metrics.record_job(&job(JobState::Completed, Some(partial), None));
+        metrics.record_job(&job(JobState::Running, None, None));
+
+        // Nothing listens on port 1
+        let options = UrlLoaderOptions {
+            retries: 0,
+            ..UrlLoaderOptions::default()
+        };
+        let errors = metrics.count_fetch_errors();
+        assert!(fetch_url_with("http://127.0.0.1:1/ro-crate-metadata.json", &options).is_err());
+        drop(errors);
+        assert!(fetch_url_with("http://127.0.0.1:1/ro-crate-metadata.json", &options).is_err());
+
+        let text = metrics.render();
+        for line in [
+            "rocrate_consolidate_consolidations_total{outcome=\"ok\"} 1",
+            "rocrate_consolidate_consolidations_total{outcome=\"partial\"} 1",
+            "rocrate_consolidate_consolidations_total{outcome=\"error\"} 1",
+            "rocrate_consolidate_entities_total 80",
+            "rocrate_consolidate_fetch_errors_total 1",
+            "rocrate_consolidate_duration_seconds_bucket{le=\"0.1\"} 1",
+            "rocrate_consolidate_duration_seconds_bucket{le=\"5\"} 3",
+            "rocrate_consolidate_duration_seconds_bucket{le=\"+Inf\"} 3",
+            "rocrate_consolidate_duration_seconds_sum 4.05",
+            "rocrate_consolidate_duration_seconds_count 3",
+            "# TYPE rocrate_consolidate_duration_seconds histogram",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {}:\n{}",
+                line,
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Mirrors of remote crates
+//!
+//! When an upstream host is down, its crates may still be available from a
+//! mirror. [`Mirrors`] maps URL prefixes of upstream hosts to prefixes of
+//! their mirrors, e.g. `https://original.org/` to `https://mirror.internal/`.
+//! A URL is fetched from upstream first, then from each of its mirrors in the
+//! order they were added, and every fetch answered by a mirror is recorded.
+
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::error::IndexError;
+use crate::loader::{fetch_url_with, UrlLoaderOptions};
+
+/// A URL fetched from a mirror instead of its source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MirrorUse {
+    /// The URL that was to be fetched
+    pub source: String,
+    /// The mirror URL it was fetched from
+    pub mirror: String,
+}
+
+/// Mirror URL prefixes to fall back to when fetching
+#[derive(Debug, Default)]
+pub struct Mirrors {
+    /// (source prefix, mirror prefix) in the order they are tried
+    prefixes: Vec<(String, String)>,
+    used: Mutex<Vec<MirrorUse>>,
+    /// Retries, timeout and credentials of [`fetch`](Self::fetch)
+    options: UrlLoaderOptions,
+}
+
+impl Mirrors {
+    /// No mirrors, so URLs are only fetched from their source
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fall back to `mirror` for URLs starting with `source`
+    pub fn with_mirror(mut self, source: impl Into<String>, mirror: impl Into<String>) -> Self {
+        self.prefixes.push((source.into(), mirror.into()));
+        self
+    }
+
+    /// Fetch with `options` instead of [`UrlLoaderOptions::DEFAULT`]
+    pub fn with_url_options(mut self, options: UrlLoaderOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Check if no mirrors are configured
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.is_empty()
+    }
+
+    /// Mirror URLs of `url`, in the order they are tried
+    pub fn mirror_urls(&self, url: &str) -> Vec<String> {
+        self.prefixes
+            .iter()
+            .filter_map(|(source, mirror)| {
+                url.strip_prefix(source.as_str())
+                    .map(|rest| format!("{}{}", mirror, rest))
+            })
+            .collect()
+    }
+
+    /// Fetch the body of `url`, falling back to its mirrors
+    ///
+    /// If every mirror fails as well, the error of the source is returned.
+    pub fn fetch(&self, url: &str) -> Result<String, IndexError> {
+        self.fetch_with(url, |url| fetch_url_with(url, &self.options))
+    }
+
+    /// Like [`fetch`](Self::fetch), with `fetch_url` fetching a URL's body
+    pub fn fetch_with(
+        &self,
+        url: &str,
+        fetch_url: impl Fn(&str) -> Result<String, IndexError>,
+    ) -> Result<String, IndexError> {
+        let err = match fetch_url(url) {
+            Ok(content) => return Ok(content),
+            Err(err) => err,
+        };
+        for mirror in self.mirror_urls(url) {
+            if let Ok(content) = fetch_url(&mirror) {
+                self.used.lock().unwrap().push(MirrorUse {
+                    source: url.to_string(),
+                    mirror,
+                });
+                return Ok(content);
+            }
+        }
+        Err(err)
+    }
+
+    /// The URLs fetched from a mirror so far
+    pub fn used(&self) -> Vec<MirrorUse> {
+        self.used.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirror_fallback() {
+        let mirrors = Mirrors::new()
+            .with_mirror("https://original.org/", "https://down.internal/")
+            .with_mirror("https://original.org/", "https://mirror.internal/crates/");
+        // Only the second mirror answers
+        let fetch = |url: &str| {
+            if url.starts_with("https://mirror.internal/") {
+                Ok(format!("from {}", url))
+            } else {
+                Err(IndexError::LoadError {
+                    path: url.to_string(),
+                    reason: "unreachable".to_string(),
+                    source: None,
+                })
+            }
+        };
+
+        let content = mirrors
+            .fetch_with("https://original.org/a/ro-crate-metadata.json", fetch)
+            .unwrap();
+        assert_eq!(
+            content,
+            "from https://mirror.internal/crates/a/ro-crate-metadata.json"
+        );
+        assert_eq!(
+            mirrors.used(),
+            [MirrorUse {
+                source: "https://original.org/a/ro-crate-metadata.json".to_string(),
+                mirror: "https://mirror.internal/crates/a/ro-crate-metadata.json".to_string(),
+            }]
+        );
+
+        // Without a mirror the source's error is returned
+        let err = mirrors
+            .fetch_with("https://other.org/b/", fetch)
+            .unwrap_err();
+        assert!(err.to_string().contains("https://other.org/b/"));
+        assert_eq!(mirrors.used().len(), 1);
+    }
+}
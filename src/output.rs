@@ -0,0 +1,454 @@
+//! Output shaping and serialization style for consolidated graphs
+//!
+//! JSON-LD allows a single value to be written either as a scalar or as a
+//! one-element array. Consumers differ in what they expect, so the shape of
+//! property values in the consolidated graph can be normalized. The
+//! serialized document can also be given a stable key order and layout so
+//! consolidated crates diff cleanly in version control.
+
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::ser::PrettyFormatter;
+use serde_json::Value;
+
+use crate::collect::{extract_id, is_metadata_descriptor};
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+
+/// How single property values are shaped in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShapePolicy {
+    /// Keep values as they come out of consolidation
+    #[default]
+    AsIs,
+    /// Wrap every value in an array
+    AlwaysArray,
+    /// Unwrap one-element arrays into a scalar
+    ScalarWhenSingle,
+}
+
+/// Normalize the shape of all property values in a graph
+///
+/// Applies to `@type` and every property, including those of nested
+/// objects. The metadata descriptor is left as is, since RO-Crate fixes
+/// its shape.
+pub fn normalize_graph(graph: &mut [Value], policy: ShapePolicy) {
+    if policy == ShapePolicy::AsIs {
+        return;
+    }
+    for entity in graph.iter_mut() {
+        if !is_metadata_descriptor(entity) {
+            normalize_entity(entity, policy);
+        }
+    }
+}
+
+/// Normalize the shape of all property values of one entity
+pub fn normalize_entity(entity: &mut Value, policy: ShapePolicy) {
+    let obj = match entity.as_object_mut() {
+        // Value objects carry a single datatype in @type
+        Some(obj) if !obj.contains_key("@value") => obj,
+        _ => return,
+    };
+
+    for (key, value) in obj.iter_mut() {
+        // Keywords other than @type are single-valued by definition
+        if key.starts_with('@') && key != "@type" {
+            continue;
+        }
+
+        match &mut *value {
+            Value::Array(arr) => arr.iter_mut().for_each(|v| normalize_entity(v, policy)),
+            other => normalize_entity(other, policy),
+        }
+
+        match policy {
+            ShapePolicy::AsIs => {}
+            ShapePolicy::AlwaysArray => {
+                if !value.is_array() {
+                    *value = Value::Array(vec![value.take()]);
+                }
+            }
+            ShapePolicy::ScalarWhenSingle => {
+                if let Value::Array(arr) = value {
+                    if arr.len() == 1 {
+                        *value = arr.pop().unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Order of object keys in serialized output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyOrder {
+    /// Keep keys in the order they are stored in
+    #[default]
+    AsIs,
+    /// `@id` and `@type` first, then all other keys alphabetically
+    Canonical,
+}
+
+/// Serialization style for consolidated documents
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OutputStyle {
+    /// Order of object keys
+    pub key_order: KeyOrder,
+    /// Spaces per indentation level (compact output if `None`)
+    pub indent: Option<usize>,
+    /// End the document with a newline
+    pub trailing_newline: bool,
+    /// Write @graph entities sorted by @id (after the descriptor and root)
+    pub sort_entities: bool,
+    /// Write each @graph entity as its own chunk: one line per entity when
+    /// compact, separated by a blank line when indented
+    pub entity_chunks: bool,
+}
+
+impl OutputStyle {
+    /// Pretty-printed with two-space indentation, as `to_json_string(_, true)`
+    pub fn pretty() -> Self {
+        Self {
+            indent: Some(2),
+            ..Self::default()
+        }
+    }
+
+    /// Preset minimizing diffs between consecutive consolidations
+    ///
+    /// Pair with [`FragmentIdPolicy::AlwaysNamespace`](crate::id::FragmentIdPolicy)
+    /// so ids don't shift when crates are added to the hierarchy.
+    pub fn vcs_friendly() -> Self {
+        Self {
+            key_order: KeyOrder::Canonical,
+            indent: Some(2),
+            trailing_newline: true,
+            sort_entities: true,
+            entity_chunks: true,
+        }
+    }
+}
+
+/// Serialize any JSON value in the given style
+pub fn to_styled_string(value: &Value, style: &OutputStyle) -> Result<String, ConsolidateError> {
+    write_styled(
+        &Ordered {
+            value,
+            order: style.key_order,
+        },
+        style,
+    )
+}
+
+pub(crate) fn write_styled<T: Serialize>(
+    value: &T,
+    style: &OutputStyle,
+) -> Result<String, ConsolidateError> {
+    let mut out = match style.indent {
+        Some(width) => {
+            let indent = " ".repeat(width);
+            let mut buf = Vec::new();
+            let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+            let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+            value.serialize(&mut serializer)?;
+            String::from_utf8(buf).expect("serde_json emits valid UTF-8")
+        }
+        None => serde_json::to_string(value)?,
+    };
+    if style.trailing_newline {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Serialize a consolidation result as a JSON-LD document in the given style
+///
+/// Serializes straight from the result without building an intermediate
+/// document, so large graphs are not held in memory twice.
+pub fn to_json_string_styled(
+    result: &ConsolidateResult,
+    style: &OutputStyle,
+) -> Result<String, ConsolidateError> {
+    write_document(&result.context, result.graph.iter().collect(), style)
+}
+
+/// Serialize a JSON-LD document of `context` and `graph` in the given style
+pub(crate) fn write_document(
+    context: &Value,
+    mut graph: Vec<&Value>,
+    style: &OutputStyle,
+) -> Result<String, ConsolidateError> {
+    if style.sort_entities {
+        let root_id = descriptor_about(graph.iter().copied());
+        graph.sort_by_cached_key(|entity| sort_key(entity, root_id.as_deref()));
+    }
+    if !style.entity_chunks {
+        let document = Document {
+            context,
+            graph: &graph,
+            order: style.key_order,
+        };
+        return write_styled(&document, style);
+    }
+
+    let inner = OutputStyle {
+        trailing_newline: false,
+        ..style.clone()
+    };
+    let indent = " ".repeat(style.indent.unwrap_or(0));
+    let entity_indent = indent.repeat(2);
+    let separator = if style.indent.is_some() {
+        ",\n\n"
+    } else {
+        ",\n"
+    };
+
+    let context = to_styled_string(context, &inner)?;
+    let entities = graph
+        .iter()
+        .map(|entity| {
+            let text = to_styled_string(entity, &inner)?;
+            Ok(format!(
+                "{}{}",
+                entity_indent,
+                text.replace('\n', &format!("\n{}", entity_indent))
+            ))
+        })
+        .collect::<Result<Vec<String>, ConsolidateError>>()?;
+
+    let mut out = format!(
+        "{{\n{i}\"@context\": {},\n{i}\"@graph\": [\n{}\n{i}]\n}}",
+        context.replace('\n', &format!("\n{}", indent)),
+        entities.join(separator),
+        i = indent
+    );
+    if style.trailing_newline {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Sort a graph by @id, keeping the metadata descriptor and root entity first
+pub fn sort_graph(graph: &mut [Value]) {
+    let root_id = descriptor_about(graph.iter());
+    graph.sort_by_cached_key(|entity| sort_key(entity, root_id.as_deref()));
+}
+
+/// The id the metadata descriptor is about (the root entity)
+fn descriptor_about<'a>(mut graph: impl Iterator<Item = &'a Value>) -> Option<String> {
+    graph
+        .find(|e| is_metadata_descriptor(e))
+        .and_then(|d| d.get("about"))
+        .and_then(extract_id)
+        .map(String::from)
+}
+
+fn sort_key(entity: &Value, root_id: Option<&str>) -> (u8, String) {
+    let id = extract_id(entity).unwrap_or_default();
+    let rank = if is_metadata_descriptor(entity) {
+        0
+    } else if root_id == Some(id) {
+        1
+    } else {
+        2
+    };
+    (rank, id.to_string())
+}
+
+/// A JSON-LD document borrowing its context and entities
+struct Document<'a> {
+    context: &'a Value,
+    graph: &'a [&'a Value],
+    order: KeyOrder,
+}
+
+impl Serialize for Document<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let entities: Vec<Ordered> = self
+            .graph
+            .iter()
+            .map(|value| Ordered {
+                value,
+                order: self.order,
+            })
+            .collect();
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry(
+            "@context",
+            &Ordered {
+                value: self.context,
+                order: self.order,
+            },
+        )?;
+        map.serialize_entry("@graph", &entities)?;
+        map.end()
+    }
+}
+
+/// A JSON value serialized with a specific key order
+struct Ordered<'a> {
+    value: &'a Value,
+    order: KeyOrder,
+}
+
+impl Serialize for Ordered<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.value {
+            Value::Object(obj) => {
+                let mut keys: Vec<&String> = obj.keys().collect();
+                if self.order == KeyOrder::Canonical {
+                    keys.sort_by_key(|k| (key_rank(k), k.as_str()));
+                }
+                let mut map = serializer.serialize_map(Some(keys.len()))?;
+                for key in keys {
+                    let value = Ordered {
+                        value: &obj[key],
+                        order: self.order,
+                    };
+                    map.serialize_entry(key, &value)?;
+                }
+                map.end()
+            }
+            Value::Array(arr) => {
+                let mut seq = serializer.serialize_seq(Some(arr.len()))?;
+                for item in arr {
+                    seq.serialize_element(&Ordered {
+                        value: item,
+                        order: self.order,
+                    })?;
+                }
+                seq.end()
+            }
+            other => other.serialize(serializer),
+        }
+    }
+}
+
+/// Sort rank of a key in canonical order
+fn key_rank(key: &str) -> u8 {
+    match key {
+        "@id" => 0,
+        "@type" => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_always_array() {
+        let mut graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "#p",
+                "@type": "PropertyValue",
+                "name": "x",
+                "value": {"@value": 1, "@type": "xsd:int"},
+                "author": [{"@id": "#a"}]
+            }),
+        ];
+
+        normalize_graph(&mut graph, ShapePolicy::AlwaysArray);
+
+        assert_eq!(graph[0]["about"], json!({"@id": "./"}));
+        assert_eq!(graph[1]["@id"], "#p");
+        assert_eq!(graph[1]["@type"], json!(["PropertyValue"]));
+        assert_eq!(graph[1]["name"], json!(["x"]));
+        assert_eq!(
+            graph[1]["value"],
+            json!([{"@value": 1, "@type": "xsd:int"}])
+        );
+        assert_eq!(graph[1]["author"], json!([{"@id": "#a"}]));
+    }
+
+    #[test]
+    fn test_scalar_when_single() {
+        let mut graph = vec![json!({
+            "@id": "./",
+            "@type": ["Dataset"],
+            "name": ["Root"],
+            "keywords": ["a", "b"],
+            "hasPart": []
+        })];
+
+        normalize_graph(&mut graph, ShapePolicy::ScalarWhenSingle);
+
+        assert_eq!(graph[0]["@type"], "Dataset");
+        assert_eq!(graph[0]["name"], "Root");
+        assert_eq!(graph[0]["keywords"], json!(["a", "b"]));
+        assert_eq!(graph[0]["hasPart"], json!([]));
+    }
+
+    #[test]
+    fn test_to_styled_string() {
+        let value = json!({
+            "name": "x",
+            "@type": "File",
+            "author": {"name": "A", "@id": "#a"},
+            "@id": "./x"
+        });
+
+        let style = OutputStyle {
+            key_order: KeyOrder::Canonical,
+            indent: None,
+            trailing_newline: true,
+            ..OutputStyle::default()
+        };
+        assert_eq!(
+            to_styled_string(&value, &style).unwrap(),
+            "{\"@id\":\"./x\",\"@type\":\"File\",\"author\":{\"@id\":\"#a\",\"name\":\"A\"},\"name\":\"x\"}\n"
+        );
+
+        let style = OutputStyle {
+            key_order: KeyOrder::Canonical,
+            indent: Some(4),
+            ..OutputStyle::default()
+        };
+        let out = to_styled_string(&json!({"b": [1], "@id": "#a"}), &style).unwrap();
+        assert_eq!(
+            out,
+            "{\n    \"@id\": \"#a\",\n    \"b\": [\n        1\n    ]\n}"
+        );
+    }
+
+    #[test]
+    fn test_vcs_friendly() {
+        let result = ConsolidateResult {
+            graph: vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "name": "Root"}),
+                json!({"@id": "./b.csv"}),
+                json!({"@id": "./a.csv"}),
+            ],
+            context: json!("https://w3id.org/ro/crate/1.1/context"),
+            ..ConsolidateResult::default()
+        };
+
+        let out = to_json_string_styled(&result, &OutputStyle::vcs_friendly()).unwrap();
+        let parsed: Value = serde_json::from_str(&out).unwrap();
+        let ids: Vec<&str> = parsed["@graph"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(extract_id)
+            .collect();
+        assert_eq!(
+            ids,
+            vec!["ro-crate-metadata.json", "./", "./a.csv", "./b.csv"]
+        );
+        assert!(out.contains("},\n\n    {\n      \"@id\": \"./\","));
+        assert!(out.ends_with("  ]\n}\n"));
+
+        let compact = OutputStyle {
+            entity_chunks: true,
+            ..OutputStyle::default()
+        };
+        let out = to_json_string_styled(&result, &compact).unwrap();
+        assert_eq!(out.lines().count(), 9);
+        assert!(out.contains("\n{\"@id\":\"./a.csv\"}\n"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Mapping between crate-relative ids and filesystem paths
+//!
+//! Ids are percent-encoded URI references, while file names are OS strings
+//! that need not be valid UTF-8. Ids are decoded segment by segment into path
+//! components, and components are encoded back byte by byte, so the mapping
+//! is deterministic in both directions.
+//!
+//! Paths are built by pushing components rather than joining strings with
+//! '/', which keeps them valid below Windows verbatim (`\\?\`) prefixes such
+//! as those returned by [`std::fs::canonicalize`] for long paths.
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+
+/// Resolve a crate-relative id (e.g. "./raw%20data/run.csv") below `base`
+///
+/// Empty and "." segments are skipped; escapes that don't decode to a name
+/// the platform can represent are kept as written. Fails on segments that
+/// are not plain file names once decoded, such as "..", "%2E%2E" or
+/// "%2Fetc", which would leave `base` or replace it.
+pub fn join_id(base: &Path, relative_id: &str) -> Result<PathBuf, String> {
+    let mut path = base.to_path_buf();
+    for segment in relative_id.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        let name = decode_component(segment);
+        if !is_file_name(&name) {
+            return Err(format!(
+                "'{}' in '{}' is not a file name",
+                segment, relative_id
+            ));
+        }
+        path.push(name);
+    }
+    Ok(path)
+}
+
+/// Whether `name` is a single plain path component, without separators
+pub(crate) fn is_file_name(name: &OsStr) -> bool {
+    let bytes = name.as_encoded_bytes();
+    let mut components = Path::new(name).components();
+    !bytes.contains(&b'/')
+        && !bytes.contains(&b'\\')
+        && matches!(components.next(), Some(Component::Normal(n)) if n == name)
+        && components.next().is_none()
+}
+
+/// Decode one percent-encoded id segment into a file name
+pub fn decode_component(segment: &str) -> OsString {
+    let bytes = percent_decode(segment);
+    match String::from_utf8(bytes) {
+        Ok(name) => name.into(),
+        Err(e) => bytes_to_os_string(e.into_bytes()).unwrap_or_else(|| segment.into()),
+    }
+}
+
+/// Encode a file name as an id segment
+///
+/// '%', '#' and '?' are escaped so the segment reads back as the same name,
+/// as is every byte that is not part of valid UTF-8.
+pub fn component_to_id(name: &OsStr) -> String {
+    let mut id = String::new();
+    for chunk in name.as_encoded_bytes().utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '%' | '#' | '?' => push_escaped(&mut id, c as u8),
+                _ => id.push(c),
+            }
+        }
+        for &byte in chunk.invalid() {
+            push_escaped(&mut id, byte);
+        }
+    }
+    id
+}
+
+/// A path as a string, with non-UTF-8 components encoded as in [`component_to_id`]
+///
+/// Valid UTF-8 paths are returned unchanged.
+pub fn path_to_string(path: &Path) -> String {
+    if let Some(s) = path.to_str() {
+        return s.to_string();
+    }
+    let mut string = String::new();
+    let mut separate = false;
+    for component in path.components() {
+        let part = component.as_os_str();
+        if separate {
+            string.push(std::path::MAIN_SEPARATOR);
+        }
+        match part.to_str() {
+            Some(s) => string.push_str(s),
+            None => string.push_str(&component_to_id(part)),
+        }
+        // Roots and prefixes carry their own separator
+        separate = matches!(
+            component,
+            Component::Normal(_) | Component::CurDir | Component::ParentDir
+        );
+    }
+    string
+}
+
+fn push_escaped(id: &mut String, byte: u8) {
+    id.push_str(&format!("%{:02X}", byte));
+}
+
+fn percent_decode(segment: &str) -> Vec<u8> {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| segment.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(unix)]
+fn bytes_to_os_string(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(OsString::from_vec(bytes))
+}
+
+/// File names on other platforms are Unicode, so non-UTF-8 bytes cannot name a file
+#[cfg(not(unix))]
+fn bytes_to_os_string(_bytes: Vec<u8>) -> Option<OsString> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_join_id() {
+        let base = Path::new("/crate");
+        assert_eq!(
+            join_id(base, "./raw%20data/run.csv").unwrap(),
+            Path::new("/crate/raw data/run.csv")
+        );
+        assert_eq!(join_id(base, "./sub/").unwrap(), Path::new("/crate/sub"));
+        // Malformed escapes are kept as written
+        assert_eq!(
+            join_id(base, "100%.txt").unwrap(),
+            Path::new("/crate/100%.txt")
+        );
+        // Nothing may decode to a way out of the base
+        for hostile in [
+            "../x",
+            "./%2Fetc/",
+            "a/%2E%2E/%2E%2E/b",
+            "a%2F..%2Fb",
+            "a%5Cb",
+        ] {
+            assert!(join_id(base, hostile).is_err(), "{}", hostile);
+        }
+    }
+
+    #[test]
+    fn test_component_round_trip() {
+        let name = OsStr::new("50% #1?.csv");
+        let id = component_to_id(name);
+        assert_eq!(id, "50%25 %231%3F.csv");
+        assert_eq!(decode_component(&id), name);
+        assert_eq!(component_to_id(OsStr::new("caf\u{e9}")), "caf\u{e9}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_component() {
+        use std::os::unix::ffi::OsStrExt;
+
+        // Latin-1 "café"
+        let name = OsStr::from_bytes(b"caf\xe9");
+        let id = component_to_id(name);
+        assert_eq!(id, "caf%E9");
+        assert_eq!(decode_component(&id), name);
+        assert_eq!(
+            path_to_string(&Path::new("/data").join(name)),
+            "/data/caf%E9"
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Consolidation stages as a public pipeline
+//!
+//! [`consolidate`](crate::consolidate()) runs every stage over a whole
+//! hierarchy. Tools that need only some of them, e.g. moving one crate's ids
+//! into a folder of another, or merging graphs whose ids are already
+//! distinct, can run the stages themselves through a [`Pipeline`]:
+//!
+//! 1. **collect** ([`collect_from_graph`]): sort a crate's entities into
+//!    local and shared ones and find its root, descriptor and subcrates
+//! 2. **rewrite** ([`build_id_map`], [`rewrite_references`]): move the ids
+//!    of local entities into a namespace and follow them in all references
+//! 3. **merge** ([`resolve_merge_entities_with`], or [`union_merge_entities`]
+//!    and [`merge_entities`]): merge entities sharing an @id
+//! 4. **fold** ([`create_subcrate_folder`]): turn a subcrate's root into the
+//!    folder standing in for it
+//!
+//! A [`Pipeline`] made [`with_options`](Pipeline::with_options) runs them
+//! with the settings of a consolidation.
+//!
+//! ```ignore
+//! let mut pipeline = Pipeline::new();
+//! let mut collection = pipeline.collect(graph, "experiments");
+//! let id_map = pipeline.id_map(&collection, "experiments");
+//! pipeline.rewrite(&mut collection, &id_map);
+//! let folder = pipeline.folder("./experiments/", None, &collection);
+//! ```
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde_json::Value;
+
+use crate::collect::{extract_id, CrateCollection};
+use crate::consolidate::ConsolidateOptions;
+use crate::error::ConsolidateError;
+use crate::id::{namespace_from_folder_id, validate_folder_id, FragmentIdPolicy};
+use crate::merge::{ConflictStrategy, PropertyStrategy};
+use crate::transform::ReferenceMergePolicy;
+use crate::value::ValueComparison;
+
+pub use crate::collect::collect_from_graph;
+pub use crate::id::{build_id_map, rewrite_references};
+pub use crate::merge::{merge_entities, resolve_merge_entities_with, union_merge_entities};
+pub use crate::transform::{create_subcrate_folder, create_subcrate_folder_with};
+
+/// A crate moved into a folder by [`namespace_crate`]
+#[derive(Debug, Clone)]
+pub struct NamespacedCrate {
+    /// The crate's entities with rewritten ids, in their original order
+    pub graph: Vec<Value>,
+    /// Map from original ids to rewritten ones, for those that changed
+    pub id_map: HashMap<String, String>,
+}
+
+/// Move a crate's ids into the folder `folder_id`, without consolidating
+/// its subcrates or merging it with anything
+///
+/// See [`Pipeline::namespace`].
+pub fn namespace_crate(
+    graph: Vec<Value>,
+    folder_id: &str,
+) -> Result<NamespacedCrate, ConsolidateError> {
+    Pipeline::new().namespace(graph, folder_id)
+}
+
+/// The stages of consolidation, run one at a time
+///
+/// Fragment ids rewritten by one pipeline stay unique across all crates it
+/// rewrites, as in a single consolidation.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    /// How fragment ids of namespaced crates are rewritten
+    pub fragment_id_policy: FragmentIdPolicy,
+    /// Fragment ids in use, extended by each id map built
+    pub used_fragments: HashSet<String>,
+    /// Add "Subcrate" to the @type of folders
+    pub add_subcrate_type: bool,
+    /// Which side wins properties both a subcrate's root and the parent's
+    /// reference to it set
+    pub reference_merge: ReferenceMergePolicy,
+    /// How merged entities' properties set to different single values are
+    /// resolved
+    pub conflict_strategy: ConflictStrategy,
+    /// How these properties of merged entities are merged, whatever the
+    /// conflict strategy
+    pub property_strategies: BTreeMap<String, PropertyStrategy>,
+    /// When the values of merged entities are taken to be the same
+    pub value_comparison: ValueComparison,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::with_options(&ConsolidateOptions::default())
+    }
+}
+
+impl Pipeline {
+    /// Pipeline with the defaults of [`ConsolidateOptions`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pipeline running the stages as a consolidation with `options` does
+    pub fn with_options(options: &ConsolidateOptions) -> Self {
+        Self {
+            fragment_id_policy: options.fragment_id_policy,
+            used_fragments: HashSet::new(),
+            add_subcrate_type: options.add_subcrate_type,
+            reference_merge: options.reference_merge,
+            conflict_strategy: options.conflict_strategy,
+            property_strategies: options.property_strategies.clone(),
+            value_comparison: options.value_comparison,
+        }
+    }
+
+    /// Collect the entities of a crate's graph, to be namespaced with
+    /// `namespace` ("" for a root crate)
+    pub fn collect(&self, graph: Vec<Value>, namespace: &str) -> CrateCollection {
+        collect_from_graph(graph, namespace)
+    }
+
+    /// Map from the ids of a collected crate's local entities and root to
+    /// their ids in `namespace`, for those that change
+    pub fn id_map(
+        &mut self,
+        collection: &CrateCollection,
+        namespace: &str,
+    ) -> HashMap<String, String> {
+        let ids = collection
+            .local_entities
+            .iter()
+            .chain(&collection.root_entity)
+            .map(|e| e.original_id.as_str());
+        build_id_map(
+            ids,
+            namespace,
+            &mut self.used_fragments,
+            self.fragment_id_policy,
+        )
+    }
+
+    /// Rewrite the ids of a collected crate's entities, and the references
+    /// within all of them, as `id_map` says
+    ///
+    /// The `original_id` of each entity is kept.
+    pub fn rewrite(&self, collection: &mut CrateCollection, id_map: &HashMap<String, String>) {
+        let CrateCollection {
+            local_entities,
+            shared_entities,
+            root_entity,
+            extra_roots,
+            metadata_descriptor,
+            ..
+        } = collection;
+        let entities = local_entities
+            .iter_mut()
+            .chain(shared_entities.iter_mut())
+            .chain(root_entity.iter_mut())
+            .chain(extra_roots.iter_mut())
+            .chain(metadata_descriptor.iter_mut());
+        for collected in entities {
+            if let Some(new_id) = id_map.get(&collected.original_id) {
+                if let Some(Value::String(id)) = collected.entity.get_mut("@id") {
+                    id.clone_from(new_id);
+                }
+            }
+            rewrite_references(&mut collected.entity, id_map);
+        }
+    }
+
+    /// Collect a crate's graph and rewrite its ids into the folder
+    /// `folder_id`, for moving the crate into another one by hand
+    ///
+    /// The crate's root becomes the folder, and its metadata descriptor is
+    /// left out. Subcrates are not consolidated: references to them are
+    /// rewritten like any other.
+    pub fn namespace(
+        &mut self,
+        graph: Vec<Value>,
+        folder_id: &str,
+    ) -> Result<NamespacedCrate, ConsolidateError> {
+        validate_folder_id(folder_id).map_err(ConsolidateError::InvalidFolderId)?;
+        let namespace = namespace_from_folder_id(folder_id);
+        let mut collection = self.collect(graph, namespace);
+        let id_map = self.id_map(&collection, namespace);
+        self.rewrite(&mut collection, &id_map);
+
+        let mut entities: Vec<_> = collection
+            .local_entities
+            .into_iter()
+            .chain(collection.shared_entities)
+            .chain(collection.root_entity)
+            .chain(collection.extra_roots)
+            .collect();
+        entities.sort_by_key(|e| e.index);
+        Ok(NamespacedCrate {
+            graph: entities.into_iter().map(|e| e.entity).collect(),
+            id_map,
+        })
+    }
+
+    /// Merge entities sharing an @id, in the order each id first appears
+    ///
+    /// Entities are merged as consolidation merges shared entities (see
+    /// [`resolve_merge_entities_with`]). Fails with the first conflict
+    /// [`ConflictStrategy::Error`] leaves unresolved.
+    pub fn merge(
+        &self,
+        entities: impl IntoIterator<Item = Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let mut merged: Vec<Value> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for entity in entities {
+            let position = extract_id(&entity).and_then(|id| positions.get(id).copied());
+            match position {
+                Some(i) if self.value_comparison.equal(&merged[i], &entity) => {}
+                Some(i) => {
+                    let (entity, _) = resolve_merge_entities_with(
+                        &merged[i],
+                        &entity,
+                        self.conflict_strategy,
+                        &self.property_strategies,
+                        self.value_comparison,
+                    )?;
+                    merged[i] = entity;
+                }
+                None => {
+                    if let Some(id) = extract_id(&entity) {
+                        positions.insert(id.to_string(), merged.len());
+                    }
+                    merged.push(entity);
+                }
+            }
+        }
+        Ok(merged)
+    }
+
+    /// The folder `folder_id` standing in for a collected (and rewritten)
+    /// subcrate, merged with `parent_ref`, the parent's reference to it
+    ///
+    /// Lists the crate's local entities as its consolidated entities. `None`
+    /// if the crate has no root.
+    pub fn folder(
+        &self,
+        folder_id: &str,
+        parent_ref: Option<&Value>,
+        collection: &CrateCollection,
+    ) -> Option<Value> {
+        let root = collection.root_entity.as_ref()?;
+        let contained = collection
+            .local_entities
+            .iter()
+            .filter_map(|e| extract_id(&e.entity).map(String::from))
+            .collect();
+        Some(create_subcrate_folder_with(
+            folder_id,
+            parent_ref,
+            &root.entity,
+            contained,
+            self.add_subcrate_type,
+            self.reference_merge,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "name": "Run", "hasPart": {"@id": "data.csv"}}),
+            json!({"@id": "data.csv", "@type": "File", "author": {"@id": "#alice"}}),
+            json!({"@id": "#alice", "@type": "Person"}),
+            json!({"@id": "https://orcid.org/0000-0001", "@type": "Person", "name": "A"}),
+        ];
+        let mut pipeline = Pipeline::new();
+        // A fragment id taken by another crate
+        pipeline.used_fragments.insert("#alice".to_string());
+
+        let mut collection = pipeline.collect(graph, "run");
+        let id_map = pipeline.id_map(&collection, "run");
+        assert_eq!(id_map["./"], "./run/");
+        assert_eq!(id_map["#alice"], "#run-alice");
+        pipeline.rewrite(&mut collection, &id_map);
+
+        let data = &collection.local_entities[0];
+        assert_eq!(data.original_id, "data.csv");
+        assert_eq!(data.entity["@id"], "./run/data.csv");
+        assert_eq!(data.entity["author"], json!({"@id": "#run-alice"}));
+
+        let folder = pipeline.folder("./run/", None, &collection).unwrap();
+        assert_eq!(folder["@id"], "./run/");
+        assert_eq!(folder["hasPart"], json!({"@id": "./run/data.csv"}));
+        assert_eq!(folder["consolidatedEntities"].as_array().unwrap().len(), 2);
+
+        let shared = collection.shared_entities.iter().map(|e| e.entity.clone());
+        let other = json!({"@id": "https://orcid.org/0000-0001", "name": "B"});
+        let merged = pipeline
+            .merge(shared.clone().chain([other.clone()]))
+            .unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0]["name"], json!(["A", "B"]));
+
+        // Merges follow the strategies of the options
+        let options = ConsolidateOptions {
+            conflict_strategy: ConflictStrategy::Error,
+            ..Default::default()
+        };
+        let strict = Pipeline::with_options(&options);
+        assert!(matches!(
+            strict.merge(shared.clone().chain([other.clone()])),
+            Err(ConsolidateError::MergeConflict { .. })
+        ));
+        let options = ConsolidateOptions {
+            property_strategies: BTreeMap::from([(
+                "name".to_string(),
+                PropertyStrategy::PreferIncoming,
+            )]),
+            ..options
+        };
+        let merged = Pipeline::with_options(&options)
+            .merge(shared.chain([other]))
+            .unwrap();
+        assert_eq!(merged[0]["name"], "B");
+    }
+
+    #[test]
+    fn test_namespace_crate() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": "data.csv"}, {"@id": "sub/"}]}),
+            json!({"@id": "data.csv", "@type": "File", "author": {"@id": "https://orcid.org/0000-0001"}}),
+            json!({"@id": "sub/", "@type": "Dataset", "conformsTo": {"@id": "https://w3id.org/ro/crate"}}),
+        ];
+        let namespaced = namespace_crate(graph, "./runs/one/").unwrap();
+        let ids: Vec<_> = namespaced.graph.iter().map(|e| e["@id"].clone()).collect();
+        assert_eq!(
+            ids,
+            ["./runs/one/", "./runs/one/data.csv", "./runs/one/sub/"]
+        );
+        assert_eq!(
+            namespaced.graph[0]["hasPart"],
+            json!([{"@id": "./runs/one/data.csv"}, {"@id": "./runs/one/sub/"}])
+        );
+        assert_eq!(namespaced.id_map["data.csv"], "./runs/one/data.csv");
+        assert!(!namespaced
+            .id_map
+            .contains_key("https://orcid.org/0000-0001"));
+
+        assert!(matches!(
+            namespace_crate(Vec::new(), "./"),
+            Err(ConsolidateError::InvalidFolderId(_))
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+//! Planning the fetches of a consolidation
+//!
+//! [`plan_fetches`] runs discovery over the metadata already at hand, the
+//! root crate's graph and whatever the loader lists up front (such as a
+//! [`ManifestLoader`](crate::ManifestLoader)'s manifest), and reports where
+//! each subcrate would be loaded from, without loading any of them. Operators
+//! can pre-approve or pre-cache the URLs before running the consolidation.
+//!
+//! As planned subcrates aren't loaded, subcrates referenced only from within
+//! them are not part of the plan.
+
+use std::sync::Mutex;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::consolidate::{consolidate, ConsolidateInput, ConsolidateOptions, SubcrateLoader};
+use crate::error::ConsolidateError;
+use crate::vocab::METADATA_DESCRIPTOR_ID;
+
+/// A subcrate a consolidation would load
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedFetch {
+    /// Id of the reference to the subcrate
+    pub subcrate_id: String,
+    /// Namespace of the crate referencing the subcrate
+    pub parent_namespace: String,
+    /// Where the loader would load the subcrate from, if it can tell
+    pub location: Option<String>,
+}
+
+/// Plan the subcrate loads of consolidating `graph` with `loader`
+///
+/// Subcrates are listed in the order they would be loaded.
+pub fn plan_fetches(
+    graph: Vec<Value>,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+) -> Result<Vec<PlannedFetch>, ConsolidateError> {
+    let planner = PlanningLoader {
+        inner: loader,
+        fetches: Mutex::new(Vec::new()),
+    };
+    consolidate(ConsolidateInput::Single(graph), &planner, options)?;
+    Ok(planner.fetches.into_inner().unwrap())
+}
+
+/// Loader recording where subcrates would be loaded from, standing in an
+/// empty crate for each
+struct PlanningLoader<'a> {
+    inner: &'a dyn SubcrateLoader,
+    fetches: Mutex<Vec<PlannedFetch>>,
+}
+
+impl SubcrateLoader for PlanningLoader<'_> {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let location = self
+            .inner
+            .location(subcrate_id, parent_namespace, subcrate_entity);
+        self.fetches.lock().unwrap().push(PlannedFetch {
+            subcrate_id: subcrate_id.to_string(),
+            parent_namespace: parent_namespace.to_string(),
+            location,
+        });
+        Ok(vec![
+            json!({"@id": METADATA_DESCRIPTOR_ID, "@type": "CreativeWork", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset"}),
+        ])
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        self.inner.listed_subcrates(namespace)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{ManifestLoader, NoOpLoader, UrlLoader};
+
+    fn root_graph() -> Vec<Value> {
+        vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "hasPart": [{"@id": "./exp/"}, {"@id": "./mirror/"}]
+            }),
+            json!({
+                "@id": "./exp/",
+                "@type": "Dataset",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }),
+            json!({
+                "@id": "./mirror/",
+                "@type": "Dataset",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"},
+                "subjectOf": {"@id": "https://mirror.example.org/m/ro-crate-metadata.json"}
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_plan_fetches() {
+        let options = ConsolidateOptions::default();
+        let loader = UrlLoader::from_metadata_url("https://example.org/crate/");
+        let plan = plan_fetches(root_graph(), &loader, &options).unwrap();
+        let locations: Vec<_> = plan.iter().map(|f| f.location.as_deref()).collect();
+        assert_eq!(
+            locations,
+            [
+                Some("https://example.org/crate/exp/ro-crate-metadata.json"),
+                Some("https://mirror.example.org/m/ro-crate-metadata.json"),
+            ]
+        );
+        assert_eq!(plan[0].subcrate_id, "./exp/");
+        assert_eq!(plan[0].parent_namespace, "");
+
+        // Loaders that can't tell leave the location open
+        let plan = plan_fetches(root_graph(), &NoOpLoader, &options).unwrap();
+        assert!(plan.iter().all(|f| f.location.is_none()));
+    }
+
+    #[test]
+    fn test_plan_includes_listed_subcrates() {
+        // Listed subcrates of planned crates are known without loading them
+        let loader = ManifestLoader::new(
+            "https://example.org/crate/",
+            ["exp/", "exp/run1/ro-crate-metadata.json"],
+        );
+        let plan = plan_fetches(root_graph(), &loader, &ConsolidateOptions::default()).unwrap();
+        assert!(plan.iter().any(|f| f.subcrate_id == "./run1/"
+            && f.parent_namespace == "exp"
+            && f.location.as_deref()
+                == Some("https://example.org/crate/exp/run1/ro-crate-metadata.json")));
+    }
+}
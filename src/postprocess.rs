@@ -0,0 +1,672 @@
+//! Post-processing of the consolidated result
+//!
+//! Post-processors run in order over the finished result, before data
+//! pointers are collected and before serialization. Output tweaks such as
+//! redaction, enrichment or extra vocabulary belong here rather than in
+//! [`ConsolidateOptions`](crate::consolidate::ConsolidateOptions).
+//!
+//! Any `Fn(&mut ConsolidateResult) -> Result<(), ConsolidateError>` closure
+//! is a post-processor.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::collect::extract_id;
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+use crate::id::rewrite_references;
+use crate::vocab::ROOT_ENTITY_ID;
+
+/// A transformation of the consolidated result
+pub trait PostProcessor: Send + Sync {
+    /// Transform the result in place
+    ///
+    /// An error fails the consolidation.
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError>;
+}
+
+impl<F> PostProcessor for F
+where
+    F: Fn(&mut ConsolidateResult) -> Result<(), ConsolidateError> + Send + Sync,
+{
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        self(result)
+    }
+}
+
+impl fmt::Debug for dyn PostProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PostProcessor")
+    }
+}
+
+/// Removes properties from every entity (e.g. personal contact details)
+#[derive(Debug, Clone)]
+pub struct Redact {
+    properties: Vec<String>,
+}
+
+impl Redact {
+    pub fn new(properties: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            properties: properties.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl PostProcessor for Redact {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        for obj in result.graph.iter_mut().filter_map(Value::as_object_mut) {
+            for property in &self.properties {
+                obj.remove(property);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A new @id for entities of the consolidated graph, given their current one
+///
+/// Any `Fn(&str) -> Option<String>` closure is an id rewriter.
+pub trait IdRewriter: Send + Sync {
+    /// The new @id, or `None` to keep `id`
+    fn rewrite(&self, id: &str) -> Option<String>;
+}
+
+impl<F> IdRewriter for F
+where
+    F: Fn(&str) -> Option<String> + Send + Sync,
+{
+    fn rewrite(&self, id: &str) -> Option<String> {
+        self(id)
+    }
+}
+
+impl fmt::Debug for dyn IdRewriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IdRewriter")
+    }
+}
+
+/// Replaces a prefix of @ids, e.g. to move entities to another base URL
+#[derive(Debug, Clone)]
+pub struct ReplaceIdPrefix {
+    from: String,
+    to: String,
+}
+
+impl ReplaceIdPrefix {
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> Self {
+        Self {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+}
+
+impl IdRewriter for ReplaceIdPrefix {
+    fn rewrite(&self, id: &str) -> Option<String> {
+        id.strip_prefix(&self.from)
+            .map(|rest| format!("{}{}", self.to, rest))
+    }
+}
+
+/// Rewrites the @ids of the consolidated graph, and all references to them
+///
+/// Each entity's @id is rewritten by the first rewriter returning a new one.
+/// Entity origins and conflicts follow along, while references to ids
+/// outside the graph are left alone. Rewriting two entities to the same @id
+/// fails.
+#[derive(Debug, Clone)]
+pub struct RewriteIds {
+    rewriters: Vec<Arc<dyn IdRewriter>>,
+}
+
+impl RewriteIds {
+    pub fn new(rewriters: impl IntoIterator<Item = Arc<dyn IdRewriter>>) -> Self {
+        Self {
+            rewriters: rewriters.into_iter().collect(),
+        }
+    }
+}
+
+impl PostProcessor for RewriteIds {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        let mut id_map: HashMap<String, String> = HashMap::new();
+        for id in result.graph.iter().filter_map(extract_id) {
+            if let Some(new_id) = self.rewriters.iter().find_map(|r| r.rewrite(id)) {
+                id_map.insert(id.to_string(), new_id);
+            }
+        }
+        if id_map.is_empty() {
+            return Ok(());
+        }
+        let mut ids: HashMap<&str, &str> = HashMap::new();
+        for id in result.graph.iter().filter_map(extract_id) {
+            let new_id = id_map.get(id).map_or(id, String::as_str);
+            if let Some(other) = ids.insert(new_id, id).filter(|&other| other != id) {
+                return Err(ConsolidateError::InvalidStructure(format!(
+                    "Id rewriters map both '{}' and '{}' to '{}'",
+                    other, id, new_id
+                )));
+            }
+        }
+        for entity in &mut result.graph {
+            rewrite_references(entity, &id_map);
+        }
+        for origin in &mut result.origins {
+            if let Some(new_id) = id_map.get(&origin.id) {
+                origin.id.clone_from(new_id);
+            }
+        }
+        for conflict in &mut result.conflicts {
+            if let Some(new_id) = id_map.get(&conflict.id) {
+                conflict.id.clone_from(new_id);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Adds term definitions to the @context (for custom vocabulary)
+#[derive(Debug, Clone)]
+pub struct ExtendContext {
+    terms: Map<String, Value>,
+}
+
+impl ExtendContext {
+    pub fn new(terms: Map<String, Value>) -> Self {
+        Self { terms }
+    }
+}
+
+impl PostProcessor for ExtendContext {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        let context = result.context.take();
+        let mut entries = match context {
+            Value::Array(entries) => entries,
+            other => vec![other],
+        };
+        // Extend the last embedded context, or add one
+        match entries.last_mut() {
+            Some(Value::Object(last)) => {
+                last.extend(self.terms.clone());
+            }
+            _ => entries.push(Value::Object(self.terms.clone())),
+        }
+        result.context = Value::Array(entries);
+        Ok(())
+    }
+}
+
+/// Aggregates the coverage of all source crates onto the consolidated root
+///
+/// `spatialCoverage` becomes the union of all crates' places, and
+/// `temporalCoverage` the smallest ISO 8601 date or interval spanning all
+/// of theirs ("2019/2021", with ".." for open ends). Temporal coverage is
+/// left as is, with a warning, when a crate's isn't a date or interval.
+#[derive(Debug, Clone)]
+pub struct AggregateCoverage {
+    spatial: bool,
+    temporal: bool,
+}
+
+impl AggregateCoverage {
+    /// Aggregate both spatial and temporal coverage
+    pub fn new() -> Self {
+        Self {
+            spatial: true,
+            temporal: true,
+        }
+    }
+
+    /// Set whether to aggregate `spatialCoverage`
+    pub fn with_spatial(mut self, spatial: bool) -> Self {
+        self.spatial = spatial;
+        self
+    }
+
+    /// Set whether to aggregate `temporalCoverage`
+    pub fn with_temporal(mut self, temporal: bool) -> Self {
+        self.temporal = temporal;
+        self
+    }
+}
+
+impl Default for AggregateCoverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PostProcessor for AggregateCoverage {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        // The root first, then the roots of all subcrates (their folders)
+        let mut root_id = ROOT_ENTITY_ID.to_string();
+        let mut subcrate_ids = Vec::new();
+        for origin in result.origins.iter() {
+            if origin.original_id != ROOT_ENTITY_ID {
+                continue;
+            }
+            if origin.namespace.is_empty() {
+                root_id = origin.id.clone();
+            } else if !subcrate_ids.contains(&origin.id) {
+                subcrate_ids.push(origin.id.clone());
+            }
+        }
+        let Some(root_index) = result
+            .graph
+            .iter()
+            .position(|e| extract_id(e) == Some(root_id.as_str()))
+        else {
+            return Ok(());
+        };
+        let mut sources = vec![root_index];
+        sources.extend(result.graph.iter().enumerate().filter_map(|(i, e)| {
+            let id = extract_id(e)?;
+            (i != root_index && subcrate_ids.iter().any(|s| s == id)).then_some(i)
+        }));
+
+        if self.spatial {
+            let mut places: Vec<Value> = Vec::new();
+            for &i in &sources {
+                for place in values(&result.graph[i]["spatialCoverage"]) {
+                    if !places.contains(place) {
+                        places.push(place.clone());
+                    }
+                }
+            }
+            set_values(&mut result.graph[root_index], "spatialCoverage", places);
+        }
+
+        if self.temporal {
+            let mut span: Option<Interval> = None;
+            let mut invalid = None;
+            for &i in &sources {
+                for value in values(&result.graph[i]["temporalCoverage"]) {
+                    match value.as_str().and_then(Interval::parse) {
+                        Some(interval) => {
+                            span = Some(match span {
+                                Some(span) => span.union(interval),
+                                None => interval,
+                            })
+                        }
+                        None => {
+                            invalid.get_or_insert((i, value.to_string()));
+                        }
+                    }
+                }
+            }
+            match (invalid, span) {
+                (Some((i, value)), _) => result.warnings.push(format!(
+                    "Temporal coverage {} of {} isn't an ISO 8601 date or interval, \
+                     not aggregated",
+                    value,
+                    extract_id(&result.graph[i]).unwrap_or_default()
+                )),
+                (None, Some(span)) => {
+                    result.graph[root_index]["temporalCoverage"] = Value::String(span.to_string())
+                }
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The values of a property, single or in an array
+fn values(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Null => vec![],
+        Value::Array(values) => values.iter().collect(),
+        value => vec![value],
+    }
+}
+
+/// Set a property to `values`, single or as an array (none leave it unset)
+fn set_values(entity: &mut Value, property: &str, mut values: Vec<Value>) {
+    let Some(obj) = entity.as_object_mut() else {
+        return;
+    };
+    match values.len() {
+        0 => {}
+        1 => {
+            obj.insert(property.to_string(), values.remove(0));
+        }
+        _ => {
+            obj.insert(property.to_string(), Value::Array(values));
+        }
+    }
+}
+
+/// An ISO 8601 date or interval of dates, None bounds being open
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Interval {
+    start: Option<String>,
+    end: Option<String>,
+}
+
+impl Interval {
+    /// Parse a date ("2020-05") or interval ("2019/2021-06", "2019/..")
+    fn parse(value: &str) -> Option<Self> {
+        let bound = |bound: &str| match bound {
+            "" | ".." => Some(None),
+            date if is_date(date) => Some(Some(date.to_string())),
+            _ => None,
+        };
+        match value.trim().split_once('/') {
+            Some((start, end)) => Some(Self {
+                start: bound(start)?,
+                end: bound(end)?,
+            }),
+            None if is_date(value.trim()) => Some(Self {
+                start: Some(value.trim().to_string()),
+                end: Some(value.trim().to_string()),
+            }),
+            None => None,
+        }
+    }
+
+    /// The smallest interval spanning both
+    fn union(self, other: Self) -> Self {
+        let start = match (self.start, other.start) {
+            (Some(a), Some(b)) => Some(if date_key(&b, false) < date_key(&a, false) {
+                b
+            } else {
+                a
+            }),
+            _ => None,
+        };
+        let end = match (self.end, other.end) {
+            (Some(a), Some(b)) => Some(if date_key(&b, true) > date_key(&a, true) {
+                b
+            } else {
+                a
+            }),
+            _ => None,
+        };
+        Self { start, end }
+    }
+}
+
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.start, &self.end) {
+            (Some(start), Some(end)) if start == end => f.write_str(start),
+            (start, end) => write!(
+                f,
+                "{}/{}",
+                start.as_deref().unwrap_or(".."),
+                end.as_deref().unwrap_or("..")
+            ),
+        }
+    }
+}
+
+/// Check if `value` is an ISO 8601 date, e.g. "2020", "2020-05-01" or
+/// "2020-05-01T12:00:00Z"
+fn is_date(value: &str) -> bool {
+    let date = value.split_once('T').map_or(value, |(date, _)| date);
+    let parts: Vec<&str> = date.split('-').collect();
+    parts.len() <= 3
+        && parts.iter().enumerate().all(|(i, part)| {
+            part.len() == if i == 0 { 4 } else { 2 } && part.bytes().all(|b| b.is_ascii_digit())
+        })
+}
+
+/// A key ordering dates of any precision by their first (or for `end`, last)
+/// instant
+fn date_key(date: &str, end: bool) -> String {
+    let (day, time) = date.split_once('T').unwrap_or((date, ""));
+    let padding = match (day.len(), end) {
+        (4, false) => "-01-01",
+        (7, false) => "-01",
+        (4, true) => "-12-31",
+        (7, true) => "-31",
+        _ => "",
+    };
+    match (time, end) {
+        ("", true) => format!("{}{}T~", day, padding),
+        _ => format!("{}{}T{}", day, padding, time),
+    }
+}
+
+/// Run post-processors in order
+pub(crate) fn run_post_processors(
+    processors: &[std::sync::Arc<dyn PostProcessor>],
+    result: &mut ConsolidateResult,
+) -> Result<(), ConsolidateError> {
+    for processor in processors {
+        processor.process(result)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{
+        consolidate, ConsolidateInput, ConsolidateOptions, NoOpLoader, SubcrateLoader,
+    };
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn graph() -> Vec<Value> {
+        vec![
+            json!({
+                "@id": "ro-crate-metadata.json",
+                "@type": "CreativeWork",
+                "about": {"@id": "./"},
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }),
+            json!({"@id": "./", "@type": "Dataset", "name": "Root"}),
+            json!({
+                "@id": "https://orcid.org/0000-0001",
+                "@type": "Person",
+                "name": "Alice",
+                "email": "alice@example.org"
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_post_processors_run_in_order() {
+        let options = ConsolidateOptions::default()
+            .with_post_processor(Redact::new(["email"]))
+            .with_post_processor(ExtendContext::new(
+                json!({"lab": "https://example.org/lab#"})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ))
+            .with_post_processor(|result: &mut ConsolidateResult| {
+                // Sees the redacted graph
+                assert!(result.graph.iter().all(|e| e.get("email").is_none()));
+                result
+                    .graph
+                    .push(json!({"@id": "#note", "@type": "lab:Note"}));
+                Ok(())
+            });
+
+        let result = consolidate(ConsolidateInput::Single(graph()), &NoOpLoader, &options).unwrap();
+        let person = result
+            .graph
+            .iter()
+            .find(|e| e["@id"] == "https://orcid.org/0000-0001")
+            .unwrap();
+        assert_eq!(person["name"], "Alice");
+        assert!(person.get("email").is_none());
+        assert_eq!(result.context[1]["lab"], "https://example.org/lab#");
+        assert_eq!(result.stats.total_entities, result.graph.len());
+    }
+
+    #[test]
+    fn test_post_processor_error() {
+        let options =
+            ConsolidateOptions::default().with_post_processor(|_: &mut ConsolidateResult| {
+                Err(ConsolidateError::InvalidStructure("rejected".to_string()))
+            });
+        let err =
+            consolidate(ConsolidateInput::Single(graph()), &NoOpLoader, &options).unwrap_err();
+        assert_eq!(err.code(), "invalid_structure");
+    }
+
+    #[test]
+    fn test_rewrite_ids() {
+        let mut input = graph();
+        input[1]["author"] = json!({"@id": "https://orcid.org/0000-0001"});
+        input[1]["license"] = json!({"@id": "https://orcid.org/elsewhere"});
+        let rewrite = RewriteIds::new([
+            Arc::new(ReplaceIdPrefix::new("https://orcid.org/", "orcid:")) as Arc<dyn IdRewriter>,
+            Arc::new(|id: &str| (id == "./").then(|| "https://example.org/".to_string())),
+        ]);
+        let options = ConsolidateOptions::default().with_post_processor(rewrite);
+
+        let result = consolidate(ConsolidateInput::Single(input), &NoOpLoader, &options).unwrap();
+        assert_eq!(
+            result.graph[0]["about"],
+            json!({"@id": "https://example.org/"})
+        );
+        assert_eq!(result.graph[1]["@id"], "https://example.org/");
+        assert_eq!(result.graph[1]["author"], json!({"@id": "orcid:0000-0001"}));
+        assert_eq!(
+            result.graph[1]["license"],
+            json!({"@id": "https://orcid.org/elsewhere"})
+        );
+        assert_eq!(result.graph[2]["@id"], "orcid:0000-0001");
+        assert!(result.origins.iter().any(|o| o.id == "orcid:0000-0001"));
+
+        let collide =
+            RewriteIds::new([Arc::new(|_: &str| Some("./".to_string())) as Arc<dyn IdRewriter>]);
+        let options = ConsolidateOptions::default().with_post_processor(collide);
+        let err =
+            consolidate(ConsolidateInput::Single(graph()), &NoOpLoader, &options).unwrap_err();
+        assert!(err.to_string().contains("Id rewriters map both"));
+    }
+
+    /// Loader serving subcrates by id
+    struct MapLoader(HashMap<&'static str, Vec<Value>>);
+
+    impl SubcrateLoader for MapLoader {
+        fn load(
+            &self,
+            subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            self.0
+                .get(subcrate_id)
+                .cloned()
+                .ok_or_else(|| ConsolidateError::InvalidStructure(subcrate_id.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_aggregate_coverage() {
+        let subcrate = |place: &str, time: &str| {
+            vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({
+                    "@id": "./",
+                    "@type": "Dataset",
+                    "spatialCoverage": {"@id": place},
+                    "temporalCoverage": time
+                }),
+            ]
+        };
+        let loader = MapLoader(HashMap::from([
+            ("a/", subcrate("https://www.geonames.org/2950159", "2019")),
+            (
+                "b/",
+                subcrate("https://www.geonames.org/2867714", "2020-05-01/.."),
+            ),
+        ]));
+        let mut graph = graph();
+        graph[1]["hasPart"] = json!([{"@id": "a/"}, {"@id": "b/"}]);
+        graph[1]["spatialCoverage"] = json!({"@id": "https://www.geonames.org/2950159"});
+        graph[1]["temporalCoverage"] = json!("2020-03/2020-06-15");
+        for id in ["a/", "b/"] {
+            graph.push(json!({
+                "@id": id,
+                "@type": "Dataset",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }));
+        }
+        let root = |graph: &[Value]| graph.iter().find(|e| e["@id"] == "./").unwrap().clone();
+
+        let options = ConsolidateOptions::default().with_post_processor(AggregateCoverage::new());
+        let result =
+            consolidate(ConsolidateInput::Single(graph.clone()), &loader, &options).unwrap();
+        assert_eq!(
+            root(&result.graph)["spatialCoverage"],
+            json!([
+                {"@id": "https://www.geonames.org/2950159"},
+                {"@id": "https://www.geonames.org/2867714"}
+            ])
+        );
+        assert_eq!(root(&result.graph)["temporalCoverage"], "2019/..");
+
+        let options = ConsolidateOptions::default()
+            .with_post_processor(AggregateCoverage::new().with_temporal(false));
+        let result =
+            consolidate(ConsolidateInput::Single(graph.clone()), &loader, &options).unwrap();
+        assert_eq!(
+            root(&result.graph)["temporalCoverage"],
+            "2020-03/2020-06-15"
+        );
+
+        // Coverage that isn't a date or interval is kept
+        graph[1]["temporalCoverage"] = json!("Spring 2020");
+        let options = ConsolidateOptions::default().with_post_processor(AggregateCoverage::new());
+        let result = consolidate(ConsolidateInput::Single(graph), &loader, &options).unwrap();
+        assert_eq!(root(&result.graph)["temporalCoverage"], "Spring 2020");
+        assert!(result
+            .warnings
+            .iter()
+            .any(|w| w.contains("\"Spring 2020\"")));
+    }
+
+    #[test]
+    fn test_interval_union() {
+        let union = |a: &str, b: &str| {
+            Interval::parse(a)
+                .unwrap()
+                .union(Interval::parse(b).unwrap())
+                .to_string()
+        };
+        assert_eq!(union("2020", "2020-05-01"), "2020");
+        assert_eq!(union("2020-12", "2021-01-15"), "2020-12/2021-01-15");
+        assert_eq!(
+            union("2021", "2019-01-01T10:00:00Z/2020"),
+            "2019-01-01T10:00:00Z/2021"
+        );
+        assert_eq!(union("../2019", "2020"), "../2020");
+        assert!(Interval::parse("May 2020").is_none());
+        assert!(Interval::parse("2020-5").is_none());
+    }
+
+    #[test]
+    fn test_extend_plain_context() {
+        let mut result = consolidate(
+            ConsolidateInput::Single(graph()),
+            &NoOpLoader,
+            &ConsolidateOptions {
+                extend_context: false,
+                ..ConsolidateOptions::default()
+            },
+        )
+        .unwrap();
+        let mut terms = Map::new();
+        terms.insert("lab".to_string(), json!("https://example.org/lab#"));
+        ExtendContext::new(terms).process(&mut result).unwrap();
+        assert_eq!(
+            result.context,
+            json!([
+                "https://w3id.org/ro/crate/1.1/context",
+                {"lab": "https://example.org/lab#"}
+            ])
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Instrumented consolidation
+//!
+//! [`profile`] runs a consolidation with per-phase counters enabled, so the
+//! cost of loading, collecting, rewriting, merging and assembling can be
+//! compared across inputs and implementations.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+use crate::consolidate::{
+    consolidate_with_profiler, ConsolidateInput, ConsolidateOptions, ConsolidateResult,
+    SubcrateLoader,
+};
+use crate::error::ConsolidateError;
+
+/// A phase of consolidation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Loading subcrate graphs through the loader
+    Load,
+    /// Sorting a crate's entities into local, shared, root and descriptor
+    Collect,
+    /// Building id maps and rewriting local entities and references
+    Rewrite,
+    /// Union-merging shared entities by @id
+    Merge,
+    /// Building, rebasing and normalizing the final graph
+    Assemble,
+}
+
+impl Phase {
+    /// All phases in pipeline order
+    pub const ALL: [Phase; 5] = [
+        Phase::Load,
+        Phase::Collect,
+        Phase::Rewrite,
+        Phase::Merge,
+        Phase::Assemble,
+    ];
+
+    /// Short name of the phase
+    pub fn name(&self) -> &'static str {
+        match self {
+            Phase::Load => "load",
+            Phase::Collect => "collect",
+            Phase::Rewrite => "rewrite",
+            Phase::Merge => "merge",
+            Phase::Assemble => "assemble",
+        }
+    }
+}
+
+/// Counters for one phase, summed over all crates
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PhaseProfile {
+    pub phase: Phase,
+    /// Number of times the phase ran (e.g. once per crate)
+    pub runs: usize,
+    /// Entities the phase processed
+    pub entities: usize,
+    /// Estimated heap allocations: JSON strings, arrays, objects and keys
+    /// produced by the phase
+    pub allocations: usize,
+    /// Wall-clock time spent in the phase
+    pub duration: Duration,
+}
+
+/// Per-phase counters of a profiled consolidation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Profile {
+    /// One entry per phase, in pipeline order
+    pub phases: Vec<PhaseProfile>,
+    /// Wall-clock time of the whole consolidation
+    pub total: Duration,
+}
+
+impl Profile {
+    /// Counters of a single phase
+    pub fn phase(&self, phase: Phase) -> &PhaseProfile {
+        self.phases
+            .iter()
+            .find(|p| p.phase == phase)
+            .expect("profile has an entry for every phase")
+    }
+}
+
+/// Collects phase counters during consolidation (a no-op unless enabled)
+#[derive(Debug)]
+pub(crate) struct Profiler {
+    phases: Option<Vec<PhaseProfile>>,
+}
+
+impl Profiler {
+    pub(crate) fn disabled() -> Self {
+        Self { phases: None }
+    }
+
+    pub(crate) fn enabled() -> Self {
+        Self {
+            phases: Some(
+                Phase::ALL
+                    .iter()
+                    .map(|&phase| PhaseProfile {
+                        phase,
+                        runs: 0,
+                        entities: 0,
+                        allocations: 0,
+                        duration: Duration::ZERO,
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    /// A fresh profiler for a worker thread, enabled if this one is
+    pub(crate) fn fork(&self) -> Self {
+        if self.is_enabled() {
+            Self::enabled()
+        } else {
+            Self::disabled()
+        }
+    }
+
+    /// Add the counters of a worker's profiler
+    pub(crate) fn absorb(&mut self, other: Profiler) {
+        let (Some(phases), Some(other)) = (self.phases.as_mut(), other.phases) else {
+            return;
+        };
+        for (entry, other) in phases.iter_mut().zip(other) {
+            entry.runs += other.runs;
+            entry.entities += other.entities;
+            entry.allocations += other.allocations;
+            entry.duration += other.duration;
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.phases.is_some()
+    }
+
+    /// Start timing a phase
+    pub(crate) fn start(&self) -> Option<Instant> {
+        self.phases.as_ref().map(|_| Instant::now())
+    }
+
+    /// Record a phase run started with [`start`](Self::start)
+    ///
+    /// `allocations` is only evaluated when profiling is enabled.
+    pub(crate) fn record(
+        &mut self,
+        phase: Phase,
+        started: Option<Instant>,
+        entities: usize,
+        allocations: impl FnOnce() -> usize,
+    ) {
+        let (Some(phases), Some(started)) = (self.phases.as_mut(), started) else {
+            return;
+        };
+        let duration = started.elapsed();
+        let allocations = allocations();
+        let entry = &mut phases[phase as usize];
+        entry.runs += 1;
+        entry.entities += entities;
+        entry.allocations += allocations;
+        entry.duration += duration;
+    }
+
+    fn finish(self, total: Duration) -> Profile {
+        Profile {
+            phases: self.phases.unwrap_or_default(),
+            total,
+        }
+    }
+}
+
+/// Number of heap allocations backing a JSON value
+pub fn count_allocations(value: &Value) -> usize {
+    match value {
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+        Value::String(s) => usize::from(!s.is_empty()),
+        Value::Array(arr) => 1 + arr.iter().map(count_allocations).sum::<usize>(),
+        Value::Object(obj) => {
+            1 + obj
+                .iter()
+                .map(|(key, v)| usize::from(!key.is_empty()) + count_allocations(v))
+                .sum::<usize>()
+        }
+    }
+}
+
+/// Run a consolidation with instrumentation enabled
+///
+/// Returns the same result as [`consolidate`](crate::consolidate::consolidate)
+/// together with per-phase counters.
+pub fn profile(
+    input: ConsolidateInput,
+    loader: &dyn SubcrateLoader,
+    options: &ConsolidateOptions,
+) -> Result<(ConsolidateResult, Profile), ConsolidateError> {
+    let started = Instant::now();
+    let mut profiler = Profiler::enabled();
+    let result = consolidate_with_profiler(input, loader, options, &mut profiler)?;
+    Ok((result, profiler.finish(started.elapsed())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{consolidate, MergeCrate, NoOpLoader};
+    use serde_json::json;
+
+    fn crate_graph(name: &str) -> Vec<Value> {
+        vec![
+            json!({
+                "@id": "ro-crate-metadata.json",
+                "@type": "CreativeWork",
+                "about": {"@id": "./"},
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }),
+            json!({"@id": "./", "@type": "Dataset", "name": name, "hasPart": [{"@id": "./data.csv"}]}),
+            json!({"@id": "./data.csv", "@type": "File"}),
+            json!({"@id": "https://orcid.org/0000-0001", "@type": "Person", "name": "Alice"}),
+        ]
+    }
+
+    fn merge_input() -> ConsolidateInput {
+        ConsolidateInput::Merge {
+            main: crate_graph("Main"),
+            others: vec![MergeCrate {
+                graph: crate_graph("Other"),
+                folder_id: "./other/".to_string(),
+                name: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_profile() {
+        let options = ConsolidateOptions::default();
+        let (result, profile) = profile(merge_input(), &NoOpLoader, &options).unwrap();
+
+        // Profiling does not change the result
+        let plain = consolidate(merge_input(), &NoOpLoader, &options).unwrap();
+        assert_eq!(result.graph, plain.graph);
+
+        assert_eq!(profile.phases.len(), Phase::ALL.len());
+        let collect = profile.phase(Phase::Collect);
+        assert_eq!(collect.runs, 2);
+        assert_eq!(collect.entities, 8);
+        let rewrite = profile.phase(Phase::Rewrite);
+        assert_eq!(rewrite.runs, 2);
+        // data.csv and the root of each crate
+        assert_eq!(rewrite.entities, 4);
+        let merge = profile.phase(Phase::Merge);
+        assert_eq!(merge.entities, 2);
+        assert!(merge.allocations > 0);
+        assert_eq!(profile.phase(Phase::Load).runs, 0);
+        assert_eq!(profile.phase(Phase::Assemble).entities, result.graph.len());
+    }
+
+    #[test]
+    fn test_count_allocations() {
+        assert_eq!(count_allocations(&json!(1)), 0);
+        assert_eq!(count_allocations(&json!("a")), 1);
+        // object + 2 keys + id string + array + 1 string
+        assert_eq!(count_allocations(&json!({"@id": "#a", "k": ["x"]})), 6);
+    }
+
+    #[test]
+    fn test_disabled_profiler_records_nothing() {
+        let mut profiler = Profiler::disabled();
+        let started = profiler.start();
+        profiler.record(Phase::Merge, started, 10, || unreachable!());
+        assert!(!profiler.is_enabled());
+        assert!(profiler.finish(Duration::ZERO).phases.is_empty());
+    }
+}
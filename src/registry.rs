@@ -0,0 +1,519 @@
+//! Components selected by name
+//!
+//! A [`Registry`] maps names to factories of subcrate loaders, id rewriters,
+//! merge policies and post-processors, so config files and command lines can
+//! choose components by name, e.g. `--loader url --merge-policy latest-dates
+//! --post redact=email:telephone,normalize-licenses`.
+//! Binaries and services embedding the library register their own
+//! components next to the built-in ones:
+//!
+//! ```ignore
+//! let registry = Registry::with_builtins()
+//!     .with_post_processor("stamp", |_arg: Option<&str>| {
+//!         Ok(Arc::new(Stamp::new()) as Arc<dyn PostProcessor>)
+//!     });
+//! let processors = registry.post_processors(["redact=email", "stamp"])?;
+//! ```
+//!
+//! A post-processor is named as `NAME` or `NAME=ARG`, the argument being
+//! passed to its factory. Built-in post-processors:
+//!
+//! - `redact=PROPERTY:...`: [`Redact`] the properties
+//! - `aggregate-coverage[=spatial|temporal]`: [`AggregateCoverage`]
+//! - `canonical-entities[=FILE]`: the built-in [`EntityLibrary`], and that
+//!   of the JSON file
+//! - `normalize-licenses`: [`NormalizeLicenses`]
+//! - `consolidate-citations`, `summarize-citations`: [`ConsolidateCitations`]
+//! - `mention-key-entities[=TYPE:...]`: [`MentionKeyEntities`]
+//! - `entity-script=FILE`: the `EntityScript` in the file, with the
+//!   `scripting` feature
+//!
+//! Id rewriters and merge policies are named the same way. Built-in id
+//! rewriters, run by a [`RewriteIds`] post-processor:
+//!
+//! - `replace-prefix=FROM=TO`: [`ReplaceIdPrefix`]
+//! - `base=URL`: rebase the root and relative ids onto URL
+//!
+//! Built-in merge policies are the conflict strategies `union`,
+//! `prefer-main`, `prefer-incoming` and `error`, and `latest-dates`, which
+//! unions but keeps the latest `dateModified` and the earliest
+//! `dateCreated` and `datePublished`.
+//!
+//! Built-in loaders, made for the source being consolidated: `url` (a
+//! [`UrlLoader`] for the source's metadata URL), `s3` (an [`S3Loader`] for an
+//! `s3://` source, with credentials from the environment) and `none`
+//! ([`NoOpLoader`]). They fetch with [`UrlLoaderOptions::DEFAULT`] unless
+//! given others with [`Registry::with_url_options`].
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::citation::ConsolidateCitations;
+use crate::consolidate::{NoOpLoader, SubcrateLoader, UrlLoader};
+use crate::error::ConsolidateError;
+use crate::id::rebase_id;
+use crate::loader::UrlLoaderOptions;
+use crate::mentions::MentionKeyEntities;
+use crate::merge::{ConflictStrategy, MergePolicy, PropertyStrategy};
+use crate::postprocess::{
+    AggregateCoverage, IdRewriter, PostProcessor, Redact, ReplaceIdPrefix, RewriteIds,
+};
+use crate::s3::{split_s3_url, S3Client, S3Loader};
+#[cfg(feature = "scripting")]
+use crate::script::EntityScript;
+use crate::wellknown::{EntityLibrary, NormalizeLicenses};
+
+/// Makes a subcrate loader for the source being consolidated
+pub type LoaderFactory =
+    Arc<dyn Fn(&str) -> Result<Box<dyn SubcrateLoader>, ConsolidateError> + Send + Sync>;
+
+/// Makes an id rewriter, given the argument it was named with
+pub type IdRewriterFactory =
+    Arc<dyn Fn(Option<&str>) -> Result<Arc<dyn IdRewriter>, ConsolidateError> + Send + Sync>;
+
+/// Makes a merge policy, given the argument it was named with
+pub type MergePolicyFactory =
+    Arc<dyn Fn(Option<&str>) -> Result<MergePolicy, ConsolidateError> + Send + Sync>;
+
+/// Makes a post-processor, given the argument it was named with
+pub type PostProcessorFactory =
+    Arc<dyn Fn(Option<&str>) -> Result<Arc<dyn PostProcessor>, ConsolidateError> + Send + Sync>;
+
+/// Loaders, id rewriters, merge policies and post-processors by name
+///
+/// Registering a name again replaces the component registered before.
+#[derive(Clone, Default)]
+pub struct Registry {
+    loaders: BTreeMap<String, LoaderFactory>,
+    id_rewriters: BTreeMap<String, IdRewriterFactory>,
+    merge_policies: BTreeMap<String, MergePolicyFactory>,
+    post_processors: BTreeMap<String, PostProcessorFactory>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("loaders", &self.loaders.keys().collect::<Vec<_>>())
+            .field(
+                "id_rewriters",
+                &self.id_rewriters.keys().collect::<Vec<_>>(),
+            )
+            .field(
+                "merge_policies",
+                &self.merge_policies.keys().collect::<Vec<_>>(),
+            )
+            .field(
+                "post_processors",
+                &self.post_processors.keys().collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl Registry {
+    /// Registry without any components
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry of the built-in components
+    pub fn with_builtins() -> Self {
+        Self::new()
+            .with_url_options(UrlLoaderOptions::DEFAULT)
+            .with_loader("none", |_: &str| {
+                Ok(Box::new(NoOpLoader) as Box<dyn SubcrateLoader>)
+            })
+            .with_id_rewriter("replace-prefix", |arg: Option<&str>| {
+                let (from, to) = arg
+                    .and_then(|arg| arg.split_once('='))
+                    .filter(|(from, _)| !from.is_empty())
+                    .ok_or_else(|| {
+                        ConsolidateError::InvalidStructure(
+                            "replace-prefix needs the prefixes, e.g. \
+                             replace-prefix=https://old.example.org/=https://example.org/"
+                                .to_string(),
+                        )
+                    })?;
+                Ok(Arc::new(ReplaceIdPrefix::new(from, to)) as Arc<dyn IdRewriter>)
+            })
+            .with_id_rewriter("base", |arg: Option<&str>| {
+                let base = arg.filter(|arg| !arg.is_empty()).ok_or_else(|| {
+                    ConsolidateError::InvalidStructure(
+                        "base needs the URL, e.g. base=https://example.org/crate/".to_string(),
+                    )
+                })?;
+                let base = base.to_string();
+                let rebase = move |id: &str| match rebase_id(id, &base) {
+                    Cow::Owned(rebased) => Some(rebased),
+                    Cow::Borrowed(_) => None,
+                };
+                Ok(Arc::new(rebase) as Arc<dyn IdRewriter>)
+            })
+            .with_merge_policy("union", |_: Option<&str>| {
+                Ok(MergePolicy::new(ConflictStrategy::Union))
+            })
+            .with_merge_policy("prefer-main", |_: Option<&str>| {
+                Ok(MergePolicy::new(ConflictStrategy::PreferMain))
+            })
+            .with_merge_policy("prefer-incoming", |_: Option<&str>| {
+                Ok(MergePolicy::new(ConflictStrategy::PreferIncoming))
+            })
+            .with_merge_policy("error", |_: Option<&str>| {
+                Ok(MergePolicy::new(ConflictStrategy::Error))
+            })
+            .with_merge_policy("latest-dates", |_: Option<&str>| {
+                Ok(MergePolicy::new(ConflictStrategy::Union)
+                    .with_property("dateModified", PropertyStrategy::Latest)
+                    .with_property("dateCreated", PropertyStrategy::Earliest)
+                    .with_property("datePublished", PropertyStrategy::Earliest))
+            })
+            .with_post_processor("redact", |arg: Option<&str>| {
+                let properties = arg.filter(|arg| !arg.is_empty()).ok_or_else(|| {
+                    ConsolidateError::InvalidStructure(
+                        "redact needs the properties to remove, e.g. redact=email:telephone"
+                            .to_string(),
+                    )
+                })?;
+                Ok(Arc::new(Redact::new(properties.split(':'))) as Arc<dyn PostProcessor>)
+            })
+            .with_post_processor("aggregate-coverage", |arg: Option<&str>| {
+                let (spatial, temporal) = match arg {
+                    None | Some("all") => (true, true),
+                    Some("spatial") => (true, false),
+                    Some("temporal") => (false, true),
+                    Some(arg) => {
+                        return Err(ConsolidateError::InvalidStructure(format!(
+                            "Unknown coverage '{}', expected spatial, temporal or all",
+                            arg
+                        )))
+                    }
+                };
+                let coverage = AggregateCoverage::new()
+                    .with_spatial(spatial)
+                    .with_temporal(temporal);
+                Ok(Arc::new(coverage) as Arc<dyn PostProcessor>)
+            })
+            .with_post_processor("canonical-entities", |arg: Option<&str>| {
+                let library = match arg {
+                    Some(path) => EntityLibrary::builtin().with_file(Path::new(path))?,
+                    None => EntityLibrary::builtin(),
+                };
+                Ok(Arc::new(library) as Arc<dyn PostProcessor>)
+            })
+            .with_post_processor("normalize-licenses", |_: Option<&str>| {
+                let normalize = NormalizeLicenses::new(EntityLibrary::builtin());
+                Ok(Arc::new(normalize) as Arc<dyn PostProcessor>)
+            })
+            .with_post_processor("consolidate-citations", |_: Option<&str>| {
+                Ok(Arc::new(ConsolidateCitations::new()) as Arc<dyn PostProcessor>)
+            })
+            .with_post_processor("summarize-citations", |_: Option<&str>| {
+                let citations = ConsolidateCitations::new().with_summary(true);
+                Ok(Arc::new(citations) as Arc<dyn PostProcessor>)
+            })
+            .with_post_processor("mention-key-entities", |arg: Option<&str>| {
+                let mention = match arg {
+                    Some(types) => MentionKeyEntities::new().with_types(types.split(':')),
+                    None => MentionKeyEntities::new(),
+                };
+                Ok(Arc::new(mention) as Arc<dyn PostProcessor>)
+            })
+            .with_builtin_scripts()
+    }
+
+    /// Register the built-in `entity-script` post-processor
+    #[cfg(feature = "scripting")]
+    fn with_builtin_scripts(self) -> Self {
+        self.with_post_processor("entity-script", |arg: Option<&str>| {
+            let path = arg.ok_or_else(|| {
+                ConsolidateError::InvalidStructure(
+                    "entity-script needs the script file, e.g. entity-script=curate.rhai"
+                        .to_string(),
+                )
+            })?;
+            let script = EntityScript::from_file(Path::new(path))?;
+            Ok(Arc::new(script) as Arc<dyn PostProcessor>)
+        })
+    }
+
+    /// Without the `scripting` feature, there are no scripts to register
+    #[cfg(not(feature = "scripting"))]
+    fn with_builtin_scripts(self) -> Self {
+        self
+    }
+
+    /// Register the built-in `url` and `s3` loaders, fetching with `options`
+    pub fn with_url_options(self, options: UrlLoaderOptions) -> Self {
+        let s3_options = options.clone();
+        self.with_loader("url", move |source: &str| {
+            let loader = UrlLoader::from_metadata_url(source).with_options(options.clone());
+            Ok(Box::new(loader) as Box<dyn SubcrateLoader>)
+        })
+        .with_loader("s3", move |source: &str| {
+            let (bucket, key) = split_s3_url(source).ok_or_else(|| {
+                ConsolidateError::InvalidStructure(format!(
+                    "The s3 loader needs an s3:// source, got '{}'",
+                    source
+                ))
+            })?;
+            let client = S3Client::from_env().with_url_options(s3_options.clone());
+            let loader = S3Loader::new(client, bucket, key)?;
+            Ok(Box::new(loader) as Box<dyn SubcrateLoader>)
+        })
+    }
+
+    /// Register a loader under `name`
+    pub fn with_loader<F>(mut self, name: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(&str) -> Result<Box<dyn SubcrateLoader>, ConsolidateError> + Send + Sync + 'static,
+    {
+        self.loaders.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// Register an id rewriter under `name`
+    pub fn with_id_rewriter<F>(mut self, name: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(Option<&str>) -> Result<Arc<dyn IdRewriter>, ConsolidateError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.id_rewriters.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// Register a merge policy under `name`
+    pub fn with_merge_policy<F>(mut self, name: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(Option<&str>) -> Result<MergePolicy, ConsolidateError> + Send + Sync + 'static,
+    {
+        self.merge_policies.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// Register a post-processor under `name`
+    pub fn with_post_processor<F>(mut self, name: impl Into<String>, factory: F) -> Self
+    where
+        F: Fn(Option<&str>) -> Result<Arc<dyn PostProcessor>, ConsolidateError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.post_processors.insert(name.into(), Arc::new(factory));
+        self
+    }
+
+    /// Names of the registered loaders, sorted
+    pub fn loader_names(&self) -> impl Iterator<Item = &str> {
+        self.loaders.keys().map(String::as_str)
+    }
+
+    /// Names of the registered id rewriters, sorted
+    pub fn id_rewriter_names(&self) -> impl Iterator<Item = &str> {
+        self.id_rewriters.keys().map(String::as_str)
+    }
+
+    /// Names of the registered merge policies, sorted
+    pub fn merge_policy_names(&self) -> impl Iterator<Item = &str> {
+        self.merge_policies.keys().map(String::as_str)
+    }
+
+    /// Names of the registered post-processors, sorted
+    pub fn post_processor_names(&self) -> impl Iterator<Item = &str> {
+        self.post_processors.keys().map(String::as_str)
+    }
+
+    /// The loader `name` for `source`
+    pub fn loader(
+        &self,
+        name: &str,
+        source: &str,
+    ) -> Result<Box<dyn SubcrateLoader>, ConsolidateError> {
+        let factory = self
+            .loaders
+            .get(name)
+            .ok_or_else(|| unknown("loader", name, self.loader_names()))?;
+        factory(source)
+    }
+
+    /// The id rewriter named by `spec`, `NAME` or `NAME=ARG`
+    pub fn id_rewriter(&self, spec: &str) -> Result<Arc<dyn IdRewriter>, ConsolidateError> {
+        let (name, arg) = split_spec(spec);
+        let factory = self
+            .id_rewriters
+            .get(name)
+            .ok_or_else(|| unknown("id rewriter", name, self.id_rewriter_names()))?;
+        factory(arg)
+    }
+
+    /// A [`RewriteIds`] post-processor of the id rewriters named by `specs`,
+    /// tried in order
+    pub fn rewrite_ids<'a>(
+        &self,
+        specs: impl IntoIterator<Item = &'a str>,
+    ) -> Result<RewriteIds, ConsolidateError> {
+        let rewriters = specs
+            .into_iter()
+            .map(|spec| self.id_rewriter(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(RewriteIds::new(rewriters))
+    }
+
+    /// The merge policy named by `spec`, `NAME` or `NAME=ARG`
+    pub fn merge_policy(&self, spec: &str) -> Result<MergePolicy, ConsolidateError> {
+        let (name, arg) = split_spec(spec);
+        let factory = self
+            .merge_policies
+            .get(name)
+            .ok_or_else(|| unknown("merge policy", name, self.merge_policy_names()))?;
+        factory(arg)
+    }
+
+    /// The post-processor named by `spec`, `NAME` or `NAME=ARG`
+    pub fn post_processor(&self, spec: &str) -> Result<Arc<dyn PostProcessor>, ConsolidateError> {
+        let (name, arg) = split_spec(spec);
+        let factory = self
+            .post_processors
+            .get(name)
+            .ok_or_else(|| unknown("post-processor", name, self.post_processor_names()))?;
+        factory(arg)
+    }
+
+    /// The post-processors named by `specs`, in order
+    pub fn post_processors<'a>(
+        &self,
+        specs: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<Arc<dyn PostProcessor>>, ConsolidateError> {
+        specs
+            .into_iter()
+            .map(|spec| self.post_processor(spec))
+            .collect()
+    }
+}
+
+/// The name and argument of a `NAME` or `NAME=ARG` spec
+fn split_spec(spec: &str) -> (&str, Option<&str>) {
+    match spec.split_once('=') {
+        Some((name, arg)) => (name, Some(arg)),
+        None => (spec, None),
+    }
+}
+
+fn unknown<'a>(kind: &str, name: &str, names: impl Iterator<Item = &'a str>) -> ConsolidateError {
+    ConsolidateError::InvalidStructure(format!(
+        "Unknown {} '{}', expected one of {}",
+        kind,
+        name,
+        names.collect::<Vec<_>>().join(", ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::consolidate::ConsolidateResult;
+
+    #[test]
+    fn test_registry() {
+        let registry =
+            Registry::with_builtins().with_post_processor("stamp", |arg: Option<&str>| {
+                let stamp = arg.unwrap_or("stamped").to_string();
+                let processor = move |result: &mut ConsolidateResult| {
+                    result.graph[0]["stamp"] = json!(stamp);
+                    Ok(())
+                };
+                Ok(Arc::new(processor) as Arc<dyn PostProcessor>)
+            });
+
+        let mut result = ConsolidateResult {
+            graph: vec![json!({"@id": "./", "email": "a@example.org", "name": "Root"})],
+            ..ConsolidateResult::default()
+        };
+        let processors = registry
+            .post_processors(["redact=email:telephone", "stamp=v1"])
+            .unwrap();
+        for processor in &processors {
+            processor.process(&mut result).unwrap();
+        }
+        assert_eq!(
+            result.graph[0],
+            json!({"@id": "./", "name": "Root", "stamp": "v1"})
+        );
+
+        let err = registry.post_processor("sort").unwrap_err();
+        assert!(err.to_string().contains("Unknown post-processor 'sort'"));
+        assert!(err
+            .to_string()
+            .contains("redact, stamp, summarize-citations"));
+        assert!(registry.post_processor("redact").is_err());
+        assert!(registry
+            .post_processor("aggregate-coverage=spatial")
+            .is_ok());
+
+        assert!(registry.loader("none", "./crate").is_ok());
+        assert!(registry.loader("s3", "./crate").is_err());
+        assert_eq!(
+            registry.loader_names().collect::<Vec<_>>(),
+            ["none", "s3", "url"]
+        );
+        assert!(Registry::new()
+            .loader("url", "https://example.org/")
+            .is_err());
+    }
+
+    #[test]
+    fn test_registry_id_rewriters_and_merge_policies() {
+        let registry = Registry::with_builtins();
+        let rewrite = registry
+            .id_rewriter("replace-prefix=https://old.example.org/=https://example.org/")
+            .unwrap();
+        assert_eq!(
+            rewrite.rewrite("https://old.example.org/a/b").as_deref(),
+            Some("https://example.org/a/b")
+        );
+        assert_eq!(rewrite.rewrite("https://other.example.org/"), None);
+        let rebase = registry
+            .id_rewriter("base=https://example.org/crate/")
+            .unwrap();
+        assert_eq!(
+            rebase.rewrite("./data/a.csv").as_deref(),
+            Some("https://example.org/crate/data/a.csv")
+        );
+        assert_eq!(rebase.rewrite("#fragment"), None);
+        assert!(registry.id_rewriter("replace-prefix").is_err());
+        assert!(registry
+            .rewrite_ids(["base=https://example.org/", "sort"])
+            .is_err());
+
+        let policy = registry.merge_policy("latest-dates").unwrap();
+        assert_eq!(policy.conflict_strategy, ConflictStrategy::Union);
+        assert_eq!(
+            policy.property_strategies["dateModified"],
+            PropertyStrategy::Latest
+        );
+        let options = crate::ConsolidateOptions {
+            property_strategies: [("dateModified".to_string(), PropertyStrategy::PreferMain)]
+                .into(),
+            ..Default::default()
+        }
+        .with_merge_policy(registry.merge_policy("prefer-incoming").unwrap())
+        .with_merge_policy(policy);
+        assert_eq!(options.conflict_strategy, ConflictStrategy::Union);
+        assert_eq!(
+            options.property_strategies["dateModified"],
+            PropertyStrategy::PreferMain
+        );
+        assert_eq!(
+            options.property_strategies["dateCreated"],
+            PropertyStrategy::Earliest
+        );
+        let err = registry.merge_policy("newest").unwrap_err();
+        assert!(err.to_string().contains("Unknown merge policy 'newest'"));
+    }
+}
@@ -0,0 +1,597 @@
+//! Consolidation reports for people
+//!
+//! [`HtmlReport`] renders a consolidation's statistics, conflicts, warnings,
+//! lint findings and what each crate contributed as a standalone HTML page,
+//! e.g. to attach to a release for sign-off. Given the URL of an HTML
+//! preview of the consolidated crate (such as `ro-crate-preview.html`), the
+//! ids of entities link to their place in it:
+//!
+//! ```ignore
+//! let html = HtmlReport::new(&result)
+//!     .with_findings(&Linter::new().lint(&result.graph))
+//!     .with_preview("ro-crate-preview.html")
+//!     .render();
+//! ```
+//!
+//! [`notification`] sums a run up in a few lines for chat-ops, as plain
+//! text (e.g. for email), Markdown (e.g. for Matrix) or Slack markup.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use serde_json::Value;
+
+use crate::collect::extract_id;
+use crate::consolidate::ConsolidateResult;
+use crate::id::{classify_id, IdKind};
+use crate::lint::{Finding, Severity};
+use crate::vocab::ROOT_ENTITY_ID;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em auto;max-width:70em;color:#222}\
+table{border-collapse:collapse;margin-bottom:1.5em}\
+th,td{border:1px solid #ccc;padding:.3em .6em;text-align:left;vertical-align:top}\
+th{background:#f3f3f3}td.count{text-align:right}\
+.error{color:#a00}.warning{color:#a60}.failure{background:#fee;border:1px solid #a00;padding:.6em}";
+
+/// A consolidation result rendered as an HTML page
+#[derive(Debug, Clone)]
+pub struct HtmlReport<'a> {
+    result: &'a ConsolidateResult,
+    findings: &'a [Finding],
+    preview: Option<&'a str>,
+}
+
+impl<'a> HtmlReport<'a> {
+    pub fn new(result: &'a ConsolidateResult) -> Self {
+        Self {
+            result,
+            findings: &[],
+            preview: None,
+        }
+    }
+
+    /// Also list these lint findings, e.g. of the consolidated graph
+    pub fn with_findings(mut self, findings: &'a [Finding]) -> Self {
+        self.findings = findings;
+        self
+    }
+
+    /// Link entity ids to their place in this HTML preview of the
+    /// consolidated crate, as `<preview>#<id>`
+    pub fn with_preview(mut self, url: &'a str) -> Self {
+        self.preview = Some(url);
+        self
+    }
+
+    /// The report as a standalone HTML document
+    pub fn render(&self) -> String {
+        let result = self.result;
+        let title = match root_entity(result).and_then(name) {
+            Some(name) => format!("Consolidation report: {}", name),
+            None => "Consolidation report".to_string(),
+        };
+
+        let mut out = String::new();
+        let _ = writeln!(out, "<!DOCTYPE html>");
+        let _ = writeln!(out, "<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">");
+        let _ = writeln!(out, "<title>{}</title>", escape(&title));
+        let _ = writeln!(out, "<style>{}</style>\n</head>\n<body>", STYLE);
+        let _ = writeln!(out, "<h1>{}</h1>", escape(&title));
+
+        if let Some(failure) = &result.failure {
+            let _ = writeln!(
+                out,
+                "<p class=\"failure\">Partial result: consolidation failed after {} crates \
+                 with <code>{}</code>: {}{}</p>",
+                failure.crates_consolidated,
+                escape(&failure.code),
+                escape(&failure.error),
+                match failure.subcrate_path.as_slice() {
+                    [] => String::new(),
+                    path => format!(" (in {})", escape(&path.join(" / "))),
+                }
+            );
+        }
+
+        self.write_stats(&mut out);
+        self.write_crates(&mut out);
+        self.write_conflicts(&mut out);
+        self.write_findings(&mut out);
+
+        let _ = writeln!(out, "<h2>Warnings</h2>");
+        if result.warnings.is_empty() {
+            let _ = writeln!(out, "<p>None</p>");
+        } else {
+            let _ = writeln!(out, "<ul>");
+            for warning in &result.warnings {
+                let _ = writeln!(out, "<li>{}</li>", escape(warning));
+            }
+            let _ = writeln!(out, "</ul>");
+        }
+
+        let _ = writeln!(out, "</body>\n</html>");
+        out
+    }
+
+    fn write_stats(&self, out: &mut String) {
+        let stats = &self.result.stats;
+        let errors = self
+            .findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .count();
+        let rows = [
+            ("Crates consolidated", stats.crates_consolidated),
+            ("Crates taken from the cache", stats.cached_crates),
+            ("Entities", stats.total_entities),
+            ("Shared entities merged", stats.merged_entities),
+            ("Identical copies dropped", stats.identical_entities),
+            ("Conflicting properties", self.result.conflicts.len()),
+            ("Warnings", self.result.warnings.len()),
+            ("Lint errors", errors),
+            ("Lint warnings", self.findings.len() - errors),
+        ];
+        let _ = writeln!(out, "<h2>Statistics</h2>\n<table>");
+        for (label, count) in rows {
+            let _ = writeln!(
+                out,
+                "<tr><th>{}</th><td class=\"count\">{}</td></tr>",
+                label, count
+            );
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    /// What each crate contributed, by namespace
+    fn write_crates(&self, out: &mut String) {
+        let mut conflicts: HashMap<&str, usize> = HashMap::new();
+        for conflict in &self.result.conflicts {
+            *conflicts.entry(&conflict.id).or_default() += 1;
+        }
+        let mut crates: BTreeMap<&str, CrateRow> = BTreeMap::new();
+        for origin in &self.result.origins {
+            let row = crates.entry(&origin.namespace).or_default();
+            if origin.original_id == ROOT_ENTITY_ID {
+                row.folder = Some(&origin.id);
+                continue;
+            }
+            row.entities += 1;
+            if classify_id(&origin.original_id) == IdKind::Absolute {
+                row.shared += 1;
+                row.conflicts += conflicts.get(origin.id.as_str()).copied().unwrap_or(0);
+            }
+        }
+
+        let entities: HashMap<&str, &Value> = self
+            .result
+            .graph
+            .iter()
+            .filter_map(|e| extract_id(e).map(|id| (id, e)))
+            .collect();
+        let _ = writeln!(out, "<h2>Crates</h2>\n<table>");
+        let _ = writeln!(
+            out,
+            "<tr><th>Crate</th><th>Folder</th><th>Name</th><th>Entities</th>\
+             <th>Shared</th><th>Conflicts</th></tr>"
+        );
+        for (namespace, row) in &crates {
+            let crate_name = match *namespace {
+                "" => "(root)",
+                namespace => namespace,
+            };
+            let folder_name = row
+                .folder
+                .and_then(|id| entities.get(id))
+                .and_then(|entity| name(entity))
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td class=\"count\">{}</td>\
+                 <td class=\"count\">{}</td><td class=\"count\">{}</td></tr>",
+                escape(crate_name),
+                row.folder.map(|id| self.link(id)).unwrap_or_default(),
+                escape(folder_name),
+                row.entities,
+                row.shared,
+                row.conflicts
+            );
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    fn write_conflicts(&self, out: &mut String) {
+        let _ = writeln!(out, "<h2>Conflicts</h2>");
+        if self.result.conflicts.is_empty() {
+            let _ = writeln!(out, "<p>None</p>");
+            return;
+        }
+        let _ = writeln!(
+            out,
+            "<table>\n<tr><th>Entity</th><th>Property</th><th>Main value</th>\
+             <th>Incoming value</th></tr>"
+        );
+        for conflict in &self.result.conflicts {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td><code>{}</code></td><td><code>{}</code></td></tr>",
+                self.link(&conflict.id),
+                escape(&conflict.property),
+                escape(&conflict.first.to_string()),
+                escape(&conflict.second.to_string())
+            );
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    fn write_findings(&self, out: &mut String) {
+        let _ = writeln!(out, "<h2>Lint findings</h2>");
+        if self.findings.is_empty() {
+            let _ = writeln!(out, "<p>None</p>");
+            return;
+        }
+        let _ = writeln!(
+            out,
+            "<table>\n<tr><th>Severity</th><th>Rule</th><th>Message</th><th>Entity</th>\
+             <th>Pointer</th></tr>"
+        );
+        for finding in self.findings {
+            let severity = match finding.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+            };
+            let _ = writeln!(
+                out,
+                "<tr class=\"{0}\"><td>{0}</td><td><code>{1}</code></td><td>{2}</td><td>{3}</td>\
+                 <td><code>{4}</code></td></tr>",
+                severity,
+                escape(&finding.rule),
+                escape(&finding.message),
+                finding
+                    .id
+                    .as_deref()
+                    .map(|id| self.link(id))
+                    .unwrap_or_default(),
+                escape(finding.pointer.as_deref().unwrap_or_default())
+            );
+        }
+        let _ = writeln!(out, "</table>");
+    }
+
+    /// An entity id, linked into the preview if there is one
+    fn link(&self, id: &str) -> String {
+        match self.preview {
+            Some(preview) => format!(
+                "<a href=\"{}#{}\"><code>{}</code></a>",
+                escape(preview),
+                escape(id),
+                escape(id)
+            ),
+            None => format!("<code>{}</code>", escape(id)),
+        }
+    }
+}
+
+/// The root entity of a consolidated graph
+fn root_entity(result: &ConsolidateResult) -> Option<&Value> {
+    let origin = result
+        .origins
+        .iter()
+        .find(|o| o.namespace.is_empty() && o.original_id == ROOT_ENTITY_ID)?;
+    result
+        .graph
+        .iter()
+        .find(|e| extract_id(e) == Some(origin.id.as_str()))
+}
+
+/// Markup of a [`notification`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NotifyFormat {
+    /// Plain text, e.g. for email
+    #[default]
+    Text,
+    /// Markdown, e.g. for Matrix
+    Markdown,
+    /// Slack's mrkdwn
+    Slack,
+}
+
+impl NotifyFormat {
+    fn bold(self, text: &str) -> String {
+        match self {
+            NotifyFormat::Text => text.to_string(),
+            NotifyFormat::Markdown => format!("**{}**", text),
+            NotifyFormat::Slack => format!("*{}*", text),
+        }
+    }
+
+    fn code(self, text: &str) -> String {
+        match self {
+            NotifyFormat::Text => text.to_string(),
+            NotifyFormat::Markdown | NotifyFormat::Slack => format!("`{}`", text),
+        }
+    }
+
+    fn bullet(self) -> &'static str {
+        match self {
+            NotifyFormat::Text | NotifyFormat::Markdown => "- ",
+            NotifyFormat::Slack => "\u{2022} ",
+        }
+    }
+
+    /// Text from the run, with the characters Slack reserves escaped
+    fn text(self, text: &str) -> String {
+        match self {
+            NotifyFormat::Slack => text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+            NotifyFormat::Text | NotifyFormat::Markdown => text.to_string(),
+        }
+    }
+}
+
+/// Warnings and conflicting properties listed in a [`notification`]
+const NOTIFY_TOP: usize = 3;
+
+/// A few lines summing up a consolidation for chat-ops: the counts, the
+/// first warnings and which properties conflicted how often
+///
+/// `findings` are the lint findings of the result, if it was linted.
+pub fn notification(
+    result: &ConsolidateResult,
+    findings: &[Finding],
+    format: NotifyFormat,
+) -> String {
+    let stats = &result.stats;
+    let status = if result.failure.is_some() {
+        "partial"
+    } else {
+        "ok"
+    };
+    let headline = match root_entity(result).and_then(name) {
+        Some(name) => format!("Consolidation of {}: {}", format.text(name), status),
+        None => format!("Consolidation: {}", status),
+    };
+    let errors = findings
+        .iter()
+        .filter(|f| f.severity == Severity::Error)
+        .count();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{}", format.bold(&headline));
+    let _ = writeln!(
+        out,
+        "{} crates, {} entities ({} shared merged), {} conflicts, {} warnings, {} lint errors",
+        stats.crates_consolidated,
+        stats.total_entities,
+        stats.merged_entities,
+        result.conflicts.len(),
+        result.warnings.len(),
+        errors
+    );
+    if let Some(failure) = &result.failure {
+        let _ = writeln!(
+            out,
+            "Failed with {} after {} crates: {}",
+            format.code(&failure.code),
+            failure.crates_consolidated,
+            format.text(&failure.error)
+        );
+    }
+
+    if !result.warnings.is_empty() {
+        let _ = writeln!(out, "Warnings:");
+        for warning in result.warnings.iter().take(NOTIFY_TOP) {
+            let _ = writeln!(out, "{}{}", format.bullet(), format.text(warning));
+        }
+        if result.warnings.len() > NOTIFY_TOP {
+            let _ = writeln!(out, "and {} more", result.warnings.len() - NOTIFY_TOP);
+        }
+    }
+
+    if !result.conflicts.is_empty() {
+        // Properties by how often they conflicted, then by name
+        let mut properties: BTreeMap<&str, usize> = BTreeMap::new();
+        for conflict in &result.conflicts {
+            *properties.entry(&conflict.property).or_default() += 1;
+        }
+        let mut properties: Vec<(&str, usize)> = properties.into_iter().collect();
+        properties.sort_by_key(|&(_, count)| Reverse(count));
+        let mut listed: Vec<String> = properties
+            .iter()
+            .take(NOTIFY_TOP)
+            .map(|(property, count)| format!("{} ({})", format.code(property), count))
+            .collect();
+        if properties.len() > NOTIFY_TOP {
+            listed.push(format!("{} more", properties.len() - NOTIFY_TOP));
+        }
+        let _ = writeln!(out, "Conflicts: {}", listed.join(", "));
+    }
+    out
+}
+
+/// One row of the crates table
+#[derive(Default)]
+struct CrateRow<'a> {
+    folder: Option<&'a str>,
+    entities: usize,
+    shared: usize,
+    /// Conflicts in the shared entities the crate contributed to
+    conflicts: usize,
+}
+
+/// The first name of an entity
+fn name(entity: &Value) -> Option<&str> {
+    match entity.get("name")? {
+        Value::Array(names) => names.iter().find_map(Value::as_str),
+        name => name.as_str(),
+    }
+}
+
+/// Escape text for HTML element content and attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::consolidate::{EntityOrigin, PartialFailure};
+    use crate::merge::MergeConflict;
+
+    fn origin(id: &str, original_id: &str, namespace: &str) -> EntityOrigin {
+        EntityOrigin {
+            id: id.to_string(),
+            original_id: original_id.to_string(),
+            namespace: namespace.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_html_report() {
+        let mut result = ConsolidateResult {
+            graph: vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "name": "Study <2024>"}),
+                json!({"@id": "./exp/", "@type": ["Dataset", "Subcrate"], "name": "Experiment"}),
+                json!({"@id": "./exp/data.csv", "@type": "File"}),
+                json!({"@id": "https://orcid.org/0000-0001", "@type": "Person", "name": ["Alice", "A. Smith"]}),
+            ],
+            origins: vec![
+                origin(
+                    "https://orcid.org/0000-0001",
+                    "https://orcid.org/0000-0001",
+                    "",
+                ),
+                origin(
+                    "https://orcid.org/0000-0001",
+                    "https://orcid.org/0000-0001",
+                    "exp",
+                ),
+                origin("ro-crate-metadata.json", "ro-crate-metadata.json", ""),
+                origin("./", "./", ""),
+                origin("./exp/data.csv", "data.csv", "exp"),
+                origin("./exp/", "./", "exp"),
+            ],
+            warnings: vec!["Subcrate './missing/' could not be loaded".to_string()],
+            conflicts: vec![MergeConflict {
+                id: "https://orcid.org/0000-0001".to_string(),
+                property: "name".to_string(),
+                first: json!("Alice"),
+                second: json!("A. Smith"),
+            }],
+            ..ConsolidateResult::default()
+        };
+        result.stats.crates_consolidated = 2;
+        let findings = [Finding {
+            rule: "dangling_reference".to_string(),
+            severity: Severity::Warning,
+            message: "Reference to #nobody, which the graph doesn't describe".to_string(),
+            id: Some("./exp/data.csv".to_string()),
+            pointer: Some("/@graph/3/author".to_string()),
+        }];
+
+        let html = HtmlReport::new(&result)
+            .with_findings(&findings)
+            .with_preview("ro-crate-preview.html")
+            .render();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<h1>Consolidation report: Study &lt;2024&gt;</h1>"));
+        assert!(html.contains("<tr><th>Crates consolidated</th><td class=\"count\">2</td></tr>"));
+        assert!(html.contains("<tr><th>Lint warnings</th><td class=\"count\">1</td></tr>"));
+        // Each crate with its folder, name and the entities it contributed
+        assert!(html.contains(
+            "<tr><td>exp</td><td><a href=\"ro-crate-preview.html#./exp/\"><code>./exp/</code></a></td>\
+             <td>Experiment</td><td class=\"count\">2</td><td class=\"count\">1</td>\
+             <td class=\"count\">1</td></tr>"
+        ));
+        assert!(html.contains(
+            "<td><code>&quot;Alice&quot;</code></td><td><code>&quot;A. Smith&quot;</code></td>"
+        ));
+        assert!(
+            html.contains("<td>Reference to #nobody, which the graph doesn&#39;t describe</td>")
+        );
+        assert!(html.contains("<li>Subcrate &#39;./missing/&#39; could not be loaded</li>"));
+        assert!(!html.contains("Partial result"));
+
+        // Without a preview, ids aren't linked
+        result.failure = Some(PartialFailure {
+            code: "load_error".to_string(),
+            error: "Failed to load crate".to_string(),
+            subcrate_path: vec!["./exp/".to_string()],
+            pointer: None,
+            crates_consolidated: 1,
+        });
+        let html = HtmlReport::new(&result).render();
+        assert!(!html.contains("<a href"));
+        assert!(html.contains("failed after 1 crates with <code>load_error</code>"));
+        assert!(html.contains("<h2>Lint findings</h2>\n<p>None</p>"));
+    }
+
+    #[test]
+    fn test_notification() {
+        let mut result = ConsolidateResult {
+            graph: vec![json!({"@id": "./", "@type": "Dataset", "name": "Study <2024>"})],
+            origins: vec![origin("./", "./", "")],
+            warnings: (1..=5).map(|i| format!("Warning {}", i)).collect(),
+            ..ConsolidateResult::default()
+        };
+        result.stats.crates_consolidated = 3;
+        result.stats.total_entities = 120;
+        let conflict = |id: &str, property: &str| MergeConflict {
+            id: id.to_string(),
+            property: property.to_string(),
+            first: json!("a"),
+            second: json!("b"),
+        };
+        result.conflicts = vec![
+            conflict("#a", "name"),
+            conflict("#b", "name"),
+            conflict("#a", "email"),
+            conflict("#a", "datePublished"),
+            conflict("#a", "url"),
+        ];
+
+        assert_eq!(
+            notification(&result, &[], NotifyFormat::Text),
+            "Consolidation of Study <2024>: ok\n\
+             3 crates, 120 entities (0 shared merged), 5 conflicts, 5 warnings, 0 lint errors\n\
+             Warnings:\n\
+             - Warning 1\n\
+             - Warning 2\n\
+             - Warning 3\n\
+             and 2 more\n\
+             Conflicts: name (2), datePublished (1), email (1), 1 more\n"
+        );
+
+        result.warnings.truncate(1);
+        result.failure = Some(PartialFailure {
+            code: "load_error".to_string(),
+            error: "Failed to load crate".to_string(),
+            subcrate_path: vec![],
+            pointer: None,
+            crates_consolidated: 3,
+        });
+        let slack = notification(&result, &[], NotifyFormat::Slack);
+        assert!(slack.starts_with("*Consolidation of Study &lt;2024&gt;: partial*\n"));
+        assert!(slack.contains("Failed with `load_error` after 3 crates"));
+        assert!(slack.contains("\u{2022} Warning 1\n"));
+        let markdown = notification(&result, &[], NotifyFormat::Markdown);
+        assert!(markdown.starts_with("**Consolidation of Study <2024>: partial**\n"));
+        assert!(markdown.contains("Conflicts: `name` (2)"));
+    }
+}
@@ -0,0 +1,541 @@
+//! Capability restrictions for subcrate loaders
+//!
+//! Consolidating user-supplied crates lets their references decide what gets
+//! read: a subcrate may point at `/etc/`, `file:///home/`, an internal host
+//! or a multi-gigabyte document. A [`Sandbox`] lists what loading may touch
+//! (file system roots, hosts and a byte budget), and [`Sandbox::wrap`] puts
+//! any [`SubcrateLoader`] behind it. The checks run in the wrapper, before
+//! and after every load, so they hold whichever loader does the reading:
+//! both the id and the location the loader resolves it to are checked, and
+//! reads through the crate's loaders stop once the byte budget is used up
+//! (see [`crate::limit_reads`]).
+//!
+//! References relative to their crate are resolved by the loader against a
+//! crate that already passed the sandbox; only those climbing out of it
+//! with `..`, or with segments decoding to separators, are refused.
+//! Embedding applications check the root crate's location themselves with
+//! [`Sandbox::check_location`] before loading it.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::Value;
+
+use crate::consolidate::{SubcrateLoader, SubcrateRef};
+use crate::error::ConsolidateError;
+use crate::loader::limit_reads;
+use crate::path::{decode_component, is_file_name};
+
+/// What loaders may access
+///
+/// A new sandbox allows nothing but relative references.
+#[derive(Debug, Clone, Default)]
+pub struct Sandbox {
+    roots: Vec<PathBuf>,
+    hosts: Vec<String>,
+    max_bytes: Option<u64>,
+}
+
+impl Sandbox {
+    /// A sandbox allowing no files and no hosts, without byte limit
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow reading files below `root`
+    pub fn allow_root(mut self, root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        // Compare against the real location, so symlinks can't widen it
+        self.roots.push(root.canonicalize().unwrap_or(root));
+        self
+    }
+
+    /// Allow fetching URLs of `host` over HTTP(S), e.g. "data.example.org"
+    pub fn allow_host(mut self, host: impl Into<String>) -> Self {
+        self.hosts.push(host.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Refuse loading more than `max_bytes` of metadata in total
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Put `loader` behind this sandbox
+    pub fn wrap<L: SubcrateLoader>(&self, loader: L) -> SandboxedLoader<L> {
+        SandboxedLoader {
+            sandbox: self.clone(),
+            inner: loader,
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Fail unless `location`, a URL or file path, may be accessed
+    ///
+    /// Relative paths are allowed unless they climb out with `..` or have
+    /// segments decoding to anything but a file name, like `%2Fetc`.
+    pub fn check_location(&self, location: &str) -> Result<(), ConsolidateError> {
+        match url::Url::parse(location) {
+            Ok(url) => self.check_url(&url),
+            // Windows paths like C:\ parse as URLs with scheme "c"
+            Err(_) if Path::new(location).is_absolute() => self.check_path(Path::new(location)),
+            Err(_) => {
+                let climbs = location
+                    .split(['/', '\\'])
+                    .filter(|segment| !segment.is_empty() && *segment != ".")
+                    .any(|segment| !is_file_name(&decode_component(segment)));
+                if climbs {
+                    return Err(violation(location, "climbs out of its crate"));
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Fail unless `url` uses HTTP(S) on an allowed host or is a file URL
+    /// below an allowed root
+    fn check_url(&self, url: &url::Url) -> Result<(), ConsolidateError> {
+        match url.scheme() {
+            "http" | "https" => {
+                let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+                if !self.hosts.contains(&host) {
+                    return Err(violation(url.as_str(), "host is not allowed"));
+                }
+                Ok(())
+            }
+            "file" => match url.to_file_path() {
+                Ok(path) => self.check_path(&path),
+                Err(()) => Err(violation(url.as_str(), "not a local file")),
+            },
+            scheme => Err(violation(
+                url.as_str(),
+                &format!("scheme '{}' is not allowed", scheme),
+            )),
+        }
+    }
+
+    /// Fail unless `path` is below an allowed root
+    fn check_path(&self, path: &Path) -> Result<(), ConsolidateError> {
+        // A path that doesn't exist yet can't be followed through links
+        let resolved = path
+            .canonicalize()
+            .unwrap_or_else(|_| normalize_lexically(path));
+        if !self.roots.iter().any(|root| resolved.starts_with(root)) {
+            return Err(violation(
+                &path.display().to_string(),
+                "outside the allowed roots",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Bytes left of the budget after the `used` ones
+    fn bytes_left(&self, used: u64) -> Option<u64> {
+        self.max_bytes.map(|max| max.saturating_sub(used))
+    }
+
+    fn limit_exceeded(&self, location: &str) -> ConsolidateError {
+        violation(
+            location,
+            &format!(
+                "loading exceeds the limit of {} bytes",
+                self.max_bytes.unwrap_or_default()
+            ),
+        )
+    }
+
+    /// Count `bytes` more against the budget in `total`
+    fn charge(
+        &self,
+        total: &AtomicU64,
+        bytes: u64,
+        location: &str,
+    ) -> Result<(), ConsolidateError> {
+        let total = total.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        match self.max_bytes {
+            Some(max) if total > max => Err(self.limit_exceeded(location)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A loader restricted by a [`Sandbox`]
+pub struct SandboxedLoader<L> {
+    sandbox: Sandbox,
+    inner: L,
+    /// Bytes of metadata loaded so far
+    bytes: AtomicU64,
+}
+
+impl<L> SandboxedLoader<L> {
+    /// Bytes of metadata loaded so far, as serialized JSON
+    pub fn bytes_loaded(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+}
+
+impl<L: SubcrateLoader> SubcrateLoader for SandboxedLoader<L> {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        self.load_ref(&SubcrateRef::new(
+            subcrate_id,
+            parent_namespace,
+            subcrate_entity,
+        ))
+    }
+
+    fn load_ref(&self, subcrate: &SubcrateRef) -> Result<Vec<Value>, ConsolidateError> {
+        // Every location the reference names, before anything is read
+        self.sandbox.check_location(subcrate.id)?;
+        let subject_of = subcrate.entity.and_then(|entity| entity.get("subjectOf"));
+        for location in subject_of.into_iter().flat_map(referenced_ids) {
+            self.sandbox.check_location(location)?;
+        }
+        // And the one the loader will actually open
+        let resolved = self
+            .inner
+            .location(subcrate.id, subcrate.parent_namespace, subcrate.entity);
+        if let Some(location) = &resolved {
+            self.sandbox.check_location(location)?;
+        }
+
+        // Reads stop once the budget left is used up
+        let read_limit = limit_reads(self.sandbox.bytes_left(self.bytes_loaded()));
+        let loaded = self.inner.load_ref(subcrate);
+        if read_limit.hit() {
+            return Err(self.sandbox.limit_exceeded(subcrate.id));
+        }
+        drop(read_limit);
+        let graph = loaded?;
+        let bytes = serialized_len(&graph)?;
+        self.sandbox.charge(&self.bytes, bytes, subcrate.id)?;
+        Ok(graph)
+    }
+
+    fn listed_subcrates(&self, namespace: &str) -> Vec<String> {
+        // Listed ids are checked when they are loaded
+        self.inner.listed_subcrates(namespace)
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        self.inner
+            .location(subcrate_id, parent_namespace, subcrate_entity)
+    }
+}
+
+/// The ids of a reference or array of references
+fn referenced_ids(value: &Value) -> Vec<&str> {
+    match value {
+        Value::Array(values) => values.iter().flat_map(referenced_ids).collect(),
+        Value::Object(object) => object
+            .get("@id")
+            .and_then(Value::as_str)
+            .into_iter()
+            .collect(),
+        Value::String(id) => vec![id.as_str()],
+        _ => Vec::new(),
+    }
+}
+
+/// Resolve `.` and `..` in `path` without touching the file system
+fn normalize_lexically(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+fn violation(location: &str, reason: &str) -> ConsolidateError {
+    ConsolidateError::SandboxViolation {
+        location: location.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// The host of `url` and the address to connect to, if all the host's
+/// addresses are public
+///
+/// Clients pin the host to the address returned (see
+/// `reqwest::ClientBuilder::resolve`), so it can't resolve to another one
+/// by the time they connect.
+pub(crate) fn public_address(url: &url::Url) -> Result<(String, SocketAddr), String> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("URL {} has no host", url))?;
+    let addrs = url
+        .socket_addrs(|| None)
+        .map_err(|e| format!("resolving {} failed: {}", host, e))?;
+    if let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!(
+            "URL {} resolves to the non-public address {}",
+            url,
+            addr.ip()
+        ));
+    }
+    let addr = addrs
+        .first()
+        .ok_or_else(|| format!("{} has no address", host))?;
+    Ok((host.to_string(), *addr))
+}
+
+/// Whether `ip` is reachable on the internet, not only on the host or its
+/// network
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            // 100.64.0.0/10 is shared between carrier-grade NAT customers
+            let shared = a == 100 && (b & 0xc0) == 64;
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || shared)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            // fc00::/7 is unique local, fe80::/10 link-local
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+/// Length of `graph` serialized as compact JSON
+pub(crate) fn serialized_len(graph: &[Value]) -> Result<u64, serde_json::Error> {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, graph)?;
+    Ok(counter.0)
+}
+
+/// Writer counting the bytes written to it
+struct ByteCounter(u64);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{consolidate, ConsolidateInput, ConsolidateOptions};
+    use serde_json::json;
+
+    /// Loader serving the same small crate for every subcrate
+    struct AnyLoader;
+
+    impl SubcrateLoader for AnyLoader {
+        fn load(
+            &self,
+            _subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            Ok(vec![
+                json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+                json!({"@id": "./", "@type": "Dataset", "name": "Sub"}),
+            ])
+        }
+    }
+
+    #[test]
+    fn test_sandbox_locations() {
+        let root = std::env::temp_dir();
+        let sandbox = Sandbox::new()
+            .allow_root(&root)
+            .allow_host("Data.Example.org");
+
+        assert!(sandbox.check_location("./experiments/").is_ok());
+        assert!(sandbox
+            .check_location("https://data.example.org/crate/")
+            .is_ok());
+        assert!(sandbox
+            .check_location(&root.join("crate").display().to_string())
+            .is_ok());
+
+        for denied in [
+            "../secrets/",
+            "./a/%2E%2E/%2e%2e/",
+            "./%2Fetc/",
+            "./a%5C..%5C..%5C/",
+            "https://internal.example.org/",
+            "ftp://data.example.org/crate/",
+            "file:///etc/",
+            "/etc/",
+        ] {
+            let err = sandbox.check_location(denied).unwrap_err();
+            assert_eq!(err.code(), "sandbox_violation", "{}", denied);
+        }
+        let escaping = root.join("crate/../..").display().to_string();
+        assert!(sandbox.check_location(&escaping).is_err());
+    }
+
+    #[test]
+    fn test_sandboxed_loader() {
+        let sandbox = Sandbox::new().allow_host("data.example.org");
+        let loader = sandbox.wrap(AnyLoader);
+        assert!(loader.load("./sub/", "", None).is_ok());
+        assert!(loader.bytes_loaded() > 0);
+
+        // subjectOf is checked as well as the id
+        let entity = json!({
+            "@id": "./sub/",
+            "subjectOf": [{"@id": "https://data.example.org/sub/"}, {"@id": "http://10.0.0.1/"}]
+        });
+        assert!(loader.load("./sub/", "", Some(&entity)).is_err());
+
+        // The byte budget covers all loads
+        let loader = sandbox.clone().with_max_bytes(150).wrap(AnyLoader);
+        assert!(loader.load("./a/", "", None).is_ok());
+        let err = loader.load("./b/", "", None).unwrap_err();
+        assert!(err.to_string().contains("limit of 150 bytes"));
+    }
+
+    /// Loader reading every subcrate from one file, found at `location`
+    struct FileLoader {
+        path: PathBuf,
+        location: Option<String>,
+    }
+
+    impl SubcrateLoader for FileLoader {
+        fn load(
+            &self,
+            _subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Result<Vec<Value>, ConsolidateError> {
+            let content =
+                crate::audit::read_file(&self.path).map_err(|e| ConsolidateError::LoadError {
+                    path: self.path.display().to_string(),
+                    reason: e.to_string(),
+                    source: Some(e.into()),
+                })?;
+            let graph: Value = serde_json::from_str(&content)?;
+            Ok(graph["@graph"].as_array().cloned().unwrap_or_default())
+        }
+
+        fn location(
+            &self,
+            _subcrate_id: &str,
+            _parent_namespace: &str,
+            _subcrate_entity: Option<&Value>,
+        ) -> Option<String> {
+            self.location.clone()
+        }
+    }
+
+    #[test]
+    fn test_sandbox_resolved_location_and_reads() {
+        let path = std::env::temp_dir().join(format!("sandbox-{}.json", std::process::id()));
+        let padding = "x".repeat(1000);
+        let document = json!({"@graph": [
+            {"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+            {"@id": "./", "@type": "Dataset", "description": padding}
+        ]});
+        std::fs::write(&path, document.to_string()).unwrap();
+
+        // A harmless id the loader resolves to a host that isn't allowed
+        let sandbox = Sandbox::new().allow_host("data.example.org");
+        let loader = sandbox.wrap(FileLoader {
+            path: path.clone(),
+            location: Some("http://169.254.169.254/latest/".to_string()),
+        });
+        let err = loader.load("./sub/", "", None).unwrap_err();
+        assert_eq!(err.code(), "sandbox_violation");
+        assert!(err.to_string().contains("169.254.169.254"));
+
+        // Reading stops at the budget, before the document is parsed
+        let loader = sandbox.with_max_bytes(100).wrap(FileLoader {
+            path,
+            location: None,
+        });
+        let err = loader.load("./sub/", "", None).unwrap_err();
+        assert_eq!(err.code(), "sandbox_violation");
+        assert!(err.to_string().contains("limit of 100 bytes"));
+        assert_eq!(loader.bytes_loaded(), 0);
+        std::fs::remove_file(&loader.inner.path).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox_in_consolidation() {
+        let internal = "https://internal.example.org/crate/";
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "hasPart": [{"@id": internal}]}),
+            json!({
+                "@id": internal,
+                "@type": "Dataset",
+                "conformsTo": {"@id": "https://w3id.org/ro/crate/1.1"}
+            }),
+        ];
+        let loader = Sandbox::new().wrap(AnyLoader);
+        let options = ConsolidateOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = consolidate(ConsolidateInput::Single(graph), &loader, &options).unwrap_err();
+        assert_eq!(err.code(), "sandbox_violation");
+        assert_eq!(err.numeric_code(), 19);
+    }
+
+    #[test]
+    fn test_public_addresses() {
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        let url = url::Url::parse("http://[::1]:8080/hook").unwrap();
+        assert!(public_address(&url).is_err());
+        let url = url::Url::parse("http://93.184.216.34/").unwrap();
+        assert_eq!(public_address(&url).unwrap().1.port(), 80);
+    }
+}
@@ -0,0 +1,229 @@
+//! Curation rules scripted per entity
+//!
+//! An [`EntityScript`] is a post-processor running a [Rhai](https://rhai.rs)
+//! script over every entity of the consolidated graph, for one-off rules not
+//! worth a Rust [`PostProcessor`]. The script sees the entity as the map
+//! `entity` and its @id as `id`; changes it makes to `entity` are kept, and
+//! an entity for which it evaluates to `false` is dropped, together with the
+//! references to it.
+//!
+//! ```ignore
+//! let script = EntityScript::new(r##"
+//!     if has_type(entity, "Person") { entity.remove("email"); }
+//!     if id.starts_with("#tmp-") { return false; }
+//!     entity.publisher = #{ "@id": "https://ror.org/03yrm5c26" };
+//! "##)?;
+//! ```
+//!
+//! `has_type(entity, TYPE)` tells whether `@type` is or includes `TYPE`. Each
+//! run over an entity may take at most [`DEFAULT_MAX_OPERATIONS`] operations
+//! (see [`EntityScript::with_max_operations`]), so a runaway script fails the
+//! consolidation instead of hanging it. Strings, arrays and maps the script
+//! builds, and how deeply it nests calls and expressions, are bounded too,
+//! and it can't import modules, so it reads no files.
+//!
+//! Requires the `scripting` feature.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use serde_json::Value;
+
+use crate::audit::read_file;
+use crate::collect::extract_id;
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+use crate::id::remove_references;
+use crate::postprocess::PostProcessor;
+
+/// Operations a script may take per entity, unless set otherwise
+pub const DEFAULT_MAX_OPERATIONS: u64 = 100_000;
+
+/// Longest string a script may build, in bytes
+const MAX_STRING_SIZE: usize = 1 << 20;
+/// Most elements of an array a script may build, e.g. a `hasPart`
+const MAX_ARRAY_SIZE: usize = 100_000;
+/// Most properties of a map a script may build
+const MAX_MAP_SIZE: usize = 10_000;
+/// Deepest nesting of function calls
+const MAX_CALL_LEVELS: usize = 32;
+/// Deepest nesting of expressions, at the top level and in functions
+const MAX_EXPR_DEPTHS: (usize, usize) = (64, 32);
+
+/// Post-processor running a script over every entity
+pub struct EntityScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl fmt::Debug for EntityScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntityScript")
+            .field("source", &self.ast.source())
+            .finish()
+    }
+}
+
+impl EntityScript {
+    /// Compile `script`
+    pub fn new(script: &str) -> Result<Self, ConsolidateError> {
+        let mut engine = Engine::new();
+        engine
+            .set_max_operations(DEFAULT_MAX_OPERATIONS)
+            .set_max_string_size(MAX_STRING_SIZE)
+            .set_max_array_size(MAX_ARRAY_SIZE)
+            .set_max_map_size(MAX_MAP_SIZE)
+            .set_max_call_levels(MAX_CALL_LEVELS)
+            .set_max_expr_depths(MAX_EXPR_DEPTHS.0, MAX_EXPR_DEPTHS.1)
+            .set_module_resolver(DummyModuleResolver::new());
+        engine.register_fn("has_type", has_type);
+        let ast = engine
+            .compile(script)
+            .map_err(|e| ConsolidateError::ScriptError(e.to_string()))?;
+        Ok(Self { engine, ast })
+    }
+
+    /// Compile the script in the file at `path`
+    pub fn from_file(path: &Path) -> Result<Self, ConsolidateError> {
+        let script = read_file(path)?;
+        let mut compiled = Self::new(&script)
+            .map_err(|e| ConsolidateError::ScriptError(format!("{}: {}", path.display(), e)))?;
+        compiled.ast.set_source(path.display().to_string());
+        Ok(compiled)
+    }
+
+    /// Limit the operations the script may take per entity (0 for no limit)
+    pub fn with_max_operations(mut self, operations: u64) -> Self {
+        self.engine.set_max_operations(operations);
+        self
+    }
+
+    /// Run the script over `entity`, returning the entity to keep, if any
+    fn run(&self, entity: &Value) -> Result<Option<Value>, ConsolidateError> {
+        let id = extract_id(entity).unwrap_or_default().to_string();
+        let failed = |reason: String| ConsolidateError::ScriptError(format!("{} ({})", reason, id));
+        let mut scope = Scope::new();
+        scope.push_dynamic(
+            "entity",
+            rhai::serde::to_dynamic(entity).map_err(|e| failed(e.to_string()))?,
+        );
+        scope.push("id", id.clone());
+        let keep = self
+            .engine
+            .eval_ast_with_scope::<Dynamic>(&mut scope, &self.ast)
+            .map_err(|e| failed(e.to_string()))?;
+        if keep.as_bool() == Ok(false) {
+            return Ok(None);
+        }
+        let entity = scope.get_value::<Dynamic>("entity").unwrap_or_default();
+        match rhai::serde::from_dynamic::<Value>(&entity) {
+            Ok(entity @ Value::Object(_)) => Ok(Some(entity)),
+            Ok(other) => Err(failed(format!("entity was replaced by {}", other))),
+            Err(e) => Err(failed(e.to_string())),
+        }
+    }
+}
+
+impl PostProcessor for EntityScript {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        let mut dropped = HashSet::new();
+        let mut graph = Vec::with_capacity(result.graph.len());
+        for entity in &result.graph {
+            match self.run(entity)? {
+                Some(entity) => graph.push(entity),
+                None => dropped.extend(extract_id(entity).map(String::from)),
+            }
+        }
+        for entity in &mut graph {
+            remove_references(entity, &dropped);
+        }
+        result.graph = graph;
+        Ok(())
+    }
+}
+
+/// Whether the `@type` of `entity` is or includes `type_name`
+fn has_type(entity: Map, type_name: &str) -> bool {
+    match entity.get("@type") {
+        Some(types) if types.is_array() => types
+            .clone()
+            .into_array()
+            .unwrap_or_default()
+            .iter()
+            .any(|t| t.clone().into_string().is_ok_and(|t| t == type_name)),
+        Some(single) => single.clone().into_string().is_ok_and(|t| t == type_name),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn result() -> ConsolidateResult {
+        ConsolidateResult {
+            graph: vec![
+                json!({"@id": "./", "@type": "Dataset", "author": [{"@id": "#alice"}, {"@id": "#tmp-bob"}]}),
+                json!({"@id": "#alice", "@type": ["Person", "Agent"], "email": "alice@example.org", "name": "Alice"}),
+                json!({"@id": "#tmp-bob", "@type": "Person", "name": "Bob"}),
+                json!({"@id": "data.csv", "@type": "File"}),
+            ],
+            ..ConsolidateResult::default()
+        }
+    }
+
+    #[test]
+    fn test_entity_script() {
+        let script = EntityScript::new(
+            r##"
+            if has_type(entity, "Person") { entity.remove("email"); }
+            if id.starts_with("#tmp-") { return false; }
+            if has_type(entity, "File") { entity.encodingFormat = "text/csv"; }
+            "##,
+        )
+        .unwrap();
+        let mut result = result();
+        script.process(&mut result).unwrap();
+        assert_eq!(
+            result.graph,
+            [
+                json!({"@id": "./", "@type": "Dataset", "author": [{"@id": "#alice"}]}),
+                json!({"@id": "#alice", "@type": ["Person", "Agent"], "name": "Alice"}),
+                json!({"@id": "data.csv", "@type": "File", "encodingFormat": "text/csv"}),
+            ]
+        );
+
+        let err = EntityScript::new("if {").unwrap_err();
+        assert_eq!(err.code(), "script_error");
+        let err = EntityScript::new("entity = 1;")
+            .unwrap()
+            .process(&mut result)
+            .unwrap_err();
+        assert!(err.to_string().contains("(./)"));
+        assert_eq!(result.graph.len(), 3);
+        let err = EntityScript::new("let n = 0; loop { n += 1; }")
+            .unwrap()
+            .with_max_operations(1_000)
+            .process(&mut result)
+            .unwrap_err();
+        assert_eq!(err.code(), "script_error");
+
+        // Scripts can't grow values without bound or read modules
+        for script in [
+            r#"let s = "x"; loop { s += s; }"#,
+            "let a = [1]; loop { a += a; }",
+            r#"fn f(n) { f(n + 1) } f(0)"#,
+            r#"import "/etc/passwd" as m;"#,
+        ] {
+            let err = EntityScript::new(script)
+                .and_then(|script| script.with_max_operations(0).process(&mut result))
+                .unwrap_err();
+            assert_eq!(err.code(), "script_error", "{}", script);
+        }
+    }
+}
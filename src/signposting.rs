@@ -0,0 +1,217 @@
+//! FAIR Signposting links
+//!
+//! Landing pages of repositories point at the metadata of what they describe
+//! with typed links, either in the `Link` header of their responses or as
+//! `<link>` elements of their HTML (see <https://signposting.org/FAIR/>).
+//! `describedby` links lead to metadata documents, and `cite-as` names the
+//! persistent identifier of the landing page.
+
+use url::Url;
+
+/// Relation of links to metadata documents
+pub const DESCRIBED_BY: &str = "describedby";
+
+/// Relation of links to the persistent identifier of a landing page
+pub const CITE_AS: &str = "cite-as";
+
+/// A typed link of a landing page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signpost {
+    /// Relation type, lowercased (e.g. "describedby")
+    pub rel: String,
+    /// Link target, resolved against the landing page
+    pub href: String,
+    /// Media type of the target (e.g. "application/ld+json")
+    pub media_type: Option<String>,
+    /// Profile the target conforms to
+    pub profile: Option<String>,
+}
+
+/// Parse the value of a `Link` header of the response to `base`
+///
+/// A link with several relation types yields a signpost for each.
+pub fn parse_link_header(value: &str, base: &str) -> Vec<Signpost> {
+    let mut signposts = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>').map(|end| start + end) else {
+            break;
+        };
+        let target = &rest[start + 1..end];
+        rest = &rest[end + 1..];
+        // Parameters run up to the next comma outside of quotes
+        let mut quoted = false;
+        let params_end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c == ',' && !quoted
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let params = rest[..params_end].split(';').filter_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            Some((name.trim(), value.trim().trim_matches('"')))
+        });
+        signposts.extend(signposts_for(target, base, params));
+        rest = &rest[params_end..];
+    }
+    signposts
+}
+
+/// Parse the `<link>` elements of the HTML page at `base`
+pub fn parse_html_links(html: &str, base: &str) -> Vec<Signpost> {
+    // Byte offsets into the lowercased page are valid for the page itself,
+    // as only ASCII is lowercased
+    let lower = html.to_ascii_lowercase();
+    let mut signposts = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = lower[offset..].find("<link").map(|start| offset + start) {
+        let Some(end) = lower[start..].find('>').map(|end| start + end) else {
+            break;
+        };
+        let attributes = html_attributes(&html[start + "<link".len()..end]);
+        if let Some((_, href)) = attributes.iter().find(|(name, _)| name == "href") {
+            let params = attributes
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()));
+            signposts.extend(signposts_for(href, base, params));
+        }
+        offset = end;
+    }
+    signposts
+}
+
+/// The metadata document among `signposts`
+///
+/// Of several `describedby` links, one to JSON-LD is preferred, then one to
+/// a file named like RO-Crate metadata.
+pub fn metadata_link(signposts: &[Signpost]) -> Option<&str> {
+    let described_by = || signposts.iter().filter(|s| s.rel == DESCRIBED_BY);
+    described_by()
+        .find(|s| s.media_type.as_deref() == Some("application/ld+json"))
+        .or_else(|| described_by().find(|s| s.href.ends_with("ro-crate-metadata.json")))
+        .or_else(|| described_by().next())
+        .map(|s| s.href.as_str())
+}
+
+/// The persistent identifier among `signposts`
+pub fn cite_as(signposts: &[Signpost]) -> Option<&str> {
+    signposts
+        .iter()
+        .find(|s| s.rel == CITE_AS)
+        .map(|s| s.href.as_str())
+}
+
+/// Signposts for each relation type of the link to `target`
+fn signposts_for<'a>(
+    target: &str,
+    base: &str,
+    params: impl Iterator<Item = (&'a str, &'a str)>,
+) -> Vec<Signpost> {
+    let (mut rels, mut media_type, mut profile) = (None, None, None);
+    for (name, value) in params {
+        match name.to_ascii_lowercase().as_str() {
+            "rel" => rels = Some(value.to_ascii_lowercase()),
+            "type" => media_type = Some(value.to_string()),
+            "profile" => profile = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    let href = match Url::parse(base).and_then(|base| base.join(target)) {
+        Ok(url) => url.to_string(),
+        Err(_) => target.to_string(),
+    };
+    rels.unwrap_or_default()
+        .split_whitespace()
+        .map(|rel| Signpost {
+            rel: rel.to_string(),
+            href: href.clone(),
+            media_type: media_type.clone(),
+            profile: profile.clone(),
+        })
+        .collect()
+}
+
+/// Attributes of an HTML tag, names lowercased
+fn html_attributes(tag: &str) -> Vec<(String, String)> {
+    let mut attributes = Vec::new();
+    let mut rest = tag.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace() || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = rest[name_end..].trim_start();
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                let (value, remainder) = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let value = &value[1..];
+                        let end = value.find(quote).unwrap_or(value.len());
+                        (&value[..end], value.get(end + 1..).unwrap_or_default())
+                    }
+                    _ => value.split_at(value.find(char::is_whitespace).unwrap_or(value.len())),
+                };
+                rest = remainder;
+                value.to_string()
+            }
+            None => String::new(),
+        };
+        if name.is_empty() {
+            rest = rest.get(1..).unwrap_or_default();
+        } else {
+            attributes.push((name, value));
+        }
+        rest = rest.trim_start();
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_link_header() {
+        let header = concat!(
+            r#"<https://doi.org/10.5281/zenodo.1>; rel="cite-as", "#,
+            r#"</records/1/files/ro-crate-metadata.json>; rel="describedby item"; "#,
+            r#"type="application/ld+json"; profile="https://w3id.org/ro/crate/1.1""#
+        );
+        let signposts = parse_link_header(header, "https://zenodo.org/records/1");
+        assert_eq!(signposts.len(), 3);
+        assert_eq!(
+            cite_as(&signposts),
+            Some("https://doi.org/10.5281/zenodo.1")
+        );
+        assert_eq!(
+            metadata_link(&signposts),
+            Some("https://zenodo.org/records/1/files/ro-crate-metadata.json")
+        );
+        assert_eq!(
+            signposts[1].profile.as_deref(),
+            Some("https://w3id.org/ro/crate/1.1")
+        );
+        assert_eq!(signposts[2].rel, "item");
+    }
+
+    #[test]
+    fn test_parse_html_links() {
+        let html = r#"<html><head>
+            <LINK rel="stylesheet" href="/style.css">
+            <link rel=describedby href="meta.xml" type="application/xml" />
+            <link href='meta.jsonld' type="application/ld+json" rel='DescribedBy'>
+            </head></html>"#;
+        let signposts = parse_html_links(html, "https://example.org/dataset/");
+        assert_eq!(signposts.len(), 3);
+        assert_eq!(
+            metadata_link(&signposts),
+            Some("https://example.org/dataset/meta.jsonld")
+        );
+        assert_eq!(cite_as(&signposts), None);
+        assert!(metadata_link(&parse_html_links("<p>no links</p>", "")).is_none());
+    }
+}
@@ -0,0 +1,126 @@
+//! Sitemap of consolidated ids to their original locations
+//!
+//! Maps every id of a consolidated crate back to the crate it came from,
+//! its id in that crate and where it can be found, so a resolver service
+//! can redirect requests for consolidated ids to the original artifacts.
+
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+use crate::id::{classify_id, IdKind};
+use crate::manifest::{resolve_in_namespace, SourceLocation};
+use crate::vocab::RESOLVE_ENTITY_ID;
+
+/// One consolidated id and where it originally came from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SitemapEntry {
+    /// The @id in the consolidated graph
+    pub id: String,
+    /// Namespace of the originating crate (empty string for root crate)
+    pub namespace: String,
+    /// The @id in the originating crate
+    pub original_id: String,
+    /// Location of the originating crate (if known)
+    pub crate_source: Option<SourceLocation>,
+    /// Location of the original artifact (for relative and absolute ids)
+    pub source: Option<SourceLocation>,
+}
+
+/// Build the sitemap for a consolidation result
+///
+/// `bases` maps crate namespaces to crate locations, as for
+/// [`build_manifest`](crate::manifest::build_manifest).
+pub fn build_sitemap(
+    result: &ConsolidateResult,
+    bases: &HashMap<String, SourceLocation>,
+) -> Vec<SitemapEntry> {
+    result
+        .origins
+        .iter()
+        .map(|origin| {
+            let source = match classify_id(&origin.original_id) {
+                IdKind::Relative | IdKind::Root => {
+                    resolve_in_namespace(bases, &origin.namespace, &origin.original_id)
+                }
+                IdKind::Absolute => Some(SourceLocation::Url {
+                    url: origin.original_id.clone(),
+                }),
+                IdKind::Fragment | IdKind::MetadataDescriptor => None,
+            };
+            SitemapEntry {
+                id: origin.id.clone(),
+                namespace: origin.namespace.clone(),
+                original_id: origin.original_id.clone(),
+                crate_source: resolve_in_namespace(bases, &origin.namespace, ""),
+                source,
+            }
+        })
+        .collect()
+}
+
+/// Build a `#ro-crate-resolve` entity embedding the sitemap as JSON text
+pub fn sitemap_entity(entries: &[SitemapEntry]) -> Result<Value, ConsolidateError> {
+    Ok(json!({
+        "@id": RESOLVE_ENTITY_ID,
+        "@type": "CreativeWork",
+        "name": "Consolidated id resolution map",
+        "encodingFormat": "application/json",
+        "text": serde_json::to_string(entries)?
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::EntityOrigin;
+
+    #[test]
+    fn test_build_sitemap() {
+        let result = ConsolidateResult {
+            origins: vec![
+                EntityOrigin {
+                    id: "./experiments/data.csv".to_string(),
+                    original_id: "data.csv".to_string(),
+                    namespace: "experiments".to_string(),
+                },
+                EntityOrigin {
+                    id: "#experiments-person1".to_string(),
+                    original_id: "#person1".to_string(),
+                    namespace: "experiments".to_string(),
+                },
+            ],
+            ..ConsolidateResult::default()
+        };
+        let bases = HashMap::from([(
+            String::new(),
+            SourceLocation::Url {
+                url: "https://example.org/crate".to_string(),
+            },
+        )]);
+
+        let sitemap = build_sitemap(&result, &bases);
+        assert_eq!(sitemap.len(), 2);
+        assert_eq!(
+            sitemap[0].source,
+            Some(SourceLocation::Url {
+                url: "https://example.org/crate/experiments/data.csv".to_string()
+            })
+        );
+        assert_eq!(sitemap[1].original_id, "#person1");
+        assert_eq!(sitemap[1].source, None);
+        assert_eq!(
+            sitemap[1].crate_source,
+            Some(SourceLocation::Url {
+                url: "https://example.org/crate/experiments".to_string()
+            })
+        );
+
+        let entity = sitemap_entity(&sitemap).unwrap();
+        assert_eq!(entity["@id"], RESOLVE_ENTITY_ID);
+        let embedded: Value = serde_json::from_str(entity["text"].as_str().unwrap()).unwrap();
+        assert_eq!(embedded.as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,285 @@
+//! Folder ids of merged crates generated from a template
+//!
+//! A template such as `"{name-slug}-{index}/"` is expanded for each merged
+//! crate from the crate's own metadata, so callers need not derive folder ids
+//! themselves. Placeholders:
+//!
+//! - `{name}`: the `name` of the crate's root entity
+//! - `{name-slug}`: that name as a slug (see [`slugify`])
+//! - `{source-slug}`: the last segment of the crate's path or URL (without a
+//!   ".zip", ".tar", ".tar.gz" or ".tgz" extension) as a slug
+//! - `{index}`: the 1-based position of the crate among those merged
+//!
+//! Crates without a name fall back to their source. `{{` and `}}` stand for
+//! literal braces.
+
+use icu_normalizer::{ComposingNormalizerBorrowed, DecomposingNormalizerBorrowed};
+use serde_json::Value;
+
+use crate::collect::{extract_id, is_metadata_descriptor};
+use crate::error::ConsolidateError;
+use crate::loader::archive_stem;
+use crate::path::decode_component;
+use crate::vocab::{METADATA_DESCRIPTOR_ID, ROOT_ENTITY_ID};
+
+/// Longest slug produced by [`slugify`], in characters
+const MAX_SLUG_LEN: usize = 64;
+
+/// What a folder template is expanded from
+#[derive(Debug, Clone, Copy)]
+pub struct TemplateVars<'a> {
+    /// Name of the crate's root entity
+    pub name: Option<&'a str>,
+    /// Path or URL the crate was loaded from
+    pub source: &'a str,
+    /// 1-based position of the crate among those merged
+    pub index: usize,
+}
+
+impl<'a> TemplateVars<'a> {
+    /// Variables of the crate with metadata `graph`, loaded from `source`
+    pub fn from_graph(graph: &'a [Value], source: &'a str, index: usize) -> Self {
+        Self {
+            name: root_name(graph),
+            source,
+            index,
+        }
+    }
+}
+
+/// Expand a folder template into a folder id like "./name/"
+///
+/// A leading "./" and a trailing '/' are added if the template lacks them.
+pub fn expand_folder_template(
+    template: &str,
+    vars: &TemplateVars,
+) -> Result<String, ConsolidateError> {
+    let invalid = |reason: &str| {
+        ConsolidateError::InvalidFolderTemplate(format!("{} in '{}'", reason, template))
+    };
+    let mut folder_id = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                folder_id.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                folder_id.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or_else(|| invalid("unclosed '{'"))?;
+                let placeholder = &rest[..end];
+                let name = || {
+                    vars.name
+                        .map(str::to_string)
+                        .unwrap_or_else(|| source_name(vars.source))
+                };
+                match placeholder {
+                    "name" => folder_id.push_str(&name()),
+                    "name-slug" => folder_id.push_str(&slug_or_default(&name())),
+                    "source-slug" => {
+                        folder_id.push_str(&slug_or_default(&source_name(vars.source)))
+                    }
+                    "index" => folder_id.push_str(&vars.index.to_string()),
+                    _ => {
+                        return Err(invalid(&format!(
+                            "unknown placeholder '{{{}}}'",
+                            placeholder
+                        )))
+                    }
+                }
+                chars = rest[end + 1..].chars();
+            }
+            '}' => return Err(invalid("unmatched '}'")),
+            c => folder_id.push(c),
+        }
+    }
+    if !folder_id.ends_with('/') {
+        folder_id.push('/');
+    }
+    if !folder_id.starts_with("./") {
+        folder_id.insert_str(0, "./");
+    }
+    Ok(folder_id)
+}
+
+/// Make a folder id unique by appending "-2", "-3", ... to its last segment
+pub fn unique_folder_id(folder_id: &str, taken: impl Fn(&str) -> bool) -> String {
+    if !taken(folder_id) {
+        return folder_id.to_string();
+    }
+    let stem = folder_id.trim_end_matches('/');
+    (2..)
+        .map(|n| format!("{}-{}/", stem, n))
+        .find(|candidate| !taken(candidate))
+        .expect("some suffix is free")
+}
+
+/// Turn text into a lowercase, hyphen-separated slug
+///
+/// Accents are stripped after compatibility decomposition ("Café" becomes
+/// "cafe"); other letters and digits are kept, and every run of anything
+/// else becomes a single '-'. Slugs are at most 64 characters long and may
+/// be empty.
+pub fn slugify(text: &str) -> String {
+    let stripped: String = DecomposingNormalizerBorrowed::new_nfkd()
+        .normalize(text)
+        .chars()
+        .filter(|c| !is_combining_mark(*c))
+        .collect();
+    // Recompose what remains, e.g. Japanese voiced kana
+    let composed = ComposingNormalizerBorrowed::new_nfc().normalize(&stripped);
+    let mut slug = String::new();
+    let mut separate = false;
+    for c in composed.chars() {
+        if c.is_alphanumeric() {
+            if separate && !slug.is_empty() {
+                slug.push('-');
+            }
+            separate = false;
+            slug.extend(c.to_lowercase());
+        } else {
+            separate = true;
+        }
+    }
+    if let Some((end, _)) = slug.char_indices().nth(MAX_SLUG_LEN) {
+        slug.truncate(end);
+        slug.truncate(slug.trim_end_matches('-').len());
+    }
+    slug
+}
+
+/// Name of the root entity of a crate's graph, if it has one
+pub fn root_name(graph: &[Value]) -> Option<&str> {
+    let root_id = graph
+        .iter()
+        .find(|e| is_metadata_descriptor(e))
+        .and_then(|d| d.get("about"))
+        .and_then(extract_id)
+        .unwrap_or(ROOT_ENTITY_ID);
+    let root = graph.iter().find(|e| extract_id(e) == Some(root_id))?;
+    match root.get("name")? {
+        Value::String(name) => Some(name.as_str()),
+        Value::Array(names) => names.iter().find_map(Value::as_str),
+        _ => None,
+    }
+    .filter(|name| !name.trim().is_empty())
+}
+
+fn slug_or_default(text: &str) -> String {
+    let slug = slugify(text);
+    if slug.is_empty() {
+        "crate".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Decoded last path segment of a path or URL, without a metadata file name
+/// or archive extension
+fn source_name(source: &str) -> String {
+    let trimmed = source.trim_end_matches(['/', '\\']);
+    let trimmed = trimmed
+        .strip_suffix(METADATA_DESCRIPTOR_ID)
+        .map(|dir| dir.trim_end_matches(['/', '\\']))
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or(trimmed);
+    let segment = trimmed.rsplit(['/', '\\']).next().unwrap_or(trimmed);
+    let segment = archive_stem(segment).unwrap_or(segment);
+    decode_component(segment).to_string_lossy().into_owned()
+}
+
+/// Whether a character is a combining diacritical mark (accents and the like)
+fn is_combining_mark(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Crème Brûlée: Batch #2"), "creme-brulee-batch-2");
+        assert_eq!(slugify("  --Ünïcode__"), "unicode");
+        assert_eq!(slugify("ﬁle Ⅸ"), "file-ix");
+        assert_eq!(slugify("データ 1"), "データ-1");
+        assert_eq!(slugify("!!!"), "");
+        assert_eq!(slugify(&"ab ".repeat(40)).len(), 64);
+        // Truncation doesn't leave a trailing '-'
+        assert_eq!(slugify(&"abc ".repeat(20)).len(), 63);
+    }
+
+    #[test]
+    fn test_expand_folder_template() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "name": "Sequencing Run 7"}),
+        ];
+        let vars = TemplateVars::from_graph(&graph, "/data/incoming/run7/", 3);
+        assert_eq!(
+            expand_folder_template("{name-slug}-{index}", &vars).unwrap(),
+            "./sequencing-run-7-3/"
+        );
+        assert_eq!(
+            expand_folder_template("./runs/{source-slug}/", &vars).unwrap(),
+            "./runs/run7/"
+        );
+        assert_eq!(
+            expand_folder_template("{{{index}}}/", &vars).unwrap(),
+            "./{3}/"
+        );
+
+        // Unnamed crates fall back to their source
+        let vars = TemplateVars::from_graph(
+            &[],
+            "https://example.org/Crate%20A/ro-crate-metadata.json",
+            1,
+        );
+        assert_eq!(
+            expand_folder_template("{name-slug}/", &vars).unwrap(),
+            "./crate-a/"
+        );
+
+        let vars = TemplateVars::from_graph(&[], "/incoming/Run 8.ZIP", 2);
+        assert_eq!(
+            expand_folder_template("{source-slug}", &vars).unwrap(),
+            "./run-8/"
+        );
+        let vars = TemplateVars::from_graph(&[], "/incoming/Run 9.tar.gz", 2);
+        assert_eq!(
+            expand_folder_template("{source-slug}", &vars).unwrap(),
+            "./run-9/"
+        );
+
+        for template in ["{nmae}/", "{index/", "index}/"] {
+            let err = expand_folder_template(template, &vars).unwrap_err();
+            assert_eq!(err.code(), "invalid_folder_template");
+        }
+    }
+
+    #[test]
+    fn test_unique_folder_id() {
+        let taken = ["./run/", "./run-2/"];
+        assert_eq!(
+            unique_folder_id("./run/", |id| taken.contains(&id)),
+            "./run-3/"
+        );
+        assert_eq!(
+            unique_folder_id("./other/", |id| taken.contains(&id)),
+            "./other/"
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Per-tenant isolation of loading in a shared service
+//!
+//! A service consolidating crates for several tenants in one process must
+//! not let one tenant's private crates or credentials reach another tenant's
+//! consolidation. A [`TenantContext`] holds everything loading may reuse on
+//! a tenant's behalf: its credentials and the documents already fetched with
+//! them, and how it fetches. Loaders made by a context
+//! ([`TenantContext::url_loader`]) fetch only through it, so nothing is
+//! shared between contexts: neither credentials nor fetched documents.
+//!
+//! Tenants decide which URLs are fetched, so a context only fetches from
+//! public addresses, following redirects by hand to check each one, unless
+//! it was allowed a prefix with [`TenantContext::allow_fetches_to`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use reqwest::blocking::Client;
+use reqwest::header::LOCATION;
+use serde_json::Value;
+use url::Url;
+
+use crate::audit::{self, AccessKind};
+use crate::consolidate::{parse_graph, SubcrateLoader, UrlLoader};
+use crate::error::{BoxError, ConsolidateError, IndexError};
+use crate::loader::{
+    fetch_metadata_with, read_to_string_limited, send_with_retries_unredirected, UrlLoaderOptions,
+    MAX_REDIRECTS,
+};
+use crate::sandbox::public_address;
+
+/// Credentials and cache of the tenant a consolidation runs for
+pub struct TenantContext {
+    id: String,
+    /// (URL prefix, bearer token) pairs; a token is only sent to URLs
+    /// starting with its prefix
+    credentials: Vec<(String, String)>,
+    /// How the tenant's documents are fetched
+    options: UrlLoaderOptions,
+    /// Prefixes of URLs that may resolve to any address; all others are
+    /// only fetched from public addresses
+    allowed_prefixes: Vec<String>,
+    /// Documents fetched for this tenant, by URL
+    cache: Mutex<HashMap<String, String>>,
+}
+
+impl TenantContext {
+    /// Context of the tenant `id`, without credentials
+    ///
+    /// The empty id stands for requests made without a tenant. Fetches
+    /// retry and time out as [`UrlLoaderOptions::DEFAULT`] unless given
+    /// other options.
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            credentials: Vec::new(),
+            options: UrlLoaderOptions::DEFAULT,
+            allowed_prefixes: Vec::new(),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Fetch with `options` instead; their credentials and cache are
+    /// ignored, only the tenant's own are used
+    pub fn with_url_options(mut self, options: UrlLoaderOptions) -> Self {
+        self.options = UrlLoaderOptions {
+            auth: None,
+            cache: None,
+            ..options
+        };
+        self
+    }
+
+    /// Send `token` as bearer token with requests to URLs starting with
+    /// `prefix`, e.g. "https://data.example.org/"
+    pub fn with_credentials(mut self, prefix: impl Into<String>, token: impl Into<String>) -> Self {
+        self.credentials.push((prefix.into(), token.into()));
+        self
+    }
+
+    /// Let URLs starting with `prefix` resolve to any address, e.g.
+    /// "http://127.0.0.1:8080/" for crates served next to the service
+    pub fn allow_fetches_to(mut self, prefix: impl Into<String>) -> Self {
+        self.allowed_prefixes.push(prefix.into());
+        self
+    }
+
+    /// The tenant's id
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The token to send with a request to `url`, from the credentials with
+    /// the longest matching prefix
+    fn token_for(&self, url: &str) -> Option<&str> {
+        self.credentials
+            .iter()
+            .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, token)| token.as_str())
+    }
+
+    /// Fetch the body of a URL with the tenant's credentials
+    ///
+    /// Successful responses are cached for the lifetime of the context.
+    /// Redirects are followed by hand, and every URL on the way must
+    /// resolve to public addresses only, unless it starts with an allowed
+    /// prefix (see [`TenantContext::allow_fetches_to`]).
+    pub fn fetch(&self, url: &str) -> Result<String, IndexError> {
+        if let Some(content) = self.cache.lock().unwrap().get(url) {
+            return Ok(content.clone());
+        }
+        let load_error = |reason: String, source: Option<BoxError>| IndexError::LoadError {
+            path: url.to_string(),
+            reason,
+            source,
+        };
+        let mut location = url.to_string();
+        let mut redirects = 0;
+        let response = loop {
+            let client = self.client_for(&location)?;
+            let request = || {
+                let request = client.get(&location);
+                match self.token_for(&location) {
+                    Some(token) => request.bearer_auth(token),
+                    None => request,
+                }
+            };
+            // Only the tenant's own credentials are sent
+            let response = send_with_retries_unredirected(&location, &self.options, request)?;
+            if !response.status().is_redirection() {
+                break response;
+            }
+            if redirects == MAX_REDIRECTS {
+                return Err(load_error("too many redirects".to_string(), None));
+            }
+            redirects += 1;
+            let next = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|next| next.to_str().ok())
+                .and_then(|next| response.url().join(next).ok())
+                .ok_or_else(|| load_error(format!("invalid redirect from {}", location), None))?;
+            location = next.to_string();
+        };
+        let content = read_to_string_limited(response)
+            .map_err(|e| load_error(format!("Failed to read response: {}", e), Some(e.into())))?;
+        audit::record(AccessKind::Url, url, None, content.as_bytes());
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), content.clone());
+        Ok(content)
+    }
+
+    /// Client for a request to `url`, not following redirects
+    ///
+    /// Unless `url` starts with an allowed prefix, the client connects
+    /// only to the public address its host was checked to resolve to.
+    fn client_for(&self, url: &str) -> Result<Client, IndexError> {
+        let violation = |reason: String| IndexError::SandboxViolation {
+            location: url.to_string(),
+            reason,
+        };
+        let mut client = Client::builder().redirect(reqwest::redirect::Policy::none());
+        if !self
+            .allowed_prefixes
+            .iter()
+            .any(|prefix| url.starts_with(prefix.as_str()))
+        {
+            let parsed = Url::parse(url).map_err(|e| violation(e.to_string()))?;
+            let (host, addr) = public_address(&parsed).map_err(violation)?;
+            client = client.resolve(&host, addr);
+        }
+        client.build().map_err(|e| IndexError::LoadError {
+            path: url.to_string(),
+            reason: format!("HTTP client failed: {}", e),
+            source: Some(e.into()),
+        })
+    }
+
+    /// Load the @graph of the crate at `url`, its metadata file or directory
+    pub fn load_graph(&self, url: &str) -> Result<Vec<Value>, ConsolidateError> {
+        let (metadata_url, content) = fetch_metadata_with(url, |url| self.fetch(url))?;
+        parse_graph(&content, &metadata_url)
+    }
+
+    /// Subcrate loader resolving subcrates like [`UrlLoader`] against the
+    /// crate at `root_url`, fetching them for this tenant
+    pub fn url_loader(&self, root_url: &str) -> TenantUrlLoader<'_> {
+        TenantUrlLoader {
+            tenant: self,
+            urls: UrlLoader::from_metadata_url(root_url),
+        }
+    }
+}
+
+impl Default for TenantContext {
+    fn default() -> Self {
+        Self::new("")
+    }
+}
+
+impl fmt::Debug for TenantContext {
+    // Keep tokens out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefixes: Vec<&str> = self.credentials.iter().map(|(p, _)| p.as_str()).collect();
+        f.debug_struct("TenantContext")
+            .field("id", &self.id)
+            .field("credentials", &prefixes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Subcrate loader fetching remote subcrates for a tenant
+pub struct TenantUrlLoader<'a> {
+    tenant: &'a TenantContext,
+    urls: UrlLoader,
+}
+
+impl SubcrateLoader for TenantUrlLoader<'_> {
+    fn load(
+        &self,
+        subcrate_id: &str,
+        _parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Result<Vec<Value>, ConsolidateError> {
+        let url = self.urls.subcrate_url(subcrate_id, subcrate_entity);
+        self.tenant.load_graph(&url)
+    }
+
+    fn location(
+        &self,
+        subcrate_id: &str,
+        _parent_namespace: &str,
+        subcrate_entity: Option<&Value>,
+    ) -> Option<String> {
+        Some(self.urls.subcrate_url(subcrate_id, subcrate_entity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_context() {
+        let tenant = TenantContext::new("a")
+            .with_credentials("https://data.example.org/", "general")
+            .with_credentials("https://data.example.org/private/", "private");
+        assert_eq!(tenant.id(), "a");
+        assert_eq!(
+            tenant.token_for("https://data.example.org/private/crate/"),
+            Some("private")
+        );
+        assert_eq!(
+            tenant.token_for("https://data.example.org/public/"),
+            Some("general")
+        );
+        // Tokens are not sent to other hosts
+        assert_eq!(tenant.token_for("https://data.example.org.evil/"), None);
+        assert_eq!(tenant.token_for("https://other.example.org/"), None);
+        assert!(!format!("{:?}", tenant).contains("private\""));
+    }
+
+    #[test]
+    fn test_tenant_caches_are_separate() {
+        // Nothing listens on port 1, so only a cached document loads
+        let url = "http://127.0.0.1:1/private/ro-crate-metadata.json";
+        let metadata = r#"{"@graph": [
+            {"@id": "ro-crate-metadata.json", "about": {"@id": "./"}},
+            {"@id": "./", "@type": "Dataset"}
+        ]}"#;
+        let a = TenantContext::new("a");
+        a.cache
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), metadata.to_string());
+        assert_eq!(a.load_graph(url).unwrap().len(), 2);
+
+        // Tenant b can't see what was fetched for a
+        let b = TenantContext::new("b");
+        assert!(b.cache.lock().unwrap().is_empty());
+        let loader = b.url_loader("http://127.0.0.1:1/");
+        let subcrate = serde_json::json!({"@id": "./private/", "subjectOf": {"@id": url}});
+        assert!(loader.load("./private/", "", Some(&subcrate)).is_err());
+    }
+
+    #[test]
+    fn test_tenant_fetches_from_public_addresses() {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let prefix = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let response = "HTTP/1.1 302 Found\r\n\
+                Location: http://169.254.169.254/ro-crate-metadata.json\r\n\
+                Content-Length: 0\r\nConnection: close\r\n\r\n";
+            reader.into_inner().write_all(response.as_bytes()).unwrap();
+        });
+        let url = format!("{}ro-crate-metadata.json", prefix);
+
+        let err = TenantContext::new("a").fetch(&url).unwrap_err();
+        assert!(
+            matches!(err, IndexError::SandboxViolation { .. }),
+            "{}",
+            err
+        );
+
+        // An allowed prefix doesn't extend to where it redirects
+        let tenant = TenantContext::new("a").allow_fetches_to(prefix);
+        let err = tenant.fetch(&url).unwrap_err();
+        match err {
+            IndexError::SandboxViolation { location, .. } => {
+                assert_eq!(location, "http://169.254.169.254/ro-crate-metadata.json")
+            }
+            err => panic!("{}", err),
+        }
+    }
+}
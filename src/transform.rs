@@ -3,11 +3,31 @@
 //! Handles converting subcrate root entities into Subcrate-typed folder
 //! entities during consolidation.
 
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 
 use crate::collect::extract_types;
 use crate::merge::union_merge_values;
-use crate::vocab::{CONSOLIDATED_ENTITIES_SHORT, ROCRATE_PROFILE_PREFIX, SUBCRATE_TYPE_SHORT};
+use crate::vocab::{
+    CONSOLIDATED_ENTITIES_SHORT, ROCRATE_PROFILE_PREFIX, ROCRATE_SPEC_ID, ROOT_ENTITY_ID,
+    SUBCRATE_TYPE_SHORT,
+};
+
+/// Which side wins a property both the parent's reference to a subcrate and
+/// the subcrate's root entity set
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReferenceMergePolicy {
+    /// Keep the values of both
+    #[default]
+    Union,
+    /// Keep the parent's value
+    ParentWins,
+    /// Keep the subcrate root's value
+    ChildWins,
+}
 
 /// Create a Subcrate-typed folder entity from a subcrate's root
 ///
@@ -27,12 +47,35 @@ use crate::vocab::{CONSOLIDATED_ENTITIES_SHORT, ROCRATE_PROFILE_PREFIX, SUBCRATE
 /// * `subcrate_root` - The subcrate's root entity ("./")
 /// * `consolidated_entity_ids` - List of all @ids of entities that came from this subcrate
 /// * `add_subcrate_type` - Whether to add the Subcrate type
+///
+/// Properties set by both the parent and the subcrate keep the values of
+/// both; see [`create_subcrate_folder_with`] for the other policies.
 pub fn create_subcrate_folder(
     folder_id: &str,
     parent_folder: Option<&Value>,
     subcrate_root: &Value,
     consolidated_entity_ids: Vec<String>,
     add_subcrate_type: bool,
+) -> Value {
+    create_subcrate_folder_with(
+        folder_id,
+        parent_folder,
+        subcrate_root,
+        consolidated_entity_ids,
+        add_subcrate_type,
+        ReferenceMergePolicy::default(),
+    )
+}
+
+/// Like [`create_subcrate_folder`], merging properties set by both the
+/// parent and the subcrate as `merge` says
+pub fn create_subcrate_folder_with(
+    folder_id: &str,
+    parent_folder: Option<&Value>,
+    subcrate_root: &Value,
+    consolidated_entity_ids: Vec<String>,
+    add_subcrate_type: bool,
+    merge: ReferenceMergePolicy,
 ) -> Value {
     let mut result = Map::new();
 
@@ -65,7 +108,11 @@ pub fn create_subcrate_folder(
 
             match result.get(key) {
                 Some(existing) => {
-                    let merged = union_merge_values(existing, value);
+                    let merged = match merge {
+                        ReferenceMergePolicy::Union => union_merge_values(existing, value),
+                        ReferenceMergePolicy::ParentWins => continue,
+                        ReferenceMergePolicy::ChildWins => value.clone(),
+                    };
                     result.insert(key.clone(), merged);
                 }
                 None => {
@@ -113,6 +160,45 @@ pub fn create_subcrate_folder(
     Value::Object(result)
 }
 
+/// Add the types configured for subcrates like `subcrate_root` to their
+/// folder
+///
+/// `subcrate_types` maps a type or a `conformsTo` profile of the subcrate's
+/// root entity to the types its folder gets beyond "Dataset" and "Subcrate",
+/// e.g. a run crate profile to "LabProcess".
+pub fn add_specialized_types(
+    folder: &mut Value,
+    subcrate_root: &Value,
+    subcrate_types: &BTreeMap<String, Vec<String>>,
+) {
+    if subcrate_types.is_empty() {
+        return;
+    }
+    let profiles = match subcrate_root.get("conformsTo") {
+        Some(Value::Array(values)) => values.iter().collect(),
+        Some(value) => vec![value],
+        None => vec![],
+    };
+    let natures = extract_types(subcrate_root).into_iter().chain(
+        profiles
+            .into_iter()
+            .filter_map(|v| v.get("@id").unwrap_or(v).as_str())
+            .map(String::from),
+    );
+    let mut types = extract_types(folder);
+    let before = types.len();
+    for nature in natures {
+        for t in subcrate_types.get(&nature).into_iter().flatten() {
+            if !types.contains(t) {
+                types.push(t.clone());
+            }
+        }
+    }
+    if types.len() > before {
+        folder["@type"] = json!(types);
+    }
+}
+
 /// Check if a property should be stripped during subcrate transformation
 fn should_strip_property(key: &str, value: &Value) -> bool {
     match key {
@@ -217,6 +303,50 @@ pub fn update_root_has_part(root: &mut Value, subcrate_folder_ids: &[String]) {
     }
 }
 
+/// Give the metadata descriptor a new @id
+///
+/// Points `about` at the root entity and adds `conformsTo` if it is missing,
+/// so the renamed descriptor still describes the crate. Returns the previous @id.
+pub fn rename_descriptor(descriptor: &mut Value, descriptor_id: &str) -> Option<String> {
+    let obj = descriptor.as_object_mut()?;
+    let previous = obj.insert("@id".to_string(), json!(descriptor_id));
+    obj.insert("about".to_string(), json!({"@id": ROOT_ENTITY_ID}));
+    obj.entry("conformsTo")
+        .or_insert_with(|| json!({"@id": ROCRATE_SPEC_ID}));
+    match previous {
+        Some(Value::String(id)) => Some(id),
+        _ => None,
+    }
+}
+
+/// Add a reference to `profile_id` to an entity's `conformsTo`, unless present
+pub fn add_conforms_to(entity: &mut Value, profile_id: &str) {
+    add_reference(entity, "conformsTo", profile_id);
+}
+
+/// Add a reference to `id` to a property of an entity, unless present
+pub fn add_reference(entity: &mut Value, property: &str, id: &str) {
+    let Some(obj) = entity.as_object_mut() else {
+        return;
+    };
+    let reference = json!({"@id": id});
+    match obj.get_mut(property) {
+        None => {
+            obj.insert(property.to_string(), reference);
+        }
+        Some(Value::Array(values)) => {
+            if !values.contains(&reference) {
+                values.push(reference);
+            }
+        }
+        Some(existing) => {
+            if *existing != reference {
+                *existing = json!([existing.take(), reference]);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -298,6 +428,50 @@ mod tests {
         assert!(obj.contains_key("author"));
     }
 
+    #[test]
+    fn test_reference_merge_policy() {
+        let parent_folder = json!({
+            "@id": "./experiments/",
+            "@type": "Dataset",
+            "name": "Experiments Folder",
+            "license": {"@id": "https://spdx.org/licenses/MIT"}
+        });
+        let subcrate_root = json!({
+            "@id": "./",
+            "@type": "Dataset",
+            "name": "Experiments",
+            "description": "Detailed description from subcrate"
+        });
+        let folder = |merge| {
+            create_subcrate_folder_with(
+                "./experiments/",
+                Some(&parent_folder),
+                &subcrate_root,
+                vec![],
+                true,
+                merge,
+            )
+        };
+
+        let union = folder(ReferenceMergePolicy::Union);
+        assert_eq!(union["name"], json!(["Experiments Folder", "Experiments"]));
+
+        let parent_wins = folder(ReferenceMergePolicy::ParentWins);
+        assert_eq!(parent_wins["name"], "Experiments Folder");
+
+        let child_wins = folder(ReferenceMergePolicy::ChildWins);
+        assert_eq!(child_wins["name"], "Experiments");
+
+        // Properties only one side sets are kept either way
+        for merged in [parent_wins, child_wins] {
+            assert_eq!(
+                merged["license"],
+                json!({"@id": "https://spdx.org/licenses/MIT"})
+            );
+            assert_eq!(merged["description"], "Detailed description from subcrate");
+        }
+    }
+
     #[test]
     fn test_strip_rocrate_properties() {
         let mut entity = json!({
@@ -349,4 +523,36 @@ mod tests {
         // Should be just "Dataset" as a string, not array
         assert_eq!(types, &json!("Dataset"));
     }
+
+    #[test]
+    fn test_rename_descriptor() {
+        let mut descriptor = json!({
+            "@id": "ro-crate-metadata.json",
+            "@type": "CreativeWork",
+            "about": {"@id": "./"}
+        });
+
+        let previous = rename_descriptor(&mut descriptor, "mycrate-ro-crate-metadata.json");
+
+        assert_eq!(previous.as_deref(), Some("ro-crate-metadata.json"));
+        assert_eq!(descriptor["@id"], "mycrate-ro-crate-metadata.json");
+        assert_eq!(descriptor["about"], json!({"@id": "./"}));
+        assert_eq!(descriptor["conformsTo"], json!({"@id": ROCRATE_SPEC_ID}));
+    }
+
+    #[test]
+    fn test_add_conforms_to() {
+        let profile = "https://example.org/profile";
+        let mut descriptor = json!({"@id": "ro-crate-metadata.json"});
+        add_conforms_to(&mut descriptor, profile);
+        assert_eq!(descriptor["conformsTo"], json!({"@id": profile}));
+
+        let mut descriptor = json!({"conformsTo": {"@id": ROCRATE_SPEC_ID}});
+        add_conforms_to(&mut descriptor, profile);
+        add_conforms_to(&mut descriptor, profile);
+        assert_eq!(
+            descriptor["conformsTo"],
+            json!([{"@id": ROCRATE_SPEC_ID}, {"@id": profile}])
+        );
+    }
 }
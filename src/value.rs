@@ -0,0 +1,210 @@
+//! Comparing property values
+//!
+//! Consolidation takes two values to be the same if they are equal as JSON,
+//! when deduplicating merged arrays and dropping identical copies of
+//! entities. With [`ValueComparison::Canonical`] (see
+//! [`ConsolidateOptions::value_comparison`]) it compares their canonical
+//! forms instead. The canonical form of a value
+//!
+//! - has its @ids, and those of nested references, in Unicode NFC (see
+//!   [`normalize_id`]); `contentUrl` values are left as they are
+//! - has plain value objects (`{"@value": "x"}`, without type or language)
+//!   replaced by their value
+//! - compares objects regardless of key order
+//!
+//! [`values_equal`] and [`value_hash`] compare values this way without
+//! building their canonical forms. Which entities are merged is still
+//! decided by their @ids as written, normalized only with
+//! [`ConsolidateOptions::normalize_unicode`] (the default).
+//!
+//! [`ConsolidateOptions::normalize_unicode`]: crate::ConsolidateOptions::normalize_unicode
+//! [`ConsolidateOptions::value_comparison`]: crate::ConsolidateOptions::value_comparison
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::id::normalize_id;
+
+/// When merging takes two values to be the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueComparison {
+    /// If they are equal as JSON
+    #[default]
+    Exact,
+    /// If their canonical forms are equal (see [`values_equal`])
+    Canonical,
+}
+
+impl ValueComparison {
+    /// Whether `a` and `b` are the same
+    pub fn equal(self, a: &Value, b: &Value) -> bool {
+        match self {
+            Self::Exact => a == b,
+            Self::Canonical => values_equal(a, b),
+        }
+    }
+
+    /// Hash of `value`, equal for values that are the same
+    pub fn hash(self, value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hash_value(value, self == Self::Canonical, &mut hasher);
+        hasher.finish()
+    }
+}
+
+/// The canonical form of a value
+pub fn canonical_value(value: &Value) -> Value {
+    match plain(value) {
+        Value::Object(obj) => Value::Object(
+            obj.iter()
+                .map(|(key, value)| {
+                    let value = match (key.as_str(), value) {
+                        ("@id", Value::String(id)) => Value::String(normalize_id(id).into_owned()),
+                        ("contentUrl", value) => value.clone(),
+                        (_, value) => canonical_value(value),
+                    };
+                    (key.clone(), value)
+                })
+                .collect::<Map<_, _>>(),
+        ),
+        Value::Array(arr) => Value::Array(arr.iter().map(canonical_value).collect()),
+        value => value.clone(),
+    }
+}
+
+/// Check if two values have the same canonical form
+pub fn values_equal(a: &Value, b: &Value) -> bool {
+    match (plain(a), plain(b)) {
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter().all(|(key, va)| {
+                    b.get(key).is_some_and(|vb| match (key.as_str(), va, vb) {
+                        ("@id", Value::String(a), Value::String(b)) => {
+                            a == b || normalize_id(a) == normalize_id(b)
+                        }
+                        ("contentUrl", va, vb) => va == vb,
+                        (_, va, vb) => values_equal(va, vb),
+                    })
+                })
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| values_equal(a, b))
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Hash of a value's canonical form
+///
+/// Values equal by [`values_equal`] hash equally.
+pub fn value_hash(value: &Value) -> u64 {
+    ValueComparison::Canonical.hash(value)
+}
+
+/// The value of a plain value object, or `value` itself
+fn plain(value: &Value) -> &Value {
+    match value {
+        Value::Object(obj) if obj.len() == 1 => obj.get("@value").unwrap_or(value),
+        value => value,
+    }
+}
+
+/// Hash `value`, or its canonical form if `canonical`
+fn hash_value(value: &Value, canonical: bool, hasher: &mut DefaultHasher) {
+    let value = if canonical { plain(value) } else { value };
+    match value {
+        Value::Null => 0u8.hash(hasher),
+        Value::Bool(b) => {
+            1u8.hash(hasher);
+            b.hash(hasher);
+        }
+        Value::Number(n) => {
+            2u8.hash(hasher);
+            n.to_string().hash(hasher);
+        }
+        Value::String(s) => {
+            3u8.hash(hasher);
+            s.hash(hasher);
+        }
+        Value::Array(arr) => {
+            4u8.hash(hasher);
+            arr.len().hash(hasher);
+            for item in arr {
+                hash_value(item, canonical, hasher);
+            }
+        }
+        Value::Object(obj) => {
+            5u8.hash(hasher);
+            obj.len().hash(hasher);
+            let mut keys: Vec<&String> = obj.keys().collect();
+            keys.sort();
+            for key in keys {
+                key.hash(hasher);
+                match (key.as_str(), &obj[key]) {
+                    ("@id", Value::String(id)) if canonical => {
+                        3u8.hash(hasher);
+                        normalize_id(id).hash(hasher);
+                    }
+                    (_, value) => hash_value(value, canonical, hasher),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_values_equal() {
+        let pairs = [
+            (json!("x"), json!({"@value": "x"})),
+            (
+                json!({"@id": "./caf\u{e9}"}),
+                json!({"@id": "./cafe\u{301}"}),
+            ),
+            (
+                json!({"a": 1, "b": [{"@value": true}]}),
+                json!({"b": [true], "a": 1}),
+            ),
+        ];
+        for (a, b) in &pairs {
+            assert!(values_equal(a, b), "{} == {}", a, b);
+            assert_eq!(value_hash(a), value_hash(b));
+            assert_eq!(canonical_value(a), canonical_value(b));
+        }
+
+        let different = [
+            (json!("x"), json!({"@value": "x", "@language": "en"})),
+            (
+                json!({"contentUrl": "caf\u{e9}"}),
+                json!({"contentUrl": "cafe\u{301}"}),
+            ),
+            (json!(["a", "b"]), json!(["b", "a"])),
+            (json!(1), json!("1")),
+        ];
+        for (a, b) in &different {
+            assert!(!values_equal(a, b), "{} != {}", a, b);
+            assert_ne!(canonical_value(a), canonical_value(b));
+        }
+
+        // Only canonical comparison takes the pairs above to be the same
+        for (a, b) in &pairs[..2] {
+            assert!(!ValueComparison::Exact.equal(a, b), "{} != {}", a, b);
+            assert!(ValueComparison::Canonical.equal(a, b), "{} == {}", a, b);
+        }
+        let (a, b) = (json!({"a": 1, "b": 2}), json!({"b": 2, "a": 1}));
+        assert!(ValueComparison::Exact.equal(&a, &b));
+        assert_eq!(
+            ValueComparison::Exact.hash(&a),
+            ValueComparison::Exact.hash(&b)
+        );
+    }
+}
@@ -20,15 +20,58 @@ pub const CONSOLIDATED_ENTITIES: &str = "https://w3id.org/ro/terms/consolidate/c
 /// Short form of consolidatedEntities property
 pub const CONSOLIDATED_ENTITIES_SHORT: &str = "consolidatedEntities";
 
+/// Property on a Subcrate loaded from a remote metadata file, referencing
+/// that file
+pub const CONSOLIDATED_FROM: &str = "https://w3id.org/ro/terms/consolidate/consolidatedFrom";
+
+/// Short form of consolidatedFrom property
+pub const CONSOLIDATED_FROM_SHORT: &str = "consolidatedFrom";
+
+/// Property of the consolidation CreateAction holding the options it ran
+/// with, as a JSON string
+pub const CONSOLIDATION_OPTIONS: &str =
+    "https://w3id.org/ro/terms/consolidate/consolidationOptions";
+
+/// Short form of consolidationOptions property
+pub const CONSOLIDATION_OPTIONS_SHORT: &str = "consolidationOptions";
+
+/// Property of the consolidation CreateAction listing the files and URLs it
+/// read with their hashes, as a JSON string
+pub const CONSOLIDATION_INPUTS: &str = "https://w3id.org/ro/terms/consolidate/consolidationInputs";
+
+/// Short form of consolidationInputs property
+pub const CONSOLIDATION_INPUTS_SHORT: &str = "consolidationInputs";
+
+/// ID of the CreateAction describing the consolidation run
+pub const CONSOLIDATION_ACTION_ID: &str = "#rocrate-consolidate";
+
+/// ID of the consolidating software, the instrument of that action
+pub const CONSOLIDATION_SOFTWARE_ID: &str = "https://github.com/arunaengine/rocrate-merger";
+
 /// RO-Crate conformsTo URL prefix (to detect subcrate references)
 pub const ROCRATE_PROFILE_PREFIX: &str = "https://w3id.org/ro/crate/";
 
 /// Standard metadata descriptor filename
 pub const METADATA_DESCRIPTOR_ID: &str = "ro-crate-metadata.json";
 
+/// Standard HTML preview filename
+pub const PREVIEW_ID: &str = "ro-crate-preview.html";
+
+/// RO-Crate specification the consolidated document conforms to
+pub const ROCRATE_SPEC_ID: &str = "https://w3id.org/ro/crate/1.1";
+
+/// Profile that consolidated crates conform to, defining the vocabulary below
+pub const CONSOLIDATION_PROFILE_ID: &str = "https://w3id.org/ro/terms/consolidate/profile/0.1";
+
+/// Version of the consolidation profile
+pub const CONSOLIDATION_PROFILE_VERSION: &str = "0.1";
+
 /// Root entity ID
 pub const ROOT_ENTITY_ID: &str = "./";
 
+/// ID of the entity embedding the consolidated id resolution map
+pub const RESOLVE_ENTITY_ID: &str = "#ro-crate-resolve";
+
 /// Context extension for consolidation vocabulary
 /// Should be added to the RO-Crate context when using consolidation features
 pub fn context_extension() -> serde_json::Value {
@@ -38,7 +81,105 @@ pub fn context_extension() -> serde_json::Value {
             "@id": CONSOLIDATED_ENTITIES,
             "@container": "@set",
             "@type": "@id"
-        }
+        },
+        "consolidatedFrom": {
+            "@id": CONSOLIDATED_FROM,
+            "@type": "@id"
+        },
+        "consolidationOptions": CONSOLIDATION_OPTIONS,
+        "consolidationInputs": CONSOLIDATION_INPUTS
+    })
+}
+
+/// The consolidation profile as a Profile Crate
+///
+/// Defines `Subcrate` as an RDFS class and `consolidatedEntities`,
+/// `consolidatedFrom`, `consolidationOptions` and `consolidationInputs` as
+/// properties, collected in a DefinedTermSet. Publish the result at
+/// [`CONSOLIDATION_PROFILE_ID`] so the terms resolve.
+pub fn profile_crate() -> serde_json::Value {
+    serde_json::json!({
+        "@context": [
+            "https://w3id.org/ro/crate/1.1/context",
+            {
+                "rdf": "http://www.w3.org/1999/02/22-rdf-syntax-ns#",
+                "rdfs": "http://www.w3.org/2000/01/rdf-schema#",
+                "Profile": "http://www.w3.org/ns/dx/prof/Profile"
+            }
+        ],
+        "@graph": [
+            {
+                "@id": METADATA_DESCRIPTOR_ID,
+                "@type": "CreativeWork",
+                "about": {"@id": ROOT_ENTITY_ID},
+                "conformsTo": {"@id": ROCRATE_SPEC_ID}
+            },
+            {
+                "@id": ROOT_ENTITY_ID,
+                "@type": ["Dataset", "Profile"],
+                "identifier": CONSOLIDATION_PROFILE_ID,
+                "name": "RO-Crate Consolidation Profile",
+                "description": "Crates conforming to this profile were consolidated from a hierarchy of RO-Crates into a single metadata file. Folders that were standalone crates are typed Subcrate and list the entities that came from them in consolidatedEntities.",
+                "version": CONSOLIDATION_PROFILE_VERSION,
+                "hasPart": [{"@id": CONSOLIDATE_NS}]
+            },
+            {
+                "@id": CONSOLIDATE_NS,
+                "@type": "DefinedTermSet",
+                "name": "Consolidation vocabulary",
+                "hasDefinedTerm": [
+                    {"@id": SUBCRATE_TYPE},
+                    {"@id": CONSOLIDATED_ENTITIES},
+                    {"@id": CONSOLIDATED_FROM},
+                    {"@id": CONSOLIDATION_OPTIONS},
+                    {"@id": CONSOLIDATION_INPUTS}
+                ]
+            },
+            {
+                "@id": SUBCRATE_TYPE,
+                "@type": "rdfs:Class",
+                "rdfs:label": SUBCRATE_TYPE_SHORT,
+                "rdfs:comment": "A Dataset that was a standalone RO-Crate before it was consolidated into its parent crate.",
+                "rdfs:subClassOf": {"@id": "http://schema.org/Dataset"},
+                "inDefinedTermSet": {"@id": CONSOLIDATE_NS}
+            },
+            {
+                "@id": CONSOLIDATED_ENTITIES,
+                "@type": "rdf:Property",
+                "rdfs:label": CONSOLIDATED_ENTITIES_SHORT,
+                "rdfs:comment": "An entity that originated from the metadata of this Subcrate.",
+                "domainIncludes": {"@id": SUBCRATE_TYPE},
+                "rangeIncludes": {"@id": "http://schema.org/Thing"},
+                "inDefinedTermSet": {"@id": CONSOLIDATE_NS}
+            },
+            {
+                "@id": CONSOLIDATED_FROM,
+                "@type": "rdf:Property",
+                "rdfs:label": CONSOLIDATED_FROM_SHORT,
+                "rdfs:comment": "The remote metadata file this Subcrate was loaded from.",
+                "domainIncludes": {"@id": SUBCRATE_TYPE},
+                "rangeIncludes": {"@id": "http://schema.org/URL"},
+                "inDefinedTermSet": {"@id": CONSOLIDATE_NS}
+            },
+            {
+                "@id": CONSOLIDATION_OPTIONS,
+                "@type": "rdf:Property",
+                "rdfs:label": CONSOLIDATION_OPTIONS_SHORT,
+                "rdfs:comment": "The options a consolidation ran with, as a JSON object serialized to a string.",
+                "domainIncludes": {"@id": "http://schema.org/CreateAction"},
+                "rangeIncludes": {"@id": "http://schema.org/Text"},
+                "inDefinedTermSet": {"@id": CONSOLIDATE_NS}
+            },
+            {
+                "@id": CONSOLIDATION_INPUTS,
+                "@type": "rdf:Property",
+                "rdfs:label": CONSOLIDATION_INPUTS_SHORT,
+                "rdfs:comment": "The files and URLs a consolidation read, with their sizes and SHA-256 hashes, as a JSON array serialized to a string.",
+                "domainIncludes": {"@id": "http://schema.org/CreateAction"},
+                "rangeIncludes": {"@id": "http://schema.org/Text"},
+                "inDefinedTermSet": {"@id": CONSOLIDATE_NS}
+            }
+        ]
     })
 }
 
@@ -52,4 +193,19 @@ mod tests {
         assert!(ext.get("Subcrate").is_some());
         assert!(ext.get("consolidatedEntities").is_some());
     }
+
+    #[test]
+    fn test_profile_crate() {
+        let profile = profile_crate();
+        let graph = profile["@graph"].as_array().unwrap();
+        assert_eq!(graph[0]["@id"], METADATA_DESCRIPTOR_ID);
+        assert_eq!(graph[1]["identifier"], CONSOLIDATION_PROFILE_ID);
+
+        // Every term of the context extension is defined
+        for (term, definition) in context_extension().as_object().unwrap() {
+            let id = definition.get("@id").unwrap_or(definition);
+            let entity = graph.iter().find(|e| &e["@id"] == id).unwrap();
+            assert_eq!(entity["rdfs:label"], term.as_str());
+        }
+    }
 }
@@ -0,0 +1,490 @@
+//! Canonical forms of well-known contextual entities
+//!
+//! Crates describe the same license, organization or profile in many ways:
+//! `http://spdx.org/licenses/MIT`, `https://opensource.org/licenses/MIT`,
+//! with a name or without. An [`EntityLibrary`] knows the canonical entity
+//! of each, and as a post-processor replaces every variant in the
+//! consolidated graph with it, pointing references at its id.
+//!
+//! [`EntityLibrary::builtin`] holds common licenses (by SPDX id), a few
+//! organizations and the RO-Crate specifications and profiles. Libraries are
+//! JSON arrays of `{"entity": {...}, "aliases": ["<id>", ...], "labels":
+//! ["<name>", ...]}` entries. Ids match regardless of case, scheme, a leading
+//! "www.", a trailing '/' or ".html", so only other spellings need to be
+//! listed as aliases.
+//!
+//! [`NormalizeLicenses`] also replaces license strings such as "CC-BY-4.0" or
+//! "Creative Commons Attribution 4.0" with references to the canonical
+//! license. Strings match an entity's `identifier`, `name` or labels,
+//! ignoring case and punctuation.
+
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::collect::extract_id;
+use crate::consolidate::ConsolidateResult;
+use crate::error::ConsolidateError;
+use crate::postprocess::PostProcessor;
+
+/// The built-in library
+const BUILTIN: &str = include_str!("wellknown.json");
+
+/// An entry of a library document
+#[derive(Debug, Deserialize)]
+struct LibraryEntry {
+    entity: Value,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+/// Canonical entities, by the ids of their variants
+#[derive(Debug, Clone, Default)]
+pub struct EntityLibrary {
+    /// Canonical entities by their id
+    entities: HashMap<String, Value>,
+    /// Canonical id by the match key of each id and alias
+    keys: HashMap<String, String>,
+    /// Canonical id by the label key of each identifier, name and label
+    labels: HashMap<String, String>,
+}
+
+impl EntityLibrary {
+    /// An empty library
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The library shipped with this crate
+    pub fn builtin() -> Self {
+        Self::new()
+            .with_json(BUILTIN)
+            .expect("the built-in entity library is valid")
+    }
+
+    /// Add the entries of the library document `json`, replacing entities
+    /// with the same ids or aliases
+    pub fn with_json(mut self, json: &str) -> Result<Self, ConsolidateError> {
+        let entries: Vec<LibraryEntry> = serde_json::from_str(json).map_err(|e| {
+            ConsolidateError::InvalidStructure(format!("Invalid entity library: {}", e))
+        })?;
+        for entry in entries {
+            let id = self.insert(entry.entity, entry.aliases)?;
+            for label in &entry.labels {
+                self.labels.insert(label_key(label), id.clone());
+            }
+        }
+        Ok(self)
+    }
+
+    /// Add the entries of the library document at `path`
+    pub fn with_file(self, path: &Path) -> Result<Self, ConsolidateError> {
+        let content = fs::read_to_string(path).map_err(|e| ConsolidateError::LoadError {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+            source: Some(e.into()),
+        })?;
+        self.with_json(&content)
+    }
+
+    /// Add `entity` as the canonical form of its id and `aliases`, returning
+    /// its id
+    ///
+    /// The entity's `identifier` and `name` become its labels.
+    pub fn insert(
+        &mut self,
+        entity: Value,
+        aliases: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<String, ConsolidateError> {
+        let id = extract_id(&entity).and_then(|id| match_key(id).map(|key| (id.to_string(), key)));
+        let Some((id, key)) = id else {
+            return Err(ConsolidateError::InvalidStructure(format!(
+                "Library entity {} has no absolute HTTP(S) @id",
+                entity
+            )));
+        };
+        self.keys.insert(key, id.clone());
+        for alias in aliases {
+            let alias = alias.into();
+            let key = match_key(&alias).ok_or_else(|| {
+                ConsolidateError::InvalidStructure(format!(
+                    "Library alias {} is not an HTTP(S) URL",
+                    alias
+                ))
+            })?;
+            self.keys.insert(key, id.clone());
+        }
+        for property in ["identifier", "name"] {
+            if let Some(label) = entity.get(property).and_then(Value::as_str) {
+                self.labels.insert(label_key(label), id.clone());
+            }
+        }
+        self.entities.insert(id.clone(), entity);
+        Ok(id)
+    }
+
+    /// Number of canonical entities
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    /// Check if the library has no entities
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    /// The canonical entity `id` is a variant of, if any
+    pub fn canonical(&self, id: &str) -> Option<&Value> {
+        self.entities.get(self.keys.get(&match_key(id)?)?)
+    }
+
+    /// The canonical entity with the identifier, name or label `label`, if any
+    pub fn by_label(&self, label: &str) -> Option<&Value> {
+        self.entities.get(self.labels.get(&label_key(label))?)
+    }
+
+    /// The canonical id of `id`, if it's one of `only` (or any, for None)
+    fn canonical_id(&self, id: &str, only: Option<&HashSet<String>>) -> Option<&str> {
+        let canonical = self.canonical(id).and_then(extract_id)?;
+        match only {
+            Some(only) if !only.contains(canonical) => None,
+            _ => Some(canonical),
+        }
+    }
+
+    /// Replace the variants of the canonical entities in `only` (or of all,
+    /// for None) in `result` and point references at them
+    fn replace_variants(&self, result: &mut ConsolidateResult, only: Option<&HashSet<String>>) {
+        // Point every matching @id, the entities' own included, at the
+        // canonical id
+        for entity in &mut result.graph {
+            canonicalize_references(entity, &|id| self.canonical_id(id, only));
+        }
+        for origin in &mut result.origins {
+            if let Some(canonical) = self.canonical_id(&origin.id, only) {
+                origin.id = canonical.to_string();
+            }
+        }
+
+        // Replace the variants, keeping the first of each
+        let mut seen = HashSet::new();
+        result.graph.retain_mut(|entity| {
+            let Some(canonical) = extract_id(entity)
+                .filter(|id| only.is_none_or(|only| only.contains(*id)))
+                .and_then(|id| self.entities.get(id))
+            else {
+                return true;
+            };
+            if !seen.insert(extract_id(canonical).unwrap_or_default().to_string()) {
+                return false;
+            }
+            *entity = canonical.clone();
+            true
+        });
+    }
+}
+
+impl PostProcessor for EntityLibrary {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        self.replace_variants(result, None);
+        Ok(())
+    }
+}
+
+/// Replaces license strings and variants with references to canonical
+/// licenses, adding their entities to the graph
+#[derive(Debug, Clone)]
+pub struct NormalizeLicenses {
+    library: EntityLibrary,
+}
+
+impl NormalizeLicenses {
+    /// Normalize to the licenses in `library`
+    pub fn new(library: EntityLibrary) -> Self {
+        Self { library }
+    }
+
+    /// Turn the license strings in `value` into references, collecting the
+    /// ids of the canonical licenses referenced
+    fn normalize(&self, value: &mut Value, used: &mut BTreeSet<String>) {
+        match value {
+            Value::String(license) => {
+                let canonical = self
+                    .library
+                    .canonical(license)
+                    .or_else(|| self.library.by_label(license))
+                    .and_then(extract_id);
+                if let Some(id) = canonical {
+                    used.insert(id.to_string());
+                    *value = serde_json::json!({ "@id": id });
+                }
+            }
+            Value::Object(obj) => {
+                let canonical = obj
+                    .get("@id")
+                    .and_then(Value::as_str)
+                    .and_then(|id| self.library.canonical(id))
+                    .and_then(extract_id);
+                if let Some(id) = canonical {
+                    used.insert(id.to_string());
+                }
+            }
+            Value::Array(licenses) => {
+                for license in licenses {
+                    self.normalize(license, used);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Default for NormalizeLicenses {
+    fn default() -> Self {
+        Self::new(EntityLibrary::builtin())
+    }
+}
+
+impl PostProcessor for NormalizeLicenses {
+    fn process(&self, result: &mut ConsolidateResult) -> Result<(), ConsolidateError> {
+        let mut used = BTreeSet::new();
+        for obj in result.graph.iter_mut().filter_map(Value::as_object_mut) {
+            if let Some(license) = obj.get_mut("license") {
+                self.normalize(license, &mut used);
+            }
+        }
+        if used.is_empty() {
+            return Ok(());
+        }
+
+        let only: HashSet<String> = used.iter().cloned().collect();
+        self.library.replace_variants(result, Some(&only));
+        let present: HashSet<String> = result
+            .graph
+            .iter()
+            .filter_map(extract_id)
+            .filter(|id| only.contains(*id))
+            .map(str::to_string)
+            .collect();
+        for id in used.iter().filter(|id| !present.contains(*id)) {
+            result.graph.push(self.library.entities[id].clone());
+        }
+        Ok(())
+    }
+}
+
+/// Rewrite all @ids within a JSON value that have a canonical id to it
+/// (recursive)
+///
+/// Includes the value's own @id. Like
+/// [`rewrite_references`](crate::id::rewrite_references), `contentUrl`
+/// values are left untouched.
+pub(crate) fn canonicalize_references<'a>(
+    value: &mut Value,
+    canonical_id: &dyn Fn(&str) -> Option<&'a str>,
+) {
+    match value {
+        Value::Object(obj) => {
+            if let Some(Value::String(id)) = obj.get_mut("@id") {
+                if let Some(canonical) = canonical_id(id) {
+                    if canonical != id {
+                        *id = canonical.to_string();
+                    }
+                }
+            }
+            for (key, v) in obj.iter_mut() {
+                if key == "contentUrl" {
+                    continue;
+                }
+                canonicalize_references(v, canonical_id);
+            }
+        }
+        Value::Array(arr) => {
+            for item in arr.iter_mut() {
+                canonicalize_references(item, canonical_id);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// What ids are matched by: lowercased, without scheme, "www.", trailing '/'
+/// or ".html"
+///
+/// None for ids other than HTTP(S) URLs.
+fn match_key(id: &str) -> Option<String> {
+    let id = id.to_lowercase();
+    let rest = id
+        .strip_prefix("https://")
+        .or_else(|| id.strip_prefix("http://"))?;
+    let rest = rest.strip_prefix("www.").unwrap_or(rest);
+    let rest = rest.trim_end_matches('/');
+    let rest = rest.strip_suffix(".html").unwrap_or(rest);
+    Some(rest.to_string())
+}
+
+/// What labels are matched by: their lowercased alphanumeric words
+fn label_key(label: &str) -> String {
+    label
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consolidate::{consolidate, ConsolidateInput, ConsolidateOptions, NoOpLoader};
+    use serde_json::json;
+
+    #[test]
+    fn test_builtin_library() {
+        let library = EntityLibrary::builtin();
+        assert!(library.len() > 20);
+        let mit = |id| library.canonical(id).and_then(extract_id);
+        assert_eq!(
+            mit("http://spdx.org/licenses/MIT.html"),
+            Some("https://spdx.org/licenses/MIT")
+        );
+        assert_eq!(
+            mit("https://opensource.org/licenses/MIT/"),
+            Some("https://spdx.org/licenses/MIT")
+        );
+        assert_eq!(
+            mit("http://creativecommons.org/licenses/by/4.0/"),
+            Some("https://spdx.org/licenses/CC-BY-4.0")
+        );
+        assert_eq!(mit("https://spdx.org/licenses/MIT-0"), None);
+        assert_eq!(mit("./LICENSE"), None);
+    }
+
+    #[test]
+    fn test_canonical_entities() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "license": {"@id": "http://spdx.org/licenses/MIT"},
+                "hasPart": [{"@id": "data.csv"}]
+            }),
+            json!({
+                "@id": "data.csv",
+                "@type": "File",
+                "license": {"@id": "https://opensource.org/licenses/MIT"},
+                "author": {"@id": "https://example.org/lab"}
+            }),
+            json!({"@id": "http://spdx.org/licenses/MIT", "name": "MIT"}),
+            json!({"@id": "https://opensource.org/licenses/MIT", "@type": "CreativeWork"}),
+            json!({"@id": "https://example.org/lab", "@type": "Organization", "name": "Lab"}),
+        ];
+
+        // User-supplied entries extend the built-in ones
+        let library = EntityLibrary::builtin()
+            .with_json(
+                &json!([{
+                    "entity": {
+                        "@id": "https://ror.org/00example",
+                        "@type": "Organization",
+                        "name": "Example Lab"
+                    },
+                    "aliases": ["https://example.org/lab"]
+                }])
+                .to_string(),
+            )
+            .unwrap();
+        let options = ConsolidateOptions::default().with_post_processor(library);
+        let result = consolidate(ConsolidateInput::Single(graph), &NoOpLoader, &options).unwrap();
+
+        assert_eq!(
+            result.graph[1]["license"]["@id"],
+            "https://spdx.org/licenses/MIT"
+        );
+        assert_eq!(
+            result.graph[2]["license"]["@id"],
+            "https://spdx.org/licenses/MIT"
+        );
+        assert_eq!(
+            result.graph[2]["author"]["@id"],
+            "https://ror.org/00example"
+        );
+        let licenses: Vec<&Value> = result
+            .graph
+            .iter()
+            .filter(|e| extract_id(e).is_some_and(|id| id.contains("MIT")))
+            .collect();
+        assert_eq!(licenses.len(), 1);
+        assert_eq!(licenses[0]["name"], "MIT License");
+        assert_eq!(result.stats.total_entities, result.graph.len());
+        assert!(result
+            .origins
+            .iter()
+            .any(|o| o.id == "https://ror.org/00example"));
+    }
+
+    #[test]
+    fn test_normalize_licenses() {
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({
+                "@id": "./",
+                "@type": "Dataset",
+                "license": "CC-BY-4.0",
+                "hasPart": [{"@id": "a.csv"}, {"@id": "b.csv"}, {"@id": "c.csv"}]
+            }),
+            json!({"@id": "a.csv", "license": ["Creative Commons Attribution 4.0", "cc by 4.0"]}),
+            json!({"@id": "b.csv", "license": {"@id": "http://creativecommons.org/licenses/by/4.0"}}),
+            json!({"@id": "c.csv", "license": "Some custom terms"}),
+            json!({"@id": "http://creativecommons.org/licenses/by/4.0", "name": "CC BY"}),
+            json!({"@id": "https://opensource.org/licenses/MIT", "@type": "CreativeWork"}),
+        ];
+        let options =
+            ConsolidateOptions::default().with_post_processor(NormalizeLicenses::default());
+        let result = consolidate(ConsolidateInput::Single(graph), &NoOpLoader, &options).unwrap();
+
+        let cc_by = json!({"@id": "https://spdx.org/licenses/CC-BY-4.0"});
+        assert_eq!(result.graph[1]["license"], cc_by);
+        assert_eq!(result.graph[2]["license"], json!([cc_by, cc_by]));
+        assert_eq!(result.graph[3]["license"], cc_by);
+        assert_eq!(result.graph[4]["license"], "Some custom terms");
+        assert_eq!(result.graph[5]["identifier"], "CC-BY-4.0");
+        assert_eq!(result.graph[5]["@type"], "CreativeWork");
+        // Only licenses in use are normalized
+        assert_eq!(
+            result.graph[6]["@id"],
+            "https://opensource.org/licenses/MIT"
+        );
+        assert_eq!(result.graph.len(), 7);
+
+        // Canonical entities referenced only by strings are added
+        let graph = vec![
+            json!({"@id": "ro-crate-metadata.json", "about": {"@id": "./"}}),
+            json!({"@id": "./", "@type": "Dataset", "license": "Apache License, Version 2.0"}),
+        ];
+        let result = consolidate(ConsolidateInput::Single(graph), &NoOpLoader, &options).unwrap();
+        assert_eq!(
+            result.graph[1]["license"]["@id"],
+            "https://spdx.org/licenses/Apache-2.0"
+        );
+        assert_eq!(
+            result.graph[2]["@id"],
+            "https://spdx.org/licenses/Apache-2.0"
+        );
+        assert_eq!(result.stats.total_entities, 3);
+    }
+
+    #[test]
+    fn test_invalid_library() {
+        let err = EntityLibrary::new()
+            .with_json(r##"[{"entity": {"@id": "#local"}}]"##)
+            .unwrap_err();
+        assert_eq!(err.code(), "invalid_structure");
+        assert!(EntityLibrary::new().with_json("{}").is_err());
+    }
+}